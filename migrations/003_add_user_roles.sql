@@ -0,0 +1,10 @@
+-- Add roles to users so admin-only pages can be restricted
+-- Run this with: psql $DATABASE_URL -f migrations/003_add_user_roles.sql
+
+ALTER TABLE users ADD COLUMN IF NOT EXISTS role VARCHAR(20) NOT NULL DEFAULT 'staff'
+    CHECK (role IN ('admin', 'staff'));
+
+-- The seeded admin account becomes the first administrator
+UPDATE users SET role = 'admin' WHERE username = 'admin';
+
+CREATE INDEX IF NOT EXISTS idx_users_created_at ON users(created_at);
@@ -0,0 +1,10 @@
+-- Store missing pictures as NULL instead of an empty string
+-- Run this with: psql $DATABASE_URL -f migrations/004_nullable_picture_urls.sql
+
+ALTER TABLE products ALTER COLUMN picture_url DROP NOT NULL;
+ALTER TABLE preparations ALTER COLUMN picture_url DROP NOT NULL;
+ALTER TABLE preparation_steps ALTER COLUMN picture_url DROP NOT NULL;
+
+UPDATE products SET picture_url = NULL WHERE picture_url = '';
+UPDATE preparations SET picture_url = NULL WHERE picture_url = '';
+UPDATE preparation_steps SET picture_url = NULL WHERE picture_url = '';
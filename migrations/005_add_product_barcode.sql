@@ -0,0 +1,6 @@
+-- Add an optional EAN-13/UPC-A barcode to products for scan lookups
+-- Run this with: psql $DATABASE_URL -f migrations/005_add_product_barcode.sql
+
+ALTER TABLE products ADD COLUMN IF NOT EXISTS barcode VARCHAR(13);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_products_barcode ON products(barcode);
@@ -0,0 +1,8 @@
+-- Add optional prep time and difficulty to preparations for shift planning
+-- Run this with: psql $DATABASE_URL -f migrations/006_add_prep_time_and_difficulty.sql
+
+ALTER TABLE preparations ADD COLUMN IF NOT EXISTS prep_time_minutes INTEGER
+    CHECK (prep_time_minutes BETWEEN 1 AND 600);
+
+ALTER TABLE preparations ADD COLUMN IF NOT EXISTS difficulty VARCHAR(10)
+    CHECK (difficulty IN ('easy', 'medium', 'hard'));
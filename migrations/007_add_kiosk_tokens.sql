@@ -0,0 +1,12 @@
+-- Device tokens for shared kitchen tablets running in kiosk mode
+-- Run this with: psql $DATABASE_URL -f migrations/007_add_kiosk_tokens.sql
+
+CREATE TABLE IF NOT EXISTS kiosk_tokens (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    name VARCHAR(100) NOT NULL,
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    last_seen_at TIMESTAMP WITH TIME ZONE,
+    revoked_at TIMESTAMP WITH TIME ZONE
+);
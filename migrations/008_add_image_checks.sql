@@ -0,0 +1,15 @@
+-- Results of the admin image broken-link check, one row per stored picture_url
+-- Run this with: psql $DATABASE_URL -f migrations/008_add_image_checks.sql
+
+CREATE TABLE IF NOT EXISTS image_checks (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    source_table VARCHAR(30) NOT NULL CHECK (source_table IN ('products', 'preparations', 'preparation_steps')),
+    source_id UUID NOT NULL,
+    parent_id UUID NOT NULL,
+    picture_url VARCHAR(500) NOT NULL,
+    status_code INT,
+    error TEXT,
+    is_ok BOOLEAN NOT NULL,
+    checked_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (source_table, source_id)
+);
@@ -0,0 +1,9 @@
+-- Check step number uniqueness at the end of each statement so steps can be renumbered in one UPDATE
+-- Run this with: psql $DATABASE_URL -f migrations/009_deferrable_step_number_unique.sql
+
+ALTER TABLE preparation_steps
+    DROP CONSTRAINT IF EXISTS preparation_steps_preparation_id_step_number_key;
+
+ALTER TABLE preparation_steps
+    ADD CONSTRAINT preparation_steps_preparation_id_step_number_key
+    UNIQUE (preparation_id, step_number) DEFERRABLE INITIALLY IMMEDIATE;
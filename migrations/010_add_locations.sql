@@ -0,0 +1,9 @@
+-- Admin-managed list of allowed product and preparation locations
+-- While the table is empty, locations stay free text
+-- Run this with: psql $DATABASE_URL -f migrations/010_add_locations.sql
+
+CREATE TABLE IF NOT EXISTS locations (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    name VARCHAR(255) NOT NULL UNIQUE,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+);
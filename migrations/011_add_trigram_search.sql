@@ -0,0 +1,9 @@
+-- Trigram indexes for typo-tolerant search (FUZZY_SEARCH=true)
+-- They also speed up the plain ILIKE search, so they're worth having either way
+-- Run this with: psql $DATABASE_URL -f migrations/011_add_trigram_search.sql
+
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX IF NOT EXISTS idx_products_product_name_trgm ON products USING GIN (product_name gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS idx_products_supplier_name_trgm ON products USING GIN (supplier_name gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS idx_preparations_name_trgm ON preparations USING GIN (name gin_trgm_ops);
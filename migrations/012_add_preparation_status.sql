@@ -0,0 +1,14 @@
+-- Draft status for preparations saved before the form is complete
+-- Drafts may leave the type and shift blank; published preparations still need valid ones
+-- Run this with: psql $DATABASE_URL -f migrations/012_add_preparation_status.sql
+
+ALTER TABLE preparations ADD COLUMN IF NOT EXISTS status VARCHAR(20) NOT NULL DEFAULT 'published'
+    CHECK (status IN ('draft', 'published'));
+
+ALTER TABLE preparations DROP CONSTRAINT IF EXISTS preparations_prep_type_check;
+ALTER TABLE preparations ADD CONSTRAINT preparations_prep_type_check
+    CHECK (status = 'draft' OR prep_type IN ('fruit', 'bread', 'veg', 'meat', 'seafood'));
+
+ALTER TABLE preparations DROP CONSTRAINT IF EXISTS preparations_shift_check;
+ALTER TABLE preparations ADD CONSTRAINT preparations_shift_check
+    CHECK (status = 'draft' OR shift IN ('brekkie', 'lunch', 'both'));
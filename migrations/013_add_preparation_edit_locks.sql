@@ -0,0 +1,10 @@
+-- Advisory edit locks so two people don't edit the same preparation at once
+-- A lock lasts 15 minutes and is refreshed by the editor's heartbeat; expired rows are ignored and cleared
+-- Run this with: psql $DATABASE_URL -f migrations/013_add_preparation_edit_locks.sql
+
+CREATE TABLE IF NOT EXISTS preparation_edit_locks (
+    preparation_id UUID PRIMARY KEY REFERENCES preparations(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    acquired_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
@@ -0,0 +1,39 @@
+-- Usernames and emails are unique regardless of case, and stored lowercase
+-- Stops without changing anything if existing users already collide; rename or merge them, then run it again
+-- Run this with: psql $DATABASE_URL -f migrations/014_case_insensitive_user_identity.sql
+
+DO $$
+DECLARE
+    username_collisions TEXT;
+    email_collisions TEXT;
+BEGIN
+    SELECT string_agg(names, '; ') INTO username_collisions
+    FROM (
+        SELECT string_agg(username, ', ' ORDER BY created_at) AS names
+        FROM users
+        GROUP BY lower(username)
+        HAVING COUNT(*) > 1
+    ) AS collisions;
+
+    SELECT string_agg(emails, '; ') INTO email_collisions
+    FROM (
+        SELECT string_agg(email || ' (' || username || ')', ', ' ORDER BY created_at) AS emails
+        FROM users
+        GROUP BY lower(email)
+        HAVING COUNT(*) > 1
+    ) AS collisions;
+
+    IF username_collisions IS NOT NULL OR email_collisions IS NOT NULL THEN
+        RAISE EXCEPTION 'Users differ only by case. Usernames: %. Emails: %.',
+            COALESCE(username_collisions, 'none'), COALESCE(email_collisions, 'none');
+    END IF;
+END $$;
+
+UPDATE users SET username = lower(username) WHERE username <> lower(username);
+UPDATE users SET email = lower(email) WHERE email <> lower(email);
+
+-- Lookups compare lowercase, so index that instead of the raw columns
+DROP INDEX IF EXISTS idx_users_username;
+DROP INDEX IF EXISTS idx_users_email;
+CREATE UNIQUE INDEX IF NOT EXISTS users_username_lower_key ON users (lower(username));
+CREATE UNIQUE INDEX IF NOT EXISTS users_email_lower_key ON users (lower(email));
@@ -0,0 +1,6 @@
+-- WebP copies of uploaded product and preparation photos (made when WEBP_CONVERT is set)
+-- Pages offer the WebP copy to browsers that support it and fall back to picture_url
+-- Run this with: psql $DATABASE_URL -f migrations/015_add_webp_urls.sql
+
+ALTER TABLE products ADD COLUMN IF NOT EXISTS webp_url VARCHAR(500);
+ALTER TABLE preparations ADD COLUMN IF NOT EXISTS webp_url VARCHAR(500);
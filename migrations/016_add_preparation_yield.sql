@@ -0,0 +1,7 @@
+-- Add an optional yield (e.g. 4 L, 12 portions) to preparations so recipes can be scaled
+-- Run this with: psql $DATABASE_URL -f migrations/016_add_preparation_yield.sql
+
+ALTER TABLE preparations ADD COLUMN IF NOT EXISTS yield_quantity DOUBLE PRECISION
+    CHECK (yield_quantity > 0);
+
+ALTER TABLE preparations ADD COLUMN IF NOT EXISTS yield_unit VARCHAR(30);
@@ -0,0 +1,9 @@
+-- Per-user preferences, such as the preparations list filters last used
+-- Stored as JSON so new preferences don't need a migration
+-- Run this with: psql $DATABASE_URL -f migrations/017_add_user_preferences.sql
+
+CREATE TABLE IF NOT EXISTS user_preferences (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    preferences JSONB NOT NULL DEFAULT '{}',
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
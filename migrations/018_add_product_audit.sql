@@ -0,0 +1,14 @@
+-- Audit trail of product edits, with the product as JSON before and after each change
+-- Run this with: psql $DATABASE_URL -f migrations/018_add_product_audit.sql
+
+CREATE TABLE IF NOT EXISTS product_audit (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    product_id UUID NOT NULL REFERENCES products(id) ON DELETE CASCADE,
+    user_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    username VARCHAR(255),
+    before JSONB NOT NULL,
+    after JSONB NOT NULL,
+    changed_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_product_audit_product_changed ON product_audit(product_id, changed_at DESC);
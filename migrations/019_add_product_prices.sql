@@ -0,0 +1,18 @@
+-- Pack size and last known price of each product, with every price recorded over time
+-- Prices are whole cents so sums and comparisons never suffer float rounding
+-- Run this with: psql $DATABASE_URL -f migrations/019_add_product_prices.sql
+
+ALTER TABLE products ADD COLUMN IF NOT EXISTS unit VARCHAR(50);
+ALTER TABLE products ADD COLUMN IF NOT EXISTS last_price_cents BIGINT CHECK (last_price_cents >= 0);
+
+CREATE TABLE IF NOT EXISTS product_price_history (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    product_id UUID NOT NULL REFERENCES products(id) ON DELETE CASCADE,
+    price_cents BIGINT NOT NULL CHECK (price_cents >= 0),
+    unit VARCHAR(50),
+    recorded_by VARCHAR(255),
+    recorded_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_product_price_history_product ON product_price_history(product_id, recorded_at DESC);
+CREATE INDEX IF NOT EXISTS idx_product_price_history_recorded_at ON product_price_history(recorded_at);
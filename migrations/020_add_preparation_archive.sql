@@ -0,0 +1,7 @@
+-- Archive preparations for the off season: they leave the list and search but keep
+-- their steps and links, and can be restored
+-- Run this with: psql $DATABASE_URL -f migrations/020_add_preparation_archive.sql
+
+ALTER TABLE preparations ADD COLUMN IF NOT EXISTS archived_at TIMESTAMP WITH TIME ZONE;
+
+CREATE INDEX IF NOT EXISTS idx_preparations_archived_at ON preparations(archived_at) WHERE archived_at IS NOT NULL;
@@ -0,0 +1,13 @@
+-- One-time tokens from the add product and add preparation forms, so a double
+-- submit redirects to the record the first one created instead of adding another.
+-- Rows are only needed briefly and are pruned after a day.
+-- Run this with: psql $DATABASE_URL -f migrations/021_add_form_submissions.sql
+
+CREATE TABLE IF NOT EXISTS form_submissions (
+    token UUID PRIMARY KEY,
+    entity_type VARCHAR(20) NOT NULL CHECK (entity_type IN ('product', 'preparation')),
+    entity_id UUID NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_form_submissions_created_at ON form_submissions(created_at);
@@ -0,0 +1,12 @@
+-- Order cut-offs and delivery days per supplier, for the /ordering page.
+-- Suppliers are the names on products, so schedules are keyed by that name.
+-- Weekdays are 0 = Monday .. 6 = Sunday; cut-off times are in VENUE_TIMEZONE.
+-- Run this with: psql $DATABASE_URL -f migrations/022_add_supplier_schedules.sql
+
+CREATE TABLE IF NOT EXISTS supplier_schedules (
+    supplier_name VARCHAR(255) PRIMARY KEY,
+    cutoff_weekdays SMALLINT[] NOT NULL CHECK (cutoff_weekdays <@ ARRAY[0, 1, 2, 3, 4, 5, 6]::SMALLINT[]),
+    cutoff_time TIME NOT NULL,
+    delivery_weekdays SMALLINT[] NOT NULL DEFAULT '{}' CHECK (delivery_weekdays <@ ARRAY[0, 1, 2, 3, 4, 5, 6]::SMALLINT[]),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,24 @@
+-- Stored size of each picture, for the /admin/storage report
+-- New uploads record their size; older pictures are measured by the backfill on that page.
+-- picture_uploaded_at is when the picture was stored (the object's Last-Modified for backfilled rows).
+-- Run this with: psql $DATABASE_URL -f migrations/023_add_picture_sizes.sql
+
+ALTER TABLE products ADD COLUMN IF NOT EXISTS picture_size_bytes BIGINT CHECK (picture_size_bytes >= 0);
+ALTER TABLE products ADD COLUMN IF NOT EXISTS picture_uploaded_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE preparations ADD COLUMN IF NOT EXISTS picture_size_bytes BIGINT CHECK (picture_size_bytes >= 0);
+ALTER TABLE preparations ADD COLUMN IF NOT EXISTS picture_uploaded_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE preparation_steps ADD COLUMN IF NOT EXISTS picture_size_bytes BIGINT CHECK (picture_size_bytes >= 0);
+ALTER TABLE preparation_steps ADD COLUMN IF NOT EXISTS picture_uploaded_at TIMESTAMP WITH TIME ZONE;
+
+-- Measuring a picture isn't an edit, so it leaves updated_at alone
+CREATE OR REPLACE FUNCTION update_updated_at_column()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF to_jsonb(NEW) - 'picture_size_bytes' - 'picture_uploaded_at' - 'updated_at'
+       = to_jsonb(OLD) - 'picture_size_bytes' - 'picture_uploaded_at' - 'updated_at' THEN
+        RETURN NEW;
+    END IF;
+    NEW.updated_at = CURRENT_TIMESTAMP;
+    RETURN NEW;
+END;
+$$ language 'plpgsql';
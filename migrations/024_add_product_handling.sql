@@ -0,0 +1,17 @@
+-- Storage temperature range and handling notes for products, so they don't end up in the description.
+-- Either end of the range may be left open, e.g. "below 5°C" only sets the max.
+-- The /products/cold-chain page lists products with a max, coldest first.
+-- Run this with: psql $DATABASE_URL -f migrations/024_add_product_handling.sql
+
+ALTER TABLE products ADD COLUMN IF NOT EXISTS storage_temp_min_c DOUBLE PRECISION
+    CHECK (storage_temp_min_c BETWEEN -40 AND 60);
+ALTER TABLE products ADD COLUMN IF NOT EXISTS storage_temp_max_c DOUBLE PRECISION
+    CHECK (storage_temp_max_c BETWEEN -40 AND 60);
+ALTER TABLE products ADD COLUMN IF NOT EXISTS handling_notes TEXT;
+
+ALTER TABLE products DROP CONSTRAINT IF EXISTS products_storage_temp_order;
+ALTER TABLE products ADD CONSTRAINT products_storage_temp_order
+    CHECK (storage_temp_min_c <= storage_temp_max_c);
+
+CREATE INDEX IF NOT EXISTS idx_products_storage_temp_max
+    ON products(storage_temp_max_c) WHERE storage_temp_max_c IS NOT NULL;
@@ -0,0 +1,12 @@
+-- Free-form tags on products (e.g. "gluten-free", "christmas menu"), for organising a large catalog.
+-- Tags are stored normalised (trimmed, lowercase, single spaces) by the app.
+-- Run this with: psql $DATABASE_URL -f migrations/025_add_product_tags.sql
+
+CREATE TABLE IF NOT EXISTS product_tags (
+    product_id UUID NOT NULL REFERENCES products(id) ON DELETE CASCADE,
+    tag VARCHAR(50) NOT NULL CHECK (tag <> ''),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (product_id, tag)
+);
+
+CREATE INDEX IF NOT EXISTS idx_product_tags_tag ON product_tags(tag);
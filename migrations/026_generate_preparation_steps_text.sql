@@ -0,0 +1,34 @@
+-- The preparation_steps rows are now the only source of a preparation's steps.
+-- preparations.steps is kept for search, but generated from them ("1. ...", one line
+-- per step) by the app through preparation_steps_text() whenever steps change.
+-- Run this with: psql $DATABASE_URL -f migrations/026_generate_preparation_steps_text.sql
+
+CREATE OR REPLACE FUNCTION preparation_steps_text(prep_id UUID)
+RETURNS TEXT AS $$
+    SELECT COALESCE(string_agg(numbered.position || '. ' || numbered.description, E'\n' ORDER BY numbered.position), '')
+    FROM (
+        SELECT ROW_NUMBER() OVER (ORDER BY step_number, created_at, id) AS position, description
+        FROM preparation_steps
+        WHERE preparation_id = prep_id
+    ) AS numbered
+$$ LANGUAGE SQL STABLE;
+
+BEGIN;
+
+-- Preparations from before structured steps only have the text: give them one step
+-- per non-blank line, dropping the "1." numbering (and the form's "placeholder")
+INSERT INTO preparation_steps (preparation_id, step_number, description)
+SELECT p.id,
+       ROW_NUMBER() OVER (PARTITION BY p.id ORDER BY line.position),
+       regexp_replace(btrim(line.text), '^[0-9]+[.)][[:space:]]*', '')
+FROM preparations p
+CROSS JOIN LATERAL regexp_split_to_table(p.steps, E'\r?\n') WITH ORDINALITY AS line(text, position)
+WHERE NOT EXISTS (SELECT 1 FROM preparation_steps s WHERE s.preparation_id = p.id)
+  AND regexp_replace(btrim(line.text), '^[0-9]+[.)][[:space:]]*', '') NOT IN ('', 'placeholder');
+
+-- Then regenerate every preparation's text from its steps
+UPDATE preparations
+SET steps = preparation_steps_text(id)
+WHERE steps IS DISTINCT FROM preparation_steps_text(id);
+
+COMMIT;
@@ -0,0 +1,11 @@
+-- The language a preparation is written in, and the group of preparations that are
+-- translations of each other (one per language). A preparation with no translations
+-- is a group of one. New preparations get PRIMARY_LANG from the app unless a language
+-- is picked; existing ones are taken to be English.
+-- Run this with: psql $DATABASE_URL -f migrations/027_add_preparation_languages.sql
+
+ALTER TABLE preparations ADD COLUMN IF NOT EXISTS lang VARCHAR(10) NOT NULL DEFAULT 'en';
+ALTER TABLE preparations ADD COLUMN IF NOT EXISTS translation_group UUID NOT NULL DEFAULT uuid_generate_v4();
+
+-- One variant per language in a group; also serves lookups by group
+CREATE UNIQUE INDEX IF NOT EXISTS idx_preparations_translation_lang ON preparations(translation_group, lang);
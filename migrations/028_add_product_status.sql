@@ -0,0 +1,10 @@
+-- Draft status for products created from a bulk photo upload, which start with just
+-- a picture and a placeholder name. Drafts are left out of the product lists, search,
+-- exports and ordering until their edit form is saved, and are deleted after 30 days.
+-- Run this with: psql $DATABASE_URL -f migrations/028_add_product_status.sql
+
+ALTER TABLE products ADD COLUMN IF NOT EXISTS status VARCHAR(20) NOT NULL DEFAULT 'published'
+    CHECK (status IN ('draft', 'published'));
+
+-- For the drafts page and the cleanup of old drafts
+CREATE INDEX IF NOT EXISTS idx_products_drafts ON products(created_at) WHERE status = 'draft';
@@ -0,0 +1,5 @@
+-- Tiny blurred placeholder of each product photo, as a data: URI of a few hundred bytes,
+-- shown until the photo itself loads (made on upload when LQIP_ENABLED is set)
+-- Run this with: psql $DATABASE_URL -f migrations/029_add_product_lqip.sql
+
+ALTER TABLE products ADD COLUMN IF NOT EXISTS lqip TEXT;
@@ -0,0 +1,20 @@
+-- Documents attached to products, like a supplier's spec sheet. Kept apart from
+-- pictures: only the types in DOCUMENT_TYPES are accepted, and files are stored under
+-- documents/ in the bucket or in DOCUMENT_DIR, and downloaded through the app.
+-- Run this with: psql $DATABASE_URL -f migrations/030_add_product_documents.sql
+
+CREATE TABLE IF NOT EXISTS product_documents (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    product_id UUID NOT NULL REFERENCES products(id) ON DELETE CASCADE,
+    -- Name it was uploaded as, offered again on download
+    filename VARCHAR(255) NOT NULL CHECK (filename <> ''),
+    doc_type VARCHAR(10) NOT NULL,
+    -- Where the file is: an object key when 's3', a file in DOCUMENT_DIR when 'local'
+    storage VARCHAR(10) NOT NULL CHECK (storage IN ('local', 's3')),
+    storage_key TEXT NOT NULL,
+    size_bytes BIGINT NOT NULL CHECK (size_bytes >= 0),
+    uploaded_by VARCHAR(255),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_product_documents_product ON product_documents(product_id, created_at);
@@ -0,0 +1,20 @@
+-- Merging a duplicate product into another: the duplicate's tags, price history, audit
+-- trail and documents move to the product it was merged into, and the duplicate is kept
+-- with status 'merged' only so its old URL can redirect there. Merged products are left
+-- out of the lists, search and exports like drafts.
+-- Run this with: psql $DATABASE_URL -f migrations/031_add_product_merges.sql
+
+ALTER TABLE products DROP CONSTRAINT IF EXISTS products_status_check;
+ALTER TABLE products ADD CONSTRAINT products_status_check
+    CHECK (status IN ('draft', 'published', 'merged'));
+
+-- The product a merged one now redirects to, and when it was merged
+ALTER TABLE products ADD COLUMN IF NOT EXISTS merged_into UUID REFERENCES products(id) ON DELETE CASCADE;
+ALTER TABLE products ADD COLUMN IF NOT EXISTS merged_at TIMESTAMP WITH TIME ZONE;
+
+ALTER TABLE products DROP CONSTRAINT IF EXISTS products_merged_into_check;
+ALTER TABLE products ADD CONSTRAINT products_merged_into_check
+    CHECK ((status = 'merged') = (merged_into IS NOT NULL));
+
+-- For following merges when the product they point at is deleted
+CREATE INDEX IF NOT EXISTS idx_products_merged_into ON products(merged_into) WHERE merged_into IS NOT NULL;
@@ -7,8 +7,9 @@
 -- Connect to the database
 -- \c kitchen_hand_guide
 
--- Enable UUID extension
+-- Enable UUID and trigram (fuzzy search) extensions
 CREATE EXTENSION IF NOT EXISTS "uuid-ossp";
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
 
 -- Products table
 CREATE TABLE products (
@@ -16,10 +17,34 @@ CREATE TABLE products (
     supplier_name VARCHAR(255) NOT NULL,
     product_name VARCHAR(255) NOT NULL,
     location VARCHAR(255) NOT NULL,
-    picture_url VARCHAR(500) NOT NULL,
+    barcode VARCHAR(13),
+    picture_url VARCHAR(500),
+    -- WebP copy of the picture, when WEBP_CONVERT made one
+    webp_url VARCHAR(500),
+    -- Tiny blurred placeholder of the picture as a data: URI, when LQIP_ENABLED made one
+    lqip TEXT,
+    -- Stored size of the picture and when it was stored, for the storage report
+    picture_size_bytes BIGINT CHECK (picture_size_bytes >= 0),
+    picture_uploaded_at TIMESTAMP WITH TIME ZONE,
     description TEXT NOT NULL,
+    -- Pack size the price is for, e.g. "5kg box"
+    unit VARCHAR(50),
+    -- Last known price in whole cents
+    last_price_cents BIGINT CHECK (last_price_cents >= 0),
+    -- Storage temperature range in °C; either end may be open
+    storage_temp_min_c DOUBLE PRECISION CHECK (storage_temp_min_c BETWEEN -40 AND 60),
+    storage_temp_max_c DOUBLE PRECISION CHECK (storage_temp_max_c BETWEEN -40 AND 60),
+    -- How to handle the product, e.g. "don't stack above 3 high"
+    handling_notes TEXT,
+    -- Drafts come from bulk photo uploads and are hidden until their details are filled in;
+    -- merged products are duplicates kept only so their URL redirects to merged_into
+    status VARCHAR(20) NOT NULL DEFAULT 'published' CHECK (status IN ('draft', 'published', 'merged')),
+    merged_into UUID REFERENCES products(id) ON DELETE CASCADE,
+    merged_at TIMESTAMP WITH TIME ZONE,
     created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
-    updated_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT products_storage_temp_order CHECK (storage_temp_min_c <= storage_temp_max_c),
+    CONSTRAINT products_merged_into_check CHECK ((status = 'merged') = (merged_into IS NOT NULL))
 );
 
 -- Create index on supplier_name for faster queries
@@ -28,16 +53,33 @@ CREATE INDEX idx_products_supplier ON products(supplier_name);
 -- Create index on location for faster queries
 CREATE INDEX idx_products_location ON products(location);
 
+-- Barcodes are optional but must be unique when set
+CREATE UNIQUE INDEX idx_products_barcode ON products(barcode);
+
+-- For the cold-chain list, coldest first
+CREATE INDEX idx_products_storage_temp_max ON products(storage_temp_max_c) WHERE storage_temp_max_c IS NOT NULL;
+
+-- For the drafts page and the cleanup of old drafts
+CREATE INDEX idx_products_drafts ON products(created_at) WHERE status = 'draft';
+
+-- For following merges when the product they point at is deleted
+CREATE INDEX idx_products_merged_into ON products(merged_into) WHERE merged_into IS NOT NULL;
+
 -- Insert sample data (optional)
-INSERT INTO products (supplier_name, product_name, location, picture_url, description) VALUES
-    ('Fresh Farm Co.', 'Organic Tomatoes', 'Cold Room A - Shelf 2', '/static/uploads/placeholder.jpg', 'Fresh organic tomatoes. Store at 4°C. Check daily for spoilage. Shelf life: 5-7 days.'),
-    ('Ocean Catch Ltd.', 'Atlantic Salmon Fillet', 'Freezer B - Drawer 3', '/static/uploads/placeholder.jpg', 'Premium Atlantic salmon. Keep frozen at -18°C. Thaw in refrigerator overnight before use. Use within 24 hours of thawing.'),
-    ('Dairy Delights', 'Full Cream Milk', 'Refrigerator - Door Shelf', '/static/uploads/placeholder.jpg', 'Pasteurized full cream milk. Store at 4°C. Check use-by date daily. Once opened, use within 3 days.');
+INSERT INTO products (supplier_name, product_name, location, description) VALUES
+    ('Fresh Farm Co.', 'Organic Tomatoes', 'Cold Room A - Shelf 2', 'Fresh organic tomatoes. Store at 4°C. Check daily for spoilage. Shelf life: 5-7 days.'),
+    ('Ocean Catch Ltd.', 'Atlantic Salmon Fillet', 'Freezer B - Drawer 3', 'Premium Atlantic salmon. Keep frozen at -18°C. Thaw in refrigerator overnight before use. Use within 24 hours of thawing.'),
+    ('Dairy Delights', 'Full Cream Milk', 'Refrigerator - Door Shelf', 'Pasteurized full cream milk. Store at 4°C. Check use-by date daily. Once opened, use within 3 days.');
 
 -- Function to update updated_at timestamp
 CREATE OR REPLACE FUNCTION update_updated_at_column()
 RETURNS TRIGGER AS $$
 BEGIN
+    -- Measuring a picture isn't an edit, so it leaves updated_at alone
+    IF to_jsonb(NEW) - 'picture_size_bytes' - 'picture_uploaded_at' - 'updated_at'
+       = to_jsonb(OLD) - 'picture_size_bytes' - 'picture_uploaded_at' - 'updated_at' THEN
+        RETURN NEW;
+    END IF;
     NEW.updated_at = CURRENT_TIMESTAMP;
     RETURN NEW;
 END;
@@ -51,12 +93,35 @@ CREATE TRIGGER update_products_updated_at BEFORE UPDATE ON products
 CREATE TABLE preparations (
     id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
     name VARCHAR(255) NOT NULL,
-    prep_type VARCHAR(50) NOT NULL CHECK (prep_type IN ('fruit', 'bread', 'veg', 'meat', 'seafood')),
-    shift VARCHAR(50) NOT NULL CHECK (shift IN ('brekkie', 'lunch', 'both')),
+    prep_type VARCHAR(50) NOT NULL,
+    shift VARCHAR(50) NOT NULL,
     location VARCHAR(255) NOT NULL,
+    picture_url VARCHAR(500),
+    -- WebP copy of the picture, when WEBP_CONVERT made one
+    webp_url VARCHAR(500),
+    -- Stored size of the picture and when it was stored, for the storage report
+    picture_size_bytes BIGINT CHECK (picture_size_bytes >= 0),
+    picture_uploaded_at TIMESTAMP WITH TIME ZONE,
+    -- Numbered lines generated from preparation_steps (see preparation_steps_text), for search
     steps TEXT NOT NULL,
+    prep_time_minutes INTEGER CHECK (prep_time_minutes BETWEEN 1 AND 600),
+    difficulty VARCHAR(10) CHECK (difficulty IN ('easy', 'medium', 'hard')),
+    -- What one batch makes, e.g. 4 L or 12 portions
+    yield_quantity DOUBLE PRECISION CHECK (yield_quantity > 0),
+    yield_unit VARCHAR(30),
+    -- Drafts may be saved with a blank type or shift
+    status VARCHAR(20) NOT NULL DEFAULT 'published' CHECK (status IN ('draft', 'published')),
+    -- Set while the preparation is archived (e.g. out of season); hidden from lists and search
+    archived_at TIMESTAMP WITH TIME ZONE,
+    -- Language it is written in; translations of each other share a translation_group
+    lang VARCHAR(10) NOT NULL DEFAULT 'en',
+    translation_group UUID NOT NULL DEFAULT uuid_generate_v4(),
     created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
-    updated_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT preparations_prep_type_check
+        CHECK (status = 'draft' OR prep_type IN ('fruit', 'bread', 'veg', 'meat', 'seafood')),
+    CONSTRAINT preparations_shift_check
+        CHECK (status = 'draft' OR shift IN ('brekkie', 'lunch', 'both'))
 );
 
 -- Create index on prep_type for faster queries
@@ -65,6 +130,12 @@ CREATE INDEX idx_preparations_type ON preparations(prep_type);
 -- Create index on shift for faster queries
 CREATE INDEX idx_preparations_shift ON preparations(shift);
 
+-- Archived preparations are few, so only they are indexed
+CREATE INDEX idx_preparations_archived_at ON preparations(archived_at) WHERE archived_at IS NOT NULL;
+
+-- One variant per language in a translation group
+CREATE UNIQUE INDEX idx_preparations_translation_lang ON preparations(translation_group, lang);
+
 -- Insert sample data (optional)
 INSERT INTO preparations (name, prep_type, shift, location, steps) VALUES
     ('Diced Tomatoes', 'veg', 'both', 'Prep Station 1', E'1. Wash tomatoes thoroughly under cold running water\n2. Remove the stem and core with a paring knife\n3. Cut tomatoes in half from top to bottom\n4. Place cut side down and slice into 1cm strips\n5. Rotate 90 degrees and dice into 1cm cubes\n6. Store in airtight container in cold room\n7. Label with date and time - use within 24 hours'),
@@ -82,9 +153,13 @@ CREATE TABLE IF NOT EXISTS preparation_steps (
     step_number INTEGER NOT NULL,
     description TEXT NOT NULL,
     picture_url VARCHAR(500),
+    -- Stored size of the picture and when it was stored, for the storage report
+    picture_size_bytes BIGINT CHECK (picture_size_bytes >= 0),
+    picture_uploaded_at TIMESTAMP WITH TIME ZONE,
     created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
     updated_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
-    UNIQUE(preparation_id, step_number)
+    -- Deferrable so steps can be renumbered in a single UPDATE
+    UNIQUE(preparation_id, step_number) DEFERRABLE INITIALLY IMMEDIATE
 );
 
 -- Create index on preparation_id for faster queries
@@ -94,6 +169,25 @@ CREATE INDEX idx_prep_steps_preparation ON preparation_steps(preparation_id);
 CREATE TRIGGER update_preparation_steps_updated_at BEFORE UPDATE ON preparation_steps
     FOR EACH ROW EXECUTE FUNCTION update_updated_at_column();
 
+-- A preparation's steps as numbered lines ("1. ..."), which the app stores in
+-- preparations.steps for search whenever the steps change
+CREATE OR REPLACE FUNCTION preparation_steps_text(prep_id UUID)
+RETURNS TEXT AS $$
+    SELECT COALESCE(string_agg(numbered.position || '. ' || numbered.description, E'\n' ORDER BY numbered.position), '')
+    FROM (
+        SELECT ROW_NUMBER() OVER (ORDER BY step_number, created_at, id) AS position, description
+        FROM preparation_steps
+        WHERE preparation_id = prep_id
+    ) AS numbered
+$$ LANGUAGE SQL STABLE;
+
+-- The sample preparations above as structured steps, one per line
+INSERT INTO preparation_steps (preparation_id, step_number, description)
+SELECT p.id, line.position, regexp_replace(line.text, '^[0-9]+[.)][[:space:]]*', '')
+FROM preparations p
+CROSS JOIN LATERAL regexp_split_to_table(p.steps, E'\n') WITH ORDINALITY AS line(text, position)
+WHERE NOT EXISTS (SELECT 1 FROM preparation_steps s WHERE s.preparation_id = p.id);
+
 -- Users table
 CREATE TABLE IF NOT EXISTS users (
     id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
@@ -101,13 +195,15 @@ CREATE TABLE IF NOT EXISTS users (
     email VARCHAR(255) NOT NULL UNIQUE,
     password_hash VARCHAR(255) NOT NULL,
     is_active BOOLEAN DEFAULT TRUE,
+    role VARCHAR(20) NOT NULL DEFAULT 'staff' CHECK (role IN ('admin', 'staff')),
     created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
     updated_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
 );
 
--- Create index on username for faster login queries
-CREATE INDEX idx_users_username ON users(username);
-CREATE INDEX idx_users_email ON users(email);
+-- Usernames and emails are stored lowercase and unique regardless of case (also used by login lookups)
+CREATE UNIQUE INDEX users_username_lower_key ON users (lower(username));
+CREATE UNIQUE INDEX users_email_lower_key ON users (lower(email));
+CREATE INDEX idx_users_created_at ON users(created_at);
 
 -- Trigger to automatically update updated_at for users
 CREATE TRIGGER update_users_updated_at BEFORE UPDATE ON users
@@ -115,6 +211,130 @@ CREATE TRIGGER update_users_updated_at BEFORE UPDATE ON users
 
 -- Insert default admin user (password: admin123 - CHANGE THIS IN PRODUCTION!)
 -- Password hash is for 'admin123' using bcrypt
-INSERT INTO users (username, email, password_hash) VALUES
-    ('admin', 'admin@kitchen-hand.local', '$2b$12$LQv3c1yqBWVHxkd0LHAkCOYz6TtxMQJqhN8/LewY.5Q8J8z4bOhyS')
+INSERT INTO users (username, email, password_hash, role) VALUES
+    ('admin', 'admin@kitchen-hand.local', '$2b$12$LQv3c1yqBWVHxkd0LHAkCOYz6TtxMQJqhN8/LewY.5Q8J8z4bOhyS', 'admin')
 ON CONFLICT (username) DO NOTHING;
+
+-- Kiosk device tokens for shared kitchen tablets (only the SHA-256 hash is stored)
+CREATE TABLE IF NOT EXISTS kiosk_tokens (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    name VARCHAR(100) NOT NULL,
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    last_seen_at TIMESTAMP WITH TIME ZONE,
+    revoked_at TIMESTAMP WITH TIME ZONE
+);
+
+-- Results of the admin image broken-link check, one row per stored picture_url
+CREATE TABLE IF NOT EXISTS image_checks (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    source_table VARCHAR(30) NOT NULL CHECK (source_table IN ('products', 'preparations', 'preparation_steps')),
+    source_id UUID NOT NULL,
+    parent_id UUID NOT NULL,
+    picture_url VARCHAR(500) NOT NULL,
+    status_code INT,
+    error TEXT,
+    is_ok BOOLEAN NOT NULL,
+    checked_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (source_table, source_id)
+);
+
+-- Admin-managed list of allowed product and preparation locations (free text while empty)
+CREATE TABLE IF NOT EXISTS locations (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    name VARCHAR(255) NOT NULL UNIQUE,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Advisory preparation edit locks, refreshed by the editor's heartbeat (expired rows are ignored)
+CREATE TABLE IF NOT EXISTS preparation_edit_locks (
+    preparation_id UUID PRIMARY KEY REFERENCES preparations(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    acquired_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+-- Per-user preferences as JSON, e.g. the preparations list filters last used
+CREATE TABLE IF NOT EXISTS user_preferences (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    preferences JSONB NOT NULL DEFAULT '{}',
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Product edit history: the product as JSON before and after each update, and who made it
+-- (username is kept so kiosk edits and deleted users still show a name)
+CREATE TABLE IF NOT EXISTS product_audit (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    product_id UUID NOT NULL REFERENCES products(id) ON DELETE CASCADE,
+    user_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    username VARCHAR(255),
+    before JSONB NOT NULL,
+    after JSONB NOT NULL,
+    changed_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_product_audit_product_changed ON product_audit(product_id, changed_at DESC);
+
+-- Every price recorded for a product, appended whenever its price changes
+CREATE TABLE IF NOT EXISTS product_price_history (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    product_id UUID NOT NULL REFERENCES products(id) ON DELETE CASCADE,
+    price_cents BIGINT NOT NULL CHECK (price_cents >= 0),
+    unit VARCHAR(50),
+    recorded_by VARCHAR(255),
+    recorded_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_product_price_history_product ON product_price_history(product_id, recorded_at DESC);
+CREATE INDEX IF NOT EXISTS idx_product_price_history_recorded_at ON product_price_history(recorded_at);
+
+-- One-time tokens from the add forms, so a double submit doesn't create a second record
+-- (pruned after a day)
+CREATE TABLE IF NOT EXISTS form_submissions (
+    token UUID PRIMARY KEY,
+    entity_type VARCHAR(20) NOT NULL CHECK (entity_type IN ('product', 'preparation')),
+    entity_id UUID NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_form_submissions_created_at ON form_submissions(created_at);
+
+-- Order cut-offs (venue time) and delivery days per supplier name; weekdays are 0 = Monday
+CREATE TABLE IF NOT EXISTS supplier_schedules (
+    supplier_name VARCHAR(255) PRIMARY KEY,
+    cutoff_weekdays SMALLINT[] NOT NULL CHECK (cutoff_weekdays <@ ARRAY[0, 1, 2, 3, 4, 5, 6]::SMALLINT[]),
+    cutoff_time TIME NOT NULL,
+    delivery_weekdays SMALLINT[] NOT NULL DEFAULT '{}' CHECK (delivery_weekdays <@ ARRAY[0, 1, 2, 3, 4, 5, 6]::SMALLINT[]),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Free-form tags on products, stored normalised (trimmed, lowercase, single spaces)
+CREATE TABLE IF NOT EXISTS product_tags (
+    product_id UUID NOT NULL REFERENCES products(id) ON DELETE CASCADE,
+    tag VARCHAR(50) NOT NULL CHECK (tag <> ''),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (product_id, tag)
+);
+
+CREATE INDEX IF NOT EXISTS idx_product_tags_tag ON product_tags(tag);
+
+-- Documents attached to products (spec sheets), stored apart from pictures
+CREATE TABLE IF NOT EXISTS product_documents (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    product_id UUID NOT NULL REFERENCES products(id) ON DELETE CASCADE,
+    filename VARCHAR(255) NOT NULL CHECK (filename <> ''),
+    doc_type VARCHAR(10) NOT NULL,
+    storage VARCHAR(10) NOT NULL CHECK (storage IN ('local', 's3')),
+    storage_key TEXT NOT NULL,
+    size_bytes BIGINT NOT NULL CHECK (size_bytes >= 0),
+    uploaded_by VARCHAR(255),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_product_documents_product ON product_documents(product_id, created_at);
+
+-- Trigram indexes for typo-tolerant search (FUZZY_SEARCH=true)
+CREATE INDEX IF NOT EXISTS idx_products_product_name_trgm ON products USING GIN (product_name gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS idx_products_supplier_name_trgm ON products USING GIN (supplier_name gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS idx_preparations_name_trgm ON preparations USING GIN (name gin_trgm_ops);
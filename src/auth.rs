@@ -1,10 +1,17 @@
 use bcrypt::{hash, verify, DEFAULT_COST};
 use chrono::{Duration, Utc};
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use hmac::{Hmac, Mac};
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::{decode, decode_header, encode, DecodingKey, EncodingKey, Header, Validation};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::env;
+use std::sync::OnceLock;
 use uuid::Uuid;
 
+/// Cookie holding a kiosk device token on shared tablets
+pub const KIOSK_COOKIE: &str = "kiosk_token";
+
 /// JWT Claims structure
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
@@ -14,6 +21,103 @@ pub struct Claims {
     pub iat: usize,       // Issued at
 }
 
+/// A secret that signs or verifies tokens, and the `kid` that names it in token headers
+#[derive(Debug, Clone)]
+pub struct JwtKey {
+    pub kid: String,
+    secret: String,
+}
+
+impl JwtKey {
+    /// The `kid` is derived from the secret, so every server agrees on it without
+    /// extra config, and it reveals nothing about the secret itself
+    pub fn new(secret: &str) -> JwtKey {
+        JwtKey {
+            kid: hex::encode(&Sha256::digest(secret.as_bytes())[..8]),
+            secret: secret.to_string(),
+        }
+    }
+}
+
+/// Bytes of the HMAC kept by `JwtKeys::sign_value`
+const VALUE_MAC_BYTES: usize = 16;
+
+impl JwtKey {
+    fn value_hmac(&self, purpose: &str, value: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.as_bytes()).expect("HMAC takes keys of any length");
+        mac.update(purpose.as_bytes());
+        mac.update(b"\0");
+        mac.update(value.as_bytes());
+        mac
+    }
+
+    fn value_mac(&self, purpose: &str, value: &str) -> String {
+        hex::encode(&self.value_hmac(purpose, value).finalize().into_bytes()[..VALUE_MAC_BYTES])
+    }
+}
+
+/// The key new tokens are signed with, plus retired keys that still verify
+///
+/// Rotating `JWT_SECRET` while moving the old value into `JWT_OLD_SECRETS`
+/// keeps existing sessions valid until their tokens expire.
+#[derive(Debug, Clone)]
+pub struct JwtKeys {
+    primary: JwtKey,
+    old: Vec<JwtKey>,
+}
+
+impl JwtKeys {
+    /// `primary` signs; `old` only verify
+    pub fn new(primary: &str, old: &[&str]) -> JwtKeys {
+        JwtKeys {
+            primary: JwtKey::new(primary),
+            old: old.iter().map(|secret| JwtKey::new(secret)).collect(),
+        }
+    }
+
+    /// `JWT_SECRET` plus the comma-separated `JWT_OLD_SECRETS`
+    pub fn from_env() -> JwtKeys {
+        let primary = env::var("JWT_SECRET").expect("JWT_SECRET must be set in .env file");
+        let old_secrets = env::var("JWT_OLD_SECRETS").unwrap_or_default();
+        let old: Vec<&str> = old_secrets
+            .split(',')
+            .map(str::trim)
+            .filter(|secret| !secret.is_empty())
+            .collect();
+        JwtKeys::new(&primary, &old)
+    }
+
+    /// Sign a value that isn't a JWT (e.g. a cookie) with the primary key
+    ///
+    /// `purpose` keeps a signature made for one use from verifying for another.
+    /// Returns a hex HMAC-SHA256 truncated to 128 bits.
+    pub fn sign_value(&self, purpose: &str, value: &str) -> String {
+        self.primary.value_mac(purpose, value)
+    }
+
+    /// Whether `signature` is what `sign_value` gives under any key, old ones included
+    pub fn verify_value(&self, purpose: &str, value: &str, signature: &str) -> bool {
+        // A shorter signature would be easier to guess, so only the full length counts
+        let signature = match hex::decode(signature) {
+            Ok(signature) if signature.len() == VALUE_MAC_BYTES => signature,
+            _ => return false,
+        };
+        std::iter::once(&self.primary)
+            .chain(&self.old)
+            .any(|key| key.value_hmac(purpose, value).verify_truncated_left(&signature).is_ok())
+    }
+
+    /// Keys to try for a token: the one its `kid` names, or every key for
+    /// tokens without a known `kid` (such as those issued before rotation support)
+    fn candidates(&self, kid: Option<&str>) -> Vec<&JwtKey> {
+        let keys = std::iter::once(&self.primary).chain(&self.old);
+        match kid.and_then(|kid| keys.clone().find(|key| key.kid == kid)) {
+            Some(key) => vec![key],
+            None => keys.collect(),
+        }
+    }
+}
+
 /// Hash a password using bcrypt
 ///
 /// # Arguments
@@ -37,6 +141,26 @@ pub fn verify_password(password: &str, hash: &str) -> Result<bool, bcrypt::Bcryp
     verify(password, hash)
 }
 
+/// Bcrypt hash of a throwaway password, created once at the same cost as real hashes
+fn dummy_password_hash() -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    DUMMY_HASH.get_or_init(|| {
+        hash(Uuid::new_v4().to_string(), DEFAULT_COST).expect("Failed to create dummy password hash")
+    })
+}
+
+/// Burn the same bcrypt work as `verify_password` for a login whose user does not exist
+///
+/// Without this, an unknown username returns immediately while a known one
+/// pays the full bcrypt cost, so response times reveal which usernames exist.
+/// The result is always a failed match.
+///
+/// # Arguments
+/// * `password` - Plain text password from the login attempt
+pub fn verify_dummy_password(password: &str) {
+    let _ = verify(password, dummy_password_hash());
+}
+
 /// Generate a JWT token for a user
 ///
 /// # Arguments
@@ -46,7 +170,6 @@ pub fn verify_password(password: &str, hash: &str) -> Result<bool, bcrypt::Bcryp
 /// # Returns
 /// Result containing the JWT token string or an error
 pub fn generate_token(user_id: Uuid, username: &str) -> Result<String, jsonwebtoken::errors::Error> {
-    let jwt_secret = env::var("JWT_SECRET").expect("JWT_SECRET must be set in .env file");
     let expiration_hours = env::var("JWT_EXPIRATION_HOURS")
         .unwrap_or_else(|_| "24".to_string())
         .parse::<i64>()
@@ -64,11 +187,16 @@ pub fn generate_token(user_id: Uuid, username: &str) -> Result<String, jsonwebto
         iat,
     };
 
-    encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(jwt_secret.as_bytes()),
-    )
+    encode_claims(&JwtKeys::from_env(), &claims)
+}
+
+/// Sign claims with the primary key, naming it in the `kid` header
+pub fn encode_claims(keys: &JwtKeys, claims: &Claims) -> Result<String, jsonwebtoken::errors::Error> {
+    let header = Header {
+        kid: Some(keys.primary.kid.clone()),
+        ..Header::default()
+    };
+    encode(&header, claims, &EncodingKey::from_secret(keys.primary.secret.as_bytes()))
 }
 
 /// Validate and decode a JWT token
@@ -79,29 +207,64 @@ pub fn generate_token(user_id: Uuid, username: &str) -> Result<String, jsonwebto
 /// # Returns
 /// Result containing the Claims if valid, or an error
 pub fn validate_token(token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
-    let jwt_secret = env::var("JWT_SECRET").expect("JWT_SECRET must be set in .env file");
+    decode_claims(&JwtKeys::from_env(), token, jwt_leeway_secs())
+}
 
-    let token_data = decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(jwt_secret.as_bytes()),
-        &Validation::default(),
-    )?;
+/// Seconds a token is still accepted after it expires, by default
+///
+/// Matches `jsonwebtoken`'s own default, so leaving `JWT_LEEWAY_SECS` unset changes nothing.
+pub const DEFAULT_JWT_LEEWAY_SECS: u64 = 60;
 
-    Ok(token_data.claims)
+/// Allowance for clock skew between hosts when checking expiry (`JWT_LEEWAY_SECS`)
+pub fn jwt_leeway_secs() -> u64 {
+    env::var("JWT_LEEWAY_SECS")
+        .ok()
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .unwrap_or(DEFAULT_JWT_LEEWAY_SECS)
 }
 
-/// Extract the user ID from a JWT token
+/// Verify a token against the key its `kid` names, or else each key in turn,
+/// accepting it up to `leeway_secs` after it expires
+///
+/// Only a bad signature moves on to the next key; a token that verifies but
+/// has expired is rejected straight away.
+pub fn decode_claims(keys: &JwtKeys, token: &str, leeway_secs: u64) -> Result<Claims, jsonwebtoken::errors::Error> {
+    let kid = decode_header(token)?.kid;
+    let mut validation = Validation::default();
+    validation.leeway = leeway_secs;
+
+    let mut result = Err(ErrorKind::InvalidSignature.into());
+    for key in keys.candidates(kid.as_deref()) {
+        result = decode::<Claims>(token, &DecodingKey::from_secret(key.secret.as_bytes()), &validation)
+            .map(|token_data| token_data.claims);
+        match &result {
+            Err(e) if *e.kind() == ErrorKind::InvalidSignature => continue,
+            _ => break,
+        }
+    }
+    result
+}
+
+/// Generate a random device token for a kiosk tablet
+///
+/// # Returns
+/// A 64 character hex string, shown to the admin once and never stored
+pub fn generate_kiosk_token() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+/// Hash a kiosk token for storage and lookup
+///
+/// Kiosk tokens are long random values rather than passwords, so a fast
+/// unsalted SHA-256 is enough and lets the hash be used as a lookup key.
 ///
 /// # Arguments
-/// * `token` - JWT token string
+/// * `token` - Plain kiosk token from the device cookie
 ///
 /// # Returns
-/// Result containing the user UUID if valid, or an error
-pub fn get_user_id_from_token(token: &str) -> Result<Uuid, String> {
-    match validate_token(token) {
-        Ok(claims) => Uuid::parse_str(&claims.sub).map_err(|e| e.to_string()),
-        Err(e) => Err(format!("Invalid token: {}", e)),
-    }
+/// Hex-encoded SHA-256 digest of the token
+pub fn hash_kiosk_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
 }
 
 #[cfg(test)]
@@ -117,6 +280,18 @@ mod tests {
         assert!(!verify_password("wrong_password", &hashed).expect("Failed to verify password"));
     }
 
+    #[test]
+    fn test_dummy_password_hash_matches_real_cost() {
+        // The dummy hash only hides unknown usernames if verifying against it
+        // costs the same as verifying a real password hash
+        let real = hash_password("test_password_123").expect("Failed to hash password");
+        let dummy = dummy_password_hash();
+
+        assert_eq!(&dummy[..7], &real[..7]);
+        assert!(!verify_password("test_password_123", dummy).expect("Failed to verify password"));
+        verify_dummy_password("test_password_123");
+    }
+
     #[test]
     fn test_token_generation_and_validation() {
         // Set up environment variable for test
@@ -157,4 +332,96 @@ mod tests {
         // This should fail because the token is expired
         assert!(validate_token(&token).is_err());
     }
+
+    #[test]
+    fn test_leeway_accepts_tokens_just_past_expiry() {
+        let keys = JwtKeys::new("leeway_secret", &[]);
+        let expired_secs_ago = |secs: i64| {
+            let claims = Claims {
+                sub: Uuid::new_v4().to_string(),
+                username: "skewed".to_string(),
+                exp: (Utc::now() - Duration::seconds(secs)).timestamp() as usize,
+                iat: (Utc::now() - Duration::hours(1)).timestamp() as usize,
+            };
+            encode_claims(&keys, &claims).expect("Failed to sign token")
+        };
+
+        let token = expired_secs_ago(20);
+        assert!(decode_claims(&keys, &token, 30).is_ok());
+        let error = decode_claims(&keys, &token, 5).expect_err("Token is past the leeway");
+        assert_eq!(*error.kind(), ErrorKind::ExpiredSignature);
+
+        let token = expired_secs_ago(120);
+        assert!(decode_claims(&keys, &token, DEFAULT_JWT_LEEWAY_SECS).is_err());
+    }
+
+    #[test]
+    fn test_rotated_secret_still_verifies_old_tokens() {
+        let claims = |username: &str| Claims {
+            sub: Uuid::new_v4().to_string(),
+            username: username.to_string(),
+            exp: (Utc::now() + Duration::hours(1)).timestamp() as usize,
+            iat: Utc::now().timestamp() as usize,
+        };
+
+        let before = JwtKeys::new("old_secret", &[]);
+        let old_token = encode_claims(&before, &claims("before")).expect("Failed to sign token");
+        // Tokens from before kid headers were added
+        let legacy_token = encode(&Header::default(), &claims("legacy"), &EncodingKey::from_secret(b"old_secret"))
+            .expect("Failed to sign token");
+
+        let rotated = JwtKeys::new("new_secret", &["old_secret"]);
+        let new_token = encode_claims(&rotated, &claims("after")).expect("Failed to sign token");
+        assert_eq!(decode_header(&new_token).unwrap().kid, Some(JwtKey::new("new_secret").kid));
+
+        assert_eq!(decode_claims(&rotated, &old_token, 0).unwrap().username, "before");
+        assert_eq!(decode_claims(&rotated, &legacy_token, 0).unwrap().username, "legacy");
+        assert_eq!(decode_claims(&rotated, &new_token, 0).unwrap().username, "after");
+
+        // Once the old secret is dropped, its tokens stop working
+        let retired = JwtKeys::new("new_secret", &[]);
+        assert!(decode_claims(&retired, &old_token, 0).is_err());
+        assert!(decode_claims(&retired, &new_token, 0).is_ok());
+        assert!(decode_claims(&before, &new_token, 0).is_err());
+    }
+
+    #[test]
+    fn test_signed_values_verify_under_any_key_and_purpose_only() {
+        let old = JwtKeys::new("old_secret", &[]);
+        let keys = JwtKeys::new("new_secret", &["old_secret"]);
+
+        let signature = keys.sign_value("cookie", "value");
+        assert_eq!(signature.len(), VALUE_MAC_BYTES * 2);
+        assert!(keys.verify_value("cookie", "value", &signature));
+        assert!(keys.verify_value("cookie", "value", &old.sign_value("cookie", "value")));
+
+        assert!(!keys.verify_value("cookie", "other value", &signature));
+        assert!(!keys.verify_value("other purpose", "value", &signature));
+        assert!(!keys.verify_value("cookie", "value", &signature[..8]));
+        assert!(!keys.verify_value("cookie", "value", "not hex"));
+        assert!(!JwtKeys::new("unrelated", &[]).verify_value("cookie", "value", &signature));
+    }
+
+    #[test]
+    fn test_kiosk_token_generation() {
+        let token = generate_kiosk_token();
+
+        assert_eq!(token.len(), 64);
+        assert!(token.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(token, generate_kiosk_token());
+    }
+
+    #[test]
+    fn test_kiosk_token_hashing() {
+        let token = generate_kiosk_token();
+        let hashed = hash_kiosk_token(&token);
+
+        assert_eq!(hashed, hash_kiosk_token(&token));
+        assert_ne!(hashed, token);
+        assert_ne!(hashed, hash_kiosk_token(&generate_kiosk_token()));
+        assert_eq!(
+            hash_kiosk_token("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
 }
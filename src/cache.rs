@@ -0,0 +1,212 @@
+use crate::events::{DomainEvent, Subscriber};
+use crate::models::ProductSummary;
+use crate::utils;
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+/// The homepage product list, kept for a few seconds so bursts of visits share one query
+///
+/// Subscribed to the event dispatcher, so a product change clears it and the next
+/// visit sees the change rather than waiting out the TTL. Concurrent misses wait
+/// for a single load instead of each querying the database.
+#[derive(Clone)]
+pub struct ProductListCache {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    ttl: Duration,
+    state: RwLock<State>,
+    /// Held while loading, so concurrent misses wait for one query
+    loading: tokio::sync::Mutex<()>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Bumped on every invalidation, so a load that overlapped one isn't stored
+    generation: u64,
+    entry: Option<(Instant, Arc<Vec<ProductSummary>>)>,
+}
+
+impl ProductListCache {
+    /// Keep the list for `ttl`; zero turns caching off
+    pub fn new(ttl: Duration) -> ProductListCache {
+        ProductListCache {
+            inner: Arc::new(Inner {
+                ttl,
+                state: RwLock::new(State::default()),
+                loading: tokio::sync::Mutex::new(()),
+            }),
+        }
+    }
+
+    /// TTL from `HOMEPAGE_CACHE_SECS` (default 10, 0 disables)
+    pub fn from_env() -> ProductListCache {
+        ProductListCache::new(utils::env_secs("HOMEPAGE_CACHE_SECS", 10))
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.inner.ttl
+    }
+
+    /// The cached list, or the result of `load` when it is missing or stale
+    ///
+    /// A failed load is returned as is and not cached.
+    pub async fn get_or_load<F, Fut, E>(&self, load: F) -> Result<Arc<Vec<ProductSummary>>, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Vec<ProductSummary>, E>>,
+    {
+        if self.inner.ttl.is_zero() {
+            return load().await.map(Arc::new);
+        }
+        if let Some(products) = self.fresh() {
+            return Ok(products);
+        }
+
+        let _loading = self.inner.loading.lock().await;
+        // Another request may have loaded it while this one waited
+        if let Some(products) = self.fresh() {
+            return Ok(products);
+        }
+        let generation = self.inner.state.read().unwrap().generation;
+        let products = Arc::new(load().await?);
+
+        let mut state = self.inner.state.write().unwrap();
+        if state.generation == generation {
+            state.entry = Some((Instant::now(), products.clone()));
+        }
+        Ok(products)
+    }
+
+    /// Drop the cached list; the next visit loads it again
+    pub fn invalidate(&self) {
+        let mut state = self.inner.state.write().unwrap();
+        state.generation += 1;
+        state.entry = None;
+    }
+
+    fn fresh(&self) -> Option<Arc<Vec<ProductSummary>>> {
+        let state = self.inner.state.read().unwrap();
+        state
+            .entry
+            .as_ref()
+            .filter(|(loaded_at, _)| loaded_at.elapsed() < self.inner.ttl)
+            .map(|(_, products)| products.clone())
+    }
+}
+
+/// Whether an event can change what the homepage product list shows
+///
+/// Every event is listed, so adding one means deciding whether it matters here.
+fn changes_product_list(event: &DomainEvent) -> bool {
+    match event {
+        DomainEvent::ProductCreated { .. }
+        | DomainEvent::ProductUpdated { .. }
+        | DomainEvent::ProductMerged { .. }
+        | DomainEvent::SuppliersMerged { .. }
+        | DomainEvent::LocationRenamed { .. }
+        | DomainEvent::ImageUrlsRewritten { .. } => true,
+        DomainEvent::PreparationCreated { .. }
+        | DomainEvent::PreparationUpdated { .. }
+        | DomainEvent::PreparationArchived { .. }
+        | DomainEvent::PreparationRestored { .. }
+        | DomainEvent::ProductsTagged { .. }
+        | DomainEvent::StepNumbersRepaired { .. }
+        | DomainEvent::LocationCreated { .. }
+        | DomainEvent::LocationDeleted { .. }
+        | DomainEvent::SupplierScheduleChanged { .. }
+        | DomainEvent::DatabaseBackedUp { .. }
+        | DomainEvent::DatabaseBackupFailed { .. } => false,
+    }
+}
+
+impl Subscriber for ProductListCache {
+    fn name(&self) -> &'static str {
+        "homepage-cache"
+    }
+
+    fn handle(&self, event: &DomainEvent, _actor: Option<&str>) -> Result<(), String> {
+        if changes_product_list(event) {
+            self.invalidate();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventDispatcher;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use uuid::Uuid;
+
+    /// Load the list through `cache`, counting how often the database would be queried
+    async fn load(cache: &ProductListCache, loads: &AtomicUsize) -> Arc<Vec<ProductSummary>> {
+        cache
+            .get_or_load(|| async {
+                loads.fetch_add(1, Ordering::SeqCst);
+                Ok::<_, ()>(Vec::new())
+            })
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_product_events_invalidate_the_list() {
+        let cache = ProductListCache::new(Duration::from_secs(60));
+        let events = EventDispatcher::new().subscribe(cache.clone());
+        let loads = AtomicUsize::new(0);
+
+        load(&cache, &loads).await;
+        load(&cache, &loads).await;
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+
+        // Preparations aren't in the product list
+        events.emit(DomainEvent::PreparationCreated { id: Uuid::new_v4() }, None);
+        load(&cache, &loads).await;
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+
+        // A new product must show up on the next visit
+        events.emit(DomainEvent::ProductCreated { id: Uuid::new_v4() }, Some("sam"));
+        load(&cache, &loads).await;
+        assert_eq!(loads.load(Ordering::SeqCst), 2);
+        load(&cache, &loads).await;
+        assert_eq!(loads.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_load_overlapping_an_invalidation_is_not_kept() {
+        let cache = ProductListCache::new(Duration::from_secs(60));
+        let loads = AtomicUsize::new(0);
+
+        // The product is saved while the list is being read
+        cache
+            .get_or_load(|| async {
+                cache.invalidate();
+                Ok::<_, ()>(Vec::new())
+            })
+            .await
+            .unwrap();
+        load(&cache, &loads).await;
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+
+        // Failed loads aren't cached either
+        cache.invalidate();
+        let failed = cache.get_or_load(|| async { Err::<Vec<ProductSummary>, _>("database down") }).await;
+        assert!(failed.is_err());
+        load(&cache, &loads).await;
+        assert_eq!(loads.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_zero_ttl_disables_caching() {
+        let cache = ProductListCache::new(Duration::ZERO);
+        let loads = AtomicUsize::new(0);
+
+        load(&cache, &loads).await;
+        load(&cache, &loads).await;
+        assert_eq!(loads.load(Ordering::SeqCst), 2);
+    }
+}
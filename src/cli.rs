@@ -0,0 +1,475 @@
+use crate::models::{ImageReference, PreparationStep, User, UserExport, UserImportReport};
+use crate::storage::{self, MediaBackend, MediaMigrationReport};
+use crate::utils;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Usage text printed when a subcommand is given bad arguments
+pub const USAGE: &str = "Usage:
+  kitchen-hand-guide                                  Start the web server
+  kitchen-hand-guide export-users --out <file>        Dump users (with password hashes) to a JSON file
+  kitchen-hand-guide import-users --in <file> [--allow-role-downgrade]
+                                                      Insert users from an export, skipping existing ones
+  kitchen-hand-guide migrate-media --from <local|s3[:bucket]> --to <local|s3[:bucket]>
+                     [--dry-run] [--concurrency <n>]
+                                                      Copy uploaded images to another storage backend
+                                                      and point the database at the copies
+  kitchen-hand-guide migrate-images-to-s3 [--dry-run] [--concurrency <n>]
+                                                      Same as migrate-media --from local --to s3
+  kitchen-hand-guide fsck [--fix]                     Check step numbering, orphaned steps and missing
+                                                      local pictures; --fix repairs the steps";
+
+/// Images moved at once by migrate-media unless --concurrency says otherwise
+const DEFAULT_MIGRATION_CONCURRENCY: usize = 4;
+
+/// Maintenance subcommands run against DATABASE_URL instead of starting the server
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    ExportUsers { out: PathBuf },
+    ImportUsers { input: PathBuf, allow_role_downgrade: bool },
+    MigrateMedia { from: MediaLocation, to: MediaLocation, dry_run: bool, concurrency: usize },
+    Fsck { fix: bool },
+}
+
+/// A storage backend named on the command line
+#[derive(Debug, Clone, PartialEq)]
+pub enum MediaLocation {
+    /// The upload directory (`UPLOAD_DIR`)
+    Local,
+    /// An S3 bucket, `S3_BUCKET_NAME` unless one is named (`s3:<bucket>`)
+    S3 { bucket: Option<String> },
+}
+
+impl MediaLocation {
+    fn parse(value: &str) -> Result<MediaLocation, String> {
+        match value.split_once(':') {
+            None if value == "local" => Ok(MediaLocation::Local),
+            None if value == "s3" => Ok(MediaLocation::S3 { bucket: None }),
+            Some(("s3", bucket)) if !bucket.is_empty() => Ok(MediaLocation::S3 { bucket: Some(bucket.to_string()) }),
+            _ => Err(format!("Unknown storage '{}', expected local, s3 or s3:<bucket>", value)),
+        }
+    }
+
+    /// The backend this names, using the same settings as uploads
+    async fn backend(&self) -> MediaBackend {
+        match self {
+            MediaLocation::Local => MediaBackend::local(utils::upload_dir()),
+            MediaLocation::S3 { bucket } => MediaBackend::S3 {
+                client: utils::init_s3_client().await,
+                bucket: bucket.clone().unwrap_or_else(|| {
+                    std::env::var("S3_BUCKET_NAME").unwrap_or_else(|_| "kitchen-hand-guide".to_string())
+                }),
+                region: std::env::var("AWS_REGION").unwrap_or_else(|_| "ap-southeast-2".to_string()),
+                layout: utils::S3KeyLayout::from_env(),
+            },
+        }
+    }
+}
+
+impl Command {
+    /// Parse the arguments after the program name; Ok(None) means start the server
+    pub fn parse(args: &[String]) -> Result<Option<Command>, String> {
+        let Some((name, rest)) = args.split_first() else {
+            return Ok(None);
+        };
+        if name == "migrate-media" {
+            return Command::parse_migrate_media(rest).map(Some);
+        }
+        if name == "migrate-images-to-s3" {
+            // The usual move when a kitchen that started on local storage turns S3 on
+            if let Some(flag) = rest.iter().find(|arg| matches!(arg.as_str(), "--from" | "--to")) {
+                return Err(format!("Unexpected argument '{}' for migrate-images-to-s3", flag));
+            }
+            let args: Vec<String> = ["--from", "local", "--to", "s3"]
+                .into_iter()
+                .map(String::from)
+                .chain(rest.iter().cloned())
+                .collect();
+            return Command::parse_migrate_media(&args).map(Some);
+        }
+        if name == "fsck" {
+            return match rest {
+                [] => Ok(Some(Command::Fsck { fix: false })),
+                [flag] if flag == "--fix" => Ok(Some(Command::Fsck { fix: true })),
+                [other, ..] => Err(format!("Unexpected argument '{}' for fsck", other)),
+            };
+        }
+
+        let mut path = None;
+        let mut allow_role_downgrade = false;
+        let path_flag = match name.as_str() {
+            "export-users" => "--out",
+            "import-users" => "--in",
+            other => return Err(format!("Unknown command '{}'", other)),
+        };
+
+        let mut rest = rest.iter();
+        while let Some(arg) = rest.next() {
+            match arg.as_str() {
+                flag if flag == path_flag => {
+                    let value = rest.next().ok_or_else(|| format!("{} needs a file path", flag))?;
+                    path = Some(PathBuf::from(value));
+                }
+                "--allow-role-downgrade" if name == "import-users" => allow_role_downgrade = true,
+                other => return Err(format!("Unexpected argument '{}' for {}", other, name)),
+            }
+        }
+        let path = path.ok_or_else(|| format!("{} requires {} <file>", name, path_flag))?;
+
+        Ok(Some(if name == "export-users" {
+            Command::ExportUsers { out: path }
+        } else {
+            Command::ImportUsers { input: path, allow_role_downgrade }
+        }))
+    }
+
+    fn parse_migrate_media(args: &[String]) -> Result<Command, String> {
+        let mut from = None;
+        let mut to = None;
+        let mut dry_run = false;
+        let mut concurrency = DEFAULT_MIGRATION_CONCURRENCY;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                flag @ ("--from" | "--to" | "--concurrency") => {
+                    let value = args.next().ok_or_else(|| format!("{} needs a value", flag))?;
+                    match flag {
+                        "--from" => from = Some(MediaLocation::parse(value)?),
+                        "--to" => to = Some(MediaLocation::parse(value)?),
+                        _ => {
+                            concurrency = value
+                                .parse()
+                                .ok()
+                                .filter(|n| *n > 0)
+                                .ok_or_else(|| format!("--concurrency must be a positive number, got '{}'", value))?
+                        }
+                    }
+                }
+                "--dry-run" => dry_run = true,
+                other => return Err(format!("Unexpected argument '{}' for migrate-media", other)),
+            }
+        }
+
+        let from = from.ok_or("migrate-media requires --from <local|s3[:bucket]>")?;
+        let to = to.ok_or("migrate-media requires --to <local|s3[:bucket]>")?;
+        if from == to {
+            return Err("migrate-media needs different --from and --to storage".to_string());
+        }
+        Ok(Command::MigrateMedia { from, to, dry_run, concurrency })
+    }
+}
+
+/// Run a subcommand to completion
+pub async fn run(command: Command, pool: &sqlx::PgPool) -> Result<(), String> {
+    match command {
+        Command::ExportUsers { out } => export_users(pool, &out).await,
+        Command::ImportUsers { input, allow_role_downgrade } => {
+            import_users(pool, &input, allow_role_downgrade).await
+        }
+        Command::MigrateMedia { from, to, dry_run, concurrency } => {
+            migrate_media(pool, &from, &to, dry_run, concurrency).await
+        }
+        Command::Fsck { fix } => fsck(pool, fix).await,
+    }
+}
+
+/// Login name of the admin that `ADMIN_BOOTSTRAP_PASSWORD` creates
+pub const BOOTSTRAP_ADMIN_USERNAME: &str = "admin";
+
+/// Email of the bootstrapped admin, the same placeholder schema.sql seeds
+const BOOTSTRAP_ADMIN_EMAIL: &str = "admin@kitchen-hand.local";
+
+/// First run: while there are no users at all, create the initial admin with `password`
+///
+/// Returns the new admin's username, or None when users already exist.
+pub async fn bootstrap_admin(pool: &sqlx::PgPool, password: &str) -> Result<Option<String>, String> {
+    if password.len() < 6 {
+        return Err("the password must be at least 6 characters".to_string());
+    }
+    let password_hash = crate::auth::hash_password(password).map_err(|e| format!("Failed to hash password: {}", e))?;
+
+    let admin = User::create_first_admin(pool, BOOTSTRAP_ADMIN_USERNAME, BOOTSTRAP_ADMIN_EMAIL, &password_hash)
+        .await
+        .map_err(|e| format!("Failed to create admin: {}", e))?;
+    Ok(admin.map(|admin| admin.username))
+}
+
+async fn export_users(pool: &sqlx::PgPool, out: &PathBuf) -> Result<(), String> {
+    let export = UserExport::load(pool)
+        .await
+        .map_err(|e| format!("Failed to load users: {}", e))?;
+    let json = serde_json::to_vec_pretty(&export)
+        .map_err(|e| format!("Failed to serialize users: {}", e))?;
+
+    // The file holds password hashes: never overwrite an existing file and keep it owner-only
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options
+        .open(out)
+        .map_err(|e| format!("Failed to create {}: {}", out.display(), e))?;
+    file.write_all(&json)
+        .map_err(|e| format!("Failed to write {}: {}", out.display(), e))?;
+
+    println!("Exported {} users to {}", export.users.len(), out.display());
+    Ok(())
+}
+
+async fn import_users(
+    pool: &sqlx::PgPool,
+    input: &PathBuf,
+    allow_role_downgrade: bool,
+) -> Result<(), String> {
+    let json = std::fs::read_to_string(input)
+        .map_err(|e| format!("Failed to read {}: {}", input.display(), e))?;
+    let export = UserExport::from_json(&json)?;
+
+    let report = export
+        .import(pool, allow_role_downgrade)
+        .await
+        .map_err(|e| format!("Import rolled back, {}", e))?;
+
+    print_import_report(&report);
+    Ok(())
+}
+
+/// Print what the import did, by username only (hashes are never logged)
+fn print_import_report(report: &UserImportReport) {
+    let sections = [
+        ("Inserted", &report.inserted),
+        ("Skipped, username exists", &report.skipped_username),
+        ("Skipped, email exists", &report.skipped_email),
+        ("Downgraded from admin", &report.downgraded),
+    ];
+    for (label, usernames) in sections {
+        if usernames.is_empty() {
+            println!("{}: 0", label);
+        } else {
+            println!("{}: {} ({})", label, usernames.len(), usernames.join(", "));
+        }
+    }
+}
+
+async fn migrate_media(
+    pool: &sqlx::PgPool,
+    from: &MediaLocation,
+    to: &MediaLocation,
+    dry_run: bool,
+    concurrency: usize,
+) -> Result<(), String> {
+    let from = from.backend().await;
+    let to = to.backend().await;
+    if from.describe() == to.describe() {
+        return Err(format!("--from and --to are both {}", from.describe()));
+    }
+
+    println!(
+        "{} images from {} to {}, {} at a time",
+        if dry_run { "Checking" } else { "Moving" },
+        from.describe(),
+        to.describe(),
+        concurrency
+    );
+    let report = storage::migrate_media(pool, &from, &to, concurrency, dry_run).await?;
+    print_migration_report(&report, dry_run);
+
+    if report.failed.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("{} images failed to move; run again to retry them", report.failed.len()))
+    }
+}
+
+/// Print totals, then every URL that needs a look
+fn print_migration_report(report: &MediaMigrationReport, dry_run: bool) {
+    println!("{}: {}", if dry_run { "Would migrate" } else { "Migrated" }, report.migrated.len());
+    println!("Skipped, already on target: {}", report.already_on_target.len());
+    println!("Skipped, not stored in source: {}", report.not_in_source.len());
+    println!("Missing from source: {}", report.missing.len());
+    for url in &report.missing {
+        println!("  {}", url);
+    }
+    println!("Failed: {}", report.failed.len());
+    for (url, error) in &report.failed {
+        println!("  {}: {}", url, error);
+    }
+    if !dry_run && !report.migrated.is_empty() {
+        println!("Source files were left in place; remove them once the new URLs check out");
+    }
+}
+
+/// Report data problems and, with `fix`, repair the step ones in one transaction
+///
+/// Orphaned steps are deleted and misnumbered preparations renumbered 1..N.
+/// Missing local pictures are only listed: they need re-uploading, and clearing
+/// the URL would lose the record of which picture it was.
+async fn fsck(pool: &sqlx::PgPool, fix: bool) -> Result<(), String> {
+    let misnumbered = PreparationStep::misnumbered_preparation_ids(pool)
+        .await
+        .map_err(|e| format!("Failed to check step numbers: {}", e))?;
+    let orphaned = PreparationStep::orphaned(pool)
+        .await
+        .map_err(|e| format!("Failed to check for orphaned steps: {}", e))?;
+    let missing_pictures = missing_local_pictures(pool).await?;
+
+    println!("Preparations with gaps in their step numbers: {}", misnumbered.len());
+    for id in &misnumbered {
+        println!("  {}", id);
+    }
+    println!("Steps whose preparation no longer exists: {}", orphaned.len());
+    for (step_id, preparation_id) in &orphaned {
+        println!("  step {} (preparation {})", step_id, preparation_id);
+    }
+    println!("Pictures missing from local storage: {}", missing_pictures.len());
+    for (image, problem) in &missing_pictures {
+        println!("  {} {}: {} ({})", image.source_table, image.source_id, image.picture_url, problem);
+    }
+
+    let step_problems = misnumbered.len() + orphaned.len();
+    if fix && step_problems > 0 {
+        let mut tx = pool.begin().await.map_err(|e| format!("Failed to start transaction: {}", e))?;
+        // Orphans go first so they aren't renumbered for nothing
+        let deleted = PreparationStep::delete_orphaned(&mut *tx)
+            .await
+            .map_err(|e| format!("Failed to delete orphaned steps: {}", e))?;
+        let misnumbered = PreparationStep::misnumbered_preparation_ids(&mut *tx)
+            .await
+            .map_err(|e| format!("Failed to check step numbers: {}", e))?;
+        let mut renumbered = 0;
+        for &preparation_id in &misnumbered {
+            renumbered += PreparationStep::renumber(&mut *tx, preparation_id)
+                .await
+                .map_err(|e| format!("Failed to renumber steps of {}: {}", preparation_id, e))?;
+        }
+        tx.commit().await.map_err(|e| format!("Failed to commit repairs: {}", e))?;
+        println!(
+            "Fixed: deleted {} orphaned steps, renumbered {} steps in {} preparations",
+            deleted,
+            renumbered,
+            misnumbered.len()
+        );
+    }
+
+    if step_problems > 0 && !fix {
+        return Err(format!("Found {} step problems; run fsck --fix to repair them", step_problems));
+    }
+    if !missing_pictures.is_empty() {
+        return Err(format!("{} pictures are missing and need uploading again", missing_pictures.len()));
+    }
+    if step_problems == 0 {
+        println!("No problems found");
+    }
+    Ok(())
+}
+
+/// Product, preparation and step pictures kept locally whose file isn't there, with why
+async fn missing_local_pictures(pool: &sqlx::PgPool) -> Result<Vec<(ImageReference, String)>, String> {
+    let images = ImageReference::all(pool)
+        .await
+        .map_err(|e| format!("Failed to list pictures: {}", e))?;
+
+    let mut missing = Vec::new();
+    for image in images {
+        let Some(path) = utils::local_image_path(&image.picture_url) else {
+            continue;
+        };
+        let problem = match path {
+            Ok(path) => match tokio::fs::try_exists(&path).await {
+                Ok(true) => continue,
+                Ok(false) => format!("no file at {}", path.display()),
+                Err(e) => format!("can't check {}: {}", path.display(), e),
+            },
+            Err(e) => e,
+        };
+        missing.push((image, problem));
+    }
+    Ok(missing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_user_transfer_commands() {
+        assert_eq!(Command::parse(&[]), Ok(None));
+        assert_eq!(
+            Command::parse(&args(&["export-users", "--out", "users.json"])),
+            Ok(Some(Command::ExportUsers { out: PathBuf::from("users.json") }))
+        );
+        assert_eq!(
+            Command::parse(&args(&["import-users", "--allow-role-downgrade", "--in", "users.json"])),
+            Ok(Some(Command::ImportUsers {
+                input: PathBuf::from("users.json"),
+                allow_role_downgrade: true,
+            }))
+        );
+
+        assert!(Command::parse(&args(&["export-users"])).is_err());
+        assert!(Command::parse(&args(&["import-users", "--in"])).is_err());
+        assert!(Command::parse(&args(&["export-users", "--out", "u.json", "--allow-role-downgrade"])).is_err());
+        assert!(Command::parse(&args(&["serve"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_migrate_media() {
+        assert_eq!(
+            Command::parse(&args(&["migrate-media", "--from", "local", "--to", "s3", "--dry-run"])),
+            Ok(Some(Command::MigrateMedia {
+                from: MediaLocation::Local,
+                to: MediaLocation::S3 { bucket: None },
+                dry_run: true,
+                concurrency: DEFAULT_MIGRATION_CONCURRENCY,
+            }))
+        );
+        assert_eq!(
+            Command::parse(&args(&["migrate-media", "--concurrency", "8", "--from", "s3", "--to", "s3:new-bucket"])),
+            Ok(Some(Command::MigrateMedia {
+                from: MediaLocation::S3 { bucket: None },
+                to: MediaLocation::S3 { bucket: Some("new-bucket".to_string()) },
+                dry_run: false,
+                concurrency: 8,
+            }))
+        );
+
+        assert!(Command::parse(&args(&["migrate-media", "--from", "local"])).is_err());
+        assert!(Command::parse(&args(&["migrate-media", "--from", "local", "--to", "local"])).is_err());
+        assert!(Command::parse(&args(&["migrate-media", "--from", "local", "--to", "ftp"])).is_err());
+        assert!(Command::parse(&args(&["migrate-media", "--from", "local", "--to", "s3:"])).is_err());
+        assert!(Command::parse(&args(&["migrate-media", "--from", "local", "--to", "s3", "--concurrency", "0"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_migrate_images_to_s3() {
+        assert_eq!(
+            Command::parse(&args(&["migrate-images-to-s3", "--dry-run"])),
+            Command::parse(&args(&["migrate-media", "--from", "local", "--to", "s3", "--dry-run"]))
+        );
+        assert_eq!(
+            Command::parse(&args(&["migrate-images-to-s3", "--concurrency", "2"])),
+            Ok(Some(Command::MigrateMedia {
+                from: MediaLocation::Local,
+                to: MediaLocation::S3 { bucket: None },
+                dry_run: false,
+                concurrency: 2,
+            }))
+        );
+
+        assert!(Command::parse(&args(&["migrate-images-to-s3", "--to", "s3:other"])).is_err());
+        assert!(Command::parse(&args(&["migrate-images-to-s3", "--concurrency"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_fsck() {
+        assert_eq!(Command::parse(&args(&["fsck"])), Ok(Some(Command::Fsck { fix: false })));
+        assert_eq!(Command::parse(&args(&["fsck", "--fix"])), Ok(Some(Command::Fsck { fix: true })));
+        assert!(Command::parse(&args(&["fsck", "--dry-run"])).is_err());
+        assert!(Command::parse(&args(&["fsck", "--fix", "--fix"])).is_err());
+    }
+}
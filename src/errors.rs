@@ -0,0 +1,335 @@
+use actix_web::{http::StatusCode, HttpRequest, HttpResponse, ResponseError};
+use serde::Serialize;
+use std::fmt;
+
+/// Stable error codes returned by every `/api` route
+///
+/// These strings are part of the API contract: clients branch on them, so
+/// never rename one. Add a new code instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    ValidationFailed,
+    NotFound,
+    Unauthorized,
+    Forbidden,
+    Conflict,
+    PayloadTooLarge,
+    RateLimited,
+    InternalError,
+}
+
+impl ErrorCode {
+    /// HTTP status sent with this code
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ErrorCode::ValidationFailed => StatusCode::BAD_REQUEST,
+            ErrorCode::NotFound => StatusCode::NOT_FOUND,
+            ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorCode::Forbidden => StatusCode::FORBIDDEN,
+            ErrorCode::Conflict => StatusCode::CONFLICT,
+            ErrorCode::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorCode::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            ErrorCode::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// A problem with one field of a form or request body
+///
+/// `code` is a stable machine-readable reason (e.g. `required`, `invalid_choice`)
+/// that clients can branch on or translate; `message` is the English text that
+/// HTML pages show. Like `ErrorCode`, codes are never renamed once published.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ValidationError {
+    pub field: String,
+    pub code: String,
+    pub message: String,
+}
+
+impl ValidationError {
+    pub fn new(
+        field: impl Into<String>,
+        code: impl Into<String>,
+        message: impl Into<String>,
+    ) -> ValidationError {
+        ValidationError {
+            field: field.into(),
+            code: code.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// The message, as HTML pages show it
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Error returned by API handlers, rendered as
+/// `{ "error": { "code", "message", "details": [{ "field", "code", "message" }] } }`
+#[derive(Debug)]
+pub struct ApiError {
+    code: ErrorCode,
+    message: String,
+    details: Vec<ValidationError>,
+}
+
+/// Wire format of an API error
+#[derive(Serialize)]
+struct ErrorEnvelope<'a> {
+    error: ErrorBody<'a>,
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    code: ErrorCode,
+    message: &'a str,
+    details: &'a [ValidationError],
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> ApiError {
+        ApiError {
+            code,
+            message: message.into(),
+            details: Vec::new(),
+        }
+    }
+
+    /// Request body failed validation; `details` lists each problem by field
+    pub fn validation(details: Vec<ValidationError>) -> ApiError {
+        ApiError {
+            code: ErrorCode::ValidationFailed,
+            message: "Validation failed".to_string(),
+            details,
+        }
+    }
+
+    pub fn not_found(message: impl Into<String>) -> ApiError {
+        ApiError::new(ErrorCode::NotFound, message)
+    }
+
+    pub fn unauthorized() -> ApiError {
+        ApiError::new(ErrorCode::Unauthorized, "Authentication required")
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> ApiError {
+        ApiError::new(ErrorCode::Forbidden, message)
+    }
+
+    pub fn rate_limited() -> ApiError {
+        ApiError::new(ErrorCode::RateLimited, "Too many requests, try again shortly")
+    }
+
+    /// Log the underlying cause and return a generic 500 that doesn't leak it
+    pub fn internal(message: impl Into<String>, cause: impl fmt::Debug) -> ApiError {
+        eprintln!("API error: {:?}", cause);
+        ApiError::new(ErrorCode::InternalError, message)
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.code.status()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(ErrorEnvelope {
+            error: ErrorBody {
+                code: self.code,
+                message: &self.message,
+                details: &self.details,
+            },
+        })
+    }
+}
+
+/// Database errors: unique violations become CONFLICT, anything else a logged 500
+impl From<sqlx::Error> for ApiError {
+    fn from(e: sqlx::Error) -> ApiError {
+        match &e {
+            sqlx::Error::Database(db) if db.is_unique_violation() => {
+                ApiError::new(ErrorCode::Conflict, "A record with these details already exists")
+            }
+            _ => ApiError::internal("Database error", e),
+        }
+    }
+}
+
+/// Whether a request is for the JSON API, so errors should use the envelope
+pub fn is_api_path(path: &str) -> bool {
+    path == "/api" || path.starts_with("/api/")
+}
+
+/// `JsonConfig` error handler: malformed or oversized JSON bodies as API errors
+pub fn json_error_handler(err: actix_web::error::JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    use actix_web::error::JsonPayloadError;
+
+    match err {
+        JsonPayloadError::Overflow { .. } | JsonPayloadError::OverflowKnownLength { .. } => {
+            ApiError::new(ErrorCode::PayloadTooLarge, "Request body is too large").into()
+        }
+        JsonPayloadError::ContentType => {
+            ApiError::new(ErrorCode::ValidationFailed, "Content-Type must be application/json").into()
+        }
+        JsonPayloadError::Deserialize(e) => ApiError::validation(vec![ValidationError::new("body", "invalid_json", e.to_string())]).into(),
+        other => ApiError::new(ErrorCode::ValidationFailed, other.to_string()).into(),
+    }
+}
+
+/// `PathConfig` error handler: unparseable ids under `/api` are NOT_FOUND envelopes,
+/// other pages keep actix's plain 404
+pub fn path_error_handler(err: actix_web::error::PathError, req: &HttpRequest) -> actix_web::Error {
+    if is_api_path(req.path()) {
+        ApiError::not_found("Resource not found").into()
+    } else {
+        actix_web::error::ErrorNotFound(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{web, App};
+    use actix_web::test::{call_service, init_service, read_body_json, try_call_service, TestRequest};
+    use serde_json::json;
+
+    async fn body_json(response: HttpResponse) -> serde_json::Value {
+        let bytes = actix_web::body::to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice(&bytes).expect("Error body is not JSON")
+    }
+
+    #[actix_web::test]
+    async fn test_error_envelope_shape_for_each_code() {
+        let cases = [
+            (ApiError::not_found("Preparation not found"), 404, "NOT_FOUND", "Preparation not found"),
+            (ApiError::unauthorized(), 401, "UNAUTHORIZED", "Authentication required"),
+            (ApiError::forbidden("Kiosk devices are read-only"), 403, "FORBIDDEN", "Kiosk devices are read-only"),
+            (ApiError::new(ErrorCode::Conflict, "Already exists"), 409, "CONFLICT", "Already exists"),
+            (ApiError::new(ErrorCode::PayloadTooLarge, "Too big"), 413, "PAYLOAD_TOO_LARGE", "Too big"),
+            (ApiError::rate_limited(), 429, "RATE_LIMITED", "Too many requests, try again shortly"),
+            (ApiError::internal("Database error", "boom"), 500, "INTERNAL_ERROR", "Database error"),
+        ];
+
+        for (error, status, code, message) in cases {
+            let response = error.error_response();
+            assert_eq!(response.status().as_u16(), status);
+            assert_eq!(
+                body_json(response).await,
+                json!({ "error": { "code": code, "message": message, "details": [] } })
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_validation_error_lists_field_details() {
+        let error = ApiError::validation(vec![
+            ValidationError::new("preparation.location", "required", "Location cannot be empty"),
+            ValidationError::new("steps[1].description", "required", "Step 2 has no description"),
+        ]);
+
+        let response = error.error_response();
+        assert_eq!(response.status().as_u16(), 400);
+        assert_eq!(
+            body_json(response).await,
+            json!({
+                "error": {
+                    "code": "VALIDATION_FAILED",
+                    "message": "Validation failed",
+                    "details": [
+                        { "field": "preparation.location", "code": "required", "message": "Location cannot be empty" },
+                        { "field": "steps[1].description", "code": "required", "message": "Step 2 has no description" }
+                    ]
+                }
+            })
+        );
+    }
+
+    #[derive(serde::Deserialize)]
+    struct Payload {
+        #[allow(dead_code)]
+        name: String,
+    }
+
+    async fn accept_payload(_payload: web::Json<Payload>) -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    #[actix_web::test]
+    async fn test_json_body_errors_use_envelope() {
+        let app = init_service(
+            App::new()
+                .app_data(
+                    web::JsonConfig::default()
+                        .limit(64)
+                        .error_handler(json_error_handler),
+                )
+                .route("/api/test", web::post().to(accept_payload)),
+        )
+        .await;
+
+        let request = TestRequest::post()
+            .uri("/api/test")
+            .insert_header(("Content-Type", "application/json"))
+            .set_payload(r#"{"nope": 1}"#)
+            .to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(response.status().as_u16(), 400);
+        let body: serde_json::Value = read_body_json(response).await;
+        assert_eq!(body["error"]["code"], "VALIDATION_FAILED");
+        assert_eq!(body["error"]["details"][0]["field"], "body");
+        assert_eq!(body["error"]["details"][0]["code"], "invalid_json");
+
+        let request = TestRequest::post()
+            .uri("/api/test")
+            .insert_header(("Content-Type", "application/json"))
+            .set_payload(format!(r#"{{"name": "{}"}}"#, "x".repeat(100)))
+            .to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(response.status().as_u16(), 413);
+        let body: serde_json::Value = read_body_json(response).await;
+        assert_eq!(
+            body,
+            json!({ "error": { "code": "PAYLOAD_TOO_LARGE", "message": "Request body is too large", "details": [] } })
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_unauthenticated_api_request_uses_envelope() {
+        let app = init_service(
+            App::new().service(
+                web::resource("/api/private")
+                    .route(web::get().to(HttpResponse::Ok))
+                    .wrap(crate::middleware::Authentication),
+            ),
+        )
+        .await;
+
+        let error = try_call_service(&app, TestRequest::get().uri("/api/private").to_request())
+            .await
+            .expect_err("Request without credentials should be rejected");
+        let response = error.error_response();
+        assert_eq!(response.status().as_u16(), 401);
+        assert_eq!(
+            body_json(response).await,
+            json!({ "error": { "code": "UNAUTHORIZED", "message": "Authentication required", "details": [] } })
+        );
+    }
+
+    #[test]
+    fn test_is_api_path() {
+        assert!(is_api_path("/api"));
+        assert!(is_api_path("/api/preparations/import"));
+        assert!(!is_api_path("/apiary"));
+        assert!(!is_api_path("/preparation/1"));
+    }
+}
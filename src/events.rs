@@ -0,0 +1,216 @@
+use std::fmt;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// A change to the guide, emitted by a handler once it has been committed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DomainEvent {
+    ProductCreated { id: Uuid },
+    ProductUpdated { id: Uuid },
+    PreparationCreated { id: Uuid },
+    PreparationUpdated { id: Uuid },
+    /// Taken out of the lists and search until restored, e.g. for the off season
+    PreparationArchived { id: Uuid },
+    PreparationRestored { id: Uuid },
+    /// A duplicate product, and everything attached to it, merged into another
+    ProductMerged { from: Uuid, into: Uuid },
+    /// Products from one supplier name moved to another
+    SuppliersMerged { from: String, into: String, products: u64 },
+    /// Tags added to (or, with `removed`, stripped from) products in bulk
+    ProductsTagged { tags: Vec<String>, products: u64, removed: bool },
+    /// Step numbers closed up in each of these preparations
+    StepNumbersRepaired { preparations: Vec<Uuid> },
+    LocationCreated { id: Uuid },
+    LocationRenamed { id: Uuid },
+    LocationDeleted { id: Uuid },
+    /// Stored image links moved from one base URL to another
+    ImageUrlsRewritten { rows: u64 },
+    /// A supplier's order cut-offs or delivery days set or cleared
+    SupplierScheduleChanged { supplier: String },
+    /// A database backup uploaded to the bucket, and how many old ones were pruned
+    DatabaseBackedUp { key: String, bytes: u64, pruned: usize },
+    DatabaseBackupFailed { error: String },
+}
+
+/// How the audit log and error reports describe an event
+impl fmt::Display for DomainEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DomainEvent::ProductCreated { id } => write!(f, "created product {}", id),
+            DomainEvent::ProductUpdated { id } => write!(f, "updated product {}", id),
+            DomainEvent::PreparationCreated { id } => write!(f, "created preparation {}", id),
+            DomainEvent::PreparationUpdated { id } => write!(f, "updated preparation {}", id),
+            DomainEvent::PreparationArchived { id } => write!(f, "archived preparation {}", id),
+            DomainEvent::PreparationRestored { id } => write!(f, "restored preparation {}", id),
+            DomainEvent::ProductMerged { from, into } => write!(f, "merged product {} into {}", from, into),
+            DomainEvent::SuppliersMerged { from, into, products } => {
+                write!(f, "merged supplier {:?} into {:?} ({} products)", from, into, products)
+            }
+            DomainEvent::ProductsTagged { tags, products, removed: false } => {
+                write!(f, "tagged {} products with {:?}", products, tags)
+            }
+            DomainEvent::ProductsTagged { tags, products, removed: true } => {
+                write!(f, "removed tags {:?} from {} products", tags, products)
+            }
+            DomainEvent::StepNumbersRepaired { preparations } => {
+                write!(f, "repaired step numbers in {} preparations", preparations.len())
+            }
+            DomainEvent::LocationCreated { id } => write!(f, "created location {}", id),
+            DomainEvent::LocationRenamed { id } => write!(f, "renamed location {}", id),
+            DomainEvent::LocationDeleted { id } => write!(f, "deleted location {}", id),
+            DomainEvent::ImageUrlsRewritten { rows } => write!(f, "rewrote {} image links", rows),
+            DomainEvent::SupplierScheduleChanged { supplier } => write!(f, "changed the ordering schedule of {:?}", supplier),
+            DomainEvent::DatabaseBackedUp { key, bytes, pruned } => {
+                write!(f, "backed up the database to {} ({} bytes, {} old backups deleted)", key, bytes, pruned)
+            }
+            DomainEvent::DatabaseBackupFailed { error } => write!(f, "failed to back up the database: {}", error),
+        }
+    }
+}
+
+/// Reacts to events, e.g. to invalidate a cache or record an audit trail
+///
+/// Runs on the request's thread after the change is committed, so slow work
+/// (like delivering a webhook) should be spawned rather than awaited.
+pub trait Subscriber: Send + Sync {
+    /// Name used when reporting a failure
+    fn name(&self) -> &'static str;
+
+    /// Handle an event; `actor` is the username behind it, if any
+    fn handle(&self, event: &DomainEvent, actor: Option<&str>) -> Result<(), String>;
+}
+
+/// Hands each event to every subscriber, in the order they subscribed
+///
+/// Built once in main.rs and shared through app data. A subscriber that fails
+/// or panics is reported and skipped; the others still get the event, and the
+/// request that emitted it isn't affected.
+#[derive(Clone, Default)]
+pub struct EventDispatcher {
+    subscribers: Vec<Arc<dyn Subscriber>>,
+}
+
+impl EventDispatcher {
+    pub fn new() -> EventDispatcher {
+        EventDispatcher::default()
+    }
+
+    pub fn subscribe(mut self, subscriber: impl Subscriber + 'static) -> EventDispatcher {
+        self.subscribers.push(Arc::new(subscriber));
+        self
+    }
+
+    pub fn emit(&self, event: DomainEvent, actor: Option<&str>) {
+        for subscriber in &self.subscribers {
+            match catch_unwind(AssertUnwindSafe(|| subscriber.handle(&event, actor))) {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => eprintln!("Event subscriber {} failed on \"{}\": {}", subscriber.name(), event, e),
+                Err(_) => eprintln!("Event subscriber {} panicked on \"{}\"", subscriber.name(), event),
+            }
+        }
+    }
+}
+
+impl fmt::Debug for EventDispatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<_> = self.subscribers.iter().map(|s| s.name()).collect();
+        f.debug_struct("EventDispatcher").field("subscribers", &names).finish()
+    }
+}
+
+/// Writes each change, and who made it, to the server log
+pub struct AuditLog;
+
+impl Subscriber for AuditLog {
+    fn name(&self) -> &'static str {
+        "audit-log"
+    }
+
+    fn handle(&self, event: &DomainEvent, actor: Option<&str>) -> Result<(), String> {
+        println!("Audit: {} {}", actor.unwrap_or("(system)"), event);
+        Ok(())
+    }
+}
+
+/// An event with the username behind it
+#[cfg(test)]
+pub type CapturedEvent = (DomainEvent, Option<String>);
+
+/// Records events so tests can assert what a handler emitted
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct Capture {
+    events: Arc<std::sync::Mutex<Vec<CapturedEvent>>>,
+}
+
+#[cfg(test)]
+impl Capture {
+    /// Events so far, with their actors
+    pub fn events(&self) -> Vec<CapturedEvent> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+impl Subscriber for Capture {
+    fn name(&self) -> &'static str {
+        "capture"
+    }
+
+    fn handle(&self, event: &DomainEvent, actor: Option<&str>) -> Result<(), String> {
+        self.events.lock().unwrap().push((event.clone(), actor.map(str::to_string)));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Failing;
+
+    impl Subscriber for Failing {
+        fn name(&self) -> &'static str {
+            "failing"
+        }
+
+        fn handle(&self, _event: &DomainEvent, _actor: Option<&str>) -> Result<(), String> {
+            Err("webhook endpoint unreachable".to_string())
+        }
+    }
+
+    struct Panicking;
+
+    impl Subscriber for Panicking {
+        fn name(&self) -> &'static str {
+            "panicking"
+        }
+
+        fn handle(&self, _event: &DomainEvent, _actor: Option<&str>) -> Result<(), String> {
+            panic!("subscriber bug")
+        }
+    }
+
+    #[test]
+    fn test_failing_subscribers_do_not_stop_others() {
+        let capture = Capture::default();
+        let events = EventDispatcher::new()
+            .subscribe(Failing)
+            .subscribe(Panicking)
+            .subscribe(capture.clone());
+
+        let id = Uuid::new_v4();
+        events.emit(DomainEvent::ProductCreated { id }, Some("sam"));
+        events.emit(DomainEvent::StepNumbersRepaired { preparations: vec![id] }, None);
+
+        assert_eq!(
+            capture.events(),
+            vec![
+                (DomainEvent::ProductCreated { id }, Some("sam".to_string())),
+                (DomainEvent::StepNumbersRepaired { preparations: vec![id] }, None),
+            ]
+        );
+        assert_eq!(format!("{:?}", events), r#"EventDispatcher { subscribers: ["failing", "panicking", "capture"] }"#);
+    }
+}
@@ -0,0 +1,1513 @@
+use crate::auth;
+use crate::backup::{self, BackupConfig};
+use crate::errors::{ApiError, ValidationError};
+use crate::events::{DomainEvent, EventDispatcher};
+use crate::i18n::Translator;
+use crate::middleware::{AuthenticatedUser, ClientInfo};
+use crate::models::{DuplicateCandidate, ImageCheck, ImageReference, KioskToken, Location, MonthlyUploads, PreparationStep, PriceChange, Product, ProductMerge, ProductMergeError, StorageItem, TagMode, StorageTotals, weekday_from_number, SupplierSchedule, User, UserSummary};
+use crate::utils;
+use super::common::{filters, channel_body, is_unique_violation, load_admin};
+use super::errors::{error_page, forbidden_response};
+use actix_web::{web, HttpResponse, Result};
+use askama::Template;
+use aws_sdk_s3::Client as S3Client;
+use bytes::Bytes;
+use futures_util::StreamExt;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use uuid::Uuid;
+/// Number of users shown per page on the admin users list
+const ADMIN_USERS_PER_PAGE: i64 = 25;
+
+/// Template for the admin users list
+#[derive(Template)]
+#[template(path = "admin_users.html")]
+struct AdminUsersTemplate {
+    users: Vec<UserSummary>,
+    query: String,
+    page: i64,
+    total_pages: i64,
+    total_users: i64,
+    is_authenticated: bool,
+    username: Option<String>,
+}
+
+/// Query parameters for the admin users list
+#[derive(Debug, serde::Deserialize)]
+pub struct AdminUsersQuery {
+    q: Option<String>,
+    page: Option<i64>,
+}
+
+/// Template for the admin report of recent price changes
+#[derive(Template)]
+#[template(path = "admin_price_changes.html")]
+struct AdminPriceChangesTemplate {
+    changes: Vec<PriceChange>,
+    days: i32,
+    is_authenticated: bool,
+    username: Option<String>,
+}
+
+impl AdminPriceChangesTemplate {
+    /// Periods offered in the picker, with whether each is the one shown
+    fn period_options(&self) -> Vec<(i32, bool)> {
+        let mut periods = vec![7, DEFAULT_PRICE_CHANGE_DAYS, 90, MAX_PRICE_CHANGE_DAYS];
+        if !periods.contains(&self.days) {
+            periods.push(self.days);
+            periods.sort_unstable();
+        }
+        periods.into_iter().map(|days| (days, days == self.days)).collect()
+    }
+}
+
+/// Query parameters for the price changes report
+#[derive(Debug, serde::Deserialize)]
+pub struct PriceChangesQuery {
+    days: Option<i32>,
+}
+
+/// Days the price changes report covers unless `?days=` says otherwise
+const DEFAULT_PRICE_CHANGE_DAYS: i32 = 30;
+
+/// Longest window the price changes report accepts
+const MAX_PRICE_CHANGE_DAYS: i32 = 365;
+
+/// GET /admin/price-changes?days=30 - Recent price changes, biggest increase first (admin only)
+pub async fn admin_price_changes(
+    pool: web::Data<sqlx::PgPool>,
+    user: AuthenticatedUser,
+    query: web::Query<PriceChangesQuery>,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    if load_admin(pool.get_ref(), &user).await?.is_none() {
+        return Ok(forbidden_response(&tr));
+    }
+
+    let days = query.days.unwrap_or(DEFAULT_PRICE_CHANGE_DAYS).clamp(1, MAX_PRICE_CHANGE_DAYS);
+    let changes = PriceChange::recent(pool.get_ref(), days)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to fetch price changes")
+        })?;
+
+    let template = AdminPriceChangesTemplate {
+        changes,
+        days,
+        is_authenticated: true,
+        username: Some(user.username),
+    };
+    let html = template.render().map_err(|e| {
+        eprintln!("Template error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to render template")
+    })?;
+
+    Ok(HttpResponse::Ok().content_type("text/html").body(html))
+}
+
+/// GET /admin/users - Paginated, searchable list of users (admin only)
+pub async fn admin_users(
+    pool: web::Data<sqlx::PgPool>,
+    user: AuthenticatedUser,
+    query: web::Query<AdminUsersQuery>,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    if load_admin(pool.get_ref(), &user).await?.is_none() {
+        return Ok(forbidden_response(&tr));
+    }
+
+    let search_term = query.q.as_deref().unwrap_or("").trim().to_string();
+
+    let (users, page, total_pages, total_users) = if search_term.is_empty() {
+        let total_users = User::count(pool.get_ref())
+            .await
+            .map_err(|e| {
+                eprintln!("Database error: {:?}", e);
+                actix_web::error::ErrorInternalServerError("Failed to count users")
+            })?;
+        let total_pages = ((total_users + ADMIN_USERS_PER_PAGE - 1) / ADMIN_USERS_PER_PAGE).max(1);
+        let page = query.page.unwrap_or(1).clamp(1, total_pages);
+
+        let users = User::list(pool.get_ref(), ADMIN_USERS_PER_PAGE, (page - 1) * ADMIN_USERS_PER_PAGE)
+            .await
+            .map_err(|e| {
+                eprintln!("Database error: {:?}", e);
+                actix_web::error::ErrorInternalServerError("Failed to fetch users")
+            })?;
+
+        (users, page, total_pages, total_users)
+    } else {
+        let users = User::search(pool.get_ref(), &search_term)
+            .await
+            .map_err(|e| {
+                eprintln!("Database error searching users: {:?}", e);
+                actix_web::error::ErrorInternalServerError("Failed to search users")
+            })?;
+        let total_users = users.len() as i64;
+
+        (users, 1, 1, total_users)
+    };
+
+    let template = AdminUsersTemplate {
+        users,
+        query: search_term,
+        page,
+        total_pages,
+        total_users,
+        is_authenticated: true,
+        username: Some(user.username),
+    };
+
+    let html = template.render().map_err(|e| {
+        eprintln!("Template error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to render template")
+    })?;
+
+    Ok(HttpResponse::Ok().content_type("text/html").body(html))
+}
+
+/// Form data for merging one supplier name into another
+#[derive(Debug, serde::Deserialize)]
+pub struct MergeSuppliersForm {
+    from: String,
+    to: String,
+}
+
+/// POST /admin/suppliers/merge - Rename a supplier across all products (admin only)
+pub async fn merge_suppliers(
+    pool: web::Data<sqlx::PgPool>,
+    events: web::Data<EventDispatcher>,
+    user: AuthenticatedUser,
+    form: web::Form<MergeSuppliersForm>,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    if load_admin(pool.get_ref(), &user).await?.is_none() {
+        return Ok(forbidden_response(&tr));
+    }
+
+    let from = form.from.trim();
+    let to = form.to.trim();
+
+    if from.is_empty() || to.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Both 'from' and 'to' supplier names are required"
+        })));
+    }
+    if from == to {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Cannot merge a supplier into itself"
+        })));
+    }
+
+    let rows_changed = Product::merge_suppliers(pool.get_ref(), from, to)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error merging suppliers: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to merge suppliers")
+        })?;
+    if rows_changed > 0 {
+        events.emit(
+            DomainEvent::SuppliersMerged { from: from.to_string(), into: to.to_string(), products: rows_changed },
+            Some(&user.username),
+        );
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "from": from,
+        "to": to,
+        "rows_changed": rows_changed,
+    })))
+}
+
+/// Most possible duplicates offered as the product to keep
+const MERGE_CANDIDATES_SHOWN: i64 = 10;
+
+/// Template for previewing a product merge
+#[derive(Template)]
+#[template(path = "admin_product_merge.html")]
+struct AdminProductMergeTemplate {
+    /// The duplicate to merge away
+    source: Product,
+    /// Products named like it, to pick the one to keep from
+    candidates: Vec<DuplicateCandidate>,
+    /// The product to keep, once picked
+    target: Option<Product>,
+    /// What the merge would move, when it can go ahead
+    merge: Option<ProductMerge>,
+    /// Why it can't, or why the picked product wasn't found
+    error: String,
+    /// The target id as typed, kept for the form
+    target_input: String,
+    is_authenticated: bool,
+    username: Option<String>,
+}
+
+/// Query parameters for the merge preview
+#[derive(Debug, serde::Deserialize)]
+pub struct MergeProductsQuery {
+    source: Uuid,
+    target: Option<String>,
+}
+
+/// Form data for merging one product into another
+#[derive(Debug, serde::Deserialize)]
+pub struct MergeProductsForm {
+    source: Uuid,
+    target: Uuid,
+    /// Present when the box to copy the duplicate's picture is ticked
+    copy_picture: Option<String>,
+}
+
+/// The product with `id`, if any
+async fn fetch_product(pool: &sqlx::PgPool, id: Uuid) -> Result<Option<Product>> {
+    Product::get_by_id(pool, id).await.map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to fetch product")
+    })
+}
+
+/// GET /admin/products/merge?source=&target= - Preview merging a duplicate product into another (admin only)
+///
+/// Without a target, lists products with similar names to pick one from.
+pub async fn merge_products_preview(
+    pool: web::Data<sqlx::PgPool>,
+    user: AuthenticatedUser,
+    query: web::Query<MergeProductsQuery>,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    if load_admin(pool.get_ref(), &user).await?.is_none() {
+        return Ok(forbidden_response(&tr));
+    }
+
+    let Some(source) = fetch_product(pool.get_ref(), query.source).await? else {
+        return Ok(HttpResponse::NotFound()
+            .content_type("text/html")
+            .body(error_page(&tr.t("error-product-not-found"), None, &[("/", tr.t("error-back-home"))])));
+    };
+
+    let candidates = Product::possible_duplicates(pool.get_ref(), None, &source.product_name, MERGE_CANDIDATES_SHOWN + 1)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to find similar products")
+        })?
+        .into_iter()
+        .filter(|candidate| candidate.id != source.id)
+        .take(MERGE_CANDIDATES_SHOWN as usize)
+        .collect();
+
+    let target_input = query.target.as_deref().unwrap_or("").trim().to_string();
+    let mut target = None;
+    let mut merge = None;
+    let mut error = String::new();
+    if !target_input.is_empty() {
+        match Uuid::parse_str(&target_input) {
+            Ok(id) => target = fetch_product(pool.get_ref(), id).await?,
+            Err(_) => error = format!("\"{}\" isn't a product id", target_input),
+        }
+        match &target {
+            Some(target) => match Product::merge_problem(&source, target) {
+                Some(problem) => error = problem.to_string(),
+                None => {
+                    let preview = Product::merge_preview(pool.get_ref(), &source, target, true)
+                        .await
+                        .map_err(|e| {
+                            eprintln!("Database error: {:?}", e);
+                            actix_web::error::ErrorInternalServerError("Failed to preview merge")
+                        })?;
+                    merge = Some(preview);
+                }
+            },
+            None if error.is_empty() => error = "No product has that id".to_string(),
+            None => {}
+        }
+    } else if source.is_merged() {
+        error = "This product has already been merged into another".to_string();
+    }
+
+    let template = AdminProductMergeTemplate {
+        source,
+        candidates,
+        target,
+        merge,
+        error,
+        target_input,
+        is_authenticated: true,
+        username: Some(user.username),
+    };
+    let html = template.render().map_err(|e| {
+        eprintln!("Template error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to render template")
+    })?;
+
+    Ok(HttpResponse::Ok().content_type("text/html").body(html))
+}
+
+/// POST /admin/products/merge - Merge a duplicate product into another (admin only)
+///
+/// Everything attached to the duplicate moves in one transaction; its page then
+/// redirects to the product kept.
+pub async fn merge_products(
+    pool: web::Data<sqlx::PgPool>,
+    events: web::Data<EventDispatcher>,
+    user: AuthenticatedUser,
+    form: web::Form<MergeProductsForm>,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    if load_admin(pool.get_ref(), &user).await?.is_none() {
+        return Ok(forbidden_response(&tr));
+    }
+
+    let merged = Product::merge_into(
+        pool.get_ref(),
+        form.source,
+        form.target,
+        form.copy_picture.is_some(),
+        Some(user.user_id),
+        Some(&user.username),
+    )
+    .await;
+    match merged {
+        Ok(_) => events.emit(DomainEvent::ProductMerged { from: form.source, into: form.target }, Some(&user.username)),
+        Err(ProductMergeError::NotFound(_)) => {
+            return Ok(HttpResponse::NotFound()
+                .content_type("text/html")
+                .body(error_page(&tr.t("error-product-not-found"), None, &[("/", tr.t("error-back-home"))])));
+        }
+        Err(ProductMergeError::Invalid(problem)) => {
+            let back = format!("/admin/products/merge?source={}&target={}", form.source, form.target);
+            return Ok(HttpResponse::BadRequest()
+                .content_type("text/html")
+                .body(error_page(&tr.t("error-validation"), Some(problem), &[(&back, tr.t("error-back-merge"))])));
+        }
+        Err(ProductMergeError::Database(e)) => {
+            eprintln!("Database error merging products: {:?}", e);
+            return Err(actix_web::error::ErrorInternalServerError("Failed to merge products"));
+        }
+    }
+
+    Ok(HttpResponse::SeeOther()
+        .append_header(("Location", format!("/product/{}", form.target)))
+        .finish())
+}
+
+/// Most products one bulk tag request can change
+const MAX_BULK_TAG_PRODUCTS: usize = 1000;
+
+/// Most tags one bulk tag request can apply
+const MAX_BULK_TAGS: usize = 20;
+
+/// JSON body for tagging many products at once
+#[derive(Debug, serde::Deserialize)]
+pub struct BulkTagRequest {
+    product_ids: Vec<Uuid>,
+    tags: Vec<String>,
+    /// "add" (the default) or "remove"
+    #[serde(default)]
+    mode: TagMode,
+}
+
+/// POST /admin/products/tag - Add tags to (or remove them from) a list of products (admin only)
+///
+/// Tags are normalised first, so "Gluten Free" and "gluten free" are the same tag.
+/// Everything happens in one transaction; unknown product ids are skipped and listed.
+/// Errors use the API's JSON envelope, like a malformed body does.
+pub async fn bulk_tag_products(
+    pool: web::Data<sqlx::PgPool>,
+    events: web::Data<EventDispatcher>,
+    user: AuthenticatedUser,
+    body: web::Json<BulkTagRequest>,
+) -> std::result::Result<HttpResponse, ApiError> {
+    let admin = load_admin(pool.get_ref(), &user)
+        .await
+        .map_err(|e| ApiError::internal("Failed to fetch user", e))?;
+    if admin.is_none() {
+        return Err(ApiError::forbidden("Only administrators can tag products in bulk"));
+    }
+
+    let mut ids: Vec<Uuid> = Vec::new();
+    for id in &body.product_ids {
+        if !ids.contains(id) {
+            ids.push(*id);
+        }
+    }
+    let mut tags: Vec<String> = Vec::new();
+    for raw in &body.tags {
+        let Some(tag) = utils::normalize_tag(raw) else {
+            return Err(ApiError::validation(vec![ValidationError::new(
+                "tags",
+                "invalid_format",
+                format!("Invalid tag {:?}: tags must be 1 to {} characters", raw, utils::MAX_TAG_CHARS),
+            )]));
+        };
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+
+    let mut problems = Vec::new();
+    if ids.is_empty() {
+        problems.push(ValidationError::new("product_ids", "required", "'product_ids' needs at least one entry"));
+    } else if ids.len() > MAX_BULK_TAG_PRODUCTS {
+        problems.push(ValidationError::new(
+            "product_ids",
+            "too_many",
+            format!("At most {} products can be changed at once", MAX_BULK_TAG_PRODUCTS),
+        ));
+    }
+    if tags.is_empty() {
+        problems.push(ValidationError::new("tags", "required", "'tags' needs at least one entry"));
+    } else if tags.len() > MAX_BULK_TAGS {
+        problems.push(ValidationError::new(
+            "tags",
+            "too_many",
+            format!("At most {} tags can be applied at once", MAX_BULK_TAGS),
+        ));
+    }
+    if !problems.is_empty() {
+        return Err(ApiError::validation(problems));
+    }
+
+    let change = Product::apply_tags(pool.get_ref(), &ids, &tags, body.mode)
+        .await
+        .map_err(|e| ApiError::internal("Failed to tag products", e))?;
+    if change.changed > 0 {
+        events.emit(
+            DomainEvent::ProductsTagged { tags: tags.clone(), products: change.products, removed: body.mode == TagMode::Remove },
+            Some(&user.username),
+        );
+    }
+
+    let count_key = match body.mode {
+        TagMode::Add => "created",
+        TagMode::Remove => "removed",
+    };
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "mode": body.mode,
+        "tags": tags,
+        "products": change.products,
+        count_key: change.changed,
+        "not_found": change.not_found,
+    })))
+}
+
+/// Preparations renumbered per transaction by the step repair
+const STEP_REPAIR_BATCH_SIZE: usize = 50;
+
+/// POST /admin/repair/steps - Renumber steps that have gaps so every preparation runs 1..N (admin only)
+pub async fn repair_step_numbers(
+    pool: web::Data<sqlx::PgPool>,
+    events: web::Data<EventDispatcher>,
+    user: AuthenticatedUser,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    if load_admin(pool.get_ref(), &user).await?.is_none() {
+        return Ok(forbidden_response(&tr));
+    }
+
+    let report = PreparationStep::repair_numbering(pool.get_ref(), STEP_REPAIR_BATCH_SIZE)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error repairing step numbers: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to repair step numbers")
+        })?;
+    if !report.preparations_repaired.is_empty() {
+        events.emit(
+            DomainEvent::StepNumbersRepaired { preparations: report.preparations_repaired.clone() },
+            Some(&user.username),
+        );
+    }
+
+    println!(
+        "Step repair: renumbered {} steps in {} preparations",
+        report.steps_renumbered,
+        report.preparations_repaired.len()
+    );
+    Ok(HttpResponse::Ok().json(report))
+}
+
+/// Template for the kiosk device admin page
+#[derive(Template)]
+#[template(path = "admin_kiosk.html")]
+struct AdminKioskTemplate {
+    tokens: Vec<KioskToken>,
+    /// Setup link for a just-created device; the token is never shown again
+    new_device_url: Option<String>,
+    error: String,
+    is_authenticated: bool,
+    username: Option<String>,
+}
+
+/// Form data for creating a kiosk device token
+#[derive(Debug, serde::Deserialize)]
+pub struct NewKioskTokenForm {
+    name: String,
+}
+
+/// Render the kiosk device admin page
+async fn render_admin_kiosk(
+    pool: &sqlx::PgPool,
+    user: AuthenticatedUser,
+    new_device_url: Option<String>,
+    error: String,
+) -> Result<String> {
+    let tokens = KioskToken::list(pool)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to fetch kiosk devices")
+        })?;
+
+    let template = AdminKioskTemplate {
+        tokens,
+        new_device_url,
+        error,
+        is_authenticated: true,
+        username: Some(user.username),
+    };
+
+    template.render().map_err(|e| {
+        eprintln!("Template error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to render template")
+    })
+}
+
+/// GET /admin/kiosk - List kiosk device tokens (admin only)
+pub async fn admin_kiosk(
+    pool: web::Data<sqlx::PgPool>,
+    user: AuthenticatedUser,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    if load_admin(pool.get_ref(), &user).await?.is_none() {
+        return Ok(forbidden_response(&tr));
+    }
+
+    let html = render_admin_kiosk(pool.get_ref(), user, None, String::new()).await?;
+    Ok(HttpResponse::Ok().content_type("text/html").body(html))
+}
+
+/// POST /admin/kiosk - Create a kiosk device token and show its setup link once (admin only)
+pub async fn create_kiosk_token(
+    req: actix_web::HttpRequest,
+    pool: web::Data<sqlx::PgPool>,
+    user: AuthenticatedUser,
+    form: web::Form<NewKioskTokenForm>,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    let admin = match load_admin(pool.get_ref(), &user).await? {
+        Some(admin) => admin,
+        None => return Ok(forbidden_response(&tr)),
+    };
+
+    let name = form.name.trim();
+    if name.is_empty() || name.len() > 100 {
+        let html = render_admin_kiosk(
+            pool.get_ref(),
+            user,
+            None,
+            "Device name must be between 1 and 100 characters".to_string(),
+        )
+        .await?;
+        return Ok(HttpResponse::BadRequest().content_type("text/html").body(html));
+    }
+
+    let token = auth::generate_kiosk_token();
+    let new_device_url = ClientInfo::of(&req).absolute_url(&format!("/kiosk/{}", token));
+
+    KioskToken::create(pool.get_ref(), name, &auth::hash_kiosk_token(&token), admin.id)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to create kiosk device")
+        })?;
+
+    let html = render_admin_kiosk(pool.get_ref(), user, Some(new_device_url), String::new()).await?;
+    Ok(HttpResponse::Ok().content_type("text/html").body(html))
+}
+
+/// POST /admin/kiosk/{id}/revoke - Revoke a kiosk device token (admin only)
+pub async fn revoke_kiosk_token(
+    pool: web::Data<sqlx::PgPool>,
+    user: AuthenticatedUser,
+    token_id: web::Path<Uuid>,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    if load_admin(pool.get_ref(), &user).await?.is_none() {
+        return Ok(forbidden_response(&tr));
+    }
+
+    KioskToken::revoke(pool.get_ref(), *token_id)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to revoke kiosk device")
+        })?;
+
+    Ok(HttpResponse::SeeOther()
+        .append_header(("Location", "/admin/kiosk"))
+        .finish())
+}
+
+/// Template for the allowed locations admin page
+#[derive(Template)]
+#[template(path = "admin_locations.html")]
+struct AdminLocationsTemplate {
+    locations: Vec<Location>,
+    error: String,
+    is_authenticated: bool,
+    username: Option<String>,
+}
+
+/// Form data for adding or renaming a location
+#[derive(Debug, serde::Deserialize)]
+pub struct LocationForm {
+    name: String,
+}
+
+/// Render the allowed locations admin page
+async fn render_admin_locations(
+    pool: &sqlx::PgPool,
+    user: AuthenticatedUser,
+    error: String,
+) -> Result<String> {
+    let locations = Location::list(pool)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to fetch locations")
+        })?;
+
+    let template = AdminLocationsTemplate {
+        locations,
+        error,
+        is_authenticated: true,
+        username: Some(user.username),
+    };
+
+    template.render().map_err(|e| {
+        eprintln!("Template error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to render template")
+    })
+}
+
+/// Re-render the locations page with an error for a rejected add or rename
+async fn admin_locations_error(
+    pool: &sqlx::PgPool,
+    user: AuthenticatedUser,
+    error: &str,
+) -> Result<HttpResponse> {
+    let html = render_admin_locations(pool, user, error.to_string()).await?;
+    Ok(HttpResponse::BadRequest().content_type("text/html").body(html))
+}
+
+/// Trimmed location name, or None if it is empty or too long for the column
+fn valid_location_name(name: &str) -> Option<&str> {
+    let name = name.trim();
+    (!name.is_empty() && name.len() <= 255).then_some(name)
+}
+
+/// GET /admin/locations - List the allowed locations (admin only)
+pub async fn admin_locations(
+    pool: web::Data<sqlx::PgPool>,
+    user: AuthenticatedUser,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    if load_admin(pool.get_ref(), &user).await?.is_none() {
+        return Ok(forbidden_response(&tr));
+    }
+
+    let html = render_admin_locations(pool.get_ref(), user, String::new()).await?;
+    Ok(HttpResponse::Ok().content_type("text/html").body(html))
+}
+
+/// POST /admin/locations - Add an allowed location (admin only)
+pub async fn create_location(
+    pool: web::Data<sqlx::PgPool>,
+    events: web::Data<EventDispatcher>,
+    user: AuthenticatedUser,
+    form: web::Form<LocationForm>,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    if load_admin(pool.get_ref(), &user).await?.is_none() {
+        return Ok(forbidden_response(&tr));
+    }
+
+    let name = match valid_location_name(&form.name) {
+        Some(name) => name,
+        None => {
+            return admin_locations_error(pool.get_ref(), user, "Location must be between 1 and 255 characters").await;
+        }
+    };
+
+    match Location::create(pool.get_ref(), name).await {
+        Ok(location) => events.emit(DomainEvent::LocationCreated { id: location.id }, Some(&user.username)),
+        Err(e) if is_unique_violation(&e) => {
+            return admin_locations_error(pool.get_ref(), user, &format!("\"{}\" is already in the list", name)).await;
+        }
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return Err(actix_web::error::ErrorInternalServerError("Failed to add location"));
+        }
+    }
+
+    Ok(HttpResponse::SeeOther()
+        .append_header(("Location", "/admin/locations"))
+        .finish())
+}
+
+/// POST /admin/locations/{id}/rename - Rename a location and everything stored there (admin only)
+pub async fn rename_location(
+    pool: web::Data<sqlx::PgPool>,
+    events: web::Data<EventDispatcher>,
+    user: AuthenticatedUser,
+    location_id: web::Path<Uuid>,
+    form: web::Form<LocationForm>,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    if load_admin(pool.get_ref(), &user).await?.is_none() {
+        return Ok(forbidden_response(&tr));
+    }
+
+    let name = match valid_location_name(&form.name) {
+        Some(name) => name,
+        None => {
+            return admin_locations_error(pool.get_ref(), user, "Location must be between 1 and 255 characters").await;
+        }
+    };
+
+    match Location::rename(pool.get_ref(), *location_id, name).await {
+        Ok(true) => events.emit(DomainEvent::LocationRenamed { id: *location_id }, Some(&user.username)),
+        Ok(false) => {
+            return Ok(HttpResponse::NotFound()
+                .content_type("text/html")
+                .body(error_page(&tr.t("error-location-not-found"), None, &[("/admin/locations", tr.t("error-back-locations"))])));
+        }
+        Err(e) if is_unique_violation(&e) => {
+            return admin_locations_error(pool.get_ref(), user, &format!("\"{}\" is already in the list", name)).await;
+        }
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return Err(actix_web::error::ErrorInternalServerError("Failed to rename location"));
+        }
+    }
+
+    Ok(HttpResponse::SeeOther()
+        .append_header(("Location", "/admin/locations"))
+        .finish())
+}
+
+/// POST /admin/locations/{id}/delete - Remove an allowed location (admin only)
+pub async fn delete_location(
+    pool: web::Data<sqlx::PgPool>,
+    events: web::Data<EventDispatcher>,
+    user: AuthenticatedUser,
+    location_id: web::Path<Uuid>,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    if load_admin(pool.get_ref(), &user).await?.is_none() {
+        return Ok(forbidden_response(&tr));
+    }
+
+    let deleted = Location::delete(pool.get_ref(), *location_id)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to delete location")
+        })?;
+    if deleted {
+        events.emit(DomainEvent::LocationDeleted { id: *location_id }, Some(&user.username));
+    }
+
+    Ok(HttpResponse::SeeOther()
+        .append_header(("Location", "/admin/locations"))
+        .finish())
+}
+
+/// Template for the supplier schedules admin page
+#[derive(Template)]
+#[template(path = "admin_suppliers.html")]
+struct AdminSuppliersTemplate {
+    /// Every supplier name on products or with a schedule, and its schedule if set
+    suppliers: Vec<(String, Option<SupplierSchedule>)>,
+    error: String,
+    is_authenticated: bool,
+    username: Option<String>,
+}
+
+impl AdminSuppliersTemplate {
+    /// Weekdays in the order the form shows them, as (stored number, label)
+    fn weekdays(&self) -> Vec<(i16, &'static str)> {
+        vec![(0, "Mon"), (1, "Tue"), (2, "Wed"), (3, "Thu"), (4, "Fri"), (5, "Sat"), (6, "Sun")]
+    }
+}
+
+/// A supplier schedule as submitted: `cutoff_day` and `delivery_day` repeat, one per ticked box
+#[derive(Debug, Default, PartialEq)]
+struct SupplierScheduleForm {
+    supplier_name: String,
+    cutoff_weekdays: Vec<i16>,
+    cutoff_time: Option<chrono::NaiveTime>,
+    delivery_weekdays: Vec<i16>,
+}
+
+impl SupplierScheduleForm {
+    /// Read the submitted fields, rejecting unknown weekdays and malformed times
+    fn from_pairs(pairs: &[(String, String)]) -> std::result::Result<SupplierScheduleForm, String> {
+        let weekday = |value: &str| {
+            value
+                .parse::<i16>()
+                .ok()
+                .filter(|number| weekday_from_number(*number).is_some())
+                .ok_or_else(|| format!("\"{}\" isn't a day of the week", value))
+        };
+
+        let mut form = SupplierScheduleForm::default();
+        for (name, value) in pairs {
+            match name.as_str() {
+                "supplier_name" => form.supplier_name = value.trim().to_string(),
+                "cutoff_day" => form.cutoff_weekdays.push(weekday(value)?),
+                "delivery_day" => form.delivery_weekdays.push(weekday(value)?),
+                "cutoff_time" if !value.trim().is_empty() => {
+                    let time = chrono::NaiveTime::parse_from_str(value.trim(), "%H:%M")
+                        .map_err(|_| format!("\"{}\" isn't a time like 15:00", value))?;
+                    form.cutoff_time = Some(time);
+                }
+                _ => {}
+            }
+        }
+        for days in [&mut form.cutoff_weekdays, &mut form.delivery_weekdays] {
+            days.sort_unstable();
+            days.dedup();
+        }
+        if form.supplier_name.is_empty() || form.supplier_name.len() > 255 {
+            return Err("Supplier must be between 1 and 255 characters".to_string());
+        }
+        if !form.cutoff_weekdays.is_empty() && form.cutoff_time.is_none() {
+            return Err(format!("Set the time orders for {} must be in by", form.supplier_name));
+        }
+        Ok(form)
+    }
+}
+
+/// Render the supplier schedules admin page
+async fn render_admin_suppliers(pool: &sqlx::PgPool, user: AuthenticatedUser, error: String) -> Result<String> {
+    let names = Product::supplier_names(pool).await.map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to fetch suppliers")
+    })?;
+    let schedules = SupplierSchedule::list(pool).await.map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to fetch supplier schedules")
+    })?;
+
+    // Schedules can outlive their supplier's last product, so list those names too
+    let mut schedules: HashMap<String, SupplierSchedule> =
+        schedules.into_iter().map(|schedule| (schedule.supplier_name.clone(), schedule)).collect();
+    let mut suppliers: Vec<(String, Option<SupplierSchedule>)> = names
+        .into_iter()
+        .map(|name| {
+            let schedule = schedules.remove(&name);
+            (name, schedule)
+        })
+        .collect();
+    suppliers.extend(schedules.into_values().map(|schedule| (schedule.supplier_name.clone(), Some(schedule))));
+    suppliers.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let template = AdminSuppliersTemplate {
+        suppliers,
+        error,
+        is_authenticated: true,
+        username: Some(user.username),
+    };
+    template.render().map_err(|e| {
+        eprintln!("Template error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to render template")
+    })
+}
+
+/// GET /admin/suppliers - Order cut-offs and delivery days per supplier (admin only)
+pub async fn admin_suppliers(
+    pool: web::Data<sqlx::PgPool>,
+    user: AuthenticatedUser,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    if load_admin(pool.get_ref(), &user).await?.is_none() {
+        return Ok(forbidden_response(&tr));
+    }
+
+    let html = render_admin_suppliers(pool.get_ref(), user, String::new()).await?;
+    Ok(HttpResponse::Ok().content_type("text/html").body(html))
+}
+
+/// POST /admin/suppliers/schedule - Set a supplier's cut-offs and delivery days; no cut-off days clears it (admin only)
+pub async fn save_supplier_schedule(
+    pool: web::Data<sqlx::PgPool>,
+    events: web::Data<EventDispatcher>,
+    user: AuthenticatedUser,
+    form: web::Form<Vec<(String, String)>>,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    if load_admin(pool.get_ref(), &user).await?.is_none() {
+        return Ok(forbidden_response(&tr));
+    }
+
+    let form = match SupplierScheduleForm::from_pairs(&form) {
+        Ok(form) => form,
+        Err(error) => {
+            let html = render_admin_suppliers(pool.get_ref(), user, error).await?;
+            return Ok(HttpResponse::BadRequest().content_type("text/html").body(html));
+        }
+    };
+
+    let changed = match form.cutoff_time {
+        Some(cutoff_time) if !form.cutoff_weekdays.is_empty() => SupplierSchedule::save(
+            pool.get_ref(),
+            &form.supplier_name,
+            &form.cutoff_weekdays,
+            cutoff_time,
+            &form.delivery_weekdays,
+        )
+        .await
+        .map(|_| true),
+        _ => SupplierSchedule::delete(pool.get_ref(), &form.supplier_name).await,
+    }
+    .map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to save supplier schedule")
+    })?;
+    if changed {
+        events.emit(DomainEvent::SupplierScheduleChanged { supplier: form.supplier_name }, Some(&user.username));
+    }
+
+    Ok(HttpResponse::SeeOther()
+        .append_header(("Location", "/admin/suppliers"))
+        .finish())
+}
+
+/// Number of picture URLs checked at the same time by the image check
+const IMAGE_CHECK_CONCURRENCY: usize = 8;
+
+/// Timeout for each outbound HEAD request made by the image check
+const IMAGE_CHECK_TIMEOUT_SECS: u64 = 10;
+
+/// Set while a background image check is running so a second one can't start
+static IMAGE_CHECK_RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Marks a background job as running; clears its flag when dropped, even if the job panics
+struct JobRun(&'static AtomicBool);
+
+impl JobRun {
+    /// Claim the job's running flag, or None if it is already in progress
+    fn start(running: &'static AtomicBool) -> Option<JobRun> {
+        running
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .ok()
+            .map(|_| JobRun(running))
+    }
+}
+
+impl Drop for JobRun {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+/// A picture URL that the host rewrite would change
+struct ImageRewrite {
+    source_table: String,
+    old_url: String,
+    new_url: String,
+}
+
+/// Template for the image check report
+#[derive(Template)]
+#[template(path = "admin_image_checks.html")]
+struct AdminImageChecksTemplate {
+    checks: Vec<ImageCheck>,
+    broken_count: usize,
+    running: bool,
+    /// Configured (old, new) base URLs for the rewrite repair, if any
+    rewrite_config: Option<(String, String)>,
+    /// Dry-run preview of the rewrite
+    rewrites: Option<Vec<ImageRewrite>>,
+    /// Rows changed by an applied rewrite
+    rows_rewritten: Option<u64>,
+    is_authenticated: bool,
+    username: Option<String>,
+}
+
+/// Form data for the host rewrite repair
+#[derive(Debug, serde::Deserialize)]
+pub struct RewriteImagesForm {
+    /// "apply" to change the database; anything else is a dry run
+    mode: String,
+}
+
+/// Old and new base URLs from IMAGE_REWRITE_FROM / IMAGE_REWRITE_TO, if both are set and differ
+fn image_rewrite_config() -> Option<(String, String)> {
+    let from = std::env::var("IMAGE_REWRITE_FROM").ok().filter(|s| !s.trim().is_empty())?;
+    let to = std::env::var("IMAGE_REWRITE_TO").ok().filter(|s| !s.trim().is_empty())?;
+    (from != to).then_some((from, to))
+}
+
+/// Render the image check report, optionally with rewrite results
+async fn render_admin_image_checks(
+    pool: &sqlx::PgPool,
+    user: AuthenticatedUser,
+    rewrites: Option<Vec<ImageRewrite>>,
+    rows_rewritten: Option<u64>,
+) -> Result<String> {
+    let checks = ImageCheck::list(pool)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to fetch image checks")
+        })?;
+
+    let template = AdminImageChecksTemplate {
+        broken_count: checks.iter().filter(|c| !c.is_ok).count(),
+        checks,
+        running: IMAGE_CHECK_RUNNING.load(Ordering::SeqCst),
+        rewrite_config: image_rewrite_config(),
+        rewrites,
+        rows_rewritten,
+        is_authenticated: true,
+        username: Some(user.username),
+    };
+
+    template.render().map_err(|e| {
+        eprintln!("Template error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to render template")
+    })
+}
+
+/// GET /admin/image-checks - Report of the latest image broken-link check (admin only)
+pub async fn admin_image_checks(
+    pool: web::Data<sqlx::PgPool>,
+    user: AuthenticatedUser,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    if load_admin(pool.get_ref(), &user).await?.is_none() {
+        return Ok(forbidden_response(&tr));
+    }
+
+    let html = render_admin_image_checks(pool.get_ref(), user, None, None).await?;
+    Ok(HttpResponse::Ok().content_type("text/html").body(html))
+}
+
+/// POST /admin/check-images - Start a background check of every stored picture URL (admin only)
+pub async fn start_image_check(
+    pool: web::Data<sqlx::PgPool>,
+    s3_client: web::Data<S3Client>,
+    user: AuthenticatedUser,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    if load_admin(pool.get_ref(), &user).await?.is_none() {
+        return Ok(forbidden_response(&tr));
+    }
+
+    // If a check is already running, just show its progress
+    if let Some(run) = JobRun::start(&IMAGE_CHECK_RUNNING) {
+        let references = ImageReference::all(pool.get_ref())
+            .await
+            .map_err(|e| {
+                eprintln!("Database error: {:?}", e);
+                actix_web::error::ErrorInternalServerError("Failed to fetch picture URLs")
+            })?;
+
+        ImageCheck::clear(pool.get_ref())
+            .await
+            .map_err(|e| {
+                eprintln!("Database error: {:?}", e);
+                actix_web::error::ErrorInternalServerError("Failed to clear image checks")
+            })?;
+
+        actix_web::rt::spawn(run_image_check(
+            run,
+            pool.get_ref().clone(),
+            s3_client.get_ref().clone(),
+            references,
+        ));
+    }
+
+    Ok(HttpResponse::SeeOther()
+        .append_header(("Location", "/admin/image-checks"))
+        .finish())
+}
+
+/// Check each picture URL with bounded concurrency and record the results
+async fn run_image_check(
+    _run: JobRun,
+    pool: sqlx::PgPool,
+    s3_client: S3Client,
+    references: Vec<ImageReference>,
+) {
+    let http_client = match reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(IMAGE_CHECK_TIMEOUT_SECS))
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("Failed to create HTTP client for image check: {:?}", e);
+            return;
+        }
+    };
+    let bucket_name = std::env::var("S3_BUCKET_NAME")
+        .unwrap_or_else(|_| "kitchen-hand-guide".to_string());
+
+    let (pool, s3_client, http_client, bucket_name) = (&pool, &s3_client, &http_client, &bucket_name);
+    futures_util::stream::iter(references)
+        .for_each_concurrent(IMAGE_CHECK_CONCURRENCY, |reference| async move {
+            let outcome = utils::check_image_url(http_client, s3_client, bucket_name, &reference.picture_url).await;
+            if let Err(e) = ImageCheck::record(
+                pool,
+                &reference,
+                outcome.status_code,
+                outcome.error.as_deref(),
+                outcome.is_ok(),
+            )
+            .await
+            {
+                eprintln!("Database error: {:?}", e);
+            }
+        })
+        .await;
+}
+
+/// POST /admin/image-checks/rewrite - Preview or apply the configured base URL rewrite (admin only)
+pub async fn rewrite_image_urls(
+    pool: web::Data<sqlx::PgPool>,
+    events: web::Data<EventDispatcher>,
+    user: AuthenticatedUser,
+    form: web::Form<RewriteImagesForm>,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    if load_admin(pool.get_ref(), &user).await?.is_none() {
+        return Ok(forbidden_response(&tr));
+    }
+
+    let (from, to) = match image_rewrite_config() {
+        Some(config) => config,
+        None => {
+            return Ok(HttpResponse::BadRequest()
+                .content_type("text/html")
+                .body(error_page(
+                    &tr.t("error-bad-request"),
+                    Some(&tr.t("error-image-rewrite-unset")),
+                    &[("/admin/image-checks", tr.t("error-back-image-checks"))],
+                )));
+        }
+    };
+
+    let html = if form.mode == "apply" {
+        let rows_changed = ImageReference::rewrite_base_url(pool.get_ref(), &from, &to)
+            .await
+            .map_err(|e| {
+                eprintln!("Database error: {:?}", e);
+                actix_web::error::ErrorInternalServerError("Failed to rewrite picture URLs")
+            })?;
+        if rows_changed > 0 {
+            events.emit(DomainEvent::ImageUrlsRewritten { rows: rows_changed }, Some(&user.username));
+        }
+
+        render_admin_image_checks(pool.get_ref(), user, None, Some(rows_changed)).await?
+    } else {
+        let references = ImageReference::all(pool.get_ref())
+            .await
+            .map_err(|e| {
+                eprintln!("Database error: {:?}", e);
+                actix_web::error::ErrorInternalServerError("Failed to fetch picture URLs")
+            })?;
+
+        let rewrites = references
+            .into_iter()
+            .filter_map(|reference| {
+                let new_url = reference.rewritten_url(&from, &to)?;
+                Some(ImageRewrite {
+                    source_table: reference.source_table,
+                    old_url: reference.picture_url,
+                    new_url,
+                })
+            })
+            .collect();
+
+        render_admin_image_checks(pool.get_ref(), user, Some(rewrites), None).await?
+    };
+
+    Ok(HttpResponse::Ok().content_type("text/html").body(html))
+}
+
+/// Number of items listed in the storage report
+const STORAGE_REPORT_LIMIT: i64 = 50;
+
+/// Number of pictures measured at the same time by the storage backfill
+const STORAGE_BACKFILL_CONCURRENCY: usize = 8;
+
+/// Set while the storage backfill is running so a second one can't start
+static STORAGE_BACKFILL_RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Template for the storage usage report
+#[derive(Template)]
+#[template(path = "admin_storage.html")]
+struct AdminStorageTemplate {
+    totals: StorageTotals,
+    heaviest: Vec<StorageItem>,
+    months: Vec<MonthlyUploads>,
+    running: bool,
+    is_authenticated: bool,
+    username: Option<String>,
+}
+
+/// GET /admin/storage - Heaviest items and upload totals per month (admin only)
+pub async fn admin_storage(
+    pool: web::Data<sqlx::PgPool>,
+    user: AuthenticatedUser,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    if load_admin(pool.get_ref(), &user).await?.is_none() {
+        return Ok(forbidden_response(&tr));
+    }
+
+    let db_error = |e: sqlx::Error| {
+        eprintln!("Database error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to fetch storage usage")
+    };
+    let template = AdminStorageTemplate {
+        totals: StorageTotals::get(pool.get_ref()).await.map_err(db_error)?,
+        heaviest: StorageItem::heaviest(pool.get_ref(), STORAGE_REPORT_LIMIT).await.map_err(db_error)?,
+        months: MonthlyUploads::all(pool.get_ref()).await.map_err(db_error)?,
+        running: STORAGE_BACKFILL_RUNNING.load(Ordering::SeqCst),
+        is_authenticated: true,
+        username: Some(user.username),
+    };
+
+    let html = template.render().map_err(|e| {
+        eprintln!("Template error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to render template")
+    })?;
+    Ok(HttpResponse::Ok().content_type("text/html").body(html))
+}
+
+/// POST /admin/storage/backfill - Measure every picture without a recorded size in the background (admin only)
+pub async fn start_storage_backfill(
+    pool: web::Data<sqlx::PgPool>,
+    s3_client: web::Data<S3Client>,
+    user: AuthenticatedUser,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    if load_admin(pool.get_ref(), &user).await?.is_none() {
+        return Ok(forbidden_response(&tr));
+    }
+
+    // If the backfill is already running, just show its progress
+    if let Some(run) = JobRun::start(&STORAGE_BACKFILL_RUNNING) {
+        let references = ImageReference::without_size(pool.get_ref())
+            .await
+            .map_err(|e| {
+                eprintln!("Database error: {:?}", e);
+                actix_web::error::ErrorInternalServerError("Failed to fetch picture URLs")
+            })?;
+
+        actix_web::rt::spawn(run_storage_backfill(
+            run,
+            pool.get_ref().clone(),
+            s3_client.get_ref().clone(),
+            references,
+        ));
+    }
+
+    Ok(HttpResponse::SeeOther()
+        .append_header(("Location", "/admin/storage"))
+        .finish())
+}
+
+/// HEAD each picture with bounded concurrency and record the sizes found
+///
+/// Pictures that can't be measured (gone, or no Content-Length) are logged and
+/// left without a size; the image check is the place to chase those.
+async fn run_storage_backfill(
+    _run: JobRun,
+    pool: sqlx::PgPool,
+    s3_client: S3Client,
+    references: Vec<ImageReference>,
+) {
+    let http_client = match reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(IMAGE_CHECK_TIMEOUT_SECS))
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("Failed to create HTTP client for storage backfill: {:?}", e);
+            return;
+        }
+    };
+    let bucket_name = std::env::var("S3_BUCKET_NAME")
+        .unwrap_or_else(|_| "kitchen-hand-guide".to_string());
+
+    let (pool, s3_client, http_client, bucket_name) = (&pool, &s3_client, &http_client, &bucket_name);
+    futures_util::stream::iter(references)
+        .for_each_concurrent(STORAGE_BACKFILL_CONCURRENCY, |reference| async move {
+            match utils::measure_image_url(http_client, s3_client, bucket_name, &reference.picture_url).await {
+                Ok(size) => {
+                    if let Err(e) = reference.record_measured_size(pool, size.size_bytes, size.modified_at).await {
+                        eprintln!("Database error: {:?}", e);
+                    }
+                }
+                Err(e) => eprintln!("Could not measure {}: {}", reference.picture_url, e),
+            }
+        })
+        .await;
+}
+
+/// POST /admin/backup - Back up the database to the bucket now, streaming progress as plain text (admin only)
+///
+/// The backup carries on if the browser disconnects. A second request while one
+/// is running (including the nightly one) gets 409.
+pub async fn start_backup(
+    pool: web::Data<sqlx::PgPool>,
+    s3_client: web::Data<S3Client>,
+    events: web::Data<EventDispatcher>,
+    config: web::Data<BackupConfig>,
+    user: AuthenticatedUser,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    if load_admin(pool.get_ref(), &user).await?.is_none() {
+        return Ok(forbidden_response(&tr));
+    }
+
+    let Some(claim) = backup::claim() else {
+        return Ok(HttpResponse::Conflict()
+            .content_type("text/plain; charset=utf-8")
+            .body("A backup is already running\n"));
+    };
+
+    let (tx, rx) = tokio::sync::mpsc::channel::<Result<Bytes>>(32);
+    let (config, s3_client, events) = (config.into_inner(), s3_client.into_inner(), events.into_inner());
+    actix_web::rt::spawn(async move {
+        let progress = |line: String| {
+            // A slow or departed reader only misses progress lines
+            let _ = tx.try_send(Ok(Bytes::from(line + "\n")));
+        };
+        let summary = match backup::run(claim, &config, &s3_client, &events, Some(&user.username), progress).await {
+            Ok(report) => format!("OK: saved {} ({})", report.key, utils::format_bytes(report.bytes as i64)),
+            Err(e) => format!("FAILED: {}", e),
+        };
+        let _ = tx.send(Ok(Bytes::from(summary + "\n"))).await;
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .streaming(channel_body(rx)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Status and JSON body of a bulk tag request that failed
+    async fn bulk_tag_error(pool: &sqlx::PgPool, user: AuthenticatedUser, body: serde_json::Value) -> (u16, serde_json::Value) {
+        use actix_web::ResponseError;
+
+        let body = serde_json::from_value(body).expect("Invalid request body");
+        let error = bulk_tag_products(
+            web::Data::new(pool.clone()),
+            web::Data::new(EventDispatcher::new()),
+            user,
+            web::Json(body),
+        )
+        .await
+        .expect_err("Expected the request to be refused");
+        let response = error.error_response();
+        let status = response.status().as_u16();
+        let bytes = actix_web::body::to_bytes(response.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[actix_web::test]
+    async fn test_bulk_tag_errors_use_the_api_envelope() {
+        // A kiosk is never an admin, so this is refused before the database is used
+        let pool = sqlx::PgPool::connect_lazy("postgres://localhost/unused").expect("Failed to build pool");
+        let kiosk = AuthenticatedUser { user_id: Uuid::new_v4(), username: "Pass".to_string(), is_kiosk: true };
+        let (status, body) = bulk_tag_error(&pool, kiosk, serde_json::json!({ "product_ids": [], "tags": [] })).await;
+        assert_eq!(status, 403);
+        assert_eq!(body["error"]["code"], "FORBIDDEN");
+
+        let Ok(url) = std::env::var("TEST_DATABASE_URL") else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+        let pool = sqlx::PgPool::connect(&url).await.expect("Failed to connect to TEST_DATABASE_URL");
+        // The handler reads through the pool, so the admin is committed and removed afterwards
+        let admin_id: Uuid = sqlx::query_scalar(
+            "INSERT INTO users (username, email, password_hash, role)
+             VALUES ('bulk_tag_admin', 'bulk_tag_admin@example.com', 'hash', 'admin')
+             RETURNING id"
+        )
+        .fetch_one(&pool)
+        .await
+        .expect("Failed to create user");
+        let admin = || AuthenticatedUser { user_id: admin_id, username: "bulk_tag_admin".to_string(), is_kiosk: false };
+
+        let too_many_tags: Vec<String> = (0..=MAX_BULK_TAGS).map(|i| format!("tag {}", i)).collect();
+        let counts = bulk_tag_error(&pool, admin(), serde_json::json!({ "product_ids": [], "tags": too_many_tags })).await;
+        let invalid = bulk_tag_error(&pool, admin(), serde_json::json!({ "product_ids": [Uuid::new_v4()], "tags": ["  "] })).await;
+
+        sqlx::query("DELETE FROM users WHERE id = $1")
+            .bind(admin_id)
+            .execute(&pool)
+            .await
+            .expect("Failed to clean up");
+
+        let (status, body) = counts;
+        assert_eq!(status, 400);
+        assert_eq!(body["error"]["code"], "VALIDATION_FAILED");
+        let problems: Vec<(&str, &str)> = body["error"]["details"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|detail| (detail["field"].as_str().unwrap(), detail["code"].as_str().unwrap()))
+            .collect();
+        assert_eq!(problems, [("product_ids", "required"), ("tags", "too_many")]);
+
+        let (status, body) = invalid;
+        assert_eq!(status, 400);
+        assert_eq!(body["error"]["details"][0]["field"], "tags");
+        assert_eq!(body["error"]["details"][0]["code"], "invalid_format");
+    }
+
+    #[test]
+    fn test_supplier_schedule_form() {
+        let pairs = |pairs: &[(&str, &str)]| -> Vec<(String, String)> {
+            pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+        };
+
+        let form = SupplierScheduleForm::from_pairs(&pairs(&[
+            ("supplier_name", " PFD "),
+            ("cutoff_day", "4"),
+            ("cutoff_day", "1"),
+            ("cutoff_day", "1"),
+            ("cutoff_time", "15:00"),
+            ("delivery_day", "3"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            form,
+            SupplierScheduleForm {
+                supplier_name: "PFD".to_string(),
+                cutoff_weekdays: vec![1, 4],
+                cutoff_time: chrono::NaiveTime::from_hms_opt(15, 0, 0),
+                delivery_weekdays: vec![3],
+            }
+        );
+
+        // No days ticked clears the schedule, so the time can be left blank
+        let form = SupplierScheduleForm::from_pairs(&pairs(&[("supplier_name", "PFD"), ("cutoff_time", "")])).unwrap();
+        assert!(form.cutoff_weekdays.is_empty() && form.cutoff_time.is_none());
+
+        assert!(SupplierScheduleForm::from_pairs(&pairs(&[("supplier_name", "PFD"), ("cutoff_day", "1")])).is_err());
+        assert!(SupplierScheduleForm::from_pairs(&pairs(&[("supplier_name", "PFD"), ("cutoff_day", "7")])).is_err());
+        assert!(SupplierScheduleForm::from_pairs(&pairs(&[("supplier_name", "PFD"), ("cutoff_time", "3pm")])).is_err());
+        assert!(SupplierScheduleForm::from_pairs(&pairs(&[("supplier_name", " ")])).is_err());
+    }
+}
@@ -0,0 +1,799 @@
+use crate::backup::{self, BackupStatus};
+use crate::errors::{ApiError, ValidationError};
+use crate::events::{DomainEvent, EventDispatcher};
+use crate::health;
+use crate::middleware::AuthenticatedUser;
+use crate::models::{DuplicateCandidate, Location, NewPreparationForm, NewProductForm, Preparation, PreparationImport, PreparationStep, PreparationWithSteps, Product, PREP_STATUS_PUBLISHED, Stats, StorageTotals, User, WithLocalTimes};
+use crate::storage::{self, StorageErrorCounts};
+use crate::utils;
+use super::common::channel_body;
+use super::products::barcode_taken_error;
+use actix_web::http::header::{
+    CacheControl, CacheDirective, ETag, EntityTag, IfModifiedSince, IfNoneMatch, LastModified,
+};
+use actix_web::{web, HttpMessage, HttpResponse, Result};
+use bytes::Bytes;
+use futures_util::StreamExt;
+use uuid::Uuid;
+/// GET /api/v1/products/by-barcode/{code} - Product lookup for the scanning app
+pub async fn api_product_by_barcode(
+    pool: web::Data<sqlx::PgPool>,
+    code: web::Path<String>,
+) -> std::result::Result<HttpResponse, ApiError> {
+    let code = code.trim();
+
+    if !utils::is_valid_barcode(code) {
+        return Err(ApiError::validation(vec![ValidationError::new(
+            "code",
+            "invalid_format",
+            "Barcode must be a valid EAN-13 or UPC-A code",
+        )]));
+    }
+
+    let mut product = Product::get_by_barcode(pool.get_ref(), code)
+        .await?
+        .ok_or_else(|| ApiError::not_found("No product with this barcode"))?;
+    // A merged duplicate keeps its barcode when the product kept has its own, so scans go on to that one
+    if let Some(target) = product.merged_into {
+        product = Product::get_by_id(pool.get_ref(), target)
+            .await?
+            .ok_or_else(|| ApiError::not_found("No product with this barcode"))?;
+    }
+
+    Ok(HttpResponse::Ok().json(WithLocalTimes::new(product, utils::venue_timezone())))
+}
+
+/// POST /api/products/validate - Check product fields before saving them
+///
+/// Runs the product form's checks on a JSON body. Problems come back as a
+/// VALIDATION_FAILED error with a `code` per field; otherwise the response lists
+/// any warnings, which don't stop a save.
+pub async fn api_validate_product(
+    pool: web::Data<sqlx::PgPool>,
+    payload: web::Json<NewProductForm>,
+) -> std::result::Result<HttpResponse, ApiError> {
+    let form = payload.into_inner();
+    let barcode = form.barcode.as_deref().map(str::trim).filter(|b| !b.is_empty()).map(str::to_string);
+    let form = NewProductForm { barcode, ..form };
+
+    let locations = Location::names(pool.get_ref()).await?;
+    let mut validation = form.validate_with_locations(&locations);
+    if let (true, Some(barcode)) = (validation.is_ok(), &form.barcode) {
+        if Product::get_by_barcode(pool.get_ref(), barcode).await?.is_some() {
+            validation.errors.push(barcode_taken_error(barcode));
+        }
+    }
+
+    if !validation.is_ok() {
+        return Err(ApiError::validation(validation.errors));
+    }
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "warnings": validation.warnings })))
+}
+
+/// GET /api/schema/product - The product form's fields, for clients that build the form themselves
+///
+/// Lists each field's type, whether it's required and its limits, as the
+/// validators enforce them. `location` is a choice once admins have set up locations.
+pub async fn api_product_schema(pool: web::Data<sqlx::PgPool>) -> std::result::Result<HttpResponse, ApiError> {
+    let locations = Location::names(pool.get_ref()).await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "fields": NewProductForm::schema(&locations) })))
+}
+
+/// GET /api/schema/preparation - The preparation form's fields, like `/api/schema/product`
+pub async fn api_preparation_schema(pool: web::Data<sqlx::PgPool>) -> std::result::Result<HttpResponse, ApiError> {
+    let locations = Location::names(pool.get_ref()).await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "fields": NewPreparationForm::schema(&locations) })))
+}
+
+/// Most possible duplicates returned by a duplicate check
+const DUPLICATE_CHECK_LIMIT: i64 = 5;
+
+/// Query parameters for the duplicate checks; `supplier` is ignored for preparations
+#[derive(Debug, serde::Deserialize)]
+pub struct DuplicateCheckQuery {
+    supplier: Option<String>,
+    name: Option<String>,
+}
+
+impl DuplicateCheckQuery {
+    /// Trimmed name and supplier, blank ones read as missing; the name is required
+    fn terms(&self) -> std::result::Result<(&str, Option<&str>), ApiError> {
+        let supplier = self.supplier.as_deref().map(str::trim).filter(|s| !s.is_empty());
+        match self.name.as_deref().map(str::trim).filter(|n| !n.is_empty()) {
+            Some(name) => Ok((name, supplier)),
+            None => Err(ApiError::validation(vec![ValidationError::new("name", "required", "Name cannot be empty")])),
+        }
+    }
+}
+
+/// A possible duplicate with the page to open when the user wants to look
+#[derive(serde::Serialize)]
+struct DuplicateMatch {
+    #[serde(flatten)]
+    candidate: DuplicateCandidate,
+    url: String,
+}
+
+/// The JSON body for a duplicate check
+fn duplicate_matches(candidates: Vec<DuplicateCandidate>, path: &str) -> HttpResponse {
+    let matches: Vec<_> = candidates
+        .into_iter()
+        .map(|candidate| DuplicateMatch {
+            url: format!("/{}/{}", path, candidate.id),
+            candidate,
+        })
+        .collect();
+    HttpResponse::Ok().json(serde_json::json!({ "matches": matches }))
+}
+
+/// GET /api/v1/products/check-duplicate?supplier=&name= - Products that may
+/// already be the one about to be added, for a confirmation before saving
+///
+/// Uses the fuzzy search's similarity threshold; see `Product::possible_duplicates`.
+pub async fn api_check_product_duplicate(
+    pool: web::Data<sqlx::PgPool>,
+    query: web::Query<DuplicateCheckQuery>,
+) -> std::result::Result<HttpResponse, ApiError> {
+    let (name, supplier) = query.terms()?;
+    let candidates = Product::possible_duplicates(pool.get_ref(), supplier, name, DUPLICATE_CHECK_LIMIT).await?;
+    Ok(duplicate_matches(candidates, "product"))
+}
+
+/// GET /api/v1/preparations/check-duplicate?name= - Preparations that may
+/// already be the one about to be added
+///
+/// An exact name the add form would warn about always comes first, scored 1.0.
+pub async fn api_check_preparation_duplicate(
+    pool: web::Data<sqlx::PgPool>,
+    query: web::Query<DuplicateCheckQuery>,
+) -> std::result::Result<HttpResponse, ApiError> {
+    let (name, _) = query.terms()?;
+    let candidates = Preparation::possible_duplicates(pool.get_ref(), name, DUPLICATE_CHECK_LIMIT).await?;
+    Ok(duplicate_matches(candidates, "preparation"))
+}
+
+/// Validator for a product's JSON, which changes whenever the product is saved
+fn product_etag(product: &Product) -> EntityTag {
+    EntityTag::new_strong(format!("{}-{}", product.id, product.updated_at.timestamp_micros()))
+}
+
+/// Whether the client's copy is current: If-None-Match decides when sent, otherwise If-Modified-Since
+fn is_not_modified(req: &actix_web::HttpRequest, etag: &EntityTag, updated_at: chrono::DateTime<chrono::Utc>) -> bool {
+    if let Some(if_none_match) = req.get_header::<IfNoneMatch>() {
+        return match if_none_match {
+            IfNoneMatch::Any => true,
+            IfNoneMatch::Items(tags) => tags.iter().any(|tag| tag.weak_eq(etag)),
+        };
+    }
+
+    match req.get_header::<IfModifiedSince>() {
+        // HTTP dates are whole seconds, so compare at that precision
+        Some(IfModifiedSince(since)) => {
+            let since = chrono::DateTime::<chrono::Utc>::from(std::time::SystemTime::from(since));
+            updated_at.timestamp() <= since.timestamp()
+        }
+        None => false,
+    }
+}
+
+/// GET /api/products/{id} - A product as JSON, answering 304 when the client's copy is current
+///
+/// Also serves HEAD, which gets the same headers without the body. A merged
+/// product redirects permanently to the one it was merged into, like its page.
+pub async fn api_product(
+    req: actix_web::HttpRequest,
+    pool: web::Data<sqlx::PgPool>,
+    product_id: web::Path<Uuid>,
+) -> std::result::Result<HttpResponse, ApiError> {
+    let product = Product::get_by_id(pool.get_ref(), *product_id)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Product not found"))?;
+    if let Some(target) = product.merged_into {
+        return Ok(HttpResponse::MovedPermanently()
+            .append_header(("Location", format!("/api/products/{}", target)))
+            .finish());
+    }
+
+    let etag = product_etag(&product);
+    let not_modified = is_not_modified(&req, &etag, product.updated_at);
+
+    let mut response = if not_modified {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+    response
+        .insert_header(ETag(etag))
+        .insert_header(LastModified(std::time::SystemTime::from(product.updated_at).into()))
+        // Pollers should check back rather than trust a cached copy
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]));
+
+    if not_modified {
+        Ok(response.finish())
+    } else {
+        Ok(response.json(WithLocalTimes::new(product, utils::venue_timezone())))
+    }
+}
+
+
+/// GET /api/preparations/{id} - A preparation and its steps as JSON
+pub async fn api_preparation(
+    pool: web::Data<sqlx::PgPool>,
+    preparation_id: web::Path<Uuid>,
+) -> std::result::Result<HttpResponse, ApiError> {
+    let preparation = Preparation::get_by_id(pool.get_ref(), *preparation_id)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Preparation not found"))?;
+
+    let steps = PreparationStep::get_by_preparation_id(pool.get_ref(), preparation.id).await?;
+
+    let tz = utils::venue_timezone();
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "preparation": WithLocalTimes::new(preparation, tz),
+        "steps": steps.into_iter().map(|step| WithLocalTimes::new(step, tz)).collect::<Vec<_>>(),
+    })))
+}
+
+/// GET /api/preparations/{id}/steps - Just the steps, for displays that already have the preparation
+///
+/// An unknown preparation is a 404; one without steps is `[]`.
+pub async fn api_preparation_steps(
+    pool: web::Data<sqlx::PgPool>,
+    preparation_id: web::Path<Uuid>,
+) -> std::result::Result<HttpResponse, ApiError> {
+    let preparation = Preparation::get_by_id(pool.get_ref(), *preparation_id)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Preparation not found"))?;
+
+    let steps = PreparationStep::get_by_preparation_id(pool.get_ref(), preparation.id).await?;
+
+    let tz = utils::venue_timezone();
+    let steps: Vec<_> = steps.into_iter().map(|step| WithLocalTimes::new(step, tz)).collect();
+    Ok(HttpResponse::Ok().json(steps))
+}
+
+/// Most locations offered by the autocomplete
+const LOCATION_SUGGEST_LIMIT: i64 = 10;
+
+/// Shortest query the autocomplete answers; one letter matches most of the list
+const LOCATION_SUGGEST_MIN_CHARS: usize = 2;
+
+/// Query parameters for location autocomplete
+#[derive(Debug, serde::Deserialize)]
+pub struct LocationSuggestQuery {
+    q: Option<String>,
+}
+
+/// GET /api/locations/suggest?q= - Locations already in use that start with `q`
+///
+/// Feeds the location fields' datalist. A `q` under two characters gets `[]`.
+pub async fn api_location_suggest(
+    pool: web::Data<sqlx::PgPool>,
+    query: web::Query<LocationSuggestQuery>,
+) -> std::result::Result<HttpResponse, ApiError> {
+    let prefix = query.q.as_deref().unwrap_or("").trim();
+    if prefix.chars().count() < LOCATION_SUGGEST_MIN_CHARS {
+        return Ok(HttpResponse::Ok().json(Vec::<String>::new()));
+    }
+
+    let locations = Location::suggest(pool.get_ref(), prefix, LOCATION_SUGGEST_LIMIT).await?;
+    Ok(HttpResponse::Ok().json(locations))
+}
+
+/// GET /api/stats - Product and preparation counts for dashboards (admin only)
+pub async fn api_stats(
+    pool: web::Data<sqlx::PgPool>,
+    user: AuthenticatedUser,
+) -> std::result::Result<HttpResponse, ApiError> {
+    let is_admin = !user.is_kiosk
+        && User::get_by_id(pool.get_ref(), user.user_id)
+            .await?
+            .is_some_and(|u| u.is_admin());
+    if !is_admin {
+        return Err(ApiError::forbidden("Stats are only available to administrators"));
+    }
+
+    Ok(HttpResponse::Ok().json(StatsResponse {
+        stats: Stats::load(pool.get_ref()).await?,
+        picture_storage: StorageTotals::get(pool.get_ref()).await?,
+        storage_errors: storage::STORAGE_ERRORS.snapshot(),
+        backups: backup::BACKUP_STATS.snapshot(),
+    }))
+}
+
+/// GET /api/health/deep - Prove the database and upload storage accept writes (admin only)
+///
+/// Unlike a ping this does real I/O: a rolled-back insert, and a marker object in
+/// the bucket (or a file in `UPLOAD_DIR`), so bad credentials or permissions show
+/// up here before an upload fails. 503 when any component is failing.
+pub async fn api_health_deep(
+    pool: web::Data<sqlx::PgPool>,
+    s3_client: Option<web::Data<aws_sdk_s3::Client>>,
+    user: AuthenticatedUser,
+) -> std::result::Result<HttpResponse, ApiError> {
+    let is_admin = !user.is_kiosk
+        && User::get_by_id(pool.get_ref(), user.user_id)
+            .await?
+            .is_some_and(|u| u.is_admin());
+    if !is_admin {
+        return Err(ApiError::forbidden("Health checks are only available to administrators"));
+    }
+
+    let report = health::deep_check(pool.get_ref(), s3_client.as_ref().map(|c| c.get_ref())).await;
+    let mut response = if report.is_ok() {
+        HttpResponse::Ok()
+    } else {
+        HttpResponse::ServiceUnavailable()
+    };
+    Ok(response.insert_header(CacheControl(vec![CacheDirective::NoStore])).json(report))
+}
+
+/// `GET /api/stats` body: the database counts and picture bytes, plus this server's S3 failures and backups since it started
+#[derive(serde::Serialize)]
+struct StatsResponse {
+    #[serde(flatten)]
+    stats: Stats,
+    picture_storage: StorageTotals,
+    storage_errors: StorageErrorCounts,
+    backups: BackupStatus,
+}
+
+/// POST /api/preparations/import - Recreate an exported preparation with a new id
+pub async fn api_import_preparation(
+    pool: web::Data<sqlx::PgPool>,
+    events: web::Data<EventDispatcher>,
+    user: AuthenticatedUser,
+    payload: web::Json<PreparationImport>,
+) -> std::result::Result<HttpResponse, ApiError> {
+    let import = payload.into_inner();
+    let locations = Location::names(pool.get_ref()).await?;
+    import.validate(&locations, &utils::picture_url_hosts()).map_err(ApiError::validation)?;
+
+    let mut tx = pool.begin().await?;
+
+    let preparation = Preparation::create(
+        &mut *tx,
+        &import.preparation.name,
+        &import.preparation.prep_type,
+        &import.preparation.shift,
+        &import.preparation.location,
+        crate::models::imported_picture_url(&import.preparation.picture_url),
+        import.preparation.prep_time_minutes,
+        import.preparation.difficulty.as_deref().filter(|d| !d.is_empty()),
+        import.preparation.yield_quantity,
+        import.preparation.yield_unit.as_deref().map(str::trim).filter(|u| !u.is_empty()),
+        import.preparation.lang.as_deref().filter(|l| !l.is_empty()).unwrap_or(crate::i18n::primary_lang()),
+        None,
+        PREP_STATUS_PUBLISHED,
+    )
+    .await?;
+
+    let mut steps = import.steps;
+    steps.sort_by_key(|step| step.step_number);
+    let steps: Vec<_> = steps
+        .iter()
+        .map(|step| (step.description.as_str(), crate::models::imported_picture_url(&step.picture_url)))
+        .collect();
+
+    PreparationStep::create_many(&mut *tx, preparation.id, 1, &steps).await?;
+
+    PreparationStep::renumber(&mut *tx, preparation.id).await?;
+    tx.commit().await?;
+    events.emit(DomainEvent::PreparationCreated { id: preparation.id }, Some(&user.username));
+
+    Ok(HttpResponse::Created().json(serde_json::json!({ "id": preparation.id })))
+}
+
+/// GET /api/export/preparations.json - Every preparation with its steps, for backups
+///
+/// Preparations and steps come from a single joined cursor, and each preparation is
+/// written out as soon as its last step has been read, so the response never holds
+/// more than one preparation in memory. A database error mid-export ends the stream.
+pub async fn api_export_preparations(pool: web::Data<sqlx::PgPool>) -> Result<HttpResponse> {
+    let pool = pool.get_ref().clone();
+    let (tx, rx) = tokio::sync::mpsc::channel::<Result<Bytes>>(32);
+
+    actix_web::rt::spawn(async move {
+        if tx.send(Ok(Bytes::from_static(b"["))).await.is_err() {
+            return;
+        }
+
+        let mut current: Option<PreparationWithSteps> = None;
+        let mut written = 0usize;
+        let mut rows = Preparation::stream_with_steps(&pool);
+
+        while let Some(row) = rows.next().await {
+            let row = match row {
+                Ok(row) => row,
+                Err(e) => {
+                    eprintln!("Database error exporting preparations: {:?}", e);
+                    let _ = tx
+                        .send(Err(actix_web::error::ErrorInternalServerError("Failed to export preparations")))
+                        .await;
+                    return;
+                }
+            };
+
+            // Rows arrive grouped by preparation, so a new id means the previous one is complete
+            if let Some(done) = current.take_if(|export| export.preparation.id != row.preparation.id) {
+                if !send_export_item(&tx, &done, written == 0).await {
+                    return;
+                }
+                written += 1;
+            }
+
+            let step = row.step();
+            current
+                .get_or_insert_with(|| PreparationWithSteps {
+                    preparation: row.preparation,
+                    steps: Vec::new(),
+                })
+                .steps
+                .extend(step);
+        }
+
+        if let Some(done) = current {
+            if !send_export_item(&tx, &done, written == 0).await {
+                return;
+            }
+        }
+
+        let _ = tx.send(Ok(Bytes::from_static(b"]"))).await;
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .append_header(("Content-Disposition", "attachment; filename=\"preparations.json\""))
+        .streaming(channel_body(rx)))
+}
+
+/// Send one exported preparation as a JSON array element; false means the export should stop
+async fn send_export_item(
+    tx: &tokio::sync::mpsc::Sender<Result<Bytes>>,
+    export: &PreparationWithSteps,
+    first: bool,
+) -> bool {
+    let json = match serde_json::to_string(export) {
+        Ok(json) => json,
+        Err(e) => {
+            eprintln!("Serialization error exporting preparations: {:?}", e);
+            let _ = tx
+                .send(Err(actix_web::error::ErrorInternalServerError("Failed to export preparations")))
+                .await;
+            return false;
+        }
+    };
+    let chunk = if first { json } else { format!(",{}", json) };
+    tx.send(Ok(Bytes::from(chunk))).await.is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth;
+
+    #[test]
+    fn test_is_not_modified_prefers_etag_over_date() {
+        use actix_web::test::TestRequest;
+
+        let updated_at = chrono::DateTime::parse_from_rfc3339("2024-03-01T09:30:00.250Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let etag = EntityTag::new_strong("abc".to_string());
+        let check = |headers: &[(&str, &str)]| {
+            let mut request = TestRequest::get();
+            for header in headers {
+                request = request.insert_header(*header);
+            }
+            is_not_modified(&request.to_http_request(), &etag, updated_at)
+        };
+
+        assert!(!check(&[]));
+        assert!(check(&[("If-None-Match", "\"abc\"")]));
+        assert!(check(&[("If-None-Match", "W/\"abc\"")]));
+        assert!(check(&[("If-None-Match", "*")]));
+        assert!(!check(&[("If-None-Match", "\"old\"")]));
+
+        // The fraction of a second is lost in an HTTP date, so the same second counts as current
+        assert!(check(&[("If-Modified-Since", "Fri, 01 Mar 2024 09:30:00 GMT")]));
+        assert!(!check(&[("If-Modified-Since", "Fri, 01 Mar 2024 09:29:59 GMT")]));
+        // A stale ETag wins over a current date
+        assert!(!check(&[
+            ("If-None-Match", "\"old\""),
+            ("If-Modified-Since", "Fri, 01 Mar 2024 09:30:00 GMT"),
+        ]));
+    }
+
+    #[actix_web::test]
+    async fn test_api_product_answers_304_when_unchanged() {
+        use actix_web::test::{call_service, init_service, read_body, TestRequest};
+        use actix_web::App;
+
+        let Ok(url) = std::env::var("TEST_DATABASE_URL") else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+        let pool = sqlx::PgPool::connect(&url).await.expect("Failed to connect to TEST_DATABASE_URL");
+
+        // The handler reads through the pool, so the product is committed and removed afterwards
+        let product = Product::create(&pool, "Conditional Co", "Cream", "Coolroom", None, None, "")
+            .await
+            .expect("Failed to create product");
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .route("/api/products/{id}", web::get().to(api_product))
+                .route("/api/products/{id}", web::head().to(api_product)),
+        )
+        .await;
+        let uri = format!("/api/products/{}", product.id);
+
+        let response = call_service(&app, TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!(response.status(), 200);
+        let etag = response.headers().get("etag").expect("ETag header").clone();
+        let last_modified = response.headers().get("last-modified").expect("Last-Modified header").clone();
+
+        let request = TestRequest::get().uri(&uri).insert_header(("If-None-Match", etag.clone())).to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(response.status(), 304);
+        assert_eq!(response.headers().get("etag"), Some(&etag));
+        assert!(read_body(response).await.is_empty());
+
+        let request = TestRequest::get().uri(&uri).insert_header(("If-Modified-Since", last_modified)).to_request();
+        assert_eq!(call_service(&app, request).await.status(), 304);
+
+        let request = TestRequest::default().method(actix_web::http::Method::HEAD).uri(&uri).to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers().get("etag"), Some(&etag));
+
+        // Saving the product changes the ETag, so the old one gets the full body again
+        sqlx::query("UPDATE products SET description = 'Thickened' WHERE id = $1")
+            .bind(product.id)
+            .execute(&pool)
+            .await
+            .expect("Failed to update product");
+        let request = TestRequest::get().uri(&uri).insert_header(("If-None-Match", etag)).to_request();
+        assert_eq!(call_service(&app, request).await.status(), 200);
+
+        sqlx::query("DELETE FROM products WHERE id = $1")
+            .bind(product.id)
+            .execute(&pool)
+            .await
+            .expect("Failed to clean up product");
+    }
+
+    #[actix_web::test]
+    async fn test_api_follows_merged_products() {
+        use actix_web::test::{call_service, init_service, read_body_json, TestRequest};
+        use actix_web::App;
+
+        let Ok(url) = std::env::var("TEST_DATABASE_URL") else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+        let pool = sqlx::PgPool::connect(&url).await.expect("Failed to connect to TEST_DATABASE_URL");
+
+        // Both have barcodes (in-store codes, prefix 2), so the duplicate keeps its own
+        let duplicate = Product::create(&pool, "Merged API Co", "Merged API butter", "Coolroom", Some("2000000000039"), None, "")
+            .await
+            .expect("Failed to create product");
+        let kept = Product::create(&pool, "Merged API Co", "Merged API unsalted butter", "Coolroom", Some("2000000000046"), None, "")
+            .await
+            .expect("Failed to create product");
+        Product::merge_into(&pool, duplicate.id, kept.id, false, None, None).await.expect("Failed to merge");
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .route("/api/products/{id}", web::get().to(api_product))
+                .route("/api/v1/products/by-barcode/{code}", web::get().to(api_product_by_barcode)),
+        )
+        .await;
+
+        let by_id = call_service(&app, TestRequest::get().uri(&format!("/api/products/{}", duplicate.id)).to_request()).await;
+        let by_id_status = by_id.status();
+        let by_id_location = by_id.headers().get("location").cloned();
+        let scanned = call_service(&app, TestRequest::get().uri("/api/v1/products/by-barcode/2000000000039").to_request()).await;
+        let scanned_status = scanned.status();
+        let scanned: serde_json::Value = read_body_json(scanned).await;
+
+        // Deleting the product kept takes the merged duplicate with it
+        sqlx::query("DELETE FROM products WHERE id = $1")
+            .bind(kept.id)
+            .execute(&pool)
+            .await
+            .expect("Failed to clean up");
+
+        assert_eq!(by_id_status, 301);
+        assert_eq!(by_id_location.unwrap(), format!("/api/products/{}", kept.id).as_str());
+        assert_eq!(scanned_status, 200);
+        assert_eq!(scanned["id"], kept.id.to_string());
+        assert_eq!(scanned["barcode"], "2000000000046");
+    }
+
+    #[actix_web::test]
+    async fn test_api_preparation_steps_tells_missing_from_empty() {
+        use actix_web::test::{call_service, init_service, read_body_json, TestRequest};
+        use actix_web::App;
+
+        let Ok(url) = std::env::var("TEST_DATABASE_URL") else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+        let pool = sqlx::PgPool::connect(&url).await.expect("Failed to connect to TEST_DATABASE_URL");
+
+        // The handler reads through the pool, so the preparations are committed and removed afterwards
+        let create = |name| {
+            Preparation::create(&pool, name, "veg", "lunch", "Bench", None, None, None, None, None, "en", None, PREP_STATUS_PUBLISHED)
+        };
+        let with_steps = create("Steps API test slaw").await.expect("Failed to create preparation");
+        let without_steps = create("Steps API test garnish").await.expect("Failed to create preparation");
+        PreparationStep::create_many(&pool, with_steps.id, 1, &[("Shred the cabbage", None), ("Dress it", None)])
+            .await
+            .expect("Failed to create steps");
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .route("/api/preparations/{id}/steps", web::get().to(api_preparation_steps)),
+        )
+        .await;
+        let steps_of = |id: Uuid| TestRequest::get().uri(&format!("/api/preparations/{}/steps", id)).to_request();
+
+        let response = call_service(&app, steps_of(with_steps.id)).await;
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = read_body_json(response).await;
+        let descriptions: Vec<_> = body.as_array().expect("steps array").iter().map(|step| step["description"].clone()).collect();
+        assert_eq!(descriptions, vec!["Shred the cabbage", "Dress it"]);
+
+        let response = call_service(&app, steps_of(without_steps.id)).await;
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = read_body_json(response).await;
+        assert_eq!(body, serde_json::json!([]));
+
+        let response = call_service(&app, steps_of(Uuid::new_v4())).await;
+        assert_eq!(response.status(), 404);
+
+        sqlx::query("DELETE FROM preparations WHERE id = ANY($1)")
+            .bind(vec![with_steps.id, without_steps.id])
+            .execute(&pool)
+            .await
+            .expect("Failed to clean up preparations");
+    }
+
+    #[actix_web::test]
+    async fn test_import_emits_preparation_created() {
+        use crate::events::Capture;
+        use actix_web::test::{call_service, init_service, read_body_json, TestRequest};
+        use actix_web::App;
+
+        let Ok(url) = std::env::var("TEST_DATABASE_URL") else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+        let pool = sqlx::PgPool::connect(&url).await.expect("Failed to connect to TEST_DATABASE_URL");
+        std::env::set_var("JWT_SECRET", "test_secret_key_for_testing");
+        let token = auth::generate_token(Uuid::new_v4(), "events-test").expect("Failed to generate token");
+
+        let capture = Capture::default();
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(EventDispatcher::new().subscribe(capture.clone())))
+                .service(
+                    web::resource("/api/preparations/import")
+                        .route(web::post().to(api_import_preparation))
+                        .wrap(crate::middleware::Authentication),
+                ),
+        )
+        .await;
+
+        let location = Location::names(&pool).await.expect("Failed to list locations").into_iter().next();
+        let payload = serde_json::json!({
+            "preparation": {
+                "name": "Event Test Slaw",
+                "prep_type": "veg",
+                "shift": "both",
+                "location": location.as_deref().unwrap_or("Bench"),
+                "picture_url": null,
+            },
+            "steps": [{ "step_number": 1, "description": "Shred the cabbage", "picture_url": null }],
+        });
+        let request = TestRequest::post()
+            .uri("/api/preparations/import")
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .set_json(&payload)
+            .to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(response.status(), 201);
+        let body: serde_json::Value = read_body_json(response).await;
+        let id: Uuid = body["id"].as_str().and_then(|id| id.parse().ok()).expect("Created id");
+
+        assert_eq!(
+            capture.events(),
+            vec![(DomainEvent::PreparationCreated { id }, Some("events-test".to_string()))]
+        );
+
+        sqlx::query("DELETE FROM preparations WHERE id = $1")
+            .bind(id)
+            .execute(&pool)
+            .await
+            .expect("Failed to clean up preparation");
+    }
+
+    #[actix_web::test]
+    async fn test_duplicate_checks_response_contract() {
+        use actix_web::test::{call_service, init_service, read_body_json, TestRequest};
+        use actix_web::App;
+
+        let Ok(url) = std::env::var("TEST_DATABASE_URL") else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+        let pool = sqlx::PgPool::connect(&url).await.expect("Failed to connect to TEST_DATABASE_URL");
+
+        // The handlers read through the pool, so rows are committed and removed afterwards
+        let product = Product::create(&pool, "Wombat Spice Co", "Wombat Smoked Paprika", "Dry store", None, None, "")
+            .await
+            .expect("Failed to create product");
+        let other_product = Product::create(&pool, "Numbat Spices", "Wombat Smoked Paprika", "Dry store", None, None, "")
+            .await
+            .expect("Failed to create product");
+        let preparation = Preparation::create(
+            &pool, "Wombat Bone Stock", "veg", "both", "Stove", None, None, None, None, None, "en", None, PREP_STATUS_PUBLISHED,
+        )
+        .await
+        .expect("Failed to create preparation");
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .route("/api/v1/products/check-duplicate", web::get().to(api_check_product_duplicate))
+                .route("/api/v1/preparations/check-duplicate", web::get().to(api_check_preparation_duplicate)),
+        )
+        .await;
+        let check = |uri: &str| TestRequest::get().uri(uri).to_request();
+
+        // The same name from the same supplier, ignoring case and spaces
+        let response = call_service(&app, check("/api/v1/products/check-duplicate?supplier=wombat%20spice%20co&name=%20wombat%20smoked%20paprika")).await;
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = read_body_json(response).await;
+        let matches = body["matches"].as_array().unwrap();
+        assert_eq!(matches.len(), 1);
+        let mut keys: Vec<_> = matches[0].as_object().unwrap().keys().map(String::as_str).collect();
+        keys.sort();
+        assert_eq!(keys, ["id", "name", "similarity", "supplier_name", "updated_at", "url"]);
+        assert_eq!(matches[0]["id"], product.id.to_string());
+        assert_eq!(matches[0]["supplier_name"], "Wombat Spice Co");
+        assert_eq!(matches[0]["similarity"], 1.0);
+        assert_eq!(matches[0]["url"], format!("/product/{}", product.id));
+
+        // A misspelling still finds both, with a lower score
+        let body: serde_json::Value = read_body_json(call_service(&app, check("/api/v1/products/check-duplicate?name=wombat%20smoked%20papirka")).await).await;
+        let found: Vec<_> = body["matches"].as_array().unwrap().iter().map(|m| m["id"].as_str().unwrap().to_string()).collect();
+        assert!(found.contains(&product.id.to_string()) && found.contains(&other_product.id.to_string()));
+        let similarity = body["matches"][0]["similarity"].as_f64().unwrap();
+        assert!((0.3..1.0).contains(&similarity), "{}", similarity);
+
+        // Preparations have no supplier
+        let body: serde_json::Value = read_body_json(call_service(&app, check("/api/v1/preparations/check-duplicate?name=WOMBAT%20BONE%20STOCK")).await).await;
+        assert_eq!(body["matches"][0]["id"], preparation.id.to_string());
+        assert_eq!(body["matches"][0]["similarity"], 1.0);
+        assert_eq!(body["matches"][0]["url"], format!("/preparation/{}", preparation.id));
+        assert!(body["matches"][0].get("supplier_name").is_none());
+
+        let response = call_service(&app, check("/api/v1/preparations/check-duplicate?name=%20")).await;
+        assert_eq!(response.status(), 400);
+        let body: serde_json::Value = read_body_json(response).await;
+        assert_eq!(body["error"]["details"][0]["field"], "name");
+
+        sqlx::query("DELETE FROM products WHERE id = ANY($1)")
+            .bind(vec![product.id, other_product.id])
+            .execute(&pool)
+            .await
+            .expect("Failed to clean up products");
+        sqlx::query("DELETE FROM preparations WHERE id = $1")
+            .bind(preparation.id)
+            .execute(&pool)
+            .await
+            .expect("Failed to clean up preparation");
+    }
+}
@@ -0,0 +1,358 @@
+use crate::i18n::Translator;
+use crate::middleware::AuthenticatedUser;
+use crate::models::{ProductAudit, ProductAuditEntry, ProductFieldChange};
+use crate::pdf::{Font, PdfWriter};
+use crate::utils;
+use super::common::{channel_body, load_admin};
+use super::errors::{error_page, forbidden_response};
+use actix_web::{web, HttpResponse, Result};
+use bytes::Bytes;
+use chrono::{DateTime, Months, NaiveDate, Utc};
+use chrono_tz::Tz;
+use futures_util::{StreamExt, TryStreamExt};
+
+/// Longest period one export can cover
+const MAX_AUDIT_EXPORT_MONTHS: u32 = 12;
+
+/// Most changes put in a PDF; the CSV streams any number
+const MAX_PDF_AUDIT_ENTRIES: usize = 5000;
+
+/// Query parameters for the audit export
+#[derive(Debug, serde::Deserialize)]
+pub struct AuditExportQuery {
+    from: Option<String>,
+    to: Option<String>,
+    format: Option<String>,
+}
+
+/// What the audit export is downloaded as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AuditFormat {
+    Csv,
+    Pdf,
+}
+
+/// The venue-local days an export covers, both included
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct AuditRange {
+    from: NaiveDate,
+    to: NaiveDate,
+}
+
+impl AuditRange {
+    /// Read `?from=&to=` as YYYY-MM-DD dates, at most `MAX_AUDIT_EXPORT_MONTHS` apart
+    fn parse(from: Option<&str>, to: Option<&str>) -> std::result::Result<AuditRange, String> {
+        let date = |name: &str, value: Option<&str>| {
+            let value = value.map(str::trim).filter(|value| !value.is_empty());
+            let value = value.ok_or_else(|| format!("'{}' is required, as a date like 2026-07-01", name))?;
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .map_err(|_| format!("'{}' must be a date like 2026-07-01, not {:?}", name, value))
+        };
+        let (from, to) = (date("from", from)?, date("to", to)?);
+
+        if to < from {
+            return Err("'to' is before 'from'".to_string());
+        }
+        if from.checked_add_months(Months::new(MAX_AUDIT_EXPORT_MONTHS)).is_none_or(|limit| to >= limit) {
+            return Err(format!("An export can cover at most {} months", MAX_AUDIT_EXPORT_MONTHS));
+        }
+        Ok(AuditRange { from, to })
+    }
+
+    /// Start of `from` and of the day after `to`, in the venue's timezone
+    fn bounds(&self, tz: Tz) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        let start = utils::local_to_utc(tz, self.from.and_hms_opt(0, 0, 0)?)?;
+        let end = utils::local_to_utc(tz, self.to.succ_opt()?.and_hms_opt(0, 0, 0)?)?;
+        Some((start, end))
+    }
+
+    /// Download filename without extension, e.g. "audit-2026-07-01-to-2026-09-30"
+    fn file_stem(&self) -> String {
+        format!("audit-{}-to-{}", self.from, self.to)
+    }
+}
+
+/// CSV columns of the audit export
+const AUDIT_CSV_HEADER: [&str; 7] = ["changed_at", "changed_by", "entity_type", "entity_id", "entity_name", "changed_fields", "changes"];
+
+/// Each change as "Field: before -> after", separated by semicolons
+fn change_summary(changes: &[ProductFieldChange]) -> String {
+    let value = |value: &str| if value.is_empty() { "(none)".to_string() } else { value.to_string() };
+    changes
+        .iter()
+        .map(|change| format!("{}: {} -> {}", change.field, value(&change.before), value(&change.after)))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Who made a recorded change, as the export shows it
+fn changed_by(audit: &ProductAudit) -> &str {
+    audit.username.as_deref().unwrap_or("(unknown)")
+}
+
+/// One audit entry as a CSV record; times are in the venue's timezone with their offset
+fn audit_csv_row(entry: &ProductAuditEntry, tz: Tz) -> String {
+    let changes = entry.audit.changes();
+    let fields = changes.iter().map(|change| change.field).collect::<Vec<_>>().join(", ");
+    utils::csv_record(&[
+        &entry.audit.changed_at.with_timezone(&tz).to_rfc3339(),
+        changed_by(&entry.audit),
+        "product",
+        &entry.product_id.to_string(),
+        &format!("{} ({})", entry.product_name, entry.supplier_name),
+        &fields,
+        &change_summary(&changes),
+    ])
+}
+
+/// Lay out the PDF report: a heading per product, then each change to it, oldest first
+///
+/// `entries` come grouped by product, as `ProductAudit::stream_between` returns them.
+fn audit_pdf_document(range: AuditRange, tz: Tz, entries: &[ProductAuditEntry], truncated: bool) -> Vec<u8> {
+    let mut pdf = PdfWriter::new("Change report");
+    pdf.text("Change report", Font::Bold, 20.0);
+    pdf.text(
+        &format!("Product changes from {} to {} ({} time)", range.from, range.to, tz.name()),
+        Font::Regular,
+        11.0,
+    );
+    pdf.text(
+        &format!("Generated {}", Utc::now().with_timezone(&tz).format("%a %-d %b %Y, %-I:%M %P")),
+        Font::Regular,
+        9.0,
+    );
+    pdf.text("Preparation edits are not recorded in the audit trail, so they are not included.", Font::Regular, 9.0);
+    pdf.space(10.0);
+
+    if entries.is_empty() {
+        pdf.text("No changes were recorded in this period.", Font::Regular, 11.0);
+        return pdf.finish();
+    }
+    if truncated {
+        pdf.text(
+            &format!("Only the first {} changes are included; download the CSV for the full list.", MAX_PDF_AUDIT_ENTRIES),
+            Font::Bold,
+            10.0,
+        );
+        pdf.space(6.0);
+    }
+
+    let mut current = None;
+    for entry in entries {
+        if current != Some(entry.product_id) {
+            current = Some(entry.product_id);
+            pdf.space(6.0);
+            pdf.text(&format!("{} - {}", entry.product_name, entry.supplier_name), Font::Bold, 13.0);
+            pdf.text(&format!("Product {}", entry.product_id), Font::Regular, 8.0);
+            pdf.space(4.0);
+        }
+        let when = entry.audit.changed_at.with_timezone(&tz).format("%a %-d %b %Y, %-I:%M %P");
+        pdf.text(&format!("{}, by {}", when, changed_by(&entry.audit)), Font::Bold, 10.0);
+        let changes = entry.audit.changes();
+        if changes.is_empty() {
+            pdf.text("Saved with no field changes", Font::Regular, 10.0);
+        }
+        for change in &changes {
+            pdf.text(&change_summary(std::slice::from_ref(change)), Font::Regular, 10.0);
+        }
+        pdf.space(4.0);
+    }
+
+    pdf.finish()
+}
+
+/// GET /admin/audit/export?from=&to=&format=csv|pdf - Recorded changes in a date range (admin only)
+///
+/// Dates are days in the venue's timezone, both included. The CSV is streamed
+/// from a database cursor like the product export; the PDF is laid out in
+/// memory, so it stops at `MAX_PDF_AUDIT_ENTRIES` changes.
+pub async fn export_audit(
+    pool: web::Data<sqlx::PgPool>,
+    user: AuthenticatedUser,
+    query: web::Query<AuditExportQuery>,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    if load_admin(pool.get_ref(), &user).await?.is_none() {
+        return Ok(forbidden_response(&tr));
+    }
+
+    let bad_request = |message: &str| {
+        HttpResponse::BadRequest()
+            .content_type("text/html")
+            .body(error_page("Can't export the change report", Some(message), &[("/", tr.t("error-back-home"))]))
+    };
+    let format = match query.format.as_deref().map(str::trim).unwrap_or("csv") {
+        "csv" => AuditFormat::Csv,
+        "pdf" => AuditFormat::Pdf,
+        other => return Ok(bad_request(&format!("'format' must be csv or pdf, not {:?}", other))),
+    };
+    let range = match AuditRange::parse(query.from.as_deref(), query.to.as_deref()) {
+        Ok(range) => range,
+        Err(message) => return Ok(bad_request(&message)),
+    };
+    let tz = utils::venue_timezone();
+    let Some((start, end)) = range.bounds(tz) else {
+        return Ok(bad_request("Those dates can't be used"));
+    };
+
+    if format == AuditFormat::Pdf {
+        let mut entries: Vec<ProductAuditEntry> = ProductAudit::stream_between(pool.get_ref(), start, end)
+            .take(MAX_PDF_AUDIT_ENTRIES + 1)
+            .try_collect()
+            .await
+            .map_err(|e| {
+                eprintln!("Database error exporting audit entries: {:?}", e);
+                actix_web::error::ErrorInternalServerError("Failed to export changes")
+            })?;
+        let truncated = entries.len() > MAX_PDF_AUDIT_ENTRIES;
+        entries.truncate(MAX_PDF_AUDIT_ENTRIES);
+
+        return Ok(HttpResponse::Ok()
+            .content_type("application/pdf")
+            .append_header(("Content-Disposition", format!("attachment; filename=\"{}.pdf\"", range.file_stem())))
+            .body(audit_pdf_document(range, tz, &entries, truncated)));
+    }
+
+    let pool = pool.get_ref().clone();
+    let (tx, rx) = tokio::sync::mpsc::channel::<Result<Bytes>>(32);
+    actix_web::rt::spawn(async move {
+        if tx.send(Ok(Bytes::from(utils::csv_record(&AUDIT_CSV_HEADER)))).await.is_err() {
+            return;
+        }
+
+        let mut rows = ProductAudit::stream_between(&pool, start, end);
+        while let Some(row) = rows.next().await {
+            let chunk = match row {
+                Ok(entry) => Ok(Bytes::from(audit_csv_row(&entry, tz))),
+                Err(e) => {
+                    eprintln!("Database error exporting audit entries: {:?}", e);
+                    Err(actix_web::error::ErrorInternalServerError("Failed to export changes"))
+                }
+            };
+
+            let failed = chunk.is_err();
+            // Stop pulling rows once the client disconnects or the export has failed
+            if tx.send(chunk).await.is_err() || failed {
+                return;
+            }
+        }
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .append_header(("Content-Disposition", format!("attachment; filename=\"{}.csv\"", range.file_stem())))
+        .streaming(channel_body(rx)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ProductSnapshot;
+    use uuid::Uuid;
+
+    fn date(value: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(value, "%Y-%m-%d").unwrap()
+    }
+
+    fn entry(before: &ProductSnapshot, after: &ProductSnapshot) -> ProductAuditEntry {
+        ProductAuditEntry {
+            product_id: Uuid::from_u128(7),
+            supplier_name: "Bidfood, Sydney".to_string(),
+            product_name: "12\" pizza base".to_string(),
+            audit: ProductAudit {
+                id: Uuid::from_u128(8),
+                username: Some("sam".to_string()),
+                before: serde_json::to_string(before).unwrap(),
+                after: serde_json::to_string(after).unwrap(),
+                changed_at: "2026-07-01T04:30:00Z".parse().unwrap(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_audit_range_parse() {
+        assert_eq!(
+            AuditRange::parse(Some("2026-07-01"), Some(" 2026-09-30 ")),
+            Ok(AuditRange { from: date("2026-07-01"), to: date("2026-09-30") })
+        );
+        // A single day, and a full year, are fine
+        assert!(AuditRange::parse(Some("2026-07-01"), Some("2026-07-01")).is_ok());
+        assert!(AuditRange::parse(Some("2025-01-01"), Some("2025-12-31")).is_ok());
+
+        for (from, to) in [
+            (None, Some("2026-07-01")),
+            (Some("2026-07-01"), Some("")),
+            (Some("01/07/2026"), Some("2026-07-02")),
+            (Some("2026-07-02"), Some("2026-07-01")),
+            (Some("2025-01-01"), Some("2026-01-01")),
+        ] {
+            assert!(AuditRange::parse(from, to).is_err(), "{:?} to {:?}", from, to);
+        }
+    }
+
+    #[test]
+    fn test_audit_range_bounds_use_the_venue_timezone() {
+        let range = AuditRange { from: date("2026-07-01"), to: date("2026-07-31") };
+        let (start, end) = range.bounds(chrono_tz::Australia::Sydney).unwrap();
+        assert_eq!(start.to_rfc3339(), "2026-06-30T14:00:00+00:00");
+        assert_eq!(end.to_rfc3339(), "2026-07-31T14:00:00+00:00");
+        assert_eq!(range.file_stem(), "audit-2026-07-01-to-2026-07-31");
+    }
+
+    #[test]
+    fn test_audit_csv_row_escapes_fields() {
+        let before = ProductSnapshot {
+            location: "Dry Store".to_string(),
+            description: "Keep flat".to_string(),
+            ..ProductSnapshot::default()
+        };
+        let after = ProductSnapshot {
+            location: "Cold Room, Shelf 2".to_string(),
+            description: "Keep flat.\nSay \"hi\"".to_string(),
+            barcode: Some("9300633603206".to_string()),
+            ..ProductSnapshot::default()
+        };
+
+        assert_eq!(
+            audit_csv_row(&entry(&before, &after), chrono_tz::Australia::Sydney),
+            format!(
+                "2026-07-01T14:30:00+10:00,sam,product,{},\"12\"\" pizza base (Bidfood, Sydney)\",\"Location, Description, Barcode\",\"{}\"\r\n",
+                Uuid::from_u128(7),
+                "Location: Dry Store -> Cold Room, Shelf 2; Description: Keep flat -> Keep flat.\nSay \"\"hi\"\"; Barcode: (none) -> 9300633603206"
+            )
+        );
+        assert_eq!(utils::csv_record(&AUDIT_CSV_HEADER), "changed_at,changed_by,entity_type,entity_id,entity_name,changed_fields,changes\r\n");
+    }
+
+    #[test]
+    fn test_audit_csv_row_escapes_formulas() {
+        let snapshot = ProductSnapshot::default();
+        let mut entry = entry(&snapshot, &snapshot);
+        entry.product_name = "=HYPERLINK(\"http://x\",\"y\")".to_string();
+        entry.audit.username = Some("@admin".to_string());
+
+        assert_eq!(
+            audit_csv_row(&entry, chrono_tz::UTC),
+            format!(
+                "2026-07-01T04:30:00+00:00,'@admin,product,{},\"'=HYPERLINK(\"\"http://x\"\",\"\"y\"\") (Bidfood, Sydney)\",,\r\n",
+                Uuid::from_u128(7)
+            )
+        );
+    }
+
+    #[test]
+    fn test_audit_pdf_for_an_empty_range() {
+        let range = AuditRange { from: date("2026-07-01"), to: date("2026-09-30") };
+        let pdf = String::from_utf8_lossy(&audit_pdf_document(range, chrono_tz::UTC, &[], false)).into_owned();
+        assert!(pdf.starts_with("%PDF-"));
+        assert!(pdf.contains("No changes were recorded in this period."));
+        assert!(pdf.contains("Product changes from 2026-07-01 to 2026-09-30 \\(UTC time\\)"));
+
+        let snapshot = ProductSnapshot::default();
+        let pdf = audit_pdf_document(range, chrono_tz::UTC, &[entry(&snapshot, &snapshot)], true);
+        let pdf = String::from_utf8_lossy(&pdf).into_owned();
+        assert!(!pdf.contains("No changes were recorded"));
+        assert!(pdf.contains("Saved with no field changes"));
+        assert!(pdf.contains("download the CSV for the full list"));
+    }
+}
@@ -0,0 +1,261 @@
+use crate::auth;
+use crate::i18n::Translator;
+use crate::middleware::ClientInfo;
+use crate::models::{KioskToken, LoginForm, RegisterForm, User};
+use crate::utils;
+use super::errors::error_page;
+use actix_web::{web, HttpResponse, Result};
+use askama::Template;
+/// Template for login page
+#[derive(Template)]
+#[template(path = "login.html")]
+struct LoginTemplate {
+    error: String,
+    next: String,
+}
+
+/// Query string of the login page
+#[derive(serde::Deserialize)]
+pub struct LoginQuery {
+    next: Option<String>,
+}
+
+/// Template for register page
+#[allow(dead_code)] // Unused while registration is disabled
+#[derive(Template)]
+#[template(path = "register.html")]
+struct RegisterTemplate {
+    error: String,
+}
+
+/// GET /login - Show login form
+pub async fn login_form(
+    auth: crate::middleware::OptionalAuth,
+    query: web::Query<LoginQuery>,
+) -> Result<HttpResponse> {
+    let next = utils::safe_next_path(query.next.as_deref());
+
+    // If already logged in, go straight to where the login was meant to lead
+    if auth.user.is_some() {
+        return Ok(HttpResponse::SeeOther()
+            .append_header(("Location", next))
+            .finish());
+    }
+
+    let template = LoginTemplate {
+        error: String::new(),
+        next: next.to_string(),
+    };
+
+    let html = template.render().map_err(|e| {
+        eprintln!("Template error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to render template")
+    })?;
+
+    Ok(HttpResponse::Ok().content_type("text/html").body(html))
+}
+
+/// POST /login - Handle login submission
+pub async fn login(
+    pool: web::Data<sqlx::PgPool>,
+    form: web::Form<LoginForm>,
+    client: ClientInfo,
+) -> Result<HttpResponse> {
+    let next = utils::safe_next_path(form.next.as_deref());
+
+    // Find user by username
+    let user = User::get_by_username(pool.get_ref(), &form.username)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+
+    match user {
+        Some(user) => {
+            // Verify password
+            match auth::verify_password(&form.password, &user.password_hash) {
+                Ok(true) => {
+                    // Password correct - generate JWT token
+                    let token = auth::generate_token(user.id, &user.username)
+                        .map_err(|e| {
+                            eprintln!("Token generation error: {:?}", e);
+                            actix_web::error::ErrorInternalServerError("Failed to generate token")
+                        })?;
+
+                    // Set cookie and return to the page that asked for a login
+                    Ok(HttpResponse::SeeOther()
+                        .append_header(("Location", next))
+                        .cookie(
+                            client.cookie("auth_token", token)
+                                .http_only(true)
+                                .finish()
+                        )
+                        .finish())
+                }
+                Ok(false) => {
+                    // Password incorrect
+                    let template = LoginTemplate {
+                        error: "Invalid username or password".to_string(),
+                        next: next.to_string(),
+                    };
+                    let html = template.render().map_err(|e| {
+                        eprintln!("Template error: {:?}", e);
+                        actix_web::error::ErrorInternalServerError("Failed to render template")
+                    })?;
+                    Ok(HttpResponse::Unauthorized()
+                        .content_type("text/html")
+                        .body(html))
+                }
+                Err(e) => {
+                    eprintln!("Password verification error: {:?}", e);
+                    Err(actix_web::error::ErrorInternalServerError("Authentication error"))
+                }
+            }
+        }
+        None => {
+            // User not found - still run a bcrypt verification so this path takes
+            // as long as a wrong password and doesn't reveal which usernames exist
+            auth::verify_dummy_password(&form.password);
+
+            let template = LoginTemplate {
+                error: "Invalid username or password".to_string(),
+                next: next.to_string(),
+            };
+            let html = template.render().map_err(|e| {
+                eprintln!("Template error: {:?}", e);
+                actix_web::error::ErrorInternalServerError("Failed to render template")
+            })?;
+            Ok(HttpResponse::Unauthorized()
+                .content_type("text/html")
+                .body(html))
+        }
+    }
+}
+
+/// GET /register - Show registration form (TEMPORARILY DISABLED)
+pub async fn register_form(tr: Translator) -> Result<HttpResponse> {
+    // Registration temporarily disabled
+    Ok(HttpResponse::NotFound()
+        .content_type("text/html")
+        .body(error_page(
+            &tr.t("error-registration-disabled"),
+            Some(&tr.t("error-registration-contact")),
+            &[("/login", tr.t("error-go-to-login")), ("/", tr.t("error-back-home"))],
+        )))
+}
+
+/// POST /register - Handle registration submission (TEMPORARILY DISABLED)
+pub async fn register(
+    _pool: web::Data<sqlx::PgPool>,
+    _form: web::Form<RegisterForm>,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    // Registration temporarily disabled
+    Ok(HttpResponse::NotFound()
+        .content_type("text/html")
+        .body(error_page(
+            &tr.t("error-registration-disabled"),
+            Some(&tr.t("error-registration-contact")),
+            &[("/login", tr.t("error-go-to-login")), ("/", tr.t("error-back-home"))],
+        )))
+}
+
+/// GET /logout - Handle logout
+pub async fn logout(client: ClientInfo) -> Result<HttpResponse> {
+    Ok(HttpResponse::SeeOther()
+        .append_header(("Location", "/"))
+        .cookie(
+            client.cookie("auth_token", "")
+                .http_only(true)
+                .max_age(actix_web::cookie::time::Duration::seconds(0))
+                .finish()
+        )
+        .finish())
+}
+
+/// GET /lang/{code} - Remember the visitor's language, overriding their browser's
+pub async fn set_language(code: web::Path<String>, client: ClientInfo) -> Result<HttpResponse> {
+    let mut response = HttpResponse::SeeOther();
+    response.append_header(("Location", "/"));
+    // Unknown codes just go home, keeping whatever language was in use
+    if let Some(locale) = crate::i18n::supported_locale(&code) {
+        response.cookie(
+            client.cookie(crate::i18n::LANG_COOKIE, locale)
+                .same_site(actix_web::cookie::SameSite::Lax)
+                .max_age(actix_web::cookie::time::Duration::days(365))
+                .finish()
+        );
+    }
+    Ok(response.finish())
+}
+
+
+/// GET /kiosk/{token} - Enrol a shared tablet by storing its device token in a long-lived cookie
+pub async fn kiosk_enrol(
+    pool: web::Data<sqlx::PgPool>,
+    token: web::Path<String>,
+    tr: Translator,
+    client: ClientInfo,
+) -> Result<HttpResponse> {
+    let kiosk = KioskToken::find_active_by_hash(pool.get_ref(), &auth::hash_kiosk_token(&token))
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to check kiosk token")
+        })?;
+
+    let kiosk = match kiosk {
+        Some(kiosk) => kiosk,
+        None => {
+            return Ok(HttpResponse::NotFound()
+                .content_type("text/html")
+                .body(error_page(
+                    &tr.t("error-kiosk-link-not-found"),
+                    Some(&tr.t("error-kiosk-link-invalid")),
+                    &[("/", tr.t("error-back-home"))],
+                )));
+        }
+    };
+
+    KioskToken::touch(pool.get_ref(), kiosk.id)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to update kiosk device")
+        })?;
+
+    Ok(HttpResponse::SeeOther()
+        .append_header(("Location", "/"))
+        .cookie(
+            client.cookie(auth::KIOSK_COOKIE, token.into_inner())
+                .http_only(true)
+                .same_site(actix_web::cookie::SameSite::Lax)
+                .max_age(actix_web::cookie::time::Duration::days(365))
+                .finish()
+        )
+        .finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_set_language_only_remembers_supported_locales() {
+        use actix_web::test::{call_service, init_service, TestRequest};
+        use actix_web::App;
+
+        let app = init_service(App::new().route("/lang/{code}", web::get().to(set_language))).await;
+
+        let response = call_service(&app, TestRequest::get().uri("/lang/es-AR").to_request()).await;
+        assert_eq!(response.status(), 303);
+        assert_eq!(response.headers().get("location").unwrap(), "/");
+        let cookie = response.response().cookies().next().expect("lang cookie");
+        assert_eq!((cookie.name(), cookie.value()), (crate::i18n::LANG_COOKIE, "es"));
+
+        let response = call_service(&app, TestRequest::get().uri("/lang/xx").to_request()).await;
+        assert_eq!(response.status(), 303);
+        assert_eq!(response.response().cookies().count(), 0);
+    }
+}
@@ -0,0 +1,368 @@
+use crate::i18n::Translator;
+use crate::middleware::AuthenticatedUser;
+use crate::models::{FormEntity, FormSubmission, ImageReference, Location, Onboarding, User, UserPreferences};
+use crate::recently_viewed::{self, RecentItem, RecentlyViewed, ViewedKind, RECENTLY_VIEWED_COOKIE};
+use crate::storage::{self, Storage, StorageError};
+use crate::utils;
+use super::errors::error_page;
+use actix_multipart::form::tempfile::TempFile;
+use actix_web::{web, HttpResponse, Result};
+use aws_sdk_s3::Client as S3Client;
+use bytes::Bytes;
+use std::io::Read;
+use uuid::Uuid;
+/// Template filters for timestamps in the venue's timezone, linked text and file sizes
+pub(super) mod filters {
+    use crate::utils;
+    use chrono::{DateTime, Utc};
+
+    /// `{{ product.updated_at|venue_time }}` renders "Tue 14 May, 2:45 pm"
+    pub fn venue_time(at: &DateTime<Utc>) -> askama::Result<String> {
+        Ok(utils::format_local_time(*at, utils::venue_timezone()))
+    }
+
+    /// `{{ prep.created_at|time_ago }}` renders "2 hours ago"
+    pub fn time_ago(at: &DateTime<Utc>) -> askama::Result<String> {
+        Ok(utils::format_time_ago(*at, Utc::now(), utils::venue_timezone()))
+    }
+
+    /// `{{ product.description|autolink|safe }}` escapes the text and links its URLs
+    pub fn autolink(text: &str) -> askama::Result<String> {
+        Ok(utils::autolink(text))
+    }
+
+    /// `{{ item.total_bytes|filesize }}` renders "1.5 MB"
+    pub fn filesize(bytes: &i64) -> askama::Result<String> {
+        Ok(utils::format_bytes(*bytes))
+    }
+}
+
+/// Which getting-started steps a fresh install has done
+pub(super) async fn load_onboarding(pool: &sqlx::PgPool) -> Result<Onboarding> {
+    Onboarding::load(pool).await.map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to check setup progress")
+    })
+}
+
+/// The visitor's recently viewed list: from their preferences when signed in,
+/// otherwise (kiosk tablets too, so each keeps its own) from the signed cookie
+///
+/// Failing to read it only hides the strip, so errors are logged rather than returned.
+pub(super) async fn load_recently_viewed(
+    req: &actix_web::HttpRequest,
+    pool: &sqlx::PgPool,
+    auth: &crate::middleware::OptionalAuth,
+) -> RecentlyViewed {
+    match auth.user.as_ref().filter(|user| !user.is_kiosk) {
+        Some(user) => match UserPreferences::get(pool, user.user_id).await {
+            Ok(preferences) => preferences.recently_viewed,
+            Err(e) => {
+                eprintln!("Failed to load recently viewed: {:?}", e);
+                RecentlyViewed::default()
+            }
+        },
+        None => recently_viewed_cookie(req),
+    }
+}
+
+/// The recently viewed list kept in the cookie; empty when missing or not signed by us
+pub(super) fn recently_viewed_cookie(req: &actix_web::HttpRequest) -> RecentlyViewed {
+    req.cookie(RECENTLY_VIEWED_COOKIE)
+        .map(|cookie| RecentlyViewed::from_cookie_value(cookie.value(), &crate::auth::JwtKeys::from_env()))
+        .unwrap_or_default()
+}
+
+/// Names and links for the quick-access strip, leaving out the page being shown
+pub(super) async fn recently_viewed_items(pool: &sqlx::PgPool, list: &RecentlyViewed, exclude: Option<Uuid>) -> Vec<RecentItem> {
+    recently_viewed::load_items(pool, list, exclude).await.unwrap_or_else(|e| {
+        eprintln!("Failed to load recently viewed: {:?}", e);
+        Vec::new()
+    })
+}
+
+/// Put a view at the front of `list` and save it where `load_recently_viewed` reads it
+///
+/// Returns the cookie to set for visitors without an account. Nothing is
+/// written when the item was already the most recent, e.g. on a refresh.
+pub(super) async fn record_recent_view(
+    req: &actix_web::HttpRequest,
+    pool: &sqlx::PgPool,
+    auth: &crate::middleware::OptionalAuth,
+    mut list: RecentlyViewed,
+    kind: ViewedKind,
+    id: Uuid,
+) -> Option<actix_web::cookie::Cookie<'static>> {
+    let before = list.clone();
+    list.record(kind, id);
+    if list == before {
+        return None;
+    }
+    match auth.user.as_ref().filter(|user| !user.is_kiosk) {
+        Some(user) => {
+            // Losing a view only costs a search later
+            if let Err(e) = UserPreferences::set_recently_viewed(pool, user.user_id, &list).await {
+                eprintln!("Failed to save recently viewed: {:?}", e);
+            }
+            None
+        }
+        None => Some(
+            crate::middleware::ClientInfo::of(req)
+                .cookie(RECENTLY_VIEWED_COOKIE, list.to_cookie_value(&crate::auth::JwtKeys::from_env()))
+                .http_only(true)
+                .same_site(actix_web::cookie::SameSite::Lax)
+                .max_age(actix_web::cookie::time::Duration::days(30))
+                .finish(),
+        ),
+    }
+}
+
+/// The add form's one-time token, or the "form expired" page when it's missing
+/// and `REQUIRE_FORM_TOKEN` is on
+///
+/// Forms opened before tokens were added have none, so by default a missing or
+/// malformed token just skips the double-submit check.
+pub(super) fn submitted_form_token(token: Option<&str>, tr: &Translator) -> Result<Option<Uuid>> {
+    match token.and_then(|token| Uuid::parse_str(token.trim()).ok()) {
+        Some(token) => Ok(Some(token)),
+        None if utils::env_flag("REQUIRE_FORM_TOKEN") => {
+            let page = HttpResponse::BadRequest().content_type("text/html").body(error_page(
+                &tr.t("error-bad-request"),
+                Some(&tr.t("error-form-expired")),
+                &[("/", tr.t("error-back-home"))],
+            ));
+            Err(actix_web::error::InternalError::from_response("missing form token", page).into())
+        }
+        None => Ok(None),
+    }
+}
+
+/// The record an earlier submit of the same form created, if any
+pub(super) async fn created_with_form_token(pool: &sqlx::PgPool, token: Option<Uuid>, entity: FormEntity) -> Result<Option<Uuid>> {
+    let Some(token) = token else {
+        return Ok(None);
+    };
+    FormSubmission::created_with(pool, token, entity).await.map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to check the form token")
+    })
+}
+
+/// Record the form token in the transaction creating `entity_id`; false means
+/// another submit of the same form got there first, so the transaction must not commit
+pub(super) async fn record_form_token(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    token: Option<Uuid>,
+    entity: FormEntity,
+    entity_id: Uuid,
+) -> Result<bool> {
+    let Some(token) = token else {
+        return Ok(true);
+    };
+    FormSubmission::record(tx, token, entity, entity_id).await.map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to record the form token")
+    })
+}
+
+/// Send a repeated submit to what the first one created
+pub(super) fn already_created_redirect(location: String) -> HttpResponse {
+    HttpResponse::SeeOther().append_header(("Location", location)).finish()
+}
+
+/// Turn the receiving end of an export channel into a streaming response body
+pub(super) fn channel_body(
+    rx: tokio::sync::mpsc::Receiver<Result<Bytes>>,
+) -> impl futures_util::Stream<Item = Result<Bytes>> {
+    futures_util::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    })
+}
+
+
+/// Allowed locations for the product and preparation forms
+pub(super) async fn load_locations(pool: &sqlx::PgPool) -> Result<Vec<String>> {
+    Location::names(pool).await.map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to fetch locations")
+    })
+}
+
+/// Helper function to upload an image to S3 or local storage
+pub(super) async fn upload_image_to_storage(
+    s3_client: &web::Data<S3Client>,
+    file_data: &[u8],
+    kind: utils::ImageKind,
+) -> Result<String> {
+    let s3_enabled = utils::env_flag("S3_ENABLED");
+
+    if s3_enabled {
+        // Upload to S3
+        let bucket_name = std::env::var("S3_BUCKET_NAME")
+            .unwrap_or_else(|_| "kitchen-hand-guide".to_string());
+
+        utils::upload_to_s3(
+            s3_client.get_ref(),
+            &bucket_name,
+            Bytes::from(file_data.to_vec()),
+            kind,
+        )
+        .await
+        .map_err(storage_upload_error)
+    } else {
+        // Save to local filesystem (fallback)
+        utils::save_to_upload_dir(&utils::upload_dir(), file_data, kind).map_err(|e| {
+            eprintln!("Failed to save uploaded file: {}", e);
+            actix_web::error::ErrorInternalServerError("Failed to save uploaded file")
+        })
+    }
+}
+
+/// Log a failed S3 upload by how serious it is, and tell the user whether to retry
+pub(super) fn storage_upload_error(error: StorageError) -> actix_web::Error {
+    if error.is_misconfiguration() {
+        eprintln!("S3 configuration error, check the bucket and credentials: {}", error);
+    } else {
+        eprintln!("S3 upload error: {}", error);
+    }
+    match error {
+        StorageError::Auth(_) | StorageError::NotFound(_) => actix_web::error::ErrorInternalServerError(
+            "Image storage isn't set up correctly, so the picture couldn't be saved. Please tell an administrator.",
+        ),
+        StorageError::Throttled(_) => actix_web::error::ErrorServiceUnavailable(
+            "Image storage is busy right now. Please wait a minute and try again.",
+        ),
+        StorageError::Network(_) => actix_web::error::ErrorServiceUnavailable(
+            "Image storage couldn't be reached. Please try again.",
+        ),
+        StorageError::Other(_) => actix_web::error::ErrorInternalServerError("Failed to upload file to S3"),
+    }
+}
+
+/// Store a WebP copy of a freshly uploaded picture when `WEBP_CONVERT` is on
+///
+/// Conversion problems are only logged: the original picture is kept either way.
+pub(super) async fn upload_webp_variant(
+    s3_client: &web::Data<S3Client>,
+    file_data: &[u8],
+    kind: utils::ImageKind,
+) -> Option<String> {
+    if !utils::env_flag("WEBP_CONVERT") {
+        return None;
+    }
+
+    // Decoding and re-encoding a large photo takes a while, so keep it off the worker thread
+    let data = file_data.to_vec();
+    let converted = web::block(move || utils::webp_variant(&data, kind))
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()));
+
+    match converted {
+        Ok(Some(webp)) => upload_image_to_storage(s3_client, &webp, utils::ImageKind::Webp).await.ok(),
+        Ok(None) => None,
+        Err(e) => {
+            eprintln!("WebP conversion error: {}", e);
+            None
+        }
+    }
+}
+
+/// Make the blurred placeholder of a freshly uploaded picture when `LQIP_ENABLED` is on
+///
+/// Like the WebP copy, a failure is only logged and the picture is kept without one.
+pub(super) async fn image_placeholder(file_data: &[u8], kind: utils::ImageKind) -> Option<String> {
+    if !utils::env_flag("LQIP_ENABLED") {
+        return None;
+    }
+
+    let data = file_data.to_vec();
+    let placeholder = web::block(move || utils::lqip(&data, kind))
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()));
+
+    placeholder.unwrap_or_else(|e| {
+        eprintln!("Placeholder error: {}", e);
+        None
+    })
+}
+
+/// Delete images a save stopped using, unless another record still shows them
+///
+/// Failures are only logged: the save itself has already succeeded.
+pub(super) async fn delete_replaced_images(pool: &sqlx::PgPool, s3_client: &S3Client, mut urls: Vec<String>) {
+    urls.sort();
+    urls.dedup();
+    let storage = Storage::from_env(s3_client);
+    for url in urls {
+        if let Err(e) = storage::delete_if_unreferenced(pool, &storage, &url).await {
+            eprintln!("Image cleanup error: {}", e);
+        }
+    }
+}
+
+/// Record the stored size of a picture that was just uploaded, once its row has been saved
+pub(super) async fn record_picture_size<'e, E: sqlx::PgExecutor<'e>>(
+    executor: E,
+    url: Option<&str>,
+    size_bytes: Option<i64>,
+) -> Result<()> {
+    let (Some(url), Some(size_bytes)) = (url, size_bytes) else {
+        return Ok(());
+    };
+    ImageReference::record_size(executor, url, size_bytes).await.map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to save picture size")
+    })
+}
+
+/// Helper function to read an uploaded temp file into memory
+pub(super) fn read_temp_file(picture: &TempFile) -> Result<Vec<u8>> {
+    let mut file_content = Vec::new();
+    let mut file = std::fs::File::open(picture.file.path()).map_err(|e| {
+        eprintln!("Failed to open uploaded file: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to read uploaded file")
+    })?;
+    file.read_to_end(&mut file_content).map_err(|e| {
+        eprintln!("Failed to read uploaded file: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to read uploaded file")
+    })?;
+    Ok(file_content)
+}
+
+/// Look up the authenticated user and return them only if they are an admin
+pub(super) async fn load_admin(pool: &sqlx::PgPool, user: &AuthenticatedUser) -> Result<Option<User>> {
+    if user.is_kiosk {
+        return Ok(None);
+    }
+
+    let user = User::get_by_id(pool, user.user_id)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to fetch user")
+        })?;
+
+    Ok(user.filter(|u| u.is_admin()))
+}
+
+/// Whether a database error is a unique constraint violation
+pub(super) fn is_unique_violation(e: &sqlx::Error) -> bool {
+    matches!(e, sqlx::Error::Database(db) if db.is_unique_violation())
+}
+
+/// Permanent redirect from a merged product's page (`/product/{id}{path}`) to the product it was merged into
+pub(super) fn merged_product_redirect(target: Uuid, path: &str) -> HttpResponse {
+    HttpResponse::MovedPermanently()
+        .append_header(("Location", format!("/product/{}{}", target, path)))
+        .finish()
+}
+
+/// A 409 page for a change sent to a merged product, which would otherwise bring it back
+pub(super) fn merged_product_conflict(target: Uuid, tr: &Translator) -> HttpResponse {
+    let kept = format!("/product/{}", target);
+    HttpResponse::Conflict().content_type("text/html").body(error_page(
+        "409 - Product Merged",
+        Some("This product was merged into another. Make the change there instead."),
+        &[(&kept, tr.t("error-back-product"))],
+    ))
+}
@@ -0,0 +1,201 @@
+use crate::i18n::Translator;
+use crate::models::{Product, ProductDocument};
+use crate::storage;
+use crate::utils;
+use super::common::{merged_product_conflict, read_temp_file, storage_upload_error};
+use super::errors::error_page;
+use actix_multipart::form::{tempfile::TempFile, MultipartForm};
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::{web, HttpResponse, Result};
+use aws_sdk_s3::Client as S3Client;
+use bytes::Bytes;
+use uuid::Uuid;
+
+/// Multipart form for attaching a document to a product
+#[derive(Debug, MultipartForm)]
+pub struct ProductDocumentForm {
+    #[multipart(limit = "20 MB")]
+    document: TempFile,
+}
+
+/// Longest document name kept, in characters (the column holds 255)
+const DOCUMENT_NAME_MAX_CHARS: usize = 255;
+
+/// Name to show and download a document under: the uploaded name made safe,
+/// or `document.<ext>` when nothing is left of it
+fn document_display_name(uploaded: &str, kind: utils::DocumentKind) -> String {
+    let name: String = sanitize_filename::sanitize(uploaded.trim()).chars().take(DOCUMENT_NAME_MAX_CHARS).collect();
+    if name.trim().is_empty() {
+        format!("document.{}", kind.extension())
+    } else {
+        name
+    }
+}
+
+/// The product a document route is for, or the 404 page
+async fn document_product(pool: &sqlx::PgPool, id: Uuid, tr: &Translator) -> Result<std::result::Result<Product, HttpResponse>> {
+    let product = Product::get_by_id(pool, id).await.map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to fetch product")
+    })?;
+    Ok(product.ok_or_else(|| {
+        HttpResponse::NotFound()
+            .content_type("text/html")
+            .body(error_page(&tr.t("error-product-not-found"), None, &[("/", tr.t("error-back-home"))]))
+    }))
+}
+
+/// A 400 page explaining why a document wasn't attached, linking back to the product
+fn document_rejected(product_id: Uuid, message: &str, tr: &Translator) -> HttpResponse {
+    let back = format!("/product/{}", product_id);
+    HttpResponse::BadRequest()
+        .content_type("text/html")
+        .body(error_page(&tr.t("error-validation"), Some(message), &[(&back, tr.t("error-back-product"))]))
+}
+
+/// POST /product/{id}/documents - Attach a document of one of the `DOCUMENT_TYPES` to a product
+pub async fn upload_product_document(
+    pool: web::Data<sqlx::PgPool>,
+    s3_client: web::Data<S3Client>,
+    id: web::Path<Uuid>,
+    auth: crate::middleware::OptionalAuth,
+    MultipartForm(form): MultipartForm<ProductDocumentForm>,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    let product = match document_product(pool.get_ref(), *id, &tr).await? {
+        Ok(product) => product,
+        Err(not_found) => return Ok(not_found),
+    };
+
+    if let Some(target) = product.merged_into {
+        return Ok(merged_product_conflict(target, &tr));
+    }
+    let allowed = utils::document_types();
+    if allowed.is_empty() {
+        return Ok(document_rejected(product.id, &tr.t("error-documents-disabled"), &tr));
+    }
+    if product.is_draft() {
+        return Ok(document_rejected(product.id, &tr.t("error-document-draft-product"), &tr));
+    }
+    let uploaded_name = form.document.file_name.clone().unwrap_or_default();
+    if uploaded_name.trim().is_empty() {
+        return Ok(document_rejected(product.id, &tr.t("error-document-missing"), &tr));
+    }
+
+    let data = read_temp_file(&form.document)?;
+    let kind = match utils::document_kind(&uploaded_name, &data, &allowed) {
+        Ok(kind) => kind,
+        Err(message) => return Ok(document_rejected(product.id, &message, &tr)),
+    };
+
+    let size_bytes = data.len() as i64;
+    let stored = storage::store_document(s3_client.get_ref(), Bytes::from(data), kind)
+        .await
+        .map_err(storage_upload_error)?;
+    let created = ProductDocument::create(
+        pool.get_ref(),
+        product.id,
+        &document_display_name(&uploaded_name, kind),
+        kind.extension(),
+        stored.storage,
+        &stored.key,
+        size_bytes,
+        auth.username(),
+    )
+    .await;
+    if let Err(e) = created {
+        eprintln!("Database error saving document: {:?}", e);
+        // Nothing refers to the file, so don't leave it behind
+        if let Err(e) = storage::delete_document(s3_client.get_ref(), stored.storage, &stored.key).await {
+            eprintln!("Document cleanup error: {}", e);
+        }
+        return Err(actix_web::error::ErrorInternalServerError("Failed to save document"));
+    }
+
+    Ok(HttpResponse::SeeOther()
+        .append_header(("Location", format!("/product/{}#documents", product.id)))
+        .finish())
+}
+
+/// GET /product/{id}/documents/{document_id} - Download a product's document under its uploaded name
+pub async fn download_product_document(
+    pool: web::Data<sqlx::PgPool>,
+    s3_client: Option<web::Data<S3Client>>,
+    path: web::Path<(Uuid, Uuid)>,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    let (product_id, document_id) = path.into_inner();
+    let document = ProductDocument::get(pool.get_ref(), product_id, document_id)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to fetch document")
+        })?;
+    let not_found = || {
+        HttpResponse::NotFound()
+            .content_type("text/html")
+            .body(error_page(&tr.t("error-document-not-found"), None, &[(&format!("/product/{}", product_id), tr.t("error-back-product"))]))
+    };
+    let Some(document) = document else {
+        return Ok(not_found());
+    };
+
+    let data = storage::read_document(s3_client.as_ref().map(|client| client.get_ref()), &document.storage, &document.storage_key)
+        .await
+        .map_err(|e| {
+            eprintln!("Document download error: {}", e);
+            actix_web::error::ErrorInternalServerError("Failed to fetch document")
+        })?;
+    let Some(data) = data else {
+        eprintln!("Document {} is missing from {} storage ({})", document.id, document.storage, document.storage_key);
+        return Ok(not_found());
+    };
+
+    let content_type = utils::DocumentKind::from_extension(&document.doc_type)
+        .map_or("application/octet-stream", |kind| kind.content_type());
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(document.filename)],
+        })
+        .body(data))
+}
+
+/// POST /product/{id}/documents/{document_id}/delete - Remove a document and its file
+pub async fn delete_product_document(
+    pool: web::Data<sqlx::PgPool>,
+    s3_client: web::Data<S3Client>,
+    path: web::Path<(Uuid, Uuid)>,
+) -> Result<HttpResponse> {
+    let (product_id, document_id) = path.into_inner();
+    let deleted = ProductDocument::delete(pool.get_ref(), product_id, document_id)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error deleting document: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to delete document")
+        })?;
+    // The row is gone either way; a file that can't be removed is only logged
+    if let Some(document) = deleted {
+        if let Err(e) = storage::delete_document(s3_client.get_ref(), &document.storage, &document.storage_key).await {
+            eprintln!("Document cleanup error: {}", e);
+        }
+    }
+
+    Ok(HttpResponse::SeeOther()
+        .append_header(("Location", format!("/product/{}#documents", product_id)))
+        .finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_document_display_name() {
+        assert_eq!(document_display_name(" Spec Sheet 2024.pdf ", utils::DocumentKind::Pdf), "Spec Sheet 2024.pdf");
+        assert_eq!(document_display_name("../../etc/passwd.pdf", utils::DocumentKind::Pdf), "....etcpasswd.pdf");
+        assert_eq!(document_display_name("/", utils::DocumentKind::Docx), "document.docx");
+        assert_eq!(document_display_name(&"a".repeat(300), utils::DocumentKind::Pdf).chars().count(), 255);
+    }
+}
@@ -0,0 +1,138 @@
+use crate::errors::ApiError;
+use crate::i18n::Translator;
+use crate::utils;
+use actix_web::{HttpResponse, Result};
+use askama::Template;
+/// Template for the page a read-only mirror shows instead of a form or write
+#[derive(Template)]
+#[template(path = "read_only.html")]
+struct ReadOnlyTemplate {
+    tr: Translator,
+    is_authenticated: bool,
+    username: Option<String>,
+}
+
+/// Any method on a write route when READ_ONLY_MODE is set - 403 with the read-only page
+pub async fn read_only_mirror(req: actix_web::HttpRequest, tr: Translator) -> Result<HttpResponse> {
+    if crate::errors::is_api_path(req.path()) {
+        return Err(ApiError::forbidden("This site is a read-only mirror").into());
+    }
+
+    let template = ReadOnlyTemplate {
+        tr,
+        is_authenticated: false,
+        username: None,
+    };
+    let html = template.render().map_err(|e| {
+        eprintln!("Template error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to render template")
+    })?;
+
+    Ok(HttpResponse::Forbidden().content_type("text/html").body(html))
+}
+
+/// Unmatched requests when READ_ONLY_MODE is set: writes get the read-only page, reads a 404
+pub async fn read_only_fallback(req: actix_web::HttpRequest, tr: Translator) -> Result<HttpResponse> {
+    if req.method().is_safe() {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    read_only_mirror(req, tr).await
+}
+
+/// A bare HTML error page: a heading, an optional explanation and links away
+///
+/// The text is escaped, since explanations can carry user input such as a username.
+pub(super) fn error_page(heading: &str, message: Option<&str>, links: &[(&str, String)]) -> String {
+    let message = message
+        .map(|message| format!("<p>{}</p>", utils::escape_html(message)))
+        .unwrap_or_default();
+    let links = links
+        .iter()
+        .map(|(href, label)| format!("<a href='{}'>{}</a>", href, utils::escape_html(label)))
+        .collect::<Vec<_>>()
+        .join(" | ");
+    format!("<h1>{}</h1>{}<p>{}</p>", utils::escape_html(heading), message, links)
+}
+
+/// Response for authenticated users who are not admins
+pub(super) fn forbidden_response(tr: &Translator) -> HttpResponse {
+    HttpResponse::Forbidden()
+        .content_type("text/html")
+        .body(error_page(&tr.t("error-forbidden"), Some(&tr.t("error-admin-only")), &[("/", tr.t("error-back-home"))]))
+}
+
+/// Template for 401 Unauthorized error page, shared with the Authentication middleware
+#[derive(Template)]
+#[template(path = "401.html")]
+pub struct Error401Template {
+    pub tr: Translator,
+    pub is_authenticated: bool,
+    pub username: Option<String>,
+    /// The page the visitor was trying to open, if it is worth returning to
+    pub attempted_path: Option<String>,
+    /// `/login`, carrying `attempted_path` as `next`
+    pub login_url: String,
+}
+
+impl Error401Template {
+    pub fn new(tr: Translator, attempted_path: Option<String>) -> Self {
+        let login_url = match &attempted_path {
+            Some(path) => utils::login_url(path),
+            None => "/login".to_string(),
+        };
+        Error401Template {
+            tr,
+            is_authenticated: false,
+            username: None,
+            attempted_path,
+            login_url,
+        }
+    }
+}
+
+/// GET /401 - Show 401 Unauthorized page
+pub async fn error_401(tr: Translator) -> Result<HttpResponse> {
+    let template = Error401Template::new(tr, None);
+
+    let html = template.render().map_err(|e| {
+        eprintln!("Template error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to render template")
+    })?;
+
+    Ok(HttpResponse::Unauthorized()
+        .content_type("text/html")
+        .body(html))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_page_escapes_its_text() {
+        let html = error_page("Can't <edit>", Some("Locked by <b>sam</b>"), &[("/", "Home & back".to_string())]);
+        assert_eq!(
+            html,
+            "<h1>Can&#x27;t &lt;edit&gt;</h1><p>Locked by &lt;b&gt;sam&lt;/b&gt;</p><p><a href='/'>Home &amp; back</a></p>"
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_read_only_fallback_refuses_writes_only() {
+        use actix_web::test::{call_service, init_service, TestRequest};
+        use actix_web::{web, App};
+
+        let app = init_service(App::new().default_service(web::to(read_only_fallback))).await;
+
+        let response = call_service(&app, TestRequest::get().uri("/product/new").to_request()).await;
+        assert_eq!(response.status(), 404);
+
+        let response = call_service(&app, TestRequest::post().uri("/product/new").to_request()).await;
+        assert_eq!(response.status(), 403);
+        assert_eq!(response.headers().get("content-type").unwrap(), "text/html");
+
+        let response = call_service(&app, TestRequest::post().uri("/api/preparations/import").to_request()).await;
+        assert_eq!(response.status(), 403);
+        assert_eq!(response.headers().get("content-type").unwrap(), "application/json");
+    }
+}
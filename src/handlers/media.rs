@@ -0,0 +1,62 @@
+use crate::media;
+use crate::utils;
+use actix_files::NamedFile;
+use actix_web::http::header::{self, Accept, HeaderValue};
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Result};
+
+/// Query for a resized upload
+#[derive(Debug, serde::Deserialize)]
+pub struct VariantQuery {
+    w: String,
+}
+
+/// Whether the browser says it can show WebP (`image/webp` with a non-zero quality)
+fn accepts_webp(req: &HttpRequest) -> bool {
+    req.get_header::<Accept>().is_some_and(|accept| {
+        accept
+            .iter()
+            .any(|item| item.item.essence_str() == "image/webp" && item.quality > header::Quality::ZERO)
+    })
+}
+
+/// GET /static/uploads/{filename}?w=400 - An upload scaled down to one of `VARIANT_WIDTHS`
+///
+/// Variants are made on first request and cached in `IMAGE_VARIANT_DIR`. Browsers
+/// that accept WebP get WebP, others the upload's own format. Uploads that can't be
+/// resized, or fail to, are served as they are rather than breaking the page.
+pub async fn upload_variant(
+    req: HttpRequest,
+    filename: web::Path<String>,
+    query: web::Query<VariantQuery>,
+) -> Result<HttpResponse> {
+    let width = media::parse_width(&query.w).ok_or_else(|| {
+        let widths: Vec<String> = media::VARIANT_WIDTHS.iter().map(u32::to_string).collect();
+        actix_web::error::ErrorBadRequest(format!("Width must be one of {}", widths.join(", ")))
+    })?;
+
+    let upload_dir = utils::upload_dir();
+    let source = utils::upload_path(&upload_dir, &filename)
+        .ok()
+        .filter(|path| path.is_file() && !utils::path_has_symlink(&upload_dir, std::path::Path::new(filename.as_str())))
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Not found"))?;
+
+    let file = match media::variant(&source, &media::variant_dir(), width, accepts_webp(&req)).await {
+        Ok(Some(variant)) => {
+            if variant.generated {
+                println!("Made a {}px {} variant of {}", width, variant.kind.extension(), filename);
+            }
+            let mime = variant.kind.content_type().parse().expect("image content types are valid MIME types");
+            NamedFile::open_async(&variant.path).await?.set_content_type(mime)
+        }
+        Ok(None) => NamedFile::open_async(&source).await?,
+        Err(e) => {
+            eprintln!("Failed to make a {}px variant of {}: {}", width, filename, e);
+            NamedFile::open_async(&source).await?
+        }
+    };
+
+    let mut response = file.into_response(&req);
+    // The same URL is WebP for some browsers and not others
+    response.headers_mut().insert(header::VARY, HeaderValue::from_static("Accept"));
+    Ok(response)
+}
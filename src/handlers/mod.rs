@@ -0,0 +1,40 @@
+// Route handlers, one module per area of the site. Everything routes.rs
+// points at is re-exported here, so routes keep naming `handlers::index`
+// whichever module the handler lives in.
+
+/// Admin pages: users, kiosks, locations, suppliers, price changes, image checks
+mod admin;
+/// JSON API for products, preparations, stats and exports
+mod api;
+/// The change report auditors download as CSV or PDF
+mod audit;
+/// Login, registration, kiosk enrolment and the language switcher
+mod auth;
+/// Helpers shared across modules: template filters, uploads, form tokens, lookups
+mod common;
+/// Documents attached to products: upload, download and removal
+mod documents;
+/// Error pages and the read-only mirror's responses
+mod errors;
+/// Resized variants of uploaded images
+mod media;
+/// The supplier ordering page
+mod ordering;
+/// Preparation pages and forms, PDFs and edit locks
+mod preparations;
+/// Product pages and forms, price history and barcode scans
+mod products;
+/// The search page and search APIs
+mod search;
+
+pub use admin::*;
+pub use api::*;
+pub use audit::*;
+pub use auth::*;
+pub use documents::*;
+pub use errors::*;
+pub use media::*;
+pub use ordering::*;
+pub use preparations::*;
+pub use products::*;
+pub use search::*;
@@ -0,0 +1,138 @@
+use crate::middleware::AuthenticatedUser;
+use crate::models::{ProductSummary, SupplierSchedule};
+use crate::utils;
+use super::common::{filters, load_admin};
+use actix_web::{web, HttpResponse, Result};
+use askama::Template;
+/// How far ahead the ordering page looks for cut-offs
+const ORDERING_WINDOW_HOURS: i64 = 24;
+
+/// A scheduled supplier on the ordering page, with its next cut-off
+struct OrderingSupplier {
+    schedule: SupplierSchedule,
+    cutoff: chrono::DateTime<chrono::Utc>,
+    /// Day an order placed by the cut-off arrives, if delivery days are set
+    delivery: Option<chrono::NaiveDate>,
+    /// Filled in for suppliers whose cut-off is within the window
+    products: Vec<ProductSummary>,
+}
+
+impl OrderingSupplier {
+    /// Delivery day as "Thu 16 May", empty when unknown
+    fn delivery_label(&self) -> String {
+        self.delivery.map(|date| date.format("%a %-d %b").to_string()).unwrap_or_default()
+    }
+}
+
+/// Template for the ordering page
+#[derive(Template)]
+#[template(path = "ordering.html")]
+struct OrderingTemplate {
+    /// Cut-offs within the next `ORDERING_WINDOW_HOURS`, soonest first
+    due: Vec<OrderingSupplier>,
+    /// Every other scheduled supplier, by next cut-off
+    later: Vec<OrderingSupplier>,
+    window_hours: i64,
+    is_admin: bool,
+    is_authenticated: bool,
+    username: Option<String>,
+}
+
+/// Split schedules into those whose next cut-off falls within `window` of `now` and the rest,
+/// each soonest first; schedules without cut-off days are left out
+fn split_by_cutoff(
+    schedules: Vec<SupplierSchedule>,
+    now: chrono::DateTime<chrono::Utc>,
+    tz: chrono_tz::Tz,
+    window: chrono::Duration,
+) -> (Vec<OrderingSupplier>, Vec<OrderingSupplier>) {
+    let mut suppliers: Vec<OrderingSupplier> = schedules
+        .into_iter()
+        .filter_map(|schedule| {
+            let cutoff = schedule.next_cutoff(now, tz)?;
+            let delivery = schedule.delivery_after(cutoff, tz);
+            Some(OrderingSupplier { schedule, cutoff, delivery, products: Vec::new() })
+        })
+        .collect();
+    suppliers.sort_by(|a, b| a.cutoff.cmp(&b.cutoff).then_with(|| a.schedule.supplier_name.cmp(&b.schedule.supplier_name)));
+    suppliers.into_iter().partition(|supplier| supplier.cutoff <= now + window)
+}
+
+/// GET /ordering - Suppliers whose order cut-off is in the next 24 hours, with their products (login required)
+pub async fn ordering(pool: web::Data<sqlx::PgPool>, user: AuthenticatedUser) -> Result<HttpResponse> {
+    let schedules = SupplierSchedule::list(pool.get_ref()).await.map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to fetch supplier schedules")
+    })?;
+    let (mut due, later) = split_by_cutoff(
+        schedules,
+        chrono::Utc::now(),
+        utils::venue_timezone(),
+        chrono::Duration::hours(ORDERING_WINDOW_HOURS),
+    );
+
+    let suppliers: Vec<String> = due.iter().map(|supplier| supplier.schedule.supplier_name.clone()).collect();
+    let products = ProductSummary::for_suppliers(pool.get_ref(), &suppliers).await.map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to fetch products")
+    })?;
+    for product in products {
+        if let Some(supplier) = due.iter_mut().find(|supplier| supplier.schedule.supplier_name == product.supplier_name) {
+            supplier.products.push(product);
+        }
+    }
+
+    let is_admin = load_admin(pool.get_ref(), &user).await?.is_some();
+    let template = OrderingTemplate {
+        due,
+        later,
+        window_hours: ORDERING_WINDOW_HOURS,
+        is_admin,
+        is_authenticated: true,
+        username: Some(user.username),
+    };
+    let html = template.render().map_err(|e| {
+        eprintln!("Template error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to render template")
+    })?;
+
+    Ok(HttpResponse::Ok().content_type("text/html").body(html))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_by_cutoff_takes_the_next_24_hours() {
+        let tz: chrono_tz::Tz = "Australia/Sydney".parse().unwrap();
+        let schedule = |name: &str, cutoff_weekdays: Vec<i16>, hour| SupplierSchedule {
+            supplier_name: name.to_string(),
+            cutoff_weekdays,
+            cutoff_time: chrono::NaiveTime::from_hms_opt(hour, 0, 0).unwrap(),
+            delivery_weekdays: vec![3],
+            updated_at: chrono::Utc::now(),
+        };
+        // Monday 13 May 2024, 4pm in Sydney
+        let now = "2024-05-13T06:00:00Z".parse().unwrap();
+
+        let (due, later) = split_by_cutoff(
+            vec![
+                schedule("Bakery", vec![1], 9),
+                schedule("PFD", vec![1], 15),
+                schedule("Fishmonger", vec![0], 15),
+                schedule("No days", vec![], 15),
+            ],
+            now,
+            tz,
+            chrono::Duration::hours(24),
+        );
+        let names = |suppliers: &[OrderingSupplier]| {
+            suppliers.iter().map(|supplier| supplier.schedule.supplier_name.clone()).collect::<Vec<_>>()
+        };
+        // Tuesday 9am and 3pm are within a day; Monday's 3pm has passed, so it's next week
+        assert_eq!(names(&due), vec!["Bakery", "PFD"]);
+        assert_eq!(names(&later), vec!["Fishmonger"]);
+        assert_eq!(due[1].delivery_label(), "Thu 16 May");
+    }
+}
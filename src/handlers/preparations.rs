@@ -0,0 +1,2219 @@
+use crate::errors::ValidationError;
+use crate::events::{DomainEvent, EventDispatcher};
+use crate::i18n::Translator;
+use crate::middleware::{AuthenticatedUser, ClientInfo};
+use crate::recently_viewed::{RecentItem, ViewedKind};
+use crate::models::{EditLock, EditLockOutcome, FormEntity, NewPreparationForm, Onboarding, Preparation, PreparationFacets, PreparationFilter, PreparationListPrefs, PreparationSort, PreparationStep, PreparationSummary, PreparationTranslation, PREP_STATUS_DRAFT, PREP_STATUS_PUBLISHED, max_steps, step_limit_problems, UserPreferences};
+use crate::utils;
+use super::common::{
+    filters, already_created_redirect, created_with_form_token, delete_replaced_images, is_unique_violation,
+    load_admin, load_locations, load_onboarding, load_recently_viewed, record_form_token, record_picture_size,
+    record_recent_view, recently_viewed_cookie, recently_viewed_items, submitted_form_token,
+    upload_image_to_storage, upload_webp_variant,
+};
+use super::errors::{error_page, forbidden_response};
+use actix_multipart::Multipart;
+use actix_web::{web, HttpResponse, Result};
+use askama::Template;
+use aws_sdk_s3::Client as S3Client;
+use futures_util::StreamExt;
+use std::collections::HashMap;
+use uuid::Uuid;
+/// Step descriptions and optional image uploads keyed by the submitted step number
+type StepsData = HashMap<usize, (String, Option<(Vec<u8>, utils::ImageKind)>)>;
+
+/// Template for the archived preparations page
+#[derive(Template)]
+#[template(path = "preparations_archived.html")]
+struct ArchivedPreparationsTemplate {
+    preparations: Vec<PreparationSummary>,
+    is_authenticated: bool,
+    username: Option<String>,
+}
+
+/// Template for the preparations index page
+#[derive(Template)]
+#[template(path = "preparations_index.html")]
+struct PreparationsIndexTemplate {
+    /// Each preparation with its number of steps
+    preparations: Vec<(PreparationSummary, i64)>,
+    sort: &'static str,
+    filter: PreparationFilter,
+    facets: PreparationFacets,
+    /// The sort and filters weren't in the link but remembered from last time
+    remembered: bool,
+    /// Getting-started steps still to do on a fresh install
+    onboarding: Onboarding,
+    /// Products and preparations opened lately, for the quick-access strip
+    recently_viewed: Vec<RecentItem>,
+    is_authenticated: bool,
+    username: Option<String>,
+}
+
+impl PreparationsIndexTemplate {
+    /// Index link with the given sort and filters
+    fn index_url(sort: &str, prep_type: Option<&str>, shift: Option<&str>) -> String {
+        let mut url = format!("/preparations?sort={}", sort);
+        if let Some(prep_type) = prep_type {
+            url.push_str(&format!("&prep_type={}", prep_type));
+        }
+        if let Some(shift) = shift {
+            url.push_str(&format!("&shift={}", shift));
+        }
+        url
+    }
+
+    /// Same filters in a different order
+    fn sort_url(&self, sort: &str) -> String {
+        Self::index_url(sort, self.filter.prep_type, self.filter.shift)
+    }
+
+    /// Toggle the type filter, keeping the shift filter and sort
+    fn prep_type_url(&self, prep_type: &str) -> String {
+        let prep_type = (!self.is_prep_type_selected(prep_type)).then_some(prep_type);
+        Self::index_url(self.sort, prep_type, self.filter.shift)
+    }
+
+    /// Toggle the shift filter, keeping the type filter and sort
+    fn shift_url(&self, shift: &str) -> String {
+        let shift = (!self.is_shift_selected(shift)).then_some(shift);
+        Self::index_url(self.sort, self.filter.prep_type, shift)
+    }
+
+    /// Drop both filters, keeping the sort
+    fn clear_filters_url(&self) -> String {
+        Self::index_url(self.sort, None, None)
+    }
+
+    /// The default view, which also replaces the remembered sort and filters
+    fn reset_url(&self) -> String {
+        Self::index_url(PreparationSort::Type.as_param(), None, None)
+    }
+
+    fn is_prep_type_selected(&self, prep_type: &str) -> bool {
+        self.filter.prep_type == Some(prep_type)
+    }
+
+    fn is_shift_selected(&self, shift: &str) -> bool {
+        self.filter.shift == Some(shift)
+    }
+}
+
+/// Template for the new preparation form
+#[derive(Template)]
+#[template(path = "preparation_new.html")]
+struct PreparationNewTemplate {
+    error: String,
+    step_errors: Vec<String>,
+    /// What was submitted, so the form can be shown again without retyping it
+    values: NewPreparationForm,
+    step_descriptions: Vec<String>,
+    /// Existing preparation with the same name and type; saving again creates this one anyway
+    duplicate: Option<Preparation>,
+    /// Allowed locations; empty means the location is free text
+    locations: Vec<String>,
+    /// One-time token that stops a double submit creating the preparation twice
+    form_token: Uuid,
+    /// The preparation this one is a translation of, if any
+    translation_of: Option<Uuid>,
+    is_authenticated: bool,
+    username: Option<String>,
+}
+
+/// Template for the preparation detail page
+#[derive(Template)]
+#[template(path = "preparation_detail.html")]
+struct PreparationDetailTemplate {
+    preparation: Preparation,
+    steps: Vec<StepView>,
+    /// Other products and preparations opened lately, for the quick-access strip
+    recently_viewed: Vec<RecentItem>,
+    /// Every language version of the preparation, this one included
+    translations: Vec<PreparationTranslation>,
+    /// Code and name of each language it has no version in yet
+    untranslated: Vec<(&'static str, &'static str)>,
+    /// Fields the last save ignored
+    warnings: Vec<String>,
+    is_authenticated: bool,
+    username: Option<String>,
+}
+
+/// A preparation step as shown on the detail page
+struct StepView {
+    step: PreparationStep,
+    /// Element id, so `/preparation/{id}#step-3` links straight to the step
+    anchor: String,
+    /// Image shown in the step list
+    thumbnail_url: Option<String>,
+    /// Image the lightbox opens
+    full_url: Option<String>,
+}
+
+/// Fragment id for a step on the preparation detail page
+fn step_anchor(step_number: i32) -> String {
+    format!("step-{}", step_number)
+}
+
+/// View-models for the detail page's steps, keeping their order
+fn step_views(steps: Vec<PreparationStep>) -> Vec<StepView> {
+    steps
+        .into_iter()
+        .map(|step| StepView {
+            anchor: step_anchor(step.step_number),
+            // Uploads are stored at one size, so the list shows the full image scaled down
+            thumbnail_url: step.picture_url.clone(),
+            full_url: step.picture_url.clone(),
+            step,
+        })
+        .collect()
+}
+
+/// Template for the preparation edit page
+#[derive(Template)]
+#[template(path = "preparation_edit.html")]
+struct PreparationEditTemplate {
+    preparation: Preparation,
+    steps: Vec<PreparationStep>,
+    other_preparations: Vec<PreparationSummary>,
+    error: String,
+    step_errors: Vec<String>,
+    /// Fields the last save ignored
+    warnings: Vec<String>,
+    /// What still stops a draft from being published; empty for published preparations
+    validation_report: Vec<String>,
+    /// Set when someone else is editing; the form is then shown read-only
+    lock_notice: Option<EditLockNotice>,
+    /// Allowed locations; empty means the location is free text
+    locations: Vec<String>,
+    is_authenticated: bool,
+    username: Option<String>,
+}
+
+/// Another user's edit lock, as shown on the preparation editor
+struct EditLockNotice {
+    holder: EditLock,
+    /// Admins may take the lock over
+    can_take_over: bool,
+}
+
+impl PreparationEditTemplate {
+    /// Whether the preparation's current location is still in the allowed list
+    fn location_listed(&self) -> bool {
+        self.locations.contains(&self.preparation.location)
+    }
+}
+
+/// Query parameters for the preparations index
+#[derive(Debug, serde::Deserialize)]
+pub struct PreparationsQuery {
+    sort: Option<String>,
+    prep_type: Option<String>,
+    shift: Option<String>,
+}
+
+/// Query parameters for the add preparation form
+#[derive(Debug, serde::Deserialize)]
+pub struct NewPreparationQuery {
+    /// Start from this preparation, as a translation of it
+    translation_of: Option<Uuid>,
+    /// Language of the translation
+    lang: Option<String>,
+}
+
+/// Cookie remembering the preparations index sort and filters when nobody is signed in
+const PREP_FILTERS_COOKIE: &str = "prep_filters";
+
+/// GET /preparations - List all preparations
+///
+/// Without a sort or filter in the query, the ones last used are applied: from
+/// the user's saved preferences when signed in, otherwise from a cookie (kiosk
+/// tablets use the cookie too, so each device remembers its own).
+pub async fn preparations_index(
+    req: actix_web::HttpRequest,
+    pool: web::Data<sqlx::PgPool>,
+    auth: crate::middleware::OptionalAuth,
+    query: web::Query<PreparationsQuery>,
+) -> Result<HttpResponse> {
+    let explicit = PreparationListPrefs::from_params(query.sort.as_deref(), query.prep_type.as_deref(), query.shift.as_deref());
+    let account = auth.user.as_ref().filter(|user| !user.is_kiosk).map(|user| user.user_id);
+    let mut preferences = match account {
+        Some(user_id) => UserPreferences::get(pool.get_ref(), user_id).await.map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to load preferences")
+        })?,
+        None => UserPreferences {
+            preparations: req.cookie(PREP_FILTERS_COOKIE).map(|cookie| PreparationListPrefs::from_query(cookie.value())),
+            recently_viewed: recently_viewed_cookie(&req),
+        },
+    };
+
+    let (list, remembered) = PreparationListPrefs::resolve(explicit.clone(), preferences.preparations.clone());
+    let sort = list.sort();
+    let filter = list.filter();
+
+    // Only write when the choice changed, not on every page load
+    let mut prefs_cookie = None;
+    if !explicit.is_empty() && preferences.preparations.as_ref() != Some(&explicit) {
+        match account {
+            Some(user_id) => {
+                preferences.preparations = Some(explicit);
+                // Losing the preference only costs a click next time
+                if let Err(e) = UserPreferences::set(pool.get_ref(), user_id, &preferences).await {
+                    eprintln!("Failed to save preferences: {:?}", e);
+                }
+            }
+            None => {
+                prefs_cookie = Some(
+                    ClientInfo::of(&req)
+                        .cookie(PREP_FILTERS_COOKIE, explicit.to_query())
+                        .path("/preparations")
+                        .same_site(actix_web::cookie::SameSite::Lax)
+                        .max_age(actix_web::cookie::time::Duration::days(365))
+                        .finish(),
+                );
+            }
+        }
+    }
+
+    let preparations = Preparation::get_all_with_step_counts(pool.get_ref(), sort, &filter)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to fetch preparations")
+        })?;
+
+    let facets = Preparation::facet_counts(pool.get_ref(), &filter)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to count preparations")
+        })?;
+
+    let onboarding = load_onboarding(pool.get_ref()).await?;
+    let recently_viewed = recently_viewed_items(pool.get_ref(), &preferences.recently_viewed, None).await;
+
+    let template = PreparationsIndexTemplate {
+        preparations,
+        sort: sort.as_param(),
+        filter,
+        facets,
+        remembered,
+        onboarding,
+        recently_viewed,
+        is_authenticated: auth.user.is_some(),
+        username: auth.user.map(|u| u.username),
+    };
+
+    let html = template.render().map_err(|e| {
+        eprintln!("Template error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to render template")
+    })?;
+
+    let mut response = HttpResponse::Ok();
+    if let Some(cookie) = prefs_cookie {
+        response.cookie(cookie);
+    }
+    Ok(response.content_type("text/html").body(html))
+}
+
+/// GET /preparation/new - Show form to add new preparation
+pub async fn new_preparation_form(
+    req: actix_web::HttpRequest,
+    pool: web::Data<sqlx::PgPool>,
+    auth: crate::middleware::OptionalAuth,
+    query: web::Query<NewPreparationQuery>,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    let locations = load_locations(pool.get_ref()).await?;
+
+    // A translation starts as a copy of the original, to be rewritten in the new language
+    let (values, step_descriptions) = match query.translation_of {
+        Some(id) => {
+            let Some(original) = Preparation::get_by_id(pool.get_ref(), id).await.map_err(|e| {
+                eprintln!("Database error: {:?}", e);
+                actix_web::error::ErrorInternalServerError("Failed to fetch preparation")
+            })?
+            else {
+                return Ok(HttpResponse::NotFound().content_type("text/html").body(error_page(
+                    &tr.t("error-preparation-not-found"),
+                    None,
+                    &[("/preparations", tr.t("error-back-preparations"))],
+                )));
+            };
+            let steps = PreparationStep::get_by_preparation_id(pool.get_ref(), id)
+                .await
+                .map_err(|e| {
+                    eprintln!("Database error fetching steps: {:?}", e);
+                    actix_web::error::ErrorInternalServerError("Failed to fetch preparation steps")
+                })?;
+            let values = NewPreparationForm {
+                lang: query.lang.clone().filter(|lang| *lang != original.lang).unwrap_or_default(),
+                ..original.as_form(&steps)
+            };
+            (values, steps.into_iter().map(|step| step.description).collect())
+        }
+        // New ones default to the browser's language, which the select lets them change
+        None => (
+            NewPreparationForm {
+                lang: crate::i18n::content_lang_for_request(&req).to_string(),
+                ..NewPreparationForm::default()
+            },
+            Vec::new(),
+        ),
+    };
+
+    let template = PreparationNewTemplate {
+        error: String::new(),
+        step_errors: Vec::new(),
+        values,
+        step_descriptions,
+        duplicate: None,
+        locations,
+        form_token: Uuid::new_v4(),
+        translation_of: query.translation_of,
+        is_authenticated: auth.user.is_some(),
+        username: auth.user.map(|u| u.username),
+    };
+
+    let html = template.render().map_err(|e| {
+        eprintln!("Template error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to render template")
+    })?;
+
+    Ok(HttpResponse::Ok().content_type("text/html").body(html))
+}
+
+/// POST /preparation - Handle form submission and insert into DB
+pub async fn create_preparation(
+    req: actix_web::HttpRequest,
+    pool: web::Data<sqlx::PgPool>,
+    events: web::Data<EventDispatcher>,
+    s3_client: web::Data<S3Client>,
+    auth: crate::middleware::OptionalAuth,
+    payload: Multipart,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    let form = read_preparation_multipart(payload, &s3_client, &tr).await?;
+
+    let form_token = submitted_form_token(form.form_token.as_deref(), &tr)?;
+    if let Some(id) = created_with_form_token(pool.get_ref(), form_token, FormEntity::Preparation).await? {
+        // The picture was uploaded while reading the form, but the first submit already has it
+        let uploaded = form.picture_url.into_iter().chain(form.webp_url).collect();
+        delete_replaced_images(pool.get_ref(), &s3_client, uploaded).await;
+        return Ok(already_created_redirect(format!("/preparation/{}", id)));
+    }
+
+    // Validate form data
+    let form_data = NewPreparationForm {
+        name: form.name.clone(),
+        prep_type: form.prep_type.clone(),
+        shift: form.shift.clone(),
+        location: form.location.clone(),
+        steps: step_descriptions(&form.steps_data),
+        prep_time_minutes: form.prep_time_minutes.clone(),
+        difficulty: form.difficulty.clone(),
+        yield_quantity: form.yield_quantity.clone(),
+        yield_unit: form.yield_unit.clone(),
+        // An explicit choice wins; forms without one (older pages, scripts) get the browser's
+        lang: if form.lang.is_empty() {
+            crate::i18n::content_lang_for_request(&req).to_string()
+        } else {
+            form.lang.clone()
+        },
+    };
+
+    let mut step_errors = form.step_errors;
+    step_errors.extend(validate_steps(&tr, &form.steps_data));
+    let limit_errors = step_limit_errors(&tr, &form.steps_data);
+    let ignored_fields = form.ignored_fields;
+
+    // A translation joins the original's group, which has room for one per language
+    let translation_group = match form.translation_of {
+        Some(id) => Some(
+            Preparation::get_by_id(pool.get_ref(), id)
+                .await
+                .map_err(|e| {
+                    eprintln!("Database error: {:?}", e);
+                    actix_web::error::ErrorInternalServerError("Failed to fetch preparation")
+                })?
+                .ok_or_else(|| actix_web::error::ErrorBadRequest("The preparation being translated no longer exists"))?
+                .translation_group,
+        ),
+        None => None,
+    };
+    let translated = match translation_group {
+        Some(group) => translation_exists(pool.get_ref(), group, form_data.lang(), None).await?,
+        None => false,
+    };
+
+    let locations = load_locations(pool.get_ref()).await?;
+    let mode = SaveMode::from_field(&form.save_mode);
+    // Too many or too long steps aren't saved at all, not even as a draft
+    let error = if !limit_errors.is_empty() {
+        step_errors.extend(limit_errors);
+        Some(tr.t("form-steps-over-limit"))
+    } else if translated {
+        Some(translation_exists_message(&tr, form_data.lang()))
+    } else {
+        match mode {
+            SaveMode::Draft => form_data.validate_draft().err().map(|errors| first_message(&tr, errors)),
+            SaveMode::Publish => match form_data.validate_with_locations(&locations) {
+                Err(errors) => Some(first_message(&tr, errors)),
+                Ok(()) if !step_errors.is_empty() => Some(tr.t("form-steps-not-saved")),
+                Ok(()) => None,
+            },
+        }
+    };
+
+    if let Some(error_msg) = error {
+        let template = PreparationNewTemplate {
+            error: error_msg,
+            step_errors,
+            values: form_data,
+            step_descriptions: step_descriptions(&form.steps_data),
+            duplicate: None,
+            locations,
+            form_token: form_token.unwrap_or_else(Uuid::new_v4),
+            translation_of: form.translation_of,
+            is_authenticated: auth.user.is_some(),
+            username: auth.user.map(|u| u.username),
+        };
+        let html = template.render().map_err(|e| {
+            eprintln!("Template error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to render template")
+        })?;
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/html")
+            .body(html));
+    }
+
+    // A second "Tomato Sauce" under veg is usually a mistake, so ask first; the
+    // re-shown form carries `force` to create it anyway. Translations may keep the name.
+    if !form.force && translation_group.is_none() {
+        let duplicate = Preparation::find_by_name_and_type(pool.get_ref(), &form_data.name, &form_data.prep_type)
+            .await
+            .map_err(|e| {
+                eprintln!("Database error: {:?}", e);
+                actix_web::error::ErrorInternalServerError("Failed to check for duplicate preparations")
+            })?;
+        if let Some(duplicate) = duplicate {
+            // Files can't be sent back to the browser, so the picture is chosen again
+            let uploaded = form.picture_url.into_iter().chain(form.webp_url).collect();
+            delete_replaced_images(pool.get_ref(), &s3_client, uploaded).await;
+            let template = PreparationNewTemplate {
+                error: String::new(),
+                step_errors,
+                values: form_data,
+                step_descriptions: step_descriptions(&form.steps_data),
+                duplicate: Some(duplicate),
+                locations,
+                form_token: form_token.unwrap_or_else(Uuid::new_v4),
+                translation_of: form.translation_of,
+                is_authenticated: auth.user.is_some(),
+                username: auth.user.map(|u| u.username),
+            };
+            let html = template.render().map_err(|e| {
+                eprintln!("Template error: {:?}", e);
+                actix_web::error::ErrorInternalServerError("Failed to render template")
+            })?;
+            return Ok(HttpResponse::Ok().content_type("text/html").body(html));
+        }
+    }
+
+    // Drafts keep whatever was entered, minus values the database would reject
+    let (form_data, status) = match mode {
+        SaveMode::Draft => (form_data.for_draft(), PREP_STATUS_DRAFT),
+        SaveMode::Publish => (form_data, PREP_STATUS_PUBLISHED),
+    };
+
+    // Create preparation
+    let mut tx = pool.begin().await.map_err(|e| {
+        eprintln!("Database error starting transaction: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to create preparation")
+    })?;
+    let preparation = Preparation::create(
+        &mut *tx,
+        &form_data.name,
+        &form_data.prep_type,
+        &form_data.shift,
+        &form_data.location,
+        form.picture_url.as_deref(),
+        form_data.prep_time(),
+        form_data.difficulty(),
+        form_data.yield_quantity(),
+        form_data.yield_unit(),
+        form_data.lang(),
+        translation_group,
+        status,
+    )
+    .await
+    .map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to create preparation")
+    })?;
+    if !record_form_token(&mut tx, form_token, FormEntity::Preparation, preparation.id).await? {
+        // The same form was submitted twice at once and the other request won
+        drop(tx);
+        let uploaded = form.picture_url.into_iter().chain(form.webp_url).collect();
+        delete_replaced_images(pool.get_ref(), &s3_client, uploaded).await;
+        let id = created_with_form_token(pool.get_ref(), form_token, FormEntity::Preparation).await?;
+        return Ok(already_created_redirect(
+            id.map_or_else(|| "/preparations".to_string(), |id| format!("/preparation/{}", id)),
+        ));
+    }
+    tx.commit().await.map_err(|e| {
+        eprintln!("Database error committing transaction: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to create preparation")
+    })?;
+    set_preparation_webp_url(pool.get_ref(), preparation.id, form.webp_url.as_deref()).await?;
+    record_picture_size(pool.get_ref(), form.picture_url.as_deref(), form.picture_size).await?;
+
+    let steps = upload_step_images(&s3_client, form.steps_data).await?;
+    save_preparation_steps(pool.get_ref(), preparation.id, &steps).await?;
+    events.emit(DomainEvent::PreparationCreated { id: preparation.id }, auth.username());
+
+    if mode == SaveMode::Draft {
+        if step_errors.is_empty() {
+            // Keep working on the draft in the editor, which lists what's still missing
+            return Ok(HttpResponse::SeeOther()
+                .append_header(("Location", with_ignored_fields(format!("/preparation/{}/edit", preparation.id), &ignored_fields)))
+                .finish());
+        }
+        let report = form_data.problems_with_locations(&locations);
+        let html = render_preparation_edit(
+            pool.get_ref(),
+            &auth,
+            &tr,
+            preparation,
+            tr.t("form-draft-steps-need-attention"),
+            step_errors,
+            ignored_field_warnings(&tr, ignored_fields.iter().map(String::as_str)),
+            report,
+            None,
+            locations,
+        )
+        .await?;
+        return Ok(HttpResponse::Ok().content_type("text/html").body(html));
+    }
+
+    // Redirect to the newly created preparation's detail page
+    Ok(HttpResponse::SeeOther()
+        .append_header(("Location", with_ignored_fields(format!("/preparation/{}", preparation.id), &ignored_fields)))
+        .finish())
+}
+
+/// Whether `translation_group` already has a preparation in `lang`, other than `except`
+async fn translation_exists(
+    pool: &sqlx::PgPool,
+    translation_group: Uuid,
+    lang: &str,
+    except: Option<Uuid>,
+) -> Result<bool> {
+    let existing = Preparation::get_translation(pool, translation_group, lang)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to check translations")
+        })?;
+    Ok(existing.is_some_and(|existing| Some(existing.id) != except))
+}
+
+/// The error shown when a preparation already has a version in `lang`
+fn translation_exists_message(tr: &Translator, lang: &str) -> String {
+    tr.t_with("form-translation-exists", &[("language", crate::i18n::locale_name(lang))])
+}
+
+/// Which button submitted the preparation form
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SaveMode {
+    /// "Save Draft": only the name is required and the preparation stays a draft
+    Draft,
+    /// The main button: the form is fully validated and the preparation published
+    Publish,
+}
+
+impl SaveMode {
+    /// Parse the `save_mode` field; anything but `draft` publishes, as the form did before drafts
+    fn from_field(value: &str) -> SaveMode {
+        if value.trim() == "draft" {
+            SaveMode::Draft
+        } else {
+            SaveMode::Publish
+        }
+    }
+}
+
+/// A submitted step whose picture, if it has one, is already uploaded
+struct UploadedStep {
+    description: String,
+    picture_url: Option<String>,
+    picture_size: Option<i64>,
+}
+
+/// Upload the submitted steps' images, returning the steps in form order
+///
+/// Done before any transaction is opened, so a slow upload never holds a lock.
+async fn upload_step_images(s3_client: &web::Data<S3Client>, steps_data: StepsData) -> Result<Vec<UploadedStep>> {
+    let mut sorted_steps: Vec<_> = steps_data.into_iter().collect();
+    sorted_steps.sort_by_key(|(num, _)| *num);
+
+    let mut steps = Vec::with_capacity(sorted_steps.len());
+    for (_step_num, (description, image_data)) in sorted_steps {
+        let (picture_url, picture_size) = match image_data {
+            Some((data, kind)) => (
+                Some(upload_image_to_storage(s3_client, &data, kind).await?),
+                Some(data.len() as i64),
+            ),
+            None => (None, None),
+        };
+        steps.push(UploadedStep { description, picture_url, picture_size });
+    }
+    Ok(steps)
+}
+
+/// Create the uploaded steps, numbered in form order (accepts a pool or a transaction)
+async fn save_preparation_steps<'a, A>(db: A, preparation_id: Uuid, steps: &[UploadedStep]) -> Result<()>
+where
+    A: sqlx::Acquire<'a, Database = sqlx::Postgres>,
+{
+    let mut conn = db.acquire().await.map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to create preparation steps")
+    })?;
+
+    // Sequential numbering, inserted in one statement
+    let rows: Vec<_> = steps.iter().map(|step| (step.description.as_str(), step.picture_url.as_deref())).collect();
+    PreparationStep::create_many(&mut *conn, preparation_id, 1, &rows)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error creating steps: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to create preparation steps")
+        })?;
+    for step in steps {
+        record_picture_size(&mut *conn, step.picture_url.as_deref(), step.picture_size).await?;
+    }
+
+    PreparationStep::renumber(&mut *conn, preparation_id)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error renumbering steps: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to renumber preparation steps")
+        })?;
+
+    Ok(())
+}
+
+/// Lock a preparation's row until the transaction ends
+///
+/// Saves and step imports both take it first, so they can't interleave step
+/// numbers or both pass the step limit.
+async fn lock_preparation(conn: &mut sqlx::PgConnection, id: Uuid) -> Result<()> {
+    sqlx::query("SELECT id FROM preparations WHERE id = $1 FOR UPDATE")
+        .bind(id)
+        .execute(conn)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error locking preparation: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to lock preparation")
+        })?;
+    Ok(())
+}
+
+/// An edited preparation's new main picture, if one was uploaded
+struct PictureUpload<'a> {
+    url: Option<&'a str>,
+    webp_url: Option<&'a str>,
+    size: Option<i64>,
+}
+
+/// Save an edit in one transaction: the preparation's fields, its picture, and its steps replaced
+async fn save_preparation_edit(
+    pool: &sqlx::PgPool,
+    id: Uuid,
+    form_data: &NewPreparationForm,
+    picture: PictureUpload<'_>,
+    steps: &[UploadedStep],
+    status: &str,
+) -> Result<Preparation> {
+    let mut tx = pool.begin().await.map_err(|e| {
+        eprintln!("Database error starting transaction: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to update preparation")
+    })?;
+    lock_preparation(&mut tx, id).await?;
+
+    let preparation = Preparation::update(
+        &mut *tx,
+        id,
+        &form_data.name,
+        &form_data.prep_type,
+        &form_data.shift,
+        &form_data.location,
+        picture.url,
+        form_data.prep_time(),
+        form_data.difficulty(),
+        form_data.yield_quantity(),
+        form_data.yield_unit(),
+        form_data.lang(),
+        status,
+    )
+    .await
+    .map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to update preparation")
+    })?;
+    set_preparation_webp_url(&mut *tx, id, picture.webp_url).await?;
+    record_picture_size(&mut *tx, picture.url, picture.size).await?;
+
+    PreparationStep::delete_by_preparation_id(&mut *tx, id)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error deleting steps: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to delete old steps")
+        })?;
+    save_preparation_steps(&mut tx, id, steps).await?;
+
+    tx.commit().await.map_err(|e| {
+        eprintln!("Database error committing transaction: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to update preparation")
+    })?;
+    Ok(preparation)
+}
+
+/// Render the preparation editor with its saved steps and the given messages
+#[allow(clippy::too_many_arguments)]
+async fn render_preparation_edit(
+    pool: &sqlx::PgPool,
+    auth: &crate::middleware::OptionalAuth,
+    tr: &Translator,
+    preparation: Preparation,
+    error: String,
+    step_errors: Vec<String>,
+    warnings: Vec<String>,
+    validation_report: Vec<ValidationError>,
+    lock_notice: Option<EditLockNotice>,
+    locations: Vec<String>,
+) -> Result<String> {
+    let steps = PreparationStep::get_by_preparation_id(pool, preparation.id)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error fetching steps: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to fetch preparation steps")
+        })?;
+
+    // Other preparations that steps can be imported from
+    let other_preparations = PreparationSummary::get_all(pool)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to fetch preparations")
+        })?
+        .into_iter()
+        .filter(|p| p.id != preparation.id)
+        .collect();
+
+    let template = PreparationEditTemplate {
+        preparation,
+        steps,
+        other_preparations,
+        error,
+        step_errors,
+        warnings,
+        validation_report: tr.validation_messages(&validation_report),
+        lock_notice,
+        locations,
+        is_authenticated: auth.user.is_some(),
+        username: auth.user.as_ref().map(|u| u.username.clone()),
+    };
+
+    template.render().map_err(|e| {
+        eprintln!("Template error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to render template")
+    })
+}
+
+/// Highest step index accepted in `step_description_N` / `step_image_N` field names
+const MAX_STEP_INDEX: usize = 200;
+
+/// Which part of a step a multipart field carries, keyed by the submitted step index
+#[derive(Debug, PartialEq)]
+enum StepField {
+    Description(usize),
+    Image(usize),
+}
+
+/// Why a `step_description_N` / `step_image_N` field name was rejected
+#[derive(Debug, PartialEq)]
+enum StepFieldError {
+    InvalidNumber,
+    NumberTooHigh,
+}
+
+impl StepFieldError {
+    /// The message shown on the form for `field_name`
+    fn message(&self, tr: &Translator, field_name: &str) -> String {
+        match self {
+            StepFieldError::InvalidNumber => tr.t_with("form-step-invalid-number", &[("field", field_name)]),
+            StepFieldError::NumberTooHigh => tr.t_with(
+                "form-step-number-too-high",
+                &[("field", field_name), ("max", &MAX_STEP_INDEX.to_string())],
+            ),
+        }
+    }
+}
+
+/// Classify a multipart field name as a step field
+///
+/// Returns `Ok(None)` for fields that are not step fields and an error for step
+/// fields whose index is malformed or above `MAX_STEP_INDEX`.
+fn parse_step_field(field_name: &str) -> Result<Option<StepField>, StepFieldError> {
+    let (num_str, is_image) = if let Some(num_str) = field_name.strip_prefix("step_description_") {
+        (num_str, false)
+    } else if let Some(num_str) = field_name.strip_prefix("step_image_") {
+        (num_str, true)
+    } else {
+        return Ok(None);
+    };
+
+    let step_num = match num_str.parse::<usize>() {
+        Ok(num) if num_str.chars().all(|c| c.is_ascii_digit()) => num,
+        _ => return Err(StepFieldError::InvalidNumber),
+    };
+
+    if step_num > MAX_STEP_INDEX {
+        return Err(StepFieldError::NumberTooHigh);
+    }
+
+    Ok(Some(if is_image {
+        StepField::Image(step_num)
+    } else {
+        StepField::Description(step_num)
+    }))
+}
+
+/// Per-step validation messages, numbered by the step's position in the form
+///
+/// Like the other step messages these stop a publish but not a draft.
+fn validate_steps(tr: &Translator, steps_data: &StepsData) -> Vec<String> {
+    let mut step_nums: Vec<_> = steps_data.keys().copied().collect();
+    step_nums.sort();
+
+    let mut errors = Vec::new();
+    for (idx, step_num) in step_nums.iter().enumerate() {
+        let (description, image_data) = &steps_data[step_num];
+        if image_data.is_some() && description.trim().is_empty() {
+            errors.push(tr.t_with("form-step-image-without-description", &[("step", &(idx + 1).to_string())]));
+        }
+    }
+    errors
+}
+
+/// The first error's message, for pages that show a single error
+fn first_message(tr: &Translator, errors: Vec<ValidationError>) -> String {
+    errors.first().map(|error| tr.validation_message(error)).unwrap_or_default()
+}
+
+/// A 400 page for a preparation edit that couldn't be applied, linking back to the editor
+fn preparation_validation_response(tr: &Translator, preparation_id: Uuid, message: &str) -> HttpResponse {
+    HttpResponse::BadRequest().content_type("text/html").body(error_page(
+        &tr.t("error-validation"),
+        Some(message),
+        &[(&format!("/preparation/{}/edit", preparation_id), tr.t("error-go-back"))],
+    ))
+}
+
+/// Submitted step descriptions in form order
+fn step_descriptions(steps_data: &StepsData) -> Vec<String> {
+    let mut step_nums: Vec<_> = steps_data.keys().copied().collect();
+    step_nums.sort();
+    step_nums.iter().map(|num| steps_data[num].0.clone()).collect()
+}
+
+/// Step count and length problems, which stop even a draft being saved
+fn step_limit_errors(tr: &Translator, steps_data: &StepsData) -> Vec<String> {
+    let mut step_nums: Vec<_> = steps_data.keys().copied().collect();
+    step_nums.sort();
+    let descriptions: Vec<&str> = step_nums.iter().map(|num| steps_data[num].0.as_str()).collect();
+    tr.validation_messages(&step_limit_problems(&descriptions, max_steps()))
+}
+
+/// Fields collected from the preparation create/edit multipart form
+struct PreparationMultipart {
+    name: String,
+    prep_type: String,
+    shift: String,
+    location: String,
+    prep_time_minutes: String,
+    difficulty: String,
+    yield_quantity: String,
+    yield_unit: String,
+    lang: String,
+    /// The preparation this one translates, when created from "Add translation"
+    translation_of: Option<Uuid>,
+    /// `draft` from the "Save Draft" button; anything else publishes
+    save_mode: String,
+    /// Set when the user chose to create a preparation despite a duplicate name
+    force: bool,
+    /// The add form's one-time token, if it sent one
+    form_token: Option<String>,
+    /// URL of a newly uploaded main picture, if one was provided
+    picture_url: Option<String>,
+    /// URL of the WebP copy of that picture, if `WEBP_CONVERT` made one
+    webp_url: Option<String>,
+    /// Stored size of the new main picture, in bytes
+    picture_size: Option<i64>,
+    steps_data: StepsData,
+    /// Malformed step fields and rejected images, which stop a publish
+    step_errors: Vec<String>,
+    /// Names of fields the form doesn't have; reported after saving, but they don't stop it
+    ignored_fields: Vec<String>,
+}
+
+/// Read a multipart text field into a string
+async fn read_text_field(field: &mut actix_multipart::Field) -> Result<String> {
+    let mut bytes = Vec::new();
+    while let Some(chunk) = field.next().await {
+        let data = chunk.map_err(|e| actix_web::error::ErrorBadRequest(format!("Error reading field: {}", e)))?;
+        bytes.extend_from_slice(&data);
+    }
+    Ok(String::from_utf8_lossy(&bytes).to_string())
+}
+
+/// Read a multipart file field into memory
+async fn read_file_field(field: &mut actix_multipart::Field) -> Result<Vec<u8>> {
+    let mut file_data = Vec::new();
+    while let Some(chunk) = field.next().await {
+        let data = chunk.map_err(|e| actix_web::error::ErrorBadRequest(format!("Error reading file: {}", e)))?;
+        file_data.extend_from_slice(&data);
+    }
+    Ok(file_data)
+}
+
+/// Query string of the pages a preparation save redirects to
+#[derive(Debug, serde::Deserialize)]
+pub struct SavedPreparationQuery {
+    /// Comma-separated names of submitted fields the form doesn't have
+    ignored: Option<String>,
+}
+
+/// At most this many ignored field names are carried to the next page
+const MAX_IGNORED_FIELDS_SHOWN: usize = 10;
+
+/// `location` with the ignored field names added, so the page it leads to can warn about them
+fn with_ignored_fields(location: String, ignored_fields: &[String]) -> String {
+    if ignored_fields.is_empty() {
+        return location;
+    }
+    let names = ignored_fields.iter().take(MAX_IGNORED_FIELDS_SHOWN).map(String::as_str).collect::<Vec<_>>().join(",");
+    format!(
+        "{}?ignored={}",
+        location,
+        percent_encoding::utf8_percent_encode(&names, percent_encoding::NON_ALPHANUMERIC)
+    )
+}
+
+/// One warning per ignored field name
+fn ignored_field_warnings<'a>(tr: &Translator, names: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    names
+        .into_iter()
+        .filter(|name| !name.is_empty())
+        .take(MAX_IGNORED_FIELDS_SHOWN)
+        .map(|name| tr.t_with("form-unknown-field-ignored", &[("field", name)]))
+        .collect()
+}
+
+impl SavedPreparationQuery {
+    /// Warnings for the fields the save ignored
+    fn warnings(&self, tr: &Translator) -> Vec<String> {
+        ignored_field_warnings(tr, self.ignored.as_deref().unwrap_or("").split(','))
+    }
+}
+
+/// Shared multipart parser for the preparation create and edit forms
+async fn read_preparation_multipart(
+    mut payload: Multipart,
+    s3_client: &web::Data<S3Client>,
+    tr: &Translator,
+) -> Result<PreparationMultipart> {
+    let mut form = PreparationMultipart {
+        name: String::new(),
+        prep_type: String::new(),
+        shift: String::new(),
+        location: String::new(),
+        prep_time_minutes: String::new(),
+        difficulty: String::new(),
+        yield_quantity: String::new(),
+        yield_unit: String::new(),
+        lang: String::new(),
+        translation_of: None,
+        save_mode: String::new(),
+        force: false,
+        form_token: None,
+        picture_url: None,
+        webp_url: None,
+        picture_size: None,
+        steps_data: HashMap::new(),
+        step_errors: Vec::new(),
+        ignored_fields: Vec::new(),
+    };
+
+    while let Some(item) = payload.next().await {
+        let mut field = item.map_err(|e| {
+            eprintln!("Multipart error: {:?}", e);
+            actix_web::error::ErrorBadRequest("Invalid multipart data")
+        })?;
+
+        let content_disposition = field.content_disposition();
+        let field_name = content_disposition.get_name().unwrap_or("").to_string();
+        let filename = content_disposition.get_filename().unwrap_or("").to_string();
+        let kind = utils::ImageKind::from_filename(&filename);
+
+        match field_name.as_str() {
+            "name" => form.name = read_text_field(&mut field).await?,
+            "prep_type" => form.prep_type = read_text_field(&mut field).await?,
+            "shift" => form.shift = read_text_field(&mut field).await?,
+            "location" => form.location = read_text_field(&mut field).await?,
+            "prep_time_minutes" => form.prep_time_minutes = read_text_field(&mut field).await?,
+            "difficulty" => form.difficulty = read_text_field(&mut field).await?,
+            "yield_quantity" => form.yield_quantity = read_text_field(&mut field).await?,
+            "yield_unit" => form.yield_unit = read_text_field(&mut field).await?,
+            "lang" => form.lang = read_text_field(&mut field).await?,
+            "translation_of" => {
+                let value = read_text_field(&mut field).await?;
+                if !value.is_empty() {
+                    let id = Uuid::parse_str(value.trim())
+                        .map_err(|_| actix_web::error::ErrorBadRequest("Invalid translation_of"))?;
+                    form.translation_of = Some(id);
+                }
+            }
+            "save_mode" => form.save_mode = read_text_field(&mut field).await?,
+            "force" => form.force = read_text_field(&mut field).await? == "1",
+            "form_token" => form.form_token = Some(read_text_field(&mut field).await?),
+            "picture" => {
+                // Main preparation image (optional)
+                if let Some(kind) = kind {
+                    let file_data = read_file_field(&mut field).await?;
+                    if !file_data.is_empty() {
+                        match utils::prepare_image_for_storage(file_data, kind) {
+                            Ok((file_data, kind)) => {
+                                form.picture_url = Some(upload_image_to_storage(s3_client, &file_data, kind).await?);
+                                form.webp_url = upload_webp_variant(s3_client, &file_data, kind).await;
+                                form.picture_size = Some(file_data.len() as i64);
+                            }
+                            Err(message) => form.step_errors.push(message),
+                        }
+                    }
+                } else if !filename.is_empty() {
+                    form.step_errors.push(tr.t("form-invalid-image-type"));
+                }
+            }
+            _ => match parse_step_field(&field_name) {
+                Ok(Some(StepField::Description(step_num))) => {
+                    let description = read_text_field(&mut field).await?;
+                    form.steps_data.entry(step_num).or_insert((String::new(), None)).0 = description;
+                }
+                Ok(Some(StepField::Image(step_num))) => {
+                    if let Some(kind) = kind {
+                        let file_data = read_file_field(&mut field).await?;
+                        if !file_data.is_empty() {
+                            match utils::prepare_image_for_storage(file_data, kind) {
+                                Ok(image) => {
+                                    form.steps_data.entry(step_num).or_insert((String::new(), None)).1 = Some(image);
+                                }
+                                Err(message) => form.step_errors.push(format!("Step {}: {}", step_num, message)),
+                            }
+                        }
+                    } else if !filename.is_empty() {
+                        form.step_errors.push(tr.t_with("form-step-invalid-image-type", &[("step", &step_num.to_string())]));
+                    }
+                }
+                Ok(None) => form.ignored_fields.push(field_name),
+                Err(error) => form.step_errors.push(error.message(tr, &field_name)),
+            },
+        }
+    }
+
+    Ok(form)
+}
+
+/// Record the WebP copy of a newly uploaded main picture, if one was made
+async fn set_preparation_webp_url<'e, E: sqlx::PgExecutor<'e>>(executor: E, id: Uuid, webp_url: Option<&str>) -> Result<()> {
+    if webp_url.is_none() {
+        return Ok(());
+    }
+    Preparation::set_webp_url(executor, id, webp_url).await.map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to save preparation picture")
+    })
+}
+
+/// GET /preparation/{id} - View details of a single preparation
+pub async fn preparation_detail(
+    req: actix_web::HttpRequest,
+    pool: web::Data<sqlx::PgPool>,
+    preparation_id: web::Path<Uuid>,
+    query: web::Query<SavedPreparationQuery>,
+    auth: crate::middleware::OptionalAuth,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    let preparation = Preparation::get_by_id(pool.get_ref(), *preparation_id)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to fetch preparation")
+        })?;
+
+    match preparation {
+        Some(preparation) => {
+            // Fetch steps for this preparation
+            let steps = PreparationStep::get_by_preparation_id(pool.get_ref(), *preparation_id)
+                .await
+                .map_err(|e| {
+                    eprintln!("Database error fetching steps: {:?}", e);
+                    actix_web::error::ErrorInternalServerError("Failed to fetch preparation steps")
+                })?;
+
+            let translations = Preparation::translations(pool.get_ref(), preparation.translation_group)
+                .await
+                .map_err(|e| {
+                    eprintln!("Database error: {:?}", e);
+                    actix_web::error::ErrorInternalServerError("Failed to fetch translations")
+                })?;
+            let untranslated = crate::i18n::LOCALE_NAMES
+                .into_iter()
+                .filter(|(code, _)| !translations.iter().any(|translation| translation.lang == *code))
+                .collect();
+
+            let viewed = load_recently_viewed(&req, pool.get_ref(), &auth).await;
+            let recently_viewed = recently_viewed_items(pool.get_ref(), &viewed, Some(preparation.id)).await;
+            let viewed_cookie =
+                record_recent_view(&req, pool.get_ref(), &auth, viewed, ViewedKind::Preparation, preparation.id).await;
+
+            let template = PreparationDetailTemplate {
+                preparation,
+                steps: step_views(steps),
+                recently_viewed,
+                translations,
+                untranslated,
+                warnings: query.warnings(&tr),
+                is_authenticated: auth.user.is_some(),
+                username: auth.user.map(|u| u.username),
+            };
+
+            let html = template.render().map_err(|e| {
+                eprintln!("Template error: {:?}", e);
+                actix_web::error::ErrorInternalServerError("Failed to render template")
+            })?;
+
+            let mut response = HttpResponse::Ok();
+            if let Some(cookie) = viewed_cookie {
+                response.cookie(cookie);
+            }
+            Ok(response.content_type("text/html").body(html))
+        }
+        None => Ok(HttpResponse::NotFound()
+            .content_type("text/html")
+            .body(error_page(&tr.t("error-preparation-not-found"), None, &[("/preparations", tr.t("error-back-preparations"))]))),
+    }
+}
+
+
+/// Timeout for each image fetched into a preparation PDF
+const PDF_IMAGE_TIMEOUT_SECS: u64 = 10;
+
+/// GET /preparation/{id}/pdf - Download a preparation with its steps and photos as a PDF
+pub async fn preparation_pdf(
+    pool: web::Data<sqlx::PgPool>,
+    s3_client: Option<web::Data<S3Client>>,
+    preparation_id: web::Path<Uuid>,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    let preparation = Preparation::get_by_id(pool.get_ref(), *preparation_id)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to fetch preparation")
+        })?;
+
+    let Some(preparation) = preparation else {
+        return Ok(HttpResponse::NotFound()
+            .content_type("text/html")
+            .body(error_page(&tr.t("error-preparation-not-found"), None, &[("/preparations", tr.t("error-back-preparations"))])));
+    };
+
+    let steps = PreparationStep::get_by_preparation_id(pool.get_ref(), *preparation_id)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error fetching steps: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to fetch preparation steps")
+        })?;
+
+    let http_client = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(PDF_IMAGE_TIMEOUT_SECS))
+        .build()
+        .map_err(|e| {
+            eprintln!("Failed to create HTTP client for PDF images: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to prepare PDF")
+        })?;
+    let bucket_name = std::env::var("S3_BUCKET_NAME")
+        .unwrap_or_else(|_| "kitchen-hand-guide".to_string());
+
+    // Missing photos are noted in the PDF rather than failing the download
+    let fetch = |url: Option<String>| {
+        let (http_client, s3_client, bucket_name) = (&http_client, &s3_client, &bucket_name);
+        async move {
+            let url = url?;
+            Some(utils::fetch_image(http_client, s3_client.as_ref().map(|client| client.get_ref()), bucket_name, &url).await.map_err(|e| {
+                eprintln!("Failed to fetch {} for PDF: {}", url, e);
+            }))
+        }
+    };
+    let picture = fetch(preparation.picture_url.clone()).await;
+    let mut step_pictures = Vec::with_capacity(steps.len());
+    for step in &steps {
+        step_pictures.push(fetch(step.picture_url.clone()).await);
+    }
+
+    let pdf = preparation_pdf_document(&preparation, &steps, picture, step_pictures);
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/pdf")
+        .append_header((
+            "Content-Disposition",
+            format!("attachment; filename=\"{}.pdf\"", pdf_file_stem(&preparation.name)),
+        ))
+        .body(pdf))
+}
+
+/// A photo for the PDF: `None` when there isn't one, `Some(Err)` when it couldn't be fetched
+type PdfPicture = Option<std::result::Result<Vec<u8>, ()>>;
+
+/// Lay out a preparation sheet: details, main photo, then each step with its photo
+fn preparation_pdf_document(
+    preparation: &Preparation,
+    steps: &[PreparationStep],
+    picture: PdfPicture,
+    step_pictures: Vec<PdfPicture>,
+) -> Vec<u8> {
+    use crate::pdf::{Font, PdfWriter};
+
+    let mut pdf = PdfWriter::new(&preparation.name);
+    pdf.text(&preparation.name, Font::Bold, 20.0);
+    if preparation.is_draft() {
+        pdf.text("DRAFT - not yet published", Font::Bold, 10.0);
+    }
+    pdf.space(4.0);
+
+    let mut details = vec![
+        format!("Type: {}", preparation.prep_type),
+        format!("Shift: {}", preparation.shift),
+        format!("Location: {}", preparation.location),
+    ];
+    if let Some(minutes) = preparation.prep_time_minutes {
+        details.push(format!("Prep time: {} min", minutes));
+    }
+    if let Some(difficulty) = &preparation.difficulty {
+        details.push(format!("Difficulty: {}", difficulty));
+    }
+    pdf.text(&details.join(" · "), Font::Regular, 11.0);
+    pdf.space(8.0);
+
+    let add_picture = |pdf: &mut PdfWriter, picture: PdfPicture, max_height: f32| match picture {
+        None => {}
+        Some(Ok(data)) => {
+            if pdf.jpeg(data, max_height) {
+                pdf.space(8.0);
+            } else {
+                pdf.text("(Photo not included: only JPEG photos can be embedded)", Font::Regular, 9.0);
+            }
+        }
+        Some(Err(())) => pdf.text("(Photo unavailable)", Font::Regular, 9.0),
+    };
+    add_picture(&mut pdf, picture, 280.0);
+
+    pdf.text("Steps", Font::Bold, 14.0);
+    pdf.space(4.0);
+    for (step, picture) in steps.iter().zip(step_pictures) {
+        pdf.text(&format!("Step {}", step.step_number), Font::Bold, 12.0);
+        pdf.text(&step.description, Font::Regular, 11.0);
+        pdf.space(4.0);
+        add_picture(&mut pdf, picture, 220.0);
+        pdf.space(6.0);
+    }
+
+    pdf.finish()
+}
+
+/// Download filename (without extension) for a preparation, e.g. "zucchini-ribbons"
+fn pdf_file_stem(name: &str) -> String {
+    let slug = name
+        .to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    if slug.is_empty() {
+        "preparation".to_string()
+    } else {
+        slug
+    }
+}
+
+/// GET /preparation/{id}/edit - Show edit form for a preparation
+pub async fn edit_preparation_form(
+    pool: web::Data<sqlx::PgPool>,
+    preparation_id: web::Path<Uuid>,
+    query: web::Query<SavedPreparationQuery>,
+    auth: crate::middleware::OptionalAuth,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    let preparation = Preparation::get_by_id(pool.get_ref(), *preparation_id)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to fetch preparation")
+        })?;
+
+    match preparation {
+        Some(preparation) => {
+            let locations = load_locations(pool.get_ref()).await?;
+            let validation_report = if preparation.is_draft() {
+                let steps = PreparationStep::get_by_preparation_id(pool.get_ref(), preparation.id)
+                    .await
+                    .map_err(|e| {
+                        eprintln!("Database error fetching steps: {:?}", e);
+                        actix_web::error::ErrorInternalServerError("Failed to fetch preparation steps")
+                    })?;
+                preparation.as_form(&steps).problems_with_locations(&locations)
+            } else {
+                Vec::new()
+            };
+
+            // Kiosk tablets can only look, so they never hold the lock
+            let lock_notice = match auth.user.as_ref() {
+                Some(user) if !user.is_kiosk => take_edit_lock(pool.get_ref(), preparation.id, user).await?,
+                _ => None,
+            };
+
+            let html = render_preparation_edit(
+                pool.get_ref(),
+                &auth,
+                &tr,
+                preparation,
+                String::new(),
+                Vec::new(),
+                query.warnings(&tr),
+                validation_report,
+                lock_notice,
+                locations,
+            )
+            .await?;
+
+            Ok(HttpResponse::Ok().content_type("text/html").body(html))
+        }
+        None => Ok(HttpResponse::NotFound()
+            .content_type("text/html")
+            .body(error_page(&tr.t("error-preparation-not-found"), None, &[("/preparations", tr.t("error-back-preparations"))]))),
+    }
+}
+
+/// Take the edit lock on a preparation for `user`, or describe who holds it
+async fn take_edit_lock(
+    pool: &sqlx::PgPool,
+    preparation_id: Uuid,
+    user: &AuthenticatedUser,
+) -> Result<Option<EditLockNotice>> {
+    let outcome = EditLock::acquire(pool, preparation_id, user.user_id, false)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to lock preparation")
+        })?;
+
+    match outcome {
+        EditLockOutcome::Acquired(_) => Ok(None),
+        EditLockOutcome::HeldBy(holder) => Ok(Some(EditLockNotice {
+            holder,
+            can_take_over: load_admin(pool, user).await?.is_some(),
+        })),
+    }
+}
+
+/// A 409 page when another user holds the preparation's edit lock
+async fn locked_by_other_response(
+    pool: &sqlx::PgPool,
+    preparation_id: Uuid,
+    auth: &crate::middleware::OptionalAuth,
+    tr: &Translator,
+) -> Result<Option<HttpResponse>> {
+    let holder = EditLock::holder(pool, preparation_id)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to check edit lock")
+        })?;
+
+    Ok(match (holder, auth.user.as_ref()) {
+        (Some(holder), Some(user)) if holder.user_id != user.user_id => Some(
+            HttpResponse::Conflict()
+                .content_type("text/html")
+                .body(error_page(
+                    &tr.t("error-preparation-locked"),
+                    Some(&tr.t_with("error-preparation-locked-by", &[("username", &holder.username)])),
+                    &[(&format!("/preparation/{}/edit", preparation_id), tr.t("error-go-back"))],
+                )),
+        ),
+        _ => None,
+    })
+}
+
+/// Whether a preparation exists, for the edit lock endpoints
+async fn preparation_exists(pool: &sqlx::PgPool, preparation_id: Uuid) -> Result<bool> {
+    Preparation::get_by_id(pool, preparation_id)
+        .await
+        .map(|preparation| preparation.is_some())
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to fetch preparation")
+        })
+}
+
+/// POST /preparation/{id}/edit/heartbeat - Keep the caller's edit lock from expiring
+///
+/// Sent by the open editor every few minutes. Answers 409 with the new editor's
+/// name when the lock has been taken over.
+pub async fn preparation_edit_heartbeat(
+    pool: web::Data<sqlx::PgPool>,
+    preparation_id: web::Path<Uuid>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse> {
+    if !preparation_exists(pool.get_ref(), *preparation_id).await? {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({ "held": false })));
+    }
+
+    let outcome = EditLock::acquire(pool.get_ref(), *preparation_id, user.user_id, false)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to lock preparation")
+        })?;
+
+    Ok(match outcome {
+        EditLockOutcome::Acquired(lock) => HttpResponse::Ok().json(serde_json::json!({
+            "held": true,
+            "expires_at": lock.expires_at,
+        })),
+        EditLockOutcome::HeldBy(holder) => HttpResponse::Conflict().json(serde_json::json!({
+            "held": false,
+            "editor": holder.username,
+            "expires_at": holder.expires_at,
+        })),
+    })
+}
+
+/// POST /preparation/{id}/edit/take-over - Take the edit lock from whoever holds it (admin only)
+pub async fn take_over_preparation_edit(
+    pool: web::Data<sqlx::PgPool>,
+    preparation_id: web::Path<Uuid>,
+    user: AuthenticatedUser,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    if load_admin(pool.get_ref(), &user).await?.is_none() {
+        return Ok(forbidden_response(&tr));
+    }
+    if !preparation_exists(pool.get_ref(), *preparation_id).await? {
+        return Ok(HttpResponse::NotFound()
+            .content_type("text/html")
+            .body(error_page(&tr.t("error-preparation-not-found"), None, &[("/preparations", tr.t("error-back-preparations"))])));
+    }
+
+    EditLock::acquire(pool.get_ref(), *preparation_id, user.user_id, true)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to lock preparation")
+        })?;
+
+    Ok(HttpResponse::SeeOther()
+        .append_header(("Location", format!("/preparation/{}/edit", preparation_id)))
+        .finish())
+}
+
+/// POST /preparation/{id} - Update an existing preparation
+pub async fn update_preparation(
+    pool: web::Data<sqlx::PgPool>,
+    events: web::Data<EventDispatcher>,
+    s3_client: web::Data<S3Client>,
+    preparation_id: web::Path<Uuid>,
+    auth: crate::middleware::OptionalAuth,
+    payload: Multipart,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    // Fetch existing preparation
+    let existing_prep = Preparation::get_by_id(pool.get_ref(), *preparation_id)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to fetch preparation")
+        })?;
+
+    let existing_prep = match existing_prep {
+        Some(p) => p,
+        None => {
+            return Ok(HttpResponse::NotFound()
+                .content_type("text/html")
+                .body(error_page(&tr.t("error-preparation-not-found"), None, &[("/preparations", tr.t("error-back-preparations"))])));
+        }
+    };
+
+    // Someone else has the editor open; saving now could wipe their changes
+    if let Some(response) = locked_by_other_response(pool.get_ref(), *preparation_id, &auth, &tr).await? {
+        return Ok(response);
+    }
+
+    let form = read_preparation_multipart(payload, &s3_client, &tr).await?;
+    let webp_url = form.webp_url;
+    let picture_size = form.picture_size;
+    let picture_url = form.picture_url.or_else(|| existing_prep.picture_url.clone());
+
+    // Pictures this save may stop using: the main one if replaced, and every step's (steps are recreated)
+    let existing_steps = PreparationStep::get_by_preparation_id(pool.get_ref(), *preparation_id)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error fetching steps: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to fetch preparation steps")
+        })?;
+    let mut replaced_images: Vec<String> = existing_steps.into_iter().filter_map(|step| step.picture_url).collect();
+    if existing_prep.picture_url != picture_url {
+        replaced_images.extend(existing_prep.picture_url.clone());
+        replaced_images.extend(existing_prep.webp_url.clone());
+    }
+
+    // Validate
+    let form_data = NewPreparationForm {
+        name: form.name.clone(),
+        prep_type: form.prep_type.clone(),
+        shift: form.shift.clone(),
+        location: form.location.clone(),
+        steps: step_descriptions(&form.steps_data),
+        prep_time_minutes: form.prep_time_minutes.clone(),
+        difficulty: form.difficulty.clone(),
+        yield_quantity: form.yield_quantity.clone(),
+        yield_unit: form.yield_unit.clone(),
+        // Forms from before languages don't send one, which keeps the current language
+        lang: if form.lang.is_empty() { existing_prep.lang.clone() } else { form.lang.clone() },
+    };
+
+    let locations = load_locations(pool.get_ref()).await?;
+    let mode = SaveMode::from_field(&form.save_mode);
+
+    // Its translations already cover their own languages
+    if form_data.lang() != existing_prep.lang
+        && translation_exists(pool.get_ref(), existing_prep.translation_group, form_data.lang(), Some(existing_prep.id)).await?
+    {
+        return Ok(preparation_validation_response(&tr, *preparation_id, &translation_exists_message(&tr, form_data.lang())));
+    }
+
+    let mut step_errors = form.step_errors;
+    step_errors.extend(validate_steps(&tr, &form.steps_data));
+    let ignored_fields = form.ignored_fields;
+    let warnings = ignored_field_warnings(&tr, ignored_fields.iter().map(String::as_str));
+
+    // Too many or too long steps aren't saved at all, not even as a draft
+    let limit_errors = step_limit_errors(&tr, &form.steps_data);
+    if !limit_errors.is_empty() {
+        step_errors.extend(limit_errors);
+        let html = render_preparation_edit(
+            pool.get_ref(),
+            &auth,
+            &tr,
+            existing_prep,
+            tr.t("form-steps-over-limit"),
+            step_errors,
+            warnings.clone(),
+            Vec::new(),
+            None,
+            locations,
+        )
+        .await?;
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/html")
+            .body(html));
+    }
+
+    // A draft that can't be published yet stays a draft, keeping what was entered
+    let problems = form_data.problems_with_locations(&locations);
+    let keep_draft = mode == SaveMode::Draft
+        || (existing_prep.is_draft() && (!problems.is_empty() || !step_errors.is_empty()));
+
+    if keep_draft {
+        if let Err(errors) = form_data.validate_draft() {
+            return Ok(preparation_validation_response(&tr, *preparation_id, &first_message(&tr, errors)));
+        }
+
+        let steps = upload_step_images(&s3_client, form.steps_data).await?;
+        let preparation = save_preparation_edit(
+            pool.get_ref(),
+            *preparation_id,
+            &form_data.for_draft(),
+            PictureUpload { url: picture_url.as_deref(), webp_url: webp_url.as_deref(), size: picture_size },
+            &steps,
+            PREP_STATUS_DRAFT,
+        )
+        .await?;
+        events.emit(DomainEvent::PreparationUpdated { id: *preparation_id }, auth.username());
+        delete_replaced_images(pool.get_ref(), &s3_client, replaced_images).await;
+
+        if mode == SaveMode::Draft && step_errors.is_empty() {
+            return Ok(HttpResponse::SeeOther()
+                .append_header(("Location", with_ignored_fields(format!("/preparation/{}/edit", preparation_id), &ignored_fields)))
+                .finish());
+        }
+
+        let error = match mode {
+            SaveMode::Draft => tr.t("form-draft-steps-need-attention"),
+            SaveMode::Publish => tr.t("form-saved-as-draft"),
+        };
+        let html = render_preparation_edit(
+            pool.get_ref(),
+            &auth,
+            &tr,
+            preparation,
+            error,
+            step_errors,
+            warnings.clone(),
+            problems,
+            None,
+            locations,
+        )
+        .await?;
+        let mut response = match mode {
+            SaveMode::Draft => HttpResponse::Ok(),
+            SaveMode::Publish => HttpResponse::BadRequest(),
+        };
+        return Ok(response.content_type("text/html").body(html));
+    }
+
+    if !problems.is_empty() {
+        return Ok(preparation_validation_response(&tr, *preparation_id, &first_message(&tr, problems)));
+    }
+
+    if !step_errors.is_empty() {
+        // Re-render the editor with the saved steps and the per-step messages
+        let html = render_preparation_edit(
+            pool.get_ref(),
+            &auth,
+            &tr,
+            existing_prep,
+            tr.t("form-steps-not-saved"),
+            step_errors,
+            warnings.clone(),
+            Vec::new(),
+            None,
+            locations,
+        )
+        .await?;
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/html")
+            .body(html));
+    }
+
+    // Update preparation and replace its steps, publishing it if it was a draft
+    let steps = upload_step_images(&s3_client, form.steps_data).await?;
+    save_preparation_edit(
+        pool.get_ref(),
+        *preparation_id,
+        &form_data,
+        PictureUpload { url: picture_url.as_deref(), webp_url: webp_url.as_deref(), size: picture_size },
+        &steps,
+        PREP_STATUS_PUBLISHED,
+    )
+    .await?;
+    events.emit(DomainEvent::PreparationUpdated { id: *preparation_id }, auth.username());
+    delete_replaced_images(pool.get_ref(), &s3_client, replaced_images).await;
+
+    // Done editing, so let others in
+    if let Some(user) = auth.user.as_ref() {
+        EditLock::release(pool.get_ref(), *preparation_id, user.user_id)
+            .await
+            .map_err(|e| {
+                eprintln!("Database error: {:?}", e);
+                actix_web::error::ErrorInternalServerError("Failed to release edit lock")
+            })?;
+    }
+
+    // Redirect to preparation detail page
+    Ok(HttpResponse::SeeOther()
+        .append_header(("Location", with_ignored_fields(format!("/preparation/{}", preparation_id), &ignored_fields)))
+        .finish())
+}
+
+/// Form data for importing steps from another preparation
+#[derive(Debug, serde::Deserialize)]
+pub struct ImportStepsForm {
+    source_id: Uuid,
+}
+
+/// POST /preparation/{id}/import-steps - Append copies of another preparation's steps
+pub async fn import_preparation_steps(
+    pool: web::Data<sqlx::PgPool>,
+    events: web::Data<EventDispatcher>,
+    preparation_id: web::Path<Uuid>,
+    auth: crate::middleware::OptionalAuth,
+    form: web::Form<ImportStepsForm>,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    let target_id = *preparation_id;
+    let source_id = form.source_id;
+
+    if source_id == target_id {
+        return Ok(preparation_validation_response(&tr, target_id, &tr.t("error-same-preparation")));
+    }
+
+    let target = Preparation::get_by_id(pool.get_ref(), target_id)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to fetch preparation")
+        })?;
+
+    if target.is_none() {
+        return Ok(HttpResponse::NotFound()
+            .content_type("text/html")
+            .body(error_page(&tr.t("error-preparation-not-found"), None, &[("/preparations", tr.t("error-back-preparations"))])));
+    }
+
+    if let Some(response) = locked_by_other_response(pool.get_ref(), target_id, &auth, &tr).await? {
+        return Ok(response);
+    }
+
+    let source = Preparation::get_by_id(pool.get_ref(), source_id)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to fetch preparation")
+        })?;
+
+    if source.is_none() {
+        return Ok(preparation_validation_response(&tr, target_id, &tr.t("error-source-preparation-not-found")));
+    }
+
+    let mut tx = pool.begin().await.map_err(|e| {
+        eprintln!("Database error starting transaction: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to import steps")
+    })?;
+
+    // Held until commit, so the step limit checked below still holds when the steps are added
+    lock_preparation(&mut tx, target_id).await?;
+
+    let existing_steps = PreparationStep::get_by_preparation_id(&mut *tx, target_id)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error fetching steps: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to fetch preparation steps")
+        })?;
+
+    let source_steps = PreparationStep::get_by_preparation_id(&mut *tx, source_id)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error fetching steps: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to fetch preparation steps")
+        })?;
+
+    let combined: Vec<&str> = existing_steps
+        .iter()
+        .chain(&source_steps)
+        .map(|step| step.description.as_str())
+        .collect();
+    let limit_problems = step_limit_problems(&combined, max_steps());
+    if !limit_problems.is_empty() {
+        let message = format!("{} {}", tr.t("error-import-too-many-steps"), first_message(&tr, limit_problems));
+        return Ok(preparation_validation_response(&tr, target_id, &message));
+    }
+
+    // Imported steps are numbered after the target's existing steps
+    let last_step_number = existing_steps.iter().map(|s| s.step_number).max().unwrap_or(0);
+    let imported: Vec<_> = source_steps
+        .iter()
+        .map(|step| (step.description.as_str(), step.picture_url.as_deref()))
+        .collect();
+
+    match PreparationStep::create_many(&mut *tx, target_id, last_step_number + 1, &imported).await {
+        Ok(_) => {}
+        Err(e) if is_unique_violation(&e) => {
+            return Ok(preparation_validation_response(&tr, target_id, &tr.t("error-import-conflict")));
+        }
+        Err(e) => {
+            eprintln!("Database error creating steps: {:?}", e);
+            return Err(actix_web::error::ErrorInternalServerError("Failed to create preparation steps"));
+        }
+    }
+
+    match PreparationStep::renumber(&mut *tx, target_id).await {
+        Ok(_) => {}
+        Err(e) if is_unique_violation(&e) => {
+            return Ok(preparation_validation_response(&tr, target_id, &tr.t("error-import-conflict")));
+        }
+        Err(e) => {
+            eprintln!("Database error renumbering steps: {:?}", e);
+            return Err(actix_web::error::ErrorInternalServerError("Failed to renumber preparation steps"));
+        }
+    }
+
+    tx.commit().await.map_err(|e| {
+        eprintln!("Database error committing transaction: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to import steps")
+    })?;
+    events.emit(DomainEvent::PreparationUpdated { id: target_id }, auth.username());
+
+    // Back to the editor so the imported steps can be reviewed
+    Ok(HttpResponse::SeeOther()
+        .append_header(("Location", format!("/preparation/{}/edit", target_id)))
+        .finish())
+}
+
+/// GET /preparations/archived - Browse archived preparations so they can be restored
+pub async fn archived_preparations(
+    pool: web::Data<sqlx::PgPool>,
+    auth: crate::middleware::OptionalAuth,
+) -> Result<HttpResponse> {
+    let preparations = PreparationSummary::get_archived(pool.get_ref())
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to fetch archived preparations")
+        })?;
+
+    let template = ArchivedPreparationsTemplate {
+        preparations,
+        is_authenticated: auth.user.is_some(),
+        username: auth.user.map(|u| u.username),
+    };
+
+    let html = template.render().map_err(|e| {
+        eprintln!("Template error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to render template")
+    })?;
+
+    Ok(HttpResponse::Ok().content_type("text/html").body(html))
+}
+
+/// POST /preparation/{id}/archive - Take a preparation out of the lists and search
+pub async fn archive_preparation(
+    pool: web::Data<sqlx::PgPool>,
+    events: web::Data<EventDispatcher>,
+    preparation_id: web::Path<Uuid>,
+    auth: crate::middleware::OptionalAuth,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    set_preparation_archived(pool.get_ref(), &events, *preparation_id, true, &auth, &tr).await
+}
+
+/// POST /preparation/{id}/unarchive - Bring an archived preparation back
+pub async fn unarchive_preparation(
+    pool: web::Data<sqlx::PgPool>,
+    events: web::Data<EventDispatcher>,
+    preparation_id: web::Path<Uuid>,
+    auth: crate::middleware::OptionalAuth,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    set_preparation_archived(pool.get_ref(), &events, *preparation_id, false, &auth, &tr).await
+}
+
+/// Archive or restore a preparation, then show it again
+async fn set_preparation_archived(
+    pool: &sqlx::PgPool,
+    events: &EventDispatcher,
+    preparation_id: Uuid,
+    archived: bool,
+    auth: &crate::middleware::OptionalAuth,
+    tr: &Translator,
+) -> Result<HttpResponse> {
+    let found = Preparation::set_archived(pool, preparation_id, archived)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to archive preparation")
+        })?;
+
+    if !found {
+        return Ok(HttpResponse::NotFound()
+            .content_type("text/html")
+            .body(error_page(&tr.t("error-preparation-not-found"), None, &[("/preparations", tr.t("error-back-preparations"))])));
+    }
+
+    let event = if archived {
+        DomainEvent::PreparationArchived { id: preparation_id }
+    } else {
+        DomainEvent::PreparationRestored { id: preparation_id }
+    };
+    events.emit(event, auth.username());
+
+    Ok(HttpResponse::SeeOther()
+        .append_header(("Location", format!("/preparation/{}", preparation_id)))
+        .finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_step_views_add_anchors_and_image_urls() {
+        let step = |step_number: i32, picture_url: Option<&str>| PreparationStep {
+            id: Uuid::new_v4(),
+            preparation_id: Uuid::nil(),
+            step_number,
+            description: format!("Step {}", step_number),
+            picture_url: picture_url.map(str::to_string),
+            created_at: chrono::Utc::now(),
+        };
+
+        let views = step_views(vec![step(1, Some("/static/uploads/wash.jpg")), step(2, None)]);
+        let summary: Vec<_> = views
+            .iter()
+            .map(|v| (v.anchor.as_str(), v.thumbnail_url.as_deref(), v.full_url.as_deref()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("step-1", Some("/static/uploads/wash.jpg"), Some("/static/uploads/wash.jpg")),
+                ("step-2", None, None),
+            ]
+        );
+        assert_eq!(views[1].step.description, "Step 2");
+    }
+
+    #[test]
+    fn test_parse_step_field_valid() {
+        assert_eq!(parse_step_field("step_description_0"), Ok(Some(StepField::Description(0))));
+        assert_eq!(parse_step_field("step_image_12"), Ok(Some(StepField::Image(12))));
+        assert_eq!(parse_step_field("step_description_200"), Ok(Some(StepField::Description(200))));
+    }
+
+    #[test]
+    fn test_parse_step_field_ignores_other_fields() {
+        assert_eq!(parse_step_field("name"), Ok(None));
+        assert_eq!(parse_step_field("steps"), Ok(None));
+        assert_eq!(parse_step_field("step_notes_1"), Ok(None));
+    }
+
+    #[test]
+    fn test_parse_step_field_rejects_non_numeric() {
+        assert!(parse_step_field("step_description_abc").is_err());
+        assert!(parse_step_field("step_image_1a").is_err());
+    }
+
+    #[test]
+    fn test_parse_step_field_rejects_empty_index() {
+        assert!(parse_step_field("step_description_").is_err());
+        assert!(parse_step_field("step_image_").is_err());
+    }
+
+    #[test]
+    fn test_parse_step_field_rejects_signed_index() {
+        assert!(parse_step_field("step_description_-1").is_err());
+        assert!(parse_step_field("step_description_+1").is_err());
+    }
+
+    #[test]
+    fn test_parse_step_field_rejects_out_of_range() {
+        assert!(parse_step_field("step_description_201").is_err());
+        assert!(parse_step_field("step_image_99999999").is_err());
+        assert!(parse_step_field("step_description_99999999999999999999999").is_err());
+    }
+
+    fn s3_client() -> web::Data<S3Client> {
+        let config = aws_sdk_s3::Config::builder()
+            .behavior_version(aws_sdk_s3::config::BehaviorVersion::latest())
+            .region(aws_sdk_s3::config::Region::new("ap-southeast-2"))
+            .build();
+        web::Data::new(S3Client::from_conf(config))
+    }
+
+    /// Run the shared parser over a form with these (name, filename, value) parts
+    async fn parse_form(parts: &[(&str, Option<&str>, &str)]) -> PreparationMultipart {
+        let boundary = "preparation-form-boundary";
+        let mut body = String::new();
+        for (name, filename, value) in parts {
+            let filename = filename.map(|f| format!("; filename=\"{}\"", f)).unwrap_or_default();
+            body.push_str(&format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"{}\"{}\r\n\r\n{}\r\n",
+                boundary, name, filename, value
+            ));
+        }
+        body.push_str(&format!("--{}--\r\n", boundary));
+
+        let mut headers = actix_web::http::header::HeaderMap::new();
+        headers.insert(
+            actix_web::http::header::CONTENT_TYPE,
+            format!("multipart/form-data; boundary={}", boundary).parse().unwrap(),
+        );
+        let stream = futures_util::stream::iter(vec![Ok::<_, actix_web::error::PayloadError>(bytes::Bytes::from(body))]);
+        read_preparation_multipart(Multipart::new(&headers, stream), &s3_client(), &Translator::new("en"))
+            .await
+            .expect("Failed to parse form")
+    }
+
+    #[actix_web::test]
+    async fn test_multipart_reports_unknown_fields() {
+        let form = parse_form(&[
+            ("name", None, "Salsa"),
+            ("step_desc_1", None, "Chop"),
+            ("stepdescription_1", None, "Chop"),
+            ("step_description_1", None, "Chop tomatoes"),
+        ])
+        .await;
+
+        // Unknown names are only warned about; they don't stop the save
+        assert!(form.step_errors.is_empty());
+        assert_eq!(form.ignored_fields, vec!["step_desc_1".to_string(), "stepdescription_1".to_string()]);
+        assert_eq!(form.steps_data[&1].0, "Chop tomatoes");
+
+        let location = with_ignored_fields("/preparation/1".to_string(), &form.ignored_fields);
+        assert_eq!(location, "/preparation/1?ignored=step%5Fdesc%5F1%2Cstepdescription%5F1");
+        let query = web::Query::<SavedPreparationQuery>::from_query(location.split_once('?').unwrap().1).unwrap();
+        assert_eq!(
+            query.warnings(&Translator::new("en")),
+            vec![
+                "Unknown field 'step_desc_1' was ignored".to_string(),
+                "Unknown field 'stepdescription_1' was ignored".to_string(),
+            ]
+        );
+        assert_eq!(with_ignored_fields("/preparation/1".to_string(), &[]), "/preparation/1");
+    }
+
+    #[actix_web::test]
+    async fn test_multipart_rejects_malformed_step_fields() {
+        let form = parse_form(&[("step_description_x", None, "Chop"), ("step_image_999", None, "")]).await;
+
+        assert_eq!(
+            form.step_errors,
+            vec![
+                "Field 'step_description_x' has an invalid step number".to_string(),
+                "Field 'step_image_999' exceeds the maximum step number of 200".to_string(),
+            ]
+        );
+        assert!(form.ignored_fields.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_multipart_reports_unsupported_picture_types() {
+        let form = parse_form(&[
+            // An empty file input is sent with a blank filename and is just skipped
+            ("picture", Some(""), ""),
+            ("picture", Some("menu.bmp"), "BM"),
+            ("step_description_2", None, "Dice onions"),
+            ("step_image_2", Some("onion.gif"), "GIF89a"),
+        ])
+        .await;
+
+        assert_eq!(
+            form.step_errors,
+            vec![
+                "Invalid file type. Only JPG, PNG, WEBP, AVIF, and HEIC are allowed.".to_string(),
+                "Step 2: Invalid file type. Only JPG, PNG, WEBP, AVIF, and HEIC are allowed.".to_string(),
+            ]
+        );
+        assert!(form.picture_url.is_none());
+        assert!(form.steps_data[&2].1.is_none());
+    }
+
+    #[test]
+    fn test_validate_steps_image_without_description() {
+        let mut steps: StepsData = HashMap::new();
+        steps.insert(3, ("Chop onions".to_string(), None));
+        steps.insert(7, ("   ".to_string(), Some((vec![1, 2, 3], utils::ImageKind::Jpeg))));
+        steps.insert(9, ("Serve".to_string(), Some((vec![1], utils::ImageKind::Png))));
+
+        assert_eq!(
+            validate_steps(&Translator::new("en"), &steps),
+            vec!["Step 2 has an image but no description".to_string()]
+        );
+        assert_eq!(
+            validate_steps(&Translator::new("es"), &steps),
+            vec!["El paso 2 tiene una imagen pero no tiene descripción".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_validate_steps_accepts_text_only_steps() {
+        let mut steps: StepsData = HashMap::new();
+        steps.insert(0, ("Wash".to_string(), None));
+        steps.insert(1, (String::new(), None));
+
+        assert!(validate_steps(&Translator::new("en"), &steps).is_empty());
+    }
+
+    #[test]
+    fn test_pdf_file_stem() {
+        assert_eq!(pdf_file_stem("Zucchini Ribbons"), "zucchini-ribbons");
+        assert_eq!(pdf_file_stem("  Salsa (mild) / v2 "), "salsa-mild-v2");
+        assert_eq!(pdf_file_stem("\"; rm -rf"), "rm-rf");
+        assert_eq!(pdf_file_stem("Crème brûlée"), "cr-me-br-l-e");
+        assert_eq!(pdf_file_stem("!!!"), "preparation");
+    }
+
+    #[actix_web::test]
+    async fn test_create_preparation_rejects_too_many_steps() {
+        use actix_web::test::{call_service, init_service, read_body, TestRequest};
+        use actix_web::App;
+
+        let Ok(url) = std::env::var("TEST_DATABASE_URL") else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+        let pool = sqlx::PgPool::connect(&url).await.expect("Failed to connect to TEST_DATABASE_URL");
+        let s3_config = aws_sdk_s3::Config::builder()
+            .behavior_version(aws_sdk_s3::config::BehaviorVersion::latest())
+            .region(aws_sdk_s3::config::Region::new("ap-southeast-2"))
+            .build();
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(EventDispatcher::new()))
+                .app_data(web::Data::new(S3Client::from_conf(s3_config)))
+                .route("/preparation", web::post().to(create_preparation)),
+        )
+        .await;
+
+        let name = format!("Runaway steps {}", Uuid::new_v4());
+        let mut fields = vec![
+            ("name".to_string(), name.clone()),
+            ("prep_type".to_string(), "veg".to_string()),
+            ("shift".to_string(), "AM".to_string()),
+        ];
+        // One step over the limit, plus a repeat of step 1 that mustn't count twice
+        for num in 1..=max_steps() + 1 {
+            fields.push((format!("step_description_{}", num), format!("Step {}", num)));
+        }
+        fields.push(("step_description_1".to_string(), "Step 1 again".to_string()));
+
+        let boundary = "preparation-form-boundary";
+        let mut body = String::new();
+        for (name, value) in &fields {
+            body.push_str(&format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+                boundary, name, value
+            ));
+        }
+        body.push_str(&format!("--{}--\r\n", boundary));
+        let request = TestRequest::post()
+            .uri("/preparation")
+            .insert_header(("Content-Type", format!("multipart/form-data; boundary={}", boundary)))
+            .set_payload(body)
+            .to_request();
+
+        let response = call_service(&app, request).await;
+        assert_eq!(response.status(), 400);
+        let html = String::from_utf8(read_body(response).await.to_vec()).unwrap();
+        let expected = format!("Too many steps: {} (the limit is {})", max_steps() + 1, max_steps());
+        assert!(html.contains(&expected), "{}", html);
+
+        let saved: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM preparations WHERE name = $1")
+            .bind(&name)
+            .fetch_one(&pool)
+            .await
+            .expect("Failed to count preparations");
+        assert_eq!(saved, 0);
+    }
+}
@@ -0,0 +1,1452 @@
+use crate::cache::ProductListCache;
+use crate::errors::ValidationError;
+use crate::events::{DomainEvent, EventDispatcher};
+use crate::i18n::Translator;
+use crate::models::{AdjacentProducts, Location, Onboarding, NewProductForm, PhotoDraft, Preparation, Product, ProductAudit, ProductDocument, ProductPrice, ProductSnapshot, NO_PICTURE_WARNING, ProductSummary};
+use crate::recently_viewed::{RecentItem, ViewedKind};
+use crate::repo::{NewProduct, ProductRepo};
+use crate::utils;
+use super::common::{
+    filters, already_created_redirect, channel_body, delete_replaced_images, load_onboarding,
+    load_admin, load_recently_viewed, merged_product_conflict, merged_product_redirect, read_temp_file, record_picture_size, record_recent_view, recently_viewed_items,
+    submitted_form_token, upload_image_to_storage, upload_webp_variant, image_placeholder,
+};
+use super::errors::error_page;
+use actix_multipart::form::{tempfile::TempFile, text::Text, MultipartForm};
+use actix_web::{web, HttpResponse, Result};
+use askama::Template;
+use aws_sdk_s3::Client as S3Client;
+use bytes::Bytes;
+use futures_util::StreamExt;
+use std::sync::Arc;
+use uuid::Uuid;
+/// Template for the index page
+#[derive(Template)]
+#[template(path = "index.html")]
+struct IndexTemplate {
+    /// Shared with `ProductListCache`, so a cached list isn't copied per visit
+    products: Arc<Vec<ProductSummary>>,
+    /// Newest preparations, shown above the product list
+    recent_preparations: Vec<Preparation>,
+    /// Getting-started steps still to do on a fresh install
+    onboarding: Onboarding,
+    /// Products and preparations opened lately, for the quick-access strip
+    recently_viewed: Vec<RecentItem>,
+    is_authenticated: bool,
+    username: Option<String>,
+}
+
+/// Template for the new product form
+#[derive(Template)]
+#[template(path = "product_new.html")]
+struct ProductNewTemplate {
+    /// Problems that stopped the product being saved
+    errors: Vec<String>,
+    /// Non-blocking issues, shown alongside any errors
+    warnings: Vec<String>,
+    barcode: String,
+    /// Allowed locations; empty means the location is free text
+    locations: Vec<String>,
+    /// One-time token that stops a double submit creating the product twice
+    form_token: Uuid,
+    is_authenticated: bool,
+    username: Option<String>,
+}
+
+/// Template for the product detail page
+#[derive(Template)]
+#[template(path = "product_detail.html")]
+struct ProductDetailTemplate {
+    product: Product,
+    /// Recent recorded prices, only loaded for signed-in users
+    price_history: Vec<ProductPrice>,
+    tags: Vec<String>,
+    /// Spec sheets and other documents attached to the product
+    documents: Vec<ProductDocument>,
+    /// `accept` list for the attach form, e.g. ".pdf,.docx"; empty when uploads are off
+    document_accept: String,
+    /// Neighbours on the homepage list, for the Prev/Next links
+    adjacent: AdjacentProducts,
+    /// Other products and preparations opened lately, for the quick-access strip
+    recently_viewed: Vec<RecentItem>,
+    /// Offers merging this product into another, for admins
+    is_admin: bool,
+    is_authenticated: bool,
+    username: Option<String>,
+}
+
+/// Template for the product edit page
+#[derive(Template)]
+#[template(path = "product_edit.html")]
+struct ProductEditTemplate {
+    product: Product,
+    /// Problems that stopped the changes being saved
+    errors: Vec<String>,
+    /// Non-blocking issues with the product as shown
+    warnings: Vec<String>,
+    /// Allowed locations; empty means the location is free text
+    locations: Vec<String>,
+    is_authenticated: bool,
+    username: Option<String>,
+}
+
+/// Multipart form structure for file upload
+#[derive(Debug, MultipartForm)]
+pub struct UploadForm {
+    #[multipart(limit = "20 MB")]
+    picture: Option<TempFile>,
+    supplier_name: Text<String>,
+    product_name: Text<String>,
+    location: Text<String>,
+    barcode: Option<Text<String>>,
+    description: Text<String>,
+    unit: Option<Text<String>>,
+    price: Option<Text<String>>,
+    storage_temp_min: Option<Text<String>>,
+    storage_temp_max: Option<Text<String>>,
+    handling_notes: Option<Text<String>>,
+    form_token: Option<Text<String>>,
+}
+
+/// Most photos one bulk upload turns into drafts
+pub const BULK_PHOTO_MAX_FILES: usize = 50;
+
+/// Largest single photo a bulk upload takes, as on the product form
+const BULK_PHOTO_MAX_FILE_BYTES: usize = 20 * 1024 * 1024;
+
+/// Largest bulk upload as a whole; keep in step with the `photos` limit below
+pub const BULK_PHOTO_MAX_TOTAL_BYTES: usize = 400 * 1024 * 1024;
+
+/// Multipart form of the bulk photo upload: the `photos` field repeated once per file
+#[derive(Debug, MultipartForm)]
+pub struct BulkPhotoForm {
+    #[multipart(limit = "400 MB")]
+    photos: Vec<TempFile>,
+}
+
+/// Trimmed barcode from the product form, or None when left blank
+fn form_barcode(form: &UploadForm) -> Option<String> {
+    form.barcode
+        .as_ref()
+        .map(|barcode| barcode.trim().to_string())
+        .filter(|barcode| !barcode.is_empty())
+}
+
+/// Whether a barcode already belongs to a product other than `exclude_id`
+async fn barcode_in_use(repo: &dyn ProductRepo, barcode: &str, exclude_id: Option<Uuid>) -> Result<bool> {
+    let existing = repo
+        .product_by_barcode(barcode)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to check barcode")
+        })?;
+
+    Ok(existing.is_some_and(|product| Some(product.id) != exclude_id))
+}
+
+/// Allowed locations for the product forms; empty means the location is free text
+async fn product_locations(repo: &dyn ProductRepo) -> Result<Vec<String>> {
+    repo.location_names().await.map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to fetch locations")
+    })
+}
+
+/// The product a previous submit of this form created, if any
+async fn product_with_form_token(repo: &dyn ProductRepo, token: Option<Uuid>) -> Result<Option<Uuid>> {
+    let Some(token) = token else {
+        return Ok(None);
+    };
+    repo.created_with_form_token(token).await.map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to check the form token")
+    })
+}
+
+/// Error for a barcode that `barcode_in_use` found on another product
+pub(super) fn barcode_taken_error(barcode: &str) -> ValidationError {
+    ValidationError::new(
+        "barcode",
+        "duplicate",
+        format!("Barcode {} is already assigned to another product", barcode),
+    )
+}
+
+/// How many recently added preparations the homepage lists
+const RECENT_PREPARATIONS_LIMIT: i64 = 5;
+
+/// GET / - Homepage with list of products
+///
+/// The product list comes from `ProductListCache`, so busy periods share one query.
+pub async fn index(
+    req: actix_web::HttpRequest,
+    pool: web::Data<sqlx::PgPool>,
+    product_list_cache: web::Data<ProductListCache>,
+    auth: crate::middleware::OptionalAuth,
+) -> Result<HttpResponse> {
+    let products = product_list_cache
+        .get_or_load(|| ProductSummary::get_all(pool.get_ref()))
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to fetch products")
+        })?;
+
+    let recent_preparations = Preparation::get_recent(pool.get_ref(), RECENT_PREPARATIONS_LIMIT)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to fetch preparations")
+        })?;
+
+    let onboarding = load_onboarding(pool.get_ref()).await?;
+    let viewed = load_recently_viewed(&req, pool.get_ref(), &auth).await;
+    let recently_viewed = recently_viewed_items(pool.get_ref(), &viewed, None).await;
+
+    let template = IndexTemplate {
+        products,
+        recent_preparations,
+        onboarding,
+        recently_viewed,
+        is_authenticated: auth.user.is_some(),
+        username: auth.user.map(|u| u.username),
+    };
+
+    let html = template.render().map_err(|e| {
+        eprintln!("Template error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to render template")
+    })?;
+
+    Ok(HttpResponse::Ok().content_type("text/html").body(html))
+}
+
+/// Query parameters for pre-filling the new product form
+#[derive(Debug, serde::Deserialize)]
+pub struct NewProductQuery {
+    barcode: Option<String>,
+}
+
+/// GET /product/new - Show form to add new product
+pub async fn new_product_form(
+    repo: web::Data<dyn ProductRepo>,
+    auth: crate::middleware::OptionalAuth,
+    query: web::Query<NewProductQuery>,
+) -> Result<HttpResponse> {
+    let locations = product_locations(repo.get_ref()).await?;
+
+    let template = ProductNewTemplate {
+        errors: Vec::new(),
+        warnings: Vec::new(),
+        barcode: query.barcode.clone().unwrap_or_default(),
+        locations,
+        form_token: Uuid::new_v4(),
+        is_authenticated: auth.user.is_some(),
+        username: auth.user.map(|u| u.username),
+    };
+
+    let html = template.render().map_err(|e| {
+        eprintln!("Template error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to render template")
+    })?;
+
+    Ok(HttpResponse::Ok().content_type("text/html").body(html))
+}
+
+/// POST /product - Handle form submission and insert into DB
+pub async fn create_product(
+    repo: web::Data<dyn ProductRepo>,
+    events: web::Data<EventDispatcher>,
+    s3_client: web::Data<S3Client>,
+    auth: crate::middleware::OptionalAuth,
+    MultipartForm(form): MultipartForm<UploadForm>,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    let form_token = submitted_form_token(form.form_token.as_deref().map(String::as_str), &tr)?;
+    if let Some(id) = product_with_form_token(repo.get_ref(), form_token).await? {
+        return Ok(already_created_redirect(format!("/product/{}", id)));
+    }
+
+    // Validate form data
+    let form_data = NewProductForm {
+        supplier_name: form.supplier_name.to_string(),
+        product_name: form.product_name.to_string(),
+        location: form.location.to_string(),
+        barcode: form_barcode(&form),
+        description: form.description.to_string(),
+        unit: form.unit.as_ref().map(|unit| unit.to_string()),
+        price: form.price.as_ref().map(|price| price.to_string()),
+        storage_temp_min: form.storage_temp_min.as_ref().map(|degrees| degrees.to_string()),
+        storage_temp_max: form.storage_temp_max.as_ref().map(|degrees| degrees.to_string()),
+        handling_notes: form.handling_notes.as_ref().map(|notes| notes.to_string()),
+    };
+
+    let locations = product_locations(repo.get_ref()).await?;
+    let mut validation = form_data.validate_with_locations(&locations);
+    if let (true, Some(barcode)) = (validation.is_ok(), &form_data.barcode) {
+        if barcode_in_use(repo.get_ref(), barcode, None).await? {
+            validation.errors.push(barcode_taken_error(barcode));
+        }
+    }
+
+    // Browsers submit an empty file part when no image was chosen
+    let picture = form
+        .picture
+        .as_ref()
+        .and_then(|picture| picture.file_name.as_deref().map(|name| (picture, name)))
+        .filter(|(_, name)| !name.trim().is_empty());
+
+    if picture.is_none() {
+        if utils::env_flag("REQUIRE_PRODUCT_IMAGE") {
+            validation.errors.push(ValidationError::new("picture", "required", "A product image is required"));
+        } else {
+            validation.warnings.push(NO_PICTURE_WARNING.to_string());
+        }
+    }
+
+    // Warnings alone don't stop the product being saved
+    if !validation.is_ok() {
+        let template = ProductNewTemplate {
+            errors: tr.validation_messages(&validation.errors),
+            warnings: validation.warnings,
+            barcode: form_data.barcode.clone().unwrap_or_default(),
+            locations,
+            form_token: form_token.unwrap_or_else(Uuid::new_v4),
+            is_authenticated: auth.user.is_some(),
+            username: auth.user.map(|u| u.username),
+        };
+        let html = template.render().map_err(|e| {
+            eprintln!("Template error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to render template")
+        })?;
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/html")
+            .body(html));
+    }
+
+    // Handle optional image upload
+    let (picture_url, webp_url, lqip, picture_size) = if let Some((picture, filename)) = picture {
+        let image = match utils::ImageKind::from_filename(filename) {
+            Some(kind) => utils::prepare_image_for_storage(read_temp_file(picture)?, kind),
+            None => Err("Invalid file type. Only JPG, PNG, WEBP, AVIF, and HEIC are allowed.".to_string()),
+        };
+        let (file_content, kind) = match image {
+            Ok(image) => image,
+            Err(error_msg) => {
+                let template = ProductNewTemplate {
+                    errors: vec![error_msg],
+                    warnings: validation.warnings,
+                    barcode: form_data.barcode.clone().unwrap_or_default(),
+                    locations,
+                    form_token: form_token.unwrap_or_else(Uuid::new_v4),
+                    is_authenticated: auth.user.is_some(),
+                    username: auth.user.map(|u| u.username),
+                };
+                let html = template.render().map_err(|e| {
+                    eprintln!("Template error: {:?}", e);
+                    actix_web::error::ErrorInternalServerError("Failed to render template")
+                })?;
+                return Ok(HttpResponse::BadRequest()
+                    .content_type("text/html")
+                    .body(html));
+            }
+        };
+
+        let picture_url = upload_image_to_storage(&s3_client, &file_content, kind).await?;
+        let webp_url = upload_webp_variant(&s3_client, &file_content, kind).await;
+        let lqip = image_placeholder(&file_content, kind).await;
+        (Some(picture_url), webp_url, lqip, Some(file_content.len() as i64))
+    } else {
+        // No image provided, the placeholder is shown instead
+        (None, None, None, None)
+    };
+
+    // Insert into database
+    let created = repo
+        .create_product(NewProduct {
+            form: &form_data,
+            picture_url: picture_url.as_deref(),
+            webp_url: webp_url.as_deref(),
+            lqip: lqip.as_deref(),
+            picture_size,
+            form_token,
+            created_by: auth.username(),
+        })
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to create product")
+        })?;
+    let Some(product) = created else {
+        // The same form was submitted twice at once and the other request won
+        let uploaded = picture_url.into_iter().chain(webp_url).collect();
+        repo.discard_images(&s3_client, uploaded).await;
+        let id = product_with_form_token(repo.get_ref(), form_token).await?;
+        return Ok(already_created_redirect(id.map_or_else(|| "/".to_string(), |id| format!("/product/{}", id))));
+    };
+    events.emit(DomainEvent::ProductCreated { id: product.id }, auth.username());
+
+    // Redirect to the newly created product's detail page
+    Ok(HttpResponse::SeeOther()
+        .append_header(("Location", format!("/product/{}", product.id)))
+        .finish())
+}
+
+/// Recorded prices listed on a product's page
+const PRICE_HISTORY_SHOWN: i64 = 10;
+
+/// GET /product/{id} - View details of a single product
+pub async fn product_detail(
+    req: actix_web::HttpRequest,
+    pool: web::Data<sqlx::PgPool>,
+    id: web::Path<Uuid>,
+    auth: crate::middleware::OptionalAuth,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    let product = Product::get_by_id(pool.get_ref(), *id)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to fetch product")
+        })?;
+
+    match product {
+        Some(Product { merged_into: Some(target), .. }) => Ok(merged_product_redirect(target, "")),
+        Some(product) => {
+            let price_history = if auth.user.is_some() {
+                ProductPrice::for_product(pool.get_ref(), product.id, PRICE_HISTORY_SHOWN)
+                    .await
+                    .map_err(|e| {
+                        eprintln!("Database error: {:?}", e);
+                        actix_web::error::ErrorInternalServerError("Failed to fetch price history")
+                    })?
+            } else {
+                Vec::new()
+            };
+
+            let tags = Product::tags(pool.get_ref(), product.id)
+                .await
+                .map_err(|e| {
+                    eprintln!("Database error: {:?}", e);
+                    actix_web::error::ErrorInternalServerError("Failed to fetch product tags")
+                })?;
+            let documents = ProductDocument::for_product(pool.get_ref(), product.id)
+                .await
+                .map_err(|e| {
+                    eprintln!("Database error: {:?}", e);
+                    actix_web::error::ErrorInternalServerError("Failed to fetch product documents")
+                })?;
+            let document_accept = utils::document_types()
+                .iter()
+                .map(|kind| format!(".{}", kind.extension()))
+                .collect::<Vec<_>>()
+                .join(",");
+            let adjacent = Product::get_adjacent(pool.get_ref(), product.id)
+                .await
+                .map_err(|e| {
+                    eprintln!("Database error: {:?}", e);
+                    actix_web::error::ErrorInternalServerError("Failed to fetch neighbouring products")
+                })?;
+
+            let viewed = load_recently_viewed(&req, pool.get_ref(), &auth).await;
+            let recently_viewed = recently_viewed_items(pool.get_ref(), &viewed, Some(product.id)).await;
+            let viewed_cookie = record_recent_view(&req, pool.get_ref(), &auth, viewed, ViewedKind::Product, product.id).await;
+            let is_admin = match &auth.user {
+                Some(user) => load_admin(pool.get_ref(), user).await?.is_some(),
+                None => false,
+            };
+
+            let template = ProductDetailTemplate {
+                product,
+                price_history,
+                tags,
+                documents,
+                document_accept,
+                adjacent,
+                recently_viewed,
+                is_admin,
+                is_authenticated: auth.user.is_some(),
+                username: auth.user.map(|u| u.username),
+            };
+
+            let html = template.render().map_err(|e| {
+                eprintln!("Template error: {:?}", e);
+                actix_web::error::ErrorInternalServerError("Failed to render template")
+            })?;
+
+            let mut response = HttpResponse::Ok();
+            if let Some(cookie) = viewed_cookie {
+                response.cookie(cookie);
+            }
+            Ok(response.content_type("text/html").body(html))
+        }
+        None => Ok(HttpResponse::NotFound()
+            .content_type("text/html")
+            .body(error_page(&tr.t("error-product-not-found"), None, &[("/", tr.t("error-back-home"))]))),
+    }
+}
+
+/// Template for a product's change history
+#[derive(Template)]
+#[template(path = "product_history.html")]
+struct ProductHistoryTemplate {
+    product: Product,
+    entries: Vec<ProductHistoryEntry>,
+    is_authenticated: bool,
+    username: Option<String>,
+}
+
+/// One update on the history page, with the fields it changed
+struct ProductHistoryEntry {
+    audit: ProductAudit,
+    changes: Vec<crate::models::ProductFieldChange>,
+}
+
+/// GET /product/{id}/history - Who changed a product, when, and what changed
+pub async fn product_history(
+    pool: web::Data<sqlx::PgPool>,
+    id: web::Path<Uuid>,
+    auth: crate::middleware::OptionalAuth,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    let product = Product::get_by_id(pool.get_ref(), *id)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to fetch product")
+        })?;
+
+    let Some(product) = product else {
+        return Ok(HttpResponse::NotFound()
+            .content_type("text/html")
+            .body(error_page(&tr.t("error-product-not-found"), None, &[("/", tr.t("error-back-home"))])));
+    };
+    if let Some(target) = product.merged_into {
+        return Ok(merged_product_redirect(target, "/history"));
+    }
+
+    let entries = ProductAudit::for_product(pool.get_ref(), product.id)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to fetch product history")
+        })?
+        .into_iter()
+        .map(|audit| ProductHistoryEntry { changes: audit.changes(), audit })
+        .collect();
+
+    let template = ProductHistoryTemplate {
+        product,
+        entries,
+        is_authenticated: auth.user.is_some(),
+        username: auth.user.map(|u| u.username),
+    };
+    let html = template.render().map_err(|e| {
+        eprintln!("Template error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to render template")
+    })?;
+
+    Ok(HttpResponse::Ok().content_type("text/html").body(html))
+}
+
+/// Form for recording a product's price without the full edit form
+#[derive(Debug, serde::Deserialize)]
+pub struct ProductPriceForm {
+    price: String,
+    /// Leaves the pack size as it was when missing
+    unit: Option<String>,
+}
+
+/// POST /product/{id}/price - Record a product's current price (and optionally its pack size)
+pub async fn update_product_price(
+    pool: web::Data<sqlx::PgPool>,
+    events: web::Data<EventDispatcher>,
+    id: web::Path<Uuid>,
+    auth: crate::middleware::OptionalAuth,
+    form: web::Form<ProductPriceForm>,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    let product = Product::get_by_id(pool.get_ref(), *id)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to fetch product")
+        })?;
+
+    let Some(product) = product else {
+        return Ok(HttpResponse::NotFound()
+            .content_type("text/html")
+            .body(error_page(&tr.t("error-product-not-found"), None, &[("/", tr.t("error-back-home"))])));
+    };
+    if let Some(target) = product.merged_into {
+        return Ok(merged_product_conflict(target, &tr));
+    }
+
+    let mut form_data = product.as_form();
+    form_data.price = Some(form.price.clone());
+    if form.unit.is_some() {
+        form_data.unit = form.unit.clone();
+    }
+
+    // Only the price and unit are being changed, so only their problems count
+    let mut errors: Vec<_> = form_data
+        .validate()
+        .errors
+        .into_iter()
+        .filter(|error| error.field == "price" || error.field == "unit")
+        .collect();
+    let price_cents = form_data.price_cents();
+    if price_cents.is_none() && errors.is_empty() {
+        errors.push(ValidationError::new("price", "required", "Enter a price, like 12.50"));
+    }
+    let Some(price_cents) = price_cents.filter(|_| errors.is_empty()) else {
+        let back = format!("/product/{}", product.id);
+        let message = tr.validation_messages(&errors).join(" ");
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/html")
+            .body(error_page(&tr.t("error-validation"), Some(&message), &[(&back, tr.t("error-back-product"))])));
+    };
+
+    Product::set_price(pool.get_ref(), product.id, Some(price_cents), form_data.unit(), auth.username())
+        .await
+        .map_err(|e| {
+            eprintln!("Database error saving price: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to save price")
+        })?;
+    events.emit(DomainEvent::ProductUpdated { id: product.id }, auth.username());
+
+    Ok(HttpResponse::SeeOther()
+        .append_header(("Location", format!("/product/{}", product.id)))
+        .finish())
+}
+
+/// GET /products/export.csv - Stream every product as CSV
+///
+/// Rows are pulled from a database cursor and written as they arrive, so memory
+/// stays flat however large the table grows. The response is chunked rather than
+/// carrying a Content-Length; a database error mid-export ends the stream early.
+pub async fn export_products_csv(pool: web::Data<sqlx::PgPool>) -> Result<HttpResponse> {
+    let pool = pool.get_ref().clone();
+    let (tx, rx) = tokio::sync::mpsc::channel::<Result<Bytes>>(32);
+
+    actix_web::rt::spawn(async move {
+        let header = utils::csv_record(&[
+            "id",
+            "supplier_name",
+            "product_name",
+            "location",
+            "barcode",
+            "picture_url",
+            "description",
+            "storage_temp_min_c",
+            "storage_temp_max_c",
+            "handling_notes",
+            "created_at",
+            "updated_at",
+        ]);
+        if tx.send(Ok(Bytes::from(header))).await.is_err() {
+            return;
+        }
+
+        let mut rows = Product::stream_all(&pool);
+        while let Some(row) = rows.next().await {
+            let chunk = match row {
+                Ok(product) => Ok(Bytes::from(utils::csv_record(&[
+                    &product.id.to_string(),
+                    &product.supplier_name,
+                    &product.product_name,
+                    &product.location,
+                    product.barcode.as_deref().unwrap_or(""),
+                    product.picture_url.as_deref().unwrap_or(""),
+                    &product.description,
+                    &product.storage_temp_min_c.map(|degrees| degrees.to_string()).unwrap_or_default(),
+                    &product.storage_temp_max_c.map(|degrees| degrees.to_string()).unwrap_or_default(),
+                    product.handling_notes.as_deref().unwrap_or(""),
+                    &product.created_at.to_rfc3339(),
+                    &product.updated_at.to_rfc3339(),
+                ]))),
+                Err(e) => {
+                    eprintln!("Database error exporting products: {:?}", e);
+                    Err(actix_web::error::ErrorInternalServerError("Failed to export products"))
+                }
+            };
+
+            let failed = chunk.is_err();
+            // Stop pulling rows once the client disconnects or the export has failed
+            if tx.send(chunk).await.is_err() || failed {
+                return;
+            }
+        }
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .append_header(("Content-Disposition", "attachment; filename=\"products.csv\""))
+        .streaming(channel_body(rx)))
+}
+
+/// Template for the cold-chain list
+#[derive(Template)]
+#[template(path = "product_cold_chain.html")]
+struct ColdChainTemplate {
+    /// Products with a maximum storage temperature, coldest first
+    products: Vec<Product>,
+    /// The max filter as typed, so the form keeps it
+    max: String,
+    location: String,
+    /// Allowed locations; empty means the location filter is free text
+    locations: Vec<String>,
+    /// Why the max filter was ignored, if it was
+    error: Option<String>,
+    is_authenticated: bool,
+    username: Option<String>,
+}
+
+/// Query parameters for the cold-chain list
+#[derive(Debug, serde::Deserialize)]
+pub struct ColdChainQuery {
+    /// Only products that must be kept at or below this, in °C
+    max: Option<String>,
+    location: Option<String>,
+}
+
+/// GET /products/cold-chain?max=&location= - Products with a maximum storage
+/// temperature, coldest first, for checking deliveries and fridge outages
+pub async fn cold_chain(
+    pool: web::Data<sqlx::PgPool>,
+    auth: crate::middleware::OptionalAuth,
+    query: web::Query<ColdChainQuery>,
+) -> Result<HttpResponse> {
+    let max = query.max.as_deref().map(str::trim).unwrap_or_default().to_string();
+    let location = query.location.as_deref().map(str::trim).unwrap_or_default().to_string();
+    let max_c = utils::parse_temperature(&max);
+    let error = (!max.is_empty() && max_c.is_none())
+        .then(|| "Max temperature must be a number of degrees Celsius, like 5 or -18".to_string());
+
+    let products = Product::cold_chain(pool.get_ref(), max_c, Some(location.as_str()).filter(|l| !l.is_empty()))
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to fetch products")
+        })?;
+
+    let locations = Location::names(pool.get_ref()).await.map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to fetch locations")
+    })?;
+
+    let mut response = if error.is_some() { HttpResponse::BadRequest() } else { HttpResponse::Ok() };
+    let template = ColdChainTemplate {
+        products,
+        max,
+        location,
+        locations,
+        error,
+        is_authenticated: auth.user.is_some(),
+        username: auth.user.map(|u| u.username),
+    };
+    let html = template.render().map_err(|e| {
+        eprintln!("Template error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to render template")
+    })?;
+
+    Ok(response.content_type("text/html").body(html))
+}
+
+impl ProductEditTemplate {
+    /// Whether the product's current location is still in the allowed list
+    ///
+    /// Photo drafts have no location yet, which counts as listed so none is offered.
+    fn location_listed(&self) -> bool {
+        self.product.location.is_empty() || self.locations.contains(&self.product.location)
+    }
+}
+
+/// GET /product/{id}/edit - Show edit form for a product
+pub async fn edit_product_form(
+    repo: web::Data<dyn ProductRepo>,
+    id: web::Path<Uuid>,
+    auth: crate::middleware::OptionalAuth,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    let product = repo
+        .product_by_id(*id)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to fetch product")
+        })?;
+
+    match product {
+        Some(Product { merged_into: Some(target), .. }) => Ok(merged_product_redirect(target, "/edit")),
+        Some(product) => {
+            let locations = product_locations(repo.get_ref()).await?;
+
+            let template = ProductEditTemplate {
+                warnings: product.warnings(),
+                product,
+                errors: Vec::new(),
+                locations,
+                is_authenticated: auth.user.is_some(),
+                username: auth.user.map(|u| u.username),
+            };
+
+            let html = template.render().map_err(|e| {
+                eprintln!("Template error: {:?}", e);
+                actix_web::error::ErrorInternalServerError("Failed to render template")
+            })?;
+
+            Ok(HttpResponse::Ok().content_type("text/html").body(html))
+        }
+        None => Ok(HttpResponse::NotFound()
+            .content_type("text/html")
+            .body(error_page(&tr.t("error-product-not-found"), None, &[("/", tr.t("error-back-home"))]))),
+    }
+}
+
+/// POST /product/{id} - Update an existing product
+#[allow(clippy::too_many_arguments)]
+pub async fn update_product(
+    pool: web::Data<sqlx::PgPool>,
+    repo: web::Data<dyn ProductRepo>,
+    events: web::Data<EventDispatcher>,
+    s3_client: web::Data<S3Client>,
+    id: web::Path<Uuid>,
+    auth: crate::middleware::OptionalAuth,
+    MultipartForm(form): MultipartForm<UploadForm>,
+    tr: Translator,
+) -> Result<HttpResponse> {
+    // Fetch existing product
+    let existing_product = repo
+        .product_by_id(*id)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to fetch product")
+        })?;
+
+    let existing_product = match existing_product {
+        Some(p) => p,
+        None => {
+            return Ok(HttpResponse::NotFound()
+                .content_type("text/html")
+                .body(error_page(&tr.t("error-product-not-found"), None, &[("/", tr.t("error-back-home"))])));
+        }
+    };
+    if let Some(target) = existing_product.merged_into {
+        return Ok(merged_product_conflict(target, &tr));
+    }
+
+    // Validate form data
+    let form_data = NewProductForm {
+        supplier_name: form.supplier_name.to_string(),
+        product_name: form.product_name.to_string(),
+        location: form.location.to_string(),
+        barcode: form_barcode(&form),
+        description: form.description.to_string(),
+        unit: form.unit.as_ref().map(|unit| unit.to_string()),
+        price: form.price.as_ref().map(|price| price.to_string()),
+        storage_temp_min: form.storage_temp_min.as_ref().map(|degrees| degrees.to_string()),
+        storage_temp_max: form.storage_temp_max.as_ref().map(|degrees| degrees.to_string()),
+        handling_notes: form.handling_notes.as_ref().map(|notes| notes.to_string()),
+    };
+
+    let locations = product_locations(repo.get_ref()).await?;
+    let mut validation = form_data.validate_with_locations(&locations);
+    if let (true, Some(barcode)) = (validation.is_ok(), &form_data.barcode) {
+        if barcode_in_use(repo.get_ref(), barcode, Some(*id)).await? {
+            validation.errors.push(barcode_taken_error(barcode));
+        }
+    }
+
+    // Check if new image was uploaded
+    let new_picture = form
+        .picture
+        .as_ref()
+        .and_then(|picture| picture.file_name.as_deref().map(|name| (picture, name)))
+        .filter(|(_, name)| !name.trim().is_empty());
+
+    if new_picture.is_none() && existing_product.picture_url.is_none() {
+        validation.warnings.push(NO_PICTURE_WARNING.to_string());
+    }
+
+    // Warnings alone don't stop the changes being saved
+    if !validation.is_ok() {
+        let template = ProductEditTemplate {
+            product: existing_product,
+            errors: tr.validation_messages(&validation.errors),
+            warnings: validation.warnings,
+            locations,
+            is_authenticated: auth.user.is_some(),
+            username: auth.user.map(|u| u.username),
+        };
+        let html = template.render().map_err(|e| {
+            eprintln!("Template error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to render template")
+        })?;
+        return Ok(HttpResponse::BadRequest()
+            .content_type("text/html")
+            .body(html));
+    }
+
+    let (picture_url, webp_url, lqip, picture_size) = if let Some((picture, filename)) = new_picture {
+        let image = match utils::ImageKind::from_filename(filename) {
+            Some(kind) => utils::prepare_image_for_storage(read_temp_file(picture)?, kind),
+            None => Err("Invalid file type. Only JPG, PNG, WEBP, AVIF, and HEIC are allowed.".to_string()),
+        };
+        let (file_content, kind) = match image {
+            Ok(image) => image,
+            Err(error_msg) => {
+                let template = ProductEditTemplate {
+                    product: existing_product,
+                    errors: vec![error_msg],
+                    warnings: validation.warnings,
+                    locations,
+                    is_authenticated: auth.user.is_some(),
+                    username: auth.user.map(|u| u.username),
+                };
+                let html = template.render().map_err(|e| {
+                    eprintln!("Template error: {:?}", e);
+                    actix_web::error::ErrorInternalServerError("Failed to render template")
+                })?;
+                return Ok(HttpResponse::BadRequest()
+                    .content_type("text/html")
+                    .body(html));
+            }
+        };
+
+        let picture_url = upload_image_to_storage(&s3_client, &file_content, kind).await?;
+        let webp_url = upload_webp_variant(&s3_client, &file_content, kind).await;
+        let lqip = image_placeholder(&file_content, kind).await;
+        (Some(picture_url), webp_url, lqip, Some(file_content.len() as i64))
+    } else {
+        // Keep existing image (and its WebP copy, placeholder and recorded size)
+        (existing_product.picture_url.clone(), None, None, None)
+    };
+
+    // Update the product and record the change together, so the history never misses an edit
+    let mut tx = pool.begin().await.map_err(|e| {
+        eprintln!("Database error starting transaction: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to update product")
+    })?;
+
+    let mut product = Product::update(
+        &mut *tx,
+        *id,
+        &form_data.supplier_name,
+        &form_data.product_name,
+        &form_data.location,
+        form_data.barcode.as_deref(),
+        picture_url.as_deref(),
+        &form_data.description,
+    )
+    .await
+    .map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to update product")
+    })?;
+    if webp_url.is_some() {
+        Product::set_webp_url(&mut *tx, product.id, webp_url.as_deref())
+            .await
+            .map_err(|e| {
+                eprintln!("Database error: {:?}", e);
+                actix_web::error::ErrorInternalServerError("Failed to update product")
+            })?;
+    }
+    if lqip.is_some() {
+        Product::set_lqip(&mut *tx, product.id, lqip.as_deref())
+            .await
+            .map_err(|e| {
+                eprintln!("Database error: {:?}", e);
+                actix_web::error::ErrorInternalServerError("Failed to update product")
+            })?;
+    }
+    record_picture_size(&mut *tx, picture_url.as_deref(), picture_size).await?;
+    Product::set_price(&mut *tx, product.id, form_data.price_cents(), form_data.unit(), auth.username())
+        .await
+        .map_err(|e| {
+            eprintln!("Database error saving price: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to update product")
+        })?;
+    product.last_price_cents = form_data.price_cents();
+    product.unit = form_data.unit().map(str::to_string);
+    Product::set_handling(
+        &mut *tx,
+        product.id,
+        form_data.storage_temp_min_c(),
+        form_data.storage_temp_max_c(),
+        form_data.handling_notes(),
+    )
+    .await
+    .map_err(|e| {
+        eprintln!("Database error saving handling details: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to update product")
+    })?;
+    product.storage_temp_min_c = form_data.storage_temp_min_c();
+    product.storage_temp_max_c = form_data.storage_temp_max_c();
+    product.handling_notes = form_data.handling_notes().map(str::to_string);
+
+    // Kiosk sessions have no users row, so only their name is kept
+    let user_id = auth.user.as_ref().filter(|user| !user.is_kiosk).map(|user| user.user_id);
+    ProductAudit::record(
+        &mut *tx,
+        product.id,
+        user_id,
+        auth.username(),
+        &ProductSnapshot::from(&existing_product),
+        &ProductSnapshot::from(&product),
+    )
+    .await
+    .map_err(|e| {
+        eprintln!("Database error recording product change: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to update product")
+    })?;
+
+    tx.commit().await.map_err(|e| {
+        eprintln!("Database error committing transaction: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to update product")
+    })?;
+    events.emit(DomainEvent::ProductUpdated { id: product.id }, auth.username());
+
+    if existing_product.picture_url != product.picture_url {
+        let replaced = existing_product.picture_url.into_iter().chain(existing_product.webp_url).collect();
+        delete_replaced_images(pool.get_ref(), &s3_client, replaced).await;
+    }
+
+    // Redirect to product detail page
+    Ok(HttpResponse::SeeOther()
+        .append_header(("Location", format!("/product/{}", product.id)))
+        .finish())
+}
+
+/// Template for the bulk photo upload and the drafts it made
+#[derive(Template)]
+#[template(path = "product_bulk_photos.html")]
+struct ProductBulkPhotosTemplate {
+    /// Photos in the last upload that couldn't be used, and why
+    errors: Vec<String>,
+    /// Drafts the last upload made, shown once it's done
+    created: Option<usize>,
+    /// Every draft still waiting for its details, newest first
+    drafts: Vec<ProductSummary>,
+    max_files: usize,
+    is_authenticated: bool,
+    username: Option<String>,
+}
+
+/// Query string of the bulk photo page after an upload
+#[derive(serde::Deserialize)]
+pub struct BulkPhotosQuery {
+    created: Option<usize>,
+}
+
+/// Render the bulk photo page with the current drafts
+async fn render_bulk_photos(
+    pool: &sqlx::PgPool,
+    auth: crate::middleware::OptionalAuth,
+    errors: Vec<String>,
+    created: Option<usize>,
+) -> Result<String> {
+    let drafts = ProductSummary::drafts(pool).await.map_err(|e| {
+        eprintln!("Database error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to fetch draft products")
+    })?;
+    let template = ProductBulkPhotosTemplate {
+        errors,
+        created,
+        drafts,
+        max_files: BULK_PHOTO_MAX_FILES,
+        is_authenticated: auth.user.is_some(),
+        username: auth.user.map(|u| u.username),
+    };
+    template.render().map_err(|e| {
+        eprintln!("Template error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to render template")
+    })
+}
+
+/// GET /products/bulk-photos - Upload form for many photos, and the drafts waiting to be finished
+pub async fn bulk_photos_form(
+    pool: web::Data<sqlx::PgPool>,
+    auth: crate::middleware::OptionalAuth,
+    query: web::Query<BulkPhotosQuery>,
+) -> Result<HttpResponse> {
+    let html = render_bulk_photos(pool.get_ref(), auth, Vec::new(), query.created).await?;
+    Ok(HttpResponse::Ok().content_type("text/html").body(html))
+}
+
+/// POST /products/bulk-photos - Make a draft product from each uploaded photo
+///
+/// Photos are checked one by one: any that are too big or not an image are
+/// listed on the page, and the rest still become drafts. The drafts are
+/// inserted together, so a failed save leaves none behind.
+pub async fn upload_bulk_photos(
+    pool: web::Data<sqlx::PgPool>,
+    events: web::Data<EventDispatcher>,
+    s3_client: web::Data<S3Client>,
+    auth: crate::middleware::OptionalAuth,
+    MultipartForm(form): MultipartForm<BulkPhotoForm>,
+) -> Result<HttpResponse> {
+    // Browsers submit an empty file part when nothing was chosen
+    let photos: Vec<(&TempFile, &str)> = form
+        .photos
+        .iter()
+        .filter_map(|photo| photo.file_name.as_deref().map(|name| (photo, name)))
+        .filter(|(_, name)| !name.trim().is_empty())
+        .collect();
+
+    let mut errors = Vec::new();
+    if photos.is_empty() {
+        errors.push("Choose at least one photo to upload.".to_string());
+    } else if photos.len() > BULK_PHOTO_MAX_FILES {
+        errors.push(format!(
+            "Only the first {} photos were used; upload the other {} separately.",
+            BULK_PHOTO_MAX_FILES,
+            photos.len() - BULK_PHOTO_MAX_FILES
+        ));
+    }
+
+    let uploaded_at = utils::format_local_time(chrono::Utc::now(), utils::venue_timezone());
+    let mut drafts = Vec::new();
+    for (picture, filename) in photos.into_iter().take(BULK_PHOTO_MAX_FILES) {
+        if picture.size > BULK_PHOTO_MAX_FILE_BYTES {
+            errors.push(format!("{}: larger than 20MB.", filename));
+            continue;
+        }
+        let image = match utils::ImageKind::from_filename(filename) {
+            Some(kind) => utils::prepare_image_for_storage(read_temp_file(picture)?, kind),
+            None => Err("Invalid file type. Only JPG, PNG, WEBP, AVIF, and HEIC are allowed.".to_string()),
+        };
+        let (file_content, kind) = match image {
+            Ok(image) => image,
+            Err(error_msg) => {
+                errors.push(format!("{}: {}", filename, error_msg));
+                continue;
+            }
+        };
+
+        let picture_url = match upload_image_to_storage(&s3_client, &file_content, kind).await {
+            Ok(url) => url,
+            Err(e) => {
+                // Nothing saved refers to the photos stored so far
+                discard_photo_drafts(pool.get_ref(), &s3_client, &drafts).await;
+                return Err(e);
+            }
+        };
+        let webp_url = upload_webp_variant(&s3_client, &file_content, kind).await;
+        let lqip = image_placeholder(&file_content, kind).await;
+        drafts.push(PhotoDraft {
+            product_name: format!("Photo {}, {}", drafts.len() + 1, uploaded_at),
+            picture_url,
+            webp_url,
+            lqip,
+            picture_size_bytes: file_content.len() as i64,
+        });
+    }
+
+    let ids = if drafts.is_empty() {
+        Vec::new()
+    } else {
+        let saved = async {
+            let mut tx = pool.begin().await?;
+            let ids = Product::create_photo_drafts(&mut *tx, &drafts).await?;
+            tx.commit().await?;
+            Ok::<_, sqlx::Error>(ids)
+        }
+        .await;
+        match saved {
+            Ok(ids) => ids,
+            Err(e) => {
+                eprintln!("Database error: {:?}", e);
+                discard_photo_drafts(pool.get_ref(), &s3_client, &drafts).await;
+                return Err(actix_web::error::ErrorInternalServerError("Failed to create draft products"));
+            }
+        }
+    };
+    for id in &ids {
+        events.emit(DomainEvent::ProductCreated { id: *id }, auth.username());
+    }
+
+    if errors.is_empty() {
+        return Ok(HttpResponse::SeeOther()
+            .append_header(("Location", format!("/products/bulk-photos?created={}", ids.len())))
+            .finish());
+    }
+    let html = render_bulk_photos(pool.get_ref(), auth, errors, Some(ids.len())).await?;
+    // Some photos were still made into drafts, so only an upload with none is a bad request
+    let mut response = if ids.is_empty() { HttpResponse::BadRequest() } else { HttpResponse::Ok() };
+    Ok(response.content_type("text/html").body(html))
+}
+
+/// Delete the stored photos of drafts that weren't saved
+async fn discard_photo_drafts(pool: &sqlx::PgPool, s3_client: &S3Client, drafts: &[PhotoDraft]) {
+    let uploaded = drafts
+        .iter()
+        .flat_map(|draft| std::iter::once(draft.picture_url.clone()).chain(draft.webp_url.clone()))
+        .collect();
+    delete_replaced_images(pool, s3_client, uploaded).await;
+}
+
+/// Template shown when a scanned barcode has no product yet
+#[derive(Template)]
+#[template(path = "scan_not_found.html")]
+struct ScanNotFoundTemplate {
+    barcode: String,
+    is_valid: bool,
+    is_authenticated: bool,
+    username: Option<String>,
+}
+
+/// GET /scan/{barcode} - Jump from a scanned barcode to its product
+pub async fn scan_barcode(
+    pool: web::Data<sqlx::PgPool>,
+    barcode: web::Path<String>,
+    auth: crate::middleware::OptionalAuth,
+) -> Result<HttpResponse> {
+    let barcode = barcode.trim().to_string();
+    let is_valid = utils::is_valid_barcode(&barcode);
+
+    if is_valid {
+        let product = Product::get_by_barcode(pool.get_ref(), &barcode)
+            .await
+            .map_err(|e| {
+                eprintln!("Database error: {:?}", e);
+                actix_web::error::ErrorInternalServerError("Failed to fetch product")
+            })?;
+
+        if let Some(product) = product {
+            return Ok(HttpResponse::SeeOther()
+                .append_header(("Location", format!("/product/{}", product.id)))
+                .finish());
+        }
+    }
+
+    let template = ScanNotFoundTemplate {
+        barcode,
+        is_valid,
+        is_authenticated: auth.user.is_some(),
+        username: auth.user.map(|u| u.username),
+    };
+
+    let html = template.render().map_err(|e| {
+        eprintln!("Template error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to render template")
+    })?;
+
+    Ok(HttpResponse::NotFound().content_type("text/html").body(html))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Capture;
+    use crate::repo::FakeRepo;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::App;
+
+    const BOUNDARY: &str = "product-form-boundary";
+
+    /// A new product form submission, as a browser sends it without a picture
+    fn product_form(fields: &[(&str, &str)]) -> TestRequest {
+        let mut body = String::new();
+        for (name, value) in fields {
+            body.push_str(&format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+                BOUNDARY, name, value
+            ));
+        }
+        body.push_str(&format!("--{}--\r\n", BOUNDARY));
+        TestRequest::post()
+            .uri("/product")
+            .insert_header(("Content-Type", format!("multipart/form-data; boundary={}", BOUNDARY)))
+            .set_payload(body)
+    }
+
+    fn s3_client() -> S3Client {
+        let config = aws_sdk_s3::Config::builder()
+            .behavior_version(aws_sdk_s3::config::BehaviorVersion::latest())
+            .region(aws_sdk_s3::config::Region::new("ap-southeast-2"))
+            .build();
+        S3Client::from_conf(config)
+    }
+
+    #[actix_web::test]
+    async fn test_create_product_validates_saves_and_redirects() {
+        let repo = Arc::new(FakeRepo {
+            locations: vec!["Coolroom".to_string()],
+            ..FakeRepo::default()
+        });
+        let capture = Capture::default();
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::from(repo.clone() as Arc<dyn ProductRepo>))
+                .app_data(web::Data::new(EventDispatcher::new().subscribe(capture.clone())))
+                .app_data(web::Data::new(s3_client()))
+                .route("/product", web::post().to(create_product)),
+        )
+        .await;
+        let token = Uuid::new_v4().to_string();
+        let fields = |location| {
+            vec![
+                ("supplier_name", "Fresh Co"),
+                ("product_name", "Unsalted butter"),
+                ("location", location),
+                ("barcode", ""),
+                ("description", "Blocks of unsalted butter for pastry"),
+                ("price", "12.50"),
+                ("storage_temp_max", "4"),
+                ("handling_notes", "Keep away from the fryers"),
+                ("form_token", token.as_str()),
+            ]
+        };
+
+        // Not on the location list, so the form comes back with the error
+        let rejected = call_service(&app, product_form(&fields("Garage")).to_request()).await;
+        assert_eq!(rejected.status(), 400);
+        let html = String::from_utf8(actix_web::test::read_body(rejected).await.to_vec()).unwrap();
+        assert!(html.contains("Unknown location"), "{}", html);
+        assert!(html.contains(&token), "the retry should keep the form token");
+        assert!(repo.products.lock().unwrap().is_empty());
+
+        let created = call_service(&app, product_form(&fields("Coolroom")).to_request()).await;
+        assert_eq!(created.status(), 303);
+        let product = repo.products.lock().unwrap()[0].clone();
+        assert_eq!(created.headers().get("location").unwrap(), format!("/product/{}", product.id).as_str());
+        assert_eq!(product.product_name, "Unsalted butter");
+        assert_eq!(product.last_price_cents, Some(1250));
+        assert_eq!(product.storage_temp_label().as_deref(), Some("4 °C or below"));
+        assert_eq!(product.handling_notes.as_deref(), Some("Keep away from the fryers"));
+        assert_eq!(capture.events(), vec![(DomainEvent::ProductCreated { id: product.id }, None)]);
+
+        // Submitting the same form again goes to the product it already made
+        let repeated = call_service(&app, product_form(&fields("Coolroom")).to_request()).await;
+        assert_eq!(repeated.status(), 303);
+        assert_eq!(repeated.headers().get("location").unwrap(), format!("/product/{}", product.id).as_str());
+        assert_eq!(repo.products.lock().unwrap().len(), 1);
+        assert_eq!(capture.events().len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn test_create_product_rejects_a_barcode_in_use() {
+        let repo = Arc::new(FakeRepo::default());
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::from(repo.clone() as Arc<dyn ProductRepo>))
+                .app_data(web::Data::new(EventDispatcher::new()))
+                .app_data(web::Data::new(s3_client()))
+                .route("/product", web::post().to(create_product)),
+        )
+        .await;
+        let fields = |product_name| {
+            vec![
+                ("supplier_name", "Fresh Co"),
+                ("product_name", product_name),
+                ("location", "Dry store"),
+                ("barcode", "2000000000008"),
+                ("description", "Plain flour in 12.5kg sacks"),
+            ]
+        };
+
+        let first = call_service(&app, product_form(&fields("Plain flour")).to_request()).await;
+        assert_eq!(first.status(), 303);
+        let second = call_service(&app, product_form(&fields("Bread flour")).to_request()).await;
+        assert_eq!(second.status(), 400);
+        let html = String::from_utf8(actix_web::test::read_body(second).await.to_vec()).unwrap();
+        assert!(html.contains("2000000000008"), "{}", html);
+        assert_eq!(repo.products.lock().unwrap().len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn test_scan_barcode_finds_the_product_or_says_not_found() {
+        let Ok(url) = std::env::var("TEST_DATABASE_URL") else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+        let pool = sqlx::PgPool::connect(&url).await.expect("Failed to connect to TEST_DATABASE_URL");
+
+        // An in-store code (prefix 2), so it won't clash with a real product
+        let barcode = "2000000000008";
+        let product = Product::create(&pool, "Scan Co", "Scanned butter", "Coolroom", Some(barcode), None, "")
+            .await
+            .expect("Failed to create product");
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .route("/scan/{barcode}", web::get().to(scan_barcode)),
+        )
+        .await;
+
+        let response = call_service(&app, TestRequest::get().uri(&format!("/scan/{}", barcode)).to_request()).await;
+        let redirected_to = response.headers().get("location").cloned();
+        let unknown = call_service(&app, TestRequest::get().uri("/scan/2000000000015").to_request()).await;
+        let invalid = call_service(&app, TestRequest::get().uri("/scan/12345").to_request()).await;
+
+        sqlx::query("DELETE FROM products WHERE id = $1")
+            .bind(product.id)
+            .execute(&pool)
+            .await
+            .expect("Failed to clean up");
+
+        assert_eq!(response.status(), 303);
+        assert_eq!(redirected_to.unwrap(), format!("/product/{}", product.id).as_str());
+        assert_eq!(unknown.status(), 404);
+        assert_eq!(invalid.status(), 404);
+    }
+
+    #[actix_web::test]
+    async fn test_merged_product_redirects_to_the_one_kept() {
+        let Ok(url) = std::env::var("TEST_DATABASE_URL") else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+        let pool = sqlx::PgPool::connect(&url).await.expect("Failed to connect to TEST_DATABASE_URL");
+
+        let duplicate = Product::create(&pool, "Redirect Co", "Redirect test flour", "Dry store", None, None, "")
+            .await
+            .expect("Failed to create product");
+        let kept = Product::create(&pool, "Redirect Co", "Redirect test plain flour", "Dry store", None, None, "")
+            .await
+            .expect("Failed to create product");
+        Product::merge_into(&pool, duplicate.id, kept.id, true, None, None).await.expect("Failed to merge");
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(EventDispatcher::new()))
+                .route("/product/{id}", web::get().to(product_detail))
+                .route("/product/{id}/history", web::get().to(product_history))
+                .route("/product/{id}/price", web::post().to(update_product_price)),
+        )
+        .await;
+
+        let page = call_service(&app, TestRequest::get().uri(&format!("/product/{}", duplicate.id)).to_request()).await;
+        let page_location = page.headers().get("location").cloned();
+        let history = call_service(&app, TestRequest::get().uri(&format!("/product/{}/history", duplicate.id)).to_request()).await;
+        let history_location = history.headers().get("location").cloned();
+        let price = call_service(
+            &app,
+            TestRequest::post()
+                .uri(&format!("/product/{}/price", duplicate.id))
+                .set_form([("price", "4.50")])
+                .to_request(),
+        )
+        .await;
+        let duplicate_now = Product::get_by_id(&pool, duplicate.id).await.unwrap();
+
+        // Deleting the product kept takes the merged duplicate with it
+        sqlx::query("DELETE FROM products WHERE id = $1")
+            .bind(kept.id)
+            .execute(&pool)
+            .await
+            .expect("Failed to clean up");
+
+        assert_eq!(page.status(), 301);
+        assert_eq!(page_location.unwrap(), format!("/product/{}", kept.id).as_str());
+        assert_eq!(history.status(), 301);
+        assert_eq!(history_location.unwrap(), format!("/product/{}/history", kept.id).as_str());
+        // A change sent to the duplicate isn't saved, so it stays merged
+        assert_eq!(price.status(), 409);
+        let duplicate_now = duplicate_now.unwrap();
+        assert!(duplicate_now.is_merged());
+        assert_eq!(duplicate_now.last_price_cents, None);
+    }
+}
@@ -0,0 +1,443 @@
+use crate::errors::{ApiError, ValidationError};
+use crate::models::{PreparationStep, PreparationSummary, ProductSummary, SearchFilters, WithLocalTimes, PREP_TYPES};
+use crate::utils;
+use actix_web::{web, HttpResponse, Result};
+use askama::Template;
+use uuid::Uuid;
+/// Template for the search results page
+#[derive(Template)]
+#[template(path = "search_results.html")]
+struct SearchResultsTemplate {
+    query: String,
+    /// Active filters, each shown as a chip that links to the search without it
+    filter_chips: Vec<FilterChip>,
+    filters: SearchFilters,
+    products: Vec<ProductSummary>,
+    preparations: Vec<PreparationSummary>,
+    is_authenticated: bool,
+    username: Option<String>,
+}
+
+/// An active search filter on the results page
+struct FilterChip {
+    label: &'static str,
+    value: String,
+    remove_url: String,
+}
+
+/// Query parameters for search; a missing or empty `q` with filters lists what they match
+#[derive(Debug, serde::Deserialize)]
+pub struct SearchQuery {
+    #[serde(default)]
+    q: String,
+    location: Option<String>,
+    prep_type: Option<String>,
+}
+
+/// Filters from the `location` and `prep_type` query parameters, ignoring blank ones
+fn search_filters(
+    location: Option<&str>,
+    prep_type: Option<&str>,
+) -> std::result::Result<SearchFilters, ValidationError> {
+    let location = location.map(str::trim).filter(|l| !l.is_empty()).map(str::to_string);
+    let prep_type = match prep_type.map(str::trim).filter(|t| !t.is_empty()) {
+        None => None,
+        Some(prep_type) => {
+            let prep_type = prep_type.to_lowercase();
+            if !PREP_TYPES.contains(&prep_type.as_str()) {
+                return Err(ValidationError::new(
+                    "prep_type",
+                    "invalid_choice",
+                    format!("Prep type must be one of: {}", PREP_TYPES.join(", ")),
+                ));
+            }
+            Some(prep_type)
+        }
+    };
+    Ok(SearchFilters { location, prep_type })
+}
+
+/// The search page for `term` and `filters`, leaving out blank parameters
+fn search_url(term: &str, filters: &SearchFilters) -> String {
+    let params: Vec<String> = [
+        ("q", Some(term)),
+        ("location", filters.location.as_deref()),
+        ("prep_type", filters.prep_type.as_deref()),
+    ]
+    .into_iter()
+    .filter_map(|(name, value)| {
+        let value = value.filter(|v| !v.is_empty())?;
+        Some(format!("{}={}", name, percent_encoding::utf8_percent_encode(value, percent_encoding::NON_ALPHANUMERIC)))
+    })
+    .collect();
+
+    if params.is_empty() {
+        "/search".to_string()
+    } else {
+        format!("/search?{}", params.join("&"))
+    }
+}
+
+/// A chip for each active filter, linking to the same search without it
+fn filter_chips(term: &str, filters: &SearchFilters) -> Vec<FilterChip> {
+    let mut chips = Vec::new();
+    if let Some(location) = &filters.location {
+        chips.push(FilterChip {
+            label: "Location",
+            value: location.clone(),
+            remove_url: search_url(term, &SearchFilters { location: None, ..filters.clone() }),
+        });
+    }
+    if let Some(prep_type) = &filters.prep_type {
+        chips.push(FilterChip {
+            label: "Type",
+            value: prep_type.clone(),
+            remove_url: search_url(term, &SearchFilters { prep_type: None, ..filters.clone() }),
+        });
+    }
+    chips
+}
+
+/// Products matching a search term and filters, shared by the search page and `GET /api/v1/search`
+///
+/// FUZZY_SEARCH also finds misspelled names ("tomatoe") using pg_trgm similarity.
+async fn search_products(
+    pool: &sqlx::PgPool,
+    term: &str,
+    filters: &SearchFilters,
+) -> std::result::Result<Vec<ProductSummary>, sqlx::Error> {
+    if utils::env_flag("FUZZY_SEARCH") {
+        ProductSummary::search_fuzzy(pool, term, filters).await
+    } else {
+        ProductSummary::search(pool, term, filters).await
+    }
+}
+
+/// Preparations matching a search term and filters, shared like `search_products`
+async fn search_preparations(
+    pool: &sqlx::PgPool,
+    term: &str,
+    filters: &SearchFilters,
+) -> std::result::Result<Vec<PreparationSummary>, sqlx::Error> {
+    if utils::env_flag("FUZZY_SEARCH") {
+        PreparationSummary::search_fuzzy(pool, term, filters).await
+    } else {
+        PreparationSummary::search(pool, term, filters).await
+    }
+}
+
+/// GET /search - Search for products and preparations, optionally within a location or prep type
+pub async fn search(
+    pool: web::Data<sqlx::PgPool>,
+    query: web::Query<SearchQuery>,
+    auth: crate::middleware::OptionalAuth,
+) -> Result<HttpResponse> {
+    let search_term = query.q.trim();
+    let filters = search_filters(query.location.as_deref(), query.prep_type.as_deref())
+        .map_err(|e| actix_web::error::ErrorBadRequest(e.message))?;
+
+    let products = search_products(pool.get_ref(), search_term, &filters).await.map_err(|e| {
+        eprintln!("Database error searching products: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to search products")
+    })?;
+
+    let preparations = search_preparations(pool.get_ref(), search_term, &filters).await.map_err(|e| {
+        eprintln!("Database error searching preparations: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to search preparations")
+    })?;
+
+    let template = SearchResultsTemplate {
+        query: search_term.to_string(),
+        filter_chips: filter_chips(search_term, &filters),
+        filters,
+        products,
+        preparations,
+        is_authenticated: auth.user.is_some(),
+        username: auth.user.map(|u| u.username),
+    };
+
+    let html = template.render().map_err(|e| {
+        eprintln!("Template error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to render template")
+    })?;
+
+    Ok(HttpResponse::Ok().content_type("text/html").body(html))
+}
+
+/// Results of each kind per page of `GET /api/v1/search`
+const SEARCH_API_PAGE_SIZE: usize = 20;
+
+/// What `GET /api/v1/search` can be limited to with `type`
+const SEARCH_API_TYPES: [&str; 3] = ["all", "products", "preparations"];
+
+/// Query parameters for the search API, all optional
+#[derive(Debug, serde::Deserialize)]
+pub struct ApiSearchQuery {
+    q: Option<String>,
+    #[serde(rename = "type")]
+    result_type: Option<String>,
+    page: Option<String>,
+    location: Option<String>,
+    prep_type: Option<String>,
+}
+
+impl ApiSearchQuery {
+    /// The result type, 1-based page and filters, or what's wrong with them
+    fn validate(&self) -> std::result::Result<(&'static str, usize, SearchFilters), Vec<ValidationError>> {
+        let mut errors = Vec::new();
+
+        let requested = self.result_type.as_deref().map(str::trim).filter(|t| !t.is_empty()).unwrap_or("all");
+        let result_type = SEARCH_API_TYPES.into_iter().find(|t| *t == requested).unwrap_or_else(|| {
+            errors.push(ValidationError::new(
+                "type",
+                "invalid_choice",
+                format!("Type must be one of: {}", SEARCH_API_TYPES.join(", ")),
+            ));
+            "all"
+        });
+
+        let page = match self.page.as_deref().map(str::trim).filter(|p| !p.is_empty()) {
+            None => 1,
+            Some(page) => match page.parse::<usize>() {
+                Ok(page) if page >= 1 => page,
+                _ => {
+                    errors.push(ValidationError::new("page", "invalid_format", "Page must be a whole number from 1"));
+                    1
+                }
+            },
+        };
+
+        let filters = search_filters(self.location.as_deref(), self.prep_type.as_deref()).unwrap_or_else(|error| {
+            errors.push(error);
+            SearchFilters::default()
+        });
+
+        if errors.is_empty() {
+            Ok((result_type, page, filters))
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// One page of search results
+fn page_of<T>(results: &[T], page: usize) -> &[T] {
+    let start = (page - 1).saturating_mul(SEARCH_API_PAGE_SIZE).min(results.len());
+    let end = start.saturating_add(SEARCH_API_PAGE_SIZE).min(results.len());
+    &results[start..end]
+}
+
+/// GET /api/v1/search?q=&type=&page=&location=&prep_type= - Search results as JSON, for scripts
+///
+/// Matches exactly what the search page finds, in the same order: an empty `q`
+/// lists everything (within any filters) and `%` or `_` in it are literal. Products
+/// and preparations are paged side by side, and a type that wasn't asked for comes
+/// back empty. Like the search page it needs no login and lists drafts with their `status`.
+pub async fn api_search(
+    pool: web::Data<sqlx::PgPool>,
+    query: web::Query<ApiSearchQuery>,
+) -> std::result::Result<HttpResponse, ApiError> {
+    let (result_type, page, filters) = query.validate().map_err(ApiError::validation)?;
+    let search_term = query.q.as_deref().unwrap_or("").trim();
+
+    let products = if result_type == "preparations" {
+        Vec::new()
+    } else {
+        search_products(pool.get_ref(), search_term, &filters).await?
+    };
+    let preparations = if result_type == "products" {
+        Vec::new()
+    } else {
+        search_preparations(pool.get_ref(), search_term, &filters).await?
+    };
+
+    let total_pages = products.len().max(preparations.len()).div_ceil(SEARCH_API_PAGE_SIZE);
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "query": search_term,
+        "type": result_type,
+        "filters": {
+            "location": filters.location,
+            "prep_type": filters.prep_type,
+        },
+        "page": page,
+        "per_page": SEARCH_API_PAGE_SIZE,
+        "total_pages": total_pages,
+        "total": {
+            "products": products.len(),
+            "preparations": preparations.len(),
+        },
+        "products": page_of(&products, page),
+        "preparations": page_of(&preparations, page),
+    })))
+}
+
+/// Query parameters for searching within a preparation's steps
+#[derive(Debug, serde::Deserialize)]
+pub struct StepSearchQuery {
+    q: Option<String>,
+}
+
+/// GET /preparation/{id}/search - Steps of a single preparation matching a term, as JSON
+pub async fn search_preparation_steps(
+    pool: web::Data<sqlx::PgPool>,
+    preparation_id: web::Path<Uuid>,
+    query: web::Query<StepSearchQuery>,
+) -> Result<HttpResponse> {
+    let search_term = query.q.as_deref().unwrap_or("").trim();
+
+    if search_term.is_empty() {
+        return Ok(HttpResponse::Ok().json(Vec::<PreparationStep>::new()));
+    }
+
+    let steps = PreparationStep::search_within(pool.get_ref(), *preparation_id, search_term)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error searching steps: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to search preparation steps")
+        })?;
+
+    let tz = utils::venue_timezone();
+    let steps: Vec<_> = steps.into_iter().map(|step| WithLocalTimes::new(step, tz)).collect();
+    Ok(HttpResponse::Ok().json(steps))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Preparation, Product, PREP_STATUS_DRAFT};
+
+    #[actix_web::test]
+    async fn test_api_search_rejects_bad_type_and_page() {
+        use actix_web::test::{call_service, init_service, read_body_json, TestRequest};
+        use actix_web::App;
+
+        // Validation fails before the database is touched
+        let pool = sqlx::PgPool::connect_lazy("postgres://localhost/unused").expect("Failed to build pool");
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(pool))
+                .route("/api/v1/search", web::get().to(api_search)),
+        )
+        .await;
+
+        let request = TestRequest::get()
+            .uri("/api/v1/search?q=carrot&type=recipes&page=0&prep_type=pasta")
+            .to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(response.status(), 400);
+        let body: serde_json::Value = read_body_json(response).await;
+        assert_eq!(body["error"]["code"], "VALIDATION_FAILED");
+        let fields: Vec<_> = body["error"]["details"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|d| (d["field"].as_str().unwrap(), d["code"].as_str().unwrap()))
+            .collect();
+        assert_eq!(
+            fields,
+            [("type", "invalid_choice"), ("page", "invalid_format"), ("prep_type", "invalid_choice")]
+        );
+    }
+
+    #[test]
+    fn test_search_filters_and_chip_urls() {
+        assert_eq!(search_filters(Some("  "), None).unwrap(), SearchFilters::default());
+        let filters = search_filters(Some(" Coolroom 2 "), Some("Veg")).unwrap();
+        assert_eq!(filters.location.as_deref(), Some("Coolroom 2"));
+        assert_eq!(filters.prep_type.as_deref(), Some("veg"));
+        assert_eq!(search_filters(None, Some("pasta")).unwrap_err().field, "prep_type");
+
+        assert_eq!(search_url("", &SearchFilters::default()), "/search");
+        assert_eq!(search_url("a&b", &filters), "/search?q=a%26b&location=Coolroom%202&prep_type=veg");
+
+        // Each chip drops only its own filter and keeps the term
+        let chips = filter_chips("", &filters);
+        let removed: Vec<_> = chips.iter().map(|c| (c.label, c.remove_url.as_str())).collect();
+        assert_eq!(removed, [("Location", "/search?prep_type=veg"), ("Type", "/search?location=Coolroom%202")]);
+    }
+
+    #[actix_web::test]
+    async fn test_api_search_response_shape() {
+        use actix_web::test::{call_service, init_service, read_body_json, TestRequest};
+        use actix_web::App;
+
+        let Ok(url) = std::env::var("TEST_DATABASE_URL") else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+        let pool = sqlx::PgPool::connect(&url).await.expect("Failed to connect to TEST_DATABASE_URL");
+
+        // The handler reads through the pool, so rows are committed and removed afterwards
+        let product = Product::create(&pool, "Quokka Dairy", "Quokka Cream 35%", "Coolroom", None, None, "")
+            .await
+            .expect("Failed to create product");
+        let other_product = Product::create(&pool, "Quokka Dairy", "Quokka Cream 350", "Coolroom", None, None, "")
+            .await
+            .expect("Failed to create product");
+        let preparation = Preparation::create(
+            &pool, "Quokka Crumble", "veg", "both", "Bench", None, None, None, None, None, "en", None, PREP_STATUS_DRAFT,
+        )
+        .await
+        .expect("Failed to create preparation");
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .route("/api/v1/search", web::get().to(api_search)),
+        )
+        .await;
+
+        let request = TestRequest::get().uri("/api/v1/search?q=%20quokka%20").to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = read_body_json(response).await;
+        let mut keys: Vec<_> = body.as_object().unwrap().keys().map(String::as_str).collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            ["filters", "page", "per_page", "preparations", "products", "query", "total", "total_pages", "type"]
+        );
+        assert_eq!(body["query"], "quokka");
+        assert_eq!(body["type"], "all");
+        assert_eq!(body["filters"], serde_json::json!({ "location": null, "prep_type": null }));
+        assert_eq!(body["page"], 1);
+        assert_eq!(body["per_page"], SEARCH_API_PAGE_SIZE);
+        assert_eq!(body["total_pages"], 1);
+        assert_eq!(body["total"], serde_json::json!({ "products": 2, "preparations": 1 }));
+
+        // Summary fields only: no steps or full description
+        let mut product_keys: Vec<_> = body["products"][0].as_object().unwrap().keys().map(String::as_str).collect();
+        product_keys.sort();
+        assert_eq!(
+            product_keys,
+            ["description_preview", "id", "location", "picture_url", "product_name", "supplier_name", "webp_url"]
+        );
+        let found_preparation = &body["preparations"][0];
+        assert_eq!(found_preparation["id"], preparation.id.to_string());
+        assert_eq!(found_preparation["status"], PREP_STATUS_DRAFT);
+        assert!(found_preparation.get("steps").is_none());
+
+        // `%` is literal, and type limits which kind is searched
+        let request = TestRequest::get().uri("/api/v1/search?q=quokka%20cream%2035%25&type=products").to_request();
+        let body: serde_json::Value = read_body_json(call_service(&app, request).await).await;
+        assert_eq!(body["total"], serde_json::json!({ "products": 1, "preparations": 0 }));
+        assert_eq!(body["products"][0]["id"], product.id.to_string());
+        assert_eq!(body["preparations"], serde_json::json!([]));
+
+        // Past the last page the arrays are empty, but the totals still say what there is
+        let request = TestRequest::get().uri("/api/v1/search?q=quokka&page=2").to_request();
+        let body: serde_json::Value = read_body_json(call_service(&app, request).await).await;
+        assert_eq!(body["products"], serde_json::json!([]));
+        assert_eq!(body["total"]["products"], 2);
+
+        sqlx::query("DELETE FROM products WHERE id = ANY($1)")
+            .bind(vec![product.id, other_product.id])
+            .execute(&pool)
+            .await
+            .expect("Failed to clean up products");
+        sqlx::query("DELETE FROM preparations WHERE id = $1")
+            .bind(preparation.id)
+            .execute(&pool)
+            .await
+            .expect("Failed to clean up preparation");
+    }
+}
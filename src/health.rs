@@ -0,0 +1,174 @@
+use crate::storage::StorageError;
+use crate::utils;
+use aws_sdk_s3::config::retry::RetryConfig;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::Client as S3Client;
+use serde::Serialize;
+use std::future::Future;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// How long each check gets before it counts as failed
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// S3 key the storage check overwrites, so checks don't pile up objects
+const S3_MARKER_KEY: &str = "health/deep-check.txt";
+
+/// How one component fared in a deep health check
+#[derive(Debug, Clone, Serialize)]
+pub struct ComponentHealth {
+    /// `ok` or `failing`
+    pub status: &'static str,
+    pub latency_ms: u64,
+    /// What went wrong, when it failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ComponentHealth {
+    pub fn is_ok(&self) -> bool {
+        self.status == "ok"
+    }
+}
+
+/// Where uploads are written to, as checked by `check_storage`
+#[derive(Debug, Clone, Serialize)]
+pub struct StorageHealth {
+    /// `s3` or `local`
+    pub backend: &'static str,
+    #[serde(flatten)]
+    pub health: ComponentHealth,
+}
+
+/// `GET /api/health/deep` body: whether the database and upload storage accept writes
+#[derive(Debug, Clone, Serialize)]
+pub struct DeepHealth {
+    /// `ok` when every component is, otherwise `failing`
+    pub status: &'static str,
+    pub database: ComponentHealth,
+    pub storage: StorageHealth,
+}
+
+impl DeepHealth {
+    pub fn is_ok(&self) -> bool {
+        self.database.is_ok() && self.storage.health.is_ok()
+    }
+}
+
+/// Run `check` with `CHECK_TIMEOUT`, timing it
+async fn timed<F: Future<Output = Result<(), String>>>(check: F) -> ComponentHealth {
+    let started = Instant::now();
+    let outcome = match tokio::time::timeout(CHECK_TIMEOUT, check).await {
+        Ok(outcome) => outcome,
+        Err(_) => Err(format!("Timed out after {}s", CHECK_TIMEOUT.as_secs())),
+    };
+    let latency_ms = started.elapsed().as_millis().try_into().unwrap_or(u64::MAX);
+    match outcome {
+        Ok(()) => ComponentHealth { status: "ok", latency_ms, error: None },
+        Err(error) => ComponentHealth { status: "failing", latency_ms, error: Some(error) },
+    }
+}
+
+/// Insert a throwaway location and roll it back, proving the app can write, not just read
+async fn check_database(pool: &sqlx::PgPool) -> Result<(), String> {
+    let mut tx = pool.begin().await.map_err(|e| format!("Failed to start a transaction: {}", e))?;
+    sqlx::query("INSERT INTO locations (name) VALUES ('Health check ' || uuid_generate_v4())")
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| format!("Failed to write: {}", e))?;
+    tx.rollback().await.map_err(|e| format!("Failed to roll back: {}", e))
+}
+
+/// Overwrite a tiny marker object in the bucket, once and without retries
+async fn check_s3(client: &S3Client, bucket: &str) -> Result<(), String> {
+    client
+        .put_object()
+        .bucket(bucket)
+        .key(S3_MARKER_KEY)
+        .body(ByteStream::from_static(b"ok\n"))
+        .content_type("text/plain")
+        .customize()
+        .config_override(aws_sdk_s3::config::Builder::new().retry_config(RetryConfig::disabled()))
+        .send()
+        .await
+        .map(|_| ())
+        .map_err(|e| StorageError::from_sdk(&e).to_string())
+}
+
+/// Write and remove a marker file in the upload directory
+async fn check_upload_dir(dir: &Path) -> Result<(), String> {
+    let path = dir.join(format!(".health-check-{}", uuid::Uuid::new_v4()));
+    tokio::fs::write(&path, b"ok\n")
+        .await
+        .map_err(|e| format!("Failed to write to {:?}: {}", dir, e))?;
+    tokio::fs::remove_file(&path)
+        .await
+        .map_err(|e| format!("Failed to remove {:?}: {}", path, e))
+}
+
+/// Check uploads can be stored where `S3_ENABLED` sends them: the bucket, or `UPLOAD_DIR`
+async fn check_storage(s3_client: Option<&S3Client>) -> StorageHealth {
+    if utils::env_flag("S3_ENABLED") {
+        let health = match s3_client {
+            Some(client) => {
+                let bucket = std::env::var("S3_BUCKET_NAME").unwrap_or_else(|_| "kitchen-hand-guide".to_string());
+                timed(check_s3(client, &bucket)).await
+            }
+            None => ComponentHealth {
+                status: "failing",
+                latency_ms: 0,
+                error: Some("No S3 client on this server".to_string()),
+            },
+        };
+        StorageHealth { backend: "s3", health }
+    } else {
+        StorageHealth { backend: "local", health: timed(check_upload_dir(&utils::upload_dir())).await }
+    }
+}
+
+/// Check the database and upload storage in parallel with real (harmless) writes
+pub async fn deep_check(pool: &sqlx::PgPool, s3_client: Option<&S3Client>) -> DeepHealth {
+    let (database, storage) = tokio::join!(timed(check_database(pool)), check_storage(s3_client));
+    let mut health = DeepHealth { status: "ok", database, storage };
+    if !health.is_ok() {
+        health.status = "failing";
+    }
+    health
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_upload_dir_check_leaves_nothing_behind() {
+        let dir = std::env::temp_dir().join(format!("khg-health-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).expect("Failed to create temp dir");
+
+        let health = timed(check_upload_dir(&dir)).await;
+        assert!(health.is_ok(), "{:?}", health.error);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+        std::fs::remove_dir(&dir).expect("Failed to remove temp dir");
+        let health = timed(check_upload_dir(&dir)).await;
+        assert_eq!(health.status, "failing");
+        assert!(health.error.unwrap().starts_with("Failed to write"));
+    }
+
+    #[tokio::test]
+    async fn test_database_check_leaves_nothing_behind() {
+        let Ok(url) = std::env::var("TEST_DATABASE_URL") else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+        let pool = sqlx::PgPool::connect(&url).await.expect("Failed to connect to TEST_DATABASE_URL");
+
+        let health = timed(check_database(&pool)).await;
+        assert!(health.is_ok(), "{:?}", health.error);
+        let (left,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM locations WHERE name LIKE 'Health check %'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(left, 0);
+    }
+}
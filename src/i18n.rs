@@ -0,0 +1,279 @@
+use crate::errors::ValidationError;
+use std::collections::HashMap;
+use std::future::{ready, Ready};
+use std::sync::OnceLock;
+
+/// Locales with a catalog in `locales/`, English first
+pub const SUPPORTED_LOCALES: [&str; 2] = ["en", "es"];
+
+/// Locale used when neither the cookie nor Accept-Language names a supported one
+pub const DEFAULT_LOCALE: &str = "en";
+
+/// Cookie holding the locale a visitor picked, overriding Accept-Language
+pub const LANG_COOKIE: &str = "lang";
+
+/// Each supported locale's name in its own language, for language pickers
+pub const LOCALE_NAMES: [(&str, &str); 2] = [("en", "English"), ("es", "Español")];
+
+/// Catalog sources, compiled in so a deploy can't ship without them
+const CATALOG_SOURCES: [(&str, &str); 2] = [
+    ("en", include_str!("../locales/en.txt")),
+    ("es", include_str!("../locales/es.txt")),
+];
+
+type Catalog = HashMap<&'static str, &'static str>;
+
+static CATALOGS: OnceLock<HashMap<&'static str, Catalog>> = OnceLock::new();
+
+/// Parse a catalog: one `key = value` per line, `#` starts a comment line
+fn parse_catalog(source: &'static str) -> Result<Catalog, String> {
+    let mut catalog = Catalog::new();
+    for (index, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("line {}: expected `key = value`", index + 1))?;
+        let (key, value) = (key.trim(), value.trim());
+        if key.is_empty() || value.is_empty() {
+            return Err(format!("line {}: empty key or value", index + 1));
+        }
+        if catalog.insert(key, value).is_some() {
+            return Err(format!("line {}: duplicate key {}", index + 1, key));
+        }
+    }
+    Ok(catalog)
+}
+
+fn load_catalogs() -> Result<HashMap<&'static str, Catalog>, String> {
+    CATALOG_SOURCES
+        .iter()
+        .map(|(locale, source)| {
+            parse_catalog(source)
+                .map(|catalog| (*locale, catalog))
+                .map_err(|e| format!("locales/{}.txt {}", locale, e))
+        })
+        .collect()
+}
+
+fn catalogs() -> &'static HashMap<&'static str, Catalog> {
+    CATALOGS.get_or_init(|| load_catalogs().unwrap_or_else(|e| panic!("Invalid message catalog: {}", e)))
+}
+
+/// Load the message catalogs, so a malformed one stops the server at startup
+pub fn init() -> Result<(), String> {
+    let loaded = load_catalogs()?;
+    let _ = CATALOGS.set(loaded);
+    Ok(())
+}
+
+/// Pick the locale for a request
+///
+/// A supported `lang` cookie wins; otherwise the Accept-Language entries are
+/// tried by quality, matching on the primary subtag (`es-AR` gives `es`).
+pub fn negotiate(cookie: Option<&str>, accept_language: Option<&str>) -> &'static str {
+    cookie
+        .and_then(supported_locale)
+        .or_else(|| accepted_locale(accept_language))
+        .unwrap_or(DEFAULT_LOCALE)
+}
+
+/// The client's most preferred supported locale in an Accept-Language header
+fn accepted_locale(accept_language: Option<&str>) -> Option<&'static str> {
+    let mut ranges: Vec<(&str, f32)> = accept_language
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';');
+            let tag = parts.next()?.trim();
+            let quality = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+            (!tag.is_empty() && quality > 0.0).then_some((tag, quality))
+        })
+        .collect();
+    // Stable, so equal qualities keep the client's order
+    ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    ranges.into_iter().find_map(|(tag, _)| supported_locale(tag))
+}
+
+/// The supported locale for a language tag, if any
+pub fn supported_locale(tag: &str) -> Option<&'static str> {
+    let primary = tag.split(['-', '_']).next()?.trim().to_ascii_lowercase();
+    SUPPORTED_LOCALES.into_iter().find(|locale| *locale == primary)
+}
+
+/// The name of a supported locale in its own language, or the code itself
+pub fn locale_name(locale: &str) -> &str {
+    LOCALE_NAMES.iter().find(|(code, _)| *code == locale).map_or(locale, |(_, name)| name)
+}
+
+/// Language preparations are written in unless another is picked (`PRIMARY_LANG`)
+pub fn primary_lang() -> &'static str {
+    std::env::var("PRIMARY_LANG")
+        .ok()
+        .and_then(|lang| supported_locale(&lang))
+        .unwrap_or(DEFAULT_LOCALE)
+}
+
+/// Language a new preparation defaults to: the client's preferred one from
+/// Accept-Language, or `PRIMARY_LANG` when it names none we support
+///
+/// Unlike the UI locale, the `lang` cookie isn't consulted; it picks the
+/// language the guide is read in, not the one recipes are written in.
+pub fn content_lang(accept_language: Option<&str>) -> &'static str {
+    accepted_locale(accept_language).unwrap_or_else(primary_lang)
+}
+
+/// `content_lang` for a request's Accept-Language header
+pub fn content_lang_for_request(req: &actix_web::HttpRequest) -> &'static str {
+    content_lang(
+        req.headers()
+            .get(actix_web::http::header::ACCEPT_LANGUAGE)
+            .and_then(|h| h.to_str().ok()),
+    )
+}
+
+/// Looks up UI strings in one locale's catalog
+///
+/// Keys missing from the catalog fall back to English, then to the key itself,
+/// so an untranslated string shows up in English rather than breaking a page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Translator {
+    locale: &'static str,
+}
+
+impl Translator {
+    /// Translator for a locale; unsupported ones get the default
+    pub fn new(locale: &str) -> Translator {
+        Translator {
+            locale: supported_locale(locale).unwrap_or(DEFAULT_LOCALE),
+        }
+    }
+
+    /// Translator for a request, from its `lang` cookie and Accept-Language header
+    pub fn for_request(req: &actix_web::HttpRequest) -> Translator {
+        let cookie = req.cookie(LANG_COOKIE);
+        let accept_language = req
+            .headers()
+            .get(actix_web::http::header::ACCEPT_LANGUAGE)
+            .and_then(|h| h.to_str().ok());
+        Translator::new(negotiate(cookie.as_ref().map(|c| c.value()), accept_language))
+    }
+
+    pub fn locale(&self) -> &'static str {
+        self.locale
+    }
+
+    fn lookup(&self, key: &str) -> Option<&'static str> {
+        let catalogs = catalogs();
+        catalogs
+            .get(self.locale)
+            .and_then(|catalog| catalog.get(key))
+            .or_else(|| catalogs.get(DEFAULT_LOCALE).and_then(|catalog| catalog.get(key)))
+            .copied()
+    }
+
+    /// The message for `key`
+    pub fn t(&self, key: &str) -> String {
+        self.lookup(key).unwrap_or(key).to_string()
+    }
+
+    /// The message for `key` with each `{name}` replaced by its value
+    pub fn t_with(&self, key: &str, args: &[(&str, &str)]) -> String {
+        args.iter().fold(self.t(key), |message, (name, value)| {
+            message.replace(&format!("{{{}}}", name), value)
+        })
+    }
+
+    /// A validation error in this locale
+    ///
+    /// Catalogs key these as `validation-<field>-<code>`; errors without a
+    /// translation (including every English one) keep the validator's message.
+    pub fn validation_message(&self, error: &ValidationError) -> String {
+        let key = format!("validation-{}-{}", error.field, error.code);
+        match self.lookup(&key) {
+            Some(message) => message.to_string(),
+            None => error.message.clone(),
+        }
+    }
+
+    pub fn validation_messages(&self, errors: &[ValidationError]) -> Vec<String> {
+        errors.iter().map(|e| self.validation_message(e)).collect()
+    }
+}
+
+/// Use the translator the Localization middleware picked, or negotiate one
+/// for routes outside it (e.g. in tests)
+impl actix_web::FromRequest for Translator {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &actix_web::HttpRequest, _payload: &mut actix_web::dev::Payload) -> Self::Future {
+        use actix_web::HttpMessage;
+        let translator = req.extensions().get::<Translator>().copied();
+        ready(Ok(translator.unwrap_or_else(|| Translator::for_request(req))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catalogs_parse() {
+        init().expect("Catalogs should parse");
+        assert!(parse_catalog("a = b\n# note\n\nc = {x} d").is_ok());
+        assert!(parse_catalog("no equals sign").is_err());
+        assert!(parse_catalog("a = b\na = c").is_err());
+
+        // Every translated key must exist in English, the fallback
+        let english = &catalogs()["en"];
+        for (locale, catalog) in catalogs() {
+            for key in catalog.keys().filter(|k| !k.starts_with("validation-")) {
+                assert!(english.contains_key(key), "{} has {} but en does not", locale, key);
+            }
+        }
+    }
+
+    #[test]
+    fn test_negotiate_locale() {
+        assert_eq!(negotiate(None, None), "en");
+        assert_eq!(negotiate(None, Some("es-AR,es;q=0.9,en;q=0.8")), "es");
+        assert_eq!(negotiate(None, Some("fr-FR, en;q=0.5, es;q=0.7")), "es");
+        assert_eq!(negotiate(None, Some("es;q=0, de")), "en");
+        assert_eq!(negotiate(Some("en"), Some("es")), "en");
+        assert_eq!(negotiate(Some("xx"), Some("es")), "es");
+    }
+
+    #[test]
+    fn test_content_lang_from_accept_language() {
+        assert_eq!(content_lang(Some("es-MX,es;q=0.9")), "es");
+        assert_eq!(content_lang(Some("fr, en;q=0.3")), "en");
+        // Nothing supported falls back to PRIMARY_LANG (English unless configured)
+        assert_eq!(content_lang(Some("fr, de;q=0.5")), primary_lang());
+        assert_eq!(content_lang(Some("es;q=0")), primary_lang());
+        assert_eq!(content_lang(None), primary_lang());
+    }
+
+    #[test]
+    fn test_translator_falls_back() {
+        let es = Translator::new("es");
+        assert_eq!(es.t("error-go-back"), "Volver");
+        assert_eq!(Translator::new("de").locale(), "en");
+        assert_eq!(es.t("no-such-key"), "no-such-key");
+        assert_eq!(
+            es.t_with("error-preparation-locked-by", &[("username", "sam")]),
+            "sam está editando esta preparación, así que tus cambios no se guardaron."
+        );
+
+        let error = ValidationError::new("email", "required", "Email cannot be empty");
+        assert_eq!(es.validation_message(&error), "El correo electrónico no puede estar vacío");
+        assert_eq!(Translator::new("en").validation_message(&error), "Email cannot be empty");
+        let untranslated = ValidationError::new("steps", "too_many", "Too many steps (70, the limit is 60)");
+        assert_eq!(es.validation_message(&untranslated), untranslated.message);
+    }
+}
@@ -1,14 +1,27 @@
 mod auth;
+mod backup;
+mod cache;
+mod cli;
 mod db;
+mod errors;
+mod events;
 mod handlers;
+mod health;
+mod i18n;
+mod media;
 mod middleware;
 mod models;
+mod pdf;
+mod recently_viewed;
+mod repo;
+mod routes;
+mod storage;
 mod utils;
 
-use actix_files as fs;
 use actix_web::{middleware as actix_middleware, web, App, HttpServer};
 use dotenv::dotenv;
 use std::env;
+use std::sync::Arc;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -18,6 +31,13 @@ async fn main() -> std::io::Result<()> {
     // Initialize logger
     env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
 
+    // Maintenance subcommands (e.g. export-users) run against the database and exit
+    let args: Vec<String> = env::args().skip(1).collect();
+    let command = cli::Command::parse(&args).unwrap_or_else(|e| {
+        eprintln!("{}\n\n{}", e, cli::USAGE);
+        std::process::exit(2);
+    });
+
     // Get configuration from environment
     let database_url = env::var("DATABASE_URL")
         .expect("DATABASE_URL must be set in .env file");
@@ -25,12 +45,18 @@ async fn main() -> std::io::Result<()> {
     let host = env::var("HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
     let port = env::var("PORT").unwrap_or_else(|_| "8080".to_string());
 
-    let upload_dir = env::var("UPLOAD_DIR")
-        .unwrap_or_else(|_| "./static/uploads".to_string());
+    // Bundled assets and uploads are served from separate directories, so uploads can
+    // live outside the source tree. A read-only mirror has no write routes at all.
+    let route_config = routes::RouteConfig::from_env();
+    if route_config.static_listing {
+        println!("STATIC_DIR_LISTING is set: /static directories can be browsed (development only)");
+    }
 
-    // Create upload directory if it doesn't exist
-    std::fs::create_dir_all(&upload_dir)
-        .expect("Failed to create upload directory");
+    // Create upload directory if it doesn't exist (a mirror never writes to it)
+    if !route_config.read_only {
+        std::fs::create_dir_all(&route_config.upload_dir)
+            .expect("Failed to create upload directory");
+    }
 
     // Create database connection pool
     println!("Connecting to database...");
@@ -45,85 +71,136 @@ async fn main() -> std::io::Result<()> {
 
     println!("Database connection successful!");
 
-    // Initialize S3 client
-    println!("Initializing AWS S3 client...");
-    let s3_client = utils::init_s3_client().await;
-    println!("AWS S3 client initialized!");
+    if let Some(command) = command {
+        if let Err(e) = cli::run(command, &pool).await {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    // First run: with no users yet, ADMIN_BOOTSTRAP_PASSWORD creates the initial admin
+    // (a mirror never writes, so it is ignored there)
+    let bootstrap_password = env::var("ADMIN_BOOTSTRAP_PASSWORD").unwrap_or_default();
+    if !bootstrap_password.is_empty() && !route_config.read_only {
+        match cli::bootstrap_admin(&pool, &bootstrap_password).await {
+            Ok(Some(username)) => {
+                println!("{}", "=".repeat(72));
+                println!("FIRST RUN: created the admin user '{}' from ADMIN_BOOTSTRAP_PASSWORD.", username);
+                println!("Log in, then remove ADMIN_BOOTSTRAP_PASSWORD from the environment.");
+                println!("{}", "=".repeat(72));
+            }
+            Ok(None) => println!("Users already exist, so ADMIN_BOOTSTRAP_PASSWORD was not used"),
+            Err(e) => eprintln!("ADMIN_BOOTSTRAP_PASSWORD ignored: {}", e),
+        }
+    }
+
+    // Initialize S3 client; a read-only mirror never uploads or deletes, and
+    // fetches bucket images for PDFs over HTTP
+    let s3_client = if route_config.read_only {
+        println!("READ_ONLY_MODE is set: serving a read-only mirror without S3 or uploads");
+        None
+    } else {
+        println!("Initializing AWS S3 client...");
+        let s3_client = utils::init_s3_client().await;
+        println!("AWS S3 client initialized!");
+        Some(s3_client)
+    };
+
+    // Security headers (CSP image sources follow the storage config)
+    let security_headers = middleware::SecurityHeadersConfig::from_env();
+
+    // Connection timeouts, so slow or idle clients can't hold connections open indefinitely
+    // (0 disables a timeout)
+    let client_request_timeout = utils::env_secs("CLIENT_REQUEST_TIMEOUT_SECS", 10);
+    let client_disconnect_timeout = utils::env_secs("CLIENT_DISCONNECT_TIMEOUT_SECS", 5);
+    let keep_alive = utils::env_secs("KEEP_ALIVE_SECS", 15);
+
+    // Timestamps are stored in UTC and shown in the venue's timezone; an unknown
+    // VENUE_TIMEZONE is reported here and falls back to UTC
+    println!("Showing times in {}", utils::venue_timezone());
+
+    // Message catalogs are compiled in; check they parse before taking requests
+    i18n::init().expect("Invalid message catalog");
+    println!("UI languages: {}", i18n::SUPPORTED_LOCALES.join(", "));
+
+    // The homepage product list is cached briefly (HOMEPAGE_CACHE_SECS, 0 disables);
+    // product changes clear it through the events below
+    let product_list_cache = cache::ProductListCache::from_env();
+    println!("Caching the homepage product list for {}s", product_list_cache.ttl().as_secs());
+
+    // Side effects of writes, like the audit trail, subscribe here rather than
+    // being called from each handler
+    let events = events::EventDispatcher::new()
+        .subscribe(events::AuditLog)
+        .subscribe(product_list_cache.clone());
+
+    // Database backups to the bucket: on demand from /admin/backup, and nightly
+    // when BACKUP_NIGHTLY is set
+    let backup_config = backup::BackupConfig::from_env(&database_url);
+    if let (true, Some(s3_client)) = (backup_config.nightly, &s3_client) {
+        println!(
+            "Backing up the database nightly at {:02}:00 to s3://{}/{}",
+            backup_config.hour, backup_config.bucket, backup_config.prefix
+        );
+        backup::spawn_nightly(backup_config.clone(), s3_client.clone(), events.clone());
+    }
+
+    // Photo drafts from bulk uploads that nobody finished are deleted after 30 days
+    if let Some(s3_client) = &s3_client {
+        storage::spawn_draft_cleanup(pool.clone(), s3_client.clone());
+    }
+
+    // Handlers ported to the repository traits reach Postgres through this
+    let product_repo: Arc<dyn repo::ProductRepo> = Arc::new(repo::PgRepo::new(pool.clone()));
+
+    // X-Forwarded-For/-Proto/-Host are only believed from these proxies (TRUSTED_PROXIES)
+    let trusted_proxies = middleware::TrustedProxies::from_env().expect("Invalid TRUSTED_PROXIES");
+    if !trusted_proxies.is_empty() {
+        println!("Trusting forwarded headers from {}", env::var("TRUSTED_PROXIES").unwrap_or_default());
+    }
 
     let server_address = format!("{}:{}", host, port);
     println!("Starting server at http://{}", server_address);
 
     // Start HTTP server
     HttpServer::new(move || {
-        App::new()
-            // Add logger middleware
-            .wrap(actix_middleware::Logger::default())
+        let mut app = App::new();
+        // Add S3 client to app state
+        if let Some(s3_client) = &s3_client {
+            app = app.app_data(web::Data::new(s3_client.clone()));
+        }
+        app
+            // Add logger middleware, logging the client ForwardedHeaders found
+            .wrap(
+                actix_middleware::Logger::new(
+                    "%{client_ip}xi \"%r\" %s %b \"%{Referer}i\" \"%{User-Agent}i\" %T",
+                )
+                .custom_request_replace("client_ip", middleware::logged_client_ip),
+            )
+            // Real client address and scheme, from X-Forwarded-* behind a trusted proxy
+            .wrap(middleware::ForwardedHeaders::new(trusted_proxies.clone()))
+            // CSP, framing, sniffing, referrer and (over TLS) HSTS headers on every response
+            .wrap(middleware::SecurityHeaders::new(&security_headers))
+            // Pick each request's language from the lang cookie or Accept-Language
+            .wrap(middleware::Localization)
             // Configure payload size for large file uploads (20MB)
             .app_data(actix_web::web::PayloadConfig::default()
                 .limit(20 * 1024 * 1024)) // 20MB limit
+            // API clients get JSON error envelopes for bad bodies and ids
+            .app_data(web::JsonConfig::default().error_handler(errors::json_error_handler))
+            .app_data(web::PathConfig::default().error_handler(errors::path_error_handler))
             // Add database pool to app state
             .app_data(web::Data::new(pool.clone()))
-            // Add S3 client to app state
-            .app_data(web::Data::new(s3_client.clone()))
-            // Public Routes
-            .route("/", web::get().to(handlers::index))
-            .route("/search", web::get().to(handlers::search))
-            .route("/preparations", web::get().to(handlers::preparations_index))
-            // Authentication Routes
-            .route("/login", web::get().to(handlers::login_form))
-            .route("/login", web::post().to(handlers::login))
-            .route("/register", web::get().to(handlers::register_form))
-            .route("/register", web::post().to(handlers::register))
-            .route("/logout", web::get().to(handlers::logout))
-            // Error Pages
-            .route("/401", web::get().to(handlers::error_401))
-            // Serve static files
-            .service(fs::Files::new("/static", "./static").show_files_listing())
-            // Protected Routes - Require Authentication (specific routes first to avoid conflicts)
-            .service(
-                web::resource("/product/new")
-                    .route(web::get().to(handlers::new_product_form))
-                    .wrap(middleware::Authentication)
-            )
-            .service(
-                web::resource("/product")
-                    .route(web::post().to(handlers::create_product))
-                    .wrap(middleware::Authentication)
-            )
-            .service(
-                web::resource("/product/{id}/edit")
-                    .route(web::get().to(handlers::edit_product_form))
-                    .wrap(middleware::Authentication)
-            )
-            .service(
-                web::resource("/product/{id}/update")
-                    .route(web::post().to(handlers::update_product))
-                    .wrap(middleware::Authentication)
-            )
-            .service(
-                web::resource("/preparation/new")
-                    .route(web::get().to(handlers::new_preparation_form))
-                    .wrap(middleware::Authentication)
-            )
-            .service(
-                web::resource("/preparation")
-                    .route(web::post().to(handlers::create_preparation))
-                    .wrap(middleware::Authentication)
-            )
-            .service(
-                web::resource("/preparation/{id}/edit")
-                    .route(web::get().to(handlers::edit_preparation_form))
-                    .wrap(middleware::Authentication)
-            )
-            .service(
-                web::resource("/preparation/{id}/update")
-                    .route(web::post().to(handlers::update_preparation))
-                    .wrap(middleware::Authentication)
-            )
-            // Public detail routes (accessible without authentication, MUST come after specific routes)
-            .route("/product/{id}", web::get().to(handlers::product_detail))
-            .route("/preparation/{preparation_id}", web::get().to(handlers::preparation_detail))
+            .app_data(web::Data::from(product_repo.clone()))
+            .app_data(web::Data::new(backup_config.clone()))
+            .app_data(web::Data::new(events.clone()))
+            .app_data(web::Data::new(product_list_cache.clone()))
+            .configure(|cfg| routes::configure(cfg, &route_config))
     })
+    .client_request_timeout(client_request_timeout)
+    .client_disconnect_timeout(client_disconnect_timeout)
+    .keep_alive(keep_alive)
     .bind(&server_address)?
     .run()
     .await
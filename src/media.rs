@@ -0,0 +1,280 @@
+use crate::utils::{self, ImageKind};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+
+/// Widths `?w=` may ask for: phones, tablets, desktops and the wall display
+pub const VARIANT_WIDTHS: [u32; 4] = [200, 400, 800, 1600];
+
+/// JPEG quality of variants for browsers without WebP (0-100)
+const JPEG_QUALITY: u8 = 80;
+
+/// Directory resized variants are cached in (`IMAGE_VARIANT_DIR`, default `./cache/variants`)
+///
+/// It must not be under `STATIC_DIR`, or the cache would be served as it is.
+pub fn variant_dir() -> PathBuf {
+    PathBuf::from(std::env::var("IMAGE_VARIANT_DIR").unwrap_or_else(|_| "./cache/variants".to_string()))
+}
+
+/// The width from a `?w=` value, if it is one of `VARIANT_WIDTHS`
+pub fn parse_width(value: &str) -> Option<u32> {
+    let width = value.trim().parse::<u32>().ok()?;
+    VARIANT_WIDTHS.contains(&width).then_some(width)
+}
+
+/// What a variant of `source` is encoded as: WebP when the browser takes it,
+/// otherwise the source's own format (PNG for WebP sources, which may be transparent)
+///
+/// None for sources that can't be resized here (AVIF, or anything unknown).
+pub fn variant_kind(source: ImageKind, accepts_webp: bool) -> Option<ImageKind> {
+    match source {
+        ImageKind::Jpeg | ImageKind::Png | ImageKind::Webp if accepts_webp => Some(ImageKind::Webp),
+        ImageKind::Jpeg => Some(ImageKind::Jpeg),
+        ImageKind::Png | ImageKind::Webp => Some(ImageKind::Png),
+        ImageKind::Avif | ImageKind::Heic => None,
+    }
+}
+
+/// A resized image on disk, ready to serve
+#[derive(Debug)]
+pub struct Variant {
+    pub path: PathBuf,
+    pub kind: ImageKind,
+    /// Made by this request rather than found in the cache
+    pub generated: bool,
+}
+
+/// One lock per cache file, so a photo asked for by several screens at once is resized once
+///
+/// Entries are dropped once nobody holds their lock.
+fn generation_lock(path: &Path) -> Arc<tokio::sync::Mutex<()>> {
+    static LOCKS: OnceLock<Mutex<HashMap<PathBuf, Weak<tokio::sync::Mutex<()>>>>> = OnceLock::new();
+    let mut locks = LOCKS.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner());
+    locks.retain(|_, lock| lock.strong_count() > 0);
+    if let Some(lock) = locks.get(path).and_then(Weak::upgrade) {
+        return lock;
+    }
+    let lock = Arc::new(tokio::sync::Mutex::new(()));
+    locks.insert(path.to_path_buf(), Arc::downgrade(&lock));
+    lock
+}
+
+/// Whether `cached` exists and is at least as new as `source`, so a picture
+/// replaced in place gets fresh variants
+async fn is_fresh(cached: &Path, source: &Path) -> bool {
+    let modified = |path: &Path| {
+        let path = path.to_path_buf();
+        async move { tokio::fs::metadata(path).await.and_then(|m| m.modified()).ok() }
+    };
+    match (modified(cached).await, modified(source).await) {
+        (Some(cached), Some(source)) => cached >= source,
+        _ => false,
+    }
+}
+
+/// `source` at `width` (never wider than it already is), as `kind`, from `cache_dir`
+///
+/// The first request for a variant makes it while any others for the same one wait,
+/// then they all serve the cached file. Ok(None) when `source` can't be resized.
+pub async fn variant(source: &Path, cache_dir: &Path, width: u32, accepts_webp: bool) -> Result<Option<Variant>, String> {
+    let filename = source
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| format!("No filename in {:?}", source))?;
+    let Some(source_kind) = ImageKind::from_filename(filename) else {
+        return Ok(None);
+    };
+    let Some(kind) = variant_kind(source_kind, accepts_webp) else {
+        return Ok(None);
+    };
+    let path = cache_dir.join(format!("{}-w{}.{}", filename, width, kind.extension()));
+
+    if is_fresh(&path, source).await {
+        return Ok(Some(Variant { path, kind, generated: false }));
+    }
+    let lock = generation_lock(&path);
+    let _guard = lock.lock().await;
+    // Someone else may have made it while we waited
+    if is_fresh(&path, source).await {
+        return Ok(Some(Variant { path, kind, generated: false }));
+    }
+
+    let data = tokio::fs::read(source)
+        .await
+        .map_err(|e| format!("Failed to read {:?}: {}", source, e))?;
+    let resized = tokio::task::spawn_blocking(move || resize(&data, source_kind, width, kind))
+        .await
+        .map_err(|e| format!("Resizing {:?} panicked: {}", source, e))??;
+
+    // Write to a temporary name first, so nobody serves a half-written file
+    tokio::fs::create_dir_all(cache_dir)
+        .await
+        .map_err(|e| format!("Failed to create {:?}: {}", cache_dir, e))?;
+    let partial = cache_dir.join(format!(".{}.{}.partial", filename, uuid::Uuid::new_v4()));
+    tokio::fs::write(&partial, resized)
+        .await
+        .map_err(|e| format!("Failed to write {:?}: {}", partial, e))?;
+    if let Err(e) = tokio::fs::rename(&partial, &path).await {
+        let _ = tokio::fs::remove_file(&partial).await;
+        return Err(format!("Failed to move {:?} into place: {}", path, e));
+    }
+
+    Ok(Some(Variant { path, kind, generated: true }))
+}
+
+/// Decode `data`, scale it down to `width` keeping its shape, and encode it as `kind`
+fn resize(data: &[u8], source: ImageKind, width: u32, kind: ImageKind) -> Result<Vec<u8>, String> {
+    let decoded = match source {
+        ImageKind::Jpeg => image::load_from_memory_with_format(data, image::ImageFormat::Jpeg),
+        ImageKind::Png => image::load_from_memory_with_format(data, image::ImageFormat::Png),
+        ImageKind::Webp => return decode_webp(data).and_then(|decoded| encode(&scale(decoded, width), kind)),
+        ImageKind::Avif | ImageKind::Heic => return Err(format!("Can't resize {} images", source.extension())),
+    }
+    .map_err(|e| format!("Failed to decode {} for resizing: {}", source.extension(), e))?;
+
+    encode(&scale(decoded, width), kind)
+}
+
+/// A WebP image as pixels (the `image` crate is built without WebP)
+fn decode_webp(data: &[u8]) -> Result<image::DynamicImage, String> {
+    let decoded = webp::Decoder::new(data)
+        .decode()
+        .ok_or_else(|| "Failed to decode webp for resizing".to_string())?;
+    let (width, height, pixels) = (decoded.width(), decoded.height(), decoded.to_vec());
+    let image = if decoded.is_alpha() {
+        image::RgbaImage::from_raw(width, height, pixels).map(image::DynamicImage::ImageRgba8)
+    } else {
+        image::RgbImage::from_raw(width, height, pixels).map(image::DynamicImage::ImageRgb8)
+    };
+    image.ok_or_else(|| "Decoded webp has the wrong number of pixels".to_string())
+}
+
+/// `image` no wider than `width`; narrower images are left as they are
+fn scale(image: image::DynamicImage, width: u32) -> image::DynamicImage {
+    if image.width() <= width {
+        image
+    } else {
+        image.resize(width, u32::MAX, image::imageops::FilterType::CatmullRom)
+    }
+}
+
+fn encode(image: &image::DynamicImage, kind: ImageKind) -> Result<Vec<u8>, String> {
+    let mut encoded = Vec::new();
+    match kind {
+        ImageKind::Webp if image.color().has_alpha() => {
+            let rgba = image.to_rgba8();
+            encoded = webp::Encoder::from_rgba(&rgba, rgba.width(), rgba.height()).encode(utils::WEBP_QUALITY).to_vec();
+        }
+        ImageKind::Webp => {
+            let rgb = image.to_rgb8();
+            encoded = webp::Encoder::from_rgb(&rgb, rgb.width(), rgb.height()).encode(utils::WEBP_QUALITY).to_vec();
+        }
+        ImageKind::Jpeg => image::codecs::jpeg::JpegEncoder::new_with_quality(&mut encoded, JPEG_QUALITY)
+            .encode_image(&image.to_rgb8())
+            .map_err(|e| format!("Failed to encode jpg: {}", e))?,
+        ImageKind::Png => image
+            .write_to(&mut std::io::Cursor::new(&mut encoded), image::ImageFormat::Png)
+            .map_err(|e| format!("Failed to encode png: {}", e))?,
+        ImageKind::Avif | ImageKind::Heic => return Err(format!("Can't encode {} images", kind.extension())),
+    }
+    Ok(encoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory holding a `width` x `height` JPEG called `photo.jpg`
+    fn photo_dir(width: u32, height: u32) -> (PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("khg-variants-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).expect("Failed to create temp dir");
+        let source = dir.join("photo.jpg");
+        image::RgbImage::from_pixel(width, height, image::Rgb([200, 40, 40]))
+            .save(&source)
+            .expect("Failed to write test photo");
+        (dir, source)
+    }
+
+    #[test]
+    fn test_only_whitelisted_widths_are_accepted() {
+        assert_eq!(parse_width("400"), Some(400));
+        assert_eq!(parse_width(" 1600 "), Some(1600));
+        for value in ["", "0", "300", "401", "-200", "4000", "200px", "1e3"] {
+            assert_eq!(parse_width(value), None, "{:?}", value);
+        }
+
+        assert_eq!(variant_kind(ImageKind::Jpeg, true), Some(ImageKind::Webp));
+        assert_eq!(variant_kind(ImageKind::Jpeg, false), Some(ImageKind::Jpeg));
+        assert_eq!(variant_kind(ImageKind::Webp, false), Some(ImageKind::Png));
+        assert_eq!(variant_kind(ImageKind::Avif, true), None);
+    }
+
+    #[tokio::test]
+    async fn test_variant_is_made_once_then_served_from_cache() {
+        let (dir, source) = photo_dir(1000, 500);
+        let cache = dir.join("cache");
+
+        let first = variant(&source, &cache, 400, true).await.unwrap().expect("JPEG should resize");
+        assert!(first.generated);
+        assert_eq!(first.kind, ImageKind::Webp);
+        let decoded = webp::Decoder::new(&std::fs::read(&first.path).unwrap()).decode().expect("Variant should decode");
+        assert_eq!((decoded.width(), decoded.height()), (400, 200));
+
+        let again = variant(&source, &cache, 400, true).await.unwrap().unwrap();
+        assert!(!again.generated);
+        assert_eq!(again.path, first.path);
+
+        // Other formats and widths are cached separately; nothing is made wider
+        let jpeg = variant(&source, &cache, 1600, false).await.unwrap().unwrap();
+        assert!(jpeg.generated);
+        assert_eq!(image::open(&jpeg.path).unwrap().width(), 1000);
+
+        // A picture replaced in place gets a new variant
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        image::RgbImage::from_pixel(800, 800, image::Rgb([0, 0, 0])).save(&source).unwrap();
+        let replaced = variant(&source, &cache, 400, true).await.unwrap().unwrap();
+        assert!(replaced.generated);
+
+        std::fs::remove_dir_all(&dir).expect("Failed to clean up");
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_requests_generate_once() {
+        let (dir, source) = photo_dir(1200, 900);
+        let cache = dir.join("cache");
+
+        let requests: Vec<_> = (0..8)
+            .map(|_| {
+                let (source, cache) = (source.clone(), cache.clone());
+                tokio::spawn(async move { variant(&source, &cache, 800, true).await })
+            })
+            .collect();
+        let mut generated = 0;
+        for request in requests {
+            let variant = request.await.unwrap().unwrap().unwrap();
+            generated += usize::from(variant.generated);
+        }
+        assert_eq!(generated, 1);
+
+        // Only the finished variant is left, no partial files
+        let files: Vec<_> = std::fs::read_dir(&cache).unwrap().map(|e| e.unwrap().file_name()).collect();
+        assert_eq!(files, ["photo.jpg-w800.webp"]);
+
+        std::fs::remove_dir_all(&dir).expect("Failed to clean up");
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_sources_are_left_alone() {
+        let dir = std::env::temp_dir().join(format!("khg-variants-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("photo.avif");
+        std::fs::write(&source, b"not really avif").unwrap();
+        assert!(variant(&source, &dir.join("cache"), 400, true).await.unwrap().is_none());
+
+        let broken = dir.join("broken.png");
+        std::fs::write(&broken, b"not a png").unwrap();
+        assert!(variant(&broken, &dir.join("cache"), 400, true).await.is_err());
+
+        std::fs::remove_dir_all(&dir).expect("Failed to clean up");
+    }
+}
@@ -1,20 +1,34 @@
 use actix_web::{
     dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
-    Error, HttpMessage, HttpResponse,
+    http::header::{self, HeaderName, HeaderValue},
+    Error, HttpMessage, HttpResponse, ResponseError,
 };
 use askama::Template;
 use futures_util::future::LocalBoxFuture;
+use std::collections::HashMap;
 use std::future::{ready, Ready};
+use std::net::IpAddr;
 use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use crate::auth;
+use crate::errors::{is_api_path, ApiError};
+use crate::handlers::Error401Template;
+use crate::i18n::Translator;
+use crate::models::KioskToken;
 
-/// Template for 401 Unauthorized error page
-#[derive(Template)]
-#[template(path = "401.html")]
-struct Error401Template {}
+/// Kiosk device session, stored in request extensions when a device cookie is recognised
+#[derive(Debug, Clone)]
+pub struct KioskSession {
+    pub token_id: uuid::Uuid,
+    pub name: String,
+}
 
 /// Middleware for JWT authentication
+///
+/// Shared tablets holding a kiosk device cookie are let through as a limited
+/// pseudo-user for read-only requests; any other method is rejected with 403.
 pub struct Authentication;
 
 impl<S, B> Transform<S, ServiceRequest> for Authentication
@@ -61,13 +75,7 @@ where
                 .headers()
                 .get("Authorization")
                 .and_then(|h| h.to_str().ok())
-                .and_then(|h| {
-                    if h.starts_with("Bearer ") {
-                        Some(&h[7..])
-                    } else {
-                        None
-                    }
-                });
+                .and_then(|h| h.strip_prefix("Bearer "));
 
             // Extract token from cookie as fallback
             let token_from_cookie = req.cookie("auth_token").map(|c| c.value().to_string());
@@ -80,29 +88,92 @@ where
                         Ok(claims) => {
                             // Store claims in request extensions for handlers to access
                             req.extensions_mut().insert(claims);
-                            service.call(req).await
+                            return service.call(req).await;
                         }
                         Err(_) => {
-                            // Invalid token - return 401 with HTML template
-                            Err(render_401_error())
+                            // Invalid token - fall back to a kiosk device cookie, if any
                         }
                     }
                 }
                 None => {
-                    // No token provided - return 401 with HTML template
-                    Err(render_401_error())
+                    // No token provided - fall back to a kiosk device cookie, if any
+                }
+            }
+
+            match find_kiosk_session(&req).await? {
+                Some(session) => {
+                    // Kiosk devices have no edit rights
+                    if !req.method().is_safe() {
+                        if is_api_path(req.path()) {
+                            return Err(ApiError::forbidden("Kiosk devices are read-only").into());
+                        }
+                        return Err(render_403_error(&translator_for(&req)));
+                    }
+                    req.extensions_mut().insert(session);
+                    service.call(req).await
+                }
+                None => {
+                    // No valid user token or kiosk cookie - API clients get the JSON error
+                    // envelope, browsers the 401 HTML template
+                    Err(render_401_error(&req))
                 }
             }
         })
     }
 }
 
+/// Look up the kiosk device cookie, recording when the device was last seen
+async fn find_kiosk_session(req: &ServiceRequest) -> Result<Option<KioskSession>, Error> {
+    let token = match req.cookie(auth::KIOSK_COOKIE) {
+        Some(cookie) => cookie.value().to_string(),
+        None => return Ok(None),
+    };
+    let pool = match req.app_data::<actix_web::web::Data<sqlx::PgPool>>() {
+        Some(pool) => pool.get_ref().clone(),
+        None => return Ok(None),
+    };
+
+    let kiosk = KioskToken::find_active_by_hash(&pool, &auth::hash_kiosk_token(&token))
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to check kiosk token")
+        })?;
+
+    match kiosk {
+        Some(kiosk) => {
+            if let Err(e) = KioskToken::touch(&pool, kiosk.id).await {
+                eprintln!("Database error updating kiosk last seen: {:?}", e);
+            }
+            Ok(Some(KioskSession {
+                token_id: kiosk.id,
+                name: kiosk.name,
+            }))
+        }
+        None => Ok(None),
+    }
+}
+
 /// Extractor for authenticated user information
 /// Use this in handler parameters to ensure the request is authenticated
+///
+/// For kiosk devices `user_id` is the kiosk token id and `is_kiosk` is set;
+/// such sessions only ever reach read-only handlers.
 #[derive(Debug, Clone)]
 pub struct AuthenticatedUser {
     pub user_id: uuid::Uuid,
     pub username: String,
+    pub is_kiosk: bool,
+}
+
+impl AuthenticatedUser {
+    fn from_kiosk(session: &KioskSession) -> AuthenticatedUser {
+        AuthenticatedUser {
+            user_id: session.token_id,
+            username: session.name.clone(),
+            is_kiosk: true,
+        }
+    }
 }
 
 impl actix_web::FromRequest for AuthenticatedUser {
@@ -120,6 +191,7 @@ impl actix_web::FromRequest for AuthenticatedUser {
                         ready(Ok(AuthenticatedUser {
                             user_id,
                             username: claims.username.clone(),
+                            is_kiosk: false,
                         }))
                     }
                     Err(_) => {
@@ -127,9 +199,10 @@ impl actix_web::FromRequest for AuthenticatedUser {
                     }
                 }
             }
-            None => {
-                ready(Err(actix_web::error::ErrorUnauthorized("Authentication required")))
-            }
+            None => match req.extensions().get::<KioskSession>() {
+                Some(session) => ready(Ok(AuthenticatedUser::from_kiosk(session))),
+                None => ready(Err(actix_web::error::ErrorUnauthorized("Authentication required"))),
+            },
         }
     }
 }
@@ -141,6 +214,13 @@ pub struct OptionalAuth {
     pub user: Option<AuthenticatedUser>,
 }
 
+impl OptionalAuth {
+    /// The signed-in username, if any
+    pub fn username(&self) -> Option<&str> {
+        self.user.as_ref().map(|user| user.username.as_str())
+    }
+}
+
 impl actix_web::FromRequest for OptionalAuth {
     type Error = actix_web::Error;
     type Future = Ready<Result<Self, Self::Error>>;
@@ -149,16 +229,27 @@ impl actix_web::FromRequest for OptionalAuth {
         req: &actix_web::HttpRequest,
         _payload: &mut actix_web::dev::Payload,
     ) -> Self::Future {
+        // A read-only mirror has no logins, so pages never offer edit controls
+        if crate::utils::read_only_mode() {
+            return ready(Ok(OptionalAuth { user: None }));
+        }
+
         // First, try to get claims from extensions (set by Authentication middleware)
         let user = req.extensions().get::<auth::Claims>().and_then(|claims| {
             uuid::Uuid::parse_str(&claims.sub).ok().map(|user_id| {
                 AuthenticatedUser {
                     user_id,
                     username: claims.username.clone(),
+                    is_kiosk: false,
                 }
             })
         });
 
+        // Kiosk devices recognised by the Authentication middleware
+        let user = user.or_else(|| {
+            req.extensions().get::<KioskSession>().map(AuthenticatedUser::from_kiosk)
+        });
+
         // If not found, try to extract and validate token from cookie
         let user = user.or_else(|| {
             req.cookie("auth_token")
@@ -170,6 +261,7 @@ impl actix_web::FromRequest for OptionalAuth {
                         AuthenticatedUser {
                             user_id,
                             username: claims.username.clone(),
+                            is_kiosk: false,
                         }
                     })
                 })
@@ -179,9 +271,30 @@ impl actix_web::FromRequest for OptionalAuth {
     }
 }
 
-/// Helper function to render 401 error page
-fn render_401_error() -> Error {
-    let template = Error401Template {};
+/// The translator the Localization middleware picked for a request
+fn translator_for(req: &ServiceRequest) -> Translator {
+    req.extensions()
+        .get::<Translator>()
+        .copied()
+        .unwrap_or_else(|| Translator::for_request(req.request()))
+}
+
+/// Reject a request that has no valid credentials
+///
+/// API clients get the JSON error envelope. Browsers get the 401 page, whose
+/// login link returns them to the page they asked for - unless it was a form
+/// submission, which can't be replayed as a GET.
+fn render_401_error(req: &ServiceRequest) -> Error {
+    if is_api_path(req.path()) {
+        return ApiError::unauthorized().into();
+    }
+
+    let attempted_path = if req.method().is_safe() {
+        req.uri().path_and_query().map(|p| p.to_string())
+    } else {
+        None
+    };
+    let template = Error401Template::new(translator_for(req), attempted_path);
     match template.render() {
         Ok(html) => {
             let response = HttpResponse::Unauthorized()
@@ -195,3 +308,1016 @@ fn render_401_error() -> Error {
         }
     }
 }
+
+/// Helper function to reject kiosk devices from write requests
+fn render_403_error(tr: &Translator) -> Error {
+    let response = HttpResponse::Forbidden()
+        .content_type("text/html; charset=utf-8")
+        .body(format!(
+            "<h1>{}</h1><p>{}</p><p><a href='/'>{}</a></p>",
+            crate::utils::escape_html(&tr.t("error-forbidden")),
+            crate::utils::escape_html(&tr.t("error-kiosk-read-only")),
+            crate::utils::escape_html(&tr.t("error-back-home")),
+        ));
+    actix_web::error::InternalError::from_response("", response).into()
+}
+
+/// CDN the templates load Bootstrap's CSS and JS from
+const BOOTSTRAP_CDN: &str = "https://cdn.jsdelivr.net";
+
+/// Links out of the guide (supplier sites, image hosts) don't learn which page they came from
+const DEFAULT_REFERRER_POLICY: &str = "same-origin";
+
+/// Security headers sent with every response
+///
+/// `Default` is the safe baseline for local storage; `from_env` adds the
+/// storage and deployment specifics.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecurityHeadersConfig {
+    /// Full `Content-Security-Policy` value
+    pub content_security_policy: String,
+    /// `X-Frame-Options` value, `DENY` or `SAMEORIGIN`
+    pub frame_options: String,
+    pub referrer_policy: String,
+    /// `Strict-Transport-Security` value, only set when the site is served over TLS
+    pub strict_transport_security: Option<String>,
+}
+
+impl Default for SecurityHeadersConfig {
+    fn default() -> Self {
+        SecurityHeadersConfig {
+            content_security_policy: content_security_policy(&[], "DENY"),
+            frame_options: "DENY".to_string(),
+            referrer_policy: DEFAULT_REFERRER_POLICY.to_string(),
+            strict_transport_security: None,
+        }
+    }
+}
+
+impl SecurityHeadersConfig {
+    /// Read the configuration from the environment
+    ///
+    /// - `S3_ENABLED`, `S3_BUCKET_NAME`, `AWS_REGION`: when uploads go to S3,
+    ///   the bucket's origin is allowed as an image source
+    /// - `CSP_IMG_SOURCES`: extra image origins, space separated (e.g. a media CDN)
+    /// - `CONTENT_SECURITY_POLICY`: replaces the generated policy entirely
+    /// - `FRAME_OPTIONS`: `DENY` (default) or `SAMEORIGIN`
+    /// - `REFERRER_POLICY`: defaults to `same-origin`
+    /// - `TLS_ENABLED`: send `Strict-Transport-Security`, for `HSTS_MAX_AGE`
+    ///   seconds (default one year)
+    pub fn from_env() -> SecurityHeadersConfig {
+        let mut img_sources: Vec<String> = std::env::var("CSP_IMG_SOURCES")
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(str::to_string)
+            .collect();
+        if crate::utils::env_flag("S3_ENABLED") {
+            let bucket_name = std::env::var("S3_BUCKET_NAME").unwrap_or_else(|_| "kitchen-hand-guide".to_string());
+            let region = std::env::var("AWS_REGION").unwrap_or_else(|_| "ap-southeast-2".to_string());
+            img_sources.push(crate::utils::s3_public_origin(&bucket_name, &region));
+        }
+
+        let frame_options = match std::env::var("FRAME_OPTIONS").map(|v| v.trim().to_uppercase()) {
+            Ok(value) if value == "SAMEORIGIN" => "SAMEORIGIN",
+            _ => "DENY",
+        };
+
+        let content_security_policy = std::env::var("CONTENT_SECURITY_POLICY")
+            .ok()
+            .filter(|policy| !policy.trim().is_empty())
+            .unwrap_or_else(|| content_security_policy(&img_sources, frame_options));
+
+        let strict_transport_security = crate::utils::env_flag("TLS_ENABLED").then(|| {
+            let max_age = std::env::var("HSTS_MAX_AGE")
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .unwrap_or(31_536_000);
+            format!("max-age={}; includeSubDomains", max_age)
+        });
+
+        SecurityHeadersConfig {
+            content_security_policy,
+            frame_options: frame_options.to_string(),
+            referrer_policy: std::env::var("REFERRER_POLICY")
+                .ok()
+                .filter(|policy| !policy.trim().is_empty())
+                .unwrap_or_else(|| DEFAULT_REFERRER_POLICY.to_string()),
+            strict_transport_security,
+        }
+    }
+}
+
+/// Build the Content-Security-Policy for the given image origins
+///
+/// The templates still use inline scripts, styles and event handlers, so
+/// scripts and styles allow 'unsafe-inline'. Everything else is locked to
+/// this site and the Bootstrap CDN. `data:` images cover the "No Image" fallbacks.
+fn content_security_policy(img_sources: &[String], frame_options: &str) -> String {
+    let mut img_src = vec!["'self'", "data:"];
+    img_src.extend(img_sources.iter().map(String::as_str));
+    let frame_ancestors = if frame_options == "SAMEORIGIN" { "'self'" } else { "'none'" };
+
+    [
+        "default-src 'self'".to_string(),
+        format!("img-src {}", img_src.join(" ")),
+        format!("script-src 'self' 'unsafe-inline' {}", BOOTSTRAP_CDN),
+        format!("style-src 'self' 'unsafe-inline' {}", BOOTSTRAP_CDN),
+        format!("font-src 'self' {}", BOOTSTRAP_CDN),
+        "connect-src 'self'".to_string(),
+        "object-src 'none'".to_string(),
+        "base-uri 'self'".to_string(),
+        "form-action 'self'".to_string(),
+        format!("frame-ancestors {}", frame_ancestors),
+    ]
+    .join("; ")
+}
+
+/// Middleware adding the security headers to every response
+///
+/// Headers a handler already set are left alone, and error responses from
+/// inner middleware (like the 401 page) get the headers too.
+pub struct SecurityHeaders {
+    headers: Rc<Vec<(HeaderName, HeaderValue)>>,
+}
+
+impl SecurityHeaders {
+    /// # Panics
+    /// If a configured value isn't a valid header value, so bad config fails at startup
+    pub fn new(config: &SecurityHeadersConfig) -> SecurityHeaders {
+        let mut headers = vec![
+            (header::CONTENT_SECURITY_POLICY, config.content_security_policy.as_str()),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+            (header::X_FRAME_OPTIONS, config.frame_options.as_str()),
+            (header::REFERRER_POLICY, config.referrer_policy.as_str()),
+        ];
+        if let Some(hsts) = &config.strict_transport_security {
+            headers.push((header::STRICT_TRANSPORT_SECURITY, hsts.as_str()));
+        }
+
+        let headers = headers
+            .into_iter()
+            .map(|(name, value)| {
+                let value = HeaderValue::from_str(value)
+                    .unwrap_or_else(|_| panic!("Invalid value for the {} header: {:?}", name, value));
+                (name, value)
+            })
+            .collect();
+        SecurityHeaders { headers: Rc::new(headers) }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for SecurityHeaders
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = SecurityHeadersMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(SecurityHeadersMiddleware {
+            service: Rc::new(service),
+            headers: Rc::clone(&self.headers),
+        }))
+    }
+}
+
+pub struct SecurityHeadersMiddleware<S> {
+    service: Rc<S>,
+    headers: Rc<Vec<(HeaderName, HeaderValue)>>,
+}
+
+impl<S, B> Service<ServiceRequest> for SecurityHeadersMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let headers = Rc::clone(&self.headers);
+        let response = self.service.call(req);
+
+        Box::pin(async move {
+            match response.await {
+                Ok(mut res) => {
+                    add_missing_headers(res.headers_mut(), &headers);
+                    Ok(res)
+                }
+                Err(e) => {
+                    // Render the error now so its response carries the headers too
+                    let mut error_response = e.error_response();
+                    add_missing_headers(error_response.headers_mut(), &headers);
+                    Err(actix_web::error::InternalError::from_response(e, error_response).into())
+                }
+            }
+        })
+    }
+}
+
+/// Middleware picking each request's locale
+///
+/// The chosen `Translator` goes into the request extensions, where handlers
+/// and the error pages above read it. Responses say which language they are in
+/// and that it depends on the Accept-Language header and `lang` cookie.
+pub struct Localization;
+
+impl<S, B> Transform<S, ServiceRequest> for Localization
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = LocalizationMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(LocalizationMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct LocalizationMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for LocalizationMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let translator = Translator::for_request(req.request());
+        req.extensions_mut().insert(translator);
+        let response = self.service.call(req);
+
+        Box::pin(async move {
+            let headers = [
+                (header::CONTENT_LANGUAGE, HeaderValue::from_static(translator.locale())),
+                (header::VARY, HeaderValue::from_static("Accept-Language, Cookie")),
+            ];
+            match response.await {
+                Ok(mut res) => {
+                    add_missing_headers(res.headers_mut(), &headers);
+                    Ok(res)
+                }
+                Err(e) => {
+                    let mut error_response = e.error_response();
+                    add_missing_headers(error_response.headers_mut(), &headers);
+                    Err(actix_web::error::InternalError::from_response(e, error_response).into())
+                }
+            }
+        })
+    }
+}
+
+/// Proxies whose `X-Forwarded-*` headers are believed, as CIDR blocks
+///
+/// Empty (the default) trusts no one, so the headers are ignored and every
+/// request is taken to come from its TCP peer.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrustedProxies {
+    /// Network address, already masked, and prefix length
+    networks: Vec<(IpAddr, u8)>,
+}
+
+impl TrustedProxies {
+    /// Parse CIDR blocks (`10.0.0.0/8`, `fd00::/8`) or single addresses,
+    /// separated by commas or whitespace
+    pub fn parse(list: &str) -> Result<TrustedProxies, String> {
+        let networks = list
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (address, prefix) = match entry.split_once('/') {
+                    Some((address, prefix)) => (address, Some(prefix)),
+                    None => (entry, None),
+                };
+                let address: IpAddr = address
+                    .parse()
+                    .map_err(|_| format!("{:?} is not an IP address or CIDR block", entry))?;
+                let max_prefix = if address.is_ipv4() { 32 } else { 128 };
+                let prefix = match prefix {
+                    Some(prefix) => prefix
+                        .parse::<u8>()
+                        .ok()
+                        .filter(|prefix| *prefix <= max_prefix)
+                        .ok_or_else(|| format!("{:?} has an invalid prefix length", entry))?,
+                    None => max_prefix,
+                };
+                Ok((mask_address(address, prefix), prefix))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(TrustedProxies { networks })
+    }
+
+    /// Read `TRUSTED_PROXIES`; unset or empty trusts no proxy
+    pub fn from_env() -> Result<TrustedProxies, String> {
+        TrustedProxies::parse(&std::env::var("TRUSTED_PROXIES").unwrap_or_default())
+    }
+
+    /// Whether `ip` is one of the trusted proxies
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.networks
+            .iter()
+            .any(|(network, prefix)| network.is_ipv4() == ip.is_ipv4() && mask_address(ip, *prefix) == *network)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.networks.is_empty()
+    }
+}
+
+/// `address` with all but its first `prefix` bits cleared
+fn mask_address(address: IpAddr, prefix: u8) -> IpAddr {
+    match address {
+        IpAddr::V4(v4) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+            IpAddr::V4(std::net::Ipv4Addr::from(u32::from(v4) & mask))
+        }
+        IpAddr::V6(v6) => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0);
+            IpAddr::V6(std::net::Ipv6Addr::from(u128::from(v6) & mask))
+        }
+    }
+}
+
+/// Where a request really came from: its TCP peer, or what a trusted proxy
+/// in front of it reported in `X-Forwarded-For`/`-Proto`/`-Host`
+///
+/// `ForwardedHeaders` stores one in the request extensions; the logger, rate
+/// limits, cookies and absolute URLs read it from there.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientInfo {
+    /// Client address; `None` when the peer is unknown (as in some tests)
+    pub ip: Option<IpAddr>,
+    /// `http` or `https`, as the client connected
+    pub scheme: &'static str,
+    /// Host (and port, if any) the client asked for
+    pub host: String,
+}
+
+impl ClientInfo {
+    /// Work out the client of `req`, believing forwarded headers only from `trusted` peers
+    ///
+    /// `X-Forwarded-For` is read from the right, skipping trusted proxies; the
+    /// first address they didn't add is the client. Anything a client could have
+    /// made up, like a malformed entry or an unknown scheme, stops the walk or is ignored.
+    pub fn resolve(req: &actix_web::HttpRequest, trusted: &TrustedProxies) -> ClientInfo {
+        let peer = req.peer_addr().map(|addr| addr.ip().to_canonical());
+        let direct = ClientInfo {
+            ip: peer,
+            scheme: if req.app_config().secure() { "https" } else { "http" },
+            host: req
+                .headers()
+                .get(header::HOST)
+                .and_then(|host| host.to_str().ok())
+                .or_else(|| req.uri().authority().map(|authority| authority.as_str()))
+                .unwrap_or_else(|| req.app_config().host())
+                .to_string(),
+        };
+        let Some(peer) = peer.filter(|peer| trusted.contains(*peer)) else {
+            return direct;
+        };
+
+        let forwarded_for = req
+            .headers()
+            .get_all("x-forwarded-for")
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect::<Vec<_>>();
+        let mut client = peer;
+        for hop in forwarded_for.into_iter().rev() {
+            let Ok(ip) = hop.trim().parse::<IpAddr>() else {
+                break;
+            };
+            client = ip.to_canonical();
+            if !trusted.contains(client) {
+                break;
+            }
+        }
+
+        // The first proxy lists what the client used; later ones add their own hops
+        let first_value = |name: &str| {
+            req.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.split(',').next())
+                .map(str::trim)
+        };
+        let scheme = match first_value("x-forwarded-proto") {
+            Some(proto) if proto.eq_ignore_ascii_case("https") => "https",
+            Some(proto) if proto.eq_ignore_ascii_case("http") => "http",
+            _ => direct.scheme,
+        };
+        let host = first_value("x-forwarded-host")
+            .filter(|host| is_plausible_host(host))
+            .map_or(direct.host, str::to_string);
+
+        ClientInfo { ip: Some(client), scheme, host }
+    }
+
+    /// The `ClientInfo` `ForwardedHeaders` worked out for `req`, or its TCP peer
+    /// when that middleware isn't in front of the route (e.g. in tests)
+    pub fn of(req: &actix_web::HttpRequest) -> ClientInfo {
+        req.extensions()
+            .get::<ClientInfo>()
+            .cloned()
+            .unwrap_or_else(|| ClientInfo::resolve(req, &TrustedProxies::default()))
+    }
+
+    pub fn is_https(&self) -> bool {
+        self.scheme == "https"
+    }
+
+    /// `path` on this site, as the client reaches it
+    pub fn absolute_url(&self, path: &str) -> String {
+        format!("{}://{}{}", self.scheme, self.host, path)
+    }
+
+    /// A site-wide cookie, marked `Secure` when the client connected over HTTPS
+    pub fn cookie<'c>(
+        &self,
+        name: &'c str,
+        value: impl Into<std::borrow::Cow<'c, str>>,
+    ) -> actix_web::cookie::CookieBuilder<'c> {
+        actix_web::cookie::Cookie::build(name, value).path("/").secure(self.is_https())
+    }
+}
+
+/// Whether a forwarded host looks like `name[:port]` or `[v6]:port`, not a URL or path
+fn is_plausible_host(host: &str) -> bool {
+    !host.is_empty()
+        && host.len() <= 255
+        && host.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | ':' | '[' | ']'))
+}
+
+impl actix_web::FromRequest for ClientInfo {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &actix_web::HttpRequest, _payload: &mut actix_web::dev::Payload) -> Self::Future {
+        ready(Ok(ClientInfo::of(req)))
+    }
+}
+
+/// The client address for the access log, or `-` when unknown
+pub fn logged_client_ip(req: &ServiceRequest) -> String {
+    req.extensions()
+        .get::<ClientInfo>()
+        .and_then(|client| client.ip)
+        .map_or_else(|| "-".to_string(), |ip| ip.to_string())
+}
+
+/// Middleware storing each request's `ClientInfo` in its extensions
+///
+/// Wrap it outside the logger, so the log line has the real client address.
+pub struct ForwardedHeaders {
+    trusted: Rc<TrustedProxies>,
+}
+
+impl ForwardedHeaders {
+    pub fn new(trusted: TrustedProxies) -> ForwardedHeaders {
+        ForwardedHeaders { trusted: Rc::new(trusted) }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ForwardedHeaders
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = ForwardedHeadersMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ForwardedHeadersMiddleware {
+            service: Rc::new(service),
+            trusted: Rc::clone(&self.trusted),
+        }))
+    }
+}
+
+pub struct ForwardedHeadersMiddleware<S> {
+    service: Rc<S>,
+    trusted: Rc<TrustedProxies>,
+}
+
+impl<S, B> Service<ServiceRequest> for ForwardedHeadersMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let client = ClientInfo::resolve(req.request(), &self.trusted);
+        req.extensions_mut().insert(client);
+        Box::pin(self.service.call(req))
+    }
+}
+
+/// Clients tracked before windows that have ended are swept out
+const RATE_LIMIT_SWEEP_AT: usize = 10_000;
+
+/// Middleware allowing each client IP a number of requests per window
+///
+/// The client is the one `ForwardedHeaders` found, so behind a trusted proxy
+/// each visitor has their own budget rather than sharing the proxy's.
+///
+/// Counts reset when a client's window ends (a fixed window, not a sliding
+/// one). Clones share their counts, so build one limiter outside the server
+/// factory and every worker enforces the same budget. Over the limit, requests
+/// get a RATE_LIMITED envelope with `Retry-After`.
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    limit: usize,
+    window: Duration,
+    clients: Arc<Mutex<HashMap<Option<IpAddr>, RateWindow>>>,
+}
+
+/// When a client's current window started, and its requests so far
+type RateWindow = (Instant, usize);
+
+impl RateLimit {
+    pub fn new(limit: usize, window: Duration) -> RateLimit {
+        RateLimit {
+            limit,
+            window,
+            clients: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn per_minute(limit: usize) -> RateLimit {
+        RateLimit::new(limit, Duration::from_secs(60))
+    }
+
+    /// Count a request from `client`, or say how long until it may try again
+    fn check(&self, client: Option<IpAddr>, now: Instant) -> Result<(), Duration> {
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        if clients.len() >= RATE_LIMIT_SWEEP_AT {
+            clients.retain(|_, (started, _)| now.duration_since(*started) < self.window);
+        }
+
+        let (started, count) = clients.entry(client).or_insert((now, 0));
+        if now.duration_since(*started) >= self.window {
+            *started = now;
+            *count = 0;
+        }
+        if *count >= self.limit {
+            return Err(self.window - now.duration_since(*started));
+        }
+        *count += 1;
+        Ok(())
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RateLimitMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware {
+            service: Rc::new(service),
+            limiter: self.clone(),
+        }))
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: Rc<S>,
+    limiter: RateLimit,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let client = ClientInfo::of(req.request()).ip;
+        if let Err(retry_after) = self.limiter.check(client, Instant::now()) {
+            let error = ApiError::rate_limited();
+            let mut response = error.error_response();
+            // Whole seconds, rounded up so a client that waits isn't turned away again
+            let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(secs));
+            return Box::pin(async move { Err(actix_web::error::InternalError::from_response(error, response).into()) });
+        }
+
+        let response = self.service.call(req);
+        Box::pin(response)
+    }
+}
+
+/// Insert each header the response doesn't already have
+fn add_missing_headers(response_headers: &mut header::HeaderMap, headers: &[(HeaderName, HeaderValue)]) {
+    for (name, value) in headers {
+        if !response_headers.contains_key(name) {
+            response_headers.insert(name.clone(), value.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{call_service, init_service, try_call_service, TestRequest};
+    use actix_web::{web, App};
+
+    /// Assert the baseline headers are present on a response
+    fn assert_security_headers(headers: &actix_web::http::header::HeaderMap) {
+        let csp = headers
+            .get(header::CONTENT_SECURITY_POLICY)
+            .expect("Missing Content-Security-Policy")
+            .to_str()
+            .unwrap();
+        assert!(csp.contains("default-src 'self'"));
+        assert!(csp.contains("frame-ancestors 'none'"));
+        assert_eq!(headers.get(header::X_CONTENT_TYPE_OPTIONS).unwrap(), "nosniff");
+        assert_eq!(headers.get(header::X_FRAME_OPTIONS).unwrap(), "DENY");
+        assert_eq!(headers.get(header::REFERRER_POLICY).unwrap(), "same-origin");
+    }
+
+    #[actix_web::test]
+    async fn test_security_headers_on_html_static_and_api() {
+        let app = init_service(
+            App::new()
+                .wrap(SecurityHeaders::new(&SecurityHeadersConfig::default()))
+                .route("/", web::get().to(|| async {
+                    HttpResponse::Ok().content_type("text/html").body("<h1>Home</h1>")
+                }))
+                .route("/api/thing", web::get().to(|| async {
+                    HttpResponse::Ok().json(serde_json::json!({ "ok": true }))
+                }))
+                .service(actix_files::Files::new("/static", "./static")),
+        )
+        .await;
+
+        for uri in ["/", "/static/styles.css", "/api/thing"] {
+            let response = call_service(&app, TestRequest::get().uri(uri).to_request()).await;
+            assert!(response.status().is_success(), "{} returned {}", uri, response.status());
+            assert_security_headers(response.headers());
+            assert!(response.headers().get(header::STRICT_TRANSPORT_SECURITY).is_none());
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_security_headers_on_auth_error_page() {
+        let app = init_service(
+            App::new()
+                .wrap(SecurityHeaders::new(&SecurityHeadersConfig::default()))
+                .service(
+                    web::resource("/private")
+                        .route(web::get().to(HttpResponse::Ok))
+                        .wrap(Authentication),
+                ),
+        )
+        .await;
+
+        let error = try_call_service(&app, TestRequest::get().uri("/private").to_request())
+            .await
+            .expect_err("Request without credentials should be rejected");
+        let response = error.error_response();
+        assert_eq!(response.status().as_u16(), 401);
+        assert_security_headers(response.headers());
+    }
+
+    #[actix_web::test]
+    async fn test_unauthorized_page_links_back_to_login() {
+        let app = init_service(
+            App::new().service(
+                web::scope("")
+                    .wrap(Authentication)
+                    .route("/private", web::get().to(HttpResponse::Ok))
+                    .route("/private", web::post().to(HttpResponse::Ok))
+                    .route("/api/v1/private", web::get().to(HttpResponse::Ok)),
+            ),
+        )
+        .await;
+
+        let body_of = |response: HttpResponse| async move {
+            let body = actix_web::body::to_bytes(response.into_body()).await.unwrap();
+            String::from_utf8(body.to_vec()).unwrap()
+        };
+
+        // Browsers get the page, with a login link back to where they were going
+        let error = try_call_service(&app, TestRequest::get().uri("/private?tab=2").to_request())
+            .await
+            .expect_err("Request without credentials should be rejected");
+        let response = error.error_response();
+        assert_eq!(response.status().as_u16(), 401);
+        assert!(response.headers().get(header::CONTENT_TYPE).unwrap().to_str().unwrap().starts_with("text/html"));
+        let body = body_of(response).await;
+        assert!(body.contains("href=\"/login?next=%2Fprivate%3Ftab%3D2\""));
+        assert!(body.contains("navbar"));
+
+        // A form post can't be replayed, so its login link just goes to /login
+        let error = try_call_service(&app, TestRequest::post().uri("/private").to_request())
+            .await
+            .expect_err("Request without credentials should be rejected");
+        let body = body_of(error.error_response()).await;
+        assert!(body.contains("href=\"/login\""));
+        assert!(!body.contains("next="));
+
+        // API clients get the JSON envelope instead
+        let error = try_call_service(&app, TestRequest::get().uri("/api/v1/private").to_request())
+            .await
+            .expect_err("Request without credentials should be rejected");
+        let response = error.error_response();
+        assert_eq!(response.status().as_u16(), 401);
+        assert_eq!(response.headers().get(header::CONTENT_TYPE).unwrap(), "application/json");
+        let body: serde_json::Value = serde_json::from_str(&body_of(response).await).unwrap();
+        assert_eq!(body["error"]["code"], "UNAUTHORIZED");
+    }
+
+    #[actix_web::test]
+    async fn test_handler_headers_and_hsts() {
+        let config = SecurityHeadersConfig {
+            strict_transport_security: Some("max-age=60; includeSubDomains".to_string()),
+            ..SecurityHeadersConfig::default()
+        };
+        let app = init_service(
+            App::new()
+                .wrap(SecurityHeaders::new(&config))
+                .route("/embed", web::get().to(|| async {
+                    HttpResponse::Ok().insert_header((header::X_FRAME_OPTIONS, "SAMEORIGIN")).finish()
+                })),
+        )
+        .await;
+
+        let response = call_service(&app, TestRequest::get().uri("/embed").to_request()).await;
+        assert_eq!(response.headers().get(header::X_FRAME_OPTIONS).unwrap(), "SAMEORIGIN");
+        assert_eq!(
+            response.headers().get(header::STRICT_TRANSPORT_SECURITY).unwrap(),
+            "max-age=60; includeSubDomains"
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_localization_translates_auth_error_page() {
+        let app = init_service(
+            App::new()
+                .wrap(Localization)
+                .service(
+                    web::resource("/private")
+                        .route(web::get().to(HttpResponse::Ok))
+                        .wrap(Authentication),
+                ),
+        )
+        .await;
+
+        let request = TestRequest::get()
+            .uri("/private")
+            .insert_header((header::ACCEPT_LANGUAGE, "es-AR,es;q=0.9,en;q=0.5"))
+            .to_request();
+        let error = try_call_service(&app, request)
+            .await
+            .expect_err("Request without credentials should be rejected");
+        let response = error.error_response();
+        assert_eq!(response.headers().get(header::CONTENT_LANGUAGE).unwrap(), "es");
+        let body = actix_web::body::to_bytes(response.into_body()).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("<html lang=\"es\">"));
+        assert!(body.contains("Se requiere iniciar sesión"));
+
+        // The lang cookie overrides the browser's languages
+        let request = TestRequest::get()
+            .uri("/private")
+            .insert_header((header::ACCEPT_LANGUAGE, "es"))
+            .cookie(actix_web::cookie::Cookie::new("lang", "en"))
+            .to_request();
+        let error = try_call_service(&app, request).await.expect_err("Should be rejected");
+        assert_eq!(error.error_response().headers().get(header::CONTENT_LANGUAGE).unwrap(), "en");
+    }
+
+    #[actix_web::test]
+    async fn test_rate_limit_per_client() {
+        let limit = RateLimit::per_minute(2);
+        // Each worker builds its own app; clones of one limiter share the counts
+        let worker = || {
+            App::new().service(
+                web::resource("/api/thing")
+                    .route(web::get().to(HttpResponse::Ok))
+                    .wrap(limit.clone()),
+            )
+        };
+        let first_worker = init_service(worker()).await;
+        let second_worker = init_service(worker()).await;
+        let from = |ip: &str| {
+            TestRequest::get()
+                .uri("/api/thing")
+                .peer_addr(format!("{}:4000", ip).parse().unwrap())
+                .to_request()
+        };
+
+        assert!(call_service(&first_worker, from("10.0.0.1")).await.status().is_success());
+        assert!(call_service(&second_worker, from("10.0.0.1")).await.status().is_success());
+        let error = try_call_service(&first_worker, from("10.0.0.1"))
+            .await
+            .expect_err("Third request in the window should be limited");
+        let response = error.error_response();
+        assert_eq!(response.status().as_u16(), 429);
+        let retry_after: u64 = response.headers().get(header::RETRY_AFTER).unwrap().to_str().unwrap().parse().unwrap();
+        assert!((1..=60).contains(&retry_after));
+        let body = actix_web::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["code"], "RATE_LIMITED");
+
+        // Other clients have their own budget
+        assert!(call_service(&first_worker, from("10.0.0.2")).await.status().is_success());
+    }
+
+    #[test]
+    fn test_rate_limit_window_resets() {
+        let limit = RateLimit::new(1, Duration::from_secs(10));
+        let client = Some(IpAddr::from([10, 0, 0, 1]));
+        let start = Instant::now();
+
+        assert!(limit.check(client, start).is_ok());
+        assert_eq!(limit.check(client, start + Duration::from_secs(4)), Err(Duration::from_secs(6)));
+        assert!(limit.check(client, start + Duration::from_secs(10)).is_ok());
+    }
+
+    #[actix_web::test]
+    async fn test_rate_limit_per_client_behind_proxy() {
+        let limit = RateLimit::per_minute(1);
+        let app = init_service(
+            App::new()
+                .service(
+                    web::resource("/api/thing")
+                        .route(web::get().to(HttpResponse::Ok))
+                        .wrap(limit.clone()),
+                )
+                .wrap(ForwardedHeaders::new(TrustedProxies::parse("127.0.0.1").unwrap())),
+        )
+        .await;
+        let via_proxy = |client: &str| {
+            TestRequest::get()
+                .uri("/api/thing")
+                .peer_addr("127.0.0.1:4000".parse().unwrap())
+                .insert_header(("X-Forwarded-For", client))
+                .to_request()
+        };
+
+        assert!(call_service(&app, via_proxy("203.0.113.7")).await.status().is_success());
+        assert!(call_service(&app, via_proxy("203.0.113.8")).await.status().is_success());
+        assert!(try_call_service(&app, via_proxy("203.0.113.7")).await.is_err());
+    }
+
+    #[test]
+    fn test_trusted_proxies_parse() {
+        let trusted = TrustedProxies::parse("10.0.0.0/8, 192.168.1.5 fd00::/8").unwrap();
+
+        assert!(trusted.contains("10.200.3.4".parse().unwrap()));
+        assert!(trusted.contains("192.168.1.5".parse().unwrap()));
+        assert!(!trusted.contains("192.168.1.6".parse().unwrap()));
+        assert!(trusted.contains("fd12::1".parse().unwrap()));
+        // IPv4 peers on a dual-stack socket arrive as mapped IPv6 addresses
+        assert!(trusted.contains("::ffff:10.1.1.1".parse().unwrap()));
+        assert!(TrustedProxies::parse("").unwrap().is_empty());
+        assert!(TrustedProxies::parse("0.0.0.0/0").unwrap().contains("8.8.8.8".parse().unwrap()));
+
+        assert!(TrustedProxies::parse("10.0.0.0/33").is_err());
+        assert!(TrustedProxies::parse("localhost").is_err());
+        assert!(TrustedProxies::parse("10.0.0.1/").is_err());
+    }
+
+    fn forwarded_request(peer: &str, headers: &[(&str, &str)]) -> actix_web::HttpRequest {
+        let mut request = TestRequest::get()
+            .uri("/kiosk")
+            .insert_header((header::HOST, "10.0.0.2:8080"))
+            .peer_addr(format!("{}:4000", peer).parse().unwrap());
+        for (name, value) in headers {
+            request = request.append_header((*name, *value));
+        }
+        request.to_http_request()
+    }
+
+    #[test]
+    fn test_client_info_from_trusted_proxy() {
+        let trusted = TrustedProxies::parse("127.0.0.1, 10.0.0.0/24").unwrap();
+        let req = forwarded_request(
+            "127.0.0.1",
+            &[
+                ("X-Forwarded-For", "198.51.100.1, 203.0.113.9"),
+                ("X-Forwarded-For", "10.0.0.5"),
+                ("X-Forwarded-Proto", "https"),
+                ("X-Forwarded-Host", "kitchen.example.com"),
+            ],
+        );
+        let client = ClientInfo::resolve(&req, &trusted);
+
+        // 10.0.0.5 is another of our proxies; 203.0.113.9 is the first hop we didn't add
+        assert_eq!(client.ip, Some("203.0.113.9".parse().unwrap()));
+        assert!(client.is_https());
+        assert_eq!(client.absolute_url("/kiosk/abc"), "https://kitchen.example.com/kiosk/abc");
+        assert!(client.cookie("lang", "es").finish().secure().unwrap_or(false));
+    }
+
+    #[test]
+    fn test_client_info_ignores_untrusted_peers() {
+        let trusted = TrustedProxies::parse("127.0.0.1").unwrap();
+        let spoofed = [
+            ("X-Forwarded-For", "198.51.100.1"),
+            ("X-Forwarded-Proto", "https"),
+            ("X-Forwarded-Host", "evil.example.com"),
+        ];
+        let client = ClientInfo::resolve(&forwarded_request("203.0.113.9", &spoofed), &trusted);
+
+        assert_eq!(client.ip, Some("203.0.113.9".parse().unwrap()));
+        assert_eq!(client.scheme, "http");
+        assert_eq!(client.host, "10.0.0.2:8080");
+        assert!(!client.cookie("lang", "es").finish().secure().unwrap_or(false));
+
+        // Nothing is trusted unless configured
+        let client = ClientInfo::resolve(&forwarded_request("127.0.0.1", &spoofed), &TrustedProxies::default());
+        assert_eq!(client.ip, Some("127.0.0.1".parse().unwrap()));
+        assert_eq!(client.absolute_url("/"), "http://10.0.0.2:8080/");
+    }
+
+    #[test]
+    fn test_client_info_with_malformed_forwarded_headers() {
+        let trusted = TrustedProxies::parse("127.0.0.1, 10.0.0.0/24").unwrap();
+        let client = ClientInfo::resolve(
+            &forwarded_request(
+                "127.0.0.1",
+                &[
+                    ("X-Forwarded-For", "198.51.100.1, not-an-ip, 10.0.0.5"),
+                    ("X-Forwarded-Proto", "gopher"),
+                    ("X-Forwarded-Host", "evil.example.com/phish"),
+                ],
+            ),
+            &trusted,
+        );
+
+        // The walk stops at the garbage, at the last hop a trusted proxy vouched for
+        assert_eq!(client.ip, Some("10.0.0.5".parse().unwrap()));
+        assert_eq!(client.scheme, "http");
+        assert_eq!(client.host, "10.0.0.2:8080");
+
+        let client = ClientInfo::resolve(
+            &forwarded_request("127.0.0.1", &[("X-Forwarded-For", ""), ("X-Forwarded-Proto", "")]),
+            &trusted,
+        );
+        assert_eq!(client.ip, Some("127.0.0.1".parse().unwrap()));
+        assert_eq!(client.scheme, "http");
+    }
+
+    #[test]
+    fn test_csp_img_src_follows_storage() {
+        let s3 = crate::utils::s3_public_origin("khg", "ap-southeast-2");
+        let policy = content_security_policy(&[s3, "https://media.example.com".to_string()], "SAMEORIGIN");
+
+        assert!(policy.contains(
+            "img-src 'self' data: https://khg.s3.ap-southeast-2.amazonaws.com https://media.example.com;"
+        ));
+        assert!(policy.contains("frame-ancestors 'self'"));
+        assert!(content_security_policy(&[], "DENY").contains("img-src 'self' data:;"));
+    }
+}
@@ -1,8 +1,47 @@
-use chrono::{DateTime, Utc};
+use crate::errors::ValidationError;
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc, Weekday};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use uuid::Uuid;
 
+/// Image path served in place of a missing product or preparation picture
+pub const PLACEHOLDER_IMAGE_URL: &str = "/static/images/placeholder.svg";
+
+/// Serialize a missing picture as the placeholder path so API clients always get a usable URL
+fn serialize_picture_url<S: serde::Serializer>(
+    picture_url: &Option<String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(picture_url.as_deref().unwrap_or(PLACEHOLDER_IMAGE_URL))
+}
+
+/// A record with timestamps that API responses also give in the venue's timezone
+pub trait Timestamped {
+    /// Field names and values of the record's timestamps
+    fn timestamps(&self) -> Vec<(&'static str, DateTime<Utc>)>;
+}
+
+/// A record as the JSON API returns it: its own fields, with timestamps still RFC 3339 UTC,
+/// plus a `<field>_local` string for each timestamp ("Tue 14 May, 2:45 pm")
+#[derive(Debug, Serialize)]
+pub struct WithLocalTimes<T> {
+    #[serde(flatten)]
+    record: T,
+    #[serde(flatten)]
+    local_times: std::collections::BTreeMap<String, String>,
+}
+
+impl<T: Timestamped> WithLocalTimes<T> {
+    pub fn new(record: T, tz: chrono_tz::Tz) -> Self {
+        let local_times = record
+            .timestamps()
+            .into_iter()
+            .map(|(field, at)| (format!("{}_local", field), crate::utils::format_local_time(at, tz)))
+            .collect();
+        WithLocalTimes { record, local_times }
+    }
+}
+
 /// Database model for Product
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct Product {
@@ -10,103 +49,597 @@ pub struct Product {
     pub supplier_name: String,
     pub product_name: String,
     pub location: String,
-    pub picture_url: String,
+    pub barcode: Option<String>,
+    #[serde(serialize_with = "serialize_picture_url")]
+    pub picture_url: Option<String>,
+    /// WebP copy of the picture, if `WEBP_CONVERT` made one
+    pub webp_url: Option<String>,
+    /// Tiny blurred placeholder of the picture as a `data:` URI, if `LQIP_ENABLED` made one
+    #[serde(skip_serializing)]
+    pub lqip: Option<String>,
     pub description: String,
+    /// Pack size the price is for, e.g. "5kg box"
+    pub unit: Option<String>,
+    /// Last known price in whole cents
+    pub last_price_cents: Option<i64>,
+    /// Coldest it should be stored at, in °C
+    pub storage_temp_min_c: Option<f64>,
+    /// Warmest it should be stored at, in °C; set for anything in the cold chain
+    pub storage_temp_max_c: Option<f64>,
+    /// How to handle it, e.g. "don't stack above 3 high"
+    pub handling_notes: Option<String>,
+    /// `draft`, `published` or `merged`; see `PRODUCT_STATUS_DRAFT` and `PRODUCT_STATUS_MERGED`
+    pub status: String,
+    /// The product a merged duplicate now redirects to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub merged_into: Option<Uuid>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+/// Status of a product made from a bulk photo upload, until its details are filled in
+///
+/// Drafts are left out of the product lists, search, exports and ordering.
+pub const PRODUCT_STATUS_DRAFT: &str = "draft";
+
+/// Status of a duplicate merged into another product by `Product::merge_into`
+///
+/// Merged products are hidden like drafts; their page redirects to `merged_into`.
+pub const PRODUCT_STATUS_MERGED: &str = "merged";
+
+/// Days a photo draft is kept before `Product::delete_stale_drafts` removes it
+pub const PRODUCT_DRAFT_MAX_AGE_DAYS: i32 = 30;
+
+/// A photo from a bulk upload, already stored, to become a draft product
+#[derive(Debug, Clone)]
+pub struct PhotoDraft {
+    pub product_name: String,
+    pub picture_url: String,
+    pub webp_url: Option<String>,
+    pub lqip: Option<String>,
+    pub picture_size_bytes: i64,
+}
+
 /// Form data for creating a new product
 #[derive(Debug, Deserialize)]
 pub struct NewProductForm {
     pub supplier_name: String,
     pub product_name: String,
     pub location: String,
+    pub barcode: Option<String>,
     pub description: String,
+    /// Pack size, e.g. "5kg box"; blank for none
+    #[serde(default)]
+    pub unit: Option<String>,
+    /// Price as typed, in dollars ("12.50"); blank for unknown
+    #[serde(default)]
+    pub price: Option<String>,
+    /// Coldest storage temperature as typed, in °C; blank for no minimum
+    #[serde(default)]
+    pub storage_temp_min: Option<String>,
+    /// Warmest storage temperature as typed, in °C; blank for no maximum
+    #[serde(default)]
+    pub storage_temp_max: Option<String>,
+    /// Blank for none
+    #[serde(default)]
+    pub handling_notes: Option<String>,
+}
+
+/// Longest pack size description accepted (in characters)
+pub const MAX_UNIT_CHARS: usize = 50;
+
+/// Storage temperatures accepted, in °C; anything outside is a typo (or Fahrenheit)
+pub const STORAGE_TEMP_RANGE_C: std::ops::RangeInclusive<f64> = -40.0..=60.0;
+
+/// Longest handling notes accepted (in characters)
+pub const MAX_HANDLING_NOTES_CHARS: usize = 1000;
+
+/// Whether an optional text field was filled in
+fn is_filled(text: &Option<String>) -> bool {
+    text.as_deref().is_some_and(|text| !text.trim().is_empty())
+}
+
+/// Descriptions shorter than this (in characters) get a warning
+pub const SHORT_DESCRIPTION_CHARS: usize = 20;
+
+/// Warning shown when a product is saved without a picture
+pub const NO_PICTURE_WARNING: &str = "No image uploaded. Staff will see the placeholder instead of the product.";
+
+/// Outcome of validating a form: errors block saving, warnings are only shown
+#[derive(Debug, Default, PartialEq)]
+pub struct FormValidation {
+    pub errors: Vec<ValidationError>,
+    pub warnings: Vec<String>,
+}
+
+impl FormValidation {
+    /// Whether the form can be saved (warnings don't count)
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// One form field as `/api/schema/*` describes it, for clients that build forms themselves
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldSchema {
+    pub name: &'static str,
+    /// `string`, `text` (multi-line), `number`, `integer`, `choice`, `list` or `file`
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub required: bool,
+    /// Longest value (or list entry), in characters
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_length: Option<usize>,
+    /// Lowest number accepted (`yield_quantity` must be above it)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+    /// Allowed values of a choice
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub values: Option<Vec<String>>,
+    /// Most entries of a list
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_items: Option<usize>,
+}
+
+impl FieldSchema {
+    /// An optional field with no limits
+    pub fn new(name: &'static str, kind: &'static str) -> FieldSchema {
+        FieldSchema { name, kind, required: false, max_length: None, min: None, max: None, values: None, max_items: None }
+    }
+
+    pub fn required(self) -> FieldSchema {
+        FieldSchema { required: true, ..self }
+    }
+
+    pub fn max_length(self, max_length: usize) -> FieldSchema {
+        FieldSchema { max_length: Some(max_length), ..self }
+    }
+
+    pub fn range(self, min: f64, max: f64) -> FieldSchema {
+        FieldSchema { min: Some(min), max: Some(max), ..self }
+    }
+
+    /// A choice between `values`
+    pub fn choice<S: ToString>(name: &'static str, values: &[S]) -> FieldSchema {
+        FieldSchema { values: Some(values.iter().map(ToString::to_string).collect()), ..FieldSchema::new(name, "choice") }
+    }
+
+    /// A location: one of the admin-managed list, or free text while it's empty
+    fn location(allowed_locations: &[String]) -> FieldSchema {
+        if allowed_locations.is_empty() {
+            FieldSchema::new("location", "string").max_length(MAX_NAME_CHARS).required()
+        } else {
+            FieldSchema::choice("location", allowed_locations).required()
+        }
+    }
+}
+
+/// Longest names and locations the database stores, in characters
+pub const MAX_NAME_CHARS: usize = 255;
+
+/// Ok when there are no errors, otherwise all of them
+fn as_result(errors: Vec<ValidationError>) -> Result<(), Vec<ValidationError>> {
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
 }
 
 impl NewProductForm {
     /// Validate the form data
-    pub fn validate(&self) -> Result<(), String> {
+    pub fn validate(&self) -> FormValidation {
+        let mut validation = FormValidation::default();
         if self.supplier_name.trim().is_empty() {
-            return Err("Supplier name cannot be empty".to_string());
+            validation.errors.push(ValidationError::new("supplier_name", "required", "Supplier name cannot be empty"));
         }
         if self.product_name.trim().is_empty() {
-            return Err("Product name cannot be empty".to_string());
+            validation.errors.push(ValidationError::new("product_name", "required", "Product name cannot be empty"));
         }
         if self.location.trim().is_empty() {
-            return Err("Location cannot be empty".to_string());
+            validation.errors.push(ValidationError::new("location", "required", "Location cannot be empty"));
+        }
+        let description_chars = self.description.trim().chars().count();
+        if description_chars == 0 {
+            validation.errors.push(ValidationError::new("description", "required", "Description cannot be empty"));
+        } else if description_chars < SHORT_DESCRIPTION_CHARS {
+            validation.warnings.push(
+                "The description is very short. Consider adding storage temperature or shelf life.".to_string(),
+            );
+        }
+        if let Some(barcode) = &self.barcode {
+            if !crate::utils::is_valid_barcode(barcode) {
+                validation.errors.push(ValidationError::new(
+                    "barcode",
+                    "invalid_format",
+                    "Barcode must be a valid EAN-13 or UPC-A code",
+                ));
+            }
+        }
+        if self.has_price() && self.price_cents().is_none() {
+            validation.errors.push(ValidationError::new(
+                "price",
+                "invalid",
+                "Price must be an amount in dollars and cents, like 12.50",
+            ));
+        }
+        if self.unit().is_some_and(|unit| unit.chars().count() > MAX_UNIT_CHARS) {
+            validation.errors.push(ValidationError::new(
+                "unit",
+                "too_long",
+                format!("Unit must be {} characters or fewer", MAX_UNIT_CHARS),
+            ));
+        }
+        let temperatures = [
+            ("storage_temp_min", "Minimum", &self.storage_temp_min, self.storage_temp_min_c()),
+            ("storage_temp_max", "Maximum", &self.storage_temp_max, self.storage_temp_max_c()),
+        ];
+        for (field, label, typed, degrees) in temperatures {
+            match degrees {
+                None if is_filled(typed) => validation.errors.push(ValidationError::new(
+                    field,
+                    "invalid",
+                    format!("{} storage temperature must be a number of degrees Celsius, like 4 or -18", label),
+                )),
+                Some(degrees) if !STORAGE_TEMP_RANGE_C.contains(&degrees) => validation.errors.push(ValidationError::new(
+                    field,
+                    "out_of_range",
+                    format!(
+                        "{} storage temperature must be between {} and {} °C",
+                        label,
+                        STORAGE_TEMP_RANGE_C.start(),
+                        STORAGE_TEMP_RANGE_C.end()
+                    ),
+                )),
+                _ => {}
+            }
         }
-        if self.description.trim().is_empty() {
-            return Err("Description cannot be empty".to_string());
+        if let (Some(min), Some(max)) = (self.storage_temp_min_c(), self.storage_temp_max_c()) {
+            if min > max {
+                validation.errors.push(ValidationError::new(
+                    "storage_temp_min",
+                    "above_max",
+                    "Minimum storage temperature can't be warmer than the maximum",
+                ));
+            }
+        }
+        if self.handling_notes().is_some_and(|notes| notes.chars().count() > MAX_HANDLING_NOTES_CHARS) {
+            validation.errors.push(ValidationError::new(
+                "handling_notes",
+                "too_long",
+                format!("Handling notes must be {} characters or fewer", MAX_HANDLING_NOTES_CHARS),
+            ));
+        }
+        validation
+    }
+
+    /// Whether a price was entered at all
+    fn has_price(&self) -> bool {
+        self.price.as_deref().is_some_and(|price| !price.trim().is_empty())
+    }
+
+    /// The entered price in cents, or None when blank or invalid
+    pub fn price_cents(&self) -> Option<i64> {
+        self.price.as_deref().and_then(crate::utils::parse_price_cents)
+    }
+
+    /// The entered pack size, or None when blank
+    pub fn unit(&self) -> Option<&str> {
+        self.unit.as_deref().map(str::trim).filter(|unit| !unit.is_empty())
+    }
+
+    /// The entered minimum storage temperature in °C, or None when blank or invalid
+    pub fn storage_temp_min_c(&self) -> Option<f64> {
+        self.storage_temp_min.as_deref().and_then(crate::utils::parse_temperature)
+    }
+
+    /// The entered maximum storage temperature in °C, or None when blank or invalid
+    pub fn storage_temp_max_c(&self) -> Option<f64> {
+        self.storage_temp_max.as_deref().and_then(crate::utils::parse_temperature)
+    }
+
+    /// The entered handling notes, or None when blank
+    pub fn handling_notes(&self) -> Option<&str> {
+        self.handling_notes.as_deref().map(str::trim).filter(|notes| !notes.is_empty())
+    }
+
+    /// The form's fields and the limits `validate_with_locations` enforces
+    pub fn schema(allowed_locations: &[String]) -> Vec<FieldSchema> {
+        let mut picture = FieldSchema::new("picture", "file");
+        picture.required = crate::utils::env_flag("REQUIRE_PRODUCT_IMAGE");
+        vec![
+            FieldSchema::new("supplier_name", "string").max_length(MAX_NAME_CHARS).required(),
+            FieldSchema::new("product_name", "string").max_length(MAX_NAME_CHARS).required(),
+            FieldSchema::location(allowed_locations),
+            FieldSchema::new("barcode", "string").max_length(13),
+            FieldSchema::new("price", "number").range(0.0, crate::utils::MAX_PRICE_CENTS as f64 / 100.0),
+            FieldSchema::new("unit", "string").max_length(MAX_UNIT_CHARS),
+            FieldSchema::new("storage_temp_min", "number").range(*STORAGE_TEMP_RANGE_C.start(), *STORAGE_TEMP_RANGE_C.end()),
+            FieldSchema::new("storage_temp_max", "number").range(*STORAGE_TEMP_RANGE_C.start(), *STORAGE_TEMP_RANGE_C.end()),
+            FieldSchema::new("handling_notes", "text").max_length(MAX_HANDLING_NOTES_CHARS),
+            picture,
+            FieldSchema::new("description", "text").required(),
+        ]
+    }
+
+    /// Validate the form data, also requiring the location to be one of `allowed_locations`
+    pub fn validate_with_locations(&self, allowed_locations: &[String]) -> FormValidation {
+        let mut validation = self.validate();
+        if !self.location.trim().is_empty() {
+            if let Err(error) = check_allowed_location(&self.location, allowed_locations) {
+                validation.errors.push(error);
+            }
         }
+        validation
+    }
+}
+
+/// Reject locations that aren't in the admin-managed list
+///
+/// While the list is empty any location is accepted, so installs that never
+/// set up locations keep free-text behaviour.
+pub fn check_allowed_location(location: &str, allowed_locations: &[String]) -> Result<(), ValidationError> {
+    if allowed_locations.is_empty() || allowed_locations.iter().any(|allowed| allowed == location.trim()) {
         Ok(())
+    } else {
+        Err(ValidationError::new(
+            "location",
+            "unknown_location",
+            format!("Unknown location \"{}\". Choose one from the list.", location.trim()),
+        ))
+    }
+}
+
+impl Timestamped for Product {
+    fn timestamps(&self) -> Vec<(&'static str, DateTime<Utc>)> {
+        vec![
+            ("created_at", self.created_at),
+            ("updated_at", self.updated_at),
+        ]
     }
 }
 
 /// Database operations for Product
 impl Product {
-    /// Get all products from database
-    pub async fn get_all(pool: &sqlx::PgPool) -> Result<Vec<Product>, sqlx::Error> {
+    /// Picture to display, falling back to the placeholder image
+    pub fn image_url(&self) -> &str {
+        self.picture_url.as_deref().unwrap_or(PLACEHOLDER_IMAGE_URL)
+    }
+
+    pub fn is_draft(&self) -> bool {
+        self.status == PRODUCT_STATUS_DRAFT
+    }
+
+    pub fn is_merged(&self) -> bool {
+        self.status == PRODUCT_STATUS_MERGED
+    }
+
+    /// Non-blocking issues with the saved product, as the form would warn about them
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = self.as_form().validate().warnings;
+        if self.picture_url.is_none() {
+            warnings.push(NO_PICTURE_WARNING.to_string());
+        }
+        warnings
+    }
+
+    /// The product's current values as a form, for re-validating a saved product
+    pub fn as_form(&self) -> NewProductForm {
+        NewProductForm {
+            supplier_name: self.supplier_name.clone(),
+            product_name: self.product_name.clone(),
+            location: self.location.clone(),
+            barcode: self.barcode.clone(),
+            description: self.description.clone(),
+            unit: self.unit.clone(),
+            price: self.price_input(),
+            storage_temp_min: self.storage_temp_min_c.map(|degrees| degrees.to_string()),
+            storage_temp_max: self.storage_temp_max_c.map(|degrees| degrees.to_string()),
+            handling_notes: self.handling_notes.clone(),
+        }
+    }
+
+    /// The storage temperature range for display, e.g. "0 to 5 °C" or "5 °C or below"
+    pub fn storage_temp_label(&self) -> Option<String> {
+        match (self.storage_temp_min_c, self.storage_temp_max_c) {
+            (Some(min), Some(max)) if min == max => Some(format!("{} °C", max)),
+            (Some(min), Some(max)) => Some(format!("{} to {} °C", min, max)),
+            (None, Some(max)) => Some(format!("{} °C or below", max)),
+            (Some(min), None) => Some(format!("{} °C or above", min)),
+            (None, None) => None,
+        }
+    }
+
+    /// The last known price as the form takes it, e.g. "12.50"
+    pub fn price_input(&self) -> Option<String> {
+        self.last_price_cents.map(|cents| format!("{}.{:02}", cents / 100, cents % 100))
+    }
+
+    /// The last known price for display, e.g. "$12.50"
+    pub fn price_label(&self) -> Option<String> {
+        self.last_price_cents.map(crate::utils::format_price_cents)
+    }
+
+    /// Record the product's price and pack size, adding to its price history when the price changes
+    ///
+    /// A None price clears the last known price without adding to the history.
+    /// Accepts a pool, a connection or a transaction; returns whether the history grew.
+    pub async fn set_price<'a, A>(
+        db: A,
+        id: Uuid,
+        price_cents: Option<i64>,
+        unit: Option<&str>,
+        recorded_by: Option<&str>,
+    ) -> Result<bool, sqlx::Error>
+    where
+        A: sqlx::Acquire<'a, Database = sqlx::Postgres>,
+    {
+        let mut tx = db.begin().await?;
+
+        let previous: Option<Option<i64>> =
+            sqlx::query_scalar("SELECT last_price_cents FROM products WHERE id = $1 FOR UPDATE")
+                .bind(id)
+                .fetch_optional(&mut *tx)
+                .await?;
+        let Some(previous) = previous else {
+            return Err(sqlx::Error::RowNotFound);
+        };
+
+        sqlx::query(
+            "UPDATE products SET last_price_cents = $2, unit = $3
+             WHERE id = $1 AND (last_price_cents IS DISTINCT FROM $2 OR unit IS DISTINCT FROM $3)"
+        )
+        .bind(id)
+        .bind(price_cents)
+        .bind(unit)
+        .execute(&mut *tx)
+        .await?;
+
+        let price_changed = price_cents.is_some() && price_cents != previous;
+        if price_changed {
+            // clock_timestamp() keeps prices recorded in one transaction in order
+            sqlx::query(
+                "INSERT INTO product_price_history (product_id, price_cents, unit, recorded_by, recorded_at)
+                 VALUES ($1, $2, $3, $4, clock_timestamp())"
+            )
+            .bind(id)
+            .bind(price_cents)
+            .bind(unit)
+            .bind(recorded_by)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(price_changed)
+    }
+
+    /// Stream all published products row by row, for exports that should not buffer the whole table
+    pub fn stream_all(
+        pool: &sqlx::PgPool,
+    ) -> futures_util::stream::BoxStream<'_, Result<Product, sqlx::Error>> {
         sqlx::query_as::<_, Product>(
-            "SELECT id, supplier_name, product_name, location, picture_url, description, created_at, updated_at
+            "SELECT id, supplier_name, product_name, location, barcode, picture_url, webp_url, lqip, description, unit, last_price_cents, storage_temp_min_c, storage_temp_max_c, handling_notes, status, merged_into, created_at, updated_at
              FROM products
-             ORDER BY created_at DESC"
+             WHERE status = 'published'
+             ORDER BY supplier_name, product_name"
         )
-        .fetch_all(pool)
-        .await
+        .fetch(pool)
     }
 
     /// Get a single product by ID
-    pub async fn get_by_id(pool: &sqlx::PgPool, id: Uuid) -> Result<Option<Product>, sqlx::Error> {
+    pub async fn get_by_id<'e, E: sqlx::PgExecutor<'e>>(executor: E, id: Uuid) -> Result<Option<Product>, sqlx::Error> {
         sqlx::query_as::<_, Product>(
-            "SELECT id, supplier_name, product_name, location, picture_url, description, created_at, updated_at
+            "SELECT id, supplier_name, product_name, location, barcode, picture_url, webp_url, lqip, description, unit, last_price_cents, storage_temp_min_c, storage_temp_max_c, handling_notes, status, merged_into, created_at, updated_at
              FROM products
              WHERE id = $1"
         )
         .bind(id)
+        .fetch_optional(executor)
+        .await
+    }
+
+    /// Get a single product by its barcode
+    pub async fn get_by_barcode(pool: &sqlx::PgPool, barcode: &str) -> Result<Option<Product>, sqlx::Error> {
+        sqlx::query_as::<_, Product>(
+            "SELECT id, supplier_name, product_name, location, barcode, picture_url, webp_url, lqip, description, unit, last_price_cents, storage_temp_min_c, storage_temp_max_c, handling_notes, status, merged_into, created_at, updated_at
+             FROM products
+             WHERE barcode = $1"
+        )
+        .bind(barcode)
         .fetch_optional(pool)
         .await
     }
 
+    /// Products that may already be the one about to be added, most alike first
+    ///
+    /// Matches the same name ignoring case and surrounding spaces (similarity
+    /// 1.0) or within `FUZZY_SEARCH_THRESHOLD`, like fuzzy search. A supplier
+    /// narrows this to products from that supplier, matched the same way as names.
+    pub async fn possible_duplicates<'a, A>(
+        db: A,
+        supplier: Option<&str>,
+        name: &str,
+        limit: i64,
+    ) -> Result<Vec<DuplicateCandidate>, sqlx::Error>
+    where
+        A: sqlx::Acquire<'a, Database = sqlx::Postgres>,
+    {
+        let mut tx = db.begin().await?;
+        set_similarity_threshold(&mut tx).await?;
+
+        let candidates = sqlx::query_as::<_, DuplicateCandidate>(
+            "SELECT id, product_name AS name, supplier_name, updated_at,
+                    CASE WHEN lower(btrim(product_name)) = lower(btrim($1)) THEN 1.0
+                         ELSE similarity(product_name, $1) END::REAL AS similarity
+             FROM products
+             WHERE (product_name % $1 OR lower(btrim(product_name)) = lower(btrim($1)))
+               AND status = 'published'
+               AND ($2::TEXT IS NULL OR lower(btrim(supplier_name)) = lower(btrim($2)))
+             ORDER BY similarity DESC, product_name
+             LIMIT $3"
+        )
+        .bind(name)
+        .bind(supplier)
+        .bind(limit)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(candidates)
+    }
+
     /// Create a new product
-    pub async fn create(
-        pool: &sqlx::PgPool,
+    pub async fn create<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
         supplier_name: &str,
         product_name: &str,
         location: &str,
-        picture_url: &str,
+        barcode: Option<&str>,
+        picture_url: Option<&str>,
         description: &str,
     ) -> Result<Product, sqlx::Error> {
         sqlx::query_as::<_, Product>(
-            "INSERT INTO products (supplier_name, product_name, location, picture_url, description)
-             VALUES ($1, $2, $3, $4, $5)
-             RETURNING id, supplier_name, product_name, location, picture_url, description, created_at, updated_at"
+            "INSERT INTO products (supplier_name, product_name, location, picture_url, description, barcode)
+             VALUES ($1, $2, $3, $4, $5, $6)
+             RETURNING id, supplier_name, product_name, location, barcode, picture_url, webp_url, lqip, description, unit, last_price_cents, storage_temp_min_c, storage_temp_max_c, handling_notes, status, merged_into, created_at, updated_at"
         )
         .bind(supplier_name)
         .bind(product_name)
         .bind(location)
         .bind(picture_url)
         .bind(description)
-        .fetch_one(pool)
+        .bind(barcode)
+        .fetch_one(executor)
         .await
     }
 
-    /// Update an existing product
-    pub async fn update(
-        pool: &sqlx::PgPool,
+    /// Update an existing product, publishing it if it was a draft
+    ///
+    /// The edit form needs every required field, so a draft saved through it is complete.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn update<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
         id: Uuid,
         supplier_name: &str,
         product_name: &str,
         location: &str,
-        picture_url: &str,
+        barcode: Option<&str>,
+        picture_url: Option<&str>,
         description: &str,
     ) -> Result<Product, sqlx::Error> {
         sqlx::query_as::<_, Product>(
             "UPDATE products
-             SET supplier_name = $2, product_name = $3, location = $4, picture_url = $5, description = $6, updated_at = CURRENT_TIMESTAMP
+             SET supplier_name = $2, product_name = $3, location = $4, picture_url = $5, description = $6, barcode = $7, updated_at = CURRENT_TIMESTAMP,
+                 status = 'published',
+                 webp_url = CASE WHEN picture_url IS NOT DISTINCT FROM $5 THEN webp_url END,
+                 lqip = CASE WHEN picture_url IS NOT DISTINCT FROM $5 THEN lqip END,
+                 picture_size_bytes = CASE WHEN picture_url IS NOT DISTINCT FROM $5 THEN picture_size_bytes END,
+                 picture_uploaded_at = CASE WHEN picture_url IS NOT DISTINCT FROM $5 THEN picture_uploaded_at END
              WHERE id = $1
-             RETURNING id, supplier_name, product_name, location, picture_url, description, created_at, updated_at"
+             RETURNING id, supplier_name, product_name, location, barcode, picture_url, webp_url, lqip, description, unit, last_price_cents, storage_temp_min_c, storage_temp_max_c, handling_notes, status, merged_into, created_at, updated_at"
         )
         .bind(id)
         .bind(supplier_name)
@@ -114,321 +647,5837 @@ impl Product {
         .bind(location)
         .bind(picture_url)
         .bind(description)
-        .fetch_one(pool)
+        .bind(barcode)
+        .fetch_one(executor)
         .await
     }
-}
 
-/// Database model for Preparation
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
-pub struct Preparation {
-    pub id: Uuid,
-    pub name: String,
-    pub prep_type: String,
-    pub shift: String,
-    pub location: String,
-    pub picture_url: String,
-    pub steps: String,
-    pub created_at: DateTime<Utc>,
-    pub updated_at: DateTime<Utc>,
-}
+    /// Record the product's storage temperature range (in °C) and handling notes
+    pub async fn set_handling<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        id: Uuid,
+        storage_temp_min_c: Option<f64>,
+        storage_temp_max_c: Option<f64>,
+        handling_notes: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE products SET storage_temp_min_c = $2, storage_temp_max_c = $3, handling_notes = $4 WHERE id = $1"
+        )
+        .bind(id)
+        .bind(storage_temp_min_c)
+        .bind(storage_temp_max_c)
+        .bind(handling_notes)
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
 
-/// Form data for creating a new preparation
-#[derive(Debug, Deserialize)]
-pub struct NewPreparationForm {
-    pub name: String,
-    pub prep_type: String,
-    pub shift: String,
-    pub location: String,
-    pub steps: String,
-}
+    /// Products with a maximum storage temperature, coldest first
+    ///
+    /// `max_c` keeps those that must be kept at or below it; `location` keeps one storage location.
+    pub async fn cold_chain<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        max_c: Option<f64>,
+        location: Option<&str>,
+    ) -> Result<Vec<Product>, sqlx::Error> {
+        sqlx::query_as::<_, Product>(
+            "SELECT id, supplier_name, product_name, location, barcode, picture_url, webp_url, lqip, description, unit, last_price_cents, storage_temp_min_c, storage_temp_max_c, handling_notes, status, merged_into, created_at, updated_at
+             FROM products
+             WHERE storage_temp_max_c IS NOT NULL
+               AND status = 'published'
+               AND ($1::DOUBLE PRECISION IS NULL OR storage_temp_max_c <= $1)
+               AND ($2::TEXT IS NULL OR location = $2)
+             ORDER BY storage_temp_max_c, storage_temp_min_c NULLS FIRST, product_name"
+        )
+        .bind(max_c)
+        .bind(location)
+        .fetch_all(executor)
+        .await
+    }
 
-impl NewPreparationForm {
-    /// Validate the form data
-    pub fn validate(&self) -> Result<(), String> {
-        if self.name.trim().is_empty() {
-            return Err("Preparation name cannot be empty".to_string());
-        }
-        if !["fruit", "bread", "veg", "meat", "seafood"].contains(&self.prep_type.as_str()) {
-            return Err("Invalid preparation type".to_string());
-        }
-        if !["brekkie", "lunch", "both"].contains(&self.shift.as_str()) {
-            return Err("Invalid shift selection".to_string());
-        }
-        if self.location.trim().is_empty() {
-            return Err("Location cannot be empty".to_string());
-        }
-        if self.steps.trim().is_empty() {
-            return Err("Steps cannot be empty".to_string());
-        }
+    /// Record the WebP copy of the product's picture
+    pub async fn set_webp_url<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        id: Uuid,
+        webp_url: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE products SET webp_url = $2 WHERE id = $1")
+            .bind(id)
+            .bind(webp_url)
+            .execute(executor)
+            .await?;
         Ok(())
     }
-}
 
-/// Database operations for Preparation
-impl Preparation {
-    /// Get all preparations from database
-    pub async fn get_all(pool: &sqlx::PgPool) -> Result<Vec<Preparation>, sqlx::Error> {
-        sqlx::query_as::<_, Preparation>(
-            "SELECT id, name, prep_type, shift, location, picture_url, steps, created_at, updated_at
-             FROM preparations
-             ORDER BY prep_type, name"
+    /// Record the placeholder of the product's picture
+    pub async fn set_lqip<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        id: Uuid,
+        lqip: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE products SET lqip = $2 WHERE id = $1")
+            .bind(id)
+            .bind(lqip)
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+
+    /// Every supplier name products use, alphabetically (accepts a pool or a transaction)
+    pub async fn supplier_names<'e, E: sqlx::PgExecutor<'e>>(executor: E) -> Result<Vec<String>, sqlx::Error> {
+        sqlx::query_scalar("SELECT DISTINCT supplier_name FROM products ORDER BY supplier_name")
+            .fetch_all(executor)
+            .await
+    }
+
+    /// Move every product from one supplier name to another, returning how many rows changed
+    ///
+    /// The supplier's ordering schedule moves too, unless the target already has one.
+    /// Accepts a pool, a connection or a transaction.
+    pub async fn merge_suppliers<'a, A>(db: A, from: &str, to: &str) -> Result<u64, sqlx::Error>
+    where
+        A: sqlx::Acquire<'a, Database = sqlx::Postgres>,
+    {
+        let mut tx = db.begin().await?;
+
+        let result = sqlx::query("UPDATE products SET supplier_name = $2 WHERE supplier_name = $1")
+            .bind(from)
+            .bind(to)
+            .execute(&mut *tx)
+            .await?;
+        // The ordering schedule follows the products, unless the target already has one
+        sqlx::query(
+            "UPDATE supplier_schedules SET supplier_name = $2
+             WHERE supplier_name = $1
+               AND NOT EXISTS (SELECT 1 FROM supplier_schedules WHERE supplier_name = $2)"
         )
-        .fetch_all(pool)
-        .await
+        .bind(from)
+        .bind(to)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("DELETE FROM supplier_schedules WHERE supplier_name = $1")
+            .bind(from)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(result.rows_affected())
     }
 
-    /// Get a single preparation by ID
-    pub async fn get_by_id(pool: &sqlx::PgPool, id: Uuid) -> Result<Option<Preparation>, sqlx::Error> {
-        sqlx::query_as::<_, Preparation>(
-            "SELECT id, name, prep_type, shift, location, picture_url, steps, created_at, updated_at
-             FROM preparations
+    /// The products either side of `id` in the homepage's order (newest first)
+    ///
+    /// Previous is the newer neighbour, next the older one; either is None at
+    /// the ends of the list, and both are for an unknown id.
+    pub async fn get_adjacent<'e, E: sqlx::PgExecutor<'e>>(executor: E, id: Uuid) -> Result<AdjacentProducts, sqlx::Error> {
+        let adjacent = sqlx::query_as::<_, AdjacentProducts>(
+            "SELECT previous, next FROM (
+                 SELECT id, LAG(id) OVER list AS previous, LEAD(id) OVER list AS next
+                 FROM products
+                 WHERE status = 'published'
+                 WINDOW list AS (ORDER BY created_at DESC, id DESC)
+             ) ordered
              WHERE id = $1"
         )
         .bind(id)
-        .fetch_optional(pool)
-        .await
+        .fetch_optional(executor)
+        .await?;
+        Ok(adjacent.unwrap_or_default())
     }
 
-    /// Create a new preparation
-    pub async fn create(
-        pool: &sqlx::PgPool,
-        name: &str,
-        prep_type: &str,
-        shift: &str,
-        location: &str,
-        picture_url: &str,
-        steps: &str,
-    ) -> Result<Preparation, sqlx::Error> {
-        sqlx::query_as::<_, Preparation>(
-            "INSERT INTO preparations (name, prep_type, shift, location, picture_url, steps)
-             VALUES ($1, $2, $3, $4, $5, $6)
-             RETURNING id, name, prep_type, shift, location, picture_url, steps, created_at, updated_at"
+    /// Create a draft product for each photo, in one statement, returning their ids in order
+    ///
+    /// Drafts have just the picture and a placeholder name; supplier, location and
+    /// description are left blank for the edit form. Pass a transaction to add
+    /// other writes to the batch.
+    pub async fn create_photo_drafts<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        drafts: &[PhotoDraft],
+    ) -> Result<Vec<Uuid>, sqlx::Error> {
+        let names: Vec<&str> = drafts.iter().map(|draft| draft.product_name.as_str()).collect();
+        let picture_urls: Vec<&str> = drafts.iter().map(|draft| draft.picture_url.as_str()).collect();
+        let webp_urls: Vec<Option<&str>> = drafts.iter().map(|draft| draft.webp_url.as_deref()).collect();
+        let lqips: Vec<Option<&str>> = drafts.iter().map(|draft| draft.lqip.as_deref()).collect();
+        let sizes: Vec<i64> = drafts.iter().map(|draft| draft.picture_size_bytes).collect();
+        sqlx::query_scalar(
+            "WITH created AS (
+                 INSERT INTO products (supplier_name, product_name, location, description, picture_url, webp_url, lqip,
+                                       picture_size_bytes, picture_uploaded_at, status)
+                 SELECT '', name, '', '', picture_url, webp_url, lqip, size, CURRENT_TIMESTAMP, 'draft'
+                 FROM UNNEST($1::TEXT[], $2::TEXT[], $3::TEXT[], $4::BIGINT[], $5::TEXT[])
+                     AS photo(name, picture_url, webp_url, size, lqip)
+                 RETURNING id, picture_url
+             )
+             SELECT created.id
+             FROM created
+             JOIN UNNEST($2::TEXT[]) WITH ORDINALITY AS photo(picture_url, position) USING (picture_url)
+             ORDER BY photo.position"
         )
-        .bind(name)
-        .bind(prep_type)
-        .bind(shift)
-        .bind(location)
-        .bind(picture_url)
-        .bind(steps)
-        .fetch_one(pool)
+        .bind(names)
+        .bind(picture_urls)
+        .bind(webp_urls)
+        .bind(sizes)
+        .bind(lqips)
+        .fetch_all(executor)
         .await
     }
 
-    /// Update an existing preparation
-    pub async fn update(
-        pool: &sqlx::PgPool,
-        id: Uuid,
-        name: &str,
-        prep_type: &str,
-        shift: &str,
-        location: &str,
-        picture_url: &str,
-        steps: &str,
-    ) -> Result<Preparation, sqlx::Error> {
-        sqlx::query_as::<_, Preparation>(
-            "UPDATE preparations
-             SET name = $2, prep_type = $3, shift = $4, location = $5, picture_url = $6, steps = $7, updated_at = CURRENT_TIMESTAMP
-             WHERE id = $1
-             RETURNING id, name, prep_type, shift, location, picture_url, steps, created_at, updated_at"
+    /// Delete drafts created more than `max_age_days` ago, returning how many
+    /// there were and the images they used
+    ///
+    /// The images aren't deleted here; pass them to `storage::delete_if_unreferenced`.
+    pub async fn delete_stale_drafts<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        max_age_days: i32,
+    ) -> Result<(usize, Vec<String>), sqlx::Error> {
+        let deleted: Vec<(Option<String>, Option<String>)> = sqlx::query_as(
+            "DELETE FROM products
+             WHERE status = 'draft' AND created_at < NOW() - make_interval(days => $1)
+             RETURNING picture_url, webp_url"
         )
-        .bind(id)
-        .bind(name)
-        .bind(prep_type)
-        .bind(shift)
-        .bind(location)
-        .bind(picture_url)
-        .bind(steps)
-        .fetch_one(pool)
-        .await
+        .bind(max_age_days)
+        .fetch_all(executor)
+        .await?;
+        let images = deleted.iter().flat_map(|(picture_url, webp_url)| picture_url.iter().chain(webp_url)).cloned().collect();
+        Ok((deleted.len(), images))
     }
-}
 
-/// Database model for PreparationStep
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
-pub struct PreparationStep {
-    pub id: Uuid,
-    pub preparation_id: Uuid,
-    pub step_number: i32,
-    pub description: String,
-    pub picture_url: String,
-    pub created_at: DateTime<Utc>,
-}
+    /// A product's tags, alphabetically (accepts a pool or a transaction)
+    pub async fn tags<'e, E: sqlx::PgExecutor<'e>>(executor: E, product_id: Uuid) -> Result<Vec<String>, sqlx::Error> {
+        sqlx::query_scalar("SELECT tag FROM product_tags WHERE product_id = $1 ORDER BY tag")
+            .bind(product_id)
+            .fetch_all(executor)
+            .await
+    }
 
-/// Database operations for PreparationStep
-impl PreparationStep {
-    /// Get all steps for a preparation
-    pub async fn get_by_preparation_id(
-        pool: &sqlx::PgPool,
-        preparation_id: Uuid,
-    ) -> Result<Vec<PreparationStep>, sqlx::Error> {
-        sqlx::query_as::<_, PreparationStep>(
-            "SELECT id, preparation_id, step_number, description, picture_url, created_at
-             FROM preparation_steps
-             WHERE preparation_id = $1
-             ORDER BY step_number ASC"
+    /// Add or remove normalised `tags` on every product in `ids`, in one transaction
+    ///
+    /// Ids with no product are skipped and listed in the result. Adding a tag a
+    /// product already has (or removing one it hasn't) doesn't count as a change.
+    /// Accepts a pool, a connection or a transaction.
+    pub async fn apply_tags<'a, A>(db: A, ids: &[Uuid], tags: &[String], mode: TagMode) -> Result<TagChange, sqlx::Error>
+    where
+        A: sqlx::Acquire<'a, Database = sqlx::Postgres>,
+    {
+        let mut tx = db.begin().await?;
+
+        // Locked so a product deleted meanwhile can't make the insert fail halfway
+        let found: Vec<Uuid> = sqlx::query_scalar("SELECT id FROM products WHERE id = ANY($1) FOR SHARE")
+            .bind(ids)
+            .fetch_all(&mut *tx)
+            .await?;
+        let query = match mode {
+            TagMode::Add => sqlx::query(
+                "INSERT INTO product_tags (product_id, tag)
+                 SELECT product_id, tag FROM UNNEST($1::uuid[]) AS product_id CROSS JOIN UNNEST($2::text[]) AS tag
+                 ON CONFLICT DO NOTHING"
+            ),
+            TagMode::Remove => sqlx::query("DELETE FROM product_tags WHERE product_id = ANY($1) AND tag = ANY($2)"),
+        };
+        let result = query.bind(&found).bind(tags).execute(&mut *tx).await?;
+
+        tx.commit().await?;
+        let mut not_found: Vec<Uuid> = Vec::new();
+        for id in ids {
+            if !found.contains(id) && !not_found.contains(id) {
+                not_found.push(*id);
+            }
+        }
+        Ok(TagChange { products: found.len() as u64, changed: result.rows_affected(), not_found })
+    }
+
+    /// Why `source` can't be merged into `target`, if it can't
+    pub fn merge_problem(source: &Product, target: &Product) -> Option<&'static str> {
+        if source.id == target.id {
+            Some("A product can't be merged into itself")
+        } else if source.is_merged() {
+            Some("That product has already been merged into another")
+        } else if target.is_merged() {
+            Some("The product to keep has itself been merged into another")
+        } else if target.is_draft() {
+            Some("The product to keep is a draft; save its details first")
+        } else {
+            None
+        }
+    }
+
+    /// What merging `source` into `target` would move, for the preview
+    ///
+    /// `copy_picture` asks for the duplicate's picture when the target has none.
+    pub async fn merge_preview<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        source: &Product,
+        target: &Product,
+        copy_picture: bool,
+    ) -> Result<ProductMerge, sqlx::Error> {
+        let (tags, price_entries, audit_entries, documents): (Vec<String>, i64, i64, i64) = sqlx::query_as(
+            "SELECT ARRAY(SELECT tag FROM product_tags
+                          WHERE product_id = $1
+                            AND tag NOT IN (SELECT tag FROM product_tags WHERE product_id = $2)
+                          ORDER BY tag),
+                    (SELECT COUNT(*) FROM product_price_history WHERE product_id = $1),
+                    (SELECT COUNT(*) FROM product_audit WHERE product_id = $1),
+                    (SELECT COUNT(*) FROM product_documents WHERE product_id = $1)"
         )
-        .bind(preparation_id)
-        .fetch_all(pool)
-        .await
+        .bind(source.id)
+        .bind(target.id)
+        .fetch_one(executor)
+        .await?;
+        Ok(ProductMerge {
+            tags,
+            price_entries,
+            audit_entries,
+            documents,
+            picture: copy_picture && source.picture_url.is_some() && target.picture_url.is_none(),
+            barcode: source.barcode.is_some() && target.barcode.is_none(),
+        })
     }
 
-    /// Create a new preparation step
-    pub async fn create(
-        pool: &sqlx::PgPool,
-        preparation_id: Uuid,
-        step_number: i32,
-        description: &str,
-        picture_url: &str,
-    ) -> Result<PreparationStep, sqlx::Error> {
-        sqlx::query_as::<_, PreparationStep>(
-            "INSERT INTO preparation_steps (preparation_id, step_number, description, picture_url)
-             VALUES ($1, $2, $3, $4)
-             RETURNING id, preparation_id, step_number, description, picture_url, created_at"
+    /// Merge the duplicate `source` into `target`, in one transaction
+    ///
+    /// The duplicate's tags, price history, audit trail and documents move to
+    /// the target, as do its barcode and (with `copy_picture`) its picture when
+    /// the target has none. The duplicate is kept with status `merged` so its
+    /// page can redirect to the target. A change to the target's own fields is
+    /// recorded in its audit trail. Accepts a pool, a connection or a transaction.
+    pub async fn merge_into<'a, A>(
+        db: A,
+        source_id: Uuid,
+        target_id: Uuid,
+        copy_picture: bool,
+        user_id: Option<Uuid>,
+        username: Option<&str>,
+    ) -> Result<ProductMerge, ProductMergeError>
+    where
+        A: sqlx::Acquire<'a, Database = sqlx::Postgres>,
+    {
+        let mut tx = db.begin().await?;
+
+        // Locked in id order, so two merges of the same pair can't deadlock
+        sqlx::query("SELECT id FROM products WHERE id = ANY($1) ORDER BY id FOR UPDATE")
+            .bind(vec![source_id, target_id])
+            .execute(&mut *tx)
+            .await?;
+        let source = Product::get_by_id(&mut *tx, source_id).await?.ok_or(ProductMergeError::NotFound(source_id))?;
+        let target = Product::get_by_id(&mut *tx, target_id).await?.ok_or(ProductMergeError::NotFound(target_id))?;
+        if let Some(problem) = Product::merge_problem(&source, &target) {
+            return Err(ProductMergeError::Invalid(problem));
+        }
+        let merge = Product::merge_preview(&mut *tx, &source, &target, copy_picture).await?;
+
+        sqlx::query(
+            "INSERT INTO product_tags (product_id, tag, created_at)
+             SELECT $2, tag, created_at FROM product_tags WHERE product_id = $1
+             ON CONFLICT DO NOTHING"
         )
-        .bind(preparation_id)
-        .bind(step_number)
-        .bind(description)
-        .bind(picture_url)
-        .fetch_one(pool)
-        .await
+        .bind(source.id)
+        .bind(target.id)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("DELETE FROM product_tags WHERE product_id = $1")
+            .bind(source.id)
+            .execute(&mut *tx)
+            .await?;
+        for table in ["product_price_history", "product_audit", "product_documents"] {
+            sqlx::query(&format!("UPDATE {} SET product_id = $2 WHERE product_id = $1", table))
+                .bind(source.id)
+                .bind(target.id)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        // Barcodes are unique, so the duplicate lets go of its own first
+        sqlx::query(
+            "UPDATE products
+             SET status = 'merged', merged_into = $2, merged_at = CURRENT_TIMESTAMP,
+                 barcode = CASE WHEN $3 THEN NULL ELSE barcode END
+             WHERE id = $1"
+        )
+        .bind(source.id)
+        .bind(target.id)
+        .bind(merge.barcode)
+        .execute(&mut *tx)
+        .await?;
+        // Anything merged into the duplicate before now goes straight to the target
+        sqlx::query("UPDATE products SET merged_into = $2 WHERE merged_into = $1")
+            .bind(source.id)
+            .bind(target.id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            "UPDATE products
+             SET barcode = CASE WHEN $3 THEN $4 ELSE barcode END,
+                 picture_url = CASE WHEN $5 THEN $6 ELSE picture_url END,
+                 webp_url = CASE WHEN $5 THEN $7 ELSE webp_url END,
+                 lqip = CASE WHEN $5 THEN $8 ELSE lqip END,
+                 picture_size_bytes = CASE WHEN $5 THEN (SELECT picture_size_bytes FROM products WHERE id = $1) ELSE picture_size_bytes END,
+                 picture_uploaded_at = CASE WHEN $5 THEN (SELECT picture_uploaded_at FROM products WHERE id = $1) ELSE picture_uploaded_at END
+             WHERE id = $2 AND ($3 OR $5)"
+        )
+        .bind(source.id)
+        .bind(target.id)
+        .bind(merge.barcode)
+        .bind(&source.barcode)
+        .bind(merge.picture)
+        .bind(&source.picture_url)
+        .bind(&source.webp_url)
+        .bind(&source.lqip)
+        .execute(&mut *tx)
+        .await?;
+
+        // Both sides get an audit row naming the other, whether or not any field was filled in
+        let merged = Product::get_by_id(&mut *tx, target.id).await?.ok_or(ProductMergeError::NotFound(target.id))?;
+        ProductAudit::record(
+            &mut *tx,
+            target.id,
+            user_id,
+            username,
+            &ProductSnapshot::from(&target),
+            &ProductSnapshot { merged_from: Some(source.id), ..ProductSnapshot::from(&merged) },
+        )
+        .await?;
+        let source_snapshot = ProductSnapshot::from(&source);
+        ProductAudit::record(
+            &mut *tx,
+            source.id,
+            user_id,
+            username,
+            &source_snapshot,
+            &ProductSnapshot { merged_into: Some(target.id), ..source_snapshot.clone() },
+        )
+        .await?;
+
+        tx.commit().await?;
+        Ok(merge)
     }
+}
 
-    /// Delete all steps for a preparation
-    pub async fn delete_by_preparation_id(
-        pool: &sqlx::PgPool,
-        preparation_id: Uuid,
+/// Neighbouring product ids for the Prev/Next links on a product's page
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, FromRow)]
+pub struct AdjacentProducts {
+    pub previous: Option<Uuid>,
+    pub next: Option<Uuid>,
+}
+
+/// Whether a bulk tag request adds its tags or strips them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TagMode {
+    #[default]
+    Add,
+    Remove,
+}
+
+/// What `Product::apply_tags` did
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TagChange {
+    /// Products found and tagged (or untagged)
+    pub products: u64,
+    /// Product/tag pairs created, or removed in remove mode
+    pub changed: u64,
+    /// Requested ids with no product
+    pub not_found: Vec<Uuid>,
+}
+
+/// What merging a duplicate product into another moves, as previewed and as done
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ProductMerge {
+    /// Tags the target gains; ones it already has are dropped from the duplicate
+    pub tags: Vec<String>,
+    pub price_entries: i64,
+    pub audit_entries: i64,
+    pub documents: i64,
+    /// The duplicate's picture is copied to a target without one
+    pub picture: bool,
+    /// The duplicate's barcode moves to a target without one
+    pub barcode: bool,
+}
+
+/// Why a product merge was rolled back
+#[derive(Debug)]
+pub enum ProductMergeError {
+    Database(sqlx::Error),
+    /// No product has this id
+    NotFound(Uuid),
+    /// These two can't be merged; see `Product::merge_problem`
+    Invalid(&'static str),
+}
+
+impl From<sqlx::Error> for ProductMergeError {
+    fn from(err: sqlx::Error) -> Self {
+        ProductMergeError::Database(err)
+    }
+}
+
+impl std::fmt::Display for ProductMergeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProductMergeError::Database(err) => write!(f, "database error: {}", err),
+            ProductMergeError::NotFound(id) => write!(f, "no product {}", id),
+            ProductMergeError::Invalid(problem) => f.write_str(problem),
+        }
+    }
+}
+
+/// The editable fields of a product as stored in its audit trail
+///
+/// Unlike `Product`'s own JSON, the picture is kept as stored (no placeholder).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProductSnapshot {
+    pub supplier_name: String,
+    pub product_name: String,
+    pub location: String,
+    pub barcode: Option<String>,
+    pub picture_url: Option<String>,
+    pub description: String,
+    pub unit: Option<String>,
+    pub last_price_cents: Option<i64>,
+    pub storage_temp_min_c: Option<f64>,
+    pub storage_temp_max_c: Option<f64>,
+    pub handling_notes: Option<String>,
+    /// Set only on a merge's audit rows: the product a duplicate was merged into
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub merged_into: Option<Uuid>,
+    /// Set only on a merge's audit rows: the duplicate merged into this product
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub merged_from: Option<Uuid>,
+}
+
+impl From<&Product> for ProductSnapshot {
+    fn from(product: &Product) -> Self {
+        ProductSnapshot {
+            supplier_name: product.supplier_name.clone(),
+            product_name: product.product_name.clone(),
+            location: product.location.clone(),
+            barcode: product.barcode.clone(),
+            picture_url: product.picture_url.clone(),
+            description: product.description.clone(),
+            unit: product.unit.clone(),
+            last_price_cents: product.last_price_cents,
+            storage_temp_min_c: product.storage_temp_min_c,
+            storage_temp_max_c: product.storage_temp_max_c,
+            handling_notes: product.handling_notes.clone(),
+            merged_into: None,
+            merged_from: None,
+        }
+    }
+}
+
+/// One field that differs between two product snapshots
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProductFieldChange {
+    pub field: &'static str,
+    /// Empty when the field had no value
+    pub before: String,
+    pub after: String,
+}
+
+impl ProductSnapshot {
+    /// The fields that differ from `before` to `self`, labelled for display
+    pub fn changes_from(&self, before: &ProductSnapshot) -> Vec<ProductFieldChange> {
+        let fields = [
+            ("Supplier", &before.supplier_name, &self.supplier_name),
+            ("Product name", &before.product_name, &self.product_name),
+            ("Location", &before.location, &self.location),
+            ("Description", &before.description, &self.description),
+        ];
+        let price = |cents: Option<i64>| cents.map(crate::utils::format_price_cents);
+        let degrees = |degrees: Option<f64>| degrees.map(|degrees| format!("{} °C", degrees));
+        let optional_fields = [
+            ("Barcode", before.barcode.clone(), self.barcode.clone()),
+            ("Picture", before.picture_url.clone(), self.picture_url.clone()),
+            ("Unit", before.unit.clone(), self.unit.clone()),
+            ("Price", price(before.last_price_cents), price(self.last_price_cents)),
+            ("Min storage temperature", degrees(before.storage_temp_min_c), degrees(self.storage_temp_min_c)),
+            ("Max storage temperature", degrees(before.storage_temp_max_c), degrees(self.storage_temp_max_c)),
+            ("Handling notes", before.handling_notes.clone(), self.handling_notes.clone()),
+            ("Merged into", before.merged_into.map(|id| id.to_string()), self.merged_into.map(|id| id.to_string())),
+            ("Merged from", before.merged_from.map(|id| id.to_string()), self.merged_from.map(|id| id.to_string())),
+        ];
+
+        fields
+            .into_iter()
+            .map(|(field, before, after)| (field, before.clone(), after.clone()))
+            .chain(optional_fields.into_iter().map(|(field, before, after)| {
+                (field, before.unwrap_or_default(), after.unwrap_or_default())
+            }))
+            .filter(|(_, before, after)| before != after)
+            .map(|(field, before, after)| ProductFieldChange { field, before, after })
+            .collect()
+    }
+}
+
+/// One recorded product update, newest first on the history page
+#[derive(Debug, Clone, FromRow)]
+pub struct ProductAudit {
+    pub id: Uuid,
+    /// Who made the change (user_id is also stored, but is None for kiosk sessions)
+    pub username: Option<String>,
+    /// JSON `ProductSnapshot`s, read as text
+    pub before: String,
+    pub after: String,
+    pub changed_at: DateTime<Utc>,
+}
+
+impl ProductAudit {
+    /// Record an update to a product; run it in the same transaction as the update
+    pub async fn record<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        product_id: Uuid,
+        user_id: Option<Uuid>,
+        username: Option<&str>,
+        before: &ProductSnapshot,
+        after: &ProductSnapshot,
     ) -> Result<(), sqlx::Error> {
-        sqlx::query("DELETE FROM preparation_steps WHERE preparation_id = $1")
-            .bind(preparation_id)
-            .execute(pool)
-            .await?;
+        let before = serde_json::to_string(before).map_err(|e| sqlx::Error::Protocol(e.to_string()))?;
+        let after = serde_json::to_string(after).map_err(|e| sqlx::Error::Protocol(e.to_string()))?;
+        sqlx::query(
+            "INSERT INTO product_audit (product_id, user_id, username, before, after)
+             VALUES ($1, $2, $3, $4::jsonb, $5::jsonb)"
+        )
+        .bind(product_id)
+        .bind(user_id)
+        .bind(username)
+        .bind(before)
+        .bind(after)
+        .execute(executor)
+        .await?;
         Ok(())
     }
+
+    /// Every recorded update to a product, newest first
+    pub async fn for_product<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        product_id: Uuid,
+    ) -> Result<Vec<ProductAudit>, sqlx::Error> {
+        sqlx::query_as::<_, ProductAudit>(
+            "SELECT id, username, before::text AS before, after::text AS after, changed_at
+             FROM product_audit
+             WHERE product_id = $1
+             ORDER BY changed_at DESC, id"
+        )
+        .bind(product_id)
+        .fetch_all(executor)
+        .await
+    }
+
+    /// Updates made from `from` up to (not including) `to`, for the audit export
+    ///
+    /// Grouped by product (in name order), oldest first within each. Rows are
+    /// streamed, so a year of changes isn't held in memory at once.
+    pub fn stream_between<'e, E: sqlx::PgExecutor<'e> + 'e>(
+        executor: E,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> futures_util::stream::BoxStream<'e, Result<ProductAuditEntry, sqlx::Error>> {
+        sqlx::query_as::<_, ProductAuditEntry>(
+            "SELECT a.product_id, p.supplier_name, p.product_name,
+                    a.id, a.username, a.before::text AS before, a.after::text AS after, a.changed_at
+             FROM product_audit a
+             JOIN products p ON p.id = a.product_id
+             WHERE a.changed_at >= $1 AND a.changed_at < $2
+             ORDER BY p.product_name, p.supplier_name, a.product_id, a.changed_at, a.id"
+        )
+        .bind(from)
+        .bind(to)
+        .fetch(executor)
+    }
+
+    /// The fields this update changed; unreadable snapshots count as empty
+    pub fn changes(&self) -> Vec<ProductFieldChange> {
+        let parse = |json: &str| {
+            serde_json::from_str::<ProductSnapshot>(json)
+                .map_err(|e| eprintln!("Ignoring unreadable product audit {}: {}", self.id, e))
+                .unwrap_or_default()
+        };
+        parse(&self.after).changes_from(&parse(&self.before))
+    }
 }
 
-/// Database model for User
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
-pub struct User {
+/// A recorded product update with the product it belongs to, for the audit export
+#[derive(Debug, Clone, FromRow)]
+pub struct ProductAuditEntry {
+    pub product_id: Uuid,
+    /// The product's current supplier and name, which the update may have changed
+    pub supplier_name: String,
+    pub product_name: String,
+    #[sqlx(flatten)]
+    pub audit: ProductAudit,
+}
+
+/// One price recorded for a product
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct ProductPrice {
+    pub price_cents: i64,
+    pub unit: Option<String>,
+    pub recorded_by: Option<String>,
+    pub recorded_at: DateTime<Utc>,
+}
+
+impl ProductPrice {
+    /// The most recent prices recorded for a product, newest first
+    pub async fn for_product<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        product_id: Uuid,
+        limit: i64,
+    ) -> Result<Vec<ProductPrice>, sqlx::Error> {
+        sqlx::query_as::<_, ProductPrice>(
+            "SELECT price_cents, unit, recorded_by, recorded_at
+             FROM product_price_history
+             WHERE product_id = $1
+             ORDER BY recorded_at DESC, id
+             LIMIT $2"
+        )
+        .bind(product_id)
+        .bind(limit)
+        .fetch_all(executor)
+        .await
+    }
+
+    /// The price for display, e.g. "$12.50"
+    pub fn price_label(&self) -> String {
+        crate::utils::format_price_cents(self.price_cents)
+    }
+}
+
+/// A document attached to a product, like a supplier's spec sheet
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct ProductDocument {
     pub id: Uuid,
-    pub username: String,
-    pub email: String,
-    pub password_hash: String,
-    pub is_active: bool,
+    pub product_id: Uuid,
+    /// Name it was uploaded as, offered again on download
+    pub filename: String,
+    /// `DocumentKind` extension, e.g. `pdf`
+    pub doc_type: String,
+    /// `s3` or `local`; see `storage::StoredDocument`
+    #[serde(skip_serializing)]
+    pub storage: String,
+    #[serde(skip_serializing)]
+    pub storage_key: String,
+    pub size_bytes: i64,
+    pub uploaded_by: Option<String>,
     pub created_at: DateTime<Utc>,
-    pub updated_at: DateTime<Utc>,
 }
 
-/// Form data for user login
-#[derive(Debug, Deserialize)]
-pub struct LoginForm {
-    pub username: String,
-    pub password: String,
+impl ProductDocument {
+    /// A product's documents, oldest first (accepts a pool or a transaction)
+    pub async fn for_product<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        product_id: Uuid,
+    ) -> Result<Vec<ProductDocument>, sqlx::Error> {
+        sqlx::query_as::<_, ProductDocument>(
+            "SELECT id, product_id, filename, doc_type, storage, storage_key, size_bytes, uploaded_by, created_at
+             FROM product_documents
+             WHERE product_id = $1
+             ORDER BY created_at, id"
+        )
+        .bind(product_id)
+        .fetch_all(executor)
+        .await
+    }
+
+    /// One of a product's documents
+    pub async fn get<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        product_id: Uuid,
+        id: Uuid,
+    ) -> Result<Option<ProductDocument>, sqlx::Error> {
+        sqlx::query_as::<_, ProductDocument>(
+            "SELECT id, product_id, filename, doc_type, storage, storage_key, size_bytes, uploaded_by, created_at
+             FROM product_documents
+             WHERE product_id = $1 AND id = $2"
+        )
+        .bind(product_id)
+        .bind(id)
+        .fetch_optional(executor)
+        .await
+    }
+
+    /// Record a document stored for a product
+    #[allow(clippy::too_many_arguments)]
+    pub async fn create<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        product_id: Uuid,
+        filename: &str,
+        doc_type: &str,
+        storage: &str,
+        storage_key: &str,
+        size_bytes: i64,
+        uploaded_by: Option<&str>,
+    ) -> Result<ProductDocument, sqlx::Error> {
+        sqlx::query_as::<_, ProductDocument>(
+            "INSERT INTO product_documents (product_id, filename, doc_type, storage, storage_key, size_bytes, uploaded_by)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)
+             RETURNING id, product_id, filename, doc_type, storage, storage_key, size_bytes, uploaded_by, created_at"
+        )
+        .bind(product_id)
+        .bind(filename)
+        .bind(doc_type)
+        .bind(storage)
+        .bind(storage_key)
+        .bind(size_bytes)
+        .bind(uploaded_by)
+        .fetch_one(executor)
+        .await
+    }
+
+    /// Remove one of a product's documents, returning it so its file can be deleted too
+    pub async fn delete<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        product_id: Uuid,
+        id: Uuid,
+    ) -> Result<Option<ProductDocument>, sqlx::Error> {
+        sqlx::query_as::<_, ProductDocument>(
+            "DELETE FROM product_documents
+             WHERE product_id = $1 AND id = $2
+             RETURNING id, product_id, filename, doc_type, storage, storage_key, size_bytes, uploaded_by, created_at"
+        )
+        .bind(product_id)
+        .bind(id)
+        .fetch_optional(executor)
+        .await
+    }
+
+    /// The document's size for display, e.g. "1.2 MB"
+    pub fn size_label(&self) -> String {
+        crate::utils::format_bytes(self.size_bytes)
+    }
 }
 
-/// Form data for user registration
-#[derive(Debug, Deserialize)]
-pub struct RegisterForm {
-    pub username: String,
-    pub email: String,
-    pub password: String,
-    pub confirm_password: String,
+/// A recorded price that replaced an earlier one, for the admin price report
+#[derive(Debug, Clone, FromRow)]
+pub struct PriceChange {
+    pub product_id: Uuid,
+    pub supplier_name: String,
+    pub product_name: String,
+    pub unit: Option<String>,
+    pub previous_cents: i64,
+    pub price_cents: i64,
+    pub recorded_by: Option<String>,
+    pub recorded_at: DateTime<Utc>,
 }
 
-impl RegisterForm {
-    /// Validate the registration form
-    pub fn validate(&self) -> Result<(), String> {
-        if self.username.trim().is_empty() {
-            return Err("Username cannot be empty".to_string());
-        }
-        if self.username.len() < 3 {
-            return Err("Username must be at least 3 characters".to_string());
-        }
-        if self.username.len() > 50 {
-            return Err("Username cannot exceed 50 characters".to_string());
-        }
-        if !self.username.chars().all(|c| c.is_alphanumeric() || c == '_') {
-            return Err("Username can only contain letters, numbers, and underscores".to_string());
-        }
-        if self.email.trim().is_empty() {
-            return Err("Email cannot be empty".to_string());
-        }
-        if !self.email.contains('@') || !self.email.contains('.') {
-            return Err("Invalid email format".to_string());
-        }
-        if self.password.len() < 6 {
-            return Err("Password must be at least 6 characters".to_string());
+impl PriceChange {
+    /// Price changes recorded in the last `days` days, biggest percentage increase first
+    ///
+    /// A product's first recorded price isn't a change, so it is left out. Rises
+    /// from a price of zero have no percentage and come last.
+    pub async fn recent<'e, E: sqlx::PgExecutor<'e>>(executor: E, days: i32) -> Result<Vec<PriceChange>, sqlx::Error> {
+        sqlx::query_as::<_, PriceChange>(
+            "SELECT h.product_id, p.supplier_name, p.product_name, h.unit, h.previous_cents, h.price_cents,
+                    h.recorded_by, h.recorded_at
+             FROM (
+                 SELECT product_id, unit, price_cents, recorded_by, recorded_at,
+                        LAG(price_cents) OVER (PARTITION BY product_id ORDER BY recorded_at, id) AS previous_cents
+                 FROM product_price_history
+             ) h
+             JOIN products p ON p.id = h.product_id
+             WHERE h.previous_cents IS NOT NULL
+               AND h.recorded_at >= NOW() - make_interval(days => $1)
+             ORDER BY (h.price_cents - h.previous_cents)::float8 / NULLIF(h.previous_cents, 0) DESC NULLS LAST,
+                      h.recorded_at DESC"
+        )
+        .bind(days)
+        .fetch_all(executor)
+        .await
+    }
+
+    /// Change from the previous price as a percentage, or None when that was zero
+    pub fn percent_change(&self) -> Option<f64> {
+        (self.previous_cents != 0)
+            .then(|| (self.price_cents - self.previous_cents) as f64 * 100.0 / self.previous_cents as f64)
+    }
+
+    /// The change for display, e.g. "+12.5%", or "new" when the old price was zero
+    pub fn percent_label(&self) -> String {
+        match self.percent_change() {
+            Some(percent) => format!("{:+.1}%", percent),
+            None => "new".to_string(),
         }
-        if self.password != self.confirm_password {
-            return Err("Passwords do not match".to_string());
+    }
+
+    pub fn previous_label(&self) -> String {
+        crate::utils::format_price_cents(self.previous_cents)
+    }
+
+    pub fn price_label(&self) -> String {
+        crate::utils::format_price_cents(self.price_cents)
+    }
+}
+
+/// Product listing row for index and search pages, without the full description
+///
+/// Cards only show a few lines of the description, so just its opening is fetched.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct ProductSummary {
+    pub id: Uuid,
+    pub supplier_name: String,
+    pub product_name: String,
+    pub location: String,
+    #[serde(serialize_with = "serialize_picture_url")]
+    pub picture_url: Option<String>,
+    /// WebP copy of the picture, if `WEBP_CONVERT` made one
+    pub webp_url: Option<String>,
+    /// Placeholder shown until the picture loads; see `Product::lqip`
+    #[serde(skip_serializing)]
+    pub lqip: Option<String>,
+    /// First `DESCRIPTION_PREVIEW_CHARS` characters of the description
+    pub description_preview: String,
+}
+
+/// Characters of a product description fetched for listing cards
+const DESCRIPTION_PREVIEW_CHARS: i32 = 300;
+
+/// Minimum trigram similarity for a fuzzy name match (pg_trgm's own default)
+///
+/// Low enough that "tomatoe" finds "Diced Tomatoes" (0.44), high enough that
+/// unrelated short names don't match.
+const FUZZY_SEARCH_THRESHOLD: f32 = 0.3;
+
+/// Constraints on a search besides its text term, like "everything in Coolroom 2"
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchFilters {
+    /// Location to match exactly, ignoring case; applies to products and preparations
+    pub location: Option<String>,
+    /// One of `PREP_TYPES`; applies to preparations only
+    pub prep_type: Option<String>,
+}
+
+/// ILIKE pattern matching `term` anywhere, with its own `%`, `_` and `\`
+/// taken literally, so searching "50%" doesn't match every 50
+fn contains_pattern(term: &str) -> String {
+    let mut pattern = String::with_capacity(term.len() + 2);
+    pattern.push('%');
+    push_escaped_like(&mut pattern, term);
+    pattern.push('%');
+    pattern
+}
+
+/// ILIKE pattern matching values that start with `term`, escaped like
+/// [`contains_pattern`]
+fn prefix_pattern(term: &str) -> String {
+    let mut pattern = String::with_capacity(term.len() + 1);
+    push_escaped_like(&mut pattern, term);
+    pattern.push('%');
+    pattern
+}
+
+fn push_escaped_like(pattern: &mut String, term: &str) {
+    for c in term.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            pattern.push('\\');
         }
-        Ok(())
+        pattern.push(c);
     }
 }
 
-/// Database operations for User
-impl User {
-    /// Get a user by username
-    pub async fn get_by_username(
-        pool: &sqlx::PgPool,
-        username: &str,
-    ) -> Result<Option<User>, sqlx::Error> {
-        sqlx::query_as::<_, User>(
-            "SELECT id, username, email, password_hash, is_active, created_at, updated_at
-             FROM users
-             WHERE username = $1 AND is_active = true"
+/// A product or preparation that may be a duplicate of one about to be added
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct DuplicateCandidate {
+    pub id: Uuid,
+    pub name: String,
+    /// Set for products only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub supplier_name: Option<String>,
+    pub updated_at: DateTime<Utc>,
+    /// Trigram similarity of the names; 1.0 when they differ only in case or spacing
+    pub similarity: f32,
+}
+
+/// Set the `%` operator's similarity threshold for the rest of the transaction
+async fn set_similarity_threshold(tx: &mut sqlx::Transaction<'_, sqlx::Postgres>) -> Result<(), sqlx::Error> {
+    sqlx::query("SELECT set_config('pg_trgm.similarity_threshold', $1, true)")
+        .bind(FUZZY_SEARCH_THRESHOLD.to_string())
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+
+/// Database operations for ProductSummary
+impl ProductSummary {
+    /// Picture to display, falling back to the placeholder image
+    pub fn image_url(&self) -> &str {
+        self.picture_url.as_deref().unwrap_or(PLACEHOLDER_IMAGE_URL)
+    }
+
+    /// Get all published products, newest first
+    pub async fn get_all(pool: &sqlx::PgPool) -> Result<Vec<ProductSummary>, sqlx::Error> {
+        sqlx::query_as::<_, ProductSummary>(
+            "SELECT id, supplier_name, product_name, location, picture_url, webp_url, lqip, LEFT(description, $1) AS description_preview
+             FROM products
+             WHERE status = 'published'
+             ORDER BY created_at DESC, id DESC"
         )
-        .bind(username)
-        .fetch_optional(pool)
+        .bind(DESCRIPTION_PREVIEW_CHARS)
+        .fetch_all(pool)
         .await
     }
 
-    /// Get a user by email
-    pub async fn get_by_email(
-        pool: &sqlx::PgPool,
-        email: &str,
-    ) -> Result<Option<User>, sqlx::Error> {
-        sqlx::query_as::<_, User>(
-            "SELECT id, username, email, password_hash, is_active, created_at, updated_at
-             FROM users
-             WHERE email = $1 AND is_active = true"
+    /// Draft products from bulk photo uploads, newest first, waiting for their details
+    pub async fn drafts<'e, E: sqlx::PgExecutor<'e>>(executor: E) -> Result<Vec<ProductSummary>, sqlx::Error> {
+        sqlx::query_as::<_, ProductSummary>(
+            "SELECT id, supplier_name, product_name, location, picture_url, webp_url, lqip, LEFT(description, $1) AS description_preview
+             FROM products
+             WHERE status = 'draft'
+             ORDER BY created_at DESC, product_name DESC"
         )
-        .bind(email)
-        .fetch_optional(pool)
+        .bind(DESCRIPTION_PREVIEW_CHARS)
+        .fetch_all(executor)
         .await
     }
 
-    /// Get a user by ID
-    pub async fn get_by_id(
-        pool: &sqlx::PgPool,
-        id: Uuid,
-    ) -> Result<Option<User>, sqlx::Error> {
-        sqlx::query_as::<_, User>(
-            "SELECT id, username, email, password_hash, is_active, created_at, updated_at
-             FROM users
-             WHERE id = $1 AND is_active = true"
+    /// Products from any of `suppliers`, by supplier then name (accepts a pool or a transaction)
+    pub async fn for_suppliers<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        suppliers: &[String],
+    ) -> Result<Vec<ProductSummary>, sqlx::Error> {
+        sqlx::query_as::<_, ProductSummary>(
+            "SELECT id, supplier_name, product_name, location, picture_url, webp_url, lqip, LEFT(description, $2) AS description_preview
+             FROM products
+             WHERE supplier_name = ANY($1) AND status = 'published'
+             ORDER BY supplier_name, product_name"
         )
-        .bind(id)
-        .fetch_optional(pool)
+        .bind(suppliers)
+        .bind(DESCRIPTION_PREVIEW_CHARS)
+        .fetch_all(executor)
         .await
     }
 
-    /// Create a new user
-    pub async fn create(
-        pool: &sqlx::PgPool,
-        username: &str,
-        email: &str,
-        password_hash: &str,
-    ) -> Result<User, sqlx::Error> {
-        sqlx::query_as::<_, User>(
-            "INSERT INTO users (username, email, password_hash)
-             VALUES ($1, $2, $3)
-             RETURNING id, username, email, password_hash, is_active, created_at, updated_at"
+    /// Products whose name, supplier, location or description contain `term` (case-insensitive),
+    /// narrowed by `filters` (accepts a pool or a transaction)
+    pub async fn search<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        term: &str,
+        filters: &SearchFilters,
+    ) -> Result<Vec<ProductSummary>, sqlx::Error> {
+        sqlx::query_as::<_, ProductSummary>(
+            "SELECT id, supplier_name, product_name, location, picture_url, webp_url, lqip, LEFT(description, $2) AS description_preview
+             FROM products
+             WHERE status = 'published'
+               AND (product_name ILIKE $1
+                    OR supplier_name ILIKE $1
+                    OR location ILIKE $1
+                    OR description ILIKE $1)
+               AND ($3::TEXT IS NULL OR LOWER(location) = LOWER($3))
+             ORDER BY product_name"
         )
-        .bind(username)
-        .bind(email)
-        .bind(password_hash)
-        .fetch_one(pool)
+        .bind(contains_pattern(term))
+        .bind(DESCRIPTION_PREVIEW_CHARS)
+        .bind(filters.location.as_deref())
+        .fetch_all(executor)
         .await
     }
+
+    /// Like `search`, but also matching names within `FUZZY_SEARCH_THRESHOLD`
+    /// trigram similarity of `term`, closest names first
+    ///
+    /// Accepts a pool, a connection or a transaction; the threshold setting
+    /// is scoped to a transaction (or savepoint) opened here.
+    pub async fn search_fuzzy<'a, A>(
+        db: A,
+        term: &str,
+        filters: &SearchFilters,
+    ) -> Result<Vec<ProductSummary>, sqlx::Error>
+    where
+        A: sqlx::Acquire<'a, Database = sqlx::Postgres>,
+    {
+        let mut tx = db.begin().await?;
+        set_similarity_threshold(&mut tx).await?;
+
+        let products = sqlx::query_as::<_, ProductSummary>(
+            "SELECT id, supplier_name, product_name, location, picture_url, webp_url, lqip, LEFT(description, $3) AS description_preview
+             FROM products
+             WHERE status = 'published'
+               AND (product_name % $1
+                    OR supplier_name % $1
+                    OR product_name ILIKE $2
+                    OR supplier_name ILIKE $2
+                    OR location ILIKE $2
+                    OR description ILIKE $2)
+               AND ($4::TEXT IS NULL OR LOWER(location) = LOWER($4))
+             ORDER BY GREATEST(similarity(product_name, $1), similarity(supplier_name, $1)) DESC, product_name"
+        )
+        .bind(term)
+        .bind(contains_pattern(term))
+        .bind(DESCRIPTION_PREVIEW_CHARS)
+        .bind(filters.location.as_deref())
+        .fetch_all(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(products)
+    }
+}
+
+/// Database model for Preparation
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Preparation {
+    pub id: Uuid,
+    pub name: String,
+    pub prep_type: String,
+    pub shift: String,
+    pub location: String,
+    #[serde(serialize_with = "serialize_picture_url")]
+    pub picture_url: Option<String>,
+    /// WebP copy of the picture, if `WEBP_CONVERT` made one
+    pub webp_url: Option<String>,
+    pub prep_time_minutes: Option<i32>,
+    pub difficulty: Option<String>,
+    /// How much one batch makes, in `yield_unit`
+    pub yield_quantity: Option<f64>,
+    pub yield_unit: Option<String>,
+    /// `draft` or `published`; see `PREP_STATUS_DRAFT`
+    pub status: String,
+    /// When it was archived; archived preparations are left out of lists and search
+    pub archived_at: Option<DateTime<Utc>>,
+    /// Language it is written in, one of `i18n::SUPPORTED_LOCALES`
+    pub lang: String,
+    /// Shared by a preparation and its translations, one per language
+    pub translation_group: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// One language variant of a preparation, for the detail page's language switcher
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct PreparationTranslation {
+    pub id: Uuid,
+    pub name: String,
+    pub lang: String,
+}
+
+impl PreparationTranslation {
+    /// The language's name in that language, e.g. "Español"
+    pub fn language_name(&self) -> &str {
+        crate::i18n::locale_name(&self.lang)
+    }
+}
+
+/// Status of a preparation saved before its form was complete
+pub const PREP_STATUS_DRAFT: &str = "draft";
+
+/// Status of a preparation that passed full validation
+pub const PREP_STATUS_PUBLISHED: &str = "published";
+
+impl Timestamped for Preparation {
+    fn timestamps(&self) -> Vec<(&'static str, DateTime<Utc>)> {
+        let mut timestamps = vec![
+            ("created_at", self.created_at),
+            ("updated_at", self.updated_at),
+        ];
+        timestamps.extend(self.archived_at.map(|at| ("archived_at", at)));
+        timestamps
+    }
+}
+
+impl Preparation {
+    /// Whether this preparation is still a draft
+    pub fn is_draft(&self) -> bool {
+        self.status == PREP_STATUS_DRAFT
+    }
+
+    /// Whether this preparation is archived
+    pub fn is_archived(&self) -> bool {
+        self.archived_at.is_some()
+    }
+
+    /// The stored fields and `steps` as a form, so a draft can be checked against the form's validation
+    pub fn as_form(&self, steps: &[PreparationStep]) -> NewPreparationForm {
+        NewPreparationForm {
+            name: self.name.clone(),
+            prep_type: self.prep_type.clone(),
+            shift: self.shift.clone(),
+            location: self.location.clone(),
+            steps: steps.iter().map(|step| step.description.clone()).collect(),
+            prep_time_minutes: self.prep_time_minutes.map(|m| m.to_string()).unwrap_or_default(),
+            difficulty: self.difficulty.clone().unwrap_or_default(),
+            yield_quantity: self.yield_quantity.map(|q| q.to_string()).unwrap_or_default(),
+            yield_unit: self.yield_unit.clone().unwrap_or_default(),
+            lang: self.lang.clone(),
+        }
+    }
+
+    /// The yield as shown on the detail page, e.g. "2.5 L"
+    pub fn yield_label(&self) -> Option<String> {
+        let quantity = self.yield_quantity?;
+        Some(match self.yield_unit.as_deref() {
+            Some(unit) => format!("{} {}", quantity, unit),
+            None => quantity.to_string(),
+        })
+    }
+}
+
+/// Longest yield unit, in characters
+pub const MAX_YIELD_UNIT_CHARS: usize = 30;
+
+/// Most steps a preparation may have unless `MAX_STEPS` says otherwise
+pub const DEFAULT_MAX_STEPS: usize = 60;
+
+/// Longest step description, in characters
+pub const MAX_STEP_DESCRIPTION_CHARS: usize = 1000;
+
+/// Step limit from `MAX_STEPS`
+pub fn max_steps() -> usize {
+    crate::utils::env_usize("MAX_STEPS", DEFAULT_MAX_STEPS)
+}
+
+/// Problems with the number of steps and their lengths, naming steps by position from 1
+pub fn step_limit_problems(descriptions: &[&str], max_steps: usize) -> Vec<ValidationError> {
+    let mut problems = Vec::new();
+    if descriptions.len() > max_steps {
+        let over = if descriptions.len() == max_steps + 1 {
+            format!("Step {} is", descriptions.len())
+        } else {
+            format!("Steps {}-{} are", max_steps + 1, descriptions.len())
+        };
+        problems.push(ValidationError::new(
+            "steps",
+            "too_many",
+            format!(
+                "Too many steps: {} (the limit is {}). {} over the limit; merge or remove some.",
+                descriptions.len(),
+                max_steps,
+                over
+            ),
+        ));
+    }
+
+    let too_long: Vec<String> = descriptions
+        .iter()
+        .enumerate()
+        .filter(|(_, description)| description.trim().chars().count() > MAX_STEP_DESCRIPTION_CHARS)
+        .map(|(idx, _)| (idx + 1).to_string())
+        .collect();
+    if !too_long.is_empty() {
+        problems.push(ValidationError::new(
+            "steps",
+            "too_long",
+            format!(
+                "{} {} longer than {} characters: {}",
+                if too_long.len() == 1 { "Step" } else { "Steps" },
+                if too_long.len() == 1 { "is" } else { "are" },
+                MAX_STEP_DESCRIPTION_CHARS,
+                too_long.join(", ")
+            ),
+        ));
+    }
+    problems
+}
+
+/// Preparation types, in the order they're offered in forms and filters
+pub const PREP_TYPES: [&str; 5] = ["fruit", "bread", "veg", "meat", "seafood"];
+
+/// Shifts a preparation can belong to
+pub const SHIFTS: [&str; 3] = ["brekkie", "lunch", "both"];
+
+/// Difficulties a preparation can be marked with, easiest first
+pub const DIFFICULTIES: [&str; 3] = ["easy", "medium", "hard"];
+
+/// Prep times accepted, in minutes
+pub const PREP_TIME_MINUTES_RANGE: std::ops::RangeInclusive<i32> = 1..=600;
+
+/// Form data for creating a new preparation
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct NewPreparationForm {
+    pub name: String,
+    pub prep_type: String,
+    pub shift: String,
+    pub location: String,
+    /// Step descriptions in order, blank ones included
+    #[serde(default)]
+    pub steps: Vec<String>,
+    /// Minutes as typed into the form; empty when not given
+    pub prep_time_minutes: String,
+    /// One of easy/medium/hard; empty when not given
+    pub difficulty: String,
+    /// Yield as typed into the form; empty when not given
+    pub yield_quantity: String,
+    /// What the yield is counted in, e.g. "L" or "portions"
+    pub yield_unit: String,
+    /// Language code; empty means `PRIMARY_LANG`
+    #[serde(default)]
+    pub lang: String,
+}
+
+impl NewPreparationForm {
+    /// Validate the form data, requiring the location to be one of `allowed_locations`
+    /// and reporting every problem
+    pub fn validate_with_locations(&self, allowed_locations: &[String]) -> Result<(), Vec<ValidationError>> {
+        as_result(self.problems_with_locations(allowed_locations))
+    }
+
+    /// Every problem with the form apart from the location list check, in form order
+    pub fn problems(&self) -> Vec<ValidationError> {
+        let mut problems = Vec::new();
+        if self.name.trim().is_empty() {
+            problems.push(ValidationError::new("name", "required", "Preparation name cannot be empty"));
+        }
+        if !PREP_TYPES.contains(&self.prep_type.as_str()) {
+            problems.push(ValidationError::new("prep_type", "invalid_choice", "Invalid preparation type"));
+        }
+        if !SHIFTS.contains(&self.shift.as_str()) {
+            problems.push(ValidationError::new("shift", "invalid_choice", "Invalid shift selection"));
+        }
+        if self.location.trim().is_empty() {
+            problems.push(ValidationError::new("location", "required", "Location cannot be empty"));
+        }
+        if self.steps.iter().all(|step| step.trim().is_empty()) {
+            problems.push(ValidationError::new("steps", "required", "Steps cannot be empty"));
+        }
+        if !self.prep_time_minutes.trim().is_empty() && self.prep_time().is_none() {
+            problems.push(ValidationError::new(
+                "prep_time_minutes",
+                "out_of_range",
+                format!(
+                    "Prep time must be between {} and {} minutes",
+                    PREP_TIME_MINUTES_RANGE.start(),
+                    PREP_TIME_MINUTES_RANGE.end()
+                ),
+            ));
+        }
+        if !self.difficulty.is_empty() && !DIFFICULTIES.contains(&self.difficulty.as_str()) {
+            problems.push(ValidationError::new("difficulty", "invalid_choice", "Invalid difficulty"));
+        }
+        if !self.lang.is_empty() && !crate::i18n::SUPPORTED_LOCALES.contains(&self.lang.as_str()) {
+            problems.push(ValidationError::new("lang", "invalid_choice", "Invalid language"));
+        }
+        if !self.yield_quantity.trim().is_empty() && self.yield_quantity().is_none() {
+            problems.push(ValidationError::new("yield_quantity", "out_of_range", "Yield must be a positive number"));
+        }
+        if self.yield_unit.trim().chars().count() > MAX_YIELD_UNIT_CHARS {
+            problems.push(ValidationError::new(
+                "yield_unit",
+                "too_long",
+                format!("Yield unit must be at most {} characters", MAX_YIELD_UNIT_CHARS),
+            ));
+        }
+        problems
+    }
+
+    /// The form's fields and the limits `validate_with_locations` enforces
+    ///
+    /// The HTML form sends each step as its own field; `steps` stands for the list of them.
+    pub fn schema(allowed_locations: &[String]) -> Vec<FieldSchema> {
+        vec![
+            FieldSchema::new("name", "string").max_length(MAX_NAME_CHARS).required(),
+            FieldSchema::new("picture", "file"),
+            FieldSchema::choice("prep_type", &PREP_TYPES).required(),
+            FieldSchema::choice("shift", &SHIFTS).required(),
+            FieldSchema::new("prep_time_minutes", "integer")
+                .range(f64::from(*PREP_TIME_MINUTES_RANGE.start()), f64::from(*PREP_TIME_MINUTES_RANGE.end())),
+            FieldSchema::choice("difficulty", &DIFFICULTIES),
+            FieldSchema::choice("lang", &crate::i18n::SUPPORTED_LOCALES),
+            FieldSchema { min: Some(0.0), ..FieldSchema::new("yield_quantity", "number") },
+            FieldSchema::new("yield_unit", "string").max_length(MAX_YIELD_UNIT_CHARS),
+            FieldSchema::location(allowed_locations),
+            FieldSchema {
+                max_items: Some(max_steps()),
+                ..FieldSchema::new("steps", "list").max_length(MAX_STEP_DESCRIPTION_CHARS).required()
+            },
+        ]
+    }
+
+    /// `problems`, plus the location check from `validate_with_locations`
+    pub fn problems_with_locations(&self, allowed_locations: &[String]) -> Vec<ValidationError> {
+        let mut problems = self.problems();
+        if !self.location.trim().is_empty() {
+            if let Err(problem) = check_allowed_location(&self.location, allowed_locations) {
+                problems.push(problem);
+            }
+        }
+        problems
+    }
+
+    /// Validate the form for saving as a draft, which only needs a name
+    pub fn validate_draft(&self) -> Result<(), Vec<ValidationError>> {
+        if self.name.trim().is_empty() {
+            return Err(vec![ValidationError::new("name", "required", "Preparation name cannot be empty")]);
+        }
+        Ok(())
+    }
+
+    /// The form with any value the database would reject blanked out, so a
+    /// draft keeps everything else that was entered
+    pub fn for_draft(&self) -> NewPreparationForm {
+        let keep = |value: &str, allowed: &[&str]| {
+            if allowed.contains(&value) { value.to_string() } else { String::new() }
+        };
+        NewPreparationForm {
+            name: self.name.clone(),
+            prep_type: keep(&self.prep_type, &PREP_TYPES),
+            shift: keep(&self.shift, &SHIFTS),
+            location: self.location.clone(),
+            steps: self.steps.clone(),
+            prep_time_minutes: self.prep_time().map(|m| m.to_string()).unwrap_or_default(),
+            difficulty: keep(&self.difficulty, &DIFFICULTIES),
+            yield_quantity: self.yield_quantity().map(|q| q.to_string()).unwrap_or_default(),
+            yield_unit: self
+                .yield_unit()
+                .filter(|unit| unit.chars().count() <= MAX_YIELD_UNIT_CHARS)
+                .unwrap_or_default()
+                .to_string(),
+            lang: keep(&self.lang, &crate::i18n::SUPPORTED_LOCALES),
+        }
+    }
+
+    /// Prep time in minutes, if a valid one was entered
+    pub fn prep_time(&self) -> Option<i32> {
+        self.prep_time_minutes
+            .trim()
+            .parse()
+            .ok()
+            .filter(|minutes| PREP_TIME_MINUTES_RANGE.contains(minutes))
+    }
+
+    /// Difficulty, if one was selected
+    pub fn difficulty(&self) -> Option<&str> {
+        Some(self.difficulty.as_str()).filter(|d| !d.is_empty())
+    }
+
+    /// Yield quantity, if a positive number was entered
+    pub fn yield_quantity(&self) -> Option<f64> {
+        self.yield_quantity
+            .trim()
+            .parse()
+            .ok()
+            .filter(|quantity: &f64| quantity.is_finite() && *quantity > 0.0)
+    }
+
+    /// Yield unit, if one was entered
+    pub fn yield_unit(&self) -> Option<&str> {
+        Some(self.yield_unit.trim()).filter(|unit| !unit.is_empty())
+    }
+
+    /// The chosen language, or `PRIMARY_LANG` when none was
+    pub fn lang(&self) -> &str {
+        if self.lang.is_empty() { crate::i18n::primary_lang() } else { &self.lang }
+    }
+}
+
+/// Sort orders offered on the preparations index
+///
+/// Only these variants reach SQL, so the `?sort=` value never does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreparationSort {
+    Type,
+    Name,
+    Time,
+    Difficulty,
+}
+
+impl PreparationSort {
+    /// Parse the `?sort=` query value, falling back to the default order
+    pub fn from_param(param: Option<&str>) -> PreparationSort {
+        match param {
+            Some("name") => PreparationSort::Name,
+            Some("time") => PreparationSort::Time,
+            Some("difficulty") => PreparationSort::Difficulty,
+            _ => PreparationSort::Type,
+        }
+    }
+
+    /// Value used in `?sort=` links
+    pub fn as_param(&self) -> &'static str {
+        match self {
+            PreparationSort::Type => "type",
+            PreparationSort::Name => "name",
+            PreparationSort::Time => "time",
+            PreparationSort::Difficulty => "difficulty",
+        }
+    }
+
+    fn order_by(&self) -> &'static str {
+        match self {
+            PreparationSort::Type => "prep_type, name",
+            PreparationSort::Name => "name",
+            PreparationSort::Time => "prep_time_minutes ASC NULLS LAST, name",
+            PreparationSort::Difficulty => {
+                "CASE difficulty WHEN 'easy' THEN 1 WHEN 'medium' THEN 2 WHEN 'hard' THEN 3 END NULLS LAST, name"
+            }
+        }
+    }
+}
+
+/// Filters offered on the preparations index
+///
+/// Unknown values are dropped when parsing, so only `PREP_TYPES` and `SHIFTS`
+/// entries ever reach a query.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PreparationFilter {
+    pub prep_type: Option<&'static str>,
+    pub shift: Option<&'static str>,
+}
+
+impl PreparationFilter {
+    /// Parse the `?prep_type=` and `?shift=` query values
+    pub fn from_params(prep_type: Option<&str>, shift: Option<&str>) -> PreparationFilter {
+        PreparationFilter {
+            prep_type: prep_type.and_then(|value| PREP_TYPES.iter().copied().find(|t| *t == value)),
+            shift: shift.and_then(|value| SHIFTS.iter().copied().find(|s| *s == value)),
+        }
+    }
+
+    /// Whether any filter is applied
+    pub fn is_active(&self) -> bool {
+        self.prep_type.is_some() || self.shift.is_some()
+    }
+}
+
+/// Sort and filters of the preparations index, as remembered between visits
+///
+/// Only values the index understands are kept, so a stored or cookie value
+/// never reaches a query without going through `PreparationSort` and
+/// `PreparationFilter` again.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PreparationListPrefs {
+    #[serde(default)]
+    pub sort: Option<String>,
+    #[serde(default)]
+    pub prep_type: Option<String>,
+    #[serde(default)]
+    pub shift: Option<String>,
+}
+
+impl PreparationListPrefs {
+    /// Query values, with anything unknown dropped
+    pub fn from_params(sort: Option<&str>, prep_type: Option<&str>, shift: Option<&str>) -> PreparationListPrefs {
+        let filter = PreparationFilter::from_params(prep_type, shift);
+        PreparationListPrefs {
+            sort: sort.map(|sort| PreparationSort::from_param(Some(sort)).as_param().to_string()),
+            prep_type: filter.prep_type.map(str::to_string),
+            shift: filter.shift.map(str::to_string),
+        }
+    }
+
+    /// Whether no sort or filter is set
+    pub fn is_empty(&self) -> bool {
+        self.sort.is_none() && self.prep_type.is_none() && self.shift.is_none()
+    }
+
+    /// What the index shows: explicit query values win, then the remembered
+    /// ones, then the defaults (no filters, sorted by type)
+    ///
+    /// Also says whether the remembered values were used, so the page can offer
+    /// to clear them.
+    pub fn resolve(explicit: PreparationListPrefs, stored: Option<PreparationListPrefs>) -> (PreparationListPrefs, bool) {
+        if !explicit.is_empty() {
+            return (explicit, false);
+        }
+        match stored.map(|stored| stored.normalized()).filter(|stored| !stored.is_empty()) {
+            Some(stored) => (stored, true),
+            None => (PreparationListPrefs::default(), false),
+        }
+    }
+
+    /// The same values with anything unknown dropped
+    fn normalized(&self) -> PreparationListPrefs {
+        PreparationListPrefs::from_params(self.sort.as_deref(), self.prep_type.as_deref(), self.shift.as_deref())
+    }
+
+    pub fn sort(&self) -> PreparationSort {
+        PreparationSort::from_param(self.sort.as_deref())
+    }
+
+    pub fn filter(&self) -> PreparationFilter {
+        PreparationFilter::from_params(self.prep_type.as_deref(), self.shift.as_deref())
+    }
+
+    /// Query-string form, as kept in the preferences cookie of visitors who aren't signed in
+    pub fn to_query(&self) -> String {
+        [("sort", &self.sort), ("prep_type", &self.prep_type), ("shift", &self.shift)]
+            .iter()
+            .filter_map(|(key, value)| value.as_ref().map(|value| format!("{}={}", key, value)))
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+
+    /// Read `to_query` output back, dropping anything unknown
+    pub fn from_query(query: &str) -> PreparationListPrefs {
+        let (mut sort, mut prep_type, mut shift) = (None, None, None);
+        for (key, value) in query.split('&').filter_map(|pair| pair.split_once('=')) {
+            match key {
+                "sort" => sort = Some(value),
+                "prep_type" => prep_type = Some(value),
+                "shift" => shift = Some(value),
+                _ => {}
+            }
+        }
+        PreparationListPrefs::from_params(sort, prep_type, shift)
+    }
+}
+
+/// A signed-in user's remembered settings, stored as JSON in `user_preferences`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserPreferences {
+    /// Sort and filters last used on the preparations index
+    #[serde(default)]
+    pub preparations: Option<PreparationListPrefs>,
+    /// Products and preparations opened lately, most recent first
+    #[serde(default)]
+    pub recently_viewed: crate::recently_viewed::RecentlyViewed,
+}
+
+/// Database operations for UserPreferences
+impl UserPreferences {
+    /// The user's preferences; defaults if none were saved or they can't be read
+    pub async fn get<'e, E: sqlx::PgExecutor<'e>>(executor: E, user_id: Uuid) -> Result<UserPreferences, sqlx::Error> {
+        let json: Option<String> = sqlx::query_scalar("SELECT preferences::text FROM user_preferences WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_optional(executor)
+            .await?;
+
+        Ok(json
+            .and_then(|json| {
+                serde_json::from_str(&json)
+                    .map_err(|e| eprintln!("Ignoring unreadable preferences for user {}: {}", user_id, e))
+                    .ok()
+            })
+            .unwrap_or_default())
+    }
+
+    /// Save the user's preferences, replacing what was there
+    pub async fn set<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        user_id: Uuid,
+        preferences: &UserPreferences,
+    ) -> Result<(), sqlx::Error> {
+        let json = serde_json::to_string(preferences).map_err(|e| sqlx::Error::Protocol(e.to_string()))?;
+        sqlx::query(
+            "INSERT INTO user_preferences (user_id, preferences)
+             VALUES ($1, $2::jsonb)
+             ON CONFLICT (user_id) DO UPDATE SET preferences = EXCLUDED.preferences, updated_at = CURRENT_TIMESTAMP"
+        )
+        .bind(user_id)
+        .bind(json)
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    /// Save only the recently viewed list, leaving the other preferences as they are
+    ///
+    /// Merged in one statement, so a view recorded while the user changes a
+    /// filter in another tab doesn't undo the filter.
+    pub async fn set_recently_viewed<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        user_id: Uuid,
+        recently_viewed: &crate::recently_viewed::RecentlyViewed,
+    ) -> Result<(), sqlx::Error> {
+        let json = serde_json::json!({ "recently_viewed": recently_viewed }).to_string();
+        sqlx::query(
+            "INSERT INTO user_preferences (user_id, preferences)
+             VALUES ($1, $2::jsonb)
+             ON CONFLICT (user_id) DO UPDATE
+             SET preferences = user_preferences.preferences || EXCLUDED.preferences, updated_at = CURRENT_TIMESTAMP"
+        )
+        .bind(user_id)
+        .bind(json)
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+}
+
+/// Getting-started steps for a fresh install, each done once its data exists
+///
+/// The index and preparations pages show the steps still to do, so the
+/// checklist goes away by itself as the guide fills up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, FromRow)]
+pub struct Onboarding {
+    pub has_products: bool,
+    pub has_preparations: bool,
+    /// Someone besides the first (seeded or bootstrapped) admin has a login
+    pub has_colleagues: bool,
+}
+
+impl Onboarding {
+    /// Check each step with an EXISTS query, so this stays cheap on a full guide
+    pub async fn load<'e, E: sqlx::PgExecutor<'e>>(executor: E) -> Result<Onboarding, sqlx::Error> {
+        sqlx::query_as::<_, Onboarding>(
+            "SELECT EXISTS (SELECT 1 FROM products) AS has_products,
+                    EXISTS (SELECT 1 FROM preparations) AS has_preparations,
+                    EXISTS (SELECT 1 FROM users OFFSET 1) AS has_colleagues"
+        )
+        .fetch_one(executor)
+        .await
+    }
+
+    /// Whether every step is done
+    pub fn is_complete(&self) -> bool {
+        self.has_products && self.has_preparations && self.has_colleagues
+    }
+
+    /// How many of the steps are done
+    pub fn steps_done(&self) -> usize {
+        [self.has_products, self.has_preparations, self.has_colleagues]
+            .into_iter()
+            .filter(|done| *done)
+            .count()
+    }
+}
+
+/// Preparation counts per type and per shift, for the index sidebar
+///
+/// Each facet respects the other facet's filter but not its own, so picking
+/// "meat" still shows how many preparations the other types have.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreparationFacets {
+    /// Every entry of `PREP_TYPES` with its count (zero included)
+    pub prep_types: Vec<(&'static str, i64)>,
+    /// Every entry of `SHIFTS` with its count (zero included)
+    pub shifts: Vec<(&'static str, i64)>,
+}
+
+/// One row of the facet count query
+#[derive(FromRow)]
+struct FacetCountRow {
+    facet: String,
+    value: String,
+    count: i64,
+}
+
+/// A preparation together with its steps, as written by the preparations export
+#[derive(Debug, Serialize)]
+pub struct PreparationWithSteps {
+    pub preparation: Preparation,
+    pub steps: Vec<PreparationStep>,
+}
+
+/// A preparation joined with one of its steps; the step columns are NULL when it has none
+#[derive(Debug, FromRow)]
+pub struct PreparationStepRow {
+    #[sqlx(flatten)]
+    pub preparation: Preparation,
+    pub step_id: Option<Uuid>,
+    pub step_number: Option<i32>,
+    pub step_description: Option<String>,
+    pub step_picture_url: Option<String>,
+    pub step_created_at: Option<DateTime<Utc>>,
+}
+
+impl PreparationStepRow {
+    /// The joined step, if this row has one
+    pub fn step(&self) -> Option<PreparationStep> {
+        Some(PreparationStep {
+            id: self.step_id?,
+            preparation_id: self.preparation.id,
+            step_number: self.step_number?,
+            description: self.step_description.clone()?,
+            picture_url: self.step_picture_url.clone(),
+            created_at: self.step_created_at?,
+        })
+    }
+}
+
+/// Payload for `POST /api/preparations/import`, matching the export shape
+///
+/// Ids and timestamps in the export are ignored; the import gets fresh ones.
+#[derive(Debug, Deserialize)]
+pub struct PreparationImport {
+    pub preparation: ImportedPreparation,
+    pub steps: Vec<ImportedStep>,
+}
+
+/// Preparation fields read from an import payload
+#[derive(Debug, Deserialize)]
+pub struct ImportedPreparation {
+    pub name: String,
+    pub prep_type: String,
+    pub shift: String,
+    pub location: String,
+    pub picture_url: Option<String>,
+    #[serde(default)]
+    pub prep_time_minutes: Option<i32>,
+    #[serde(default)]
+    pub difficulty: Option<String>,
+    #[serde(default)]
+    pub yield_quantity: Option<f64>,
+    #[serde(default)]
+    pub yield_unit: Option<String>,
+    /// Language code; `PRIMARY_LANG` when left out
+    #[serde(default)]
+    pub lang: Option<String>,
+}
+
+impl ImportedPreparation {
+    /// The imported fields and `steps` as a form, so they share the form's validation
+    pub fn as_form(&self, steps: &[ImportedStep]) -> NewPreparationForm {
+        let mut ordered: Vec<&ImportedStep> = steps.iter().collect();
+        ordered.sort_by_key(|step| step.step_number);
+        NewPreparationForm {
+            name: self.name.clone(),
+            prep_type: self.prep_type.clone(),
+            shift: self.shift.clone(),
+            location: self.location.clone(),
+            steps: ordered.iter().map(|step| step.description.clone()).collect(),
+            prep_time_minutes: self.prep_time_minutes.map(|m| m.to_string()).unwrap_or_default(),
+            difficulty: self.difficulty.clone().unwrap_or_default(),
+            yield_quantity: self.yield_quantity.map(|q| q.to_string()).unwrap_or_default(),
+            yield_unit: self.yield_unit.clone().unwrap_or_default(),
+            lang: self.lang.clone().unwrap_or_default(),
+        }
+    }
+}
+
+/// Step fields read from an import payload
+#[derive(Debug, Deserialize)]
+pub struct ImportedStep {
+    pub step_number: i32,
+    pub description: String,
+    pub picture_url: Option<String>,
+}
+
+impl PreparationImport {
+    /// Validate the payload with the same rules as the preparation form
+    ///
+    /// Collects every problem rather than stopping at the first, keyed by the
+    /// JSON path of the offending field (e.g. `steps[2].description`). Picture
+    /// URLs must pass `utils::validate_picture_url` against `picture_hosts`.
+    pub fn validate(&self, allowed_locations: &[String], picture_hosts: &[String]) -> Result<(), Vec<ValidationError>> {
+        let mut errors: Vec<ValidationError> = self
+            .preparation
+            .as_form(&self.steps)
+            .problems_with_locations(allowed_locations)
+            .into_iter()
+            .map(|error| ValidationError {
+                field: format!("preparation.{}", error.field),
+                ..error
+            })
+            .collect();
+        if let Some(Err(message)) = imported_picture_url(&self.preparation.picture_url)
+            .map(|url| crate::utils::validate_picture_url(url, picture_hosts))
+        {
+            errors.push(ValidationError::new("preparation.picture_url", "invalid_format", message));
+        }
+
+        let mut step_numbers = std::collections::HashSet::new();
+        for (idx, step) in self.steps.iter().enumerate() {
+            if step.step_number < 1 {
+                errors.push(ValidationError::new(
+                    format!("steps[{}].step_number", idx),
+                    "out_of_range",
+                    format!("Invalid step number {}", step.step_number),
+                ));
+            } else if !step_numbers.insert(step.step_number) {
+                errors.push(ValidationError::new(
+                    format!("steps[{}].step_number", idx),
+                    "duplicate",
+                    format!("Duplicate step number {}", step.step_number),
+                ));
+            }
+            if step.description.trim().is_empty() {
+                errors.push(ValidationError::new(
+                    format!("steps[{}].description", idx),
+                    "required",
+                    format!("Step {} has no description", step.step_number),
+                ));
+            }
+            if let Some(Err(message)) = imported_picture_url(&step.picture_url)
+                .map(|url| crate::utils::validate_picture_url(url, picture_hosts))
+            {
+                errors.push(ValidationError::new(format!("steps[{}].picture_url", idx), "invalid_format", message));
+            }
+        }
+
+        let descriptions: Vec<&str> = self.steps.iter().map(|step| step.description.as_str()).collect();
+        errors.extend(step_limit_problems(&descriptions, max_steps()));
+
+        as_result(errors)
+    }
+}
+
+/// Picture URL from an import, treating blanks and the exported placeholder as no picture
+pub fn imported_picture_url(picture_url: &Option<String>) -> Option<&str> {
+    picture_url
+        .as_deref()
+        .filter(|url| !url.trim().is_empty() && *url != PLACEHOLDER_IMAGE_URL)
+}
+
+/// Database operations for Preparation
+impl Preparation {
+    /// Summaries of all preparations with how many steps each has, in the given sort order
+    /// (accepts a pool or a transaction)
+    ///
+    /// Steps are counted in one grouped subquery rather than a query per preparation.
+    pub async fn get_all_with_step_counts<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        sort: PreparationSort,
+        filter: &PreparationFilter,
+    ) -> Result<Vec<(PreparationSummary, i64)>, sqlx::Error> {
+        let query = format!(
+            "SELECT p.id, p.name, p.prep_type, p.shift, p.location, p.picture_url, p.webp_url, p.prep_time_minutes, p.difficulty, p.status, p.archived_at,
+                    COALESCE(c.step_count, 0) AS step_count
+             FROM preparations p
+             LEFT JOIN (
+                 SELECT preparation_id, COUNT(*) AS step_count
+                 FROM preparation_steps
+                 GROUP BY preparation_id
+             ) c ON c.preparation_id = p.id
+             WHERE p.archived_at IS NULL
+               AND ($1::text IS NULL OR p.prep_type = $1)
+               AND ($2::text IS NULL OR p.shift = $2)
+             ORDER BY {}",
+            sort.order_by()
+        );
+        let rows = sqlx::query_as::<_, PreparationSummaryRow>(&query)
+            .bind(filter.prep_type)
+            .bind(filter.shift)
+            .fetch_all(executor)
+            .await?;
+        Ok(rows.into_iter().map(|row| (row.summary, row.step_count)).collect())
+    }
+
+    /// Counts per type and per shift for the preparations matching `filter`
+    /// (accepts a pool or a transaction)
+    ///
+    /// Type counts apply only the shift filter and shift counts only the type
+    /// filter, so the sidebar shows what each choice would return.
+    pub async fn facet_counts<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        filter: &PreparationFilter,
+    ) -> Result<PreparationFacets, sqlx::Error> {
+        let rows = sqlx::query_as::<_, FacetCountRow>(
+            "SELECT 'prep_type' AS facet, prep_type AS value, COUNT(*) AS count
+             FROM preparations
+             WHERE archived_at IS NULL AND ($2::text IS NULL OR shift = $2)
+             GROUP BY prep_type
+             UNION ALL
+             SELECT 'shift' AS facet, shift AS value, COUNT(*) AS count
+             FROM preparations
+             WHERE archived_at IS NULL AND ($1::text IS NULL OR prep_type = $1)
+             GROUP BY shift"
+        )
+        .bind(filter.prep_type)
+        .bind(filter.shift)
+        .fetch_all(executor)
+        .await?;
+
+        let count_of = |facet: &str, value: &str| {
+            rows.iter()
+                .find(|row| row.facet == facet && row.value == value)
+                .map_or(0, |row| row.count)
+        };
+        Ok(PreparationFacets {
+            prep_types: PREP_TYPES.iter().map(|t| (*t, count_of("prep_type", t))).collect(),
+            shifts: SHIFTS.iter().map(|s| (*s, count_of("shift", s))).collect(),
+        })
+    }
+
+    /// Stream every preparation joined with its steps, grouped by preparation and ordered by step
+    pub fn stream_with_steps(
+        pool: &sqlx::PgPool,
+    ) -> futures_util::stream::BoxStream<'_, Result<PreparationStepRow, sqlx::Error>> {
+        sqlx::query_as::<_, PreparationStepRow>(
+            "SELECT p.id, p.name, p.prep_type, p.shift, p.location, p.picture_url, p.webp_url,
+                    p.prep_time_minutes, p.difficulty, p.yield_quantity, p.yield_unit, p.status, p.lang, p.translation_group, p.archived_at, p.created_at, p.updated_at,
+                    s.id AS step_id, s.step_number, s.description AS step_description,
+                    s.picture_url AS step_picture_url, s.created_at AS step_created_at
+             FROM preparations p
+             LEFT JOIN preparation_steps s ON s.preparation_id = p.id
+             ORDER BY p.prep_type, p.name, p.id, s.step_number"
+        )
+        .fetch(pool)
+    }
+
+    /// The `limit` most recently added preparations, newest first
+    pub async fn get_recent(pool: &sqlx::PgPool, limit: i64) -> Result<Vec<Preparation>, sqlx::Error> {
+        sqlx::query_as::<_, Preparation>(
+            "SELECT id, name, prep_type, shift, location, picture_url, webp_url, prep_time_minutes, difficulty, yield_quantity, yield_unit, status, lang, translation_group, archived_at, created_at, updated_at
+             FROM preparations
+             WHERE archived_at IS NULL
+             ORDER BY created_at DESC
+             LIMIT $1"
+        )
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Get a single preparation by ID, archived or not (accepts a pool or a transaction)
+    pub async fn get_by_id<'e, E: sqlx::PgExecutor<'e>>(executor: E, id: Uuid) -> Result<Option<Preparation>, sqlx::Error> {
+        sqlx::query_as::<_, Preparation>(
+            "SELECT id, name, prep_type, shift, location, picture_url, webp_url, prep_time_minutes, difficulty, yield_quantity, yield_unit, status, lang, translation_group, archived_at, created_at, updated_at
+             FROM preparations
+             WHERE id = $1"
+        )
+        .bind(id)
+        .fetch_optional(executor)
+        .await
+    }
+
+    /// The preparation in `lang` among `translation_group`'s translations, if there is one
+    /// (accepts a pool or a transaction)
+    pub async fn get_translation<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        translation_group: Uuid,
+        lang: &str,
+    ) -> Result<Option<Preparation>, sqlx::Error> {
+        sqlx::query_as::<_, Preparation>(
+            "SELECT id, name, prep_type, shift, location, picture_url, webp_url, prep_time_minutes, difficulty, yield_quantity, yield_unit, status, lang, translation_group, archived_at, created_at, updated_at
+             FROM preparations
+             WHERE translation_group = $1 AND lang = $2"
+        )
+        .bind(translation_group)
+        .bind(lang)
+        .fetch_optional(executor)
+        .await
+    }
+
+    /// Every language variant in `translation_group`, by language code (accepts a pool or a transaction)
+    pub async fn translations<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        translation_group: Uuid,
+    ) -> Result<Vec<PreparationTranslation>, sqlx::Error> {
+        sqlx::query_as::<_, PreparationTranslation>(
+            "SELECT id, name, lang
+             FROM preparations
+             WHERE translation_group = $1
+             ORDER BY lang"
+        )
+        .bind(translation_group)
+        .fetch_all(executor)
+        .await
+    }
+
+    /// The oldest preparation of `prep_type` with the same name, ignoring case and
+    /// surrounding spaces, for warning before a near-identical one is added
+    pub async fn find_by_name_and_type<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        name: &str,
+        prep_type: &str,
+    ) -> Result<Option<Preparation>, sqlx::Error> {
+        sqlx::query_as::<_, Preparation>(
+            "SELECT id, name, prep_type, shift, location, picture_url, webp_url, prep_time_minutes, difficulty, yield_quantity, yield_unit, status, lang, translation_group, archived_at, created_at, updated_at
+             FROM preparations
+             WHERE lower(btrim(name)) = lower(btrim($1)) AND prep_type = $2
+             ORDER BY created_at
+             LIMIT 1"
+        )
+        .bind(name)
+        .bind(prep_type)
+        .fetch_optional(executor)
+        .await
+    }
+
+    /// Preparations of any type that may already be the one about to be added,
+    /// most alike first
+    ///
+    /// Matches like `Product::possible_duplicates`. Whatever
+    /// `find_by_name_and_type` would warn about scores 1.0; archived and draft
+    /// preparations are included for the same reason they are there.
+    pub async fn possible_duplicates<'a, A>(db: A, name: &str, limit: i64) -> Result<Vec<DuplicateCandidate>, sqlx::Error>
+    where
+        A: sqlx::Acquire<'a, Database = sqlx::Postgres>,
+    {
+        let mut tx = db.begin().await?;
+        set_similarity_threshold(&mut tx).await?;
+
+        let candidates = sqlx::query_as::<_, DuplicateCandidate>(
+            "SELECT id, name, NULL::TEXT AS supplier_name, updated_at,
+                    CASE WHEN lower(btrim(name)) = lower(btrim($1)) THEN 1.0
+                         ELSE similarity(name, $1) END::REAL AS similarity
+             FROM preparations
+             WHERE name % $1 OR lower(btrim(name)) = lower(btrim($1))
+             ORDER BY similarity DESC, name
+             LIMIT $2"
+        )
+        .bind(name)
+        .bind(limit)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(candidates)
+    }
+
+    /// Create a new preparation with the given status (accepts a pool or a transaction)
+    ///
+    /// It starts without steps, so its steps text is empty until `PreparationStep::renumber`
+    /// regenerates it. Passing a `translation_group` makes it a translation of the
+    /// preparations in that group; otherwise it starts a group of its own.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn create<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        name: &str,
+        prep_type: &str,
+        shift: &str,
+        location: &str,
+        picture_url: Option<&str>,
+        prep_time_minutes: Option<i32>,
+        difficulty: Option<&str>,
+        yield_quantity: Option<f64>,
+        yield_unit: Option<&str>,
+        lang: &str,
+        translation_group: Option<Uuid>,
+        status: &str,
+    ) -> Result<Preparation, sqlx::Error> {
+        sqlx::query_as::<_, Preparation>(
+            "INSERT INTO preparations (name, prep_type, shift, location, picture_url, steps, prep_time_minutes, difficulty,
+                                       yield_quantity, yield_unit, lang, translation_group, status)
+             VALUES ($1, $2, $3, $4, $5, '', $6, $7, $8, $9, $10, COALESCE($11, uuid_generate_v4()), $12)
+             RETURNING id, name, prep_type, shift, location, picture_url, webp_url, prep_time_minutes, difficulty, yield_quantity, yield_unit, status, lang, translation_group, archived_at, created_at, updated_at"
+        )
+        .bind(name)
+        .bind(prep_type)
+        .bind(shift)
+        .bind(location)
+        .bind(picture_url)
+        .bind(prep_time_minutes)
+        .bind(difficulty)
+        .bind(yield_quantity)
+        .bind(yield_unit)
+        .bind(lang)
+        .bind(translation_group)
+        .bind(status)
+        .fetch_one(executor)
+        .await
+    }
+
+    /// Update an existing preparation, setting its status (accepts a pool or a transaction)
+    ///
+    /// The steps text is left alone; it changes along with the steps.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn update<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        id: Uuid,
+        name: &str,
+        prep_type: &str,
+        shift: &str,
+        location: &str,
+        picture_url: Option<&str>,
+        prep_time_minutes: Option<i32>,
+        difficulty: Option<&str>,
+        yield_quantity: Option<f64>,
+        yield_unit: Option<&str>,
+        lang: &str,
+        status: &str,
+    ) -> Result<Preparation, sqlx::Error> {
+        sqlx::query_as::<_, Preparation>(
+            "UPDATE preparations
+             SET name = $2, prep_type = $3, shift = $4, location = $5, picture_url = $6,
+                 prep_time_minutes = $7, difficulty = $8, yield_quantity = $9, yield_unit = $10,
+                 lang = $11, status = $12, updated_at = CURRENT_TIMESTAMP,
+                 webp_url = CASE WHEN picture_url IS NOT DISTINCT FROM $6 THEN webp_url END,
+                 picture_size_bytes = CASE WHEN picture_url IS NOT DISTINCT FROM $6 THEN picture_size_bytes END,
+                 picture_uploaded_at = CASE WHEN picture_url IS NOT DISTINCT FROM $6 THEN picture_uploaded_at END
+             WHERE id = $1
+             RETURNING id, name, prep_type, shift, location, picture_url, webp_url, prep_time_minutes, difficulty, yield_quantity, yield_unit, status, lang, translation_group, archived_at, created_at, updated_at"
+        )
+        .bind(id)
+        .bind(name)
+        .bind(prep_type)
+        .bind(shift)
+        .bind(location)
+        .bind(picture_url)
+        .bind(prep_time_minutes)
+        .bind(difficulty)
+        .bind(yield_quantity)
+        .bind(yield_unit)
+        .bind(lang)
+        .bind(status)
+        .fetch_one(executor)
+        .await
+    }
+
+    /// Archive or restore a preparation; returns whether it exists (accepts a pool or a transaction)
+    ///
+    /// Archiving an already archived preparation keeps its original `archived_at`.
+    pub async fn set_archived<'e, E: sqlx::PgExecutor<'e>>(executor: E, id: Uuid, archived: bool) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            "UPDATE preparations
+             SET archived_at = CASE WHEN $2 THEN COALESCE(archived_at, CURRENT_TIMESTAMP) END
+             WHERE id = $1"
+        )
+        .bind(id)
+        .bind(archived)
+        .execute(executor)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Record the WebP copy of the preparation's main picture (accepts a pool or a transaction)
+    pub async fn set_webp_url<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        id: Uuid,
+        webp_url: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE preparations SET webp_url = $2 WHERE id = $1")
+            .bind(id)
+            .bind(webp_url)
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+}
+
+/// Preparation listing row for index, search and picker lists, without the steps text
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct PreparationSummary {
+    pub id: Uuid,
+    pub name: String,
+    pub prep_type: String,
+    pub shift: String,
+    pub location: String,
+    #[serde(serialize_with = "serialize_picture_url")]
+    pub picture_url: Option<String>,
+    /// WebP copy of the picture, if `WEBP_CONVERT` made one
+    pub webp_url: Option<String>,
+    pub prep_time_minutes: Option<i32>,
+    pub difficulty: Option<String>,
+    pub status: String,
+    pub archived_at: Option<DateTime<Utc>>,
+}
+
+impl PreparationSummary {
+    /// Whether this preparation is still a draft
+    pub fn is_draft(&self) -> bool {
+        self.status == PREP_STATUS_DRAFT
+    }
+}
+
+/// A preparation summary with its step count, as read by `get_all_with_step_counts`
+#[derive(FromRow)]
+struct PreparationSummaryRow {
+    #[sqlx(flatten)]
+    summary: PreparationSummary,
+    step_count: i64,
+}
+
+/// Database operations for PreparationSummary
+impl PreparationSummary {
+    /// Get all preparations, grouped by type then name
+    pub async fn get_all(pool: &sqlx::PgPool) -> Result<Vec<PreparationSummary>, sqlx::Error> {
+        Self::get_all_sorted(pool, PreparationSort::Type).await
+    }
+
+    /// Get all preparations in the given sort order
+    pub async fn get_all_sorted(
+        pool: &sqlx::PgPool,
+        sort: PreparationSort,
+    ) -> Result<Vec<PreparationSummary>, sqlx::Error> {
+        let query = format!(
+            "SELECT id, name, prep_type, shift, location, picture_url, webp_url, prep_time_minutes, difficulty, status, archived_at
+             FROM preparations
+             WHERE archived_at IS NULL
+             ORDER BY {}",
+            sort.order_by()
+        );
+        sqlx::query_as::<_, PreparationSummary>(&query)
+            .fetch_all(pool)
+            .await
+    }
+
+    /// Archived preparations, most recently archived first (accepts a pool or a transaction)
+    pub async fn get_archived<'e, E: sqlx::PgExecutor<'e>>(executor: E) -> Result<Vec<PreparationSummary>, sqlx::Error> {
+        sqlx::query_as::<_, PreparationSummary>(
+            "SELECT id, name, prep_type, shift, location, picture_url, webp_url, prep_time_minutes, difficulty, status, archived_at
+             FROM preparations
+             WHERE archived_at IS NOT NULL
+             ORDER BY archived_at DESC, name"
+        )
+        .fetch_all(executor)
+        .await
+    }
+
+    /// Preparations whose name, type, shift, location or steps contain `term` (case-insensitive),
+    /// narrowed by `filters` and leaving out archived ones (accepts a pool or a transaction)
+    pub async fn search<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        term: &str,
+        filters: &SearchFilters,
+    ) -> Result<Vec<PreparationSummary>, sqlx::Error> {
+        sqlx::query_as::<_, PreparationSummary>(
+            "SELECT id, name, prep_type, shift, location, picture_url, webp_url, prep_time_minutes, difficulty, status, archived_at
+             FROM preparations
+             WHERE archived_at IS NULL
+               AND (name ILIKE $1
+                    OR prep_type ILIKE $1
+                    OR shift ILIKE $1
+                    OR location ILIKE $1
+                    OR steps ILIKE $1)
+               AND ($2::TEXT IS NULL OR LOWER(location) = LOWER($2))
+               AND ($3::TEXT IS NULL OR prep_type = $3)
+             ORDER BY name"
+        )
+        .bind(contains_pattern(term))
+        .bind(filters.location.as_deref())
+        .bind(filters.prep_type.as_deref())
+        .fetch_all(executor)
+        .await
+    }
+
+    /// Like `search`, but also matching names within `FUZZY_SEARCH_THRESHOLD`
+    /// trigram similarity of `term`, closest names first
+    ///
+    /// Accepts a pool, a connection or a transaction; the threshold setting
+    /// is scoped to a transaction (or savepoint) opened here.
+    pub async fn search_fuzzy<'a, A>(
+        db: A,
+        term: &str,
+        filters: &SearchFilters,
+    ) -> Result<Vec<PreparationSummary>, sqlx::Error>
+    where
+        A: sqlx::Acquire<'a, Database = sqlx::Postgres>,
+    {
+        let mut tx = db.begin().await?;
+        set_similarity_threshold(&mut tx).await?;
+
+        let preparations = sqlx::query_as::<_, PreparationSummary>(
+            "SELECT id, name, prep_type, shift, location, picture_url, webp_url, prep_time_minutes, difficulty, status, archived_at
+             FROM preparations
+             WHERE archived_at IS NULL
+               AND (name % $1
+                    OR name ILIKE $2
+                    OR prep_type ILIKE $2
+                    OR shift ILIKE $2
+                    OR location ILIKE $2
+                    OR steps ILIKE $2)
+               AND ($3::TEXT IS NULL OR LOWER(location) = LOWER($3))
+               AND ($4::TEXT IS NULL OR prep_type = $4)
+             ORDER BY similarity(name, $1) DESC, name"
+        )
+        .bind(term)
+        .bind(contains_pattern(term))
+        .bind(filters.location.as_deref())
+        .bind(filters.prep_type.as_deref())
+        .fetch_all(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(preparations)
+    }
+}
+
+/// Row count and latest timestamps for one table, as reported by `GET /api/stats`
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct TableStats {
+    pub total: i64,
+    pub last_created_at: Option<DateTime<Utc>>,
+    pub last_updated_at: Option<DateTime<Utc>>,
+}
+
+/// Preparation totals plus counts per type and per shift
+#[derive(Debug, Clone, Serialize)]
+pub struct PreparationStats {
+    #[serde(flatten)]
+    pub totals: TableStats,
+    /// Every entry of `PREP_TYPES` with its count (zero included)
+    pub by_type: std::collections::BTreeMap<&'static str, i64>,
+    /// Every entry of `SHIFTS` with its count (zero included)
+    pub by_shift: std::collections::BTreeMap<&'static str, i64>,
+}
+
+/// Site-wide content stats for admin dashboards
+#[derive(Debug, Clone, Serialize)]
+pub struct Stats {
+    pub products: TableStats,
+    pub preparations: PreparationStats,
+}
+
+impl Stats {
+    /// Gather the stats with one aggregate query per table plus the facet counts
+    pub async fn load(pool: &sqlx::PgPool) -> Result<Stats, sqlx::Error> {
+        let products = sqlx::query_as::<_, TableStats>(
+            "SELECT COUNT(*) AS total, MAX(created_at) AS last_created_at, MAX(updated_at) AS last_updated_at
+             FROM products"
+        )
+        .fetch_one(pool)
+        .await?;
+
+        let preparations = sqlx::query_as::<_, TableStats>(
+            "SELECT COUNT(*) AS total, MAX(created_at) AS last_created_at, MAX(updated_at) AS last_updated_at
+             FROM preparations"
+        )
+        .fetch_one(pool)
+        .await?;
+
+        // Same counts as the preparations index sidebar, with no filter applied
+        let facets = Preparation::facet_counts(pool, &PreparationFilter::default()).await?;
+
+        Ok(Stats {
+            products,
+            preparations: PreparationStats {
+                totals: preparations,
+                by_type: facets.prep_types.into_iter().collect(),
+                by_shift: facets.shifts.into_iter().collect(),
+            },
+        })
+    }
+}
+
+/// Database model for PreparationStep
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct PreparationStep {
+    pub id: Uuid,
+    pub preparation_id: Uuid,
+    pub step_number: i32,
+    pub description: String,
+    pub picture_url: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Timestamped for PreparationStep {
+    fn timestamps(&self) -> Vec<(&'static str, DateTime<Utc>)> {
+        vec![("created_at", self.created_at)]
+    }
+}
+
+/// What `PreparationStep::repair_numbering` changed
+#[derive(Debug, Default, Serialize)]
+pub struct StepRepairReport {
+    pub preparations_repaired: Vec<Uuid>,
+    pub steps_renumbered: u64,
+    pub batches: usize,
+}
+
+/// Database operations for PreparationStep
+impl PreparationStep {
+    /// Get all steps for a preparation (accepts a pool or a transaction)
+    pub async fn get_by_preparation_id<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        preparation_id: Uuid,
+    ) -> Result<Vec<PreparationStep>, sqlx::Error> {
+        sqlx::query_as::<_, PreparationStep>(
+            "SELECT id, preparation_id, step_number, description, picture_url, created_at
+             FROM preparation_steps
+             WHERE preparation_id = $1
+             ORDER BY step_number ASC"
+        )
+        .bind(preparation_id)
+        .fetch_all(executor)
+        .await
+    }
+
+    /// Create several steps in one statement, numbered in order from `first_step_number`
+    /// (accepts a pool or a transaction)
+    pub async fn create_many<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        preparation_id: Uuid,
+        first_step_number: i32,
+        steps: &[(&str, Option<&str>)],
+    ) -> Result<Vec<PreparationStep>, sqlx::Error> {
+        if steps.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let step_numbers: Vec<i32> = (first_step_number..).take(steps.len()).collect();
+        let descriptions: Vec<&str> = steps.iter().map(|(description, _)| *description).collect();
+        let picture_urls: Vec<Option<&str>> = steps.iter().map(|(_, picture_url)| *picture_url).collect();
+
+        sqlx::query_as::<_, PreparationStep>(
+            "INSERT INTO preparation_steps (preparation_id, step_number, description, picture_url,
+                                            picture_size_bytes, picture_uploaded_at)
+             SELECT $1, step.step_number, step.description, step.picture_url,
+                    sized.picture_size_bytes, sized.picture_uploaded_at
+             FROM UNNEST($2::int[], $3::text[], $4::text[]) AS step(step_number, description, picture_url)
+             -- Imported steps share their source's picture, so they share its recorded size too
+             LEFT JOIN LATERAL (
+                 SELECT picture_size_bytes, picture_uploaded_at FROM preparation_steps
+                 WHERE picture_url = step.picture_url AND picture_size_bytes IS NOT NULL
+                 LIMIT 1
+             ) sized ON true
+             RETURNING id, preparation_id, step_number, description, picture_url, created_at"
+        )
+        .bind(preparation_id)
+        .bind(step_numbers)
+        .bind(descriptions)
+        .bind(picture_urls)
+        .fetch_all(executor)
+        .await
+    }
+
+    /// Find steps of one preparation whose description contains `term` (case-insensitive)
+    pub async fn search_within(
+        pool: &sqlx::PgPool,
+        preparation_id: Uuid,
+        term: &str,
+    ) -> Result<Vec<PreparationStep>, sqlx::Error> {
+        sqlx::query_as::<_, PreparationStep>(
+            "SELECT id, preparation_id, step_number, description, picture_url, created_at
+             FROM preparation_steps
+             WHERE preparation_id = $1 AND description ILIKE $2
+             ORDER BY step_number ASC"
+        )
+        .bind(preparation_id)
+        .bind(contains_pattern(term))
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Rewrite a preparation's step numbers as a contiguous 1..N sequence, keeping their order,
+    /// and regenerate the preparation's steps text from them
+    ///
+    /// Relies on the (preparation_id, step_number) unique constraint being
+    /// deferrable, so numbers can swap places within the single UPDATE. The text
+    /// is built from the steps as they were before renumbering, which is the same
+    /// text since renumbering never changes their order. Every change to a
+    /// preparation's steps ends with this, so the text follows them.
+    ///
+    /// Returns how many steps got a new number.
+    pub async fn renumber<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        preparation_id: Uuid,
+    ) -> Result<u64, sqlx::Error> {
+        let renumbered: i64 = sqlx::query_scalar(
+            "WITH renumbered AS (
+                 UPDATE preparation_steps AS s
+                 SET step_number = numbered.new_number
+                 FROM (
+                     SELECT id, ROW_NUMBER() OVER (ORDER BY step_number, created_at, id) AS new_number
+                     FROM preparation_steps
+                     WHERE preparation_id = $1
+                 ) AS numbered
+                 WHERE s.id = numbered.id AND s.step_number <> numbered.new_number
+                 RETURNING s.id
+             ), regenerated AS (
+                 UPDATE preparations
+                 SET steps = preparation_steps_text($1)
+                 WHERE id = $1 AND steps IS DISTINCT FROM preparation_steps_text($1)
+             )
+             SELECT COUNT(*) FROM renumbered"
+        )
+        .bind(preparation_id)
+        .fetch_one(executor)
+        .await?;
+        Ok(renumbered as u64)
+    }
+
+    /// Preparations whose step numbers aren't exactly 1..N, e.g. gaps left by older edits
+    /// (accepts a pool or a transaction)
+    ///
+    /// Numbers are unique per preparation, so they run 1..N exactly when the lowest is 1
+    /// and the highest is the step count.
+    pub async fn misnumbered_preparation_ids<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+    ) -> Result<Vec<Uuid>, sqlx::Error> {
+        sqlx::query_scalar(
+            "SELECT preparation_id
+             FROM preparation_steps
+             GROUP BY preparation_id
+             HAVING MIN(step_number) <> 1 OR MAX(step_number) <> COUNT(*)
+             ORDER BY preparation_id"
+        )
+        .fetch_all(executor)
+        .await
+    }
+
+    /// Renumber every misnumbered preparation, `batch_size` preparations per transaction
+    ///
+    /// Batches that were committed stay repaired if a later one fails.
+    pub async fn repair_numbering(
+        pool: &sqlx::PgPool,
+        batch_size: usize,
+    ) -> Result<StepRepairReport, sqlx::Error> {
+        let mut report = StepRepairReport::default();
+        let preparation_ids = Self::misnumbered_preparation_ids(pool).await?;
+
+        for batch in preparation_ids.chunks(batch_size.max(1)) {
+            let mut tx = pool.begin().await?;
+            for &preparation_id in batch {
+                report.steps_renumbered += Self::renumber(&mut *tx, preparation_id).await?;
+            }
+            tx.commit().await?;
+            report.batches += 1;
+            report.preparations_repaired.extend_from_slice(batch);
+        }
+        Ok(report)
+    }
+
+    /// Steps whose preparation no longer exists, as (step id, preparation id)
+    /// (accepts a pool or a transaction)
+    ///
+    /// Deleting a preparation cascades to its steps, so these only turn up in
+    /// databases restored or edited without that foreign key.
+    pub async fn orphaned<'e, E: sqlx::PgExecutor<'e>>(executor: E) -> Result<Vec<(Uuid, Uuid)>, sqlx::Error> {
+        sqlx::query_as(
+            "SELECT s.id, s.preparation_id
+             FROM preparation_steps s
+             WHERE NOT EXISTS (SELECT 1 FROM preparations p WHERE p.id = s.preparation_id)
+             ORDER BY s.preparation_id, s.step_number"
+        )
+        .fetch_all(executor)
+        .await
+    }
+
+    /// Delete the steps `orphaned` lists, returning how many went
+    pub async fn delete_orphaned<'e, E: sqlx::PgExecutor<'e>>(executor: E) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query(
+            "DELETE FROM preparation_steps s
+             WHERE NOT EXISTS (SELECT 1 FROM preparations p WHERE p.id = s.preparation_id)"
+        )
+        .execute(executor)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Delete all steps for a preparation (accepts a pool or a transaction)
+    pub async fn delete_by_preparation_id<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        preparation_id: Uuid,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM preparation_steps WHERE preparation_id = $1")
+            .bind(preparation_id)
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+}
+
+/// How long an edit lock lasts without a heartbeat from its holder
+pub const EDIT_LOCK_MINUTES: i32 = 15;
+
+/// Database model for an advisory preparation edit lock, with the holder's username
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct EditLock {
+    pub preparation_id: Uuid,
+    pub user_id: Uuid,
+    pub username: String,
+    pub acquired_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Result of trying to take an edit lock
+#[derive(Debug, Clone)]
+pub enum EditLockOutcome {
+    /// The caller now holds the lock, newly taken or extended
+    Acquired(EditLock),
+    /// Someone else holds an unexpired lock
+    HeldBy(EditLock),
+}
+
+/// Database operations for EditLock
+impl EditLock {
+    /// Take or extend the lock on a preparation for `user_id`
+    ///
+    /// Succeeds when the lock is free, expired or already the caller's; with
+    /// `force` (admin take-over) it succeeds regardless. The upsert is a single
+    /// statement, so of two concurrent callers only one gets the lock.
+    /// Accepts a pool, a connection or a transaction.
+    pub async fn acquire<'a, A>(
+        db: A,
+        preparation_id: Uuid,
+        user_id: Uuid,
+        force: bool,
+    ) -> Result<EditLockOutcome, sqlx::Error>
+    where
+        A: sqlx::Acquire<'a, Database = sqlx::Postgres>,
+    {
+        let mut conn = db.acquire().await?;
+
+        sqlx::query("DELETE FROM preparation_edit_locks WHERE expires_at < NOW()")
+            .execute(&mut *conn)
+            .await?;
+
+        // The holder may release the lock between a failed upsert and the
+        // lookup, so try again rather than report a lock that no longer exists
+        for _ in 0..3 {
+            let acquired = sqlx::query_as::<_, EditLock>(
+                "WITH locked AS (
+                     INSERT INTO preparation_edit_locks (preparation_id, user_id, expires_at)
+                     VALUES ($1, $2, NOW() + make_interval(mins => $4))
+                     ON CONFLICT (preparation_id) DO UPDATE
+                     SET user_id = EXCLUDED.user_id,
+                         expires_at = EXCLUDED.expires_at,
+                         acquired_at = CASE
+                             WHEN preparation_edit_locks.user_id = EXCLUDED.user_id
+                                 THEN preparation_edit_locks.acquired_at
+                             ELSE NOW()
+                         END
+                     WHERE preparation_edit_locks.user_id = EXCLUDED.user_id
+                        OR preparation_edit_locks.expires_at < NOW()
+                        OR $3
+                     RETURNING preparation_id, user_id, acquired_at, expires_at
+                 )
+                 SELECT l.preparation_id, l.user_id, u.username, l.acquired_at, l.expires_at
+                 FROM locked l
+                 JOIN users u ON u.id = l.user_id"
+            )
+            .bind(preparation_id)
+            .bind(user_id)
+            .bind(force)
+            .bind(EDIT_LOCK_MINUTES)
+            .fetch_optional(&mut *conn)
+            .await?;
+
+            if let Some(lock) = acquired {
+                return Ok(EditLockOutcome::Acquired(lock));
+            }
+            if let Some(holder) = Self::holder(&mut *conn, preparation_id).await? {
+                return Ok(EditLockOutcome::HeldBy(holder));
+            }
+        }
+
+        Err(sqlx::Error::RowNotFound)
+    }
+
+    /// The unexpired lock on a preparation, if any (accepts a pool or a transaction)
+    pub async fn holder<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        preparation_id: Uuid,
+    ) -> Result<Option<EditLock>, sqlx::Error> {
+        sqlx::query_as::<_, EditLock>(
+            "SELECT l.preparation_id, l.user_id, u.username, l.acquired_at, l.expires_at
+             FROM preparation_edit_locks l
+             JOIN users u ON u.id = l.user_id
+             WHERE l.preparation_id = $1 AND l.expires_at >= NOW()"
+        )
+        .bind(preparation_id)
+        .fetch_optional(executor)
+        .await
+    }
+
+    /// Release `user_id`'s lock on a preparation; a lock someone else took over is left alone
+    pub async fn release<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        preparation_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM preparation_edit_locks WHERE preparation_id = $1 AND user_id = $2")
+            .bind(preparation_id)
+            .bind(user_id)
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+}
+
+/// Database model for User
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct User {
+    pub id: Uuid,
+    pub username: String,
+    pub email: String,
+    pub password_hash: String,
+    pub is_active: bool,
+    pub role: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl User {
+    /// Whether this user may access the admin pages
+    pub fn is_admin(&self) -> bool {
+        self.role == "admin"
+    }
+}
+
+/// User listing row for admin pages (never includes the password hash)
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct UserSummary {
+    pub id: Uuid,
+    pub username: String,
+    pub email: String,
+    pub is_active: bool,
+    pub role: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Form data for user login
+#[derive(Debug, Deserialize)]
+pub struct LoginForm {
+    pub username: String,
+    pub password: String,
+    /// Page to return to after logging in, carried over from `/login?next=`
+    #[serde(default)]
+    pub next: Option<String>,
+}
+
+/// Usernames and emails are stored trimmed and lowercase, so they match in any casing
+pub fn normalize_identity(value: &str) -> String {
+    value.trim().to_lowercase()
+}
+
+/// Friendly message when creating a user hits a taken username or email, None for other errors
+#[allow(dead_code)] // Unused while registration is disabled
+pub fn user_conflict_message(e: &sqlx::Error) -> Option<&'static str> {
+    let sqlx::Error::Database(db) = e else {
+        return None;
+    };
+    if !db.is_unique_violation() {
+        return None;
+    }
+    match db.constraint() {
+        Some(constraint) if constraint.contains("email") => Some("An account with that email already exists"),
+        _ => Some("That username is already taken"),
+    }
+}
+
+/// Form data for user registration
+#[allow(dead_code)] // Unused while registration is disabled
+#[derive(Debug, Deserialize)]
+pub struct RegisterForm {
+    pub username: String,
+    pub email: String,
+    pub password: String,
+    pub confirm_password: String,
+}
+
+#[allow(dead_code)] // Unused while registration is disabled
+impl RegisterForm {
+    /// Validate the registration form, reporting the first problem with each field
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let mut errors = Vec::new();
+        let username = self.username.trim();
+        let username_problem = if username.is_empty() {
+            Some(("required", "Username cannot be empty"))
+        } else if self.username.len() < 3 {
+            Some(("too_short", "Username must be at least 3 characters"))
+        } else if self.username.len() > 50 {
+            Some(("too_long", "Username cannot exceed 50 characters"))
+        } else if !self.username.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            Some(("invalid_format", "Username can only contain letters, numbers, and underscores"))
+        } else {
+            None
+        };
+        if let Some((code, message)) = username_problem {
+            errors.push(ValidationError::new("username", code, message));
+        }
+
+        if self.email.trim().is_empty() {
+            errors.push(ValidationError::new("email", "required", "Email cannot be empty"));
+        } else if !self.email.contains('@') || !self.email.contains('.') {
+            errors.push(ValidationError::new("email", "invalid_format", "Invalid email format"));
+        }
+
+        if self.password.len() < 6 {
+            errors.push(ValidationError::new("password", "too_short", "Password must be at least 6 characters"));
+        } else if self.password != self.confirm_password {
+            errors.push(ValidationError::new("confirm_password", "mismatch", "Passwords do not match"));
+        }
+        as_result(errors)
+    }
+}
+
+/// Database operations for User
+impl User {
+    /// Get a user by username, in any casing
+    pub async fn get_by_username(
+        pool: &sqlx::PgPool,
+        username: &str,
+    ) -> Result<Option<User>, sqlx::Error> {
+        sqlx::query_as::<_, User>(
+            "SELECT id, username, email, password_hash, is_active, role, created_at, updated_at
+             FROM users
+             WHERE lower(username) = lower($1) AND is_active = true"
+        )
+        .bind(username)
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Get a user by email, in any casing
+    #[allow(dead_code)] // Unused while registration is disabled
+    pub async fn get_by_email(
+        pool: &sqlx::PgPool,
+        email: &str,
+    ) -> Result<Option<User>, sqlx::Error> {
+        sqlx::query_as::<_, User>(
+            "SELECT id, username, email, password_hash, is_active, role, created_at, updated_at
+             FROM users
+             WHERE lower(email) = lower($1) AND is_active = true"
+        )
+        .bind(email)
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Get a user by ID
+    pub async fn get_by_id(
+        pool: &sqlx::PgPool,
+        id: Uuid,
+    ) -> Result<Option<User>, sqlx::Error> {
+        sqlx::query_as::<_, User>(
+            "SELECT id, username, email, password_hash, is_active, role, created_at, updated_at
+             FROM users
+             WHERE id = $1 AND is_active = true"
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Create a new user, storing the username and email lowercase (accepts a pool or a transaction)
+    ///
+    /// A username or email that is already taken in any casing fails with a unique
+    /// violation; `user_conflict_message` turns that into something to show the user.
+    #[allow(dead_code)] // Unused while registration is disabled
+    pub async fn create<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        username: &str,
+        email: &str,
+        password_hash: &str,
+    ) -> Result<User, sqlx::Error> {
+        sqlx::query_as::<_, User>(
+            "INSERT INTO users (username, email, password_hash)
+             VALUES ($1, $2, $3)
+             RETURNING id, username, email, password_hash, is_active, role, created_at, updated_at"
+        )
+        .bind(normalize_identity(username))
+        .bind(normalize_identity(email))
+        .bind(password_hash)
+        .fetch_one(executor)
+        .await
+    }
+
+    /// Create an admin, but only while there are no users at all (the first-run bootstrap)
+    ///
+    /// Returns None, creating nothing, once any user exists.
+    pub async fn create_first_admin<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        username: &str,
+        email: &str,
+        password_hash: &str,
+    ) -> Result<Option<User>, sqlx::Error> {
+        sqlx::query_as::<_, User>(
+            "INSERT INTO users (username, email, password_hash, role)
+             SELECT $1, $2, $3, 'admin'
+             WHERE NOT EXISTS (SELECT 1 FROM users)
+             RETURNING id, username, email, password_hash, is_active, role, created_at, updated_at"
+        )
+        .bind(normalize_identity(username))
+        .bind(normalize_identity(email))
+        .bind(password_hash)
+        .fetch_optional(executor)
+        .await
+    }
+
+    /// List users (active and inactive) for the admin pages, newest first
+    pub async fn list(
+        pool: &sqlx::PgPool,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<UserSummary>, sqlx::Error> {
+        sqlx::query_as::<_, UserSummary>(
+            "SELECT id, username, email, is_active, role, created_at
+             FROM users
+             ORDER BY created_at DESC, username
+             LIMIT $1 OFFSET $2"
+        )
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Count all users (active and inactive)
+    pub async fn count(pool: &sqlx::PgPool) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar("SELECT COUNT(*) FROM users")
+            .fetch_one(pool)
+            .await
+    }
+
+    /// Search users by username or email (case-insensitive substring match)
+    pub async fn search(
+        pool: &sqlx::PgPool,
+        term: &str,
+    ) -> Result<Vec<UserSummary>, sqlx::Error> {
+        sqlx::query_as::<_, UserSummary>(
+            "SELECT id, username, email, is_active, role, created_at
+             FROM users
+             WHERE username ILIKE $1 OR email ILIKE $1
+             ORDER BY username
+             LIMIT 100"
+        )
+        .bind(contains_pattern(term))
+        .fetch_all(pool)
+        .await
+    }
+}
+
+/// Version of the user export file format written by `export-users`
+pub const USER_EXPORT_VERSION: u32 = 1;
+
+/// Roles a user may have, most privileged first
+const USER_ROLES: [&str; 2] = ["admin", "staff"];
+
+/// One user in an export file (includes the bcrypt hash so logins survive a migration)
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct ExportedUser {
+    pub username: String,
+    pub email: String,
+    pub password_hash: String,
+    pub role: String,
+    pub is_active: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Users dumped by `export-users` and read back by `import-users`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserExport {
+    pub version: u32,
+    pub exported_at: DateTime<Utc>,
+    pub users: Vec<ExportedUser>,
+}
+
+/// What an import did, for the summary printed by `import-users`
+#[derive(Debug, Default, PartialEq)]
+pub struct UserImportReport {
+    pub inserted: Vec<String>,
+    pub skipped_username: Vec<String>,
+    pub skipped_email: Vec<String>,
+    pub downgraded: Vec<String>,
+}
+
+/// Why an import was rolled back
+#[derive(Debug)]
+pub enum UserImportError {
+    Database(sqlx::Error),
+    /// Existing admins the file would give a lesser role, refused without --allow-role-downgrade
+    RoleDowngrade(Vec<String>),
+}
+
+impl From<sqlx::Error> for UserImportError {
+    fn from(err: sqlx::Error) -> Self {
+        UserImportError::Database(err)
+    }
+}
+
+impl std::fmt::Display for UserImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UserImportError::Database(err) => write!(f, "database error: {}", err),
+            UserImportError::RoleDowngrade(usernames) => write!(
+                f,
+                "refusing to downgrade existing admins ({}); pass --allow-role-downgrade to apply",
+                usernames.join(", ")
+            ),
+        }
+    }
+}
+
+impl UserExport {
+    /// Dump every user (active and inactive), oldest first
+    pub async fn load<'e, E>(executor: E) -> Result<UserExport, sqlx::Error>
+    where
+        E: sqlx::PgExecutor<'e>,
+    {
+        let users = sqlx::query_as::<_, ExportedUser>(
+            "SELECT username, email, password_hash, role, is_active, created_at
+             FROM users
+             ORDER BY created_at, username"
+        )
+        .fetch_all(executor)
+        .await?;
+
+        Ok(UserExport {
+            version: USER_EXPORT_VERSION,
+            exported_at: Utc::now(),
+            users,
+        })
+    }
+
+    /// Parse an export file, rejecting other format versions and malformed users
+    pub fn from_json(json: &str) -> Result<UserExport, String> {
+        // Check the version on its own first so a future format gets a clear message
+        // rather than whatever field happens to fail to deserialize
+        #[derive(Deserialize)]
+        struct Header {
+            version: u32,
+        }
+        let header: Header = serde_json::from_str(json)
+            .map_err(|e| format!("Not a user export file: {}", e))?;
+        if header.version != USER_EXPORT_VERSION {
+            return Err(format!(
+                "Unsupported user export version {} (expected {})",
+                header.version, USER_EXPORT_VERSION
+            ));
+        }
+
+        let export: UserExport = serde_json::from_str(json)
+            .map_err(|e| format!("Invalid user export file: {}", e))?;
+        for user in &export.users {
+            if user.username.trim().is_empty() || user.email.trim().is_empty() {
+                return Err("Every user needs a username and email".to_string());
+            }
+            if !USER_ROLES.contains(&user.role.as_str()) {
+                return Err(format!("User {} has unknown role '{}'", user.username, user.role));
+            }
+            if !user.password_hash.starts_with("$2") {
+                return Err(format!("User {} does not have a bcrypt password hash", user.username));
+            }
+        }
+        Ok(export)
+    }
+
+    /// Insert the exported users, all or nothing.
+    ///
+    /// Usernames and emails are stored lowercase. Users whose username or email already
+    /// exists (in any casing) are skipped and left as they are,
+    /// except that an existing admin the file lists with a lesser role is an error
+    /// unless `allow_role_downgrade` is set, in which case the role is applied.
+    pub async fn import<'a, A>(
+        &self,
+        db: A,
+        allow_role_downgrade: bool,
+    ) -> Result<UserImportReport, UserImportError>
+    where
+        A: sqlx::Acquire<'a, Database = sqlx::Postgres>,
+    {
+        let mut conn = db.acquire().await?;
+        let mut tx = sqlx::Connection::begin(&mut *conn).await?;
+        let mut report = UserImportReport::default();
+        let mut refused = Vec::new();
+
+        for user in &self.users {
+            let username = normalize_identity(&user.username);
+            let existing_role: Option<String> =
+                sqlx::query_scalar("SELECT role FROM users WHERE lower(username) = $1")
+                    .bind(&username)
+                    .fetch_optional(&mut *tx)
+                    .await?;
+
+            if let Some(existing_role) = existing_role {
+                if existing_role == "admin" && user.role != "admin" {
+                    if !allow_role_downgrade {
+                        refused.push(user.username.clone());
+                        continue;
+                    }
+                    sqlx::query("UPDATE users SET role = $2 WHERE lower(username) = $1")
+                        .bind(&username)
+                        .bind(&user.role)
+                        .execute(&mut *tx)
+                        .await?;
+                    report.downgraded.push(user.username.clone());
+                } else {
+                    report.skipped_username.push(user.username.clone());
+                }
+                continue;
+            }
+
+            let inserted = sqlx::query(
+                "INSERT INTO users (username, email, password_hash, role, is_active, created_at)
+                 VALUES ($1, $2, $3, $4, $5, $6)
+                 ON CONFLICT DO NOTHING"
+            )
+            .bind(&username)
+            .bind(normalize_identity(&user.email))
+            .bind(&user.password_hash)
+            .bind(&user.role)
+            .bind(user.is_active)
+            .bind(user.created_at)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+
+            // The username is free, so a conflict can only be the email
+            if inserted == 0 {
+                report.skipped_email.push(user.username.clone());
+            } else {
+                report.inserted.push(user.username.clone());
+            }
+        }
+
+        if !refused.is_empty() {
+            return Err(UserImportError::RoleDowngrade(refused));
+        }
+
+        tx.commit().await?;
+        Ok(report)
+    }
+}
+
+/// Database model for a kiosk device token (the token itself is only stored hashed)
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct KioskToken {
+    pub id: Uuid,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+/// Database operations for KioskToken
+impl KioskToken {
+    /// Whether the token has been revoked
+    pub fn is_revoked(&self) -> bool {
+        self.revoked_at.is_some()
+    }
+
+    /// List all kiosk tokens, newest first
+    pub async fn list(pool: &sqlx::PgPool) -> Result<Vec<KioskToken>, sqlx::Error> {
+        sqlx::query_as::<_, KioskToken>(
+            "SELECT id, name, created_at, last_seen_at, revoked_at
+             FROM kiosk_tokens
+             ORDER BY created_at DESC"
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Find a token that has not been revoked by its hash
+    pub async fn find_active_by_hash(
+        pool: &sqlx::PgPool,
+        token_hash: &str,
+    ) -> Result<Option<KioskToken>, sqlx::Error> {
+        sqlx::query_as::<_, KioskToken>(
+            "SELECT id, name, created_at, last_seen_at, revoked_at
+             FROM kiosk_tokens
+             WHERE token_hash = $1 AND revoked_at IS NULL"
+        )
+        .bind(token_hash)
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Create a new kiosk token
+    pub async fn create(
+        pool: &sqlx::PgPool,
+        name: &str,
+        token_hash: &str,
+        created_by: Uuid,
+    ) -> Result<KioskToken, sqlx::Error> {
+        sqlx::query_as::<_, KioskToken>(
+            "INSERT INTO kiosk_tokens (name, token_hash, created_by)
+             VALUES ($1, $2, $3)
+             RETURNING id, name, created_at, last_seen_at, revoked_at"
+        )
+        .bind(name)
+        .bind(token_hash)
+        .bind(created_by)
+        .fetch_one(pool)
+        .await
+    }
+
+    /// Record that the device has just been seen
+    pub async fn touch(pool: &sqlx::PgPool, id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE kiosk_tokens SET last_seen_at = CURRENT_TIMESTAMP WHERE id = $1")
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Revoke a token, returning false if it was missing or already revoked
+    pub async fn revoke(pool: &sqlx::PgPool, id: Uuid) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            "UPDATE kiosk_tokens SET revoked_at = CURRENT_TIMESTAMP WHERE id = $1 AND revoked_at IS NULL"
+        )
+        .bind(id)
+        .execute(pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+/// A stored picture URL from any table that holds images
+#[derive(Debug, Clone, FromRow)]
+pub struct ImageReference {
+    pub source_table: String,
+    pub source_id: Uuid,
+    /// Product or preparation the image is shown on (steps point at their preparation)
+    pub parent_id: Uuid,
+    pub picture_url: String,
+}
+
+/// Tables with a picture_url column, used by the image check, host rewrite and storage backfill
+const IMAGE_TABLES: [&str; 3] = ["products", "preparations", "preparation_steps"];
+
+/// Every column holding a stored image URL, as (table, column)
+const IMAGE_COLUMNS: [(&str, &str); 5] = [
+    ("products", "picture_url"),
+    ("products", "webp_url"),
+    ("preparations", "picture_url"),
+    ("preparations", "webp_url"),
+    ("preparation_steps", "picture_url"),
+];
+
+/// Database operations for ImageReference
+impl ImageReference {
+    /// List every non-empty picture URL across products, preparations and steps
+    pub async fn all(pool: &sqlx::PgPool) -> Result<Vec<ImageReference>, sqlx::Error> {
+        sqlx::query_as::<_, ImageReference>(
+            "SELECT 'products' AS source_table, id AS source_id, id AS parent_id, picture_url
+             FROM products WHERE picture_url IS NOT NULL AND picture_url <> ''
+             UNION ALL
+             SELECT 'preparations', id, id, picture_url
+             FROM preparations WHERE picture_url IS NOT NULL AND picture_url <> ''
+             UNION ALL
+             SELECT 'preparation_steps', id, preparation_id, picture_url
+             FROM preparation_steps WHERE picture_url IS NOT NULL AND picture_url <> ''
+             ORDER BY 1, 4"
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Picture URLs whose stored size hasn't been recorded yet, for the storage backfill
+    pub async fn without_size(pool: &sqlx::PgPool) -> Result<Vec<ImageReference>, sqlx::Error> {
+        sqlx::query_as::<_, ImageReference>(
+            "SELECT 'products' AS source_table, id AS source_id, id AS parent_id, picture_url
+             FROM products WHERE picture_url <> '' AND picture_size_bytes IS NULL
+             UNION ALL
+             SELECT 'preparations', id, id, picture_url
+             FROM preparations WHERE picture_url <> '' AND picture_size_bytes IS NULL
+             UNION ALL
+             SELECT 'preparation_steps', id, preparation_id, picture_url
+             FROM preparation_steps WHERE picture_url <> '' AND picture_size_bytes IS NULL
+             ORDER BY 1, 4"
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Record the size of a picture that was just uploaded to `url` (accepts a pool or a transaction)
+    ///
+    /// Stored names are unique, so the URL finds the row whichever table it is in.
+    pub async fn record_size<'e, E>(executor: E, url: &str, size_bytes: i64) -> Result<(), sqlx::Error>
+    where
+        E: sqlx::PgExecutor<'e>,
+    {
+        sqlx::query(
+            "WITH products_sized AS (
+                 UPDATE products SET picture_size_bytes = $2, picture_uploaded_at = CURRENT_TIMESTAMP
+                 WHERE picture_url = $1
+             ), preparations_sized AS (
+                 UPDATE preparations SET picture_size_bytes = $2, picture_uploaded_at = CURRENT_TIMESTAMP
+                 WHERE picture_url = $1
+             )
+             UPDATE preparation_steps SET picture_size_bytes = $2, picture_uploaded_at = CURRENT_TIMESTAMP
+             WHERE picture_url = $1"
+        )
+        .bind(url)
+        .bind(size_bytes)
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    /// Record the size the backfill measured for this reference, unless its picture has changed since
+    pub async fn record_measured_size(
+        &self,
+        pool: &sqlx::PgPool,
+        size_bytes: i64,
+        uploaded_at: Option<DateTime<Utc>>,
+    ) -> Result<(), sqlx::Error> {
+        let Some(table) = IMAGE_TABLES.iter().find(|table| **table == self.source_table) else {
+            return Ok(());
+        };
+        // The table name comes from the fixed list above, never from user input
+        sqlx::query(&format!(
+            "UPDATE {} SET picture_size_bytes = $3, picture_uploaded_at = $4
+             WHERE id = $1 AND picture_url = $2",
+            table
+        ))
+        .bind(self.source_id)
+        .bind(&self.picture_url)
+        .bind(size_bytes)
+        .bind(uploaded_at)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// How many products, preparations and steps currently show `url`, as a picture or its WebP copy
+    pub async fn count_for_url<'e, E>(executor: E, url: &str) -> Result<i64, sqlx::Error>
+    where
+        E: sqlx::PgExecutor<'e>,
+    {
+        sqlx::query_scalar(
+            "SELECT (SELECT COUNT(*) FROM products WHERE picture_url = $1 OR webp_url = $1)
+                  + (SELECT COUNT(*) FROM preparations WHERE picture_url = $1 OR webp_url = $1)
+                  + (SELECT COUNT(*) FROM preparation_steps WHERE picture_url = $1)"
+        )
+        .bind(url)
+        .fetch_one(executor)
+        .await
+    }
+
+    /// The URL after replacing the `from` base with `to`, or None if it doesn't start with `from`
+    pub fn rewritten_url(&self, from: &str, to: &str) -> Option<String> {
+        self.picture_url
+            .strip_prefix(from)
+            .map(|rest| format!("{}{}", to, rest))
+    }
+
+    /// Replace the `from` base URL with `to` in every table, returning the rows changed
+    ///
+    /// Runs in one transaction so a failure leaves no table half-migrated. Check
+    /// results for the old URLs are dropped since they no longer apply.
+    pub async fn rewrite_base_url(
+        pool: &sqlx::PgPool,
+        from: &str,
+        to: &str,
+    ) -> Result<u64, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        let mut rows_changed = 0;
+
+        for table in IMAGE_TABLES {
+            // Table names come from the fixed list above, never from user input
+            let result = sqlx::query(&format!(
+                "UPDATE {} SET picture_url = $2 || substr(picture_url, length($1) + 1),
+                     updated_at = CURRENT_TIMESTAMP
+                 WHERE left(picture_url, length($1)) = $1",
+                table
+            ))
+            .bind(from)
+            .bind(to)
+            .execute(&mut *tx)
+            .await?;
+            rows_changed += result.rows_affected();
+        }
+
+        // WebP copies sit next to the pictures they were made from
+        for table in ["products", "preparations"] {
+            sqlx::query(&format!(
+                "UPDATE {} SET webp_url = $2 || substr(webp_url, length($1) + 1)
+                 WHERE left(webp_url, length($1)) = $1",
+                table
+            ))
+            .bind(from)
+            .bind(to)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        sqlx::query("DELETE FROM image_checks WHERE left(picture_url, length($1)) = $1")
+            .bind(from)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(rows_changed)
+    }
+
+    /// Every distinct image URL stored anywhere: pictures, their WebP copies and step photos
+    pub async fn distinct_urls(pool: &sqlx::PgPool) -> Result<Vec<String>, sqlx::Error> {
+        let selects: Vec<String> = IMAGE_COLUMNS
+            .iter()
+            .map(|(table, column)| format!("SELECT {1} FROM {0} WHERE {1} <> ''", table, column))
+            .collect();
+        sqlx::query_scalar(&format!("{} ORDER BY 1", selects.join(" UNION ")))
+            .fetch_all(pool)
+            .await
+    }
+
+    /// Point every row showing `from` at `to` instead, returning the rows changed
+    ///
+    /// All columns change in one transaction, so a moved image is never shown
+    /// from its old location by one record and its new one by another.
+    pub async fn replace_url(pool: &sqlx::PgPool, from: &str, to: &str) -> Result<u64, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        let mut rows_changed = 0;
+
+        for (table, column) in IMAGE_COLUMNS {
+            // Names come from the fixed list above, never from user input
+            let result = sqlx::query(&format!(
+                "UPDATE {0} SET {1} = $2, updated_at = CURRENT_TIMESTAMP WHERE {1} = $1",
+                table, column
+            ))
+            .bind(from)
+            .bind(to)
+            .execute(&mut *tx)
+            .await?;
+            rows_changed += result.rows_affected();
+        }
+
+        sqlx::query("DELETE FROM image_checks WHERE picture_url = $1")
+            .bind(from)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(rows_changed)
+    }
+}
+
+/// Database model for the latest check result of one picture URL
+#[derive(Debug, Clone, FromRow)]
+pub struct ImageCheck {
+    pub source_table: String,
+    pub parent_id: Uuid,
+    pub picture_url: String,
+    pub status_code: Option<i32>,
+    pub error: Option<String>,
+    pub is_ok: bool,
+    pub checked_at: DateTime<Utc>,
+}
+
+/// Database operations for ImageCheck
+impl ImageCheck {
+    /// Page the image is shown on
+    pub fn page_url(&self) -> String {
+        match self.source_table.as_str() {
+            "products" => format!("/product/{}", self.parent_id),
+            _ => format!("/preparation/{}", self.parent_id),
+        }
+    }
+
+    /// Short description of what was found, e.g. "HTTP 404"
+    pub fn result(&self) -> String {
+        match (&self.error, self.status_code) {
+            (Some(error), _) => error.clone(),
+            (None, Some(status_code)) => format!("HTTP {}", status_code),
+            (None, None) => "Unknown".to_string(),
+        }
+    }
+
+    /// List all results, broken images first
+    pub async fn list(pool: &sqlx::PgPool) -> Result<Vec<ImageCheck>, sqlx::Error> {
+        sqlx::query_as::<_, ImageCheck>(
+            "SELECT source_table, parent_id, picture_url, status_code, error, is_ok, checked_at
+             FROM image_checks
+             ORDER BY is_ok, source_table, picture_url"
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Remove all results before a new check starts
+    pub async fn clear(pool: &sqlx::PgPool) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM image_checks").execute(pool).await?;
+        Ok(())
+    }
+
+    /// Store the result of checking one picture URL
+    pub async fn record(
+        pool: &sqlx::PgPool,
+        reference: &ImageReference,
+        status_code: Option<i32>,
+        error: Option<&str>,
+        is_ok: bool,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO image_checks (source_table, source_id, parent_id, picture_url, status_code, error, is_ok)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)
+             ON CONFLICT (source_table, source_id) DO UPDATE
+             SET parent_id = EXCLUDED.parent_id, picture_url = EXCLUDED.picture_url,
+                 status_code = EXCLUDED.status_code, error = EXCLUDED.error,
+                 is_ok = EXCLUDED.is_ok, checked_at = CURRENT_TIMESTAMP"
+        )
+        .bind(&reference.source_table)
+        .bind(reference.source_id)
+        .bind(reference.parent_id)
+        .bind(&reference.picture_url)
+        .bind(status_code)
+        .bind(error)
+        .bind(is_ok)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+}
+
+/// A product or preparation and the stored bytes of its pictures, steps included
+#[derive(Debug, Clone, FromRow)]
+pub struct StorageItem {
+    /// "product" or "preparation"
+    pub kind: String,
+    pub id: Uuid,
+    pub name: String,
+    /// Set for products only
+    pub supplier_name: Option<String>,
+    /// Pictures with a recorded size
+    pub pictures: i64,
+    pub total_bytes: i64,
+}
+
+/// Database operations for StorageItem
+impl StorageItem {
+    /// Page the pictures are shown on
+    pub fn page_url(&self) -> String {
+        format!("/{}/{}", self.kind, self.id)
+    }
+
+    /// The items whose pictures take up the most space, heaviest first (accepts a pool or a transaction)
+    pub async fn heaviest<'e, E: sqlx::PgExecutor<'e>>(executor: E, limit: i64) -> Result<Vec<StorageItem>, sqlx::Error> {
+        sqlx::query_as::<_, StorageItem>(
+            "SELECT 'product' AS kind, id, product_name AS name, supplier_name,
+                    1::bigint AS pictures, picture_size_bytes AS total_bytes
+             FROM products
+             WHERE picture_size_bytes > 0
+             UNION ALL
+             SELECT 'preparation', p.id, p.name, NULL,
+                    (p.picture_size_bytes IS NOT NULL)::int + COUNT(s.picture_size_bytes),
+                    COALESCE(p.picture_size_bytes, 0) + COALESCE(SUM(s.picture_size_bytes), 0)::bigint
+             FROM preparations p
+             LEFT JOIN preparation_steps s ON s.preparation_id = p.id
+             GROUP BY p.id
+             HAVING COALESCE(p.picture_size_bytes, 0) + COALESCE(SUM(s.picture_size_bytes), 0) > 0
+             ORDER BY total_bytes DESC, name
+             LIMIT $1"
+        )
+        .bind(limit)
+        .fetch_all(executor)
+        .await
+    }
+}
+
+/// Pictures stored in one calendar month (UTC), counting a picture shared by several rows once
+#[derive(Debug, Clone, FromRow)]
+pub struct MonthlyUploads {
+    /// Start of the month; None for backfilled pictures whose upload time is unknown
+    pub month: Option<DateTime<Utc>>,
+    pub pictures: i64,
+    pub total_bytes: i64,
+}
+
+/// Database operations for MonthlyUploads
+impl MonthlyUploads {
+    /// Upload totals per month, newest first, then pictures of unknown age
+    pub async fn all(pool: &sqlx::PgPool) -> Result<Vec<MonthlyUploads>, sqlx::Error> {
+        sqlx::query_as::<_, MonthlyUploads>(
+            "SELECT date_trunc('month', picture_uploaded_at, 'UTC') AS month,
+                    COUNT(*) AS pictures, SUM(picture_size_bytes)::bigint AS total_bytes
+             FROM (
+                 SELECT picture_url, picture_uploaded_at, picture_size_bytes FROM products
+                 UNION
+                 SELECT picture_url, picture_uploaded_at, picture_size_bytes FROM preparations
+                 UNION
+                 SELECT picture_url, picture_uploaded_at, picture_size_bytes FROM preparation_steps
+             ) pictures
+             WHERE picture_size_bytes IS NOT NULL
+             GROUP BY 1
+             ORDER BY 1 DESC NULLS LAST"
+        )
+        .fetch_all(pool)
+        .await
+    }
+}
+
+/// Picture counts and bytes across the whole site, counting a picture shared by several rows once
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct StorageTotals {
+    /// Pictures with a recorded size
+    pub measured: i64,
+    /// Pictures the backfill hasn't measured yet
+    pub unmeasured: i64,
+    pub total_bytes: i64,
+}
+
+/// Database operations for StorageTotals
+impl StorageTotals {
+    pub async fn get(pool: &sqlx::PgPool) -> Result<StorageTotals, sqlx::Error> {
+        sqlx::query_as::<_, StorageTotals>(
+            "SELECT COUNT(picture_size_bytes) AS measured,
+                    COUNT(*) - COUNT(picture_size_bytes) AS unmeasured,
+                    COALESCE(SUM(picture_size_bytes), 0)::bigint AS total_bytes
+             FROM (
+                 SELECT picture_url, picture_size_bytes FROM products
+                 UNION
+                 SELECT picture_url, picture_size_bytes FROM preparations
+                 UNION
+                 SELECT picture_url, picture_size_bytes FROM preparation_steps
+             ) pictures
+             WHERE picture_url <> ''"
+        )
+        .fetch_one(pool)
+        .await
+    }
+}
+
+/// Database model for an allowed product/preparation location
+#[derive(Debug, Clone, FromRow)]
+pub struct Location {
+    pub id: Uuid,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Database operations for Location
+impl Location {
+    /// List all locations alphabetically
+    pub async fn list(pool: &sqlx::PgPool) -> Result<Vec<Location>, sqlx::Error> {
+        sqlx::query_as::<_, Location>("SELECT id, name, created_at FROM locations ORDER BY name")
+            .fetch_all(pool)
+            .await
+    }
+
+    /// Names of all locations alphabetically, for validation and form dropdowns
+    pub async fn names(pool: &sqlx::PgPool) -> Result<Vec<String>, sqlx::Error> {
+        sqlx::query_scalar("SELECT name FROM locations ORDER BY name")
+            .fetch_all(pool)
+            .await
+    }
+
+    /// Distinct locations products and preparations already use that start
+    /// with `prefix`, case-insensitively, for autocomplete
+    ///
+    /// Reads the `location` text on the items rather than the `locations`
+    /// table, so values typed in before the list was set up are offered too.
+    pub async fn suggest<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        prefix: &str,
+        limit: i64,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        sqlx::query_scalar(
+            "SELECT location FROM (
+                 SELECT location FROM products WHERE location ILIKE $1
+                 UNION
+                 SELECT location FROM preparations WHERE location ILIKE $1
+             ) AS used
+             ORDER BY location
+             LIMIT $2"
+        )
+        .bind(prefix_pattern(prefix))
+        .bind(limit)
+        .fetch_all(executor)
+        .await
+    }
+
+    /// Add a location
+    pub async fn create(pool: &sqlx::PgPool, name: &str) -> Result<Location, sqlx::Error> {
+        sqlx::query_as::<_, Location>(
+            "INSERT INTO locations (name) VALUES ($1) RETURNING id, name, created_at"
+        )
+        .bind(name)
+        .fetch_one(pool)
+        .await
+    }
+
+    /// Rename a location, moving products and preparations at the old name along with it
+    ///
+    /// Returns false if the location doesn't exist.
+    pub async fn rename(pool: &sqlx::PgPool, id: Uuid, name: &str) -> Result<bool, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        let old_name: Option<String> = sqlx::query_scalar("SELECT name FROM locations WHERE id = $1 FOR UPDATE")
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await?;
+        let old_name = match old_name {
+            Some(old_name) => old_name,
+            None => return Ok(false),
+        };
+
+        sqlx::query("UPDATE locations SET name = $2 WHERE id = $1")
+            .bind(id)
+            .bind(name)
+            .execute(&mut *tx)
+            .await?;
+        for table in ["products", "preparations"] {
+            // Table names come from the fixed list above, never from user input
+            sqlx::query(&format!("UPDATE {} SET location = $2 WHERE location = $1", table))
+                .bind(&old_name)
+                .bind(name)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Remove a location; products and preparations keep their location text
+    pub async fn delete(pool: &sqlx::PgPool, id: Uuid) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM locations WHERE id = $1")
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+
+/// Which kind of record an add form creates, as stored in `form_submissions.entity_type`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormEntity {
+    Product,
+    Preparation,
+}
+
+impl FormEntity {
+    fn as_str(&self) -> &'static str {
+        match self {
+            FormEntity::Product => "product",
+            FormEntity::Preparation => "preparation",
+        }
+    }
+}
+
+/// A supplier's weekly order cut-offs and delivery days, for the ordering page
+///
+/// Suppliers are the names on products, so the schedule is keyed by that name.
+/// Weekdays are stored 0 = Monday .. 6 = Sunday.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct SupplierSchedule {
+    pub supplier_name: String,
+    /// Days orders must be in by
+    pub cutoff_weekdays: Vec<i16>,
+    /// Venue time on those days that orders must be in by
+    pub cutoff_time: NaiveTime,
+    /// Days the supplier delivers
+    pub delivery_weekdays: Vec<i16>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A stored weekday number as a chrono weekday; None outside 0..=6
+pub fn weekday_from_number(number: i16) -> Option<Weekday> {
+    u8::try_from(number).ok().and_then(|number| Weekday::try_from(number).ok())
+}
+
+/// Stored weekday numbers as short names in week order: "Tue, Fri"
+fn weekday_names(numbers: &[i16]) -> String {
+    let mut weekdays: Vec<Weekday> = numbers.iter().filter_map(|&number| weekday_from_number(number)).collect();
+    weekdays.sort_by_key(|weekday| weekday.num_days_from_monday());
+    weekdays.dedup();
+    weekdays.iter().map(|weekday| weekday.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+/// Database operations for SupplierSchedule
+impl SupplierSchedule {
+    /// Every schedule, by supplier name (accepts a pool or a transaction)
+    pub async fn list<'e, E: sqlx::PgExecutor<'e>>(executor: E) -> Result<Vec<SupplierSchedule>, sqlx::Error> {
+        sqlx::query_as::<_, SupplierSchedule>(
+            "SELECT supplier_name, cutoff_weekdays, cutoff_time, delivery_weekdays, updated_at
+             FROM supplier_schedules
+             ORDER BY supplier_name"
+        )
+        .fetch_all(executor)
+        .await
+    }
+
+    /// Set a supplier's schedule, replacing any it had
+    pub async fn save<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        supplier_name: &str,
+        cutoff_weekdays: &[i16],
+        cutoff_time: NaiveTime,
+        delivery_weekdays: &[i16],
+    ) -> Result<SupplierSchedule, sqlx::Error> {
+        sqlx::query_as::<_, SupplierSchedule>(
+            "INSERT INTO supplier_schedules (supplier_name, cutoff_weekdays, cutoff_time, delivery_weekdays)
+             VALUES ($1, $2, $3, $4)
+             ON CONFLICT (supplier_name) DO UPDATE
+             SET cutoff_weekdays = EXCLUDED.cutoff_weekdays,
+                 cutoff_time = EXCLUDED.cutoff_time,
+                 delivery_weekdays = EXCLUDED.delivery_weekdays,
+                 updated_at = CURRENT_TIMESTAMP
+             RETURNING supplier_name, cutoff_weekdays, cutoff_time, delivery_weekdays, updated_at"
+        )
+        .bind(supplier_name)
+        .bind(cutoff_weekdays)
+        .bind(cutoff_time)
+        .bind(delivery_weekdays)
+        .fetch_one(executor)
+        .await
+    }
+
+    /// Remove a supplier's schedule; returns whether it had one
+    pub async fn delete<'e, E: sqlx::PgExecutor<'e>>(executor: E, supplier_name: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM supplier_schedules WHERE supplier_name = $1")
+            .bind(supplier_name)
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// The next cut-off strictly after `now`, in the venue timezone `tz`
+    pub fn next_cutoff(&self, now: DateTime<Utc>, tz: chrono_tz::Tz) -> Option<DateTime<Utc>> {
+        let weekdays: Vec<Weekday> = self.cutoff_weekdays.iter().filter_map(|&number| weekday_from_number(number)).collect();
+        crate::utils::next_weekly_time(now, tz, &weekdays, self.cutoff_time)
+    }
+
+    /// The first delivery day after the day of `cutoff`, the one an order placed by then arrives on
+    pub fn delivery_after(&self, cutoff: DateTime<Utc>, tz: chrono_tz::Tz) -> Option<NaiveDate> {
+        let cutoff_day = cutoff.with_timezone(&tz).date_naive();
+        (1..=7)
+            .map(|offset| cutoff_day + chrono::Duration::days(offset))
+            .find(|date| {
+                self.delivery_weekdays
+                    .iter()
+                    .any(|&number| weekday_from_number(number) == Some(chrono::Datelike::weekday(date)))
+            })
+    }
+
+    /// Cut-off days for display: "Tue, Fri"
+    pub fn cutoff_days(&self) -> String {
+        weekday_names(&self.cutoff_weekdays)
+    }
+
+    /// Delivery days for display, empty when none are set
+    pub fn delivery_days(&self) -> String {
+        weekday_names(&self.delivery_weekdays)
+    }
+
+    /// Whether the schedule includes weekday `number`, for ticking form checkboxes
+    pub fn has_cutoff_day(&self, number: &i16) -> bool {
+        self.cutoff_weekdays.contains(number)
+    }
+
+    /// Whether the supplier delivers on weekday `number`
+    pub fn has_delivery_day(&self, number: &i16) -> bool {
+        self.delivery_weekdays.contains(number)
+    }
+}
+
+/// How long a used form token is remembered
+const FORM_TOKEN_RETENTION: &str = "1 day";
+
+/// The one-time `form_token` an add form was submitted with, and what it created
+///
+/// A double-tapped submit button sends the same token twice; the second request
+/// finds it here and redirects to the first one's record instead of adding another.
+pub struct FormSubmission;
+
+impl FormSubmission {
+    /// The record already created with `token`, if any (accepts a pool or a transaction)
+    pub async fn created_with<'e, E: sqlx::PgExecutor<'e>>(
+        executor: E,
+        token: Uuid,
+        entity: FormEntity,
+    ) -> Result<Option<Uuid>, sqlx::Error> {
+        sqlx::query_scalar("SELECT entity_id FROM form_submissions WHERE token = $1 AND entity_type = $2")
+            .bind(token)
+            .bind(entity.as_str())
+            .fetch_optional(executor)
+            .await
+    }
+
+    /// Record that `token` created `entity_id`; false if the token was already used
+    ///
+    /// Call it in the transaction that created the record, and roll back on false.
+    /// A concurrent submit of the same token waits on the primary key until the
+    /// first transaction ends, so exactly one of them keeps its record.
+    pub async fn record(
+        conn: &mut sqlx::PgConnection,
+        token: Uuid,
+        entity: FormEntity,
+        entity_id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        sqlx::query(&format!(
+            "DELETE FROM form_submissions WHERE created_at < CURRENT_TIMESTAMP - INTERVAL '{}'",
+            FORM_TOKEN_RETENTION
+        ))
+        .execute(&mut *conn)
+        .await?;
+
+        let recorded: Option<Uuid> = sqlx::query_scalar(
+            "INSERT INTO form_submissions (token, entity_type, entity_id)
+             VALUES ($1, $2, $3)
+             ON CONFLICT (token) DO NOTHING
+             RETURNING token"
+        )
+        .bind(token)
+        .bind(entity.as_str())
+        .bind(entity_id)
+        .fetch_optional(&mut *conn)
+        .await?;
+        Ok(recorded.is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::TryStreamExt;
+
+    /// Messages of `errors`, in order
+    fn error_messages(errors: &[ValidationError]) -> Vec<String> {
+        errors.iter().map(|error| error.message.clone()).collect()
+    }
+
+    /// Connect to TEST_DATABASE_URL, or None to skip tests that need a database
+    async fn test_pool() -> Option<sqlx::PgPool> {
+        let url = std::env::var("TEST_DATABASE_URL").ok()?;
+        Some(sqlx::PgPool::connect(&url).await.expect("Failed to connect to TEST_DATABASE_URL"))
+    }
+
+    #[tokio::test]
+    async fn test_renumber_closes_step_gaps() {
+        let Some(pool) = test_pool().await else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+
+        // Everything happens in a transaction that is rolled back at the end
+        let mut tx = pool.begin().await.expect("Failed to start transaction");
+        let preparation = Preparation::create(
+            &mut *tx, "Renumber test", "veg", "both", "Bench", None, None, None, None, None, "en", None, PREP_STATUS_PUBLISHED,
+        )
+        .await
+        .expect("Failed to create preparation");
+
+        // Steps 2, 3 and 7, leaving gaps to close
+        for (first_step_number, descriptions) in [(2, vec!["first", "second"]), (7, vec!["third"])] {
+            let steps: Vec<_> = descriptions.into_iter().map(|d| (d, None)).collect();
+            PreparationStep::create_many(&mut *tx, preparation.id, first_step_number, &steps)
+                .await
+                .expect("Failed to create steps");
+        }
+
+        let misnumbered = PreparationStep::misnumbered_preparation_ids(&mut *tx)
+            .await
+            .expect("Failed to find misnumbered preparations");
+        assert!(misnumbered.contains(&preparation.id));
+
+        let renumbered = PreparationStep::renumber(&mut *tx, preparation.id)
+            .await
+            .expect("Failed to renumber steps");
+        assert_eq!(renumbered, 3);
+        let misnumbered = PreparationStep::misnumbered_preparation_ids(&mut *tx)
+            .await
+            .expect("Failed to find misnumbered preparations");
+        assert!(!misnumbered.contains(&preparation.id));
+
+        let steps = PreparationStep::get_by_preparation_id(&mut *tx, preparation.id)
+            .await
+            .expect("Failed to fetch steps");
+        let numbered: Vec<_> = steps.iter().map(|s| (s.step_number, s.description.as_str())).collect();
+        assert_eq!(numbered, vec![(1, "first"), (2, "second"), (3, "third")]);
+
+        tx.rollback().await.expect("Failed to roll back");
+    }
+
+    #[tokio::test]
+    async fn test_steps_text_follows_the_structured_steps() {
+        let Some(pool) = test_pool().await else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+
+        let mut tx = pool.begin().await.expect("Failed to start transaction");
+        let preparation = Preparation::create(
+            &mut *tx, "Steps text test", "veg", "both", "Bench", None, None, None, None, None, "en", None, PREP_STATUS_PUBLISHED,
+        )
+        .await
+        .expect("Failed to create preparation");
+
+        async fn steps_text(conn: &mut sqlx::PgConnection, id: Uuid) -> String {
+            sqlx::query_scalar("SELECT steps FROM preparations WHERE id = $1")
+                .bind(id)
+                .fetch_one(conn)
+                .await
+                .expect("Failed to fetch steps text")
+        }
+        assert_eq!(steps_text(&mut tx, preparation.id).await, "");
+
+        // Each edit replaces the steps and renumbers them, as the edit form does
+        for descriptions in [vec!["Peel", "Dice"], vec!["Wash", "Peel", "Dice finely"], vec![]] {
+            sqlx::query("DELETE FROM preparation_steps WHERE preparation_id = $1")
+                .bind(preparation.id)
+                .execute(&mut *tx)
+                .await
+                .expect("Failed to delete steps");
+            let steps: Vec<_> = descriptions.iter().map(|d| (*d, None)).collect();
+            PreparationStep::create_many(&mut *tx, preparation.id, 1, &steps)
+                .await
+                .expect("Failed to create steps");
+            PreparationStep::renumber(&mut *tx, preparation.id)
+                .await
+                .expect("Failed to renumber steps");
+
+            let steps = PreparationStep::get_by_preparation_id(&mut *tx, preparation.id)
+                .await
+                .expect("Failed to fetch steps");
+            let expected: Vec<_> = steps.iter().map(|s| format!("{}. {}", s.step_number, s.description)).collect();
+            assert_eq!(steps_text(&mut tx, preparation.id).await, expected.join("\n"));
+        }
+
+        // Steps added after the gap close it, and the text numbers them by position
+        PreparationStep::create_many(&mut *tx, preparation.id, 4, &[("Rest", None)])
+            .await
+            .expect("Failed to create steps");
+        PreparationStep::create_many(&mut *tx, preparation.id, 2, &[("Season", None)])
+            .await
+            .expect("Failed to create steps");
+        PreparationStep::renumber(&mut *tx, preparation.id)
+            .await
+            .expect("Failed to renumber steps");
+        assert_eq!(steps_text(&mut tx, preparation.id).await, "1. Season\n2. Rest");
+
+        tx.rollback().await.expect("Failed to roll back");
+    }
+
+    #[test]
+    fn test_with_local_times_keeps_utc_and_adds_venue_strings() {
+        let at = DateTime::parse_from_rfc3339("2024-05-14T04:45:00Z").unwrap().with_timezone(&Utc);
+        let step = PreparationStep {
+            id: Uuid::nil(),
+            preparation_id: Uuid::nil(),
+            step_number: 1,
+            description: "Wash".to_string(),
+            picture_url: None,
+            created_at: at,
+        };
+
+        let json = serde_json::to_value(WithLocalTimes::new(step, chrono_tz::Australia::Sydney)).unwrap();
+        assert_eq!(json["created_at"], "2024-05-14T04:45:00Z");
+        assert_eq!(json["created_at_local"], "Tue 14 May, 2:45 pm");
+        assert_eq!(json["description"], "Wash");
+    }
+
+    #[test]
+    fn test_import_rejects_unsafe_picture_urls() {
+        let import: PreparationImport = serde_json::from_value(serde_json::json!({
+            "preparation": {
+                "name": "Coleslaw",
+                "prep_type": "veg",
+                "shift": "both",
+                "location": "Bench",
+                "picture_url": "javascript:alert(document.cookie)",
+                "steps": "Shred the cabbage",
+            },
+            "steps": [
+                { "step_number": 1, "description": "Shred the cabbage", "picture_url": "/static/uploads/a.jpg" },
+                { "step_number": 2, "description": "Dress it", "picture_url": "https://evil.example.com/b.jpg" },
+                { "step_number": 3, "description": "Chill", "picture_url": PLACEHOLDER_IMAGE_URL },
+            ],
+        }))
+        .unwrap();
+
+        let errors = import.validate(&[], &["khg.s3.amazonaws.com".to_string()]).unwrap_err();
+        let fields: Vec<(&str, &str)> = errors.iter().map(|e| (e.field.as_str(), e.code.as_str())).collect();
+        assert_eq!(
+            fields,
+            vec![("preparation.picture_url", "invalid_format"), ("steps[1].picture_url", "invalid_format")]
+        );
+    }
+
+    #[test]
+    fn test_import_form_takes_the_structured_steps() {
+        let import: PreparationImport = serde_json::from_value(serde_json::json!({
+            "preparation": { "name": "Coleslaw", "prep_type": "veg", "shift": "both", "location": "Bench", "picture_url": null },
+            "steps": [
+                { "step_number": 2, "description": "Dress it", "picture_url": null },
+                { "step_number": 1, "description": "Shred the cabbage", "picture_url": null },
+            ],
+        }))
+        .unwrap();
+        assert_eq!(import.preparation.as_form(&import.steps).steps, vec!["Shred the cabbage", "Dress it"]);
+        assert!(import.validate(&[], &[]).is_ok());
+
+        let empty = PreparationImport { steps: Vec::new(), ..import };
+        let errors = empty.validate(&[], &[]).unwrap_err();
+        let fields: Vec<(&str, &str)> = errors.iter().map(|e| (e.field.as_str(), e.code.as_str())).collect();
+        assert_eq!(fields, vec![("preparation.steps", "required")]);
+    }
+
+    #[test]
+    fn test_step_limit_problems_name_the_steps() {
+        assert!(step_limit_problems(&["Wash", "Cut"], 2).is_empty());
+        assert_eq!(
+            error_messages(&step_limit_problems(&["Wash", "Peel", "Cut"], 2)),
+            vec!["Too many steps: 3 (the limit is 2). Step 3 is over the limit; merge or remove some.".to_string()]
+        );
+
+        let long = "x".repeat(MAX_STEP_DESCRIPTION_CHARS + 1);
+        let problems = step_limit_problems(&["Wash", &long, "Cut", &long], 2);
+        let codes: Vec<_> = problems.iter().map(|p| (p.field.as_str(), p.code.as_str())).collect();
+        assert_eq!(codes, vec![("steps", "too_many"), ("steps", "too_long")]);
+        assert_eq!(
+            error_messages(&problems),
+            vec![
+                "Too many steps: 4 (the limit is 2). Steps 3-4 are over the limit; merge or remove some.".to_string(),
+                format!("Steps are longer than {} characters: 2, 4", MAX_STEP_DESCRIPTION_CHARS),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_many_steps_in_order() {
+        let Some(pool) = test_pool().await else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+
+        let mut tx = pool.begin().await.expect("Failed to start transaction");
+        let preparation = Preparation::create(
+            &mut *tx, "Create many test", "veg", "both", "Bench", None, None, None, None, None, "en", None, PREP_STATUS_PUBLISHED,
+        )
+        .await
+        .expect("Failed to create preparation");
+
+        let created = PreparationStep::create_many(
+            &mut *tx,
+            preparation.id,
+            4,
+            &[("Wash", Some("/static/uploads/wash.jpg")), ("Cut", None)],
+        )
+        .await
+        .expect("Failed to create steps");
+        assert_eq!(created.len(), 2);
+
+        let steps = PreparationStep::get_by_preparation_id(&mut *tx, preparation.id)
+            .await
+            .expect("Failed to fetch steps");
+        let saved: Vec<_> = steps
+            .iter()
+            .map(|s| (s.step_number, s.description.as_str(), s.picture_url.as_deref()))
+            .collect();
+        assert_eq!(saved, vec![(4, "Wash", Some("/static/uploads/wash.jpg")), (5, "Cut", None)]);
+
+        let none = PreparationStep::create_many(&mut *tx, preparation.id, 1, &[])
+            .await
+            .expect("Empty insert should succeed");
+        assert!(none.is_empty());
+
+        tx.rollback().await.expect("Failed to roll back");
+    }
+
+    #[tokio::test]
+    async fn test_step_counts_in_one_listing() {
+        let Some(pool) = test_pool().await else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+
+        let mut tx = pool.begin().await.expect("Failed to start transaction");
+        for (name, step_count) in [("Step count test C", 0), ("Step count test A", 3), ("Step count test B", 1)] {
+            let preparation = Preparation::create(&mut *tx, name, "veg", "both", "Bench", None, None, None, None, None, "en", None, PREP_STATUS_PUBLISHED)
+                .await
+                .expect("Failed to create preparation");
+            PreparationStep::create_many(&mut *tx, preparation.id, 1, &vec![("step", None); step_count])
+                .await
+                .expect("Failed to create steps");
+        }
+
+        let listing = Preparation::get_all_with_step_counts(&mut *tx, PreparationSort::Name, &PreparationFilter::default())
+            .await
+            .expect("Failed to list preparations");
+        let counts: Vec<_> = listing
+            .iter()
+            .filter(|(prep, _)| prep.name.starts_with("Step count test"))
+            .map(|(prep, count)| (prep.name.as_str(), *count))
+            .collect();
+        assert_eq!(
+            counts,
+            vec![("Step count test A", 3), ("Step count test B", 1), ("Step count test C", 0)]
+        );
+
+        tx.rollback().await.expect("Failed to roll back");
+    }
+
+    #[tokio::test]
+    async fn test_archived_preparations_leave_lists_but_stay_reachable() {
+        let Some(pool) = test_pool().await else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+
+        let mut tx = pool.begin().await.expect("Failed to start transaction");
+        let mut ids = Vec::new();
+        for (name, status) in [
+            ("Archive test summer salad", PREP_STATUS_PUBLISHED),
+            ("Archive test summer draft", PREP_STATUS_DRAFT),
+            ("Archive test winter soup", PREP_STATUS_PUBLISHED),
+        ] {
+            let preparation = Preparation::create(&mut *tx, name, "veg", "lunch", "Bench", None, None, None, None, None, "en", None, status)
+                .await
+                .expect("Failed to create preparation");
+            ids.push(preparation.id);
+        }
+        let (salad, draft, soup) = (ids[0], ids[1], ids[2]);
+
+        for id in [salad, draft] {
+            assert!(Preparation::set_archived(&mut *tx, id, true).await.expect("Failed to archive"));
+        }
+        assert!(!Preparation::set_archived(&mut *tx, Uuid::new_v4(), true).await.expect("Failed to archive"));
+
+        let listed = |listing: Vec<(PreparationSummary, i64)>| {
+            listing.into_iter().map(|(prep, _)| prep.id).filter(|id| ids.contains(id)).collect::<Vec<_>>()
+        };
+        let listing = Preparation::get_all_with_step_counts(&mut *tx, PreparationSort::Name, &PreparationFilter::default())
+            .await
+            .expect("Failed to list preparations");
+        assert_eq!(listed(listing), vec![soup]);
+        let found = PreparationSummary::search(&mut *tx, "Archive test summer", &SearchFilters::default())
+            .await
+            .expect("Failed to search");
+        assert!(found.is_empty());
+
+        // Archived drafts are listed with the rest and keep their status
+        let archived = PreparationSummary::get_archived(&mut *tx).await.expect("Failed to list archived");
+        let archived: Vec<_> = archived.iter().filter(|prep| ids.contains(&prep.id)).collect();
+        assert_eq!(archived.len(), 2);
+        assert!(archived.iter().any(|prep| prep.id == draft && prep.is_draft()));
+
+        // Still reachable by link, and still caught by the duplicate name check
+        let stored = Preparation::get_by_id(&mut *tx, salad).await.unwrap().expect("Archived preparation should load");
+        assert!(stored.is_archived());
+        let duplicate = Preparation::find_by_name_and_type(&mut *tx, "archive test summer salad", "veg").await.unwrap();
+        assert_eq!(duplicate.map(|prep| prep.id), Some(salad));
+
+        // Archiving again keeps the original time; restoring brings it back as it was
+        let archived_at = stored.archived_at;
+        Preparation::set_archived(&mut *tx, salad, true).await.expect("Failed to archive");
+        let stored = Preparation::get_by_id(&mut *tx, salad).await.unwrap().unwrap();
+        assert_eq!(stored.archived_at, archived_at);
+        for id in [salad, draft] {
+            assert!(Preparation::set_archived(&mut *tx, id, false).await.expect("Failed to restore"));
+        }
+        let listing = Preparation::get_all_with_step_counts(&mut *tx, PreparationSort::Name, &PreparationFilter::default())
+            .await
+            .expect("Failed to list preparations");
+        assert_eq!(listed(listing), vec![draft, salad, soup]);
+        let restored = Preparation::get_by_id(&mut *tx, draft).await.unwrap().unwrap();
+        assert!(restored.is_draft() && !restored.is_archived());
+
+        tx.rollback().await.expect("Failed to roll back");
+    }
+
+    #[tokio::test]
+    async fn test_facet_counts_respect_other_filter() {
+        let Some(pool) = test_pool().await else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+
+        let mut tx = pool.begin().await.expect("Failed to start transaction");
+        // Start from an empty table so the counts are exact
+        sqlx::query("DELETE FROM preparations").execute(&mut *tx).await.expect("Failed to clear preparations");
+        for (name, prep_type, shift) in [
+            ("Facet A", "meat", "lunch"),
+            ("Facet B", "meat", "brekkie"),
+            ("Facet C", "veg", "lunch"),
+            ("Facet D", "veg", "lunch"),
+        ] {
+            Preparation::create(&mut *tx, name, prep_type, shift, "Bench", None, None, None, None, None, "en", None, PREP_STATUS_PUBLISHED)
+                .await
+                .expect("Failed to create preparation");
+        }
+
+        let filter = PreparationFilter::from_params(Some("meat"), Some("lunch"));
+        let facets = Preparation::facet_counts(&mut *tx, &filter).await.expect("Failed to count");
+        // Types are counted within the lunch shift, shifts within meat
+        assert_eq!(
+            facets.prep_types,
+            vec![("fruit", 0), ("bread", 0), ("veg", 2), ("meat", 1), ("seafood", 0)]
+        );
+        assert_eq!(facets.shifts, vec![("brekkie", 1), ("lunch", 1), ("both", 0)]);
+
+        let listing = Preparation::get_all_with_step_counts(&mut *tx, PreparationSort::Name, &filter)
+            .await
+            .expect("Failed to list preparations");
+        let names: Vec<_> = listing.iter().map(|(prep, _)| prep.name.as_str()).collect();
+        assert_eq!(names, vec!["Facet A"]);
+
+        tx.rollback().await.expect("Failed to roll back");
+    }
+
+    #[test]
+    fn test_preparation_filter_ignores_unknown_values() {
+        let filter = PreparationFilter::from_params(Some("meat"), Some("dinner"));
+        assert_eq!(filter, PreparationFilter { prep_type: Some("meat"), shift: None });
+        assert!(filter.is_active());
+        assert!(!PreparationFilter::from_params(Some("'; DROP TABLE preparations; --"), None).is_active());
+    }
+
+    #[tokio::test]
+    async fn test_product_documents_belong_to_their_product() {
+        let Some(pool) = test_pool().await else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+
+        let mut tx = pool.begin().await.expect("Failed to start transaction");
+        let product = Product::create(&mut *tx, "Doc Dairy", "Doc test cream", "Coolroom", None, None, "")
+            .await
+            .expect("Failed to create product");
+        let other = Product::create(&mut *tx, "Doc Dairy", "Doc test butter", "Coolroom", None, None, "")
+            .await
+            .expect("Failed to create product");
+
+        let sheet = ProductDocument::create(&mut *tx, product.id, "Spec.pdf", "pdf", "local", "a.pdf", 2048, Some("alice"))
+            .await
+            .expect("Failed to create document");
+        ProductDocument::create(&mut *tx, product.id, "Allergens.docx", "docx", "s3", "documents/b.docx", 10, None)
+            .await
+            .expect("Failed to create document");
+
+        // Both were added in this transaction, so they share a created_at and either may come first
+        let listed = ProductDocument::for_product(&mut *tx, product.id).await.expect("Failed to list documents");
+        let mut names: Vec<&str> = listed.iter().map(|d| d.filename.as_str()).collect();
+        names.sort_unstable();
+        assert_eq!(names, ["Allergens.docx", "Spec.pdf"]);
+        assert_eq!(sheet.size_label(), crate::utils::format_bytes(2048));
+
+        // Another product's id doesn't reach it
+        assert!(ProductDocument::get(&mut *tx, other.id, sheet.id).await.unwrap().is_none());
+        assert!(ProductDocument::delete(&mut *tx, other.id, sheet.id).await.unwrap().is_none());
+
+        let deleted = ProductDocument::delete(&mut *tx, product.id, sheet.id).await.unwrap().expect("Document should be deleted");
+        assert_eq!((deleted.storage.as_str(), deleted.storage_key.as_str()), ("local", "a.pdf"));
+        assert_eq!(ProductDocument::for_product(&mut *tx, product.id).await.unwrap().len(), 1);
+
+        tx.rollback().await.expect("Failed to roll back");
+    }
+
+    #[tokio::test]
+    async fn test_merge_into_moves_references_to_the_product_kept() {
+        let Some(pool) = test_pool().await else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+
+        let mut tx = pool.begin().await.expect("Failed to start transaction");
+        // An in-store code (prefix 2), so it won't clash with a real product
+        let duplicate = Product::create(
+            &mut *tx, "Merge Dairy", "Merge test cream", "Coolroom", Some("2000000000022"), Some("/static/uploads/cream.jpg"), "",
+        )
+        .await
+        .expect("Failed to create product");
+        let kept = Product::create(&mut *tx, "Merge Dairy", "Merge test thickened cream", "Coolroom", None, None, "")
+            .await
+            .expect("Failed to create product");
+        let earlier = Product::create(&mut *tx, "Merge Dairy", "Merge test cream (old)", "Coolroom", None, None, "")
+            .await
+            .expect("Failed to create product");
+
+        Product::apply_tags(&mut *tx, &[duplicate.id], &["dairy".to_string(), "pastry".to_string()], TagMode::Add)
+            .await
+            .expect("Failed to tag");
+        Product::apply_tags(&mut *tx, &[kept.id], &["dairy".to_string()], TagMode::Add).await.expect("Failed to tag");
+        Product::set_price(&mut *tx, duplicate.id, Some(650), Some("1L"), Some("alice")).await.expect("Failed to set price");
+        Product::set_price(&mut *tx, duplicate.id, Some(700), Some("1L"), Some("alice")).await.expect("Failed to set price");
+        let snapshot = ProductSnapshot::from(&duplicate);
+        ProductAudit::record(&mut *tx, duplicate.id, None, Some("alice"), &snapshot, &snapshot)
+            .await
+            .expect("Failed to record audit");
+        ProductDocument::create(&mut *tx, duplicate.id, "Spec.pdf", "pdf", "local", "merge.pdf", 10, None)
+            .await
+            .expect("Failed to create document");
+        Product::merge_into(&mut *tx, earlier.id, duplicate.id, false, None, None).await.expect("Failed to merge");
+
+        let preview = Product::merge_preview(&mut *tx, &duplicate, &kept, true).await.expect("Failed to preview");
+        assert_eq!(
+            preview,
+            ProductMerge {
+                tags: vec!["pastry".to_string()],
+                price_entries: 2,
+                // Alice's update, and the row recording the earlier merge
+                audit_entries: 2,
+                documents: 1,
+                picture: true,
+                barcode: true,
+            }
+        );
+        let merged = Product::merge_into(&mut *tx, duplicate.id, kept.id, true, None, Some("admin"))
+            .await
+            .expect("Failed to merge");
+        assert_eq!(merged, preview);
+
+        assert_eq!(Product::tags(&mut *tx, kept.id).await.unwrap(), ["dairy", "pastry"]);
+        assert!(Product::tags(&mut *tx, duplicate.id).await.unwrap().is_empty());
+        assert_eq!(ProductPrice::for_product(&mut *tx, kept.id, 10).await.unwrap().len(), 2);
+        assert_eq!(ProductDocument::for_product(&mut *tx, kept.id).await.unwrap().len(), 1);
+        // The duplicate's update and earlier merge, plus this merge filling in the barcode and picture
+        let history = ProductAudit::for_product(&mut *tx, kept.id).await.unwrap();
+        assert_eq!(history.len(), 3);
+        let mut recorded: Vec<(Option<String>, Vec<ProductFieldChange>)> = history
+            .iter()
+            .map(|audit| (audit.username.clone(), audit.changes()))
+            .filter(|(_, changes)| !changes.is_empty())
+            .collect();
+        recorded.sort_by_key(|(_, changes)| changes.len());
+        let merged_from = |id: Uuid| ProductFieldChange { field: "Merged from", before: String::new(), after: id.to_string() };
+        assert_eq!(recorded[0], (None, vec![merged_from(earlier.id)]));
+        assert_eq!(recorded[1].0.as_deref(), Some("admin"));
+        let fields: Vec<&str> = recorded[1].1.iter().map(|change| change.field).collect();
+        assert_eq!(fields, ["Barcode", "Picture", "Merged from"]);
+        assert_eq!(recorded[1].1[2], merged_from(duplicate.id));
+        // The duplicate keeps a marker naming the product kept
+        let marker = ProductAudit::for_product(&mut *tx, duplicate.id).await.unwrap();
+        assert_eq!(marker.len(), 1);
+        assert_eq!(marker[0].username.as_deref(), Some("admin"));
+        assert_eq!(
+            marker[0].changes(),
+            [ProductFieldChange { field: "Merged into", before: String::new(), after: kept.id.to_string() }]
+        );
+
+        let kept_now = Product::get_by_id(&mut *tx, kept.id).await.unwrap().unwrap();
+        assert_eq!(kept_now.barcode.as_deref(), Some("2000000000022"));
+        assert_eq!(kept_now.picture_url.as_deref(), Some("/static/uploads/cream.jpg"));
+        assert_eq!(kept_now.product_name, "Merge test thickened cream");
+        let duplicate_now = Product::get_by_id(&mut *tx, duplicate.id).await.unwrap().unwrap();
+        assert!(duplicate_now.is_merged());
+        assert_eq!(duplicate_now.merged_into, Some(kept.id));
+        assert_eq!(duplicate_now.barcode, None);
+        // What was merged into the duplicate now points straight at the product kept
+        let earlier_now = Product::get_by_id(&mut *tx, earlier.id).await.unwrap().unwrap();
+        assert_eq!(earlier_now.merged_into, Some(kept.id));
+
+        for (source, target, problem) in [
+            (kept.id, kept.id, "A product can't be merged into itself"),
+            (duplicate.id, kept.id, "That product has already been merged into another"),
+            (kept.id, duplicate.id, "The product to keep has itself been merged into another"),
+        ] {
+            let rejected = Product::merge_into(&mut *tx, source, target, true, None, None).await;
+            assert!(matches!(rejected, Err(ProductMergeError::Invalid(p)) if p == problem), "{:?}", rejected);
+        }
+        let missing = Uuid::new_v4();
+        let rejected = Product::merge_into(&mut *tx, missing, kept.id, true, None, None).await;
+        assert!(matches!(rejected, Err(ProductMergeError::NotFound(id)) if id == missing));
+
+        tx.rollback().await.expect("Failed to roll back");
+    }
+
+    #[tokio::test]
+    async fn test_search_filters_narrow_results() {
+        let Some(pool) = test_pool().await else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+
+        let mut tx = pool.begin().await.expect("Failed to start transaction");
+        let cream = Product::create(&mut *tx, "Filter Dairy", "Filter test cream", "Filter Coolroom 2", None, None, "")
+            .await
+            .expect("Failed to create product");
+        Product::create(&mut *tx, "Filter Dairy", "Filter test butter", "Filter Dry Store", None, None, "")
+            .await
+            .expect("Failed to create product");
+        let mut preparations = Vec::new();
+        for (name, prep_type, location) in [
+            ("Filter test salsa", "veg", "Filter Coolroom 2"),
+            ("Filter test brine", "meat", "Filter Coolroom 2"),
+            ("Filter test slaw", "veg", "Filter Bench"),
+        ] {
+            let preparation = Preparation::create(
+                &mut *tx, name, prep_type, "both", location, None, None, None, None, None, "en", None, PREP_STATUS_PUBLISHED,
+            )
+            .await
+            .expect("Failed to create preparation");
+            preparations.push(preparation.id);
+        }
+
+        // No term: a filtered listing, with the location matched ignoring case
+        let in_coolroom = SearchFilters { location: Some("filter coolroom 2".to_string()), prep_type: None };
+        let products = ProductSummary::search(&mut *tx, "", &in_coolroom).await.expect("Failed to search");
+        assert_eq!(products.iter().map(|p| p.id).collect::<Vec<_>>(), [cream.id]);
+        let found = PreparationSummary::search(&mut *tx, "", &in_coolroom).await.expect("Failed to search");
+        assert_eq!(found.iter().map(|p| p.id).collect::<Vec<_>>(), [preparations[1], preparations[0]]);
+
+        // prep_type narrows preparations only, and both compose with the term
+        let veg_in_coolroom = SearchFilters { prep_type: Some("veg".to_string()), ..in_coolroom };
+        let products = ProductSummary::search(&mut *tx, "filter test", &veg_in_coolroom)
+            .await
+            .expect("Failed to search");
+        assert_eq!(products.len(), 1);
+        let found = PreparationSummary::search_fuzzy(&mut *tx, "filter test", &veg_in_coolroom)
+            .await
+            .expect("Failed to search");
+        assert_eq!(found.iter().map(|p| p.id).collect::<Vec<_>>(), [preparations[0]]);
+        let found = PreparationSummary::search(&mut *tx, "slaw", &veg_in_coolroom).await.expect("Failed to search");
+        assert!(found.is_empty());
+
+        tx.rollback().await.expect("Failed to roll back");
+    }
+
+    #[tokio::test]
+    async fn test_fuzzy_search_finds_misspelled_names() {
+        let Some(pool) = test_pool().await else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+
+        let mut tx = pool.begin().await.expect("Failed to start transaction");
+        for name in ["Zucchini Ribbons", "Pickled Zucchini", "Carrot Batons"] {
+            Preparation::create(&mut *tx, name, "veg", "both", "Bench", None, None, None, None, None, "en", None, PREP_STATUS_PUBLISHED)
+                .await
+                .expect("Failed to create preparation");
+        }
+
+        let found = PreparationSummary::search_fuzzy(&mut *tx, "zuchini ribons", &SearchFilters::default())
+            .await
+            .expect("Failed to search");
+        let names: Vec<_> = found.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names.first(), Some(&"Zucchini Ribbons"));
+        assert!(!names.contains(&"Carrot Batons"));
+
+        tx.rollback().await.expect("Failed to roll back");
+    }
+
+    #[test]
+    fn test_contains_pattern_escapes_wildcards() {
+        assert_eq!(contains_pattern("carrot"), "%carrot%");
+        assert_eq!(contains_pattern("50%"), "%50\\%%");
+        assert_eq!(contains_pattern("a_b\\c"), "%a\\_b\\\\c%");
+        assert_eq!(contains_pattern(""), "%%");
+        assert_eq!(prefix_pattern("co"), "co%");
+        assert_eq!(prefix_pattern("50%"), "50\\%%");
+    }
+
+    /// Insert a throwaway user for lock tests, returning its id
+    async fn insert_test_user<'e, E: sqlx::PgExecutor<'e>>(executor: E, username: &str) -> Uuid {
+        sqlx::query_scalar(
+            "INSERT INTO users (username, email, password_hash) VALUES ($1, $1 || '@example.com', 'x') RETURNING id"
+        )
+        .bind(username)
+        .fetch_one(executor)
+        .await
+        .expect("Failed to create user")
+    }
+
+    #[tokio::test]
+    async fn test_edit_lock_takeover_and_expiry() {
+        let Some(pool) = test_pool().await else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+
+        let mut tx = pool.begin().await.expect("Failed to start transaction");
+        let alice = insert_test_user(&mut *tx, "lock_test_alice").await;
+        let bob = insert_test_user(&mut *tx, "lock_test_bob").await;
+        let preparation = Preparation::create(&mut *tx, "Lock test", "veg", "both", "Bench", None, None, None, None, None, "en", None, PREP_STATUS_PUBLISHED)
+            .await
+            .expect("Failed to create preparation");
+
+        let outcome = EditLock::acquire(&mut *tx, preparation.id, alice, false).await.expect("Failed to lock");
+        assert!(matches!(outcome, EditLockOutcome::Acquired(ref lock) if lock.username == "lock_test_alice"));
+        // Bob sees Alice's lock; Alice's heartbeat keeps it
+        let outcome = EditLock::acquire(&mut *tx, preparation.id, bob, false).await.expect("Failed to lock");
+        assert!(matches!(outcome, EditLockOutcome::HeldBy(ref lock) if lock.user_id == alice));
+        let outcome = EditLock::acquire(&mut *tx, preparation.id, alice, false).await.expect("Failed to lock");
+        assert!(matches!(outcome, EditLockOutcome::Acquired(_)));
+
+        // A forced take-over moves the lock, and Alice's next heartbeat learns of it
+        let outcome = EditLock::acquire(&mut *tx, preparation.id, bob, true).await.expect("Failed to lock");
+        assert!(matches!(outcome, EditLockOutcome::Acquired(ref lock) if lock.user_id == bob));
+        let outcome = EditLock::acquire(&mut *tx, preparation.id, alice, false).await.expect("Failed to lock");
+        assert!(matches!(outcome, EditLockOutcome::HeldBy(ref lock) if lock.user_id == bob));
+
+        // Once Bob's lock expires it no longer counts, and Alice can take it
+        sqlx::query("UPDATE preparation_edit_locks SET expires_at = NOW() - INTERVAL '1 minute' WHERE preparation_id = $1")
+            .bind(preparation.id)
+            .execute(&mut *tx)
+            .await
+            .expect("Failed to expire lock");
+        assert!(EditLock::holder(&mut *tx, preparation.id).await.expect("Failed to read lock").is_none());
+        let outcome = EditLock::acquire(&mut *tx, preparation.id, alice, false).await.expect("Failed to lock");
+        assert!(matches!(outcome, EditLockOutcome::Acquired(ref lock) if lock.user_id == alice));
+
+        // Releasing someone else's lock does nothing
+        EditLock::release(&mut *tx, preparation.id, bob).await.expect("Failed to release");
+        assert!(EditLock::holder(&mut *tx, preparation.id).await.expect("Failed to read lock").is_some());
+        EditLock::release(&mut *tx, preparation.id, alice).await.expect("Failed to release");
+        assert!(EditLock::holder(&mut *tx, preparation.id).await.expect("Failed to read lock").is_none());
+
+        tx.rollback().await.expect("Failed to roll back");
+    }
+
+    #[tokio::test]
+    async fn test_edit_lock_concurrent_acquisition() {
+        let Some(pool) = test_pool().await else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+
+        // Concurrent callers need separate connections, so this test commits and cleans up after itself
+        let suffix = Uuid::new_v4().simple().to_string();
+        let users = [
+            insert_test_user(&pool, &format!("lock_a_{}", &suffix[..12])).await,
+            insert_test_user(&pool, &format!("lock_b_{}", &suffix[..12])).await,
+        ];
+        let preparation = Preparation::create(&pool, "Concurrent lock test", "veg", "both", "Bench", None, None, None, None, None, "en", None, PREP_STATUS_PUBLISHED)
+            .await
+            .expect("Failed to create preparation");
+
+        let (first, second) = tokio::join!(
+            EditLock::acquire(&pool, preparation.id, users[0], false),
+            EditLock::acquire(&pool, preparation.id, users[1], false),
+        );
+        let outcomes = [first.expect("Failed to lock"), second.expect("Failed to lock")];
+
+        sqlx::query("DELETE FROM preparations WHERE id = $1").bind(preparation.id).execute(&pool).await.expect("Failed to clean up");
+        sqlx::query("DELETE FROM users WHERE id = ANY($1)").bind(&users[..]).execute(&pool).await.expect("Failed to clean up");
+
+        let acquired: Vec<_> = outcomes
+            .iter()
+            .filter_map(|outcome| match outcome {
+                EditLockOutcome::Acquired(lock) => Some(lock.user_id),
+                EditLockOutcome::HeldBy(_) => None,
+            })
+            .collect();
+        assert_eq!(acquired.len(), 1);
+        // The loser is told who won
+        let holder = outcomes.iter().find_map(|outcome| match outcome {
+            EditLockOutcome::HeldBy(lock) => Some(lock.user_id),
+            EditLockOutcome::Acquired(_) => None,
+        });
+        assert_eq!(holder, Some(acquired[0]));
+    }
+
+    #[test]
+    fn test_stats_json_shape() {
+        let totals = |total| TableStats { total, last_created_at: None, last_updated_at: None };
+        let stats = Stats {
+            products: totals(3),
+            preparations: PreparationStats {
+                totals: totals(2),
+                by_type: [("veg", 2), ("meat", 0)].into_iter().collect(),
+                by_shift: [("both", 2)].into_iter().collect(),
+            },
+        };
+
+        assert_eq!(
+            serde_json::to_value(&stats).unwrap(),
+            serde_json::json!({
+                "products": { "total": 3, "last_created_at": null, "last_updated_at": null },
+                "preparations": {
+                    "total": 2,
+                    "last_created_at": null,
+                    "last_updated_at": null,
+                    "by_type": { "meat": 0, "veg": 2 },
+                    "by_shift": { "both": 2 },
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_check_allowed_location() {
+        let allowed = vec!["Cold Room".to_string(), "Dry Store".to_string()];
+
+        assert!(check_allowed_location("Cold Room", &allowed).is_ok());
+        assert!(check_allowed_location("  Dry Store ", &allowed).is_ok());
+        assert!(check_allowed_location("cold room", &allowed).is_err());
+        assert!(check_allowed_location("Freezer", &allowed).is_err());
+        // With no locations configured, anything goes
+        assert!(check_allowed_location("Freezer", &[]).is_ok());
+    }
+
+    #[test]
+    fn test_draft_form_reports_every_problem() {
+        let form = NewPreparationForm {
+            name: "Half-done Salsa".to_string(),
+            prep_type: String::new(),
+            shift: "dinner".to_string(),
+            location: "Freezer".to_string(),
+            // Only blank steps, which is the same as none
+            steps: vec!["  ".to_string(), String::new()],
+            prep_time_minutes: "900".to_string(),
+            difficulty: "easy".to_string(),
+            yield_quantity: String::new(),
+            yield_unit: String::new(),
+            lang: "fr".to_string(),
+        };
+        let allowed = vec!["Cold Room".to_string()];
+
+        assert!(form.validate_draft().is_ok());
+        let problems = form.problems_with_locations(&allowed);
+        assert_eq!(
+            error_messages(&problems),
+            vec![
+                "Invalid preparation type",
+                "Invalid shift selection",
+                "Steps cannot be empty",
+                "Prep time must be between 1 and 600 minutes",
+                "Invalid language",
+                "Unknown location \"Freezer\". Choose one from the list.",
+            ]
+        );
+        let codes: Vec<_> = problems.iter().map(|p| (p.field.as_str(), p.code.as_str())).collect();
+        assert_eq!(
+            codes,
+            vec![
+                ("prep_type", "invalid_choice"),
+                ("shift", "invalid_choice"),
+                ("steps", "required"),
+                ("prep_time_minutes", "out_of_range"),
+                ("lang", "invalid_choice"),
+                ("location", "unknown_location"),
+            ]
+        );
+        assert_eq!(form.validate_with_locations(&allowed), Err(problems));
+
+        // Values the database would reject are blanked, the rest kept
+        let draft = form.for_draft();
+        assert_eq!(draft.shift, "");
+        assert_eq!(draft.prep_time(), None);
+        assert_eq!(draft.location, "Freezer");
+        assert_eq!(draft.difficulty(), Some("easy"));
+        assert_eq!(draft.lang(), crate::i18n::primary_lang());
+
+        let unnamed = NewPreparationForm { name: "  ".to_string(), ..form };
+        assert!(unnamed.validate_draft().is_err());
+    }
+
+    #[test]
+    fn test_preparation_yield_is_optional_and_positive() {
+        let form = NewPreparationForm {
+            name: "Stock".to_string(),
+            prep_type: "veg".to_string(),
+            shift: "both".to_string(),
+            location: "Bench".to_string(),
+            steps: vec![String::new(), "Simmer".to_string()],
+            ..NewPreparationForm::default()
+        };
+        assert!(form.problems().is_empty());
+        assert_eq!((form.yield_quantity(), form.yield_unit()), (None, None));
+
+        let scaled = NewPreparationForm { yield_quantity: " 2.5 ".to_string(), yield_unit: " L ".to_string(), ..form.clone() };
+        assert!(scaled.problems().is_empty());
+        assert_eq!((scaled.yield_quantity(), scaled.yield_unit()), (Some(2.5), Some("L")));
+
+        for quantity in ["0", "-4", "lots", "NaN", "inf"] {
+            let bad = NewPreparationForm { yield_quantity: quantity.to_string(), ..form.clone() };
+            let codes: Vec<_> = bad.problems().iter().map(|p| (p.field.clone(), p.code.clone())).collect();
+            assert_eq!(codes, vec![("yield_quantity".to_string(), "out_of_range".to_string())], "{}", quantity);
+            assert_eq!(bad.for_draft().yield_quantity, "");
+        }
+
+        let long_unit = NewPreparationForm { yield_unit: "x".repeat(MAX_YIELD_UNIT_CHARS + 1), ..form };
+        assert_eq!(long_unit.problems()[0].code, "too_long");
+        assert_eq!(long_unit.for_draft().yield_unit, "");
+    }
+
+    #[test]
+    fn test_preparation_list_prefs_precedence() {
+        let stored = PreparationListPrefs::from_params(Some("name"), Some("veg"), Some("lunch"));
+        let explicit = PreparationListPrefs::from_params(Some("time"), None, Some("brekkie"));
+
+        // Explicit query values beat remembered ones, even when they only set some of them
+        assert_eq!(PreparationListPrefs::resolve(explicit.clone(), Some(stored.clone())), (explicit, false));
+        // With nothing in the query, the remembered values apply and the page is told so
+        assert_eq!(PreparationListPrefs::resolve(PreparationListPrefs::default(), Some(stored.clone())), (stored.clone(), true));
+        // With neither, the defaults
+        let (defaults, remembered) = PreparationListPrefs::resolve(PreparationListPrefs::default(), None);
+        assert_eq!((defaults.sort(), defaults.filter(), remembered), (PreparationSort::Type, PreparationFilter::default(), false));
+
+        // Unknown values never survive a round trip through the cookie
+        assert_eq!(PreparationListPrefs::from_query(&stored.to_query()), stored);
+        let tampered = PreparationListPrefs::from_query("sort=name;drop&prep_type=veg&shift=dinner&x=1");
+        assert_eq!(tampered, PreparationListPrefs::from_params(Some("type"), Some("veg"), None));
+        let (_, remembered) = PreparationListPrefs::resolve(
+            PreparationListPrefs::default(),
+            Some(PreparationListPrefs { prep_type: Some("dessert".to_string()), ..Default::default() }),
+        );
+        assert!(!remembered);
+    }
+
+    #[tokio::test]
+    async fn test_user_preferences_get_and_set() {
+        let Some(pool) = test_pool().await else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+
+        let mut tx = pool.begin().await.expect("Failed to start transaction");
+        let user_id: Uuid = sqlx::query_scalar(
+            "INSERT INTO users (username, email, password_hash, role)
+             VALUES ('prefs_user', 'prefs_user@example.com', 'hash', 'staff')
+             RETURNING id"
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .expect("Failed to create user");
+
+        assert_eq!(UserPreferences::get(&mut *tx, user_id).await.unwrap(), UserPreferences::default());
+
+        let lunch_veg = UserPreferences {
+            preparations: Some(PreparationListPrefs::from_params(None, Some("veg"), Some("lunch"))),
+            ..UserPreferences::default()
+        };
+        UserPreferences::set(&mut *tx, user_id, &lunch_veg).await.expect("Failed to save preferences");
+        assert_eq!(UserPreferences::get(&mut *tx, user_id).await.unwrap(), lunch_veg);
+
+        // Recording a view keeps the filters
+        let mut viewed = crate::recently_viewed::RecentlyViewed::default();
+        viewed.record(crate::recently_viewed::ViewedKind::Product, Uuid::new_v4());
+        UserPreferences::set_recently_viewed(&mut *tx, user_id, &viewed).await.expect("Failed to save recently viewed");
+        let saved = UserPreferences::get(&mut *tx, user_id).await.unwrap();
+        assert_eq!(saved, UserPreferences { recently_viewed: viewed, ..lunch_veg });
+
+        // Saving again replaces rather than adding a row
+        UserPreferences::set(&mut *tx, user_id, &UserPreferences::default()).await.expect("Failed to save preferences");
+        assert_eq!(UserPreferences::get(&mut *tx, user_id).await.unwrap(), UserPreferences::default());
+
+        tx.rollback().await.expect("Failed to roll back");
+    }
+
+    #[tokio::test]
+    async fn test_product_update_is_audited_with_field_changes() {
+        let Some(pool) = test_pool().await else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+
+        let mut tx = pool.begin().await.expect("Failed to start transaction");
+        let product_id: Uuid = sqlx::query_scalar(
+            "INSERT INTO products (supplier_name, product_name, location, description)
+             VALUES ('Audit Farms', 'Audit Butter', 'Walk-in', 'Keep cold')
+             RETURNING id"
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .expect("Failed to create product");
+        let before = Product::get_by_id(&mut *tx, product_id).await.unwrap().expect("Product should exist");
+
+        let after = Product::update(&mut *tx, product_id, "Audit Farms", "Audit Butter", "Dry Store", Some("12345670"), None, "Keep cold")
+            .await
+            .expect("Failed to update product");
+        ProductAudit::record(&mut *tx, product_id, None, Some("kiosk"), &ProductSnapshot::from(&before), &ProductSnapshot::from(&after))
+            .await
+            .expect("Failed to record change");
+
+        let history = ProductAudit::for_product(&mut *tx, product_id).await.unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].username.as_deref(), Some("kiosk"));
+        assert_eq!(
+            history[0].changes(),
+            vec![
+                ProductFieldChange { field: "Location", before: "Walk-in".into(), after: "Dry Store".into() },
+                ProductFieldChange { field: "Barcode", before: String::new(), after: "12345670".into() },
+            ]
+        );
+
+        tx.rollback().await.expect("Failed to roll back");
+    }
+
+    #[tokio::test]
+    async fn test_cold_chain_lists_products_with_a_max_temperature_coldest_first() {
+        let Some(pool) = test_pool().await else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+
+        let mut tx = pool.begin().await.expect("Failed to start transaction");
+        // Other products in the database may be listed too, so only these are compared
+        let mut names = Vec::new();
+        let mut cream = None;
+        for (name, location, min, max) in [
+            ("Chain Ice Cream", "Freezer", None, Some(-18.0)),
+            ("Chain Cream", "Cold Room A", Some(0.0), Some(4.0)),
+            ("Chain Chocolate", "Dry Store", Some(15.0), Some(20.0)),
+            ("Chain Bananas", "Dry Store", Some(13.0), None),
+        ] {
+            let id: Uuid = sqlx::query_scalar(
+                "INSERT INTO products (supplier_name, product_name, location, description)
+                 VALUES ('Chain Foods', $1, $2, 'Keep as marked') RETURNING id"
+            )
+            .bind(name)
+            .bind(location)
+            .fetch_one(&mut *tx)
+            .await
+            .expect("Failed to create product");
+            Product::set_handling(&mut *tx, id, min, max, Some("Check on delivery")).await.unwrap();
+            names.push(name);
+            if name == "Chain Cream" {
+                cream = Some(id);
+            }
+        }
+
+        let listed = |products: Vec<Product>| -> Vec<String> {
+            products
+                .into_iter()
+                .filter(|product| names.contains(&product.product_name.as_str()))
+                .map(|product| product.product_name)
+                .collect()
+        };
+        let all = Product::cold_chain(&mut *tx, None, None).await.unwrap();
+        assert_eq!(listed(all), vec!["Chain Ice Cream", "Chain Cream", "Chain Chocolate"]);
+        let chilled = Product::cold_chain(&mut *tx, Some(5.0), None).await.unwrap();
+        assert_eq!(listed(chilled), vec!["Chain Ice Cream", "Chain Cream"]);
+        let dry_store = Product::cold_chain(&mut *tx, None, Some("Dry Store")).await.unwrap();
+        assert_eq!(listed(dry_store), vec!["Chain Chocolate"]);
+
+        let cream = Product::get_by_id(&mut *tx, cream.unwrap()).await.unwrap().unwrap();
+        assert_eq!(cream.storage_temp_label().as_deref(), Some("0 to 4 °C"));
+        assert_eq!(cream.handling_notes.as_deref(), Some("Check on delivery"));
+
+        tx.rollback().await.expect("Failed to roll back");
+    }
+
+    #[tokio::test]
+    async fn test_set_price_records_history_only_when_price_changes() {
+        let Some(pool) = test_pool().await else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+
+        let mut tx = pool.begin().await.expect("Failed to start transaction");
+        let mut product_ids = Vec::new();
+        for name in ["Price Flour", "Price Sugar"] {
+            let id: Uuid = sqlx::query_scalar(
+                "INSERT INTO products (supplier_name, product_name, location, description)
+                 VALUES ('Price Mill', $1, 'Dry Store', 'Keep dry') RETURNING id"
+            )
+            .bind(name)
+            .fetch_one(&mut *tx)
+            .await
+            .expect("Failed to create product");
+            product_ids.push(id);
+        }
+        let (flour, sugar) = (product_ids[0], product_ids[1]);
+
+        assert!(Product::set_price(&mut *tx, flour, Some(2000), Some("10kg bag"), Some("storeman")).await.unwrap());
+        // Same price with a new unit: no new history row
+        assert!(!Product::set_price(&mut *tx, flour, Some(2000), Some("10kg sack"), Some("storeman")).await.unwrap());
+        assert!(Product::set_price(&mut *tx, flour, Some(2500), Some("10kg sack"), Some("chef")).await.unwrap());
+        assert!(Product::set_price(&mut *tx, sugar, Some(1000), None, None).await.unwrap());
+        assert!(Product::set_price(&mut *tx, sugar, Some(1100), None, None).await.unwrap());
+
+        let product = Product::get_by_id(&mut *tx, flour).await.unwrap().unwrap();
+        assert_eq!((product.last_price_cents, product.unit.as_deref()), (Some(2500), Some("10kg sack")));
+
+        let history = ProductPrice::for_product(&mut *tx, flour, 10).await.unwrap();
+        let prices: Vec<_> = history.iter().map(|p| (p.price_cents, p.recorded_by.as_deref())).collect();
+        assert_eq!(prices, vec![(2500, Some("chef")), (2000, Some("storeman"))]);
+
+        // First prices aren't changes; the biggest rise comes first
+        let changes: Vec<_> = PriceChange::recent(&mut *tx, 30)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|change| product_ids.contains(&change.product_id))
+            .map(|change| (change.product_name.clone(), change.percent_label()))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![("Price Flour".to_string(), "+25.0%".to_string()), ("Price Sugar".to_string(), "+10.0%".to_string())]
+        );
+
+        // Clearing the price keeps the history
+        assert!(!Product::set_price(&mut *tx, flour, None, None, None).await.unwrap());
+        assert_eq!(ProductPrice::for_product(&mut *tx, flour, 10).await.unwrap().len(), 2);
+
+        tx.rollback().await.expect("Failed to roll back");
+    }
+
+    #[tokio::test]
+    async fn test_onboarding_steps_and_first_admin() {
+        let Some(pool) = test_pool().await else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+
+        let mut tx = pool.begin().await.expect("Failed to start transaction");
+        for table in ["product_audit", "product_price_history", "products", "preparations", "users"] {
+            sqlx::query(&format!("DELETE FROM {}", table))
+                .execute(&mut *tx)
+                .await
+                .expect("Failed to empty table");
+        }
+        assert_eq!(Onboarding::load(&mut *tx).await.unwrap(), Onboarding::default());
+
+        let admin = User::create_first_admin(&mut *tx, "Owner", "Owner@Example.com", "$2b$12$hash")
+            .await
+            .unwrap()
+            .expect("First admin should be created");
+        assert_eq!((admin.username.as_str(), admin.role.as_str()), ("owner", "admin"));
+        // Only ever the first user
+        assert!(User::create_first_admin(&mut *tx, "second", "second@example.com", "$2b$12$hash").await.unwrap().is_none());
+
+        // The first admin alone doesn't count as inviting a colleague
+        assert!(!Onboarding::load(&mut *tx).await.unwrap().has_colleagues);
+        User::create(&mut *tx, "colleague", "colleague@example.com", "$2b$12$hash").await.unwrap();
+        sqlx::query("INSERT INTO products (supplier_name, product_name, location, description) VALUES ('A', 'B', 'C', 'D')")
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        let onboarding = Onboarding::load(&mut *tx).await.unwrap();
+        assert_eq!(
+            onboarding,
+            Onboarding { has_products: true, has_preparations: false, has_colleagues: true }
+        );
+        assert_eq!(onboarding.steps_done(), 2);
+        assert!(!onboarding.is_complete());
+
+        tx.rollback().await.expect("Failed to roll back");
+    }
+
+    #[tokio::test]
+    async fn test_user_export_import_round_trip() {
+        let Some(pool) = test_pool().await else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+
+        let mut tx = pool.begin().await.expect("Failed to start transaction");
+        for (username, role, is_active) in [("transfer_admin", "admin", true), ("transfer_staff", "staff", false)] {
+            sqlx::query(
+                "INSERT INTO users (username, email, password_hash, role, is_active)
+                 VALUES ($1, $1 || '@example.com', '$2b$12$' || $1, $2, $3)"
+            )
+            .bind(username)
+            .bind(role)
+            .bind(is_active)
+            .execute(&mut *tx)
+            .await
+            .expect("Failed to create user");
+        }
+
+        let mut export = UserExport::load(&mut *tx).await.expect("Failed to export users");
+        export.users.retain(|u| u.username.starts_with("transfer_"));
+        assert_eq!(export.users.len(), 2);
+
+        // Through JSON and into a database without those users
+        let json = serde_json::to_string(&export).expect("Failed to serialize export");
+        let export = UserExport::from_json(&json).expect("Failed to parse export");
+        sqlx::query("DELETE FROM users WHERE username LIKE 'transfer_%'")
+            .execute(&mut *tx)
+            .await
+            .expect("Failed to delete users");
+
+        let report = export.import(&mut *tx, false).await.expect("Failed to import users");
+        assert_eq!(report.inserted, vec!["transfer_admin", "transfer_staff"]);
+        let reimported = UserExport::load(&mut *tx).await.expect("Failed to export users");
+        let reimported: Vec<_> = reimported.users.iter().filter(|u| u.username.starts_with("transfer_")).collect();
+        for (before, after) in export.users.iter().zip(reimported) {
+            assert_eq!(before.username, after.username);
+            assert_eq!(before.email, after.email);
+            assert_eq!(before.password_hash, after.password_hash);
+            assert_eq!(before.role, after.role);
+            assert_eq!(before.is_active, after.is_active);
+        }
+
+        // Importing again, or a new username with a taken email, changes nothing
+        let mut again = export.clone();
+        let mut clash = again.users[1].clone();
+        clash.username = "transfer_clash".to_string();
+        again.users.push(clash);
+        let report = again.import(&mut *tx, false).await.expect("Failed to import users");
+        assert_eq!(
+            report,
+            UserImportReport {
+                skipped_username: vec!["transfer_admin".to_string(), "transfer_staff".to_string()],
+                skipped_email: vec!["transfer_clash".to_string()],
+                ..Default::default()
+            }
+        );
+
+        // An existing admin is only downgraded when explicitly allowed
+        let mut downgrade = export.clone();
+        downgrade.users[0].role = "staff".to_string();
+        match downgrade.import(&mut *tx, false).await {
+            Err(UserImportError::RoleDowngrade(usernames)) => assert_eq!(usernames, vec!["transfer_admin"]),
+            other => panic!("Expected the downgrade to be refused, got {:?}", other),
+        }
+        let role: String = sqlx::query_scalar("SELECT role FROM users WHERE username = 'transfer_admin'")
+            .fetch_one(&mut *tx)
+            .await
+            .expect("Failed to fetch role");
+        assert_eq!(role, "admin");
+
+        let report = downgrade.import(&mut *tx, true).await.expect("Failed to import users");
+        assert_eq!(report.downgraded, vec!["transfer_admin"]);
+
+        tx.rollback().await.expect("Failed to roll back");
+    }
+
+    #[test]
+    fn test_user_export_rejects_other_versions() {
+        let user = r#"{"username":"a","email":"a@example.com","password_hash":"$2b$12$abc","role":"staff","is_active":true,"created_at":"2024-01-01T00:00:00Z"}"#;
+        let file = |version: u32, user: &str| {
+            format!(r#"{{"version":{},"exported_at":"2024-01-01T00:00:00Z","users":[{}]}}"#, version, user)
+        };
+
+        assert!(UserExport::from_json(&file(USER_EXPORT_VERSION, user)).is_ok());
+        let err = UserExport::from_json(&file(USER_EXPORT_VERSION + 1, user)).unwrap_err();
+        assert!(err.contains("Unsupported user export version"));
+        assert!(UserExport::from_json(r#"{"users":[]}"#).is_err());
+        assert!(UserExport::from_json(&file(USER_EXPORT_VERSION, &user.replace("staff", "owner"))).is_err());
+        assert!(UserExport::from_json(&file(USER_EXPORT_VERSION, &user.replace("$2b$12$abc", "plain"))).is_err());
+    }
+
+    #[test]
+    fn test_product_form_warnings_do_not_block() {
+        let form = NewProductForm {
+            supplier_name: "Fresh Farm Co.".to_string(),
+            product_name: "Cream".to_string(),
+            location: "Cold Room A".to_string(),
+            barcode: None,
+            description: "Keep cold".to_string(),
+            unit: None,
+            price: None,
+            storage_temp_min: None,
+            storage_temp_max: None,
+            handling_notes: None,
+        };
+        let validation = form.validate();
+        assert!(validation.is_ok());
+        assert_eq!(validation.warnings.len(), 1);
+
+        let long = NewProductForm { description: "Keep below 5°C, use within 3 days of opening".to_string(), ..form };
+        assert_eq!(long.validate(), FormValidation::default());
+
+        // Every blocking problem is reported, not just the first
+        let broken = NewProductForm {
+            supplier_name: " ".to_string(),
+            product_name: String::new(),
+            barcode: Some("123".to_string()),
+            ..long
+        };
+        let validation = broken.validate_with_locations(&["Dry Store".to_string()]);
+        assert!(!validation.is_ok());
+        let codes: Vec<_> = validation.errors.iter().map(|e| (e.field.as_str(), e.code.as_str())).collect();
+        assert_eq!(
+            codes,
+            vec![
+                ("supplier_name", "required"),
+                ("product_name", "required"),
+                ("barcode", "invalid_format"),
+                ("location", "unknown_location"),
+            ]
+        );
+        assert_eq!(error_messages(&validation.errors)[0], "Supplier name cannot be empty");
+    }
+
+    #[test]
+    fn test_product_form_price_and_unit() {
+        let form = NewProductForm {
+            supplier_name: "Fresh Farm Co.".to_string(),
+            product_name: "Cream".to_string(),
+            location: "Cold Room A".to_string(),
+            barcode: None,
+            description: "Keep below 5°C, use within 3 days of opening".to_string(),
+            unit: Some(" 2L bottle ".to_string()),
+            price: Some("$4.5".to_string()),
+            storage_temp_min: None,
+            storage_temp_max: None,
+            handling_notes: None,
+        };
+        assert!(form.validate().is_ok());
+        assert_eq!(form.price_cents(), Some(450));
+        assert_eq!(form.unit(), Some("2L bottle"));
+
+        // Blank means unknown rather than invalid
+        let blank = NewProductForm { unit: Some(" ".to_string()), price: Some(String::new()), ..form };
+        assert!(blank.validate().is_ok());
+        assert_eq!((blank.price_cents(), blank.unit()), (None, None));
+
+        let broken = NewProductForm { unit: Some("x".repeat(MAX_UNIT_CHARS + 1)), price: Some("-3".to_string()), ..blank };
+        let codes: Vec<_> = broken.validate().errors.iter().map(|e| (e.field.clone(), e.code.clone())).collect();
+        assert_eq!(
+            codes,
+            vec![("price".to_string(), "invalid".to_string()), ("unit".to_string(), "too_long".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_product_form_storage_temperature_and_handling() {
+        let form = NewProductForm {
+            supplier_name: "Fresh Farm Co.".to_string(),
+            product_name: "Cream".to_string(),
+            location: "Cold Room A".to_string(),
+            barcode: None,
+            description: "Use within 3 days of opening".to_string(),
+            unit: None,
+            price: None,
+            storage_temp_min: Some("0".to_string()),
+            storage_temp_max: Some(" 4.5°C ".to_string()),
+            handling_notes: Some(" Don't stack above 3 high ".to_string()),
+        };
+        assert!(form.validate().is_ok());
+        assert_eq!((form.storage_temp_min_c(), form.storage_temp_max_c()), (Some(0.0), Some(4.5)));
+        assert_eq!(form.handling_notes(), Some("Don't stack above 3 high"));
+
+        // Either end of the range can be left open
+        let below = NewProductForm { storage_temp_min: Some(String::new()), storage_temp_max: Some("-18".to_string()), ..form };
+        assert!(below.validate().is_ok());
+        assert_eq!((below.storage_temp_min_c(), below.storage_temp_max_c()), (None, Some(-18.0)));
+
+        let codes = |form: &NewProductForm| -> Vec<(String, String)> {
+            form.validate().errors.iter().map(|e| (e.field.clone(), e.code.clone())).collect()
+        };
+        let broken = NewProductForm {
+            storage_temp_min: Some("cold".to_string()),
+            storage_temp_max: Some("75".to_string()),
+            handling_notes: Some("x".repeat(MAX_HANDLING_NOTES_CHARS + 1)),
+            ..below
+        };
+        // 75 is a room in Fahrenheit, not a fridge in Celsius
+        assert_eq!(
+            codes(&broken),
+            vec![
+                ("storage_temp_min".to_string(), "invalid".to_string()),
+                ("storage_temp_max".to_string(), "out_of_range".to_string()),
+                ("handling_notes".to_string(), "too_long".to_string()),
+            ]
+        );
+
+        let backwards = NewProductForm {
+            storage_temp_min: Some("8".to_string()),
+            storage_temp_max: Some("2".to_string()),
+            handling_notes: None,
+            ..broken
+        };
+        assert_eq!(codes(&backwards), vec![("storage_temp_min".to_string(), "above_max".to_string())]);
+    }
+
+    #[test]
+    fn test_form_schemas_match_the_validators() {
+        let required = |schema: Vec<FieldSchema>| -> Vec<&str> {
+            schema.into_iter().filter(|field| field.required && field.kind != "file").map(|field| field.name).collect()
+        };
+        let blank_errors = |errors: Vec<ValidationError>| -> Vec<String> {
+            errors.into_iter().filter(|e| e.code == "required").map(|e| e.field).collect()
+        };
+
+        // The fields a blank form is told it needs are the ones marked required
+        let blank_product = NewProductForm {
+            supplier_name: String::new(),
+            product_name: String::new(),
+            location: String::new(),
+            barcode: None,
+            description: String::new(),
+            unit: None,
+            price: None,
+            storage_temp_min: None,
+            storage_temp_max: None,
+            handling_notes: None,
+        };
+        assert_eq!(required(NewProductForm::schema(&[])), blank_errors(blank_product.validate().errors));
+        let preparation_required = required(NewPreparationForm::schema(&[]));
+        assert_eq!(preparation_required, vec!["name", "prep_type", "shift", "location", "steps"]);
+        let problems = NewPreparationForm::default().problems();
+        assert!(blank_errors(problems.clone()).iter().all(|field| preparation_required.contains(&field.as_str())));
+        assert!(problems.iter().any(|e| e.field == "prep_type" && e.code == "invalid_choice"));
+
+        let preparation = NewPreparationForm::schema(&[]);
+        let values = |name: &str| preparation.iter().find(|field| field.name == name).and_then(|field| field.values.clone());
+        assert_eq!(values("prep_type"), Some(PREP_TYPES.map(String::from).to_vec()));
+        assert_eq!(values("shift"), Some(SHIFTS.map(String::from).to_vec()));
+        assert_eq!(values("difficulty"), Some(DIFFICULTIES.map(String::from).to_vec()));
+        assert_eq!(values("location"), None);
+
+        // Once admins set up locations, the location is a choice between them
+        let locations = vec!["Dry Store".to_string(), "Freezer".to_string()];
+        let location = NewProductForm::schema(&locations).into_iter().find(|field| field.name == "location").unwrap();
+        assert_eq!((location.kind, location.values), ("choice", Some(locations)));
+
+        let json = serde_json::to_value(NewProductForm::schema(&[])).unwrap();
+        assert_eq!(json[0], serde_json::json!({ "name": "supplier_name", "type": "string", "required": true, "max_length": 255 }));
+    }
+
+    #[test]
+    fn test_register_form_reports_one_error_per_field() {
+        let form = RegisterForm {
+            username: "ab".to_string(),
+            email: "chef".to_string(),
+            password: "secret1".to_string(),
+            confirm_password: "secret2".to_string(),
+        };
+        let errors = form.validate().unwrap_err();
+        let codes: Vec<_> = errors.iter().map(|e| (e.field.as_str(), e.code.as_str())).collect();
+        assert_eq!(
+            codes,
+            vec![("username", "too_short"), ("email", "invalid_format"), ("confirm_password", "mismatch")]
+        );
+
+        let valid = RegisterForm {
+            username: "chef_sam".to_string(),
+            email: "sam@example.com".to_string(),
+            confirm_password: "secret1".to_string(),
+            ..form
+        };
+        assert_eq!(valid.validate(), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_get_recent_preparations_newest_first() {
+        let Some(pool) = test_pool().await else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+
+        // get_recent takes a pool, so commit and clean up rather than roll back
+        let mut ids = Vec::new();
+        for (name, age_minutes) in [("Recent test old", 2i32), ("Recent test new", 1)] {
+            let preparation = Preparation::create(
+                &pool, name, "veg", "both", "Bench", None, None, None, None, None, "en", None, PREP_STATUS_PUBLISHED,
+            )
+            .await
+            .expect("Failed to create preparation");
+            // Put both in the future so they are the newest rows whatever else is in the table
+            sqlx::query("UPDATE preparations SET created_at = NOW() + make_interval(days => 1, mins => -$2) WHERE id = $1")
+                .bind(preparation.id)
+                .bind(age_minutes)
+                .execute(&pool)
+                .await
+                .expect("Failed to set created_at");
+            ids.push(preparation.id);
+        }
+
+        let recent = Preparation::get_recent(&pool, 2).await;
+        sqlx::query("DELETE FROM preparations WHERE id = ANY($1)")
+            .bind(&ids)
+            .execute(&pool)
+            .await
+            .expect("Failed to clean up");
+
+        let names: Vec<_> = recent.expect("Failed to fetch recent").into_iter().map(|p| p.name).collect();
+        assert_eq!(names, vec!["Recent test new", "Recent test old"]);
+    }
+
+    #[tokio::test]
+    async fn test_translations_share_a_group_with_one_per_language() {
+        let Some(pool) = test_pool().await else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+
+        let mut tx = pool.begin().await.expect("Failed to start transaction");
+        async fn create(conn: &mut sqlx::PgConnection, name: &str, lang: &str, group: Option<Uuid>) -> Result<Preparation, sqlx::Error> {
+            Preparation::create(conn, name, "veg", "both", "Bench", None, None, None, None, None, lang, group, PREP_STATUS_PUBLISHED).await
+        }
+        let original = create(&mut tx, "Translation test salsa", "en", None).await.expect("Failed to create preparation");
+        let other = create(&mut tx, "Translation test relish", "en", None).await.expect("Failed to create preparation");
+        assert_ne!(original.translation_group, other.translation_group);
+
+        // On its own, a preparation is the only version in its group
+        let alone = Preparation::translations(&mut *tx, original.translation_group)
+            .await
+            .expect("Failed to list translations");
+        assert_eq!(alone.iter().map(|t| t.id).collect::<Vec<_>>(), vec![original.id]);
+        assert!(Preparation::get_translation(&mut *tx, original.translation_group, "es")
+            .await
+            .expect("Failed to look up translation")
+            .is_none());
+
+        let translation = create(&mut tx, "Salsa de prueba", "es", Some(original.translation_group))
+            .await
+            .expect("Failed to create translation");
+        let found = Preparation::get_translation(&mut *tx, original.translation_group, "es")
+            .await
+            .expect("Failed to look up translation")
+            .expect("Translation should be found");
+        assert_eq!(found.id, translation.id);
+        let both = Preparation::translations(&mut *tx, original.translation_group)
+            .await
+            .expect("Failed to list translations");
+        let langs: Vec<_> = both.iter().map(|t| (t.lang.as_str(), t.id)).collect();
+        assert_eq!(langs, vec![("en", original.id), ("es", translation.id)]);
+
+        // A second Spanish version in the same group is refused
+        assert!(create(&mut tx, "Otra salsa", "es", Some(original.translation_group)).await.is_err());
+
+        tx.rollback().await.expect("Failed to roll back");
+    }
+
+    #[tokio::test]
+    async fn test_find_preparation_by_name_and_type() {
+        let Some(pool) = test_pool().await else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+
+        let mut tx = pool.begin().await.expect("Failed to start transaction");
+        let sauce = Preparation::create(
+            &mut *tx, "Duplicate test sauce", "veg", "both", "Bench", None, None, None, None, None, "en", None, PREP_STATUS_PUBLISHED,
+        )
+        .await
+        .expect("Failed to create preparation");
+
+        let found = Preparation::find_by_name_and_type(&mut *tx, "  duplicate TEST sauce ", "veg")
+            .await
+            .expect("Failed to search");
+        assert_eq!(found.map(|p| p.id), Some(sauce.id));
+
+        // The same name under another type is a different preparation
+        let other_type = Preparation::find_by_name_and_type(&mut *tx, "Duplicate test sauce", "meat")
+            .await
+            .expect("Failed to search");
+        assert!(other_type.is_none());
+
+        tx.rollback().await.expect("Failed to roll back");
+    }
+
+    #[tokio::test]
+    async fn test_double_submitted_form_creates_one_product() {
+        let Some(pool) = test_pool().await else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+
+        let mut tx = pool.begin().await.expect("Failed to start transaction");
+        let token = Uuid::new_v4();
+        let (supplier, name, description) = ("Form Token Supplier", "Form token test flour", "Plain flour, 10kg");
+
+        // First submit: create the product and record the token with it
+        let first = Product::create(&mut *tx, supplier, name, "Dry store", None, None, description)
+            .await
+            .expect("Failed to create product");
+        assert!(FormSubmission::record(&mut tx, token, FormEntity::Product, first.id)
+            .await
+            .expect("Failed to record token"));
+
+        // A second submit racing the first also creates a product, finds the token
+        // taken and rolls back its savepoint
+        let mut savepoint = sqlx::Connection::begin(&mut *tx).await.expect("Failed to start savepoint");
+        let second = Product::create(&mut *savepoint, supplier, name, "Dry store", None, None, description)
+            .await
+            .expect("Failed to create product");
+        assert!(!FormSubmission::record(&mut savepoint, token, FormEntity::Product, second.id)
+            .await
+            .expect("Failed to record token"));
+        savepoint.rollback().await.expect("Failed to roll back savepoint");
+
+        // A later resubmit is sent to the first product; the token means nothing for preparations
+        let created = FormSubmission::created_with(&mut *tx, token, FormEntity::Product).await.expect("Failed to look up token");
+        assert_eq!(created, Some(first.id));
+        let created = FormSubmission::created_with(&mut *tx, token, FormEntity::Preparation).await.expect("Failed to look up token");
+        assert_eq!(created, None);
+
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM products WHERE product_name = $1")
+            .bind(name)
+            .fetch_one(&mut *tx)
+            .await
+            .expect("Failed to count products");
+        assert_eq!(count, 1);
+
+        tx.rollback().await.expect("Failed to roll back");
+    }
+
+    #[tokio::test]
+    async fn test_location_suggestions_come_from_products_and_preparations() {
+        let Some(pool) = test_pool().await else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+
+        let mut tx = pool.begin().await.expect("Failed to start transaction");
+        for location in ["Zqx Cool Room", "Zqx Dry Store", "Zqx_Freezer"] {
+            Product::create(&mut *tx, "Suggest Supplier", "Suggest test item", location, None, None, "")
+                .await
+                .expect("Failed to create product");
+        }
+        // Shared with a product, so it is only suggested once
+        Preparation::create(
+            &mut *tx, "Suggest test prep", "veg", "both", "Zqx Cool Room", None, None, None, None, None, "en", None, PREP_STATUS_PUBLISHED,
+        )
+        .await
+        .expect("Failed to create preparation");
+        Preparation::create(
+            &mut *tx, "Suggest test prep", "veg", "both", "zqx pass", None, None, None, None, None, "en", None, PREP_STATUS_PUBLISHED,
+        )
+        .await
+        .expect("Failed to create preparation");
+
+        let suggestions = Location::suggest(&mut *tx, "zqx", 10).await.expect("Failed to suggest");
+        assert_eq!(suggestions, vec!["Zqx Cool Room", "Zqx Dry Store", "Zqx_Freezer", "zqx pass"]);
+
+        // `_` is taken literally rather than matching the space
+        let suggestions = Location::suggest(&mut *tx, "zqx_", 10).await.expect("Failed to suggest");
+        assert_eq!(suggestions, vec!["Zqx_Freezer"]);
+
+        let suggestions = Location::suggest(&mut *tx, "zqx", 2).await.expect("Failed to suggest");
+        assert_eq!(suggestions.len(), 2);
+
+        tx.rollback().await.expect("Failed to roll back");
+    }
+
+    #[tokio::test]
+    async fn test_picture_sizes_follow_their_pictures() {
+        let Some(pool) = test_pool().await else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+
+        async fn size_of(conn: &mut sqlx::PgConnection, table: &str, id: Uuid) -> Option<i64> {
+            sqlx::query_scalar(&format!("SELECT picture_size_bytes FROM {} WHERE id = $1", table))
+                .bind(id)
+                .fetch_one(conn)
+                .await
+                .expect("Failed to read picture size")
+        }
+
+        let mut tx = pool.begin().await.expect("Failed to start transaction");
+        let url = format!("/static/uploads/{}.jpg", Uuid::new_v4());
+        let product = Product::create(&mut *tx, "Size Supplier", "Size test tray", "Dry Store", None, Some(&url), "")
+            .await
+            .expect("Failed to create product");
+        ImageReference::record_size(&mut *tx, &url, 40_000_000_000).await.expect("Failed to record size");
+
+        assert_eq!(size_of(&mut tx, "products", product.id).await, Some(40_000_000_000));
+
+        let heaviest = StorageItem::heaviest(&mut *tx, 1).await.expect("Failed to list heaviest");
+        assert_eq!((heaviest[0].id, heaviest[0].total_bytes), (product.id, 40_000_000_000));
+        assert_eq!(heaviest[0].page_url(), format!("/product/{}", product.id));
+
+        // Imported steps share the picture, and its size, of the step they came from
+        let preparation = Preparation::create(
+            &mut *tx, "Size test prep", "veg", "both", "Dry Store", None, None, None, None, None, "en", None, PREP_STATUS_PUBLISHED,
+        )
+        .await
+        .expect("Failed to create preparation");
+        let step_url = format!("/static/uploads/{}.jpg", Uuid::new_v4());
+        PreparationStep::create_many(&mut *tx, preparation.id, 1, &[("Slice", Some(&step_url))])
+            .await
+            .expect("Failed to create step");
+        ImageReference::record_size(&mut *tx, &step_url, 2048).await.expect("Failed to record size");
+        let copies = PreparationStep::create_many(&mut *tx, preparation.id, 2, &[("Slice again", Some(&step_url))])
+            .await
+            .expect("Failed to copy step");
+        assert_eq!(size_of(&mut tx, "preparation_steps", copies[0].id).await, Some(2048));
+
+        // Keeping the picture keeps its size; replacing it forgets the old one
+        Product::update(&mut *tx, product.id, "Size Supplier", "Size test tray", "Dry Store", None, Some(&url), "renamed")
+            .await
+            .expect("Failed to update product");
+        assert_eq!(size_of(&mut tx, "products", product.id).await, Some(40_000_000_000));
+        Product::update(&mut *tx, product.id, "Size Supplier", "Size test tray", "Dry Store", None, Some("/static/uploads/new.jpg"), "")
+            .await
+            .expect("Failed to update product");
+        assert_eq!(size_of(&mut tx, "products", product.id).await, None);
+
+        tx.rollback().await.expect("Failed to roll back");
+    }
+
+    #[tokio::test]
+    async fn test_audit_export_stream_covers_only_its_range() {
+        let Some(pool) = test_pool().await else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+
+        let mut tx = pool.begin().await.expect("Failed to start transaction");
+        let product = Product::create(&mut *tx, "Audit Export Supplier", "Audit export test tray", "Dry Store", None, None, "")
+            .await
+            .expect("Failed to create product");
+        let before = ProductSnapshot { description: "old".to_string(), ..ProductSnapshot::default() };
+        let after = ProductSnapshot { description: "new".to_string(), ..ProductSnapshot::default() };
+        ProductAudit::record(&mut *tx, product.id, None, Some("auditor"), &before, &after)
+            .await
+            .expect("Failed to record audit");
+
+        // now() is the transaction's start time, so the entry sits at it
+        let now: DateTime<Utc> = sqlx::query_scalar("SELECT now()").fetch_one(&mut *tx).await.expect("Failed to read now()");
+        let found: Vec<ProductAuditEntry> = ProductAudit::stream_between(&mut *tx, now, now + chrono::Duration::seconds(1))
+            .try_collect()
+            .await
+            .expect("Failed to stream audit entries");
+        let found: Vec<_> = found.into_iter().filter(|entry| entry.product_id == product.id).collect();
+        assert_eq!(found.len(), 1);
+        assert_eq!((found[0].product_name.as_str(), found[0].audit.username.as_deref()), ("Audit export test tray", Some("auditor")));
+        assert_eq!(found[0].audit.changes()[0].after, "new");
+
+        // The end of the range is left out
+        let empty: Vec<ProductAuditEntry> = ProductAudit::stream_between(&mut *tx, now - chrono::Duration::days(1), now)
+            .try_collect()
+            .await
+            .expect("Failed to stream audit entries");
+        assert!(empty.iter().all(|entry| entry.product_id != product.id));
+
+        tx.rollback().await.expect("Failed to roll back");
+    }
+
+    #[tokio::test]
+    async fn test_adjacent_products_follow_the_homepage_order() {
+        let Some(pool) = test_pool().await else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+
+        let mut tx = pool.begin().await.expect("Failed to start transaction");
+        // Products from before any other, oldest first, so they end the list
+        let mut ids = Vec::new();
+        for (name, created_at) in [("Adjacent oldest", "1990-01-01T00:00:00Z"), ("Adjacent middle", "1990-01-02T00:00:00Z"), ("Adjacent newest", "1990-01-03T00:00:00Z")] {
+            let product = Product::create(&mut *tx, "Adjacent Supplier", name, "Dry Store", None, None, "")
+                .await
+                .expect("Failed to create product");
+            sqlx::query("UPDATE products SET created_at = $2::timestamptz WHERE id = $1")
+                .bind(product.id)
+                .bind(created_at)
+                .execute(&mut *tx)
+                .await
+                .expect("Failed to backdate product");
+            ids.push(product.id);
+        }
+        let (oldest, middle, newest) = (ids[0], ids[1], ids[2]);
+
+        let adjacent = Product::get_adjacent(&mut *tx, middle).await.expect("Failed to get neighbours");
+        assert_eq!(adjacent, AdjacentProducts { previous: Some(newest), next: Some(oldest) });
+        // The oldest product is last on the homepage
+        let adjacent = Product::get_adjacent(&mut *tx, oldest).await.expect("Failed to get neighbours");
+        assert_eq!(adjacent, AdjacentProducts { previous: Some(middle), next: None });
+        assert_eq!(Product::get_adjacent(&mut *tx, newest).await.expect("Failed to get neighbours").next, Some(middle));
+        assert_eq!(
+            Product::get_adjacent(&mut *tx, Uuid::new_v4()).await.expect("Failed to get neighbours"),
+            AdjacentProducts::default()
+        );
+
+        tx.rollback().await.expect("Failed to roll back");
+    }
+
+    #[tokio::test]
+    async fn test_photo_drafts_stay_hidden_until_saved() {
+        let Some(pool) = test_pool().await else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+
+        let mut tx = pool.begin().await.expect("Failed to start transaction");
+        let drafts: Vec<PhotoDraft> = (1..=2)
+            .map(|n| PhotoDraft {
+                product_name: format!("Photo draft test {}", n),
+                picture_url: format!("/static/uploads/photo-draft-test-{}.jpg", n),
+                webp_url: (n == 2).then(|| "/static/uploads/photo-draft-test-2.webp".to_string()),
+                picture_size_bytes: 1000 * n as i64,
+                lqip: None,
+            })
+            .collect();
+        let ids = Product::create_photo_drafts(&mut *tx, &drafts).await.expect("Failed to create drafts");
+        assert_eq!(ids.len(), 2);
+        let first = Product::get_by_id(&mut *tx, ids[0]).await.expect("Failed to get draft").expect("Draft missing");
+        assert!(first.is_draft());
+        assert_eq!(first.product_name, "Photo draft test 1");
+        assert_eq!(first.picture_url.as_deref(), Some("/static/uploads/photo-draft-test-1.jpg"));
+
+        let listed = ProductSummary::drafts(&mut *tx).await.expect("Failed to list drafts");
+        assert!(ids.iter().all(|id| listed.iter().any(|draft| draft.id == *id)));
+        // Drafts are nobody's neighbour and not among the supplier's products
+        assert_eq!(Product::get_adjacent(&mut *tx, ids[0]).await.expect("Failed to get neighbours"), AdjacentProducts::default());
+        let blank_supplier = ProductSummary::for_suppliers(&mut *tx, &[String::new()]).await.expect("Failed to list products");
+        assert!(!blank_supplier.iter().any(|product| ids.contains(&product.id)));
+
+        // Saving the edit form publishes the draft
+        let published = Product::update(&mut *tx, ids[0], "Draft Supplier", "Tinned tomatoes", "Dry Store", None, first.picture_url.as_deref(), "Diced")
+            .await
+            .expect("Failed to update draft");
+        assert!(!published.is_draft());
+        assert_eq!(published.webp_url, None);
+
+        // Only drafts past their time are cleaned up, with their images
+        sqlx::query("UPDATE products SET created_at = NOW() - INTERVAL '31 days' WHERE id = ANY($1)")
+            .bind(&ids)
+            .execute(&mut *tx)
+            .await
+            .expect("Failed to backdate drafts");
+        let (deleted, images) = Product::delete_stale_drafts(&mut *tx, PRODUCT_DRAFT_MAX_AGE_DAYS)
+            .await
+            .expect("Failed to delete old drafts");
+        assert!(deleted >= 1);
+        assert!(images.contains(&"/static/uploads/photo-draft-test-2.webp".to_string()));
+        assert!(!images.contains(&"/static/uploads/photo-draft-test-1.jpg".to_string()));
+        assert!(Product::get_by_id(&mut *tx, ids[1]).await.expect("Failed to get draft").is_none());
+        assert!(Product::get_by_id(&mut *tx, ids[0]).await.expect("Failed to get product").is_some());
+
+        tx.rollback().await.expect("Failed to roll back");
+    }
+
+    #[tokio::test]
+    async fn test_apply_tags_counts_only_real_changes() {
+        let Some(pool) = test_pool().await else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+
+        let mut tx = pool.begin().await.expect("Failed to start transaction");
+        let mut ids = Vec::new();
+        for name in ["Tag test flour", "Tag test oats"] {
+            let product = Product::create(&mut *tx, "Tag Supplier", name, "Dry Store", None, None, "")
+                .await
+                .expect("Failed to create product");
+            ids.push(product.id);
+        }
+        let missing = Uuid::new_v4();
+        let tags = vec!["gluten free".to_string(), "bulk".to_string()];
+
+        let change = Product::apply_tags(&mut *tx, &[ids[0], missing, ids[1], missing], &tags, TagMode::Add)
+            .await
+            .expect("Failed to add tags");
+        assert_eq!(change, TagChange { products: 2, changed: 4, not_found: vec![missing] });
+        assert_eq!(Product::tags(&mut *tx, ids[0]).await.expect("Failed to load tags"), vec!["bulk", "gluten free"]);
+
+        // Tags a product already has aren't counted again
+        let change = Product::apply_tags(&mut *tx, &ids, &["bulk".to_string(), "organic".to_string()], TagMode::Add)
+            .await
+            .expect("Failed to add tags");
+        assert_eq!((change.products, change.changed), (2, 2));
+
+        let change = Product::apply_tags(&mut *tx, &ids[..1], &["bulk".to_string(), "vegan".to_string()], TagMode::Remove)
+            .await
+            .expect("Failed to remove tags");
+        assert_eq!(change, TagChange { products: 1, changed: 1, not_found: Vec::new() });
+        assert_eq!(Product::tags(&mut *tx, ids[0]).await.expect("Failed to load tags"), vec!["gluten free", "organic"]);
+        assert_eq!(Product::tags(&mut *tx, ids[1]).await.expect("Failed to load tags"), vec!["bulk", "gluten free", "organic"]);
+
+        tx.rollback().await.expect("Failed to roll back");
+    }
+
+    #[tokio::test]
+    async fn test_supplier_schedule_follows_a_merge() {
+        let Some(pool) = test_pool().await else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+
+        let mut tx = pool.begin().await.expect("Failed to start transaction");
+        let three_pm = NaiveTime::from_hms_opt(15, 0, 0).unwrap();
+        let schedule = SupplierSchedule::save(&mut *tx, "Schedule Test Foods", &[1], three_pm, &[3])
+            .await
+            .expect("Failed to save schedule");
+        assert_eq!(schedule.cutoff_days(), "Tue");
+        // Saving again replaces the days
+        let schedule = SupplierSchedule::save(&mut *tx, "Schedule Test Foods", &[4, 1], three_pm, &[0, 3])
+            .await
+            .expect("Failed to save schedule");
+        assert_eq!((schedule.cutoff_days(), schedule.delivery_days()), ("Tue, Fri".to_string(), "Mon, Thu".to_string()));
+
+        // Merging moves the schedule along with the products
+        let product = Product::create(&mut *tx, "Schedule Test Foods", "Schedule test chips", "Freezer", None, None, "")
+            .await
+            .expect("Failed to create product");
+        let merged = Product::merge_suppliers(&mut *tx, "Schedule Test Foods", "Schedule Test Foods Pty Ltd")
+            .await
+            .expect("Failed to merge suppliers");
+        assert_eq!(merged, 1);
+        let names = Product::supplier_names(&mut *tx).await.expect("Failed to list suppliers");
+        assert!(names.contains(&"Schedule Test Foods Pty Ltd".to_string()));
+        let products = ProductSummary::for_suppliers(&mut *tx, &["Schedule Test Foods Pty Ltd".to_string()])
+            .await
+            .expect("Failed to list products");
+        assert_eq!(products.iter().map(|product| product.id).collect::<Vec<_>>(), vec![product.id]);
+
+        let scheduled = |schedules: &[SupplierSchedule]| {
+            schedules
+                .iter()
+                .filter(|schedule| schedule.supplier_name.starts_with("Schedule Test"))
+                .map(|schedule| (schedule.supplier_name.clone(), schedule.cutoff_days()))
+                .collect::<Vec<_>>()
+        };
+        let schedules = SupplierSchedule::list(&mut *tx).await.expect("Failed to list schedules");
+        assert_eq!(scheduled(&schedules), vec![("Schedule Test Foods Pty Ltd".to_string(), "Tue, Fri".to_string())]);
+
+        // A target with its own schedule keeps it
+        SupplierSchedule::save(&mut *tx, "Schedule Test Wholesale", &[5], three_pm, &[])
+            .await
+            .expect("Failed to save schedule");
+        Product::merge_suppliers(&mut *tx, "Schedule Test Foods Pty Ltd", "Schedule Test Wholesale")
+            .await
+            .expect("Failed to merge suppliers");
+        let schedules = SupplierSchedule::list(&mut *tx).await.expect("Failed to list schedules");
+        assert_eq!(scheduled(&schedules), vec![("Schedule Test Wholesale".to_string(), "Sat".to_string())]);
+
+        assert!(SupplierSchedule::delete(&mut *tx, "Schedule Test Wholesale").await.expect("Failed to delete"));
+        assert!(!SupplierSchedule::delete(&mut *tx, "Schedule Test Wholesale").await.expect("Failed to delete"));
+
+        tx.rollback().await.expect("Failed to roll back");
+    }
+
+    #[tokio::test]
+    async fn test_usernames_and_emails_ignore_case() {
+        let Some(pool) = test_pool().await else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+
+        let mut tx = pool.begin().await.expect("Failed to start transaction");
+        let user = User::create(&mut *tx, " Case_Test_Tom ", "Case.Tom@Example.com", "x")
+            .await
+            .expect("Failed to create user");
+        assert_eq!(user.username, "case_test_tom");
+        assert_eq!(user.email, "case.tom@example.com");
+
+        // Each failing insert gets its own savepoint so the transaction stays usable
+        for (username, email, message) in [
+            ("CASE_TEST_TOM", "other@example.com", "That username is already taken"),
+            ("case_test_other", "CASE.TOM@example.com", "An account with that email already exists"),
+        ] {
+            let mut savepoint = sqlx::Connection::begin(&mut *tx).await.expect("Failed to start savepoint");
+            let err = User::create(&mut *savepoint, username, email, "x")
+                .await
+                .expect_err("Duplicate user was created");
+            assert_eq!(user_conflict_message(&err), Some(message));
+            savepoint.rollback().await.expect("Failed to roll back savepoint");
+        }
+
+        tx.rollback().await.expect("Failed to roll back");
+        assert_eq!(user_conflict_message(&sqlx::Error::RowNotFound), None);
+
+        // Lookups take a pool, so commit and clean up
+        let user = User::create(&pool, "case_test_login", "case.login@example.com", "x")
+            .await
+            .expect("Failed to create user");
+        let by_username = User::get_by_username(&pool, "Case_Test_LOGIN").await;
+        let by_email = User::get_by_email(&pool, "CASE.Login@example.com").await;
+        sqlx::query("DELETE FROM users WHERE id = $1")
+            .bind(user.id)
+            .execute(&pool)
+            .await
+            .expect("Failed to clean up");
+
+        assert_eq!(by_username.expect("Failed to look up user").map(|u| u.id), Some(user.id));
+        assert_eq!(by_email.expect("Failed to look up user").map(|u| u.id), Some(user.id));
+    }
 }
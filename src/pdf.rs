@@ -0,0 +1,441 @@
+use std::fmt::Write as _;
+
+/// A4 page size in PDF points
+const PAGE_WIDTH: f32 = 595.0;
+const PAGE_HEIGHT: f32 = 842.0;
+const MARGIN: f32 = 50.0;
+const CONTENT_WIDTH: f32 = PAGE_WIDTH - 2.0 * MARGIN;
+
+/// Line height as a multiple of the font size
+const LINE_SPACING: f32 = 1.3;
+
+/// Helvetica glyph widths for ASCII 32..=126, in thousandths of the font size
+const HELVETICA_WIDTHS: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278, // space to /
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, // 0-9
+    278, 278, 584, 584, 584, 556, 1015, // : to @
+    667, 667, 722, 722, 667, 611, 778, 722, 278, 500, 667, 556, 833, // A-M
+    722, 778, 667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, // N-Z
+    278, 278, 278, 469, 556, 333, // [ to `
+    556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500, 222, 833, // a-m
+    556, 556, 556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, // n-z
+    334, 260, 334, 584, // { to ~
+];
+
+/// Helvetica-Bold glyph widths for ASCII 32..=126, in thousandths of the font size
+const HELVETICA_BOLD_WIDTHS: [u16; 95] = [
+    278, 333, 474, 556, 556, 889, 722, 238, 333, 333, 389, 584, 278, 333, 278, 278, // space to /
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, // 0-9
+    333, 333, 584, 584, 584, 611, 975, // : to @
+    722, 722, 722, 722, 667, 611, 778, 722, 278, 556, 722, 611, 833, // A-M
+    722, 778, 667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, // N-Z
+    333, 278, 333, 584, 556, 333, // [ to `
+    556, 611, 556, 611, 556, 333, 611, 611, 278, 278, 556, 278, 889, // a-m
+    611, 611, 611, 611, 389, 556, 333, 611, 556, 778, 556, 556, 500, // n-z
+    389, 280, 389, 584, // { to ~
+];
+
+/// Fonts available to `PdfWriter`, both built into every PDF reader
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Font {
+    Regular,
+    Bold,
+}
+
+impl Font {
+    fn resource_name(&self) -> &'static str {
+        match self {
+            Font::Regular => "F1",
+            Font::Bold => "F2",
+        }
+    }
+
+    /// Width of `text` in points at `size`
+    fn text_width(&self, text: &str, size: f32) -> f32 {
+        let widths = match self {
+            Font::Regular => &HELVETICA_WIDTHS,
+            Font::Bold => &HELVETICA_BOLD_WIDTHS,
+        };
+        let units: u32 = text
+            .chars()
+            .map(|c| match c as u32 {
+                code @ 32..=126 => u32::from(widths[(code - 32) as usize]),
+                _ => 556,
+            })
+            .sum();
+        units as f32 * size / 1000.0
+    }
+}
+
+/// A JPEG embedded as-is; PDF readers decode it themselves (DCTDecode)
+struct JpegImage {
+    width: u32,
+    height: u32,
+    components: u8,
+    data: Vec<u8>,
+}
+
+/// Minimal writer for printable A4 documents
+///
+/// Lays out wrapped text top to bottom, starting new pages as needed, using the
+/// built-in Helvetica fonts. Images must be JPEGs, which are embedded without
+/// decoding; other formats are left to the caller to describe in text.
+pub struct PdfWriter {
+    title: String,
+    pages: Vec<String>,
+    current: String,
+    /// Baseline of the next line, measured up from the bottom of the page
+    y: f32,
+    images: Vec<JpegImage>,
+}
+
+impl PdfWriter {
+    /// Start a document; `title` is shown in the reader's window title
+    pub fn new(title: &str) -> PdfWriter {
+        PdfWriter {
+            title: title.to_string(),
+            pages: Vec::new(),
+            current: String::new(),
+            y: PAGE_HEIGHT - MARGIN,
+            images: Vec::new(),
+        }
+    }
+
+    /// Add a paragraph, wrapped to the page width; line breaks in `text` are kept
+    pub fn text(&mut self, text: &str, font: Font, size: f32) {
+        let line_height = size * LINE_SPACING;
+        for line in text.lines().flat_map(|line| wrap(line, font, size, CONTENT_WIDTH)) {
+            self.ensure_space(line_height);
+            self.y -= size;
+            if !line.is_empty() {
+                let _ = writeln!(
+                    self.current,
+                    "BT /{} {} Tf {} {:.2} Td ({}) Tj ET",
+                    font.resource_name(),
+                    size,
+                    MARGIN,
+                    self.y,
+                    encode_text(&line)
+                );
+            }
+            self.y -= line_height - size;
+        }
+    }
+
+    /// Leave a vertical gap of `points`
+    pub fn space(&mut self, points: f32) {
+        self.y -= points;
+    }
+
+    /// Add a JPEG scaled to fit the page width and `max_height`
+    ///
+    /// Returns false, adding nothing, when `data` isn't a JPEG this writer can embed.
+    pub fn jpeg(&mut self, data: Vec<u8>, max_height: f32) -> bool {
+        let Some((width, height, components)) = jpeg_info(&data) else {
+            return false;
+        };
+
+        let scale = (CONTENT_WIDTH / width as f32).min(max_height / height as f32).min(1.0);
+        let (draw_width, draw_height) = (width as f32 * scale, height as f32 * scale);
+
+        self.ensure_space(draw_height);
+        self.y -= draw_height;
+        self.images.push(JpegImage { width, height, components, data });
+        let _ = writeln!(
+            self.current,
+            "q {:.2} 0 0 {:.2} {} {:.2} cm /Im{} Do Q",
+            draw_width,
+            draw_height,
+            MARGIN,
+            self.y,
+            self.images.len()
+        );
+        true
+    }
+
+    /// Start a new page unless `height` more points fit on this one
+    fn ensure_space(&mut self, height: f32) {
+        let page_is_empty = self.current.is_empty();
+        if self.y - height < MARGIN && !page_is_empty {
+            self.pages.push(std::mem::take(&mut self.current));
+            self.y = PAGE_HEIGHT - MARGIN;
+        }
+    }
+
+    /// Serialise the document
+    pub fn finish(mut self) -> Vec<u8> {
+        if !self.current.is_empty() || self.pages.is_empty() {
+            self.pages.push(std::mem::take(&mut self.current));
+        }
+
+        // Objects 1-4 are fixed; images come next, then a page and its content per page
+        let first_image = 5;
+        let first_page = first_image + self.images.len();
+        let page_ids: Vec<usize> = (0..self.pages.len()).map(|i| first_page + 2 * i).collect();
+
+        let mut out: Vec<u8> = Vec::new();
+        let mut offsets: Vec<usize> = Vec::new();
+        out.extend_from_slice(b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n");
+
+        let mut object = |out: &mut Vec<u8>, body: &[u8]| {
+            offsets.push(out.len());
+            out.extend_from_slice(format!("{} 0 obj\n", offsets.len()).as_bytes());
+            out.extend_from_slice(body);
+            out.extend_from_slice(b"\nendobj\n");
+        };
+
+        object(&mut out, b"<< /Type /Catalog /Pages 2 0 R >>");
+        let kids: Vec<String> = page_ids.iter().map(|id| format!("{} 0 R", id)).collect();
+        object(
+            &mut out,
+            format!("<< /Type /Pages /Kids [{}] /Count {} >>", kids.join(" "), page_ids.len()).as_bytes(),
+        );
+        object(
+            &mut out,
+            b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>",
+        );
+        object(
+            &mut out,
+            b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica-Bold /Encoding /WinAnsiEncoding >>",
+        );
+
+        for image in &self.images {
+            let color_space = match image.components {
+                1 => "/DeviceGray",
+                4 => "/DeviceCMYK",
+                _ => "/DeviceRGB",
+            };
+            let mut body = format!(
+                "<< /Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace {} \
+                 /BitsPerComponent 8 /Filter /DCTDecode /Length {} >>\nstream\n",
+                image.width,
+                image.height,
+                color_space,
+                image.data.len()
+            )
+            .into_bytes();
+            body.extend_from_slice(&image.data);
+            body.extend_from_slice(b"\nendstream");
+            object(&mut out, &body);
+        }
+
+        let image_refs: String = (0..self.images.len())
+            .map(|i| format!("/Im{} {} 0 R ", i + 1, first_image + i))
+            .collect();
+        for (i, content) in self.pages.iter().enumerate() {
+            object(
+                &mut out,
+                format!(
+                    "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] \
+                     /Resources << /Font << /F1 3 0 R /F2 4 0 R >> /XObject << {}>> >> \
+                     /Contents {} 0 R >>",
+                    PAGE_WIDTH,
+                    PAGE_HEIGHT,
+                    image_refs,
+                    page_ids[i] + 1
+                )
+                .as_bytes(),
+            );
+            let mut body = format!("<< /Length {} >>\nstream\n", content.len()).into_bytes();
+            body.extend_from_slice(content.as_bytes());
+            body.extend_from_slice(b"\nendstream");
+            object(&mut out, &body);
+        }
+
+        let info = format!("<< /Title ({}) /Producer (Kitchen Hand Guide) >>", encode_text(&self.title));
+        object(&mut out, info.as_bytes());
+
+        let xref_offset = out.len();
+        let mut xref = format!("xref\n0 {}\n0000000000 65535 f \n", offsets.len() + 1);
+        for offset in &offsets {
+            let _ = writeln!(xref, "{:010} 00000 n ", offset);
+        }
+        let _ = write!(
+            xref,
+            "trailer\n<< /Size {} /Root 1 0 R /Info {} 0 R >>\nstartxref\n{}\n%%EOF\n",
+            offsets.len() + 1,
+            offsets.len(),
+            xref_offset
+        );
+        out.extend_from_slice(xref.as_bytes());
+        out
+    }
+}
+
+/// Break `text` into lines no wider than `width`, splitting words only when
+/// a single word is wider than the line
+fn wrap(text: &str, font: Font, size: f32, width: f32) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+
+    for word in text.split_whitespace() {
+        let candidate = if line.is_empty() { word.to_string() } else { format!("{} {}", line, word) };
+        if font.text_width(&candidate, size) <= width {
+            line = candidate;
+            continue;
+        }
+        if !line.is_empty() {
+            lines.push(std::mem::take(&mut line));
+        }
+        for c in word.chars() {
+            line.push(c);
+            if font.text_width(&line, size) > width && line.chars().count() > 1 {
+                line.pop();
+                lines.push(std::mem::replace(&mut line, c.to_string()));
+            }
+        }
+    }
+
+    if !line.is_empty() || lines.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// Encode `text` as a PDF string body in WinAnsiEncoding
+///
+/// Parentheses and backslashes are escaped and non-ASCII bytes written as octal
+/// escapes; characters the encoding lacks become `?`.
+fn encode_text(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for c in text.chars() {
+        let byte = match c {
+            '\u{20}'..='\u{7e}' | '\u{a0}'..='\u{ff}' => c as u8,
+            '\u{20ac}' => 0x80,
+            '\u{2026}' => 0x85,
+            '\u{2018}' => 0x91,
+            '\u{2019}' => 0x92,
+            '\u{201c}' => 0x93,
+            '\u{201d}' => 0x94,
+            '\u{2022}' => 0x95,
+            '\u{2013}' => 0x96,
+            '\u{2014}' => 0x97,
+            '\t' => b' ',
+            _ => b'?',
+        };
+        match byte {
+            b'(' | b')' | b'\\' => {
+                encoded.push('\\');
+                encoded.push(byte as char);
+            }
+            0x20..=0x7e => encoded.push(byte as char),
+            _ => {
+                let _ = write!(encoded, "\\{:03o}", byte);
+            }
+        }
+    }
+    encoded
+}
+
+/// Width, height and colour components from a JPEG's frame header
+fn jpeg_info(data: &[u8]) -> Option<(u32, u32, u8)> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+
+    let mut pos = 2;
+    while pos + 4 <= data.len() {
+        if data[pos] != 0xFF {
+            return None;
+        }
+        let marker = data[pos + 1];
+        // Fill bytes and standalone markers carry no length
+        if marker == 0xFF {
+            pos += 1;
+            continue;
+        }
+        if marker == 0x01 || (0xD0..=0xD7).contains(&marker) {
+            pos += 2;
+            continue;
+        }
+        let length = usize::from(u16::from_be_bytes([data[pos + 2], data[pos + 3]]));
+        // Baseline, extended and progressive Huffman frames
+        if matches!(marker, 0xC0..=0xC2) {
+            let frame = data.get(pos + 4..pos + 10)?;
+            let height = u32::from(u16::from_be_bytes([frame[1], frame[2]]));
+            let width = u32::from(u16::from_be_bytes([frame[3], frame[4]]));
+            let components = frame[5];
+            if width == 0 || height == 0 || !matches!(components, 1 | 3 | 4) {
+                return None;
+            }
+            return Some((width, height, components));
+        }
+        if marker == 0xDA || length < 2 {
+            return None;
+        }
+        pos += 2 + length;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// SOI, a JFIF APP0 segment and a baseline frame header for a 640x480 RGB image
+    fn jpeg_header() -> Vec<u8> {
+        let mut data = vec![0xFF, 0xD8];
+        data.extend_from_slice(&[0xFF, 0xE0, 0x00, 0x10]);
+        data.extend_from_slice(b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0");
+        data.extend_from_slice(&[0xFF, 0xC0, 0x00, 0x11, 0x08, 0x01, 0xE0, 0x02, 0x80, 0x03]);
+        data.extend_from_slice(&[0; 9]);
+        data.extend_from_slice(&[0xFF, 0xD9]);
+        data
+    }
+
+    #[test]
+    fn test_wrap_fits_width() {
+        let text = "Slice the zucchini into long ribbons with a peeler and keep them chilled until service";
+        let lines = wrap(text, Font::Regular, 11.0, 200.0);
+
+        assert!(lines.len() > 1);
+        assert!(lines.iter().all(|line| Font::Regular.text_width(line, 11.0) <= 200.0));
+        assert_eq!(lines.join(" "), text);
+        // A word wider than the line is split rather than overflowing
+        let long = wrap(&"W".repeat(60), Font::Bold, 11.0, 200.0);
+        assert!(long.len() > 1);
+        assert!(long.iter().all(|line| Font::Bold.text_width(line, 11.0) <= 200.0));
+    }
+
+    #[test]
+    fn test_encode_text_escapes_and_maps_to_win_ansi() {
+        assert_eq!(encode_text("Dice (small) \\ cubes"), "Dice \\(small\\) \\\\ cubes");
+        assert_eq!(encode_text("Café – 4°C"), "Caf\\351 \\226 4\\260C");
+        assert_eq!(encode_text("Chilli 🌶"), "Chilli ?");
+    }
+
+    #[test]
+    fn test_jpeg_info_reads_frame_header() {
+        assert_eq!(jpeg_info(&jpeg_header()), Some((640, 480, 3)));
+        assert_eq!(jpeg_info(b"\x89PNG\r\n\x1a\n"), None);
+        assert_eq!(jpeg_info(&[0xFF, 0xD8, 0xFF]), None);
+    }
+
+    #[test]
+    fn test_finish_writes_valid_xref() {
+        let mut pdf = PdfWriter::new("Zucchini (ribbons)");
+        pdf.text("Zucchini Ribbons", Font::Bold, 20.0);
+        assert!(pdf.jpeg(jpeg_header(), 200.0));
+        assert!(!pdf.jpeg(b"not an image".to_vec(), 200.0));
+        for n in 1..=80 {
+            pdf.text(&format!("Step {}", n), Font::Regular, 11.0);
+        }
+        let bytes = pdf.finish();
+        let text = String::from_utf8_lossy(&bytes);
+
+        assert!(bytes.starts_with(b"%PDF-1.4"));
+        assert!(text.contains("/Count 2"));
+        assert!(text.contains("/Title (Zucchini \\(ribbons\\))"));
+        // startxref points at the xref table, and each entry at its object
+        let startxref: usize = text.rsplit("startxref\n").next().unwrap().lines().next().unwrap().parse().unwrap();
+        assert!(bytes[startxref..].starts_with(b"xref"));
+        let entries: Vec<usize> = text[text.find("0000000000 65535 f \n").unwrap() + 20..]
+            .lines()
+            .take_while(|line| line.ends_with(" n "))
+            .map(|line| line[..10].parse().unwrap())
+            .collect();
+        for (i, offset) in entries.iter().enumerate() {
+            assert!(bytes[*offset..].starts_with(format!("{} 0 obj", i + 1).as_bytes()));
+        }
+    }
+}
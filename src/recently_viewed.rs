@@ -0,0 +1,271 @@
+use crate::auth::JwtKeys;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Cookie holding the recently viewed list for visitors without an account (kiosks included)
+pub const RECENTLY_VIEWED_COOKIE: &str = "recently_viewed";
+
+/// Most items remembered; viewing another drops the oldest
+pub const RECENTLY_VIEWED_LIMIT: usize = 10;
+
+/// Longest cookie value accepted; a full list is well under this
+const MAX_COOKIE_CHARS: usize = 512;
+
+/// Purpose the cookie is signed for, so no other signed value passes as one
+const SIGNATURE_PURPOSE: &str = "recently_viewed";
+
+/// What a recently viewed id points at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ViewedKind {
+    Product,
+    Preparation,
+}
+
+impl ViewedKind {
+    /// Letter the kind is written as in the cookie
+    fn code(self) -> char {
+        match self {
+            ViewedKind::Product => 'p',
+            ViewedKind::Preparation => 'r',
+        }
+    }
+
+    fn from_code(code: char) -> Option<ViewedKind> {
+        match code {
+            'p' => Some(ViewedKind::Product),
+            'r' => Some(ViewedKind::Preparation),
+            _ => None,
+        }
+    }
+
+    /// Label shown next to the item
+    pub fn label(self) -> &'static str {
+        match self {
+            ViewedKind::Product => "product",
+            ViewedKind::Preparation => "preparation",
+        }
+    }
+}
+
+/// A product or preparation someone opened
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ViewedItem {
+    pub kind: ViewedKind,
+    pub id: Uuid,
+}
+
+/// The last products and preparations viewed, most recent first
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct RecentlyViewed(Vec<ViewedItem>);
+
+impl RecentlyViewed {
+    pub fn items(&self) -> &[ViewedItem] {
+        &self.0
+    }
+
+    /// Move the item to the front, dropping the oldest once over `RECENTLY_VIEWED_LIMIT`
+    pub fn record(&mut self, kind: ViewedKind, id: Uuid) {
+        let item = ViewedItem { kind, id };
+        self.0.retain(|viewed| *viewed != item);
+        self.0.insert(0, item);
+        self.0.truncate(RECENTLY_VIEWED_LIMIT);
+    }
+
+    /// The cookie value: each item as its kind letter and simple UUID, then the signature
+    pub fn to_cookie_value(&self, keys: &JwtKeys) -> String {
+        let payload = self
+            .0
+            .iter()
+            .map(|item| format!("{}{}", item.kind.code(), item.id.simple()))
+            .collect::<Vec<_>>()
+            .join("_");
+        let signature = keys.sign_value(SIGNATURE_PURPOSE, &payload);
+        format!("{}.{}", payload, signature)
+    }
+
+    /// Read a cookie value written by `to_cookie_value`
+    ///
+    /// Anything oversized, unsigned or signed with an unknown key reads as an
+    /// empty list rather than an error, since losing the list only costs a search.
+    pub fn from_cookie_value(value: &str, keys: &JwtKeys) -> RecentlyViewed {
+        if value.len() > MAX_COOKIE_CHARS {
+            return RecentlyViewed::default();
+        }
+        let Some((payload, signature)) = value.rsplit_once('.') else {
+            return RecentlyViewed::default();
+        };
+        if !keys.verify_value(SIGNATURE_PURPOSE, payload, signature) {
+            return RecentlyViewed::default();
+        }
+
+        let mut list = RecentlyViewed::default();
+        let items = payload.split('_').filter_map(|entry| {
+            let mut chars = entry.chars();
+            let kind = ViewedKind::from_code(chars.next()?)?;
+            let id = Uuid::try_parse(chars.as_str()).ok()?;
+            Some(ViewedItem { kind, id })
+        });
+        // Recorded oldest first, so the cookie's order (and limit) is kept
+        for item in items.collect::<Vec<_>>().into_iter().rev() {
+            list.record(item.kind, item.id);
+        }
+        list
+    }
+}
+
+/// A recently viewed item as the quick-access strip shows it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RecentItem {
+    pub id: Uuid,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: ViewedKind,
+    pub url: String,
+}
+
+/// Names and links for `list`, in its order, leaving out `exclude` (the page being shown)
+///
+/// Ids whose product or preparation has since been deleted are skipped.
+pub async fn load_items(
+    pool: &sqlx::PgPool,
+    list: &RecentlyViewed,
+    exclude: Option<Uuid>,
+) -> Result<Vec<RecentItem>, sqlx::Error> {
+    let ids_of = |kind: ViewedKind| -> Vec<Uuid> {
+        list.items().iter().filter(|item| item.kind == kind).map(|item| item.id).collect()
+    };
+    let product_ids = ids_of(ViewedKind::Product);
+    let preparation_ids = ids_of(ViewedKind::Preparation);
+
+    let products: Vec<(Uuid, String)> = if product_ids.is_empty() {
+        Vec::new()
+    } else {
+        sqlx::query_as("SELECT id, product_name FROM products WHERE id = ANY($1)")
+            .bind(&product_ids)
+            .fetch_all(pool)
+            .await?
+    };
+    let preparations: Vec<(Uuid, String)> = if preparation_ids.is_empty() {
+        Vec::new()
+    } else {
+        sqlx::query_as("SELECT id, name FROM preparations WHERE id = ANY($1)")
+            .bind(&preparation_ids)
+            .fetch_all(pool)
+            .await?
+    };
+
+    let name_of = |item: &ViewedItem| {
+        let found = match item.kind {
+            ViewedKind::Product => &products,
+            ViewedKind::Preparation => &preparations,
+        };
+        found.iter().find(|(id, _)| *id == item.id).map(|(_, name)| name.clone())
+    };
+    Ok(list
+        .items()
+        .iter()
+        .filter(|item| Some(item.id) != exclude)
+        .filter_map(|item| {
+            Some(RecentItem {
+                id: item.id,
+                name: name_of(item)?,
+                kind: item.kind,
+                url: format!("/{}/{}", item.kind.label(), item.id),
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> JwtKeys {
+        JwtKeys::new("test_secret_key_for_testing", &[])
+    }
+
+    fn id(n: u128) -> Uuid {
+        Uuid::from_u128(n)
+    }
+
+    #[test]
+    fn test_record_moves_to_front_and_drops_the_oldest() {
+        let mut list = RecentlyViewed::default();
+        for n in 1..=RECENTLY_VIEWED_LIMIT as u128 {
+            list.record(ViewedKind::Product, id(n));
+        }
+        assert_eq!(list.items().len(), RECENTLY_VIEWED_LIMIT);
+        assert_eq!(list.items()[0].id, id(10));
+
+        // Viewing one again moves it up instead of listing it twice
+        list.record(ViewedKind::Product, id(3));
+        assert_eq!(list.items().len(), RECENTLY_VIEWED_LIMIT);
+        assert_eq!(list.items()[0].id, id(3));
+
+        // One more evicts the oldest, which is now 1
+        list.record(ViewedKind::Preparation, id(11));
+        let ids: Vec<_> = list.items().iter().map(|item| item.id).collect();
+        assert_eq!(ids.len(), RECENTLY_VIEWED_LIMIT);
+        assert_eq!(ids[..3], [id(11), id(3), id(10)]);
+        assert!(!ids.contains(&id(1)));
+        assert!(ids.contains(&id(2)));
+
+        // The same id as another kind is a different item
+        list.record(ViewedKind::Product, id(11));
+        assert_eq!(list.items()[..2], [
+            ViewedItem { kind: ViewedKind::Product, id: id(11) },
+            ViewedItem { kind: ViewedKind::Preparation, id: id(11) },
+        ]);
+    }
+
+    #[test]
+    fn test_cookie_round_trips_and_stays_small() {
+        let mut list = RecentlyViewed::default();
+        for n in 1..=RECENTLY_VIEWED_LIMIT as u128 + 2 {
+            let kind = if n % 2 == 0 { ViewedKind::Product } else { ViewedKind::Preparation };
+            list.record(kind, Uuid::from_u128(u128::MAX - n));
+        }
+
+        let value = list.to_cookie_value(&keys());
+        assert!(value.len() <= MAX_COOKIE_CHARS, "{} chars", value.len());
+        assert_eq!(RecentlyViewed::from_cookie_value(&value, &keys()), list);
+        assert_eq!(RecentlyViewed::from_cookie_value(&RecentlyViewed::default().to_cookie_value(&keys()), &keys()), RecentlyViewed::default());
+    }
+
+    #[test]
+    fn test_cookie_ignores_tampered_and_foreign_values() {
+        let mut list = RecentlyViewed::default();
+        list.record(ViewedKind::Product, id(1));
+        list.record(ViewedKind::Preparation, id(2));
+        let value = list.to_cookie_value(&keys());
+
+        let empty = RecentlyViewed::default();
+        let tampered = value.replacen(&id(2).simple().to_string(), &id(3).simple().to_string(), 1);
+        assert_eq!(RecentlyViewed::from_cookie_value(&tampered, &keys()), empty);
+        let (payload, _) = value.rsplit_once('.').unwrap();
+        assert_eq!(RecentlyViewed::from_cookie_value(payload, &keys()), empty);
+        assert_eq!(RecentlyViewed::from_cookie_value(&value, &JwtKeys::new("another secret", &[])), empty);
+        assert_eq!(RecentlyViewed::from_cookie_value(&"p".repeat(MAX_COOKIE_CHARS + 1), &keys()), empty);
+        assert_eq!(RecentlyViewed::from_cookie_value("", &keys()), empty);
+
+        // A rotated secret still reads lists signed before the rotation
+        let rotated = JwtKeys::new("new secret", &["test_secret_key_for_testing"]);
+        assert_eq!(RecentlyViewed::from_cookie_value(&value, &rotated), list);
+    }
+
+    #[test]
+    fn test_cookie_keeps_only_the_limit_even_when_signed() {
+        // A list longer than the limit (e.g. from a build with a higher one) is cut, oldest first
+        let payload = (1..=RECENTLY_VIEWED_LIMIT as u128 + 3)
+            .map(|n| format!("p{}", id(n).simple()))
+            .collect::<Vec<_>>()
+            .join("_");
+        let value = format!("{}.{}", payload, keys().sign_value(SIGNATURE_PURPOSE, &payload));
+        let list = RecentlyViewed::from_cookie_value(&value, &keys());
+        assert_eq!(list.items().len(), RECENTLY_VIEWED_LIMIT);
+        assert_eq!(list.items()[0].id, id(1));
+        assert_eq!(list.items()[RECENTLY_VIEWED_LIMIT - 1].id, id(RECENTLY_VIEWED_LIMIT as u128));
+    }
+}
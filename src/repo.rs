@@ -0,0 +1,211 @@
+use crate::models::{FormEntity, FormSubmission, ImageReference, Location, NewProductForm, Product};
+use crate::storage::{self, Storage};
+use aws_sdk_s3::Client as S3Client;
+use futures_util::future::BoxFuture;
+use uuid::Uuid;
+
+/// A database call made through a repository
+pub type RepoFuture<'a, T> = BoxFuture<'a, Result<T, sqlx::Error>>;
+
+/// A validated product and its uploaded picture, ready to be saved
+pub struct NewProduct<'a> {
+    pub form: &'a NewProductForm,
+    pub picture_url: Option<&'a str>,
+    pub webp_url: Option<&'a str>,
+    /// Placeholder shown until `picture_url` loads
+    pub lqip: Option<&'a str>,
+    /// Stored size of `picture_url`, in bytes
+    pub picture_size: Option<i64>,
+    /// Token of the form that was submitted, if it had one
+    pub form_token: Option<Uuid>,
+    /// Who the first recorded price is attributed to
+    pub created_by: Option<&'a str>,
+}
+
+/// The product queries handlers need, so they can be tested without Postgres
+///
+/// `PgRepo` is the only implementation outside tests; each method wraps the
+/// model calls the handler used to make itself.
+pub trait ProductRepo: Send + Sync {
+    /// Allowed locations; empty means the location is free text
+    fn location_names(&self) -> RepoFuture<'_, Vec<String>>;
+
+    /// The product with `id`, if it exists
+    fn product_by_id(&self, id: Uuid) -> RepoFuture<'_, Option<Product>>;
+
+    /// The product with `barcode`, if any
+    fn product_by_barcode<'a>(&'a self, barcode: &'a str) -> RepoFuture<'a, Option<Product>>;
+
+    /// The product already created by the form with `token`, if any
+    fn created_with_form_token(&self, token: Uuid) -> RepoFuture<'_, Option<Uuid>>;
+
+    /// Save a product with its picture details and first price in one transaction
+    ///
+    /// None means another submit of the same form token got there first and
+    /// nothing was saved.
+    fn create_product<'a>(&'a self, product: NewProduct<'a>) -> RepoFuture<'a, Option<Product>>;
+
+    /// Delete uploads that ended up unused, unless another record shows them
+    fn discard_images<'a>(&'a self, s3_client: &'a S3Client, urls: Vec<String>) -> BoxFuture<'a, ()>;
+}
+
+/// Repositories backed by the Postgres pool
+pub struct PgRepo {
+    pool: sqlx::PgPool,
+}
+
+impl PgRepo {
+    pub fn new(pool: sqlx::PgPool) -> PgRepo {
+        PgRepo { pool }
+    }
+}
+
+impl ProductRepo for PgRepo {
+    fn location_names(&self) -> RepoFuture<'_, Vec<String>> {
+        Box::pin(Location::names(&self.pool))
+    }
+
+    fn product_by_id(&self, id: Uuid) -> RepoFuture<'_, Option<Product>> {
+        Box::pin(Product::get_by_id(&self.pool, id))
+    }
+
+    fn product_by_barcode<'a>(&'a self, barcode: &'a str) -> RepoFuture<'a, Option<Product>> {
+        Box::pin(Product::get_by_barcode(&self.pool, barcode))
+    }
+
+    fn created_with_form_token(&self, token: Uuid) -> RepoFuture<'_, Option<Uuid>> {
+        Box::pin(FormSubmission::created_with(&self.pool, token, FormEntity::Product))
+    }
+
+    fn create_product<'a>(&'a self, new: NewProduct<'a>) -> RepoFuture<'a, Option<Product>> {
+        Box::pin(async move {
+            let form = new.form;
+            let mut tx = self.pool.begin().await?;
+            let product = Product::create(
+                &mut *tx,
+                &form.supplier_name,
+                &form.product_name,
+                &form.location,
+                form.barcode.as_deref(),
+                new.picture_url,
+                &form.description,
+            )
+            .await?;
+            if let Some(token) = new.form_token {
+                if !FormSubmission::record(&mut tx, token, FormEntity::Product, product.id).await? {
+                    return Ok(None);
+                }
+            }
+            if new.webp_url.is_some() {
+                Product::set_webp_url(&mut *tx, product.id, new.webp_url).await?;
+            }
+            if new.lqip.is_some() {
+                Product::set_lqip(&mut *tx, product.id, new.lqip).await?;
+            }
+            if let (Some(url), Some(size_bytes)) = (new.picture_url, new.picture_size) {
+                ImageReference::record_size(&mut *tx, url, size_bytes).await?;
+            }
+            if form.price_cents().is_some() || form.unit().is_some() {
+                Product::set_price(&mut *tx, product.id, form.price_cents(), form.unit(), new.created_by).await?;
+            }
+            let (min, max, notes) = (form.storage_temp_min_c(), form.storage_temp_max_c(), form.handling_notes());
+            if min.is_some() || max.is_some() || notes.is_some() {
+                Product::set_handling(&mut *tx, product.id, min, max, notes).await?;
+            }
+            tx.commit().await?;
+            Ok(Some(Product {
+                storage_temp_min_c: min,
+                storage_temp_max_c: max,
+                handling_notes: notes.map(str::to_string),
+                ..product
+            }))
+        })
+    }
+
+    fn discard_images<'a>(&'a self, s3_client: &'a S3Client, mut urls: Vec<String>) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            urls.sort();
+            urls.dedup();
+            let storage = Storage::from_env(s3_client);
+            for url in urls {
+                if let Err(e) = storage::delete_if_unreferenced(&self.pool, &storage, &url).await {
+                    eprintln!("Image cleanup error: {}", e);
+                }
+            }
+        })
+    }
+}
+
+/// In-memory repositories for handler tests
+#[cfg(test)]
+#[derive(Default)]
+pub struct FakeRepo {
+    pub locations: Vec<String>,
+    pub products: std::sync::Mutex<Vec<Product>>,
+    /// Form token to the product it created
+    pub form_tokens: std::sync::Mutex<std::collections::HashMap<Uuid, Uuid>>,
+    /// Uploads passed to `discard_images`
+    pub discarded: std::sync::Mutex<Vec<String>>,
+}
+
+#[cfg(test)]
+impl ProductRepo for FakeRepo {
+    fn location_names(&self) -> RepoFuture<'_, Vec<String>> {
+        Box::pin(std::future::ready(Ok(self.locations.clone())))
+    }
+
+    fn product_by_id(&self, id: Uuid) -> RepoFuture<'_, Option<Product>> {
+        let products = self.products.lock().unwrap();
+        let found = products.iter().find(|product| product.id == id).cloned();
+        Box::pin(std::future::ready(Ok(found)))
+    }
+
+    fn product_by_barcode<'a>(&'a self, barcode: &'a str) -> RepoFuture<'a, Option<Product>> {
+        let products = self.products.lock().unwrap();
+        let found = products.iter().find(|product| product.barcode.as_deref() == Some(barcode)).cloned();
+        Box::pin(std::future::ready(Ok(found)))
+    }
+
+    fn created_with_form_token(&self, token: Uuid) -> RepoFuture<'_, Option<Uuid>> {
+        let id = self.form_tokens.lock().unwrap().get(&token).copied();
+        Box::pin(std::future::ready(Ok(id)))
+    }
+
+    fn create_product<'a>(&'a self, new: NewProduct<'a>) -> RepoFuture<'a, Option<Product>> {
+        let now = chrono::Utc::now();
+        let product = Product {
+            id: Uuid::new_v4(),
+            supplier_name: new.form.supplier_name.clone(),
+            product_name: new.form.product_name.clone(),
+            location: new.form.location.clone(),
+            barcode: new.form.barcode.clone(),
+            picture_url: new.picture_url.map(str::to_string),
+            webp_url: new.webp_url.map(str::to_string),
+            lqip: new.lqip.map(str::to_string),
+            description: new.form.description.clone(),
+            unit: new.form.unit().map(str::to_string),
+            last_price_cents: new.form.price_cents(),
+            storage_temp_min_c: new.form.storage_temp_min_c(),
+            storage_temp_max_c: new.form.storage_temp_max_c(),
+            handling_notes: new.form.handling_notes().map(str::to_string),
+            status: "published".to_string(),
+            merged_into: None,
+            created_at: now,
+            updated_at: now,
+        };
+        if let Some(token) = new.form_token {
+            let mut tokens = self.form_tokens.lock().unwrap();
+            if tokens.contains_key(&token) {
+                return Box::pin(std::future::ready(Ok(None)));
+            }
+            tokens.insert(token, product.id);
+        }
+        self.products.lock().unwrap().push(product.clone());
+        Box::pin(std::future::ready(Ok(Some(product))))
+    }
+
+    fn discard_images<'a>(&'a self, _s3_client: &'a S3Client, urls: Vec<String>) -> BoxFuture<'a, ()> {
+        self.discarded.lock().unwrap().extend(urls);
+        Box::pin(std::future::ready(()))
+    }
+}
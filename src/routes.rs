@@ -0,0 +1,537 @@
+use crate::{handlers, middleware, utils};
+use actix_files as fs;
+use actix_web::body::MessageBody;
+use actix_web::dev::{Service, ServiceFactory, ServiceRequest, ServiceResponse};
+use actix_web::{guard, web, Resource, Route};
+use std::path::{Path, PathBuf};
+
+/// Uploads get fresh names, but guide images are replaced in place, so cache for a day
+const UPLOADS_CACHE_CONTROL: &str = "public, max-age=86400";
+
+/// What the route table depends on
+#[derive(Debug, Clone)]
+pub struct RouteConfig {
+    /// Register no write routes: they, and the pages behind a login, answer 403
+    pub read_only: bool,
+    /// Let visitors read products and preparations without logging in (`PUBLIC_READ`)
+    pub public_read: bool,
+    pub static_dir: PathBuf,
+    pub upload_dir: PathBuf,
+    /// List the contents of `/static` directories (`STATIC_DIR_LISTING`, for local development only)
+    pub static_listing: bool,
+    /// Budget for `GET /api/v1/search`, shared by every worker
+    pub search_api_limit: middleware::RateLimit,
+    /// Budget for the `check-duplicate` APIs, shared by both and by every worker
+    pub duplicate_check_limit: middleware::RateLimit,
+}
+
+impl RouteConfig {
+    pub fn from_env() -> RouteConfig {
+        RouteConfig {
+            read_only: utils::read_only_mode(),
+            public_read: utils::env_flag_or("PUBLIC_READ", true),
+            static_dir: utils::static_dir(),
+            upload_dir: utils::upload_dir(),
+            static_listing: utils::env_flag("STATIC_DIR_LISTING"),
+            search_api_limit: middleware::RateLimit::per_minute(utils::env_usize("SEARCH_API_RATE_LIMIT", 30)),
+            duplicate_check_limit: middleware::RateLimit::per_minute(utils::env_usize("DUPLICATE_CHECK_RATE_LIMIT", 60)),
+        }
+    }
+}
+
+/// Files under `dir` served at `mount`, never through a symlink
+///
+/// A directory serves its `index.html` if it has one and is otherwise a 404,
+/// unless `listing` is set. Text files are sent with a UTF-8 charset.
+fn static_files(mount: &str, dir: &Path, listing: bool) -> fs::Files {
+    let base = dir.to_path_buf();
+    let files = fs::Files::new(mount, dir)
+        .index_file("index.html")
+        .prefer_utf8(true)
+        .path_filter(move |path, _| !utils::path_has_symlink(&base, path));
+    if listing {
+        files.show_files_listing()
+    } else {
+        files
+    }
+}
+
+/// Register a route that changes data or sets up a session; a read-only
+/// mirror answers every method on `path` with the read-only page instead
+fn writable(cfg: &mut web::ServiceConfig, read_only: bool, path: &str, routes: impl FnOnce(Resource) -> Resource) {
+    if read_only {
+        cfg.service(web::resource(path).to(handlers::read_only_mirror));
+    } else {
+        cfg.service(routes(web::resource(path)));
+    }
+}
+
+/// Like `writable`, for routes that require authentication
+fn protected(cfg: &mut web::ServiceConfig, read_only: bool, path: &str, routes: impl FnOnce(Resource) -> Resource) {
+    if read_only {
+        cfg.service(web::resource(path).to(handlers::read_only_mirror));
+    } else {
+        cfg.service(routes(web::resource(path)).wrap(middleware::Authentication));
+    }
+}
+
+/// Like `protected`, for a single route that is also rate limited
+///
+/// The limit sits inside authentication, so only signed-in requests count.
+fn protected_rate_limited(
+    cfg: &mut web::ServiceConfig,
+    read_only: bool,
+    path: &str,
+    limit: &middleware::RateLimit,
+    route: Route,
+) {
+    if read_only {
+        cfg.service(web::resource(path).to(handlers::read_only_mirror));
+    } else {
+        cfg.service(web::resource(path).route(route).wrap(limit.clone()).wrap(middleware::Authentication));
+    }
+}
+
+/// Register a route that only reads products or preparations; unless
+/// `public_read` is set it requires authentication like `protected`
+fn readable<T, B>(cfg: &mut web::ServiceConfig, public_read: bool, resource: Resource<T>)
+where
+    T: ServiceFactory<ServiceRequest, Config = (), Response = ServiceResponse<B>, Error = actix_web::Error, InitError = ()>
+        + 'static,
+    T::Service: 'static,
+    <T::Service as Service<ServiceRequest>>::Future: 'static,
+    B: MessageBody + 'static,
+{
+    if public_read {
+        cfg.service(resource);
+    } else {
+        cfg.service(resource.wrap(middleware::Authentication));
+    }
+}
+
+/// The app's routes
+pub fn configure(cfg: &mut web::ServiceConfig, config: &RouteConfig) {
+    let read_only = config.read_only;
+    let public_read = config.public_read;
+
+    // Read Routes - public unless PUBLIC_READ=false
+    readable(cfg, public_read, web::resource("/").guard(guard::Get()).to(handlers::index));
+    readable(cfg, public_read, web::resource("/search").guard(guard::Get()).to(handlers::search));
+    readable(cfg, public_read, web::resource("/products/export.csv").guard(guard::Get()).to(handlers::export_products_csv));
+    readable(cfg, public_read, web::resource("/products/cold-chain").guard(guard::Get()).to(handlers::cold_chain));
+    readable(cfg, public_read, web::resource("/scan/{barcode}").guard(guard::Get()).to(handlers::scan_barcode));
+    readable(
+        cfg,
+        public_read,
+        web::resource("/api/v1/products/by-barcode/{code}").guard(guard::Get()).to(handlers::api_product_by_barcode),
+    );
+    // Scripts can page through results quickly, so unlike /search this is rate limited
+    let mut api_search = web::resource("/api/v1/search").route(web::get().to(handlers::api_search));
+    if read_only {
+        api_search = api_search.default_service(web::to(handlers::read_only_fallback));
+    }
+    readable(cfg, public_read, api_search.wrap(config.search_api_limit.clone()));
+    writable(cfg, read_only, "/kiosk/{token}", |r| r.route(web::get().to(handlers::kiosk_enrol)));
+    // Registered before /api/products/{id}, which would otherwise claim the path
+    protected(cfg, read_only, "/api/products/validate", |r| {
+        r.route(web::post().to(handlers::api_validate_product))
+    });
+    // The mobile client checks as the user types, so like the search API these are rate limited
+    protected_rate_limited(
+        cfg,
+        read_only,
+        "/api/v1/products/check-duplicate",
+        &config.duplicate_check_limit,
+        web::get().to(handlers::api_check_product_duplicate),
+    );
+    protected_rate_limited(
+        cfg,
+        read_only,
+        "/api/v1/preparations/check-duplicate",
+        &config.duplicate_check_limit,
+        web::get().to(handlers::api_check_preparation_duplicate),
+    );
+    let mut api_product = web::resource("/api/products/{id}")
+        .route(web::get().to(handlers::api_product))
+        .route(web::head().to(handlers::api_product));
+    if read_only {
+        // Otherwise other methods get the resource's 405
+        api_product = api_product.default_service(web::to(handlers::read_only_fallback));
+    }
+    readable(cfg, public_read, api_product);
+    readable(cfg, public_read, web::resource("/api/schema/product").guard(guard::Get()).to(handlers::api_product_schema));
+    readable(
+        cfg,
+        public_read,
+        web::resource("/api/schema/preparation").guard(guard::Get()).to(handlers::api_preparation_schema),
+    );
+    readable(cfg, public_read, web::resource("/preparations").guard(guard::Get()).to(handlers::preparations_index));
+    readable(
+        cfg,
+        public_read,
+        web::resource("/preparations/archived").guard(guard::Get()).to(handlers::archived_preparations),
+    );
+
+    // Authentication Routes
+    writable(cfg, read_only, "/login", |r| {
+        r.route(web::get().to(handlers::login_form))
+            .route(web::post().to(handlers::login))
+    });
+    writable(cfg, read_only, "/register", |r| {
+        r.route(web::get().to(handlers::register_form))
+            .route(web::post().to(handlers::register))
+    });
+    writable(cfg, read_only, "/logout", |r| r.route(web::get().to(handlers::logout)));
+
+    // Error Pages
+    cfg.route("/401", web::get().to(handlers::error_401))
+        .route("/lang/{code}", web::get().to(handlers::set_language));
+
+    // Serve uploads (registered before /static, which would otherwise match them).
+    // Uploads are never listed, even in development. A mirror doesn't create the
+    // directory, so it only serves one that is already there (e.g. a shared mount),
+    // and it ignores `?w=`, serving originals rather than writing variants.
+    if !read_only || config.upload_dir.is_dir() {
+        let mut uploads = web::scope(utils::UPLOADS_URL_PATH);
+        if !read_only {
+            uploads = uploads.service(
+                web::resource("/{filename}")
+                    .guard(guard::fn_guard(|ctx| {
+                        ctx.head().uri.query().is_some_and(|query| query.split('&').any(|p| p.starts_with("w=")))
+                    }))
+                    .route(web::get().to(handlers::upload_variant)),
+            );
+        }
+        cfg.service(
+            uploads
+                // Only cache files that were found, so a missing image shows up once it's uploaded
+                .wrap_fn(|req, srv| {
+                    let response = srv.call(req);
+                    async move {
+                        let mut response = response.await?;
+                        if response.status().is_success() {
+                            response.headers_mut().insert(
+                                actix_web::http::header::CACHE_CONTROL,
+                                actix_web::http::header::HeaderValue::from_static(UPLOADS_CACHE_CONTROL),
+                            );
+                        }
+                        Ok(response)
+                    }
+                })
+                .service(static_files("", &config.upload_dir, false))
+        );
+    }
+    // Serve static files
+    cfg.service(static_files("/static", &config.static_dir, config.static_listing));
+
+    // Protected Routes - Require Authentication (specific routes first to avoid conflicts)
+    protected(cfg, read_only, "/product/new", |r| r.route(web::get().to(handlers::new_product_form)));
+    // A delivery's worth of photos is well over the default 50MB form limit
+    protected(cfg, read_only, "/products/bulk-photos", |r| {
+        r.app_data(
+            actix_multipart::form::MultipartFormConfig::default().total_limit(handlers::BULK_PHOTO_MAX_TOTAL_BYTES),
+        )
+        .route(web::get().to(handlers::bulk_photos_form))
+        .route(web::post().to(handlers::upload_bulk_photos))
+    });
+    protected(cfg, read_only, "/product", |r| r.route(web::post().to(handlers::create_product)));
+    protected(cfg, read_only, "/product/{id}/edit", |r| r.route(web::get().to(handlers::edit_product_form)));
+    protected(cfg, read_only, "/product/{id}/update", |r| r.route(web::post().to(handlers::update_product)));
+    protected(cfg, read_only, "/product/{id}/history", |r| r.route(web::get().to(handlers::product_history)));
+    protected(cfg, read_only, "/product/{id}/price", |r| r.route(web::post().to(handlers::update_product_price)));
+    protected(cfg, read_only, "/product/{id}/documents", |r| {
+        r.route(web::post().to(handlers::upload_product_document))
+    });
+    protected(cfg, read_only, "/product/{id}/documents/{document_id}/delete", |r| {
+        r.route(web::post().to(handlers::delete_product_document))
+    });
+    protected(cfg, read_only, "/preparation/new", |r| r.route(web::get().to(handlers::new_preparation_form)));
+    protected(cfg, read_only, "/preparation", |r| r.route(web::post().to(handlers::create_preparation)));
+    protected(cfg, read_only, "/preparation/{id}/edit", |r| {
+        r.route(web::get().to(handlers::edit_preparation_form))
+    });
+    protected(cfg, read_only, "/preparation/{id}/edit/heartbeat", |r| {
+        r.route(web::post().to(handlers::preparation_edit_heartbeat))
+    });
+    protected(cfg, read_only, "/preparation/{id}/edit/take-over", |r| {
+        r.route(web::post().to(handlers::take_over_preparation_edit))
+    });
+    protected(cfg, read_only, "/preparation/{id}/update", |r| {
+        r.route(web::post().to(handlers::update_preparation))
+    });
+    protected(cfg, read_only, "/preparation/{id}/import-steps", |r| {
+        r.route(web::post().to(handlers::import_preparation_steps))
+    });
+    protected(cfg, read_only, "/preparation/{id}/archive", |r| {
+        r.route(web::post().to(handlers::archive_preparation))
+    });
+    protected(cfg, read_only, "/preparation/{id}/unarchive", |r| {
+        r.route(web::post().to(handlers::unarchive_preparation))
+    });
+    // Preparation API - import creates data, so it requires authentication
+    protected(cfg, read_only, "/api/preparations/import", |r| {
+        r.route(web::post().to(handlers::api_import_preparation))
+    });
+    readable(cfg, public_read, web::resource("/api/preparations/{id}").guard(guard::Get()).to(handlers::api_preparation));
+    readable(
+        cfg,
+        public_read,
+        web::resource("/api/preparations/{id}/steps").guard(guard::Get()).to(handlers::api_preparation_steps),
+    );
+    readable(
+        cfg,
+        public_read,
+        web::resource("/api/locations/suggest").guard(guard::Get()).to(handlers::api_location_suggest),
+    );
+    protected(cfg, read_only, "/api/export/preparations.json", |r| {
+        r.route(web::get().to(handlers::api_export_preparations))
+    });
+    protected(cfg, read_only, "/api/stats", |r| r.route(web::get().to(handlers::api_stats)));
+    protected(cfg, read_only, "/api/health/deep", |r| r.route(web::get().to(handlers::api_health_deep)));
+
+    // Admin Routes - Require Authentication plus the admin role (checked in the handler)
+    protected(cfg, read_only, "/admin/users", |r| r.route(web::get().to(handlers::admin_users)));
+    protected(cfg, read_only, "/admin/image-checks", |r| r.route(web::get().to(handlers::admin_image_checks)));
+    protected(cfg, read_only, "/admin/check-images", |r| r.route(web::post().to(handlers::start_image_check)));
+    protected(cfg, read_only, "/admin/image-checks/rewrite", |r| {
+        r.route(web::post().to(handlers::rewrite_image_urls))
+    });
+    protected(cfg, read_only, "/admin/storage", |r| r.route(web::get().to(handlers::admin_storage)));
+    protected(cfg, read_only, "/admin/storage/backfill", |r| {
+        r.route(web::post().to(handlers::start_storage_backfill))
+    });
+    protected(cfg, read_only, "/admin/backup", |r| r.route(web::post().to(handlers::start_backup)));
+    protected(cfg, read_only, "/admin/kiosk", |r| {
+        r.route(web::get().to(handlers::admin_kiosk))
+            .route(web::post().to(handlers::create_kiosk_token))
+    });
+    protected(cfg, read_only, "/admin/kiosk/{id}/revoke", |r| {
+        r.route(web::post().to(handlers::revoke_kiosk_token))
+    });
+    protected(cfg, read_only, "/admin/locations", |r| {
+        r.route(web::get().to(handlers::admin_locations))
+            .route(web::post().to(handlers::create_location))
+    });
+    protected(cfg, read_only, "/admin/locations/{id}/rename", |r| {
+        r.route(web::post().to(handlers::rename_location))
+    });
+    protected(cfg, read_only, "/admin/locations/{id}/delete", |r| {
+        r.route(web::post().to(handlers::delete_location))
+    });
+    protected(cfg, read_only, "/admin/suppliers/merge", |r| r.route(web::post().to(handlers::merge_suppliers)));
+    protected(cfg, read_only, "/admin/products/merge", |r| {
+        r.route(web::get().to(handlers::merge_products_preview))
+            .route(web::post().to(handlers::merge_products))
+    });
+    protected(cfg, read_only, "/admin/products/tag", |r| r.route(web::post().to(handlers::bulk_tag_products)));
+    protected(cfg, read_only, "/admin/suppliers", |r| r.route(web::get().to(handlers::admin_suppliers)));
+    protected(cfg, read_only, "/admin/suppliers/schedule", |r| {
+        r.route(web::post().to(handlers::save_supplier_schedule))
+    });
+    protected(cfg, read_only, "/ordering", |r| r.route(web::get().to(handlers::ordering)));
+    protected(cfg, read_only, "/admin/audit/export", |r| r.route(web::get().to(handlers::export_audit)));
+    protected(cfg, read_only, "/admin/price-changes", |r| r.route(web::get().to(handlers::admin_price_changes)));
+    protected(cfg, read_only, "/admin/repair/steps", |r| {
+        r.route(web::post().to(handlers::repair_step_numbers))
+    });
+
+    // Detail routes (public unless PUBLIC_READ=false, MUST come after specific routes)
+    readable(cfg, public_read, web::resource("/product/{id}").guard(guard::Get()).to(handlers::product_detail));
+    readable(
+        cfg,
+        public_read,
+        web::resource("/product/{id}/documents/{document_id}").guard(guard::Get()).to(handlers::download_product_document),
+    );
+    readable(
+        cfg,
+        public_read,
+        web::resource("/preparation/{preparation_id}").guard(guard::Get()).to(handlers::preparation_detail),
+    );
+    readable(
+        cfg,
+        public_read,
+        web::resource("/preparation/{id}/search").guard(guard::Get()).to(handlers::search_preparation_steps),
+    );
+    readable(cfg, public_read, web::resource("/preparation/{id}/pdf").guard(guard::Get()).to(handlers::preparation_pdf));
+
+    // Anything else that would write, e.g. a POST to a read route, gets the read-only page too
+    if read_only {
+        cfg.default_service(web::to(handlers::read_only_fallback));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::Method;
+    use actix_web::test::{call_service, init_service, try_call_service, TestRequest};
+    use actix_web::App;
+
+    const ID: &str = "00000000-0000-0000-0000-000000000001";
+
+    fn test_config(read_only: bool) -> RouteConfig {
+        RouteConfig {
+            read_only,
+            public_read: true,
+            static_dir: PathBuf::from("./static"),
+            upload_dir: PathBuf::from("./static/uploads"),
+            static_listing: false,
+            search_api_limit: middleware::RateLimit::per_minute(30),
+            duplicate_check_limit: middleware::RateLimit::per_minute(60),
+        }
+    }
+
+    /// Every route that writes, plus the forms and pages behind a login
+    fn write_paths() -> Vec<(Method, String)> {
+        let post = |path: &str| (Method::POST, path.replace("{id}", ID));
+        let get = |path: &str| (Method::GET, path.replace("{id}", ID));
+        vec![
+            get("/kiosk/some-token"),
+            get("/login"),
+            post("/login"),
+            get("/register"),
+            post("/register"),
+            get("/logout"),
+            post("/api/products/validate"),
+            get("/api/v1/products/check-duplicate?name=milk"),
+            get("/api/v1/preparations/check-duplicate?name=stock"),
+            get("/product/new"),
+            post("/product"),
+            get("/product/{id}/edit"),
+            post("/product/{id}/update"),
+            get("/product/{id}/history"),
+            post("/product/{id}/price"),
+            post("/product/{id}/documents"),
+            post("/product/{id}/documents/{id}/delete"),
+            get("/preparation/new"),
+            post("/preparation"),
+            get("/preparation/{id}/edit"),
+            post("/preparation/{id}/edit/heartbeat"),
+            post("/preparation/{id}/edit/take-over"),
+            post("/preparation/{id}/update"),
+            post("/preparation/{id}/import-steps"),
+            post("/preparation/{id}/archive"),
+            post("/preparation/{id}/unarchive"),
+            post("/api/preparations/import"),
+            get("/api/export/preparations.json"),
+            get("/api/stats"),
+            get("/api/health/deep"),
+            get("/admin/users"),
+            get("/admin/image-checks"),
+            post("/admin/check-images"),
+            post("/admin/image-checks/rewrite"),
+            get("/admin/storage"),
+            post("/admin/storage/backfill"),
+            post("/admin/backup"),
+            get("/admin/kiosk"),
+            post("/admin/kiosk"),
+            post("/admin/kiosk/{id}/revoke"),
+            get("/admin/locations"),
+            post("/admin/locations"),
+            post("/admin/locations/{id}/rename"),
+            post("/admin/locations/{id}/delete"),
+            post("/admin/suppliers/merge"),
+            get("/admin/products/merge"),
+            post("/admin/products/merge"),
+            post("/admin/products/tag"),
+            get("/admin/suppliers"),
+            post("/admin/suppliers/schedule"),
+            get("/ordering"),
+            get("/admin/price-changes"),
+            get("/admin/audit/export"),
+            post("/admin/repair/steps"),
+            // Unsafe methods on read routes
+            post("/"),
+            post("/product/{id}"),
+            (Method::PUT, format!("/api/products/{}", ID)),
+            post("/api/v1/search"),
+            (Method::DELETE, format!("/preparation/{}", ID)),
+        ]
+    }
+
+    #[actix_web::test]
+    async fn test_read_only_mode_reaches_no_write_path() {
+        let app = init_service(App::new().configure(|cfg| configure(cfg, &test_config(true)))).await;
+
+        for (method, path) in write_paths() {
+            let request = TestRequest::default().method(method.clone()).uri(&path).to_request();
+            let response = call_service(&app, request).await;
+            assert_eq!(response.status().as_u16(), 403, "{} {}", method, path);
+        }
+
+        // Static files still load, and unknown pages are still 404s
+        let response = call_service(&app, TestRequest::get().uri("/static/styles.css").to_request()).await;
+        assert!(response.status().is_success());
+        let response = call_service(&app, TestRequest::get().uri("/no/such/page").to_request()).await;
+        assert_eq!(response.status().as_u16(), 404);
+    }
+
+    #[actix_web::test]
+    async fn test_static_directories_are_not_listed() {
+        let app = init_service(App::new().configure(|cfg| configure(cfg, &test_config(false)))).await;
+        let get = |path: &str| call_service(&app, TestRequest::get().uri(path).to_request());
+
+        for path in ["/static/", "/static/images/", "/static/uploads/"] {
+            assert_eq!(get(path).await.status().as_u16(), 404, "{}", path);
+        }
+        let response = get("/static/styles.css").await;
+        assert_eq!(response.headers().get("content-type").unwrap(), "text/css; charset=utf-8");
+
+        // The development flag lists /static, but never the uploads
+        let listing = RouteConfig { static_listing: true, ..test_config(false) };
+        let app = init_service(App::new().configure(|cfg| configure(cfg, &listing))).await;
+        let response = call_service(&app, TestRequest::get().uri("/static/").to_request()).await;
+        assert!(response.status().is_success());
+        let response = call_service(&app, TestRequest::get().uri("/static/uploads/").to_request()).await;
+        assert_eq!(response.status().as_u16(), 404);
+    }
+
+    #[actix_web::test]
+    async fn test_write_routes_need_login_when_writable() {
+        let app = init_service(App::new().configure(|cfg| configure(cfg, &test_config(false)))).await;
+
+        for path in ["/product/new", "/admin/users"] {
+            let response = try_call_service(&app, TestRequest::get().uri(path).to_request()).await;
+            assert_eq!(status_of(response), 401, "{}", path);
+        }
+    }
+
+    /// Pages and API routes that only read products and preparations
+    fn read_paths() -> Vec<String> {
+        ["/", "/search?q=milk", "/products/cold-chain?max=5", "/preparations", "/preparations/archived", "/product/{id}", "/product/{id}/documents/{id}", "/preparation/{id}", "/preparation/{id}/pdf",
+         "/api/products/{id}", "/api/preparations/{id}",
+         "/api/preparations/{id}/steps", "/api/schema/product", "/api/schema/preparation", "/api/locations/suggest?q=co", "/api/v1/search?q=milk",
+         "/scan/9300633000000"]
+            .iter()
+            .map(|path| path.replace("{id}", ID))
+            .collect()
+    }
+
+    /// Status of a response, or of the error a middleware answered with
+    fn status_of(result: Result<ServiceResponse, actix_web::Error>) -> u16 {
+        match result {
+            Ok(response) => response.status().as_u16(),
+            Err(e) => e.error_response().status().as_u16(),
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_read_routes_need_login_only_when_not_public() {
+        let private = RouteConfig { public_read: false, ..test_config(false) };
+        let app = init_service(App::new().configure(|cfg| configure(cfg, &private))).await;
+        let get = |path: &str| try_call_service(&app, TestRequest::get().uri(path).to_request());
+        for path in read_paths() {
+            assert_eq!(status_of(get(&path).await), 401, "{}", path);
+        }
+        // Logging in and the assets the login page needs stay open
+        assert_eq!(status_of(get("/login").await), 200);
+        assert_eq!(status_of(get("/static/styles.css").await), 200);
+
+        // Public by default: the handlers are reached (and, with no database here, fail)
+        let app = init_service(App::new().configure(|cfg| configure(cfg, &test_config(false)))).await;
+        for path in read_paths() {
+            let result = try_call_service(&app, TestRequest::get().uri(&path).to_request()).await;
+            assert_ne!(status_of(result), 401, "{}", path);
+        }
+    }
+}
@@ -0,0 +1,878 @@
+use crate::models::{ImageReference, Product, PRODUCT_DRAFT_MAX_AGE_DAYS};
+use crate::utils;
+use aws_sdk_s3::config::http::HttpResponse;
+use aws_sdk_s3::config::retry::RetryConfig;
+use aws_sdk_s3::error::{DisplayErrorContext, ProvideErrorMetadata, SdkError};
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::Client as S3Client;
+use bytes::Bytes;
+use futures_util::StreamExt;
+use serde::Serialize;
+use std::fmt;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Where uploaded images live: our S3 bucket and the local upload directory
+#[derive(Clone)]
+pub struct Storage {
+    pub s3_client: S3Client,
+    pub bucket_name: String,
+    pub upload_dir: PathBuf,
+}
+
+impl Storage {
+    /// Storage locations from `S3_BUCKET_NAME` and `UPLOAD_DIR`, with the same defaults as uploads
+    pub fn from_env(s3_client: &S3Client) -> Self {
+        Storage {
+            s3_client: s3_client.clone(),
+            bucket_name: std::env::var("S3_BUCKET_NAME")
+                .unwrap_or_else(|_| "kitchen-hand-guide".to_string()),
+            upload_dir: utils::upload_dir(),
+        }
+    }
+}
+
+/// Why an S3 request failed, so throttling can be told apart from bad credentials
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorageError {
+    /// Credentials are missing, wrong or not allowed to use the bucket
+    Auth(String),
+    /// The bucket (or object) doesn't exist
+    NotFound(String),
+    /// S3 asked us to slow down
+    Throttled(String),
+    /// Timed out, or S3 couldn't be reached
+    Network(String),
+    Other(String),
+}
+
+impl StorageError {
+    /// Classify an SDK error by its S3 error code, falling back to the HTTP status
+    pub fn from_sdk<E: ProvideErrorMetadata + std::error::Error + 'static>(error: &SdkError<E, HttpResponse>) -> StorageError {
+        let message = DisplayErrorContext(error).to_string();
+        match error {
+            SdkError::TimeoutError(_) | SdkError::DispatchFailure(_) => StorageError::Network(message),
+            _ => StorageError::classify(error.code(), error.raw_response().map(|r| r.status().as_u16()), message),
+        }
+    }
+
+    fn classify(code: Option<&str>, status: Option<u16>, message: String) -> StorageError {
+        match (code, status) {
+            (Some("AccessDenied" | "InvalidAccessKeyId" | "SignatureDoesNotMatch" | "ExpiredToken" | "InvalidToken"), _)
+            | (_, Some(401 | 403)) => StorageError::Auth(message),
+            (Some("NoSuchBucket" | "NoSuchKey" | "NotFound"), _) | (_, Some(404)) => StorageError::NotFound(message),
+            (Some("SlowDown" | "Throttling" | "ThrottlingException" | "RequestLimitExceeded" | "TooManyRequests"), _)
+            | (_, Some(429 | 503)) => StorageError::Throttled(message),
+            (Some("RequestTimeout" | "RequestTimeTooSkewed"), _) | (_, Some(500 | 502 | 504)) => StorageError::Network(message),
+            _ => StorageError::Other(message),
+        }
+    }
+
+    /// Short name for logs and the error counters
+    pub fn class(&self) -> &'static str {
+        match self {
+            StorageError::Auth(_) => "auth",
+            StorageError::NotFound(_) => "not_found",
+            StorageError::Throttled(_) => "throttled",
+            StorageError::Network(_) => "network",
+            StorageError::Other(_) => "other",
+        }
+    }
+
+    /// Whether trying again shortly might work
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, StorageError::Throttled(_) | StorageError::Network(_))
+    }
+
+    /// Whether the bucket settings need fixing, rather than S3 having a bad moment
+    pub fn is_misconfiguration(&self) -> bool {
+        matches!(self, StorageError::Auth(_) | StorageError::NotFound(_))
+    }
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Auth(message)
+            | StorageError::NotFound(message)
+            | StorageError::Throttled(message)
+            | StorageError::Network(message)
+            | StorageError::Other(message) => write!(f, "{}: {}", self.class(), message),
+        }
+    }
+}
+
+/// Failed S3 requests since the server started, by class, counting retried attempts too
+pub static STORAGE_ERRORS: StorageErrorCounters = StorageErrorCounters::new();
+
+/// Counters behind `STORAGE_ERRORS`
+pub struct StorageErrorCounters {
+    auth: AtomicU64,
+    not_found: AtomicU64,
+    throttled: AtomicU64,
+    network: AtomicU64,
+    other: AtomicU64,
+}
+
+/// A reading of `STORAGE_ERRORS`, as reported by `GET /api/stats`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct StorageErrorCounts {
+    pub auth: u64,
+    pub not_found: u64,
+    pub throttled: u64,
+    pub network: u64,
+    pub other: u64,
+}
+
+impl StorageErrorCounters {
+    const fn new() -> StorageErrorCounters {
+        StorageErrorCounters {
+            auth: AtomicU64::new(0),
+            not_found: AtomicU64::new(0),
+            throttled: AtomicU64::new(0),
+            network: AtomicU64::new(0),
+            other: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record(&self, error: &StorageError) {
+        let counter = match error {
+            StorageError::Auth(_) => &self.auth,
+            StorageError::NotFound(_) => &self.not_found,
+            StorageError::Throttled(_) => &self.throttled,
+            StorageError::Network(_) => &self.network,
+            StorageError::Other(_) => &self.other,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> StorageErrorCounts {
+        StorageErrorCounts {
+            auth: self.auth.load(Ordering::Relaxed),
+            not_found: self.not_found.load(Ordering::Relaxed),
+            throttled: self.throttled.load(Ordering::Relaxed),
+            network: self.network.load(Ordering::Relaxed),
+            other: self.other.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Attempts at an S3 upload before a throttling or network error is given up on
+pub const UPLOAD_ATTEMPTS: u32 = 3;
+
+/// Wait before the first retry; it doubles for each one after
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(200);
+
+/// Run `operation` up to `attempts` times while it fails with a retryable error
+///
+/// Retries wait `base_delay`, doubling each time, with jitter so uploads that
+/// were throttled together don't all come back at once. Every failed attempt
+/// is counted in `STORAGE_ERRORS`.
+pub async fn with_retries<T, F, Fut>(attempts: u32, base_delay: Duration, mut operation: F) -> Result<T, StorageError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, StorageError>>,
+{
+    let mut attempt = 1;
+    loop {
+        let error = match operation().await {
+            Ok(value) => return Ok(value),
+            Err(error) => error,
+        };
+        STORAGE_ERRORS.record(&error);
+        if !error.is_retryable() || attempt >= attempts {
+            return Err(error);
+        }
+        let delay = backoff_delay(base_delay, attempt);
+        eprintln!(
+            "S3 request failed (attempt {} of {}), retrying in {}ms: {}",
+            attempt,
+            attempts,
+            delay.as_millis(),
+            error
+        );
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+/// Wait before retry number `attempt`: between half and all of `base_delay * 2^(attempt - 1)`
+fn backoff_delay(base_delay: Duration, attempt: u32) -> Duration {
+    let ceiling = base_delay * 2u32.saturating_pow(attempt.saturating_sub(1));
+    ceiling.mul_f64(0.5 + fastrand::f64() / 2.0)
+}
+
+/// Upload an object, retrying throttling and network errors (see `with_retries`)
+///
+/// The SDK's own retries are turned off for the call, so `UPLOAD_ATTEMPTS` is the
+/// total and each attempt is logged and counted here.
+pub async fn put_object(
+    client: &S3Client,
+    bucket: &str,
+    key: &str,
+    data: Bytes,
+    content_type: &str,
+) -> Result<(), StorageError> {
+    with_retries(UPLOAD_ATTEMPTS, RETRY_BASE_DELAY, || async {
+        client
+            .put_object()
+            .bucket(bucket)
+            .key(key)
+            .body(ByteStream::from(data.clone()))
+            .content_type(content_type)
+            .customize()
+            .config_override(aws_sdk_s3::config::Builder::new().retry_config(RetryConfig::disabled()))
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|e| StorageError::from_sdk(&e))
+    })
+    .await
+}
+
+/// What `delete_if_unreferenced` did with a URL
+#[derive(Debug, PartialEq)]
+pub enum DeleteOutcome {
+    Deleted,
+    /// Other records still show the image, so it was kept
+    StillReferenced(i64),
+    /// Not one of our uploads (placeholder, bundled image or external host), so left alone
+    NotStored,
+}
+
+/// Delete an uploaded image once nothing shows it any more
+///
+/// Call this after the record that used `url` has been changed or removed, so any
+/// remaining reference belongs to another record, e.g. a preparation whose steps
+/// were imported from this one. Shared images are kept and the skip is logged.
+pub async fn delete_if_unreferenced<'e, E>(
+    executor: E,
+    storage: &Storage,
+    url: &str,
+) -> Result<DeleteOutcome, String>
+where
+    E: sqlx::PgExecutor<'e>,
+{
+    let references = ImageReference::count_for_url(executor, url)
+        .await
+        .map_err(|e| format!("Failed to count references to {}: {}", url, e))?;
+    if references > 0 {
+        println!("Keeping {}: still used by {} other record(s)", url, references);
+        return Ok(DeleteOutcome::StillReferenced(references));
+    }
+
+    if let Some(filename) = url.strip_prefix(utils::UPLOADS_URL_PATH).and_then(|rest| rest.strip_prefix('/')) {
+        let path = utils::upload_path(&storage.upload_dir, filename)?;
+        return match tokio::fs::remove_file(&path).await {
+            Ok(()) => Ok(DeleteOutcome::Deleted),
+            // Already gone, which is what we wanted
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(DeleteOutcome::Deleted),
+            Err(e) => Err(format!("Failed to delete {:?}: {}", path, e)),
+        };
+    }
+
+    if let Some(key) = utils::s3_key_from_url(url, &storage.bucket_name) {
+        storage
+            .s3_client
+            .delete_object()
+            .bucket(&storage.bucket_name)
+            .key(&key)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to delete S3 object {}: {}", key, e))?;
+        return Ok(DeleteOutcome::Deleted);
+    }
+
+    Ok(DeleteOutcome::NotStored)
+}
+
+/// How often `spawn_draft_cleanup` looks for old photo drafts
+const DRAFT_CLEANUP_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Delete photo drafts left unfinished for `PRODUCT_DRAFT_MAX_AGE_DAYS`, and their
+/// images, returning how many drafts went
+///
+/// Images that can't be deleted are only logged; the drafts are already gone.
+pub async fn delete_stale_drafts(pool: &sqlx::PgPool, storage: &Storage) -> Result<usize, String> {
+    let (deleted, images) = Product::delete_stale_drafts(pool, PRODUCT_DRAFT_MAX_AGE_DAYS)
+        .await
+        .map_err(|e| format!("Failed to delete old photo drafts: {}", e))?;
+    for url in images {
+        if let Err(e) = delete_if_unreferenced(pool, storage, &url).await {
+            eprintln!("Image cleanup error: {}", e);
+        }
+    }
+    Ok(deleted)
+}
+
+/// Clean up old photo drafts at startup and then daily, for as long as the server runs
+pub fn spawn_draft_cleanup(pool: sqlx::PgPool, s3_client: S3Client) {
+    actix_web::rt::spawn(async move {
+        let storage = Storage::from_env(&s3_client);
+        let mut interval = tokio::time::interval(DRAFT_CLEANUP_INTERVAL);
+        loop {
+            interval.tick().await;
+            match delete_stale_drafts(&pool, &storage).await {
+                Ok(0) => {}
+                Ok(deleted) => println!("Deleted {} photo draft(s) older than {} days", deleted, PRODUCT_DRAFT_MAX_AGE_DAYS),
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+    });
+}
+
+/// Where a product document's file was put, as recorded in `product_documents`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredDocument {
+    /// `s3` or `local`
+    pub storage: &'static str,
+    /// Object key under `documents/`, or the file's name in `DOCUMENT_DIR`
+    pub key: String,
+}
+
+/// Store a product document in the bucket when `S3_ENABLED`, otherwise in `DOCUMENT_DIR`
+///
+/// Documents get their own `documents/` prefix and directory, so they never mix
+/// with pictures. Like pictures they are named by a fresh UUID, never the upload's name.
+pub async fn store_document(
+    s3_client: &S3Client,
+    data: Bytes,
+    kind: utils::DocumentKind,
+) -> Result<StoredDocument, StorageError> {
+    if utils::env_flag("S3_ENABLED") {
+        let bucket = std::env::var("S3_BUCKET_NAME").unwrap_or_else(|_| "kitchen-hand-guide".to_string());
+        let key = format!("documents/{}.{}", uuid::Uuid::new_v4(), kind.extension());
+        put_object(s3_client, &bucket, &key, data, kind.content_type()).await?;
+        Ok(StoredDocument { storage: "s3", key })
+    } else {
+        let dir = utils::document_dir();
+        let key = tokio::task::spawn_blocking(move || utils::save_to_document_dir(&dir, &data, kind))
+            .await
+            .map_err(|e| StorageError::Other(format!("Saving a document panicked: {}", e)))?
+            .map_err(StorageError::Other)?;
+        Ok(StoredDocument { storage: "local", key })
+    }
+}
+
+/// Read a stored product document; None if it has gone missing
+pub async fn read_document(s3_client: Option<&S3Client>, storage: &str, key: &str) -> Result<Option<Vec<u8>>, String> {
+    match storage {
+        "s3" => {
+            let client = s3_client.ok_or_else(|| format!("No S3 client to download {}", key))?;
+            let bucket = std::env::var("S3_BUCKET_NAME").unwrap_or_else(|_| "kitchen-hand-guide".to_string());
+            let object = match client.get_object().bucket(&bucket).key(key).send().await {
+                Ok(object) => object,
+                Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => return Ok(None),
+                Err(e) => return Err(format!("Failed to download S3 object {}: {}", key, DisplayErrorContext(&e))),
+            };
+            let data = object
+                .body
+                .collect()
+                .await
+                .map_err(|e| format!("Failed to download S3 object {}: {}", key, e))?;
+            Ok(Some(data.into_bytes().to_vec()))
+        }
+        _ => match tokio::fs::read(utils::upload_path(&utils::document_dir(), key)?).await {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("Failed to read document {}: {}", key, e)),
+        },
+    }
+}
+
+/// Delete a stored product document's file; one that is already gone counts as deleted
+pub async fn delete_document(s3_client: &S3Client, storage: &str, key: &str) -> Result<(), String> {
+    match storage {
+        "s3" => {
+            let bucket = std::env::var("S3_BUCKET_NAME").unwrap_or_else(|_| "kitchen-hand-guide".to_string());
+            s3_client
+                .delete_object()
+                .bucket(&bucket)
+                .key(key)
+                .send()
+                .await
+                .map(|_| ())
+                .map_err(|e| format!("Failed to delete S3 object {}: {}", key, e))
+        }
+        _ => match tokio::fs::remove_file(utils::upload_path(&utils::document_dir(), key)?).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(format!("Failed to delete document {}: {}", key, e)),
+        },
+    }
+}
+
+/// One place uploads can be kept, as the source or target of `migrate_media`
+pub enum MediaBackend {
+    /// Files in `dir`, served at `url_prefix` (`/static/uploads` for the upload directory)
+    Local { dir: PathBuf, url_prefix: String },
+    /// Objects under `uploads/` in a bucket, served from its public URL; new ones are named by `layout`
+    S3 { client: S3Client, bucket: String, region: String, layout: utils::S3KeyLayout },
+}
+
+impl MediaBackend {
+    /// The upload directory, as the server serves it
+    pub fn local(dir: PathBuf) -> Self {
+        MediaBackend::Local { dir, url_prefix: utils::UPLOADS_URL_PATH.to_string() }
+    }
+
+    /// Name shown in progress output
+    pub fn describe(&self) -> String {
+        match self {
+            MediaBackend::Local { dir, .. } => format!("local ({})", dir.display()),
+            MediaBackend::S3 { bucket, .. } => format!("s3 ({})", bucket),
+        }
+    }
+
+    /// The file name or object key behind `url`, if it's stored here
+    fn object_key(&self, url: &str) -> Option<String> {
+        match self {
+            MediaBackend::Local { url_prefix, .. } => url
+                .strip_prefix(url_prefix.as_str())
+                .and_then(|rest| rest.strip_prefix('/'))
+                .map(str::to_string),
+            MediaBackend::S3 { bucket, .. } => utils::s3_key_from_url(url, bucket),
+        }
+    }
+
+    /// Key an image named `filename` is written under here
+    fn key_for(&self, filename: &str) -> String {
+        match self {
+            MediaBackend::Local { .. } => filename.to_string(),
+            MediaBackend::S3 { layout, .. } => layout.key(filename, chrono::Utc::now()),
+        }
+    }
+
+    /// Public URL of the image at `key`
+    fn url_for(&self, key: &str) -> String {
+        match self {
+            MediaBackend::Local { url_prefix, .. } => format!("{}/{}", url_prefix, key),
+            MediaBackend::S3 { bucket, region, .. } => utils::s3_public_url(bucket, region, key),
+        }
+    }
+
+    /// Whether the image at `key` is there
+    async fn exists(&self, key: &str) -> Result<bool, String> {
+        match self {
+            MediaBackend::Local { dir, .. } => Ok(tokio::fs::try_exists(utils::upload_path(dir, key)?)
+                .await
+                .map_err(|e| format!("Failed to check {}: {}", key, e))?),
+            MediaBackend::S3 { client, bucket, .. } => {
+                match client.head_object().bucket(bucket).key(key).send().await {
+                    Ok(_) => Ok(true),
+                    Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => Ok(false),
+                    Err(e) => Err(format!("Failed to check S3 object {}: {}", key, e)),
+                }
+            }
+        }
+    }
+
+    /// Read the image at `key`; None if it's missing
+    async fn read(&self, key: &str) -> Result<Option<Vec<u8>>, String> {
+        match self {
+            MediaBackend::Local { dir, .. } => match tokio::fs::read(utils::upload_path(dir, key)?).await {
+                Ok(data) => Ok(Some(data)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(format!("Failed to read {}: {}", key, e)),
+            },
+            MediaBackend::S3 { client, bucket, .. } => {
+                let object = match client.get_object().bucket(bucket).key(key).send().await {
+                    Ok(object) => object,
+                    Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => return Ok(None),
+                    Err(e) => return Err(format!("Failed to download S3 object {}: {}", key, e)),
+                };
+                let data = object
+                    .body
+                    .collect()
+                    .await
+                    .map_err(|e| format!("Failed to download S3 object {}: {}", key, e))?;
+                Ok(Some(data.into_bytes().to_vec()))
+            }
+        }
+    }
+
+    /// Store an image as `filename`, returning its public URL
+    async fn write(&self, filename: &str, data: Vec<u8>) -> Result<String, String> {
+        let key = self.key_for(filename);
+        match self {
+            MediaBackend::Local { dir, .. } => {
+                tokio::fs::create_dir_all(dir)
+                    .await
+                    .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+                let path = utils::upload_path(dir, filename)?;
+                tokio::fs::write(&path, data)
+                    .await
+                    .map_err(|e| format!("Failed to write {:?}: {}", path, e))?;
+            }
+            MediaBackend::S3 { client, bucket, .. } => {
+                let content_type = utils::ImageKind::from_filename(filename)
+                    .map_or("application/octet-stream", |kind| kind.content_type());
+                put_object(client, bucket, &key, Bytes::from(data), content_type)
+                    .await
+                    .map_err(|e| format!("Failed to upload {} to S3: {}", filename, e))?;
+            }
+        }
+        Ok(self.url_for(&key))
+    }
+}
+
+/// What `migrate_media` did, by URL
+#[derive(Debug, Default)]
+pub struct MediaMigrationReport {
+    /// Old and new URL of each image moved (or, in a dry run, that would be)
+    pub migrated: Vec<(String, String)>,
+    /// Already on the target, e.g. moved by an earlier run
+    pub already_on_target: Vec<String>,
+    /// Not stored in the source: placeholders, bundled images and other hosts
+    pub not_in_source: Vec<String>,
+    /// Referenced by a record, but the source has no such file
+    pub missing: Vec<String>,
+    pub failed: Vec<(String, String)>,
+}
+
+/// What happened to one URL
+enum MediaMigration {
+    Migrated(String),
+    AlreadyOnTarget,
+    NotInSource,
+    Missing,
+    Failed(String),
+}
+
+/// Copy every stored image from `from` to `to` and point the database at the copies
+///
+/// Each image is copied before the rows showing it are rewritten (all in one
+/// transaction), so an interrupted run loses nothing and can simply be run
+/// again: URLs already on the target are skipped. Up to `concurrency` images
+/// are in flight at once. Source files are left in place. A dry run only
+/// checks that each source file exists.
+pub async fn migrate_media(
+    pool: &sqlx::PgPool,
+    from: &MediaBackend,
+    to: &MediaBackend,
+    concurrency: usize,
+    dry_run: bool,
+) -> Result<MediaMigrationReport, String> {
+    let urls = ImageReference::distinct_urls(pool)
+        .await
+        .map_err(|e| format!("Failed to list image URLs: {}", e))?;
+    let total = urls.len();
+
+    let mut results = futures_util::stream::iter(urls)
+        .map(|url| async move {
+            let outcome = migrate_url(pool, from, to, &url, dry_run).await;
+            (url, outcome)
+        })
+        .buffer_unordered(concurrency.max(1));
+
+    let mut report = MediaMigrationReport::default();
+    let mut done = 0;
+    while let Some((url, outcome)) = results.next().await {
+        done += 1;
+        match outcome {
+            MediaMigration::Migrated(new_url) => {
+                println!("[{}/{}] {} {} -> {}", done, total, if dry_run { "would move" } else { "moved" }, url, new_url);
+                report.migrated.push((url, new_url));
+            }
+            MediaMigration::AlreadyOnTarget => report.already_on_target.push(url),
+            MediaMigration::NotInSource => report.not_in_source.push(url),
+            MediaMigration::Missing => {
+                println!("[{}/{}] missing {}", done, total, url);
+                report.missing.push(url);
+            }
+            MediaMigration::Failed(e) => {
+                println!("[{}/{}] failed {}: {}", done, total, url, e);
+                report.failed.push((url, e));
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+async fn migrate_url(
+    pool: &sqlx::PgPool,
+    from: &MediaBackend,
+    to: &MediaBackend,
+    url: &str,
+    dry_run: bool,
+) -> MediaMigration {
+    if to.object_key(url).is_some() {
+        return MediaMigration::AlreadyOnTarget;
+    }
+    let Some(key) = from.object_key(url) else {
+        return MediaMigration::NotInSource;
+    };
+    // S3 keys carry an `uploads/` prefix, maybe with a month; the file name is kept on the target
+    let filename = key.rsplit('/').next().unwrap_or(&key);
+
+    if dry_run {
+        return match from.exists(&key).await {
+            Ok(true) => MediaMigration::Migrated(to.url_for(&to.key_for(filename))),
+            Ok(false) => MediaMigration::Missing,
+            Err(e) => MediaMigration::Failed(e),
+        };
+    }
+
+    let data = match from.read(&key).await {
+        Ok(Some(data)) => data,
+        Ok(None) => return MediaMigration::Missing,
+        Err(e) => return MediaMigration::Failed(e),
+    };
+    let new_url = match to.write(filename, data).await {
+        Ok(new_url) => new_url,
+        Err(e) => return MediaMigration::Failed(e),
+    };
+    match ImageReference::replace_url(pool, url, &new_url).await {
+        Ok(_) => MediaMigration::Migrated(new_url),
+        Err(e) => MediaMigration::Failed(format!("Copied to {} but failed to update the database: {}", new_url, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Preparation, PreparationStep, Product, PREP_STATUS_PUBLISHED};
+
+    /// Connect to TEST_DATABASE_URL, or None to skip tests that need a database
+    async fn test_pool() -> Option<sqlx::PgPool> {
+        let url = std::env::var("TEST_DATABASE_URL").ok()?;
+        Some(sqlx::PgPool::connect(&url).await.expect("Failed to connect to TEST_DATABASE_URL"))
+    }
+
+    /// A put_object error as the SDK reports it for an S3 response
+    fn service_error(code: &str, status: u16) -> SdkError<aws_sdk_s3::operation::put_object::PutObjectError, HttpResponse> {
+        let error = aws_sdk_s3::operation::put_object::PutObjectError::generic(
+            aws_sdk_s3::error::ErrorMetadata::builder().code(code).message("test").build(),
+        );
+        let response = HttpResponse::new(status.try_into().unwrap(), aws_sdk_s3::primitives::SdkBody::empty());
+        SdkError::service_error(error, response)
+    }
+
+    #[test]
+    fn test_storage_errors_are_classified() {
+        let class = |error: &SdkError<_, HttpResponse>| StorageError::from_sdk(error).class();
+        assert_eq!(class(&service_error("InvalidAccessKeyId", 403)), "auth");
+        assert_eq!(class(&service_error("SignatureDoesNotMatch", 403)), "auth");
+        assert_eq!(class(&service_error("NoSuchBucket", 404)), "not_found");
+        assert_eq!(class(&service_error("SlowDown", 503)), "throttled");
+        assert_eq!(class(&service_error("RequestTimeout", 400)), "network");
+        assert_eq!(class(&service_error("InternalError", 500)), "network");
+        assert_eq!(class(&service_error("EntityTooLarge", 400)), "other");
+        // Without a known code the status decides
+        assert_eq!(class(&service_error("Unknown", 429)), "throttled");
+        assert_eq!(class(&SdkError::timeout_error("timed out")), "network");
+
+        let throttled = StorageError::from_sdk(&service_error("SlowDown", 503));
+        assert!(throttled.is_retryable() && !throttled.is_misconfiguration());
+        let auth = StorageError::from_sdk(&service_error("AccessDenied", 403));
+        assert!(!auth.is_retryable() && auth.is_misconfiguration());
+    }
+
+    #[tokio::test]
+    async fn test_only_retryable_errors_are_retried() {
+        let attempts = std::sync::atomic::AtomicU32::new(0);
+        let before = STORAGE_ERRORS.snapshot();
+
+        // Throttled twice, then through
+        let result = with_retries(3, Duration::ZERO, || async {
+            match attempts.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => Err(StorageError::Throttled("slow down".to_string())),
+                _ => Ok("uploaded"),
+            }
+        })
+        .await;
+        assert_eq!(result, Ok("uploaded"));
+        assert_eq!(attempts.swap(0, Ordering::SeqCst), 3);
+
+        // Bad credentials won't fix themselves
+        let result = with_retries(3, Duration::ZERO, || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err::<(), _>(StorageError::Auth("denied".to_string()))
+        })
+        .await;
+        assert_eq!(result, Err(StorageError::Auth("denied".to_string())));
+        assert_eq!(attempts.swap(0, Ordering::SeqCst), 1);
+
+        // Network errors give up after the last attempt
+        let result = with_retries(3, Duration::ZERO, || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err::<(), _>(StorageError::Network("timed out".to_string()))
+        })
+        .await;
+        assert!(matches!(result, Err(StorageError::Network(_))));
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        // Other tests may be counting too, so only check these were added
+        let after = STORAGE_ERRORS.snapshot();
+        assert!(after.throttled >= before.throttled + 2);
+        assert!(after.auth > before.auth);
+        assert!(after.network >= before.network + 3);
+    }
+
+    #[test]
+    fn test_backoff_doubles_with_jitter() {
+        let base = Duration::from_millis(200);
+        for attempt in 1..=3 {
+            let ceiling = base * 2u32.pow(attempt - 1);
+            let delay = backoff_delay(base, attempt);
+            assert!(delay >= ceiling / 2 && delay <= ceiling, "attempt {}: {:?}", attempt, delay);
+        }
+    }
+
+    /// Storage backed by a fresh upload directory; the S3 client is never called
+    fn test_storage(name: &str) -> Storage {
+        let config = aws_sdk_s3::Config::builder()
+            .behavior_version(aws_sdk_s3::config::BehaviorVersion::latest())
+            .region(aws_sdk_s3::config::Region::new("ap-southeast-2"))
+            .build();
+        let upload_dir = std::env::temp_dir().join(format!("khg-storage-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&upload_dir).expect("Failed to create upload dir");
+        Storage {
+            s3_client: S3Client::from_conf(config),
+            bucket_name: "kitchen-hand-guide".to_string(),
+            upload_dir,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delete_if_unreferenced_keeps_shared_images() {
+        let Some(pool) = test_pool().await else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+        let storage = test_storage("shared");
+        let filename = format!("{}.jpg", uuid::Uuid::new_v4());
+        let url = format!("/static/uploads/{}", filename);
+        let path = storage.upload_dir.join(&filename);
+        std::fs::write(&path, b"jpeg").expect("Failed to write image");
+
+        // A preparation and the clone its steps were imported into share the step image
+        let mut tx = pool.begin().await.expect("Failed to start transaction");
+        let mut step_ids = Vec::new();
+        for name in ["Storage original", "Storage clone"] {
+            let preparation = Preparation::create(
+                &mut *tx, name, "veg", "both", "Bench", None, None, None, None, None, "en", None, PREP_STATUS_PUBLISHED,
+            )
+            .await
+            .expect("Failed to create preparation");
+            let steps = PreparationStep::create_many(&mut *tx, preparation.id, 1, &[("Wash", Some(&url))])
+                .await
+                .expect("Failed to create step");
+            step_ids.push(steps[0].id);
+        }
+
+        // The original drops the image, but the clone still shows it
+        sqlx::query("UPDATE preparation_steps SET picture_url = NULL WHERE id = $1")
+            .bind(step_ids[0])
+            .execute(&mut *tx)
+            .await
+            .expect("Failed to clear picture");
+        let outcome = delete_if_unreferenced(&mut *tx, &storage, &url).await;
+        assert_eq!(outcome, Ok(DeleteOutcome::StillReferenced(1)));
+        assert!(path.exists());
+
+        // Once the clone drops it too, the file goes
+        sqlx::query("UPDATE preparation_steps SET picture_url = NULL WHERE id = $1")
+            .bind(step_ids[1])
+            .execute(&mut *tx)
+            .await
+            .expect("Failed to clear picture");
+        let outcome = delete_if_unreferenced(&mut *tx, &storage, &url).await;
+        assert_eq!(outcome, Ok(DeleteOutcome::Deleted));
+        assert!(!path.exists());
+
+        tx.rollback().await.expect("Failed to roll back");
+        std::fs::remove_dir_all(&storage.upload_dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_delete_if_unreferenced_ignores_foreign_urls() {
+        let Some(pool) = test_pool().await else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+        let storage = test_storage("foreign");
+
+        for url in ["/static/images/placeholder.svg", "https://example.com/cream.jpg"] {
+            let outcome = delete_if_unreferenced(&pool, &storage, url).await;
+            assert_eq!(outcome, Ok(DeleteOutcome::NotStored), "{}", url);
+        }
+        assert!(delete_if_unreferenced(&pool, &storage, "/static/uploads/../../etc/passwd").await.is_err());
+
+        std::fs::remove_dir_all(&storage.upload_dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_migrate_media_between_local_directories() {
+        let Some(pool) = test_pool().await else {
+            eprintln!("TEST_DATABASE_URL not set, skipping");
+            return;
+        };
+        // URL prefixes unique to this run, so other rows in the database are left alone
+        let run = uuid::Uuid::new_v4();
+        let old_dir = test_storage("migrate-old").upload_dir;
+        let new_dir = test_storage("migrate-new").upload_dir;
+        let from = MediaBackend::Local { dir: old_dir.clone(), url_prefix: format!("/media-{}/old", run) };
+        let to = MediaBackend::Local { dir: new_dir.clone(), url_prefix: format!("/media-{}/new", run) };
+
+        std::fs::write(old_dir.join("cream.jpg"), b"jpeg").expect("Failed to write image");
+        std::fs::write(old_dir.join("cream.webp"), b"webp").expect("Failed to write image");
+        let picture_url = format!("/media-{}/old/cream.jpg", run);
+        let webp_url = format!("/media-{}/old/cream.webp", run);
+        let missing_url = format!("/media-{}/old/gone.jpg", run);
+        let moved_url = format!("/media-{}/new/moved.jpg", run);
+
+        let product = Product::create(&pool, "Migrate", "Cream", "Cool room", None, Some(&picture_url), "")
+            .await
+            .expect("Failed to create product");
+        Product::set_webp_url(&pool, product.id, Some(&webp_url))
+            .await
+            .expect("Failed to set WebP URL");
+        let preparation = Preparation::create(
+            &pool, "Migrate prep", "veg", "both", "Bench", Some(&moved_url), None, None, None, None, "en", None, PREP_STATUS_PUBLISHED,
+        )
+        .await
+        .expect("Failed to create preparation");
+        // A step imported from the product photo, plus one whose file is gone
+        PreparationStep::create_many(&pool, preparation.id, 1, &[("Whip", Some(&picture_url)), ("Chill", Some(&missing_url))])
+            .await
+            .expect("Failed to create steps");
+
+        // A dry run reports what would move without touching files or rows
+        let report = migrate_media(&pool, &from, &to, 2, true).await.expect("Dry run failed");
+        assert!(report.migrated.contains(&(picture_url.clone(), format!("/media-{}/new/cream.jpg", run))));
+        assert!(!new_dir.join("cream.jpg").exists());
+        let stored = Product::get_by_id(&pool, product.id).await.unwrap().unwrap();
+        assert_eq!(stored.picture_url.as_deref(), Some(picture_url.as_str()));
+
+        let report = migrate_media(&pool, &from, &to, 2, false).await.expect("Migration failed");
+        let migrated: Vec<_> = report.migrated.iter().map(|(old, _)| old.as_str()).collect();
+        assert!(migrated.contains(&picture_url.as_str()) && migrated.contains(&webp_url.as_str()));
+        assert!(report.missing.contains(&missing_url));
+        assert!(report.already_on_target.contains(&moved_url));
+        assert!(report.failed.is_empty(), "{:?}", report.failed);
+
+        assert_eq!(std::fs::read(new_dir.join("cream.jpg")).unwrap(), b"jpeg");
+        assert_eq!(std::fs::read(new_dir.join("cream.webp")).unwrap(), b"webp");
+        let stored = Product::get_by_id(&pool, product.id).await.unwrap().unwrap();
+        assert_eq!(stored.picture_url, Some(format!("/media-{}/new/cream.jpg", run)));
+        assert_eq!(stored.webp_url, Some(format!("/media-{}/new/cream.webp", run)));
+        let steps = PreparationStep::get_by_preparation_id(&pool, preparation.id).await.unwrap();
+        assert_eq!(steps[0].picture_url, stored.picture_url);
+        assert_eq!(steps[1].picture_url.as_ref(), Some(&missing_url));
+
+        // Running again picks up where it left off: nothing left to move
+        let report = migrate_media(&pool, &from, &to, 2, false).await.expect("Second run failed");
+        let run_prefix = format!("/media-{}/old/", run);
+        assert!(report.migrated.iter().all(|(old, _)| !old.starts_with(&run_prefix)));
+        assert!(report.already_on_target.contains(&stored.picture_url.unwrap()));
+
+        sqlx::query("DELETE FROM preparations WHERE id = $1").bind(preparation.id).execute(&pool).await.ok();
+        sqlx::query("DELETE FROM products WHERE id = $1").bind(product.id).execute(&pool).await.ok();
+        std::fs::remove_dir_all(&old_dir).ok();
+        std::fs::remove_dir_all(&new_dir).ok();
+    }
+}